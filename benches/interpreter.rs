@@ -0,0 +1,182 @@
+//! Benchmarks for the interpreter hot path, every one reports instructions per second.
+//! The console is always a fake so no terminal or timing syscalls end up in the numbers.
+//! Run with `cargo bench`
+
+use std::hint::black_box;
+use std::io;
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use lc3::file_management::read_file_to_memory;
+use lc3::instrument::Instrumentation;
+use lc3::test_util::{ScriptedInput, TestVm};
+use lc3::{Registers, State, run_instrumented, run_step, run_with_budget};
+
+/// Big enough for every benchmark, they all stop on their own well before it
+const BUDGET: u64 = 100_000_000;
+
+/// Counts down from 30000 with ADD and BR
+const COUNTING_LOOP: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #30000
+        .END";
+
+/// Copies a 256 word block back and forth 50 times with LDR and STR
+const MEMORY_LOOP: &str = "
+        .ORIG x3000
+        LD R5, ROUNDS
+ROUND   LD R1, FROM
+        LD R2, TO
+        LD R3, SIZE
+COPY    LDR R4, R1, #0
+        STR R4, R2, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #1
+        ADD R3, R3, #-1
+        BRp COPY
+        ADD R5, R5, #-1
+        BRp ROUND
+        HALT
+ROUNDS  .FILL #50
+FROM    .FILL x4000
+TO      .FILL x5000
+SIZE    .FILL #256
+        .END";
+
+/// Prints the 4 KB string at x4000
+const PUTS_4K: &str = "
+        .ORIG x3000
+        LD R0, TEXT
+        PUTS
+        HALT
+TEXT    .FILL x4000
+        .END";
+
+/// Prints 4096 characters one OUT at a time
+const OUT_LOOP: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+        LD R0, CHARACTER
+LOOP    OUT
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #4096
+CHARACTER .FILL x61
+        .END";
+
+fn puts_4k() -> TestVm {
+    let mut vm = TestVm::with_snippet(PUTS_4K);
+    for address in 0x4000..0x5000 {
+        vm.state_mut().memory_write(address, b'a' as u16);
+    }
+    vm
+}
+
+/// 2048 starting a game and making a few moves, until the input runs out
+fn game_2048() -> TestVm {
+    let mut state = State::default();
+    let image = format!("{}/images/2048.obj", env!("CARGO_MANIFEST_DIR"));
+    read_file_to_memory(&image, &mut state).unwrap();
+    TestVm::new(state).input(ScriptedInput::new("nwasdwasd"))
+}
+
+/// Instructions the program executes before it halts or fails, stepping it by hand
+fn count_instructions(mut vm: TestVm) -> u64 {
+    let state = vm.state_mut();
+    let mut count = 0;
+    while state.is_running() {
+        let instruction = state.read_instruction(state.register_read(Registers::Pc) as usize);
+        state.increment_pc();
+        count += 1;
+        if run_step(instruction, state).is_err() {
+            break;
+        }
+    }
+    count
+}
+
+/// Counts the instructions, the cheapest collector there can be
+struct Counted(u64);
+
+impl Instrumentation for Counted {
+    #[inline]
+    fn after_instruction(&mut self, _pc: u16, _word: u16, _state: &State) {
+        self.0 += 1;
+    }
+}
+
+/// How a benchmark runs its program
+#[derive(Clone, Copy)]
+enum Mode {
+    Interpreted,
+    /// With the decode cache (`--fast`)
+    Cached,
+    /// Counting the instructions with a collector, see `lc3::instrument`
+    Instrumented,
+    /// With the JIT, only with the `jit` feature. The blocks the first iteration compiled are shared by the next ones,
+    /// see `lc3::jit`
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+fn bench(criterion: &mut Criterion, name: &str, setup: fn() -> TestVm) {
+    let mut group = criterion.benchmark_group(name);
+    group.throughput(Throughput::Elements(count_instructions(setup())));
+    let modes = [
+        ("run", Mode::Interpreted),
+        ("run_cached", Mode::Cached),
+        ("run_instrumented", Mode::Instrumented),
+        #[cfg(feature = "jit")]
+        ("run_jit", Mode::Jit),
+    ];
+    for (function, mode) in modes {
+        group.bench_function(function, |bencher| {
+            bencher.iter_batched_ref(
+                || {
+                    let mut vm = setup();
+                    vm.state_mut().set_output(io::sink());
+                    match mode {
+                        Mode::Interpreted | Mode::Instrumented => {}
+                        Mode::Cached => vm.state_mut().enable_decode_cache(),
+                        #[cfg(feature = "jit")]
+                        Mode::Jit => {
+                            vm.state_mut().enable_jit();
+                        }
+                    }
+                    vm
+                },
+                |vm| match mode {
+                    Mode::Instrumented => {
+                        let mut counted = Counted(0);
+                        let _ = run_instrumented(black_box(vm.state_mut()), BUDGET, &mut counted);
+                        black_box(counted.0);
+                    }
+                    _ => {
+                        let _ = run_with_budget(black_box(vm.state_mut()), BUDGET);
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn interpreter(criterion: &mut Criterion) {
+    bench(criterion, "add_br_loop", || {
+        TestVm::with_snippet(COUNTING_LOOP)
+    });
+    bench(criterion, "ldr_str_loop", || {
+        TestVm::with_snippet(MEMORY_LOOP)
+    });
+    bench(criterion, "puts_4k", puts_4k);
+    bench(criterion, "out_loop", || TestVm::with_snippet(OUT_LOOP));
+    bench(criterion, "game_2048", game_2048);
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! With the `ffi` feature the C header `include/lc3.h` is written from `src/ffi.rs`, see `cbindgen.toml`
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    write_c_header();
+}
+
+#[cfg(feature = "ffi")]
+fn write_c_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_directory = std::env::var("CARGO_MANIFEST_DIR").unwrap(); // Cargo always sets it for build scripts
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_directory))
+        .expect("cbindgen.toml should be valid");
+    // Only the items of the C API: read whole, the crate would put every `pub const` of it in the header too
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/ffi.rs", crate_directory))
+        .generate()
+        .expect("src/ffi.rs should be exportable to C")
+        .write_to_file(format!("{}/include/lc3.h", crate_directory));
+}
@@ -0,0 +1,55 @@
+//! A device plugin for `--device`, written against `include/lc3_device.h` like a C one would be.
+//! It has a single register at xFE10: loading it returns a counter that goes up by one on every load,
+//! storing to it sets the counter.
+//!
+//! `cargo build --example counter_device`, then `cargo run -- --device target/debug/examples/libcounter_device.so prog.obj`
+
+use std::ffi::c_void;
+
+const LC3_DEVICE_ABI_VERSION: u32 = 1;
+const COUNTER: u16 = 0xFE10;
+
+/// `Lc3Device` of `include/lc3_device.h`
+#[repr(C)]
+pub struct Lc3Device {
+    abi_version: u32,
+    first_address: u16,
+    last_address: u16,
+    context: *mut c_void,
+    read: Option<extern "C" fn(*mut c_void, u16) -> u16>,
+    write: Option<extern "C" fn(*mut c_void, u16, u16)>,
+    unload: Option<extern "C" fn(*mut c_void)>,
+}
+
+extern "C" fn read(context: *mut c_void, _address: u16) -> u16 {
+    // The context is the counter allocated in `lc3_device_register`
+    let counter = unsafe { &mut *(context as *mut u16) };
+    let value = *counter;
+    *counter = counter.wrapping_add(1);
+    value
+}
+
+extern "C" fn write(context: *mut c_void, _address: u16, value: u16) {
+    unsafe { *(context as *mut u16) = value };
+}
+
+extern "C" fn unload(context: *mut c_void) {
+    drop(unsafe { Box::from_raw(context as *mut u16) });
+}
+
+/// # Safety
+/// `device` must point to an `Lc3Device`, the VM always gives a valid one
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_device_register(device: *mut Lc3Device) -> i32 {
+    let Some(device) = (unsafe { device.as_mut() }) else {
+        return 1;
+    };
+    device.abi_version = LC3_DEVICE_ABI_VERSION;
+    device.first_address = COUNTER;
+    device.last_address = COUNTER;
+    device.context = Box::into_raw(Box::new(0_u16)) as *mut c_void;
+    device.read = Some(read);
+    device.write = Some(write);
+    device.unload = Some(unload);
+    0
+}
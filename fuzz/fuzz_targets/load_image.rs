@@ -0,0 +1,21 @@
+#![no_main]
+
+use lc3::State;
+use lc3::file_management::load_image_bytes;
+use libfuzzer_sys::fuzz_target;
+
+// Any byte slice must load or fail without panicking, and a loaded image must land exactly at its origin
+fuzz_target!(|data: &[u8]| {
+    let Ok((origin, words)) = load_image_bytes(data) else {
+        return;
+    };
+    assert!(origin as usize + words.len() <= 1 << 16);
+    assert!(words.len() <= data.len() / 2);
+    let mut state = State::default();
+    for (offset, word) in words.iter().enumerate() {
+        state.memory_write(origin as usize + offset, *word);
+    }
+    for (offset, word) in words.iter().enumerate() {
+        assert_eq!(state.memory_read(origin as usize + offset), *word);
+    }
+});
@@ -0,0 +1,40 @@
+#![no_main]
+
+use std::collections::VecDeque;
+
+use lc3::{Registers, State, run_step};
+use libfuzzer_sys::fuzz_target;
+
+const REGISTERS: [Registers; 9] = [
+    Registers::R0,
+    Registers::R1,
+    Registers::R2,
+    Registers::R3,
+    Registers::R4,
+    Registers::R5,
+    Registers::R6,
+    Registers::R7,
+    Registers::Pc,
+];
+
+// The first words fill the registers, the rest are executed one after the other.
+// Executing garbage can fail but must never panic
+fuzz_target!(|data: &[u8]| {
+    let words: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let mut state = State::default();
+    state.set_input(VecDeque::new());
+    state.set_output(std::io::sink());
+    for (register, value) in REGISTERS.iter().zip(&words) {
+        state.register_write(*register, *value);
+    }
+    for (offset, word) in words.iter().enumerate() {
+        state.memory_write(0x3000 + offset % 0x1000, *word);
+    }
+    for word in words.iter().skip(REGISTERS.len()) {
+        state.increment_pc();
+        let _ = run_step(*word, &mut state);
+    }
+});
@@ -0,0 +1,142 @@
+/* The C API of the LC-3 VM, written by cbindgen from src/ffi.rs: build with `--features ffi` instead of editing it */
+
+#ifndef LC3_H
+#define LC3_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Index of the PC for [`lc3_read_reg`] and [`lc3_write_reg`], 0 to 7 are R0 to R7
+#define LC3_REGISTER_PC 8
+
+// Index of the condition codes for [`lc3_read_reg`] and [`lc3_write_reg`]
+#define LC3_REGISTER_COND 9
+
+// What a call did, the failures are the negative ones
+enum Lc3Status
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : int32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  LC3_STATUS_OK = 0,
+  // The program ran HALT
+  LC3_STATUS_HALTED = 1,
+  // The program ran all the instructions it was given and can keep going
+  LC3_STATUS_RUNNING = 2,
+  // The program is in GETC or IN and the input callback had no key, run it again once it has one
+  LC3_STATUS_WAITING_FOR_INPUT = 3,
+  LC3_STATUS_NULL_POINTER = -1,
+  // The image is too short or doesn't fit in memory
+  LC3_STATUS_BAD_IMAGE = -2,
+  LC3_STATUS_BAD_OP_CODE = -3,
+  LC3_STATUS_BAD_TRAP_CODE = -4,
+  // A register index above [`LC3_REGISTER_COND`]
+  LC3_STATUS_BAD_REGISTER = -5,
+  // A trap routine couldn't finish
+  LC3_STATUS_TRAP = -6,
+  // The VM panicked, the machine may be left halfway through an instruction
+  LC3_STATUS_PANIC = -7,
+  LC3_STATUS_OTHER = -8,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum Lc3Status Lc3Status;
+#else
+typedef int32_t Lc3Status;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// An LC-3 machine, only used through a pointer from [`lc3_new`]
+typedef struct Lc3Vm Lc3Vm;
+
+// Called for the next key: a byte from 0 to 255, or a negative number if there is no key right now. Null when there is none
+typedef int32_t (*Lc3InputCallback)(void *user_data);
+
+// Called with every byte the program prints. Null when there is none
+typedef void (*Lc3OutputCallback)(void *user_data, uint8_t byte);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A new machine with nothing loaded, free it with [`lc3_free`].
+// Until the callbacks are set there are no keys and the program prints to the standard output. Null if it couldn't be made
+struct Lc3Vm *lc3_new(void);
+
+// Free a machine from [`lc3_new`], null does nothing
+//
+// # Safety
+// The pointer must be null or come from [`lc3_new`] and not be freed yet, it can't be used afterwards
+void lc3_free(struct Lc3Vm *vm);
+
+// Load an object image: its origin followed by its words, big endian
+//
+// # Safety
+// `vm` as in [`lc3_free`], `image` must point to `length` readable bytes
+Lc3Status lc3_load_image(struct Lc3Vm *vm, const uint8_t *image, size_t length);
+
+// Run a single instruction
+//
+// # Safety
+// `vm` as in [`lc3_free`]
+Lc3Status lc3_step(struct Lc3Vm *vm);
+
+// Run until the program halts, waits for a key or `max` instructions ran
+//
+// # Safety
+// `vm` as in [`lc3_free`]
+Lc3Status lc3_run(struct Lc3Vm *vm, uint64_t max);
+
+// Write the register with that index in `value`, see [`LC3_REGISTER_PC`]
+//
+// # Safety
+// `vm` as in [`lc3_free`], `value` must be null or writable
+Lc3Status lc3_read_reg(struct Lc3Vm *vm, uint32_t index, uint16_t *value);
+
+// Set the register with that index, see [`LC3_REGISTER_PC`]
+//
+// # Safety
+// `vm` as in [`lc3_free`]
+Lc3Status lc3_write_reg(struct Lc3Vm *vm, uint32_t index, uint16_t value);
+
+// Write the word at that address in `value`. The device registers aren't polled like a LDR would, they give the
+// word the program last read from them
+//
+// # Safety
+// `vm` as in [`lc3_free`], `value` must be null or writable
+Lc3Status lc3_read_mem(struct Lc3Vm *vm,
+                       uint16_t address,
+                       uint16_t *value);
+
+// Set the word at that address
+//
+// # Safety
+// `vm` as in [`lc3_free`]
+Lc3Status lc3_write_mem(struct Lc3Vm *vm, uint16_t address, uint16_t value);
+
+// Take the keys from `callback`, it gets `user_data` on every call. Null means there are no keys
+//
+// # Safety
+// `vm` as in [`lc3_free`], `callback` must be fine to call with `user_data` while the machine runs
+Lc3Status lc3_set_input_callback(struct Lc3Vm *vm, Lc3InputCallback callback, void *user_data);
+
+// Give every printed byte to `callback`, it gets `user_data` on every call. Null prints to the standard output again
+//
+// # Safety
+// `vm` as in [`lc3_free`], `callback` must be fine to call with `user_data` while the machine runs
+Lc3Status lc3_set_output_callback(struct Lc3Vm *vm,
+                                  Lc3OutputCallback callback,
+                                  void *user_data);
+
+// The message of the last failure on this thread, null if nothing failed yet.
+// It is valid until the next failure on the thread
+const char *lc3_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LC3_H */
@@ -0,0 +1,34 @@
+/*
+ * The ABI of the device plugins the command line VM loads with `--device <library>`.
+ *
+ * A plugin is a shared library exporting `lc3_device_register`. The VM calls it once with a zeroed
+ * `Lc3Device`, the plugin fills it in and returns 0. The VM checks `abi_version` before anything else
+ * and rejects a plugin built for another version.
+ *
+ * Loads and stores of the addresses from `first_address` to `last_address` (both on the device page,
+ * xFE00 and up, and not the keyboard registers at xFE00 and xFE02) call `read` and `write` with `context`.
+ * `unload`, if not NULL, is called once before the library is closed.
+ * The callbacks are only ever called from the thread running the VM.
+ */
+
+#ifndef LC3_DEVICE_H
+#define LC3_DEVICE_H
+
+#include <stdint.h>
+
+#define LC3_DEVICE_ABI_VERSION 1
+
+typedef struct Lc3Device {
+  uint32_t abi_version;
+  uint16_t first_address;
+  uint16_t last_address;
+  void *context;
+  uint16_t (*read)(void *context, uint16_t address);
+  void (*write)(void *context, uint16_t address, uint16_t value);
+  void (*unload)(void *context);
+} Lc3Device;
+
+/* Fill in the device, anything but 0 means the plugin couldn't start */
+int32_t lc3_device_register(Lc3Device *device);
+
+#endif /* LC3_DEVICE_H */
@@ -0,0 +1,449 @@
+//! The addresses given to the VM, by every option taking one (`--report-mem`, `--assert-mem`, `--watch-dump`,
+//! `--stack`, ...) and by the jobs of `batch`. [`parse_address`] reads them all the same way:
+//! * `x3010`, `X3010` or `0x3010` in hexadecimal
+//! * `12304` or `#12304` in decimal
+//! * `LOOP`, a label of the program, case sensitive like in the assembler
+//! * `LOOP+2`, `DATA-x10` or `x3000+#16`, any of them with numbers added or taken away
+//!
+//! With `--banks` (see [`crate::bank`]) an address can name its bank before a colon, `2:x9000`, read by
+//! [`parse_bank_address`]. A range names it before its start, `2:x9000:x9010`: `2:x9000` is the range from x0002 on.
+//! Without a bank it is the one selected.
+//!
+//! The labels come from the [`SymbolTable`] of the program: the one the assembler gave a `.asm` image, or the `.sym`
+//! written next to a `.obj` (see [`SymbolTable::read_beside`]). A misspelled label is refused with the labels it is
+//! close to:
+//! ```
+//! # use lc3::address::{parse_address, SymbolTable};
+//! let mut symbols = SymbolTable::new();
+//! symbols.insert("LOOP", 0x3002);
+//! assert_eq!(parse_address("LOOP+2", &symbols), Ok(0x3004));
+//! assert_eq!(
+//!     parse_address("LOPP", &symbols).unwrap_err().to_string(),
+//!     "unknown symbol `LOPP`, did you mean `LOOP`?"
+//! );
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fs, io};
+
+use crate::Error;
+use crate::assembler::Program;
+
+/// Most labels [`ParseError::UnknownSymbol`] suggests
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Why an address couldn't be read, see the module documentation
+#[derive(thiserror::Error, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    #[error("no address given")]
+    Empty,
+    #[error("`{0}` isn't a number nor a symbol")]
+    BadNumber(String),
+    /// The labels close to `name` come first, none if nothing is close enough
+    #[error("unknown symbol `{name}`{}", did_you_mean(suggestions))]
+    UnknownSymbol {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    /// A label but the program came without its symbols
+    #[error("`{0}` is a symbol but no symbol table was loaded")]
+    NoSymbols(String),
+    #[error("`{0}` is past the memory, x0000 to xFFFF")]
+    OutOfRange(String),
+    #[error("the range `{0}` ends before it starts")]
+    BackwardsRange(String),
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    let quoted: Vec<String> = suggestions
+        .iter()
+        .map(|suggestion| format!("`{}`", suggestion))
+        .collect();
+    match quoted.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!(", did you mean {}?", last),
+        Some((last, rest)) => format!(", did you mean {} or {}?", rest.join(", "), last),
+    }
+}
+
+/// The labels of a program with their addresses
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, address: u16) {
+        self.symbols.insert(name.into(), address);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Read a `.sym` file: the `LABEL x3000` lines of [`Program::symbol_table`], or the `//  LABEL 3000` ones of
+    /// lc3tools. Any other line, like the headers of lc3tools, is skipped
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        for line in text.lines() {
+            let line = line.trim();
+            let mut fields = line.strip_prefix("//").unwrap_or(line).split_whitespace();
+            let (Some(name), Some(address), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let hex = address
+                .strip_prefix('x')
+                .or_else(|| address.strip_prefix('X'))
+                .unwrap_or(address);
+            if let Ok(address) = u16::from_str_radix(hex, 16) {
+                symbols.insert(name, address);
+            }
+        }
+        symbols
+    }
+
+    /// The symbols of the `.sym` written next to `image` by the assembler, none if there is no such file
+    pub fn read_beside(image: impl AsRef<Path>) -> Result<SymbolTable, Error> {
+        match fs::read_to_string(image.as_ref().with_extension("sym")) {
+            Ok(text) => Ok(SymbolTable::parse(&text)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(SymbolTable::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Add the symbols of `other`, its address wins for a label both have
+    pub fn merge(&mut self, other: SymbolTable) {
+        self.symbols.extend(other.symbols);
+    }
+
+    /// The labels close to `name`, the closest first: the same but for the case, or a couple of letters apart
+    pub fn suggestions(&self, name: &str) -> Vec<String> {
+        let lower = name.to_lowercase();
+        let allowed = match name.chars().count() {
+            0..=3 => 1,
+            _ => 2,
+        };
+        let mut close: Vec<(usize, &String)> = self
+            .symbols
+            .keys()
+            .filter_map(|symbol| {
+                let distance = edit_distance(&lower, &symbol.to_lowercase());
+                (distance <= allowed).then_some((distance, symbol))
+            })
+            .collect();
+        close.sort();
+        close
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, symbol)| symbol.clone())
+            .collect()
+    }
+}
+
+impl From<&Program> for SymbolTable {
+    fn from(program: &Program) -> SymbolTable {
+        SymbolTable {
+            symbols: program.symbols.clone(),
+        }
+    }
+}
+
+/// The letters to insert, delete or replace to turn `from` into `to`
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+    for (row, from) in from.chars().enumerate() {
+        let mut current = vec![row + 1];
+        for (column, to) in to.iter().enumerate() {
+            let replaced = previous[column] + usize::from(from != *to);
+            current.push(
+                replaced
+                    .min(previous[column + 1] + 1)
+                    .min(current[column] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[to.len()]
+}
+
+/// Read an address written in any of the ways of the module documentation, labels looked up in `symbols`
+pub fn parse_address(text: &str, symbols: &SymbolTable) -> Result<u16, ParseError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    let mut address: i64 = 0;
+    let mut sign = 1;
+    let mut rest = text;
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        if term.is_empty() {
+            return Err(ParseError::BadNumber(text.to_string()));
+        }
+        address += sign * i64::from(parse_term(term, symbols)?);
+        if !(0..=0xFFFF).contains(&address) {
+            return Err(ParseError::OutOfRange(text.to_string()));
+        }
+        match rest[end..].chars().next() {
+            Some(operator) => {
+                sign = if operator == '+' { 1 } else { -1 };
+                rest = &rest[end + 1..];
+            }
+            None => return Ok(address as u16),
+        }
+    }
+}
+
+/// Read an inclusive range written as `x4000:x4008` or `TABLE:TABLE+7`, a single address is a range of one word
+pub fn parse_address_range(text: &str, symbols: &SymbolTable) -> Result<(u16, u16), ParseError> {
+    let (start, end) = match text.split_once(':') {
+        Some((start, end)) => (parse_address(start, symbols)?, parse_address(end, symbols)?),
+        None => {
+            let address = parse_address(text, symbols)?;
+            (address, address)
+        }
+    };
+    match start <= end {
+        true => Ok((start, end)),
+        false => Err(ParseError::BackwardsRange(text.to_string())),
+    }
+}
+
+/// An address with the bank it is in before a colon like `2:x9000`, `None` for the bank selected when there is no colon
+pub fn parse_bank_address(
+    text: &str,
+    symbols: &SymbolTable,
+) -> Result<(Option<u16>, u16), ParseError> {
+    match text.split_once(':') {
+        Some((bank, address)) => Ok((
+            Some(parse_address(bank, symbols)?),
+            parse_address(address, symbols)?,
+        )),
+        None => Ok((None, parse_address(text, symbols)?)),
+    }
+}
+
+/// A range in a bank like `2:x9000:x9010`, or without a bank like [`parse_address_range`] reads it
+pub fn parse_bank_range(
+    text: &str,
+    symbols: &SymbolTable,
+) -> Result<(Option<u16>, (u16, u16)), ParseError> {
+    match text.splitn(3, ':').collect::<Vec<&str>>()[..] {
+        [bank, start, end] => {
+            let bank = parse_address(bank, symbols)?;
+            let (start, end) = (parse_address(start, symbols)?, parse_address(end, symbols)?);
+            match start <= end {
+                true => Ok((Some(bank), (start, end))),
+                false => Err(ParseError::BackwardsRange(text.to_string())),
+            }
+        }
+        _ => Ok((None, parse_address_range(text, symbols)?)),
+    }
+}
+
+/// A number or a label, without any sign
+fn parse_term(term: &str, symbols: &SymbolTable) -> Result<u16, ParseError> {
+    let hex = term
+        .strip_prefix("0x")
+        .or_else(|| term.strip_prefix('x'))
+        .or_else(|| term.strip_prefix('X'))
+        .filter(|hex| !hex.is_empty() && hex.chars().all(|digit| digit.is_ascii_hexdigit()));
+    let (digits, radix) = match (hex, term.strip_prefix('#')) {
+        (Some(hex), _) => (hex, 16),
+        (None, Some(decimal)) => (decimal, 10),
+        (None, None) if term.starts_with(|first: char| first.is_ascii_digit()) => (term, 10),
+        (None, None) => return lookup(term, symbols),
+    };
+    if digits.is_empty() || !digits.chars().all(|digit| digit.is_digit(radix)) {
+        return Err(ParseError::BadNumber(term.to_string()));
+    }
+    // The digits are all right, it can only be too big
+    u16::from_str_radix(digits, radix).map_err(|_| ParseError::OutOfRange(term.to_string()))
+}
+
+fn lookup(name: &str, symbols: &SymbolTable) -> Result<u16, ParseError> {
+    if !name
+        .chars()
+        .all(|letter| letter.is_alphanumeric() || letter == '_' || letter == '.')
+    {
+        return Err(ParseError::BadNumber(name.to_string()));
+    }
+    if symbols.is_empty() {
+        return Err(ParseError::NoSymbols(name.to_string()));
+    }
+    symbols.get(name).ok_or_else(|| ParseError::UnknownSymbol {
+        name: name.to_string(),
+        suggestions: symbols.suggestions(name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("LOOP", 0x3002);
+        symbols.insert("LOOPS", 0x3008);
+        symbols.insert("DATA", 0x4000);
+        symbols.insert("END", 0xFFFF);
+        symbols
+    }
+
+    #[test]
+    fn numbers_are_hexadecimal_with_an_x_and_decimal_without() {
+        for (text, address) in [
+            ("x3010", 0x3010),
+            ("X3010", 0x3010),
+            ("0x3010", 0x3010),
+            ("xffff", 0xFFFF),
+            ("12304", 12304),
+            ("#12304", 12304),
+            (" x0 ", 0),
+        ] {
+            assert_eq!(
+                parse_address(text, &SymbolTable::new()),
+                Ok(address),
+                "{}",
+                text
+            );
+        }
+        for text in ["#", "12ab", "#x10", "x30 10", "$3000"] {
+            assert!(
+                matches!(
+                    parse_address(text, &symbols()),
+                    Err(ParseError::BadNumber(_))
+                ),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(parse_address("", &symbols()), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn symbols_take_offsets() {
+        let symbols = symbols();
+        assert_eq!(parse_address("LOOP", &symbols), Ok(0x3002));
+        assert_eq!(parse_address("LOOP+2", &symbols), Ok(0x3004));
+        assert_eq!(parse_address("DATA - x10", &symbols), Ok(0x3FF0));
+        assert_eq!(parse_address("DATA+#16-1", &symbols), Ok(0x400F));
+        assert_eq!(parse_address("x3000+LOOPS", &symbols), Ok(0x6008));
+        assert_eq!(
+            parse_address("LOOP+", &symbols),
+            Err(ParseError::BadNumber("LOOP+".to_string()))
+        );
+        assert_eq!(
+            parse_address("LOOP", &SymbolTable::new()),
+            Err(ParseError::NoSymbols("LOOP".to_string()))
+        );
+    }
+
+    #[test]
+    fn addresses_past_the_memory_are_refused() {
+        let symbols = symbols();
+        for text in ["xFFFF+1", "x10000", "65536", "END+1", "LOOP-x3003", "-1"] {
+            let error = parse_address(text, &symbols).unwrap_err();
+            assert!(
+                matches!(error, ParseError::OutOfRange(_) | ParseError::BadNumber(_)),
+                "{}: {}",
+                text,
+                error
+            );
+        }
+        assert_eq!(
+            parse_address("xFFFF+1", &symbols).unwrap_err().to_string(),
+            "`xFFFF+1` is past the memory, x0000 to xFFFF"
+        );
+        assert_eq!(
+            parse_address("x10000-1", &symbols),
+            Err(ParseError::OutOfRange("x10000".to_string()))
+        );
+        assert_eq!(parse_address("END-1+1", &symbols), Ok(0xFFFF));
+    }
+
+    #[test]
+    fn a_misspelled_symbol_gets_the_closest_ones() {
+        let symbols = symbols();
+        assert_eq!(
+            parse_address("LOPP", &symbols),
+            Err(ParseError::UnknownSymbol {
+                name: "LOPP".to_string(),
+                suggestions: vec!["LOOP".to_string(), "LOOPS".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_address("LOPP", &symbols).unwrap_err().to_string(),
+            "unknown symbol `LOPP`, did you mean `LOOP` or `LOOPS`?"
+        );
+        assert_eq!(symbols.suggestions("data"), ["DATA"]);
+        assert_eq!(symbols.suggestions("EDN"), Vec::<String>::new());
+        assert_eq!(
+            parse_address("COUNTER", &symbols).unwrap_err().to_string(),
+            "unknown symbol `COUNTER`"
+        );
+    }
+
+    #[test]
+    fn ranges_go_forward() {
+        let symbols = symbols();
+        assert_eq!(
+            parse_address_range("DATA:DATA+3", &symbols),
+            Ok((0x4000, 0x4003))
+        );
+        assert_eq!(parse_address_range("LOOP", &symbols), Ok((0x3002, 0x3002)));
+        assert_eq!(
+            parse_address_range("DATA:LOOP", &symbols),
+            Err(ParseError::BackwardsRange("DATA:LOOP".to_string()))
+        );
+    }
+
+    #[test]
+    fn a_bank_goes_before_the_address() {
+        let symbols = symbols();
+        assert_eq!(
+            parse_bank_address("2:x9000", &symbols),
+            Ok((Some(2), 0x9000))
+        );
+        assert_eq!(parse_bank_address("DATA+1", &symbols), Ok((None, 0x4001)));
+        assert_eq!(
+            parse_bank_range("1:x9000:x9003", &symbols),
+            Ok((Some(1), (0x9000, 0x9003)))
+        );
+        // Two parts are a range without a bank
+        assert_eq!(
+            parse_bank_range("2:x9000", &symbols),
+            Ok((None, (2, 0x9000)))
+        );
+        assert_eq!(
+            parse_bank_range("1:x9003:x9000", &symbols),
+            Err(ParseError::BackwardsRange("1:x9003:x9000".to_string()))
+        );
+        assert!(parse_bank_address("x:x9000", &symbols).is_err());
+    }
+
+    #[test]
+    fn sym_files_of_both_assemblers_are_read() {
+        let ours = SymbolTable::parse("LOOP x3002\nDATA x4000\n");
+        let lc3tools = SymbolTable::parse(
+            "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\tLOOP              3002\n",
+        );
+        assert_eq!(ours.get("LOOP"), Some(0x3002));
+        assert_eq!(ours.get("DATA"), Some(0x4000));
+        assert_eq!(lc3tools.get("LOOP"), Some(0x3002));
+        assert_eq!(lc3tools.symbols.len(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+//! Parameters for a program, what `--arg` and `--env` write to memory before it runs. The block is laid out as:
+//! ```text
+//! start     count of arguments    <- R0
+//!           a pointer per argument
+//!           count of variables    <- R1
+//!           a pointer per variable
+//!           the strings, a character per word ending in NUL like PUTS prints them
+//! ```
+//! A variable is a single `KEY=VALUE` string. The block goes at `--args-at` or, without it, right below the stack:
+//! the region of `--stack` or [`DEFAULT_STACK_START`]. It can't overlap the images nor reach the devices.
+//!
+//! The words are written like stores of the program, so executing them isn't reported as uninitialized
+
+use crate::{DEVICE_PAGE, Error, LoadError, Registers, State};
+
+/// Where the stack is taken to start without `--stack`, leaving it the 3.5K words below the devices
+pub const DEFAULT_STACK_START: u16 = 0xF000;
+
+/// The arguments and the variables of a program, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramArguments {
+    pub arguments: Vec<String>,
+    /// Each written `KEY=VALUE`
+    pub variables: Vec<String>,
+}
+
+impl ProgramArguments {
+    pub fn is_empty(&self) -> bool {
+        self.arguments.is_empty() && self.variables.is_empty()
+    }
+
+    /// How many words the block takes
+    pub fn size(&self) -> usize {
+        let strings: usize = self
+            .arguments
+            .iter()
+            .chain(&self.variables)
+            .map(|string| string.len() + 1)
+            .sum();
+        2 + self.arguments.len() + self.variables.len() + strings
+    }
+
+    /// Where the block goes right below a stack starting at `stack_start`
+    pub fn start_below(&self, stack_start: u16) -> Result<u16, Error> {
+        (stack_start as usize)
+            .checked_sub(self.size())
+            .map(|start| start as u16)
+            .ok_or_else(|| {
+                LoadError::BadArguments(format!(
+                    "{} words don't fit below x{:04X}",
+                    self.size(),
+                    stack_start
+                ))
+                .into()
+            })
+    }
+
+    /// The words of the block when it starts at `start`
+    pub fn words(&self, start: u16) -> Vec<u16> {
+        let mut words = Vec::with_capacity(self.size());
+        let mut characters = Vec::new();
+        let mut address = start as usize + 2 + self.arguments.len() + self.variables.len();
+        for table in [&self.arguments, &self.variables] {
+            words.push(table.len() as u16);
+            for string in table {
+                words.push(address as u16);
+                characters.extend(string.bytes().map(u16::from));
+                characters.push(0);
+                address += string.len() + 1;
+            }
+        }
+        words.extend(characters);
+        words
+    }
+
+    /// Write the block at `start` of `state`, with R0 and R1 pointing at the tables. It fails if the block would
+    /// overlap a word loaded from an image or reach the devices
+    pub fn write(&self, state: &mut State, start: u16) -> Result<(), Error> {
+        let end = start as usize + self.size();
+        if end > DEVICE_PAGE {
+            return Err(LoadError::BadArguments(format!(
+                "{} words at x{:04X} reach the devices",
+                self.size(),
+                start
+            ))
+            .into());
+        }
+        if let Some(address) = (start as usize..end).find(|&address| state.loaded.contains(address))
+        {
+            return Err(LoadError::BadArguments(format!(
+                "the block at x{:04X}-x{:04X} overlaps the image at x{:04X}",
+                start,
+                end - 1,
+                address
+            ))
+            .into());
+        }
+        for (offset, word) in self.words(start).into_iter().enumerate() {
+            state.memory_write(start as usize + offset, word);
+        }
+        state.registers[Registers::R0] = start;
+        state.registers[Registers::R1] = start + 1 + self.arguments.len() as u16;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments() -> ProgramArguments {
+        ProgramArguments {
+            arguments: vec!["ab".to_string(), "7".to_string()],
+            variables: vec!["K=v".to_string()],
+        }
+    }
+
+    #[test]
+    fn the_tables_point_at_the_strings() {
+        let words = arguments().words(0x4000);
+        assert_eq!(
+            words,
+            [
+                2, 0x4005, 0x4008, // Arguments
+                1, 0x400A, // Variables
+                0x61, 0x62, 0, 0x37, 0, 0x4B, 0x3D, 0x76, 0
+            ]
+        );
+        assert_eq!(words.len(), arguments().size());
+        assert_eq!(arguments().start_below(0x4000).unwrap(), 0x4000 - 14);
+        assert!(arguments().start_below(10).is_err());
+    }
+
+    #[test]
+    fn the_block_cant_overlap_the_image() {
+        let mut state = State::default();
+        state.load_image(0x3000, &[0x1021; 4]);
+        assert!(matches!(
+            arguments().write(&mut state, 0x2FF8),
+            Err(Error::Load(LoadError::BadArguments(_)))
+        ));
+        assert!(arguments().write(&mut state, 0xFDFF).is_err());
+        arguments().write(&mut state, 0x2FF2).unwrap();
+        assert_eq!(state.registers[Registers::R0], 0x2FF2);
+        assert_eq!(state.registers[Registers::R1], 0x2FF5);
+        assert_eq!(state.memory[0x2FF2], 2);
+    }
+}
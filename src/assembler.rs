@@ -0,0 +1,1117 @@
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use thiserror::Error;
+
+pub mod diagnostic;
+mod expression;
+pub mod format;
+pub mod listing;
+mod macros;
+use diagnostic::{Diagnostic, Span};
+use expression::{Expression, Value};
+use macros::Invocation;
+
+/// Result of assembling a source file: the origin, the emitted words and the label addresses.
+/// `lines` has the source line that produced every word, expanded macros map to the line of the invocation
+#[derive(Debug)]
+pub struct Program {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub lines: Vec<usize>,
+    pub symbols: HashMap<String, u16>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Program {
+    /// Serialize the program as an object file: the origin followed by every word, all in big endian
+    pub fn to_object_bytes(&self) -> Vec<u8> {
+        crate::file_management::image_bytes(self.origin, &self.words)
+    }
+
+    /// Render the symbol table, one `LABEL xADDR` pair per line sorted by address
+    pub fn symbol_table(&self) -> String {
+        let mut symbols: Vec<(&String, &u16)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(name, address)| (**address, *name));
+        symbols
+            .iter()
+            .map(|(name, address)| format!("{} x{:04X}\n", name, address))
+            .collect()
+    }
+}
+
+/// Every diagnostic found while assembling, at least one of them is an error
+#[derive(Error, Debug, PartialEq)]
+#[error("Assembly failed with {} error(s)", self.error_count())]
+pub struct AssemblyError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl AssemblyError {
+    pub fn error_count(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.is_error()).count()
+    }
+}
+
+/// Pieces of a source line, strings are kept apart because they can contain spaces, commas and semicolons
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Text(String),
+    Str(Vec<u16>),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+impl Token {
+    fn text(&self) -> Option<&str> {
+        match &self.kind {
+            TokenKind::Text(text) => Some(text),
+            TokenKind::Str(_) => None,
+        }
+    }
+}
+
+/// A source line that emits words, ready for the second pass
+struct Statement {
+    address: u16,
+    size: u32,
+    operation: String,
+    operation_span: Span,
+    operands: Vec<Token>,
+    expansion: Vec<Invocation>,
+}
+
+/// Where the first pass is at, carried from line to line
+struct FirstPass {
+    statements: Vec<Statement>,
+    origin: Option<(u16, Span)>,
+    missing_origin_reported: bool,
+    address: u32,
+    ended: bool,
+}
+
+const DIRECTIVES: [&str; 9] = [
+    ".ORIG", ".END", ".FILL", ".BLKW", ".STRINGZ", ".EQU", ".DEFINE", ".MACRO", ".ENDM",
+];
+const MNEMONICS: [&str; 18] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR",
+    "TRAP", "RTI", "RET", "NOP",
+];
+/// Shorthands for the TRAP instruction with the vector of each routine
+pub const TRAP_ALIASES: [(&str, u16); 8] = [
+    ("GETC", 0x20),
+    ("OUT", 0x21),
+    ("PUTS", 0x22),
+    ("IN", 0x23),
+    ("PUTSP", 0x24),
+    ("HALT", 0x25),
+    ("EXIT", 0x27),
+    ("DBG", 0x28),
+];
+/// Origin used to keep computing addresses after a missing .ORIG has been reported
+const FALLBACK_ORIGIN: u16 = 0x3000;
+
+#[derive(Default)]
+struct Assembler {
+    diagnostics: Vec<Diagnostic>,
+    symbols: HashMap<String, (u16, Span)>,
+    constants: HashMap<String, (i32, Span)>,
+}
+
+/// Assemble LC-3 source code in two passes.
+/// * First pass: split every line in label, operation and operands, and assign addresses to the labels
+/// * Second pass: encode every statement now that all the labels are known
+///
+/// Operands can be constant expressions (see [`Expression`]) over literals, labels and constants defined with
+/// `NAME .EQU value` or `.DEFINE NAME value`. Constants must be defined before they are used while labels can be used anywhere.
+///
+/// Problems don't stop the assembly, every diagnostic is collected and the program is only returned if none of them is an error
+pub fn assemble(source: &str) -> Result<Program, AssemblyError> {
+    let mut assembler = Assembler::default();
+    let (origin, statements) = assembler.first_pass(source);
+    let mut words = Vec::new();
+    let mut lines = Vec::new();
+    for statement in &statements {
+        let start = words.len();
+        if let Err(diagnostic) = assembler.encode(statement, &mut words) {
+            let mut diagnostic = [diagnostic];
+            note_expansion(&mut diagnostic, &statement.expansion);
+            assembler.diagnostics.extend(diagnostic);
+            // Keep the next statements at their addresses
+            words.truncate(start);
+            words.push(0);
+        }
+        let line = statement
+            .expansion
+            .first()
+            .map_or(statement.operation_span.line, |invocation| {
+                invocation.span.line
+            });
+        lines.resize(words.len(), line);
+    }
+    if assembler.diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(AssemblyError {
+            diagnostics: assembler.diagnostics,
+        });
+    }
+    Ok(Program {
+        origin,
+        words,
+        lines,
+        symbols: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, (address, _))| (name, address))
+            .collect(),
+        warnings: assembler.diagnostics,
+    })
+}
+
+impl Assembler {
+    fn first_pass(&mut self, source: &str) -> (u16, Vec<Statement>) {
+        let mut pass = FirstPass {
+            statements: Vec::new(),
+            origin: None,
+            missing_origin_reported: false,
+            address: FALLBACK_ORIGIN as u32,
+            ended: false,
+        };
+        for line in macros::expand(source, &mut self.diagnostics) {
+            let first_diagnostic = self.diagnostics.len();
+            let flow = self.first_pass_line(line.tokens, &line.expansion, &mut pass);
+            note_expansion(&mut self.diagnostics[first_diagnostic..], &line.expansion);
+            if flow.is_break() {
+                break;
+            }
+        }
+        if !pass.ended {
+            self.diagnostics.push(Diagnostic::warning(
+                "missing .END at the end of the file",
+                Span::new(source.lines().count().max(1), 1, 0),
+            ));
+        }
+        (
+            pass.origin.map_or(FALLBACK_ORIGIN, |(origin, _)| origin),
+            pass.statements,
+        )
+    }
+
+    /// Handle a single line of the first pass, breaks when the rest of the source must be ignored
+    fn first_pass_line(
+        &mut self,
+        mut tokens: Vec<Token>,
+        expansion: &[Invocation],
+        pass: &mut FirstPass,
+    ) -> ControlFlow<()> {
+        if pass.ended {
+            let span = tokens[0].span;
+            self.diagnostics
+                .push(Diagnostic::error("code after .END", span));
+            return ControlFlow::Break(());
+        }
+        // The first token is a label unless it names an operation
+        let label = match tokens[0].text() {
+            Some(text) if !is_operation(text) => Some(tokens.remove(0)),
+            _ => None,
+        };
+        if tokens.first().is_some_and(|token| token.text().is_none()) {
+            self.diagnostics
+                .push(Diagnostic::error("expected an operation", tokens[0].span));
+            return ControlFlow::Continue(());
+        }
+        let operation = tokens
+            .first()
+            .and_then(|token| Some((token.text()?.to_uppercase(), token.span)));
+        if let Some((".ORIG", span)) = operation.as_ref().map(|(o, s)| (o.as_str(), *s)) {
+            if let Some((_, first)) = pass.origin {
+                self.diagnostics.push(
+                    Diagnostic::error("only one .ORIG is allowed", span)
+                        .with_note("first .ORIG is here", Some(first)),
+                );
+                return ControlFlow::Continue(());
+            }
+            match single_operand(&tokens[1..], span)
+                .and_then(|token| unsigned_field(self.evaluate(token)?.value, 16, token.span))
+            {
+                Ok(value) => {
+                    pass.origin = Some((value, span));
+                    pass.address = value as u32;
+                }
+                Err(diagnostic) => {
+                    pass.origin = Some((FALLBACK_ORIGIN, span));
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+            return ControlFlow::Continue(());
+        }
+        // Constants don't emit words so they can be defined before .ORIG
+        match operation.as_ref().map(|(o, s)| (o.as_str(), *s)) {
+            Some((".EQU", span)) => {
+                match (&label, single_operand(&tokens[1..], span)) {
+                    (Some(name), Ok(value)) => self.define_constant(name, value),
+                    (None, _) => self
+                        .diagnostics
+                        .push(Diagnostic::error(".EQU needs a name before it", span)),
+                    (_, Err(diagnostic)) => self.diagnostics.push(diagnostic),
+                }
+                return ControlFlow::Continue(());
+            }
+            Some((".DEFINE", span)) => {
+                match &tokens[1..] {
+                    [name, value] => self.define_constant(name, value),
+                    _ => self.diagnostics.push(Diagnostic::error(
+                        ".DEFINE expects a name and a value",
+                        span,
+                    )),
+                }
+                return ControlFlow::Continue(());
+            }
+            _ => {}
+        }
+        if pass.origin.is_none() && !pass.missing_origin_reported {
+            let span = label.as_ref().unwrap_or(&tokens[0]).span;
+            self.diagnostics.push(Diagnostic::error(
+                "missing .ORIG before the first statement",
+                span,
+            ));
+            pass.missing_origin_reported = true;
+        }
+        if let Some(label) = label {
+            self.define_label(label, pass.address as u16);
+        }
+        let Some((operation, operation_span)) = operation else {
+            return ControlFlow::Continue(());
+        };
+        let operands = tokens.split_off(1);
+        let size = match operation.as_str() {
+            ".END" => {
+                pass.ended = true;
+                return ControlFlow::Continue(());
+            }
+            ".BLKW" => match single_operand(&operands, operation_span)
+                .and_then(|token| Ok((self.evaluate(token)?.value, token.span)))
+            {
+                Ok((amount, _)) if amount >= 0 => amount as u32,
+                Ok((_, span)) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        ".BLKW needs a non negative amount of words",
+                        span,
+                    ));
+                    return ControlFlow::Continue(());
+                }
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic.with_note(
+                        "the size of .BLKW must be known when the line is reached",
+                        None,
+                    ));
+                    return ControlFlow::Continue(());
+                }
+            },
+            ".STRINGZ" => match operands.as_slice() {
+                [
+                    Token {
+                        kind: TokenKind::Str(text),
+                        ..
+                    },
+                ] => text.len() as u32 + 1,
+                _ => {
+                    self.diagnostics.push(Diagnostic::error(
+                        ".STRINGZ expects a single string",
+                        operation_span,
+                    ));
+                    return ControlFlow::Continue(());
+                }
+            },
+            _ => 1,
+        };
+        if pass.address + size > 0x10000 {
+            self.diagnostics.push(Diagnostic::error(
+                "the program does not fit in memory",
+                operation_span,
+            ));
+            return ControlFlow::Break(());
+        }
+        pass.statements.push(Statement {
+            address: pass.address as u16,
+            size,
+            operation,
+            operation_span,
+            operands,
+            expansion: expansion.to_vec(),
+        });
+        pass.address += size;
+        ControlFlow::Continue(())
+    }
+
+    fn define_label(&mut self, label: Token, address: u16) {
+        let Some(name) = label.text() else {
+            return;
+        };
+        if !is_valid_label(name) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("`{}` is not a valid label", name),
+                label.span,
+            ));
+            return;
+        }
+        if let Some(diagnostic) = self.already_defined(name, label.span) {
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+        self.symbols.insert(name.to_string(), (address, label.span));
+    }
+
+    fn define_constant(&mut self, name: &Token, value: &Token) {
+        let Some(text) = name.text().filter(|text| is_valid_label(text)) else {
+            self.diagnostics
+                .push(Diagnostic::error("expected a constant name", name.span));
+            return;
+        };
+        if let Some(diagnostic) = self.already_defined(text, name.span) {
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+        match self.evaluate(value) {
+            Ok(value) => {
+                self.constants
+                    .insert(text.to_string(), (value.value, name.span));
+            }
+            Err(diagnostic) => self.diagnostics.push(diagnostic),
+        }
+    }
+
+    /// Labels and constants share the same names
+    fn already_defined(&self, name: &str, span: Span) -> Option<Diagnostic> {
+        let first = match (self.symbols.get(name), self.constants.get(name)) {
+            (Some((_, first)), _) | (_, Some((_, first))) => first,
+            _ => return None,
+        };
+        Some(
+            Diagnostic::error(format!("`{}` is already defined", name), span)
+                .with_note(format!("`{}` is first defined here", name), Some(*first)),
+        )
+    }
+
+    /// Evaluate an operand with the labels known at this point and the constants defined before its line
+    fn evaluate(&self, token: &Token) -> Result<Value, Diagnostic> {
+        let text = token
+            .text()
+            .ok_or_else(|| Diagnostic::error("unexpected string", token.span))?;
+        let mut definition = None;
+        let mut resolve = |name: &str| {
+            if let Some((value, span)) = self.constants.get(name) {
+                if span.line >= token.span.line {
+                    definition = Some(*span);
+                    return Err(format!("constant `{}` is used before its definition", name));
+                }
+                return Ok(Value {
+                    value: *value,
+                    is_address: false,
+                });
+            }
+            match self.symbols.get(name) {
+                Some((address, _)) => Ok(Value {
+                    value: *address as i32,
+                    is_address: true,
+                }),
+                None => Err(format!("undefined label `{}`", name)),
+            }
+        };
+        let result = Expression::parse(text).and_then(|e| e.evaluate(&mut resolve));
+        result.map_err(|message| {
+            let diagnostic = Diagnostic::error(message, token.span);
+            match definition {
+                Some(span) => diagnostic.with_note("the constant is defined here", Some(span)),
+                None => diagnostic,
+            }
+        })
+    }
+
+    /// A number is used as it is, an address (an expression with labels) is turned into an offset from the incremented PC.
+    /// Returns the offset and its encoding in the given amount of bits
+    fn pc_offset(
+        &self,
+        token: &Token,
+        bits: u32,
+        statement: &Statement,
+    ) -> Result<(i32, u16), Diagnostic> {
+        let value = self.evaluate(token)?;
+        let offset = if value.is_address {
+            value.value - (statement.address as i32 + 1)
+        } else {
+            value.value
+        };
+        Ok((offset, signed_field(offset, bits, token.span)?))
+    }
+
+    fn encode(&mut self, statement: &Statement, words: &mut Vec<u16>) -> Result<(), Diagnostic> {
+        let operands = &statement.operands;
+        let word = match statement.operation.as_str() {
+            ".FILL" => {
+                let [value] = expect_operands(statement, 1)? else {
+                    unreachable!()
+                };
+                let number = self.evaluate(value)?.value;
+                if !(-0x8000..=0xFFFF).contains(&number) {
+                    return Err(out_of_range(number, 16, value.span));
+                }
+                number as u16
+            }
+            ".BLKW" => {
+                words.extend(std::iter::repeat_n(0, statement.size as usize));
+                return Ok(());
+            }
+            ".STRINGZ" => {
+                if let [
+                    Token {
+                        kind: TokenKind::Str(text),
+                        ..
+                    },
+                ] = operands.as_slice()
+                {
+                    words.extend(text);
+                }
+                words.push(0);
+                return Ok(());
+            }
+            "ADD" | "AND" => {
+                let [destination, source, last] = expect_operands(statement, 3)? else {
+                    unreachable!()
+                };
+                let op_code = if statement.operation == "ADD" {
+                    0x1
+                } else {
+                    0x5
+                };
+                let base =
+                    (op_code << 12) | (register(destination)? << 9) | (register(source)? << 6);
+                if looks_like_register(last) {
+                    base | register(last)?
+                } else {
+                    base | 0x20 | self.immediate_5(last)?
+                }
+            }
+            "NOT" => {
+                let [destination, source] = expect_operands(statement, 2)? else {
+                    unreachable!()
+                };
+                0x903F | (register(destination)? << 9) | (register(source)? << 6)
+            }
+            "JMP" | "JSRR" => {
+                let [base] = expect_operands(statement, 1)? else {
+                    unreachable!()
+                };
+                let op_code = if statement.operation == "JMP" {
+                    0xC
+                } else {
+                    0x4
+                };
+                (op_code << 12) | (register(base)? << 6)
+            }
+            "JSR" => {
+                let [target] = expect_operands(statement, 1)? else {
+                    unreachable!()
+                };
+                0x4800 | self.pc_offset(target, 11, statement)?.1
+            }
+            "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+                let [register_token, target] = expect_operands(statement, 2)? else {
+                    unreachable!()
+                };
+                let op_code = match statement.operation.as_str() {
+                    "LD" => 0x2,
+                    "LDI" => 0xA,
+                    "LEA" => 0xE,
+                    "ST" => 0x3,
+                    _ => 0xB,
+                };
+                (op_code << 12)
+                    | (register(register_token)? << 9)
+                    | self.pc_offset(target, 9, statement)?.1
+            }
+            "LDR" | "STR" => {
+                let [register_token, base, offset] = expect_operands(statement, 3)? else {
+                    unreachable!()
+                };
+                let op_code = if statement.operation == "LDR" {
+                    0x6
+                } else {
+                    0x7
+                };
+                (op_code << 12)
+                    | (register(register_token)? << 9)
+                    | (register(base)? << 6)
+                    | signed_field(self.evaluate(offset)?.value, 6, offset.span)?
+            }
+            "TRAP" => {
+                let [vector] = expect_operands(statement, 1)? else {
+                    unreachable!()
+                };
+                0xF000 | unsigned_field(self.evaluate(vector)?.value, 8, vector.span)?
+            }
+            "RTI" => {
+                expect_operands(statement, 0)?;
+                0x8000
+            }
+            // JMP R7
+            "RET" => {
+                expect_operands(statement, 0)?;
+                0xC1C0
+            }
+            // A branch that doesn't test any flag never jumps
+            "NOP" => {
+                expect_operands(statement, 0)?;
+                0x0000
+            }
+            operation => {
+                if let Some(vector) = trap_alias(operation) {
+                    expect_operands(statement, 0)?;
+                    0xF000 | vector
+                } else if let Some(flags) = branch_flags(operation) {
+                    let [target] = expect_operands(statement, 1)? else {
+                        unreachable!()
+                    };
+                    let (offset, field) = self.pc_offset(target, 9, statement)?;
+                    if offset == 0 {
+                        self.diagnostics.push(Diagnostic::warning(
+                            "branch to the next instruction has no effect",
+                            target.span,
+                        ));
+                    }
+                    (flags << 9) | field
+                } else {
+                    return Err(Diagnostic::error(
+                        format!("unknown operation `{}`", operation),
+                        statement.operation_span,
+                    ));
+                }
+            }
+        };
+        words.push(word);
+        Ok(())
+    }
+
+    /// Values from 16 to 31 fit in the 5 bits but change their sign, they are accepted with a warning
+    fn immediate_5(&mut self, token: &Token) -> Result<u16, Diagnostic> {
+        let value = self.evaluate(token)?.value;
+        if (16..32).contains(&value) {
+            self.diagnostics.push(Diagnostic::warning(
+                format!(
+                    "`{}` does not fit in a signed 5 bit immediate, it is encoded as #{}",
+                    token.text().unwrap_or_default(),
+                    value - 32
+                ),
+                token.span,
+            ));
+            return Ok(value as u16);
+        }
+        signed_field(value, 5, token.span)
+    }
+}
+
+/// Point diagnostics produced by expanded code at the invocations, innermost first
+fn note_expansion(diagnostics: &mut [Diagnostic], expansion: &[Invocation]) {
+    for diagnostic in diagnostics {
+        for invocation in expansion.iter().rev() {
+            diagnostic.notes.push(diagnostic::Note {
+                message: format!("in expansion of macro `{}` invoked here", invocation.name),
+                span: Some(invocation.span),
+            });
+        }
+    }
+}
+
+fn is_operation(text: &str) -> bool {
+    let upper = text.to_uppercase();
+    DIRECTIVES.contains(&upper.as_str()) || is_mnemonic(&upper)
+}
+
+fn is_mnemonic(upper: &str) -> bool {
+    MNEMONICS.contains(&upper) || trap_alias(upper).is_some() || branch_flags(upper).is_some()
+}
+
+fn trap_alias(upper: &str) -> Option<u16> {
+    TRAP_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == upper)
+        .map(|(_, vector)| *vector)
+}
+
+/// Labels start with a letter or an underscore and continue with letters, digits or underscores
+fn is_valid_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_number(text).is_none()
+}
+
+/// Returns the n, z and p bits of a branch mnemonic, a plain BR tests every flag
+fn branch_flags(upper: &str) -> Option<u16> {
+    let flags = upper.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0x7);
+    }
+    let mut bits = 0;
+    let mut last = 3;
+    for c in flags.chars() {
+        // Flags must appear in n, z, p order and only once
+        let (bit, position) = match c {
+            'N' => (0x4, 0),
+            'Z' => (0x2, 1),
+            'P' => (0x1, 2),
+            _ => return None,
+        };
+        if last != 3 && position <= last {
+            return None;
+        }
+        last = position;
+        bits |= bit;
+    }
+    Some(bits)
+}
+
+/// Split a line in tokens, removing the comment and decoding string literals
+fn tokenize(text: &str, line: usize) -> Result<Vec<Token>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut chars = text.chars().enumerate();
+    let push_text = |tokens: &mut Vec<Token>, current: &mut String, start: usize| {
+        if !current.is_empty() {
+            let length = current.chars().count();
+            tokens.push(Token {
+                kind: TokenKind::Text(std::mem::take(current)),
+                span: Span::new(line, start + 1, length),
+            });
+        }
+    };
+    while let Some((column, c)) = chars.next() {
+        if current.is_empty() {
+            start = column;
+        }
+        match c {
+            ';' => break,
+            '"' => {
+                push_text(&mut tokens, &mut current, start);
+                let mut words = Vec::new();
+                let mut end = None;
+                while let Some((position, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            end = Some(position + 1);
+                            break;
+                        }
+                        '\\' => {
+                            let Some((position, escaped)) = chars.next() else {
+                                break;
+                            };
+                            let word = unescape(escaped).ok_or_else(|| {
+                                Diagnostic::error(
+                                    format!("bad escape sequence `\\{}`", escaped),
+                                    Span::new(line, position, 2),
+                                )
+                            })?;
+                            words.push(word);
+                        }
+                        c if !c.is_ascii() => return Err(not_ascii(c, line, position)),
+                        c => words.push(c as u16),
+                    }
+                }
+                let Some(end) = end else {
+                    let length = text.chars().count() - column;
+                    return Err(Diagnostic::error(
+                        "unterminated string",
+                        Span::new(line, column + 1, length),
+                    ));
+                };
+                tokens.push(Token {
+                    kind: TokenKind::Str(words),
+                    span: Span::new(line, column + 1, end - column),
+                });
+            }
+            '\'' => {
+                // Character literals are kept as text with their quotes, they are decoded as numbers
+                current.push(c);
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            current.push('\\');
+                            if let Some((_, escaped)) = chars.next() {
+                                current.push(escaped);
+                            }
+                        }
+                        Some((_, '\'')) => {
+                            current.push('\'');
+                            break;
+                        }
+                        Some((position, other)) if !other.is_ascii() => {
+                            return Err(not_ascii(other, line, position));
+                        }
+                        Some((_, other)) => current.push(other),
+                        None => {
+                            return Err(Diagnostic::error(
+                                "unterminated character literal",
+                                Span::new(line, start + 1, current.chars().count()),
+                            ));
+                        }
+                    }
+                }
+            }
+            c if c.is_whitespace() || c == ',' => push_text(&mut tokens, &mut current, start),
+            c => current.push(c),
+        }
+    }
+    push_text(&mut tokens, &mut current, start);
+    Ok(tokens)
+}
+
+/// The traps print a byte per character, one that isn't ASCII wouldn't come out as written
+fn not_ascii(c: char, line: usize, position: usize) -> Diagnostic {
+    Diagnostic::error(
+        format!(
+            "`{}` isn't an ASCII character, the traps print a byte per character",
+            c
+        ),
+        Span::new(line, position + 1, 1),
+    )
+}
+
+fn unescape(escaped: char) -> Option<u16> {
+    match escaped {
+        'n' => Some(b'\n' as u16),
+        't' => Some(b'\t' as u16),
+        '"' => Some(b'"' as u16),
+        '\'' => Some(b'\'' as u16),
+        '\\' => Some(b'\\' as u16),
+        '0' => Some(0),
+        _ => None,
+    }
+}
+
+/// Parse a numeric literal
+/// * Decimal: `#10`, `#-10` or `10`
+/// * Hexadecimal: `x0A`, `0x0A` or `x-1`
+/// * Character: `'A'` or `'\n'`
+fn parse_number(text: &str) -> Option<i32> {
+    if let Some(decimal) = text.strip_prefix('#') {
+        return decimal.parse().ok();
+    }
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        return i32::from_str_radix(hex, 16).ok();
+    }
+    if let Some(hex) = text.strip_prefix(['x', 'X']) {
+        let (sign, digits) = match hex.strip_prefix('-') {
+            Some(digits) => (-1, digits),
+            None => (1, hex),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        return i32::from_str_radix(digits, 16)
+            .ok()
+            .map(|value| sign * value);
+    }
+    if let Some(quoted) = text.strip_prefix('\'') {
+        let inner = quoted.strip_suffix('\'')?;
+        let mut chars = inner.chars();
+        let value = match (chars.next()?, chars.next(), chars.next()) {
+            ('\\', Some(escaped), None) => unescape(escaped)?,
+            (c, None, None) => c as u16,
+            _ => return None,
+        };
+        return Some(value as i32);
+    }
+    text.parse().ok()
+}
+
+fn single_operand(operands: &[Token], operation_span: Span) -> Result<&Token, Diagnostic> {
+    match operands {
+        [token] => Ok(token),
+        _ => Err(Diagnostic::error("expected 1 operand", operation_span)),
+    }
+}
+
+fn expect_operands(statement: &Statement, expected: usize) -> Result<&[Token], Diagnostic> {
+    if statement.operands.len() != expected {
+        return Err(Diagnostic::error(
+            format!(
+                "`{}` expects {} operand(s) but {} were given",
+                statement.operation,
+                expected,
+                statement.operands.len()
+            ),
+            statement.operation_span,
+        ));
+    }
+    if let Some(string) = statement.operands.iter().find(|t| t.text().is_none()) {
+        return Err(Diagnostic::error("unexpected string", string.span));
+    }
+    Ok(&statement.operands)
+}
+
+fn register(token: &Token) -> Result<u16, Diagnostic> {
+    match token.text().map(str::as_bytes) {
+        Some([b'R' | b'r', digit @ b'0'..=b'7']) => Ok((digit - b'0') as u16),
+        _ => Err(Diagnostic::error(
+            "expected a register (R0 to R7)",
+            token.span,
+        )),
+    }
+}
+
+/// An R followed by digits is meant to be a register even if it doesn't exist, like R9
+fn looks_like_register(token: &Token) -> bool {
+    token.text().is_some_and(|text| {
+        text.len() > 1
+            && text.starts_with(['R', 'r'])
+            && text[1..].chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+fn out_of_range(value: i32, bits: u32, span: Span) -> Diagnostic {
+    Diagnostic::error(format!("`{}` does not fit in {} bits", value, bits), span)
+}
+
+/// Check that a signed value fits in the given amount of bits and return its two's complement encoding
+fn signed_field(value: i32, bits: u32, span: Span) -> Result<u16, Diagnostic> {
+    let limit = 1 << (bits - 1);
+    if value < -limit || value >= limit {
+        return Err(out_of_range(value, bits, span));
+    }
+    Ok((value as u16) & ((1 << bits) - 1) as u16)
+}
+
+fn unsigned_field(value: i32, bits: u32, span: Span) -> Result<u16, Diagnostic> {
+    if value < 0 || value >= 1 << bits {
+        return Err(out_of_range(value, bits, span));
+    }
+    Ok(value as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_error(source: &str) -> Diagnostic {
+        assemble(source).unwrap_err().diagnostics.remove(0)
+    }
+
+    #[test]
+    fn directives_match_golden_object() {
+        let program = assemble(include_str!("../tests/fixtures/directives.asm")).unwrap();
+        assert_eq!(
+            program.to_object_bytes(),
+            include_bytes!("../tests/fixtures/directives.obj")
+        );
+        assert_eq!(program.symbols["MSG"], 0x300C);
+        assert_eq!(program.symbols["BUFFER"], 0x3009);
+        assert!(program.warnings.is_empty());
+        assert!(
+            program
+                .symbol_table()
+                .starts_with("VALUE x3004\nHEX x3005\n")
+        );
+    }
+
+    #[test]
+    fn label_on_its_own_line_points_at_next_word() {
+        let program = assemble(".ORIG x4000\nHERE\n.FILL #1\nSTART BR HERE\n.END").unwrap();
+        assert_eq!(program.symbols["HERE"], 0x4000);
+        assert_eq!(program.words, vec![0x0001, 0x0FFE]);
+    }
+
+    #[test]
+    fn stringz_escapes() {
+        let program = assemble(".ORIG x3000\n.STRINGZ \"a;b\\\"\\\\\\t\\n\\0\"\n.END").unwrap();
+        assert_eq!(
+            program.words,
+            vec![0x61, 0x3B, 0x62, 0x22, 0x5C, 0x09, 0x0A, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn missing_orig() {
+        let error = first_error("; comment\nADD R1, R1, #1\n.END");
+        assert_eq!(error.message, "missing .ORIG before the first statement");
+        assert_eq!(error.span, Span::new(2, 1, 3));
+    }
+
+    #[test]
+    fn code_after_end() {
+        let error = first_error(".ORIG x3000\n.END\n\n  RTI");
+        assert_eq!(error.message, "code after .END");
+        assert_eq!(error.span, Span::new(4, 3, 3));
+    }
+
+    #[test]
+    fn negative_blkw() {
+        let error = first_error(".ORIG x3000\n.BLKW #-2\n.END");
+        assert_eq!(error.message, ".BLKW needs a non negative amount of words");
+        assert_eq!(error.span, Span::new(2, 7, 3));
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let error = first_error(".ORIG x3000\n.STRINGZ \"hello\n.END");
+        assert_eq!(error.message, "unterminated string");
+        assert_eq!(error.span, Span::new(2, 10, 6));
+    }
+
+    #[test]
+    fn characters_that_arent_ascii_are_refused() {
+        let error = first_error(".ORIG x3000\n.STRINGZ \"a\u{1F600}\"\n.END");
+        assert_eq!(
+            error.message,
+            "`\u{1F600}` isn't an ASCII character, the traps print a byte per character"
+        );
+        assert_eq!(error.span, Span::new(2, 12, 1));
+        let error = first_error(".ORIG x3000\n.FILL '\u{1F600}'\n.END");
+        assert_eq!(error.span, Span::new(2, 8, 1));
+        let error = first_error(".ORIG x3000\n.STRINGZ \"\u{E9}\"\n.END");
+        assert!(
+            error
+                .message
+                .starts_with("`\u{E9}` isn't an ASCII character")
+        );
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let source = include_str!("../tests/fixtures/five_errors.asm");
+        let diagnostics = assemble(source).unwrap_err().diagnostics;
+        let errors: Vec<(&str, Span)> = diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| (d.message.as_str(), d.span))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("unterminated string", Span::new(5, 18, 5)),
+                ("`LOOP` is already defined", Span::new(7, 1, 4)),
+                ("expected a register (R0 to R7)", Span::new(3, 21, 2)),
+                ("unknown operation `MUL`", Span::new(4, 9, 3)),
+                ("undefined label `LOP`", Span::new(8, 15, 3)),
+            ]
+        );
+        let duplicate = &diagnostics[1];
+        assert_eq!(duplicate.notes[0].span, Some(Span::new(2, 1, 4)));
+        let rendered = duplicate.render("five_errors.asm", source);
+        assert!(rendered.contains(" --> five_errors.asm:7:1\n"));
+        assert!(rendered.contains("7 | LOOP    ADD R1, R1, #-1\n  | ^^^^\n"));
+        assert!(rendered.contains(" --> five_errors.asm:2:1\n"));
+    }
+
+    #[test]
+    fn aliases() {
+        let program = assemble(
+            ".ORIG x3000\nRET\nNOP\nGETC\nOUT\nPUTS\nIN\nPUTSP\nHALT\nhalt\nEXIT\nDBG\n.END",
+        )
+        .unwrap();
+        assert_eq!(
+            program.words,
+            vec![
+                0xC1C0, 0x0000, 0xF020, 0xF021, 0xF022, 0xF023, 0xF024, 0xF025, 0xF025, 0xF027,
+                0xF028
+            ]
+        );
+        assert_eq!(
+            first_error(".ORIG x3000\nHALT R0\n.END").message,
+            "`HALT` expects 0 operand(s) but 1 were given"
+        );
+    }
+
+    #[test]
+    fn operand_syntaxes() {
+        let program = assemble(
+            ".ORIG x3000\nadd r1, R2, #10\nADD R1, r2, x0A\nADD R1, R2, 0x0A\nADD R1, R2, 10\nand r7, r7, #-16\n.END",
+        )
+        .unwrap();
+        assert_eq!(program.words, vec![0x12AA, 0x12AA, 0x12AA, 0x12AA, 0x5FF0]);
+    }
+
+    #[test]
+    fn label_operands_for_pc_relative_instructions() {
+        let source = ".ORIG x3000
+            LD R0, DATA
+            LDI R1, DATA
+            LEA R2, DATA
+            ST R3, DATA
+            STI R4, DATA
+            BRnp DATA
+            JSR DATA
+DATA        .FILL x1234
+            .END";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.words,
+            vec![
+                0x2006, 0xA205, 0xE404, 0x3603, 0xB802, 0x0A01, 0x4800, 0x1234
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let source = "COUNT   .EQU 10
+        .DEFINE SIZE COUNT/5
+        .ORIG x3000
+        ADD R1, R1, #COUNT-1
+        LD R2, TABLE+1
+        BRnzp (COUNT-8)*2
+TABLE   .FILL TABLE+2
+        .FILL -SIZE
+        .BLKW SIZE*2
+END     .FILL END-TABLE
+        .END";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program.words,
+            vec![0x1269, 0x2402, 0x0E04, 0x3005, 0xFFFE, 0, 0, 0, 0, 0x0006]
+        );
+        assert!(!program.symbols.contains_key("COUNT"));
+    }
+
+    #[test]
+    fn expression_errors() {
+        let error = first_error(".ORIG x3000\n.DEFINE BIG 8\nADD R1, R1, BIG*4\n.END");
+        assert_eq!(error.message, "`32` does not fit in 5 bits");
+        assert_eq!(error.span, Span::new(3, 13, 5));
+        let error = first_error(".ORIG x3000\n.FILL LATE\nLATE .EQU 1\n.END");
+        assert_eq!(
+            error.message,
+            "constant `LATE` is used before its definition"
+        );
+        assert_eq!(error.notes[0].span, Some(Span::new(3, 1, 4)));
+        let error = first_error(".ORIG x3000\n.FILL 1/(2-2)\n.END");
+        assert_eq!(error.message, "division by zero");
+        let error = first_error(".ORIG x3000\n.FILL x7FFF*x7FFF*4\n.END");
+        assert_eq!(error.message, "expression overflows");
+        let error = first_error(".ORIG x3000\n.BLKW AFTER\nAFTER .FILL 0\n.END");
+        assert_eq!(error.message, "undefined label `AFTER`");
+    }
+
+    #[test]
+    fn suspicious_code_produces_warnings() {
+        let program =
+            assemble(".ORIG x3000\nADD R1, R1, #20\nBRz NEXT\nNEXT ADD R1, R1, #1\n").unwrap();
+        let warnings: Vec<&String> = program.warnings.iter().map(|d| &d.message).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "missing .END at the end of the file",
+                "`#20` does not fit in a signed 5 bit immediate, it is encoded as #-12",
+                "branch to the next instruction has no effect",
+            ]
+        );
+        let program = assemble(".ORIG x3000\nADD R1, R1, x1F\n.END").unwrap();
+        assert_eq!(program.words, vec![0x127F]);
+        assert_eq!(program.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Position of a piece of source code, line and column start at 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, length: usize) -> Span {
+        Span {
+            line,
+            column,
+            length,
+        }
+    }
+}
+
+/// Extra information attached to a diagnostic, it can point at a different place of the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// A problem found while assembling, errors stop the object file from being written while warnings don't
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        self.notes.push(Note {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render the diagnostic with the offending source line and a caret underline, followed by its notes:
+    /// ```text
+    /// error: undefined label `LOP`
+    ///  --> prog.asm:5:11
+    ///   |
+    /// 5 |     BRnzp LOP
+    ///   |           ^^^
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut output = format!("{}: {}\n", severity, self.message);
+        render_snippet(&mut output, file_name, source, self.span);
+        for note in &self.notes {
+            let _ = writeln!(output, "note: {}", note.message);
+            if let Some(span) = note.span {
+                render_snippet(&mut output, file_name, source, span);
+            }
+        }
+        output
+    }
+}
+
+fn render_snippet(output: &mut String, file_name: &str, source: &str, span: Span) {
+    let gutter = " ".repeat(span.line.to_string().len());
+    let _ = writeln!(
+        output,
+        "{}--> {}:{}:{}",
+        gutter, file_name, span.line, span.column
+    );
+    let Some(text) = source.lines().nth(span.line - 1) else {
+        return;
+    };
+    // Keep the tabs of the source line so the caret stays aligned
+    let padding: String = text
+        .chars()
+        .take(span.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let _ = writeln!(output, "{} |", gutter);
+    let _ = writeln!(output, "{} | {}", span.line, text);
+    let _ = writeln!(
+        output,
+        "{} | {}{}",
+        gutter,
+        padding,
+        "^".repeat(span.length.max(1))
+    );
+}
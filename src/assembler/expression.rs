@@ -0,0 +1,217 @@
+use super::parse_number;
+
+/// Constant expression over i32 with `+`, `-`, `*`, `/`, unary minus and parentheses.
+/// The operands are number literals or symbol names (labels and constants)
+#[derive(Debug, PartialEq)]
+pub enum Expression {
+    Number(i32),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+/// Result of evaluating an expression, `is_address` is true when a label takes part in it
+#[derive(Debug, PartialEq)]
+pub struct Value {
+    pub value: i32,
+    pub is_address: bool,
+}
+
+impl Expression {
+    /// Parse an operand, a leading `#` is accepted so `#COUNT-1` works like `#10`
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        // Plain literals first, they include forms like x-1 that would read as a subtraction
+        if let Some(value) = parse_number(text) {
+            return Ok(Expression::Number(value));
+        }
+        let text = text.strip_prefix('#').unwrap_or(text);
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        let expression = parser.sum()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(c) => Err(format!("unexpected `{}` in expression", c)),
+        }
+    }
+
+    /// Evaluate the expression, `resolve` returns the value of a symbol and if it is a label
+    pub fn evaluate(
+        &self,
+        resolve: &mut dyn FnMut(&str) -> Result<Value, String>,
+    ) -> Result<Value, String> {
+        match self {
+            Expression::Number(value) => Ok(Value {
+                value: *value,
+                is_address: false,
+            }),
+            Expression::Symbol(name) => resolve(name),
+            Expression::Negate(inner) => {
+                let inner = inner.evaluate(resolve)?;
+                Ok(Value {
+                    value: inner.value.checked_neg().ok_or("expression overflows")?,
+                    is_address: inner.is_address,
+                })
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(resolve)?;
+                let right = right.evaluate(resolve)?;
+                let value = match operator {
+                    '+' => left.value.checked_add(right.value),
+                    '-' => left.value.checked_sub(right.value),
+                    '*' => left.value.checked_mul(right.value),
+                    _ if right.value == 0 => return Err("division by zero".to_string()),
+                    _ => left.value.checked_div(right.value),
+                };
+                Ok(Value {
+                    value: value.ok_or("expression overflows")?,
+                    is_address: left.is_address || right.is_address,
+                })
+            }
+        }
+    }
+}
+
+/// Recursive descent parser, one function per precedence level
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut expression = self.product()?;
+        while let Some(operator @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            let right = self.product()?;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        let mut expression = self.unary()?;
+        while let Some(operator @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            let right = self.unary()?;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                Ok(Expression::Negate(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.position += 1;
+                let expression = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err("missing `)` in expression".to_string());
+                }
+                self.position += 1;
+                Ok(expression)
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expression, String> {
+        let start = self.position;
+        if self.peek() == Some('\'') {
+            // Character literal, up to the closing quote
+            self.position += 1;
+            while let Some(c) = self.peek() {
+                self.position += 1;
+                if c == '\\' {
+                    self.position += 1;
+                } else if c == '\'' {
+                    break;
+                }
+            }
+        } else {
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '#')
+            {
+                self.position += 1;
+            }
+        }
+        let text: String = self.chars[start..self.position.min(self.chars.len())]
+            .iter()
+            .collect();
+        if text.is_empty() {
+            return Err(match self.peek() {
+                Some(c) => format!("unexpected `{}` in expression", c),
+                None => "expression ends unexpectedly".to_string(),
+            });
+        }
+        if let Some(value) = parse_number(&text) {
+            return Ok(Expression::Number(value));
+        }
+        if text.starts_with(|c: char| c.is_ascii_digit() || c == '#' || c == '\'') {
+            return Err(format!("bad number `{}`", text));
+        }
+        Ok(Expression::Symbol(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str) -> Result<i32, String> {
+        Expression::parse(text)?
+            .evaluate(&mut |name| match name {
+                "COUNT" => Ok(Value {
+                    value: 10,
+                    is_address: false,
+                }),
+                "LABEL" => Ok(Value {
+                    value: 0x3000,
+                    is_address: true,
+                }),
+                _ => Err(format!("undefined label `{}`", name)),
+            })
+            .map(|value| value.value)
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(evaluate("#COUNT-1"), Ok(9));
+        assert_eq!(evaluate("2+3*4"), Ok(14));
+        assert_eq!(evaluate("(2+3)*4"), Ok(20));
+        assert_eq!(evaluate("-COUNT/3"), Ok(-3));
+        assert_eq!(evaluate("LABEL+x10"), Ok(0x3010));
+        assert_eq!(evaluate("x-1"), Ok(-1));
+        assert_eq!(evaluate("'A'+1"), Ok(66));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(evaluate("COUNT/0"), Err("division by zero".to_string()));
+        assert_eq!(
+            evaluate("x7FFFFFFF+1"),
+            Err("expression overflows".to_string())
+        );
+        assert_eq!(
+            evaluate("(1+2"),
+            Err("missing `)` in expression".to_string())
+        );
+        assert_eq!(
+            evaluate("1+"),
+            Err("expression ends unexpectedly".to_string())
+        );
+        assert_eq!(
+            evaluate("OTHER"),
+            Err("undefined label `OTHER`".to_string())
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+
+use super::{Token, TokenKind, branch_flags, is_operation, parse_number, tokenize};
+
+/// Column where operations start, labels are written before it
+const OPERATION_COLUMN: usize = 8;
+/// Column where operands start, wide enough for `.STRINGZ`
+const OPERANDS_COLUMN: usize = 17;
+const COMMENT_COLUMN: usize = 32;
+
+#[derive(Default)]
+pub struct FormatOptions {
+    /// Write hexadecimal literals as `0x1F` instead of `x1F`
+    pub zero_x_hex: bool,
+    /// Put every label on its own line instead of before its operation
+    pub labels_on_own_line: bool,
+}
+
+/// Format assembler source: labels, operations, operands and comments are aligned in columns,
+/// mnemonics and registers are upper case, decimal literals get their `#` and hexadecimal literals a single prefix.
+/// Comments and blank lines are kept, and lines that can't be tokenized are left as they are.
+/// Formatting never changes the assembled words and formatting twice gives the same result
+pub fn format(source: &str, options: &FormatOptions) -> String {
+    let macros = macro_names(source);
+    let mut parameters: HashSet<String> = HashSet::new();
+    let mut output = String::new();
+    for (index, text) in source.lines().enumerate() {
+        let (code, comment) = split_comment(text);
+        let tokens = match tokenize(code, index + 1) {
+            Ok(tokens) if tokens.first().is_none_or(|token| token.text().is_some()) => tokens,
+            _ => {
+                output.push_str(text.trim_end());
+                output.push('\n');
+                continue;
+            }
+        };
+        let Some(first) = tokens.first().and_then(Token::text) else {
+            // Blank lines and comments on their own line, indented comments go to the operation column
+            if let Some(comment) = comment {
+                if text.starts_with(char::is_whitespace) {
+                    output.push_str(&" ".repeat(OPERATION_COLUMN));
+                }
+                output.push_str(comment);
+            }
+            output.push('\n');
+            continue;
+        };
+        let is_operation = |token: &Token| {
+            token
+                .text()
+                .is_some_and(|text| is_operation(text) || macros.contains(&text.to_uppercase()))
+        };
+        let (label, rest) = match is_operation(&tokens[0]) {
+            true => (None, &tokens[..]),
+            false => (Some(first), &tokens[1..]),
+        };
+        let mut line = String::new();
+        if let Some(label) = label {
+            line.push_str(label);
+            // Unknown operations stay after their label, on their own they would read as a label.
+            // The name of a .EQU constant must stay on its line too
+            let splits = rest.first().is_some_and(|operation| {
+                is_operation(operation)
+                    && !operation
+                        .text()
+                        .is_some_and(|text| text.eq_ignore_ascii_case(".EQU"))
+            });
+            if options.labels_on_own_line && splits {
+                output.push_str(&line);
+                output.push('\n');
+                line.clear();
+            }
+        }
+        if let Some((operation, operands)) = rest.split_first() {
+            let operation = operation.text().map_or(String::new(), normalize_operation);
+            pad(&mut line, OPERATION_COLUMN);
+            line.push_str(&operation);
+            let mut operands: Vec<String> = operands
+                .iter()
+                .map(|operand| match operand.text() {
+                    Some(text) if parameters.contains(text) => text.to_string(),
+                    Some(text) => normalize_operand(text, options),
+                    None => original_text(code, operand),
+                })
+                .collect();
+            match operation.as_str() {
+                ".MACRO" => {
+                    parameters = operands.iter().skip(1).cloned().collect();
+                    if let Some(name) = operands.first_mut() {
+                        *name = name.to_uppercase();
+                    }
+                }
+                ".ENDM" => parameters.clear(),
+                _ => {}
+            }
+            if !operands.is_empty() {
+                pad(&mut line, OPERANDS_COLUMN);
+                // The name of a macro is separated from its parameters by a space like an operation
+                match operation.as_str() {
+                    ".MACRO" if operands.len() > 1 => {
+                        line.push_str(&format!("{} {}", operands[0], operands[1..].join(", ")))
+                    }
+                    _ => line.push_str(&operands.join(", ")),
+                }
+            }
+        }
+        if let Some(comment) = comment {
+            pad(&mut line, COMMENT_COLUMN);
+            line.push_str(comment);
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// Upper case operation, branches keep their flags in lower case like `BRnz`
+fn normalize_operation(text: &str) -> String {
+    let upper = text.to_uppercase();
+    match branch_flags(&upper) {
+        Some(_) => format!("BR{}", upper[2..].to_lowercase()),
+        None => upper,
+    }
+}
+
+fn normalize_operand(text: &str, options: &FormatOptions) -> String {
+    let upper = text.to_uppercase();
+    if upper.len() == 2 && upper.starts_with('R') && matches!(upper.as_bytes()[1], b'0'..=b'7') {
+        return upper;
+    }
+    if parse_number(text).is_none() || text.starts_with('\'') {
+        return text.to_string();
+    }
+    if text.starts_with('#') {
+        return text.to_string();
+    }
+    let hex = upper.strip_prefix("0X").or_else(|| upper.strip_prefix('X'));
+    match hex {
+        // Negative hexadecimal (x-1) only exists with the x prefix
+        Some(hex) if hex.starts_with('-') => format!("x{}", hex),
+        Some(hex) if options.zero_x_hex => format!("0x{}", hex),
+        Some(hex) => format!("x{}", hex),
+        None => format!("#{}", text),
+    }
+}
+
+/// Strings are rendered as they were written, with their escape sequences
+fn original_text(code: &str, token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Text(text) => text.clone(),
+        TokenKind::Str(_) => code
+            .chars()
+            .skip(token.span.column - 1)
+            .take(token.span.length)
+            .collect(),
+    }
+}
+
+/// Names of the macros defined in the source, upper case
+fn macro_names(source: &str) -> HashSet<String> {
+    source
+        .lines()
+        .filter_map(|text| {
+            let mut words = split_comment(text).0.split_whitespace();
+            match words.next() {
+                Some(directive) if directive.eq_ignore_ascii_case(".MACRO") => {
+                    Some(words.next()?.trim_end_matches(',').to_uppercase())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Split the line in code and comment, semicolons inside strings and character literals don't start a comment
+fn split_comment(text: &str) -> (&str, Option<&str>) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (position, c) in text.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => {
+                return (&text[..position], Some(text[position..].trim_end()));
+            }
+            None => {}
+        }
+    }
+    (text, None)
+}
+
+/// Pad the line up to the column, keeping at least one space after what is already written
+fn pad(line: &mut String, column: usize) {
+    let length = line.chars().count();
+    let spaces = if length < column { column - length } else { 1 };
+    line.push_str(&" ".repeat(spaces));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const MESSY: &str = include_str!("../../tests/fixtures/unformatted.asm");
+
+    fn corpus() -> Vec<&'static str> {
+        vec![
+            include_str!("../../tests/fixtures/directives.asm"),
+            include_str!("../../tests/fixtures/five_errors.asm"),
+            MESSY,
+        ]
+    }
+
+    #[test]
+    fn aligns_columns_and_normalizes_literals() {
+        let formatted = format(MESSY, &FormatOptions::default());
+        assert_eq!(
+            formatted,
+            include_str!("../../tests/fixtures/formatted.asm")
+        );
+    }
+
+    #[test]
+    fn labels_on_their_own_line_and_0x_hex() {
+        let options = FormatOptions {
+            zero_x_hex: true,
+            labels_on_own_line: true,
+        };
+        let formatted = format(
+            ".orig X3000\nloop add r1, r1, 0XFf ; count\n.end\n",
+            &options,
+        );
+        assert_eq!(
+            formatted,
+            "        .ORIG    0x3000\nloop\n        ADD      R1, R1, 0xFF   ; count\n        .END\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent_and_keeps_the_words() {
+        for options in [
+            FormatOptions::default(),
+            FormatOptions {
+                zero_x_hex: true,
+                labels_on_own_line: true,
+            },
+        ] {
+            for source in corpus() {
+                let formatted = format(source, &options);
+                assert_eq!(format(&formatted, &options), formatted);
+                match (assemble(source), assemble(&formatted)) {
+                    (Ok(before), Ok(after)) => {
+                        assert_eq!(before.to_object_bytes(), after.to_object_bytes())
+                    }
+                    (Err(before), Err(after)) => {
+                        assert_eq!(before.error_count(), after.error_count())
+                    }
+                    _ => panic!("formatting changed whether the source assembles"),
+                }
+            }
+        }
+    }
+}
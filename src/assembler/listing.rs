@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use super::Program;
+
+/// Width of the address, hex and binary columns, source lines that don't emit words leave them blank
+const WORD_COLUMNS: usize = 30;
+const SYMBOL_TABLE_HEADER: &str = "Symbol table";
+
+impl Program {
+    /// Render the classic listing of the program, one row per word:
+    /// ```text
+    /// (3000) E002  1110000000000010 (   2) LOOP    LEA R0, HELLO
+    /// ```
+    /// The address, the word in hex and binary, the source line that produced it and the source text.
+    /// Multi-word directives (.STRINGZ, .BLKW) get one row per word referencing the same source line,
+    /// lines that don't emit words keep their text with blank word columns. The symbol table follows the rows
+    pub fn listing(&self, source: &str) -> String {
+        let mut output = String::new();
+        let mut index = 0;
+        for (line_index, text) in source.lines().enumerate() {
+            let line = line_index + 1;
+            let mut text = text;
+            if self.lines.get(index) != Some(&line) {
+                let _ = writeln!(output, "{}({:4}) {}", " ".repeat(WORD_COLUMNS), line, text);
+                continue;
+            }
+            while self.lines.get(index) == Some(&line) {
+                let word = self.words[index];
+                let _ = writeln!(
+                    output,
+                    "({:04X}) {:04X}  {:016b} ({:4}) {}",
+                    self.origin as usize + index,
+                    word,
+                    word,
+                    line,
+                    text
+                );
+                text = "";
+                index += 1;
+            }
+        }
+        let _ = writeln!(output, "\n{}", SYMBOL_TABLE_HEADER);
+        output.push_str(&self.symbol_table());
+        output
+    }
+}
+
+/// Read the address to source line mapping back from a listing produced by [`Program::listing`]
+pub fn parse_listing(listing: &str) -> Result<Vec<(u16, usize)>, String> {
+    let mut mapping = Vec::new();
+    for (index, row) in listing.lines().enumerate() {
+        if row == SYMBOL_TABLE_HEADER {
+            break;
+        }
+        if !row.starts_with('(') {
+            continue;
+        }
+        let bad_row = || format!("bad listing row {}: `{}`", index + 1, row);
+        let address = row
+            .get(1..5)
+            .and_then(|address| u16::from_str_radix(address, 16).ok())
+            .ok_or_else(bad_row)?;
+        let line = row
+            .get(WORD_COLUMNS + 1..WORD_COLUMNS + 5)
+            .and_then(|line| line.trim().parse().ok())
+            .ok_or_else(bad_row)?;
+        mapping.push((address, line));
+    }
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const SOURCE: &str = "; Prints a greeting
+        .ORIG x3000
+        LEA R0, HELLO
+        PUTS
+        HALT
+HELLO   .STRINGZ \"Hi\"
+BUFFER  .BLKW 2
+        .END";
+
+    #[test]
+    fn rows_for_every_word() {
+        let listing = assemble(SOURCE).unwrap().listing(SOURCE);
+        let rows: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            rows[0],
+            format!("{}(   1) ; Prints a greeting", " ".repeat(30))
+        );
+        assert_eq!(
+            rows[2],
+            "(3000) E002  1110000000000010 (   3)         LEA R0, HELLO"
+        );
+        assert_eq!(
+            rows[5],
+            "(3003) 0048  0000000001001000 (   6) HELLO   .STRINGZ \"Hi\""
+        );
+        assert_eq!(rows[6], "(3004) 0069  0000000001101001 (   6) ");
+        assert_eq!(rows[7], "(3005) 0000  0000000000000000 (   6) ");
+        assert_eq!(rows[9], "(3007) 0000  0000000000000000 (   7) ");
+        assert!(listing.ends_with("\nSymbol table\nHELLO x3003\nBUFFER x3006\n"));
+    }
+
+    #[test]
+    fn listing_round_trips_through_the_parser() {
+        let program = assemble(SOURCE).unwrap();
+        let mapping = parse_listing(&program.listing(SOURCE)).unwrap();
+        let expected: Vec<(u16, usize)> = program
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| (program.origin + index as u16, *line))
+            .collect();
+        assert_eq!(mapping, expected);
+        assert_eq!(
+            mapping,
+            vec![
+                (0x3000, 3),
+                (0x3001, 4),
+                (0x3002, 5),
+                (0x3003, 6),
+                (0x3004, 6),
+                (0x3005, 6),
+                (0x3006, 7),
+                (0x3007, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn expanded_macros_map_to_the_invocation() {
+        let source = ".MACRO TWICE reg\nADD reg, reg, reg\nADD reg, reg, reg\n.ENDM\n.ORIG x3000\nTWICE R1\n.END";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.lines, vec![6, 6]);
+        assert_eq!(
+            parse_listing(&program.listing(source)).unwrap(),
+            vec![(0x3000, 6), (0x3001, 6)]
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::diagnostic::{Diagnostic, Span};
+use super::{Token, TokenKind, is_operation, note_expansion, tokenize};
+
+/// Maximum amount of macro invocations inside each other
+const MAX_MACRO_DEPTH: usize = 16;
+
+/// Place where a macro was invoked, expanded lines keep the whole chain of invocations that produced them
+#[derive(Debug, Clone)]
+pub(super) struct Invocation {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A tokenized line after the macros have been expanded
+pub(super) struct SourceLine {
+    pub tokens: Vec<Token>,
+    pub expansion: Vec<Invocation>,
+}
+
+/// A macro defined with `.MACRO name param1, param2` ... `.ENDM`
+struct Macro {
+    name_span: Span,
+    parameters: Vec<String>,
+    body: Vec<Vec<Token>>,
+}
+
+struct Expander<'a> {
+    macros: HashMap<String, Rc<Macro>>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+    expansions: usize,
+    lines: Vec<SourceLine>,
+}
+
+/// Tokenize the source and replace every macro invocation with the body of the macro.
+/// * Parameters are replaced textually by the arguments of the invocation
+/// * Labels defined inside the body get a unique name on each expansion so a macro can be used more than once
+/// * Macros can invoke other macros, up to a fixed depth and never themselves
+pub(super) fn expand(source: &str, diagnostics: &mut Vec<Diagnostic>) -> Vec<SourceLine> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        diagnostics,
+        expansions: 0,
+        lines: Vec::new(),
+    };
+    let mut definition: Option<(String, Span, Macro)> = None;
+    for (index, text) in source.lines().enumerate() {
+        let tokens = match tokenize(text, index + 1) {
+            Ok(tokens) => tokens,
+            Err(diagnostic) => {
+                expander.diagnostics.push(diagnostic);
+                continue;
+            }
+        };
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let directive = first.text().map(str::to_uppercase);
+        if let Some((name, _, current)) = &mut definition {
+            match directive.as_deref() {
+                Some(".ENDM") => {
+                    let (name, _, current) = definition.take().unwrap_or_else(|| unreachable!());
+                    expander.macros.insert(name, Rc::new(current));
+                }
+                Some(".MACRO") => expander.diagnostics.push(Diagnostic::error(
+                    format!("macros can't be defined inside macro `{}`", name),
+                    first.span,
+                )),
+                _ => current.body.push(tokens),
+            }
+            continue;
+        }
+        match directive.as_deref() {
+            Some(".MACRO") => {
+                let Some(name) = tokens.get(1).and_then(Token::text) else {
+                    expander
+                        .diagnostics
+                        .push(Diagnostic::error(".MACRO expects a name", first.span));
+                    continue;
+                };
+                let parameters = tokens[2..]
+                    .iter()
+                    .filter_map(|token| token.text().map(str::to_string))
+                    .collect();
+                definition = Some((
+                    name.to_uppercase(),
+                    first.span,
+                    Macro {
+                        name_span: tokens[1].span,
+                        parameters,
+                        body: Vec::new(),
+                    },
+                ));
+            }
+            Some(".ENDM") => expander
+                .diagnostics
+                .push(Diagnostic::error(".ENDM without .MACRO", first.span)),
+            _ => expander.expand_line(tokens, &mut Vec::new()),
+        }
+    }
+    if let Some((name, span, _)) = definition {
+        expander.diagnostics.push(Diagnostic::error(
+            format!("macro `{}` is missing its .ENDM", name),
+            span,
+        ));
+    }
+    expander.lines
+}
+
+impl Expander<'_> {
+    /// Error found while expanding also point at the invocations that lead to them
+    fn report(&mut self, diagnostic: Diagnostic, stack: &[Invocation]) {
+        let mut diagnostic = [diagnostic];
+        note_expansion(&mut diagnostic, stack);
+        self.diagnostics.extend(diagnostic);
+    }
+
+    fn macro_named(&self, token: &Token) -> Option<(String, Rc<Macro>)> {
+        let name = token.text()?.to_uppercase();
+        let definition = self.macros.get(&name)?.clone();
+        Some((name, definition))
+    }
+
+    fn expand_line(&mut self, mut tokens: Vec<Token>, stack: &mut Vec<Invocation>) {
+        // The invocation can follow a label, which points at the first word of the expansion
+        let call_index = if self.macro_named(&tokens[0]).is_some() {
+            0
+        } else if tokens.len() > 1
+            && tokens[0].text().is_some_and(|text| !is_operation(text))
+            && self.macro_named(&tokens[1]).is_some()
+        {
+            1
+        } else {
+            self.lines.push(SourceLine {
+                tokens,
+                expansion: stack.clone(),
+            });
+            return;
+        };
+        let arguments = tokens.split_off(call_index + 1);
+        let call = tokens.pop().unwrap_or_else(|| unreachable!());
+        let Some((name, definition)) = self.macro_named(&call) else {
+            return;
+        };
+        let defined_here = format!("macro `{}` is defined here", name);
+        if stack.iter().any(|invocation| invocation.name == name) {
+            self.report(
+                Diagnostic::error(format!("macro `{}` can't invoke itself", name), call.span)
+                    .with_note(defined_here, Some(definition.name_span)),
+                stack,
+            );
+            return;
+        }
+        if stack.len() >= MAX_MACRO_DEPTH {
+            self.report(
+                Diagnostic::error(
+                    format!(
+                        "macro invocations can't be nested more than {} levels deep",
+                        MAX_MACRO_DEPTH
+                    ),
+                    call.span,
+                ),
+                stack,
+            );
+            return;
+        }
+        if arguments.len() != definition.parameters.len() {
+            self.report(
+                Diagnostic::error(
+                    format!(
+                        "macro `{}` expects {} argument(s) but {} were given",
+                        name,
+                        definition.parameters.len(),
+                        arguments.len()
+                    ),
+                    call.span,
+                )
+                .with_note(defined_here, Some(definition.name_span)),
+                stack,
+            );
+            return;
+        }
+        if !tokens.is_empty() {
+            self.lines.push(SourceLine {
+                tokens,
+                expansion: stack.clone(),
+            });
+        }
+        self.expansions += 1;
+        let mut replacements: HashMap<&str, &Token> = HashMap::new();
+        for (parameter, argument) in definition.parameters.iter().zip(&arguments) {
+            replacements.insert(parameter, argument);
+        }
+        let local_labels: HashMap<String, String> = definition
+            .body
+            .iter()
+            .filter_map(|line| line[0].text())
+            .filter(|text| {
+                !is_operation(text)
+                    && !self.macros.contains_key(&text.to_uppercase())
+                    && !replacements.contains_key(text)
+            })
+            .map(|label| (label.to_string(), format!("{}__{}", label, self.expansions)))
+            .collect();
+        stack.push(Invocation {
+            name,
+            span: call.span,
+        });
+        for line in &definition.body {
+            let expanded = line
+                .iter()
+                .map(|token| substitute(token, &replacements, &local_labels))
+                .collect();
+            self.expand_line(expanded, stack);
+        }
+        stack.pop();
+    }
+}
+
+/// A token that is exactly a parameter becomes the argument, keeping the position of the argument.
+/// Otherwise every parameter and local label inside the text is replaced
+fn substitute(
+    token: &Token,
+    replacements: &HashMap<&str, &Token>,
+    local_labels: &HashMap<String, String>,
+) -> Token {
+    let Some(text) = token.text() else {
+        return token.clone();
+    };
+    if let Some(argument) = replacements.get(text) {
+        return (*argument).clone();
+    }
+    let mut result = String::new();
+    let mut identifier = String::new();
+    let mut in_character = false;
+    let flush = |identifier: &mut String, result: &mut String| {
+        let replacement = match replacements.get(identifier.as_str()) {
+            Some(argument) => argument.text().unwrap_or_default(),
+            None => local_labels
+                .get(identifier.as_str())
+                .map_or(identifier.as_str(), String::as_str),
+        };
+        result.push_str(replacement);
+        identifier.clear();
+    };
+    for c in text.chars() {
+        if in_character {
+            result.push(c);
+            in_character = c != '\'';
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            identifier.push(c);
+        } else {
+            flush(&mut identifier, &mut result);
+            in_character = c == '\'';
+            result.push(c);
+        }
+    }
+    flush(&mut identifier, &mut result);
+    Token {
+        kind: TokenKind::Text(result),
+        span: token.span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Registers;
+    use crate::assembler::assemble;
+    use crate::assembler::diagnostic::Span;
+    use crate::test_util::TestVm;
+
+    const STACK_MACROS: &str = "
+.MACRO PUSH reg
+        ADD R6, R6, #-1
+        STR reg, R6, #0
+.ENDM
+.MACRO POP reg
+        LDR reg, R6, #0
+        ADD R6, R6, #1
+.ENDM
+";
+
+    #[test]
+    fn push_and_pop_in_a_loop_run_in_the_vm() {
+        let source = format!(
+            "{}
+        .ORIG x3000
+        LD R6, STACK
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        PUSH R1
+        ADD R2, R1, #-3
+        BRn LOOP
+        POP R3
+        POP R4
+        POP R5
+        HALT
+STACK   .FILL x4000
+        .END",
+            STACK_MACROS
+        );
+        let mut vm = TestVm::with_snippet(&source);
+        vm.run().unwrap();
+        let state = vm.state_mut();
+        assert_eq!(state.register_read(Registers::R3), 3);
+        assert_eq!(state.register_read(Registers::R4), 2);
+        assert_eq!(state.register_read(Registers::R5), 1);
+        assert_eq!(state.register_read(Registers::R6), 0x4000);
+        assert_eq!(state.memory_read(0x3FFF), 1);
+    }
+
+    #[test]
+    fn labels_inside_macros_are_unique_per_expansion() {
+        let source = "
+.MACRO ABS reg
+        ADD reg, reg, #0
+        BRzp DONE
+        NOT reg, reg
+        ADD reg, reg, #1
+DONE    NOP
+.ENDM
+        .ORIG x3000
+FIRST   ABS R1
+        ABS R2
+        .END";
+        let program = assemble(source).unwrap();
+        assert_eq!(program.words.len(), 10);
+        assert_eq!(program.symbols["FIRST"], 0x3000);
+        assert_eq!(program.symbols["DONE__1"], 0x3004);
+        assert_eq!(program.symbols["DONE__2"], 0x3009);
+        // Both branches skip the negation of their own expansion
+        assert_eq!(program.words[1], 0x0602);
+        assert_eq!(program.words[6], 0x0602);
+    }
+
+    #[test]
+    fn nested_invocations() {
+        let source = format!(
+            "{}
+.MACRO PUSH2 first, second
+        PUSH first
+        PUSH second
+.ENDM
+        .ORIG x3000
+        PUSH2 R1, R2
+        .END",
+            STACK_MACROS
+        );
+        let program = assemble(&source).unwrap();
+        assert_eq!(program.words, vec![0x1DBF, 0x7380, 0x1DBF, 0x7580]);
+    }
+
+    #[test]
+    fn recursive_invocation_is_an_error() {
+        let source = ".MACRO LOOP
+        LOOP
+.ENDM
+        .ORIG x3000
+        LOOP
+        .END";
+        let error = assemble(source).unwrap_err().diagnostics.remove(0);
+        assert_eq!(error.message, "macro `LOOP` can't invoke itself");
+        assert_eq!(error.span, Span::new(2, 9, 4));
+        assert_eq!(error.notes[0].span, Some(Span::new(1, 8, 4)));
+        assert_eq!(
+            error.notes[1].message,
+            "in expansion of macro `LOOP` invoked here"
+        );
+        assert_eq!(error.notes[1].span, Some(Span::new(5, 9, 4)));
+    }
+
+    #[test]
+    fn wrong_arguments_point_at_the_invocation_and_the_definition() {
+        let source = format!("{}\n.ORIG x3000\nPUSH R1, R2\nPOP #5\n.END", STACK_MACROS);
+        let diagnostics = assemble(&source).unwrap_err().diagnostics;
+        assert_eq!(
+            diagnostics[0].message,
+            "macro `PUSH` expects 1 argument(s) but 2 were given"
+        );
+        assert_eq!(diagnostics[0].span, Span::new(12, 1, 4));
+        assert_eq!(
+            diagnostics[0].notes[0].message,
+            "macro `PUSH` is defined here"
+        );
+        assert_eq!(diagnostics[0].notes[0].span, Some(Span::new(2, 8, 4)));
+        // The argument is substituted with its own position, errors in it point at the invocation line
+        assert_eq!(diagnostics[1].message, "expected a register (R0 to R7)");
+        assert_eq!(diagnostics[1].span, Span::new(13, 5, 2));
+        assert_eq!(
+            diagnostics[1].notes[0].message,
+            "in expansion of macro `POP` invoked here"
+        );
+        assert_eq!(diagnostics[1].notes[0].span, Some(Span::new(13, 1, 3)));
+    }
+
+    #[test]
+    fn unterminated_definition() {
+        let diagnostics = assemble(".ORIG x3000\n.MACRO EMPTY\n.END")
+            .unwrap_err()
+            .diagnostics;
+        assert_eq!(diagnostics[0].message, "macro `EMPTY` is missing its .ENDM");
+        assert_eq!(diagnostics[0].span, Span::new(2, 1, 6));
+    }
+}
@@ -0,0 +1,323 @@
+//! Postconditions checked once a program stops, what `--assert-reg` and `--assert-mem` add:
+//! ```text
+//! --assert-reg R2=x0030 --assert-mem x4000=x0011 --assert-mem x4001:x4004=x1,x2,x3,x4
+//! ```
+//! A register is R0 to R7, PC or COND. Values are literals: hexadecimal with an `x` or `0x` prefix, decimal otherwise
+//! (with an optional `#` and `-`). Addresses can be labels too, see [`crate::address`]. A range of addresses takes a
+//! value for each of them. With `--banks` a range can name its bank, `2:x9000:x9003=...` (see
+//! [`crate::address::parse_bank_range`]), a single word in a bank is the range `2:x9000:x9000`.
+//!
+//! Programs can check themselves too with the ASSERT trap (x26), see [`TrapAssertion`]
+
+use std::fmt;
+
+use crate::address::{SymbolTable, parse_bank_range};
+use crate::snapshot::REGISTER_NAMES;
+use crate::{Error, State};
+
+/// What a register or some memory must hold, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Assertion {
+    /// The register numbered like [`crate::Registers`]
+    Register { register: usize, expected: u16 },
+    /// The words from `start` on, in `bank` or the one selected
+    Memory {
+        bank: Option<u16>,
+        start: u16,
+        expected: Vec<u16>,
+    },
+}
+
+impl Assertion {
+    /// `R2=x0030` for a register
+    pub fn parse_register(text: &str) -> Result<Assertion, Error> {
+        let bad = || Error::BadArgument(text.to_string());
+        let (name, value) = text.split_once('=').ok_or_else(bad)?;
+        let register = REGISTER_NAMES
+            .iter()
+            .position(|known| known.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(bad)?;
+        Ok(Assertion::Register {
+            register,
+            expected: parse_literal(value).ok_or_else(bad)?,
+        })
+    }
+
+    /// `x4000=x0011` for a word, `x4001:x4004=x1,x2,x3,x4` or `TABLE:TABLE+3=...` for a range, the labels looked up in
+    /// `symbols`
+    pub fn parse_memory(text: &str, symbols: &SymbolTable) -> Result<Assertion, Error> {
+        let bad = || Error::BadArgument(text.to_string());
+        let (addresses, values) = text.split_once('=').ok_or_else(bad)?;
+        let (bank, (start, end)) = parse_bank_range(addresses, symbols)?;
+        let expected = values
+            .split(',')
+            .map(parse_literal)
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(bad)?;
+        if expected.len() != (end - start) as usize + 1 {
+            return Err(bad());
+        }
+        Ok(Assertion::Memory {
+            bank,
+            start,
+            expected,
+        })
+    }
+
+    /// The bank of a memory assertion naming one
+    pub fn bank(&self) -> Option<u16> {
+        match self {
+            Assertion::Memory { bank, .. } => *bank,
+            Assertion::Register { .. } => None,
+        }
+    }
+
+    /// Compare with the state, the memory is peeked (see [`State::peek`]) so checking takes no key
+    pub fn check(&self, state: &State) -> AssertionOutcome {
+        let actual = match self {
+            Assertion::Register { register, .. } => vec![state.registers[*register]],
+            Assertion::Memory {
+                bank,
+                start,
+                expected,
+            } => (0..expected.len())
+                .map(|offset| {
+                    let address = *start as usize + offset;
+                    match bank {
+                        // A bank that isn't there holds nothing
+                        Some(bank) => state.peek_bank(*bank, address as u16).unwrap_or(0),
+                        None => state.peek(address),
+                    }
+                })
+                .collect(),
+        };
+        AssertionOutcome {
+            assertion: self.clone(),
+            actual,
+        }
+    }
+
+    fn expected(&self) -> &[u16] {
+        match self {
+            Assertion::Register { expected, .. } => std::slice::from_ref(expected),
+            Assertion::Memory { expected, .. } => expected,
+        }
+    }
+}
+
+/// `R2=x0030`, `x4000=x0011`, `x4001:x4004=x0001,x0002,x0003,x0004` or in a bank `2:x9000:x9000=x0001`
+impl fmt::Display for Assertion {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::Register { register, .. } => {
+                write!(formatter, "{}", REGISTER_NAMES[*register])?
+            }
+            Assertion::Memory {
+                bank: None,
+                start,
+                expected,
+            } if expected.len() == 1 => write!(formatter, "x{:04X}", start)?,
+            Assertion::Memory {
+                bank,
+                start,
+                expected,
+            } => write!(
+                formatter,
+                "{}x{:04X}:x{:04X}",
+                bank.map_or(String::new(), |bank| format!("{}:", bank)),
+                start,
+                *start as usize + expected.len() - 1
+            )?,
+        }
+        write!(formatter, "={}", words(self.expected()))
+    }
+}
+
+/// An assertion and what the state held
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssertionOutcome {
+    pub assertion: Assertion,
+    pub actual: Vec<u16>,
+}
+
+impl AssertionOutcome {
+    pub fn passed(&self) -> bool {
+        self.actual == self.assertion.expected()
+    }
+}
+
+/// `PASS R2=x0030`, or `FAIL R2=x0030 (found x0031)`
+impl fmt::Display for AssertionOutcome {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.passed() {
+            true => write!(formatter, "PASS {}", self.assertion),
+            false => write!(
+                formatter,
+                "FAIL {} (found {})",
+                self.assertion,
+                words(&self.actual)
+            ),
+        }
+    }
+}
+
+/// An ASSERT trap (x26) that ran: it passes if R0 isn't zero, R1 points to a message with a character per word ending
+/// in NUL like PUTS. The VM prints `ASSERT PASS: <message>` or `ASSERT FAIL: <message>` to stderr for each one, see
+/// [`crate::State::trap_assertions`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrapAssertion {
+    /// The address of the trap
+    pub pc: u16,
+    pub passed: bool,
+    pub message: String,
+}
+
+/// `ASSERT PASS: message` or `ASSERT FAIL: message`
+impl fmt::Display for TrapAssertion {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.passed {
+            true => write!(formatter, "ASSERT PASS: {}", self.message),
+            false => write!(formatter, "ASSERT FAIL: {}", self.message),
+        }
+    }
+}
+
+fn words(words: &[u16]) -> String {
+    words
+        .iter()
+        .map(|word| format!("x{:04X}", word))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A hexadecimal literal with `x` or `0x`, a decimal one otherwise. Negative decimals are two's complement
+fn parse_literal(text: &str) -> Option<u16> {
+    let text = text.trim();
+    if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'))
+    {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    let decimal: i32 = text.strip_prefix('#').unwrap_or(text).parse().ok()?;
+    match decimal {
+        -32768..=-1 => Some(decimal as i16 as u16),
+        0..=65535 => Some(decimal as u16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_are_hexadecimal_with_an_x_and_decimal_without() {
+        assert_eq!(parse_literal("x30"), Some(0x30));
+        assert_eq!(parse_literal("0x0030"), Some(0x30));
+        assert_eq!(parse_literal("48"), Some(48));
+        assert_eq!(parse_literal("#48"), Some(48));
+        assert_eq!(parse_literal("-1"), Some(0xFFFF));
+        assert_eq!(parse_literal("x10000"), None);
+        assert_eq!(parse_literal("70000"), None);
+        assert_eq!(parse_literal("R1"), None);
+    }
+
+    #[test]
+    fn registers_are_named() {
+        assert_eq!(
+            Assertion::parse_register("R2=0x0030").unwrap(),
+            Assertion::Register {
+                register: 2,
+                expected: 0x30
+            }
+        );
+        assert_eq!(
+            Assertion::parse_register("pc=x3000").unwrap(),
+            Assertion::Register {
+                register: 8,
+                expected: 0x3000
+            }
+        );
+        for bad in ["R8=1", "R2", "R2=", "R2=zz"] {
+            assert!(Assertion::parse_register(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn a_range_takes_a_value_per_address() {
+        assert_eq!(
+            Assertion::parse_memory("x4000=x0011", &SymbolTable::new()).unwrap(),
+            Assertion::Memory {
+                bank: None,
+                start: 0x4000,
+                expected: vec![0x11]
+            }
+        );
+        let range = Assertion::parse_memory("x4001:x4004=x1,x2,3,#4", &SymbolTable::new()).unwrap();
+        assert_eq!(
+            range,
+            Assertion::Memory {
+                bank: None,
+                start: 0x4001,
+                expected: vec![1, 2, 3, 4]
+            }
+        );
+        assert_eq!(range.to_string(), "x4001:x4004=x0001,x0002,x0003,x0004");
+        let mut symbols = SymbolTable::new();
+        symbols.insert("TABLE", 0x4001);
+        assert_eq!(
+            Assertion::parse_memory("TABLE:TABLE+3=x1,x2,3,#4", &symbols).unwrap(),
+            range
+        );
+        for bad in [
+            "x4001:x4004=x1,x2",
+            "x4004:x4001=x1",
+            "x4000",
+            "x4000=x1,x2",
+        ] {
+            assert!(
+                Assertion::parse_memory(bad, &SymbolTable::new()).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn a_range_in_a_bank_is_checked_in_that_bank() {
+        let mut state = State::default();
+        state.enable_banks(2).unwrap();
+        state.memory_write(0x9000, 7);
+        let assertion = Assertion::parse_memory("1:x9000:x9000=7", &SymbolTable::new()).unwrap();
+        assert_eq!(assertion.bank(), Some(1));
+        assert_eq!(assertion.to_string(), "1:x9000:x9000=x0007");
+        assert!(!assertion.check(&state).passed());
+        assert!(
+            Assertion::parse_memory("0:x9000:x9000=7", &SymbolTable::new())
+                .unwrap()
+                .check(&state)
+                .passed()
+        );
+        state.memory_write(crate::bank::BSR as usize, 1);
+        state.memory_write(0x9000, 7);
+        assert!(assertion.check(&state).passed());
+    }
+
+    #[test]
+    fn a_failure_shows_what_was_found() {
+        let mut state = State::default();
+        state.memory_write(0x4000, 0x11);
+        let outcome = Assertion::parse_memory("x4000:x4001=x11,x5", &SymbolTable::new())
+            .unwrap()
+            .check(&state);
+        assert!(!outcome.passed());
+        assert_eq!(
+            outcome.to_string(),
+            "FAIL x4000:x4001=x0011,x0005 (found x0011,x0000)"
+        );
+        let outcome = Assertion::parse_register("R0=0").unwrap().check(&state);
+        assert_eq!(outcome.to_string(), "PASS R0=x0000");
+    }
+}
@@ -0,0 +1,152 @@
+//! Running the VM as a task of a tokio runtime, only built with the `async` feature.
+//!
+//! An [`AsyncVm`] runs its program in slices and gives the thread back to the executor between them, so a single runtime
+//! can run many machines at once. Its keyboard is fed by a [`Receiver`] and what it prints goes to a [`Sender`]:
+//! GETC and IN without a key wait on the receiver without blocking the thread and the keyboard registers see every key
+//! that arrived before the current slice
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::console::{Input, MemoryOutput};
+use crate::{Error, InterruptHandle, RuntimeError, State, run_with_budget};
+
+/// The keys that came from the receiver and the program hasn't read yet
+#[derive(Default)]
+struct KeyQueue {
+    keys: VecDeque<u8>,
+    /// GETC or IN found no key and stopped the slice
+    starved: bool,
+    /// The receiver was closed, there won't be any more keys
+    ended: bool,
+}
+
+/// The input of the state of an [`AsyncVm`]. GETC and IN without a key stop the slice through the interrupt handle,
+/// the trap goes back so it runs again once [`AsyncVm::run_async`] received a key
+#[derive(Clone)]
+struct QueuedKeys {
+    queue: Arc<Mutex<KeyQueue>>,
+    waiting: InterruptHandle,
+}
+
+impl QueuedKeys {
+    fn push(&self, key: u8) {
+        self.queue.lock().unwrap().keys.push_back(key);
+    }
+
+    fn end(&self) {
+        self.queue.lock().unwrap().ended = true;
+    }
+
+    /// Whether the last slice stopped waiting for a key, cleared afterwards
+    fn take_starved(&self) -> bool {
+        std::mem::take(&mut self.queue.lock().unwrap().starved)
+    }
+}
+
+impl Input for QueuedKeys {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut queue = self.queue.lock().unwrap();
+        let key = queue.keys.pop_front();
+        // Once the receiver is closed GETC fails like at the end of stdin
+        if key.is_none() && !queue.ended {
+            queue.starved = true;
+            self.waiting.request();
+        }
+        key
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.queue.lock().unwrap().keys.pop_front()
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        self.queue.lock().unwrap().keys.drain(..).collect()
+    }
+}
+
+/// A machine with its keyboard and screen on tokio channels, see [`AsyncVm::run_async`]
+pub struct AsyncVm {
+    state: State,
+    keys: Receiver<u8>,
+    output: Sender<u8>,
+    queued: QueuedKeys,
+    printed: MemoryOutput,
+    /// Printed bytes the sender didn't take yet, they survive a cancelled run
+    unsent: VecDeque<u8>,
+}
+
+impl AsyncVm {
+    /// Run the program loaded in the state with the keys from `keys` and its output sent to `output`.
+    /// The input and output of the state are replaced
+    pub fn new(mut state: State, keys: Receiver<u8>, output: Sender<u8>) -> AsyncVm {
+        let queued = QueuedKeys {
+            queue: Arc::default(),
+            waiting: state.interrupt_handle(),
+        };
+        let printed = MemoryOutput::default();
+        state.set_input(queued.clone());
+        state.set_output(printed.clone());
+        AsyncVm {
+            state,
+            keys,
+            output,
+            queued,
+            printed,
+            unsent: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Run until the program halts, `budget_per_yield` instructions at a time with a yield to the executor after each slice.
+    /// Everything the program printed is sent before it returns.
+    ///
+    /// Dropping the future only ever stops it between slices, while it yields, sends output or waits for a key,
+    /// so the machine can be run again from where it was: a GETC waiting for a key runs again and unsent output is kept.
+    /// It fails if the output receiver is closed, and like the command line VM if the program waits for a key once the keys sender is closed.
+    /// A request through [`State::interrupt_handle`] stops it with [`RuntimeError::Interrupted`]
+    pub async fn run_async(&mut self, budget_per_yield: u32) -> Result<(), Error> {
+        let budget = budget_per_yield.max(1) as u64;
+        loop {
+            while let Ok(key) = self.keys.try_recv() {
+                self.queued.push(key);
+            }
+            let result = run_with_budget(&mut self.state, budget);
+            self.unsent.extend(self.printed.drain_output());
+            self.send_output().await?;
+            match result {
+                Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => {
+                    tokio::task::yield_now().await
+                }
+                Err(Error::Runtime(RuntimeError::Interrupted)) if self.queued.take_starved() => {
+                    match self.keys.recv().await {
+                        Some(key) => self.queued.push(key),
+                        None => self.queued.end(),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send the unsent output, a byte only leaves [`AsyncVm::unsent`] once the receiver has it
+    async fn send_output(&mut self) -> Result<(), Error> {
+        while let Some(&byte) = self.unsent.front() {
+            if self.output.send(byte).await.is_err() {
+                return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+            }
+            self.unsent.pop_front();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Banked memory, an experiment extending the address space past 64K words, what `--banks` adds. There are N banks of
+//! 16K words and the bank select register BSR (xFE14) says which one is mapped in the window x8000 to xBFFF, the rest
+//! of the address space is the same whatever the bank:
+//! * storing a number to BSR selects that bank, modulo N
+//! * loading BSR gives the bank selected, bank 0 when the machine starts
+//!
+//! Every bank starts zeroed and the images load to the bank selected. The selected bank is in the memory of the machine
+//! like any other address, so the instructions, the decode cache and the JIT never know about banks: selecting one
+//! swaps the window with the copy kept aside here. Without banks BSR is a plain device page address
+
+use crate::{Error, State};
+
+/// Bank Select Register
+pub const BSR: u16 = 0xFE14;
+/// First address of the window the selected bank is mapped in
+pub const BANK_WINDOW_START: u16 = 0x8000;
+/// Words in a bank, the window goes up to xBFFF
+pub const BANK_WORDS: usize = 0x4000;
+
+const WINDOW: std::ops::Range<usize> =
+    BANK_WINDOW_START as usize..BANK_WINDOW_START as usize + BANK_WORDS;
+/// The window in the words of an [`crate::AddressSet`]
+const WINDOW_BITS: std::ops::Range<usize> = WINDOW.start / 64..WINDOW.end / 64;
+
+/// A bank while it isn't selected
+#[derive(Clone)]
+struct Bank {
+    words: Box<[u16]>,
+    loaded: Box<[u64]>,
+    written: Box<[u64]>,
+}
+
+impl Bank {
+    fn new() -> Bank {
+        Bank {
+            words: vec![0; BANK_WORDS].into_boxed_slice(),
+            loaded: vec![0; WINDOW_BITS.len()].into_boxed_slice(),
+            written: vec![0; WINDOW_BITS.len()].into_boxed_slice(),
+        }
+    }
+}
+
+/// The banks of a [`State`], see [`State::enable_banks`]
+pub(crate) struct Banks {
+    selected: u16,
+    /// Every bank, the one selected is stale until it is swapped out
+    aside: Vec<Bank>,
+}
+
+impl Banks {
+    #[inline]
+    pub(crate) fn selected(&self) -> u16 {
+        self.selected
+    }
+}
+
+impl State {
+    /// Map `count` banks of 16K words at x8000 to xBFFF, selected through BSR, see [`crate::bank`]. Fails if a device
+    /// added before has a register at BSR. A `count` of 0 is taken as 1
+    pub fn enable_banks(&mut self, count: u16) -> Result<(), Error> {
+        if self
+            .devices
+            .iter()
+            .any(|mapped| mapped.range.contains(&BSR))
+        {
+            return Err(Error::DeviceRange(BSR, BSR));
+        }
+        self.banks = Some(Box::new(Banks {
+            selected: 0,
+            aside: vec![Bank::new(); count.max(1) as usize],
+        }));
+        Ok(())
+    }
+
+    /// How many banks there are, `None` without [`State::enable_banks`]
+    pub fn bank_count(&self) -> Option<u16> {
+        self.banks.as_ref().map(|banks| banks.aside.len() as u16)
+    }
+
+    /// The bank mapped in the window, `None` without [`State::enable_banks`]
+    pub fn selected_bank(&self) -> Option<u16> {
+        self.banks.as_ref().map(|banks| banks.selected)
+    }
+
+    /// Like [`State::peek`] with `bank` mapped in the window whatever bank is selected. `None` without banks or if
+    /// there is no such bank
+    pub fn peek_bank(&self, bank: u16, address: u16) -> Option<u16> {
+        let banks = self.banks.as_ref()?;
+        let aside = banks.aside.get(bank as usize)?;
+        match WINDOW.contains(&(address as usize)) && bank != banks.selected {
+            true => Some(aside.words[address as usize - WINDOW.start]),
+            false => Some(self.peek(address as usize)),
+        }
+    }
+
+    /// Like [`State::memory_write`] with `bank` mapped in the window whatever bank is selected. `None` without banks
+    /// or if there is no such bank
+    pub fn poke_bank(&mut self, bank: u16, address: u16, value: u16) -> Option<()> {
+        let banks = self.banks.as_mut()?;
+        let selected = banks.selected;
+        let aside = banks.aside.get_mut(bank as usize)?;
+        match WINDOW.contains(&(address as usize)) && bank != selected {
+            true => {
+                let offset = address as usize - WINDOW.start;
+                aside.words[offset] = value;
+                aside.written[offset / 64] |= 1 << (offset % 64);
+            }
+            false => self.memory_write(address as usize, value),
+        }
+        Some(())
+    }
+
+    /// A store to BSR with banks
+    pub(crate) fn select_bank(&mut self, value: u16) {
+        let Some(banks) = &mut self.banks else {
+            return;
+        };
+        let bank = value % banks.aside.len() as u16;
+        if bank == banks.selected {
+            return;
+        }
+        for swapped in [banks.selected, bank] {
+            let aside = &mut banks.aside[swapped as usize];
+            self.memory[WINDOW].swap_with_slice(&mut aside.words);
+            self.loaded.0[WINDOW_BITS].swap_with_slice(&mut aside.loaded);
+            self.written.0[WINDOW_BITS].swap_with_slice(&mut aside.written);
+        }
+        banks.selected = bank;
+        for address in WINDOW {
+            if let Some(decoded) = &mut self.decoded {
+                decoded.invalidate(address);
+            }
+            #[cfg(feature = "jit")]
+            if let Some(jit) = &mut self.jit {
+                jit.invalidate(address);
+            }
+        }
+    }
+
+    /// The words of `bank` and the bits of those loaded from an image, for a snapshot
+    pub(crate) fn bank_contents(&self, bank: u16) -> (&[u16], &[u64]) {
+        let banks = self.banks.as_ref().expect("banks were enabled");
+        match bank == banks.selected {
+            true => (&self.memory[WINDOW], &self.loaded.0[WINDOW_BITS]),
+            false => {
+                let aside = &banks.aside[bank as usize];
+                (&aside.words, &aside.loaded)
+            }
+        }
+    }
+
+    /// Put back the words of `bank` from a snapshot, any word that isn't zero counting as written
+    pub(crate) fn restore_bank(&mut self, bank: u16, words: &[u16], loaded: &[u64]) {
+        let banks = self.banks.as_mut().expect("banks were enabled");
+        let (memory, loaded_bits, written_bits) = match bank == banks.selected {
+            true => (
+                &mut self.memory[WINDOW],
+                &mut self.loaded.0[WINDOW_BITS],
+                &mut self.written.0[WINDOW_BITS],
+            ),
+            false => {
+                let aside = &mut banks.aside[bank as usize];
+                (
+                    &mut aside.words[..],
+                    &mut aside.loaded[..],
+                    &mut aside.written[..],
+                )
+            }
+        };
+        memory.copy_from_slice(words);
+        loaded_bits.copy_from_slice(loaded);
+        for (offset, word) in words.iter().enumerate() {
+            if *word != 0 || loaded[offset / 64] & (1 << (offset % 64)) != 0 {
+                written_bits[offset / 64] |= 1 << (offset % 64);
+            }
+        }
+    }
+
+    /// Select `bank` without swapping the window, for a snapshot whose window already has it
+    pub(crate) fn set_selected_bank(&mut self, bank: u16) {
+        if let Some(banks) = &mut self.banks {
+            banks.selected = bank;
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Many programs run one after the other, what `batch` reads from a jobs file:
+//! ```toml
+//! [[job]]
+//! name = "countdown"          # the stem of the image without it
+//! image = "countdown.obj"
+//! stdin = "countdown.keys"    # no keys without it
+//! max_steps = 100000          # DEFAULT_MAX_STEPS without it
+//! assert_reg = ["R2=x001E"]
+//! assert_mem = ["x4000:x4001=x1E,0"]
+//! ```
+//! Paths are relative to the jobs file. The assertions are written like `--assert-reg` and `--assert-mem`, see
+//! [`crate::assertion`], with the labels of the `.sym` next to the image.
+//!
+//! Every job runs in a [`State`] of its own, nothing a job does (its memory, its devices, what it printed) is seen by
+//! the next one. A job passes when its program halts and every assertion holds, those of the jobs file and the ASSERT
+//! traps of the program. One that fails in any way, even before running, is reported and the others run all the same.
+//! The layout of every image is checked like `--layout-check` does (see [`crate::layout`]), its conflicts end in the
+//! warnings of the report
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use std::{fs, thread};
+
+use serde::{Deserialize, Serialize};
+
+use crate::address::SymbolTable;
+use crate::assertion::Assertion;
+use crate::console::MemoryOutput;
+use crate::determinism::RunConfig;
+use crate::layout::LoadMap;
+use crate::summary::{Outcome, RunSummary};
+use crate::{Error, Registers, State, file_management, run_with_budget, stats};
+
+/// The budget of a job without `max_steps`, a job never runs forever
+pub const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+
+/// A program to run with what it must leave behind, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub name: String,
+    pub image: PathBuf,
+    /// The keys of the program, all of them available right away like with `--stdin-file`
+    pub stdin: Option<PathBuf>,
+    pub max_steps: u64,
+    pub assertions: Vec<Assertion>,
+    /// Count what the program does for [`JobReport::stats_row`], never set by the jobs file
+    pub profile: bool,
+}
+
+/// A `[[job]]` table as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobEntry {
+    name: Option<String>,
+    image: String,
+    stdin: Option<String>,
+    max_steps: Option<u64>,
+    #[serde(default)]
+    assert_reg: Vec<String>,
+    #[serde(default)]
+    assert_mem: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    #[serde(default)]
+    job: Vec<JobEntry>,
+}
+
+/// The jobs of a jobs file, with the paths in it relative to `directory`
+pub fn parse_jobs(text: &str, directory: &Path) -> Result<Vec<Job>, Error> {
+    let file: JobsFile =
+        toml::from_str(text).map_err(|error| Error::BadJobs(error.message().to_string()))?;
+    file.job
+        .into_iter()
+        .map(|entry| {
+            let image = directory.join(&entry.image);
+            let name = match entry.name {
+                Some(name) => name,
+                None => image.file_stem().map_or(entry.image.clone(), |stem| {
+                    stem.to_string_lossy().into_owned()
+                }),
+            };
+            let symbols = SymbolTable::read_beside(&image)?;
+            let assertions = entry
+                .assert_reg
+                .iter()
+                .map(|text| Assertion::parse_register(text))
+                .chain(
+                    entry
+                        .assert_mem
+                        .iter()
+                        .map(|text| Assertion::parse_memory(text, &symbols)),
+                )
+                .collect::<Result<_, _>>()
+                .map_err(|error| Error::BadJobs(format!("job {}: {}", name, error)))?;
+            Ok(Job {
+                name,
+                image,
+                stdin: entry.stdin.map(|stdin| directory.join(stdin)),
+                max_steps: entry.max_steps.unwrap_or(DEFAULT_MAX_STEPS),
+                assertions,
+                profile: false,
+            })
+        })
+        .collect()
+}
+
+/// How a job went, an element of the `jobs` of the JSON report
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct JobReport {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "passed")]
+    pub passed: bool,
+    /// The fields of `--json-summary`, see [`crate::summary`]
+    #[serde(flatten)]
+    pub summary: RunSummary,
+    /// Only checked when the program halted or ran out of steps
+    #[serde(rename = "assertions")]
+    pub assertions: Vec<AssertionReport>,
+    /// What the program printed, a byte per character
+    #[serde(rename = "output")]
+    pub output: String,
+    /// The row of `--stats-out` of the run (see [`crate::stats::csv_row`]), only for a job with [`Job::profile`]
+    #[serde(skip)]
+    pub stats_row: Option<String>,
+}
+
+/// An assertion of a job, like `R2=x001E`, with what the state held
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct AssertionReport {
+    #[serde(rename = "assertion")]
+    pub assertion: String,
+    #[serde(rename = "passed")]
+    pub passed: bool,
+    #[serde(rename = "found")]
+    pub found: Vec<u16>,
+}
+
+/// Run a job in a state of its own
+pub fn run_job(job: &Job) -> JobReport {
+    let mut state = State::default();
+    let mut output = MemoryOutput::default();
+    state.set_output(output.clone());
+    if job.profile {
+        state.enable_profile();
+    }
+    let start = Instant::now();
+    let result = load_and_run(&mut state, job);
+    let elapsed = start.elapsed();
+    let summary = RunSummary::new(&state, &result, elapsed, &[]);
+    let stats_row = job
+        .profile
+        .then(|| stats::csv_row(&job.image.to_string_lossy(), &state, Some(elapsed)));
+    let assertions: Vec<AssertionReport> = match summary.outcome {
+        Outcome::Error => Vec::new(),
+        _ => job
+            .assertions
+            .iter()
+            .map(|assertion| {
+                let outcome = assertion.check(&state);
+                AssertionReport {
+                    assertion: assertion.to_string(),
+                    passed: outcome.passed(),
+                    found: outcome.actual,
+                }
+            })
+            .collect(),
+    };
+    JobReport {
+        name: job.name.clone(),
+        passed: (summary.outcome == Outcome::Halted || summary.code == Some(0))
+            && assertions.iter().all(|report| report.passed)
+            && state
+                .trap_assertions()
+                .iter()
+                .all(|assertion| assertion.passed),
+        summary,
+        assertions,
+        output: output.drain_text(),
+        stats_row,
+    }
+}
+
+fn load_and_run(state: &mut State, job: &Job) -> Result<(), Error> {
+    let keys = match &job.stdin {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    state.set_input(VecDeque::from(keys));
+    let sections = file_management::read_sections(&job.image.to_string_lossy().into_owned())?;
+    let map = LoadMap::from_sections(&sections, state.register_read(Registers::Pc));
+    state.check_layout(&map, &RunConfig::new(false))?;
+    for (origin, words) in sections {
+        state.load_image(origin, &words);
+    }
+    run_with_budget(state, job.max_steps)
+}
+
+/// Run every job, `threads` of them at a time. The reports are in the order of the jobs whatever order they ran in
+pub fn run_jobs(jobs: &[Job], threads: usize) -> Vec<JobReport> {
+    if threads <= 1 {
+        return jobs.iter().map(run_job).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let reports = Mutex::new(vec![None; jobs.len()]);
+    thread::scope(|scope| {
+        for _ in 0..threads.min(jobs.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+                    let report = run_job(job);
+                    reports.lock().unwrap()[index] = Some(report);
+                }
+            });
+        }
+    });
+    reports
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|report| report.unwrap()) // Every job ran once the threads are done
+        .collect()
+}
+
+/// A line per job with how it ended, then why every failed job failed and the counts
+pub fn summary_table(reports: &[JobReport]) -> String {
+    let width = reports
+        .iter()
+        .map(|report| report.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("job".len());
+    let mut table = format!(
+        "{:<width$}  {:<6}  {:<8}  {:>12}  assertions\n",
+        "job", "result", "outcome", "instructions"
+    );
+    for report in reports {
+        let _ = writeln!(
+            table,
+            "{:<width$}  {:<6}  {:<8}  {:>12}  {}/{}",
+            report.name,
+            if report.passed { "PASS" } else { "FAIL" },
+            outcome_name(report.summary.outcome),
+            report.summary.instructions,
+            report
+                .assertions
+                .iter()
+                .filter(|report| report.passed)
+                .count(),
+            report.assertions.len()
+        );
+    }
+    for report in reports.iter().filter(|report| !report.passed) {
+        if let Some(error) = &report.summary.error {
+            let _ = writeln!(table, "{}: {}", report.name, error.message);
+        }
+        for assertion in report
+            .assertions
+            .iter()
+            .filter(|assertion| !assertion.passed)
+        {
+            let found = assertion
+                .found
+                .iter()
+                .map(|word| format!("x{:04X}", word))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = writeln!(
+                table,
+                "{}: FAIL {} (found {})",
+                report.name, assertion.assertion, found
+            );
+        }
+    }
+    let failed = reports.iter().filter(|report| !report.passed).count();
+    let _ = writeln!(
+        table,
+        "{} job(s), {} passed, {} failed",
+        reports.len(),
+        reports.len() - failed,
+        failed
+    );
+    table
+}
+
+fn outcome_name(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Halted => "halted",
+        Outcome::Exited => "exited",
+        Outcome::Error => "error",
+        Outcome::Timeout => "timeout",
+        Outcome::Budget => "budget",
+    }
+}
+
+/// The JSON report, every job in order with the counts:
+/// `{ "passed": 2, "failed": 1, "jobs": [ { "name": "countdown", "passed": true, "outcome": "halted", ... } ] }`
+pub fn report_json(reports: &[JobReport]) -> String {
+    #[derive(Serialize)]
+    struct Report<'a> {
+        #[serde(rename = "passed")]
+        passed: usize,
+        #[serde(rename = "failed")]
+        failed: usize,
+        #[serde(rename = "jobs")]
+        jobs: &'a [JobReport],
+    }
+    let passed = reports.iter().filter(|report| report.passed).count();
+    let report = Report {
+        passed,
+        failed: reports.len() - passed,
+        jobs: reports,
+    };
+    serde_json::to_string_pretty(&report).unwrap() // Numbers, strings and lists, it can't fail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_relative_to_the_jobs_file() {
+        let jobs = parse_jobs(
+            r#"
+            [[job]]
+            image = "programs/countdown.obj"
+            stdin = "countdown.keys"
+            assert_reg = ["R2=x1E"]
+            assert_mem = ["x4000=0"]
+
+            [[job]]
+            name = "echo"
+            image = "echo.obj"
+            max_steps = 10
+            "#,
+            Path::new("suite"),
+        )
+        .unwrap();
+        assert_eq!(jobs[0].name, "countdown");
+        assert_eq!(jobs[0].image, Path::new("suite/programs/countdown.obj"));
+        assert_eq!(
+            jobs[0].stdin.as_deref(),
+            Some(Path::new("suite/countdown.keys"))
+        );
+        assert_eq!(jobs[0].max_steps, DEFAULT_MAX_STEPS);
+        assert_eq!(jobs[0].assertions.len(), 2);
+        assert_eq!(jobs[1].name, "echo");
+        assert_eq!(jobs[1].max_steps, 10);
+        for bad in [
+            "[[job]]\nname = \"no image\"\n",
+            "[[job]]\nimage = \"a.obj\"\ncolour = \"red\"\n",
+            "[[job]]\nimage = \"a.obj\"\nassert_reg = [\"R9=1\"]\n",
+        ] {
+            assert!(
+                matches!(parse_jobs(bad, Path::new(".")), Err(Error::BadJobs(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+}
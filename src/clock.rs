@@ -0,0 +1,105 @@
+//! The time devices see. The state owns a single [`Clock`] and gives its time to a device before every access to its
+//! registers (see [`crate::device::Device::tick`]), so nothing on the machine reads the time of the host on its own.
+//!
+//! [`RealClock`] is the wall-clock time, [`VirtualClock`] only advances with the instructions executed: a run under it
+//! sees the same times whenever and wherever it runs, what a replay needs. The time and sleep traps (x34 and x35) go
+//! through the clock too, a program sleeping under a virtual clock skips ahead at once
+
+use std::time::Duration;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
+
+use crate::InterruptHandle;
+
+/// Longest a sleeping program goes without looking at the interrupt handle
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+/// Where the time of the machine comes from, see the module documentation
+pub trait Clock: Send {
+    /// The time since the machine started, after it executed `instructions`
+    fn now(&self, instructions: u64) -> Duration;
+
+    /// Whether the time passes while the host waits, like for a key. True by default
+    fn follows_host(&self) -> bool {
+        true
+    }
+
+    /// Let `duration` pass for a sleeping program. False if `interrupt` was requested before it did.
+    /// By default the thread sleeps, waking up every few milliseconds to look at `interrupt`
+    fn sleep(&mut self, duration: Duration, interrupt: &InterruptHandle) -> bool {
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            if interrupt.is_requested() {
+                return false;
+            }
+            let slice = remaining.min(SLEEP_SLICE);
+            std::thread::sleep(slice);
+            remaining -= slice;
+        }
+        !interrupt.is_requested()
+    }
+}
+
+/// The time of the host since the clock was created. A browser has no `Instant`, there the default clock is virtual
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub struct RealClock {
+    start: Instant,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Default for RealClock {
+    fn default() -> RealClock {
+        RealClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for RealClock {
+    fn now(&self, _instructions: u64) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Every instruction takes `per_instruction`, whatever the host does, and sleeping takes no time of the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VirtualClock {
+    pub per_instruction: Duration,
+    /// Every sleep so far, added to the time of the instructions
+    slept: Duration,
+}
+
+impl VirtualClock {
+    pub fn new(per_instruction: Duration) -> VirtualClock {
+        VirtualClock {
+            per_instruction,
+            slept: Duration::ZERO,
+        }
+    }
+}
+
+/// A microsecond per instruction, a machine of 1 MIPS
+impl Default for VirtualClock {
+    fn default() -> VirtualClock {
+        VirtualClock::new(Duration::from_micros(1))
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self, instructions: u64) -> Duration {
+        let nanos = self.per_instruction.as_nanos() * instructions as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64).saturating_add(self.slept)
+    }
+
+    /// Waiting takes no time, only the instructions do
+    fn follows_host(&self) -> bool {
+        false
+    }
+
+    /// The time skips ahead right away
+    fn sleep(&mut self, duration: Duration, _interrupt: &InterruptHandle) -> bool {
+        self.slept = self.slept.saturating_add(duration);
+        true
+    }
+}
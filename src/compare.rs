@@ -0,0 +1,428 @@
+//! Two machines running in lockstep, what `run --compare` and `run --compare-self` do: after every instruction their
+//! registers are compared, and what they wrote to memory, stopping at the first difference with both machines in the
+//! report. The words that differ from the start, like the code of two different images, only count once a store or a
+//! trap writes them differently. Meant to check the decode cache or the JIT against the plain interpreter on the same
+//! image, or an image against a reference one that should behave the same. A block the JIT compiled runs at once on
+//! the first machine, the second one runs as many instructions and they are compared when the block exits.
+//!
+//! Both machines must get the same keys, [`mirrored_input`] splits an input in two: the first machine reads it and
+//! the second one gets the keys the first one consumed, in the same order
+
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::console::{Input, KeyWait};
+use crate::disassembler::disassemble;
+use crate::operations::sign_extend;
+use crate::snapshot::{REGISTER_NAMES, StateDiff};
+use crate::trace::condition_codes;
+use crate::{DEVICE_PAGE, Error, Operations, Registers, RuntimeError, State, run_with_budget};
+
+/// The first difference between two machines, see [`run_in_lockstep`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The instruction that made them differ, counting from 1. The last one of a compiled block
+    pub step: u64,
+    /// The address and the word of the instruction each machine ran, the first one of a compiled block
+    pub ours: (u16, u16),
+    pub theirs: (u16, u16),
+    /// How each machine came out of it when it wasn't the same, like one halting and not the other
+    pub outcomes: Option<(String, String)>,
+    /// The registers and the memory that differ after it
+    pub diff: StateDiff,
+    /// The registers of each machine after it, R0 to R7, PC and the flags
+    pub registers: ([u16; 10], [u16; 10]),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(formatter, "The machines diverge at step {}", self.step)?;
+        for (name, (pc, instruction), registers, outcome) in [
+            (
+                "ours",
+                self.ours,
+                self.registers.0,
+                self.outcomes.as_ref().map(|outcomes| &outcomes.0),
+            ),
+            (
+                "theirs",
+                self.theirs,
+                self.registers.1,
+                self.outcomes.as_ref().map(|outcomes| &outcomes.1),
+            ),
+        ] {
+            writeln!(
+                formatter,
+                "{:>6}: x{:04X} x{:04X} {}",
+                name,
+                pc,
+                instruction,
+                disassemble(instruction)
+            )?;
+            let mut line = String::new();
+            for (index, value) in registers.iter().enumerate().take(9) {
+                let _ = write!(line, "{}=x{:04X} ", REGISTER_NAMES[index], value);
+            }
+            let _ = write!(line, "CC={}", condition_codes(registers[9]));
+            writeln!(formatter, "{:>6}  {}", "", line)?;
+            if let Some(outcome) = outcome {
+                writeln!(formatter, "{:>6}  {}", "", outcome)?;
+            }
+        }
+        write!(formatter, "{}", self.diff)
+    }
+}
+
+/// How a machine came out of an instruction
+enum Outcome {
+    Running,
+    Halted,
+    Failed(Error),
+}
+
+impl Outcome {
+    fn describe(&self) -> String {
+        match self {
+            Outcome::Running => "running".to_string(),
+            Outcome::Halted => "halted".to_string(),
+            Outcome::Failed(error) => format!("failed: {}", error.root()),
+        }
+    }
+}
+
+/// Run a single instruction
+fn step(state: &mut State) -> Outcome {
+    match run_with_budget(state, 1) {
+        Ok(()) => Outcome::Halted,
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Outcome::Running,
+        Err(error) => Outcome::Failed(error),
+    }
+}
+
+/// Where the store about to run writes, `None` if it isn't a store
+fn store_target(state: &State, instruction: u16) -> Option<usize> {
+    let pc = state.register_read(Registers::Pc).wrapping_add(1);
+    let pc_offset = pc.wrapping_add(sign_extend(instruction & 0x1FF, 9));
+    let target = match instruction >> 12 {
+        operation if operation == Operations::St as u16 => pc_offset,
+        operation if operation == Operations::Sti as u16 => state.peek(pc_offset as usize),
+        operation if operation == Operations::Str as u16 => state.registers
+            [((instruction >> 6) & 0x7) as usize]
+            .wrapping_add(sign_extend(instruction & 0x3F, 6)),
+        _ => return None,
+    };
+    Some(target as usize)
+}
+
+/// The addresses below the device page with another word on each side
+fn differing_words(ours: &State, theirs: &State) -> Vec<usize> {
+    (0..DEVICE_PAGE)
+        .filter(|&address| ours.memory[address] != theirs.memory[address])
+        .collect()
+}
+
+/// Whether the last instruction wrote the memory differently on each side. `differing` are the words that differed
+/// already, kept up to date
+fn wrote_differently(
+    ours: &State,
+    theirs: &State,
+    instructions: [u16; 2],
+    targets: [Option<usize>; 2],
+    differing: &mut Vec<usize>,
+) -> bool {
+    let trap = Operations::Trap as u16;
+    if instructions
+        .iter()
+        .any(|instruction| instruction >> 12 == trap)
+    {
+        let now = differing_words(ours, theirs);
+        let new = now
+            .iter()
+            .any(|address| differing.binary_search(address).is_err());
+        *differing = now;
+        return new;
+    }
+    for target in targets
+        .into_iter()
+        .flatten()
+        .filter(|&target| target < DEVICE_PAGE)
+    {
+        if ours.memory[target] != theirs.memory[target] {
+            return true;
+        }
+        // Stored the same word on both sides
+        if let Ok(index) = differing.binary_search(&target) {
+            differing.remove(index);
+        }
+    }
+    false
+}
+
+/// Run both machines an instruction at a time until they both halt, one of them differs from the other or they ran
+/// `budget` instructions. `None` if they both halted the same way. When they both fail the same way, that error.
+/// A block the JIT compiled for the first machine is compared where it exits, see the module documentation
+pub fn run_in_lockstep(
+    ours: &mut State,
+    theirs: &mut State,
+    budget: u64,
+) -> Result<Option<Divergence>, Error> {
+    let mut differing = differing_words(ours, theirs);
+    let mut number = 0;
+    while number < budget {
+        let instructions = [&*ours, &*theirs].map(|state| {
+            let pc = state.register_read(Registers::Pc);
+            (pc, state.read_instruction(pc as usize))
+        });
+        #[cfg(feature = "jit")]
+        if let Some(executed) = crate::run_compiled_block(ours, budget - number) {
+            number += executed;
+            let outcome = match run_with_budget(theirs, executed) {
+                Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Outcome::Running,
+                Ok(()) => Outcome::Halted,
+                Err(error) => Outcome::Failed(error),
+            };
+            // A block never writes memory, but the other machine may have
+            let now = differing_words(ours, theirs);
+            let memory_differs = now
+                .iter()
+                .any(|address| differing.binary_search(address).is_err());
+            differing = now;
+            let outcome_differs = !matches!(outcome, Outcome::Running);
+            if outcome_differs || ours.registers != theirs.registers || memory_differs {
+                return Ok(Some(Divergence {
+                    step: number,
+                    ours: instructions[0],
+                    theirs: instructions[1],
+                    outcomes: outcome_differs
+                        .then(|| (Outcome::Running.describe(), outcome.describe())),
+                    diff: ours.diff(theirs),
+                    registers: (ours.registers, theirs.registers),
+                }));
+            }
+            continue;
+        }
+        number += 1;
+        let targets = [
+            store_target(ours, instructions[0].1),
+            store_target(theirs, instructions[1].1),
+        ];
+        let outcomes = (step(ours), step(theirs));
+        // Ctrl-C stops the first machine, not a difference
+        if let (Outcome::Failed(error), _) = &outcomes
+            && error.is_interrupted()
+        {
+            return Err(RuntimeError::Interrupted.into());
+        }
+        let outcomes_differ = match &outcomes {
+            (Outcome::Running, Outcome::Running) | (Outcome::Halted, Outcome::Halted) => false,
+            (Outcome::Failed(our_error), Outcome::Failed(their_error)) => {
+                our_error.root().to_string() != their_error.root().to_string()
+            }
+            _ => true,
+        };
+        let memory_differs = wrote_differently(
+            ours,
+            theirs,
+            instructions.map(|(_, instruction)| instruction),
+            targets,
+            &mut differing,
+        );
+        if outcomes_differ || ours.registers != theirs.registers || memory_differs {
+            return Ok(Some(Divergence {
+                step: number,
+                ours: instructions[0],
+                theirs: instructions[1],
+                outcomes: outcomes_differ.then(|| (outcomes.0.describe(), outcomes.1.describe())),
+                diff: ours.diff(theirs),
+                registers: (ours.registers, theirs.registers),
+            }));
+        }
+        match outcomes.0 {
+            Outcome::Running => {}
+            Outcome::Halted => return Ok(None),
+            Outcome::Failed(error) => return Err(error),
+        }
+    }
+    Err(RuntimeError::BudgetExhausted(budget).into())
+}
+
+/// Split `input` for two machines, see the module documentation
+pub fn mirrored_input(input: impl Input + Send + 'static) -> (LeadingInput, MirroredInput) {
+    let consumed = Arc::new(Mutex::new(VecDeque::new()));
+    (
+        LeadingInput {
+            input: Box::new(input),
+            consumed: consumed.clone(),
+        },
+        MirroredInput { consumed },
+    )
+}
+
+/// The input of the first machine, every key it takes is kept for the second one
+pub struct LeadingInput {
+    input: Box<dyn Input + Send>,
+    consumed: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl LeadingInput {
+    fn keep(&self, key: Option<u8>) -> Option<u8> {
+        if let Some(key) = key {
+            self.consumed.lock().unwrap().push_back(key);
+        }
+        key
+    }
+}
+
+impl Input for LeadingInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        let key = self.input.read_byte();
+        self.keep(key)
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        let wait = self.input.read_byte_timeout(timeout);
+        if let KeyWait::Key(key) = wait {
+            self.keep(Some(key));
+        }
+        wait
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        let key = self.input.poll_byte();
+        self.keep(key)
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        self.input.take_pending()
+    }
+}
+
+/// The input of the second machine: the keys the first one took and nothing else, never waiting
+pub struct MirroredInput {
+    consumed: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Input for MirroredInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.poll_byte()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.consumed.lock().unwrap().pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{CaptureOutput, state_with_snippet};
+
+    /// Reads two keys and adds them up three times, storing the sum
+    const SUM_KEYS: &str = ".ORIG x3000
+GETC
+ADD R1, R0, #0
+GETC
+AND R2, R2, #0
+ADD R2, R2, #3
+AGAIN ADD R3, R3, R1
+ADD R3, R3, R0
+ADD R2, R2, #-1
+BRp AGAIN
+ST R3, SUM
+HALT
+SUM .BLKW 1
+.END";
+
+    fn machines(theirs: &str) -> (State, State) {
+        let mut ours = state_with_snippet(SUM_KEYS);
+        let mut theirs = state_with_snippet(theirs);
+        let (leading, mirrored) = mirrored_input(VecDeque::from(b"ab".to_vec()));
+        ours.set_input(leading);
+        theirs.set_input(mirrored);
+        ours.set_output(CaptureOutput::default());
+        theirs.set_output(CaptureOutput::default());
+        (ours, theirs)
+    }
+
+    #[test]
+    fn the_same_program_never_diverges_even_with_the_decode_cache() {
+        let (mut ours, mut theirs) = machines(SUM_KEYS);
+        ours.enable_decode_cache();
+        assert_eq!(run_in_lockstep(&mut ours, &mut theirs, 1000).unwrap(), None);
+        assert_eq!(theirs.peek(0x300B), 3 * (b'a' as u16 + b'b' as u16));
+    }
+
+    #[test]
+    fn a_bit_of_difference_is_found_at_its_step() {
+        // ADD R3, R3, R0 becomes ADD R3, R3, R1 in the 7th word, run at steps 7, 11 and 15
+        let (mut ours, mut theirs) =
+            machines(&SUM_KEYS.replace("ADD R3, R3, R0", "ADD R3, R3, R1"));
+        let divergence = run_in_lockstep(&mut ours, &mut theirs, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.step, 7);
+        assert_eq!(divergence.ours, (0x3006, 0x16C0));
+        assert_eq!(divergence.theirs, (0x3006, 0x16C1));
+        assert_eq!(divergence.diff.registers.len(), 1);
+        assert_eq!(divergence.diff.registers[0].register, "R3");
+        assert_eq!(divergence.outcomes, None);
+        let report = divergence.to_string();
+        assert!(
+            report.starts_with(
+                "The machines diverge at step 7\n  ours: x3006 x16C0 ADD R3, R3, R0\n"
+            ),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("theirs: x3006 x16C1 ADD R3, R3, R1\n"),
+            "{}",
+            report
+        );
+
+        // A store of a different value differs in memory only
+        let (mut ours, mut theirs) = machines(&SUM_KEYS.replace("ST R3, SUM", "ST R1, SUM"));
+        let divergence = run_in_lockstep(&mut ours, &mut theirs, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.step, 18);
+        assert!(divergence.diff.registers.is_empty());
+        assert_eq!(divergence.diff.words(), 2); // The ST itself and what it stored
+    }
+
+    #[test]
+    #[cfg(feature = "jit")]
+    fn a_compiled_block_is_compared_where_it_exits() {
+        let source = ".ORIG x3000\nLD R1, COUNT\nLOOP ADD R2, R2, #1\nADD R1, R1, #-1\nBRp LOOP\nHALT\nCOUNT .FILL #1000\n.END";
+        let mut ours = state_with_snippet(source);
+        let mut theirs = state_with_snippet(source);
+        assert!(ours.enable_jit(), "the JIT doesn't support this machine");
+        // 100 passes, the loop is compiled after 64 and runs the last 36 at once
+        assert!(run_in_lockstep(&mut ours, &mut theirs, 301).is_err());
+        assert_eq!(ours.registers, theirs.registers);
+        // ADD R2, R2, #2 on the other side only, the 900 passes left run at once before they are compared
+        theirs.memory_write(0x3001, 0x14A2);
+        let divergence = run_in_lockstep(&mut ours, &mut theirs, 10_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.step, 2700);
+        assert_eq!(divergence.ours, (0x3001, 0x14A1));
+        assert_eq!(divergence.theirs, (0x3001, 0x14A2));
+        assert_eq!(divergence.diff.registers[0].register, "R2");
+    }
+
+    #[test]
+    fn halting_on_one_side_only_is_a_divergence() {
+        let (mut ours, mut theirs) = machines(&SUM_KEYS.replace("ST R3, SUM", "HALT"));
+        let divergence = run_in_lockstep(&mut ours, &mut theirs, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.step, 18);
+        assert_eq!(
+            divergence.outcomes,
+            Some(("running".to_string(), "halted".to_string()))
+        );
+    }
+}
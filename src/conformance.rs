@@ -0,0 +1,811 @@
+//! What `selftest` runs: small programs covering every opcode, both modes of ADD and AND, the condition codes every
+//! branch tests, the traps, polling the keyboard and the display, arithmetic wrapping around and the offsets at both
+//! ends of their range. Each [`Case`] is assembled by the built-in assembler and run in a headless machine with its
+//! keys given in advance and a [`VirtualClock`], then checked like `--assert-reg` and `--assert-mem` check a run (see
+//! [`crate::assertion`]) along with what it printed and how it stopped.
+//!
+//! They are the definition of what the VM does, whatever runs the instructions: [`Engine::Fast`] runs them with the
+//! decode cache and the JIT too, and must give the same results. ASSERT and DBG are left out, they are debugging aids
+//! printing to stderr
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::address::SymbolTable;
+use crate::assembler::assemble;
+use crate::assertion::Assertion;
+use crate::clock::VirtualClock;
+use crate::{Error, Registers, State, run_with_budget};
+
+/// Instructions a case runs at most, none of them needs more than a few thousand
+const CASE_BUDGET: u64 = 100_000;
+
+/// How a case must stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ending {
+    /// HALT or a store to the MCR, without an exit code
+    Halts,
+    /// EXIT with this exit code
+    Exits(u8),
+    /// With an error of this kind, named like `kind` in the summary of `--json-summary`
+    Fails(&'static str),
+}
+
+/// A program and what it must leave behind, see the module documentation
+#[derive(Clone, Copy, Debug)]
+pub struct Case {
+    pub name: &'static str,
+    source: &'static str,
+    /// What the keyboard gives, there are no more keys after them
+    keys: &'static [u8],
+    /// Like `--assert-reg`, `R2=x0007` or `COND=x0004` for the flags N
+    registers: &'static [&'static str],
+    /// Like `--assert-mem`, with the labels of the source: `COPY=x8001`
+    memory: &'static [&'static str],
+    /// Everything it printed, the `HALT` of the trap included
+    output: &'static str,
+    ending: Ending,
+}
+
+/// What every case starts from: no keys, nothing checked but halting after printing `HALT`
+const HALTS: Case = Case {
+    name: "",
+    source: "",
+    keys: &[],
+    registers: &[],
+    memory: &[],
+    output: "HALT",
+    ending: Ending::Halts,
+};
+
+/// What runs the instructions of a case
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// The plain interpreter, one instruction decoded at a time
+    #[default]
+    Interpreter,
+    /// The decode cache and, built with the `jit` feature, the JIT, like `--fast`
+    Fast,
+}
+
+/// How a case went
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaseOutcome {
+    pub name: &'static str,
+    /// Every way the case differed from what it expects, none if it passed
+    pub failures: Vec<String>,
+}
+
+impl CaseOutcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// `ok   add-register`, or `FAIL add-register: R2 is x0008 instead of x0007` with every failure
+impl fmt::Display for CaseOutcome {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.passed() {
+            true => write!(formatter, "ok   {}", self.name),
+            false => write!(
+                formatter,
+                "FAIL {}: {}",
+                self.name,
+                self.failures.join(", ")
+            ),
+        }
+    }
+}
+
+/// Run every case of [`CASES`] with `engine`, in order
+pub fn run_all(engine: Engine) -> Vec<CaseOutcome> {
+    CASES.iter().map(|case| run_case(case, engine)).collect()
+}
+
+/// Assemble, run and check one case
+pub fn run_case(case: &Case, engine: Engine) -> CaseOutcome {
+    CaseOutcome {
+        name: case.name,
+        failures: check_case(case, engine).unwrap_or_else(|error| vec![error.to_string()]),
+    }
+}
+
+/// The failures of a case, an error if it couldn't even be checked
+fn check_case(case: &Case, engine: Engine) -> Result<Vec<String>, Error> {
+    let program = assemble(case.source)?;
+    let symbols = SymbolTable::from(&program);
+    let assertions = case
+        .registers
+        .iter()
+        .map(|text| Assertion::parse_register(text))
+        .chain(
+            case.memory
+                .iter()
+                .map(|text| Assertion::parse_memory(text, &symbols)),
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+    let (mut state, mut output) = State::headless();
+    state.set_input(VecDeque::from(case.keys.to_vec()));
+    state.set_clock(VirtualClock::default());
+    if engine == Engine::Fast {
+        state.enable_decode_cache();
+        #[cfg(feature = "jit")]
+        state.enable_jit(); // Without support for this machine everything is interpreted
+    }
+    state.load_image(program.origin, &program.words);
+    state.register_write(Registers::Pc, program.origin);
+    let result = run_with_budget(&mut state, CASE_BUDGET);
+    let mut failures = Vec::new();
+    let ending = match (&result, state.exit_code()) {
+        (Err(error), _) => Ending::Fails(crate::summary::error_kind(error)),
+        (Ok(()), Some(code)) => Ending::Exits(code),
+        (Ok(()), None) => Ending::Halts,
+    };
+    if ending != case.ending {
+        failures.push(match result {
+            Err(error) => format!("stopped with {} instead of {}", error, case.ending),
+            Ok(()) => format!("{} instead of {}", ending, case.ending),
+        });
+    }
+    let printed = output.drain_text();
+    if printed != case.output {
+        failures.push(format!(
+            "printed {:?} instead of {:?}",
+            printed, case.output
+        ));
+    }
+    failures.extend(
+        assertions
+            .iter()
+            .map(|assertion| assertion.check(&state))
+            .filter(|outcome| !outcome.passed())
+            .map(|outcome| outcome.to_string()),
+    );
+    Ok(failures)
+}
+
+/// `halted`, `exited with 3` or `failed with BadOpCode`
+impl fmt::Display for Ending {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ending::Halts => write!(formatter, "halted"),
+            Ending::Exits(code) => write!(formatter, "exited with {}", code),
+            Ending::Fails(kind) => write!(formatter, "failed with {}", kind),
+        }
+    }
+}
+
+/// The branches of every condition but the empty one after setting the flags with `$setup`, R7 getting a bit per
+/// branch taken: 1 for BRn, 2 for BRz, 4 for BRp, then BRnz, BRnp, BRzp and BRnzp. The flags are set again from R0
+/// before each branch, the additions in between change them
+macro_rules! branches {
+    ($setup:literal) => {
+        concat!(
+            "        .ORIG x3000\n",
+            $setup,
+            "
+        AND R7, R7, #0
+        AND R6, R6, #0
+        ADD R6, R6, #1
+        ADD R0, R0, #0
+        BRn B_N
+        BRnzp S_N
+B_N     ADD R7, R7, R6
+S_N     ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRz B_Z
+        BRnzp S_Z
+B_Z     ADD R7, R7, R6
+S_Z     ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRp B_P
+        BRnzp S_P
+B_P     ADD R7, R7, R6
+S_P     ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRnz B_NZ
+        BRnzp S_NZ
+B_NZ    ADD R7, R7, R6
+S_NZ    ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRnp B_NP
+        BRnzp S_NP
+B_NP    ADD R7, R7, R6
+S_NP    ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRzp B_ZP
+        BRnzp S_ZP
+B_ZP    ADD R7, R7, R6
+S_ZP    ADD R6, R6, R6
+        ADD R0, R0, #0
+        BRnzp B_NZP
+        HALT
+B_NZP   ADD R7, R7, R6
+        HALT
+        .END"
+        )
+    };
+}
+
+/// Every case `selftest` runs, see the module documentation
+pub static CASES: &[Case] = &[
+    Case {
+        name: "add-register",
+        source: "
+        .ORIG x3000
+        ADD R0, R0, #3
+        ADD R1, R1, #4
+        ADD R2, R0, R1
+        HALT
+        .END",
+        registers: &["R2=7", "COND=x0001"],
+        ..HALTS
+    },
+    Case {
+        name: "add-immediate",
+        source: "
+        .ORIG x3000
+        ADD R0, R0, #15
+        ADD R1, R1, #-16
+        HALT
+        .END",
+        registers: &["R0=15", "R1=xFFF0", "COND=x0004"],
+        ..HALTS
+    },
+    Case {
+        name: "add-wraps-to-negative",
+        source: "
+        .ORIG x3000
+        LD R0, MAX
+        ADD R0, R0, #1
+        HALT
+MAX     .FILL x7FFF
+        .END",
+        registers: &["R0=x8000", "COND=x0004"],
+        ..HALTS
+    },
+    Case {
+        name: "add-wraps-around",
+        source: "
+        .ORIG x3000
+        LD R0, ALL
+        ADD R0, R0, #1
+        LD R1, MIN
+        ADD R1, R1, R0
+        ADD R1, R1, #-1
+        HALT
+ALL     .FILL xFFFF
+MIN     .FILL x8000
+        .END",
+        registers: &["R0=0", "R1=x7FFF", "COND=x0001"],
+        ..HALTS
+    },
+    Case {
+        name: "and-register",
+        source: "
+        .ORIG x3000
+        LD R0, LEFT
+        LD R1, RIGHT
+        AND R2, R0, R1
+        HALT
+LEFT    .FILL x0F0F
+RIGHT   .FILL x00FF
+        .END",
+        registers: &["R2=x000F", "COND=x0001"],
+        ..HALTS
+    },
+    Case {
+        name: "and-immediate",
+        source: "
+        .ORIG x3000
+        LD R0, WORD
+        AND R1, R0, #-16
+        AND R2, R0, #15
+        HALT
+WORD    .FILL xF0F0
+        .END",
+        registers: &["R1=xF0F0", "R2=0", "COND=x0002"],
+        ..HALTS
+    },
+    Case {
+        name: "not",
+        source: "
+        .ORIG x3000
+        NOT R1, R0
+        NOT R2, R1
+        HALT
+        .END",
+        registers: &["R1=xFFFF", "R2=0", "COND=x0002"],
+        ..HALTS
+    },
+    Case {
+        name: "branches-on-negative",
+        source: branches!("        ADD R0, R0, #-1\n"),
+        registers: &["R7=89"],
+        ..HALTS
+    },
+    Case {
+        name: "branches-on-zero",
+        source: branches!(""),
+        registers: &["R7=106"],
+        ..HALTS
+    },
+    Case {
+        name: "branches-on-positive",
+        source: branches!("        ADD R0, R0, #1\n"),
+        registers: &["R7=116"],
+        ..HALTS
+    },
+    Case {
+        name: "jmp",
+        source: "
+        .ORIG x3000
+        LEA R1, TARGET
+        JMP R1
+        ADD R0, R0, #1
+TARGET  ADD R2, R2, #1
+        HALT
+        .END",
+        registers: &["R0=0", "R2=1"],
+        ..HALTS
+    },
+    Case {
+        name: "jsr-and-ret",
+        source: "
+        .ORIG x3000
+        JSR SUB
+        ADD R1, R1, #1
+        HALT
+SUB     ADD R3, R7, #0
+        ADD R0, R0, #1
+        RET
+        .END",
+        registers: &["R0=1", "R1=1", "R3=x3001"],
+        ..HALTS
+    },
+    Case {
+        name: "jsrr",
+        source: "
+        .ORIG x3000
+        LEA R2, SUB
+        JSRR R2
+        HALT
+SUB     ADD R3, R7, #0
+        RET
+        .END",
+        registers: &["R3=x3002"],
+        ..HALTS
+    },
+    Case {
+        // The target is read before R7 gets the return address
+        name: "jsrr-through-r7",
+        source: "
+        .ORIG x3000
+        LEA R7, SUB
+        JSRR R7
+        HALT
+SUB     ADD R3, R7, #0
+        ADD R0, R0, #1
+        RET
+        .END",
+        registers: &["R0=1", "R3=x3002"],
+        ..HALTS
+    },
+    Case {
+        name: "lea",
+        source: "
+        .ORIG x3000
+        LEA R0, DATA
+        HALT
+DATA    .FILL 0
+        .END",
+        registers: &["R0=x3002"],
+        ..HALTS
+    },
+    Case {
+        name: "ld-and-st",
+        source: "
+        .ORIG x3000
+        LD R0, VALUE
+        ST R0, COPY
+        HALT
+VALUE   .FILL x8001
+COPY    .FILL 0
+        .END",
+        registers: &["R0=x8001", "COND=x0004"],
+        memory: &["COPY=x8001"],
+        ..HALTS
+    },
+    Case {
+        name: "ldi-and-sti",
+        source: "
+        .ORIG x3000
+        LDI R0, SOURCE
+        STI R0, TARGET
+        HALT
+SOURCE  .FILL VALUE
+TARGET  .FILL COPY
+VALUE   .FILL x1234
+COPY    .FILL 0
+        .END",
+        registers: &["R0=x1234", "COND=x0001"],
+        memory: &["COPY=x1234"],
+        ..HALTS
+    },
+    Case {
+        // Offsets of -32 and 31 from R2, then swapped
+        name: "ldr-and-str-offsets",
+        source: "
+        .ORIG x3000
+        LEA R2, MIDDLE
+        LDR R0, R2, #-32
+        LDR R1, R2, #31
+        STR R1, R2, #-32
+        STR R0, R2, #31
+        HALT
+LOW     .FILL x1111
+        .BLKW 31
+MIDDLE  .FILL 0
+        .BLKW 30
+HIGH    .FILL x2222
+        .END",
+        registers: &["R0=x1111", "R1=x2222", "COND=x0001"],
+        memory: &["LOW=x2222", "HIGH=x1111"],
+        ..HALTS
+    },
+    Case {
+        // A branch of 255, a load of -256 and one of 255
+        name: "pc-offsets",
+        source: "
+        .ORIG x3000
+        BRnzp START
+NEAR    .FILL x5678
+        .BLKW 254
+START   LD R1, NEAR
+        LD R2, FAR
+        HALT
+        .BLKW 254
+FAR     .FILL x1234
+        .END",
+        registers: &["R1=x5678", "R2=x1234"],
+        ..HALTS
+    },
+    Case {
+        // A JSR of 1023 and one of -1024
+        name: "jsr-offsets",
+        source: "
+        .ORIG x3000
+        JSR FAR
+        HALT
+BACK    ADD R1, R1, #1
+        RET
+        .BLKW 1020
+FAR     ADD R2, R7, #0
+        JSR BACK
+        ADD R7, R2, #0
+        ADD R0, R0, #1
+        RET
+        .END",
+        registers: &["R0=1", "R1=1"],
+        ..HALTS
+    },
+    Case {
+        name: "rti-without-interrupts",
+        source: "
+        .ORIG x3000
+        RTI
+        .END",
+        output: "",
+        ending: Ending::Fails("BadOpCode"),
+        ..HALTS
+    },
+    Case {
+        name: "reserved-opcode",
+        source: "
+        .ORIG x3000
+        .FILL xD000
+        .END",
+        output: "",
+        ending: Ending::Fails("BadOpCode"),
+        ..HALTS
+    },
+    Case {
+        name: "trap-getc",
+        source: "
+        .ORIG x3000
+        GETC
+        HALT
+        .END",
+        keys: b"a",
+        registers: &["R0=x61", "COND=x0001"],
+        ..HALTS
+    },
+    Case {
+        name: "trap-getc-without-keys",
+        source: "
+        .ORIG x3000
+        GETC
+        HALT
+        .END",
+        output: "",
+        ending: Ending::Fails("Trap"),
+        ..HALTS
+    },
+    Case {
+        name: "trap-out",
+        source: "
+        .ORIG x3000
+        LD R0, CHAR
+        OUT
+        HALT
+CHAR    .FILL x78
+        .END",
+        output: "xHALT",
+        ..HALTS
+    },
+    Case {
+        name: "trap-puts",
+        source: "
+        .ORIG x3000
+        LEA R0, MESSAGE
+        PUTS
+        HALT
+MESSAGE .STRINGZ \"Hi\\n\"
+        .END",
+        output: "Hi\nHALT",
+        ..HALTS
+    },
+    Case {
+        name: "trap-in",
+        source: "
+        .ORIG x3000
+        IN
+        HALT
+        .END",
+        keys: b"q",
+        registers: &["R0=x71"],
+        output: "Enter character: qHALT",
+        ..HALTS
+    },
+    Case {
+        name: "trap-putsp",
+        source: "
+        .ORIG x3000
+        LEA R0, PACKED
+        PUTSP
+        HALT
+PACKED  .FILL x6548
+        .FILL x6C6C
+        .FILL x006F
+        .FILL 0
+        .END",
+        output: "HelloHALT",
+        ..HALTS
+    },
+    Case {
+        name: "trap-halt",
+        source: "
+        .ORIG x3000
+        HALT
+        ADD R0, R0, #1
+        .END",
+        registers: &["R0=0"],
+        ..HALTS
+    },
+    Case {
+        name: "trap-exit",
+        source: "
+        .ORIG x3000
+        ADD R0, R0, #3
+        TRAP x27
+        ADD R1, R1, #1
+        .END",
+        registers: &["R1=0"],
+        output: "",
+        ending: Ending::Exits(3),
+        ..HALTS
+    },
+    Case {
+        name: "trap-readline",
+        source: "
+        .ORIG x3000
+        LEA R0, BUFFER
+        AND R1, R1, #0
+        ADD R1, R1, #8
+        TRAP x29
+        HALT
+BUFFER  .BLKW 9
+        .END",
+        keys: b"hey\n",
+        registers: &["R0=3", "COND=x0001"],
+        memory: &["BUFFER:BUFFER+3=x68,x65,x79,0"],
+        output: "hey\nHALT",
+        ..HALTS
+    },
+    Case {
+        // The virtual clock only moves by a microsecond per instruction and by what is slept
+        name: "trap-sleep-and-time",
+        source: "
+        .ORIG x3000
+        LD R0, DELAY
+        TRAP x35
+        TRAP x34
+        HALT
+DELAY   .FILL #50
+        .END",
+        registers: &["R0=50", "R1=0"],
+        ..HALTS
+    },
+    Case {
+        name: "trap-file-without-sandbox",
+        source: "
+        .ORIG x3000
+        LEA R0, NAME
+        AND R1, R1, #0
+        TRAP x30
+        HALT
+NAME    .STRINGZ \"file.txt\"
+        .END",
+        registers: &["R0=xFFFF", "COND=x0004"],
+        ..HALTS
+    },
+    Case {
+        name: "trap-unknown",
+        source: "
+        .ORIG x3000
+        TRAP x50
+        .END",
+        output: "",
+        ending: Ending::Fails("BadTrapCode"),
+        ..HALTS
+    },
+    Case {
+        // Polling KBSR until a key is ready, and finding none once both were read
+        name: "keyboard-polling",
+        source: "
+        .ORIG x3000
+POLL1   LDI R1, KBSR
+        BRzp POLL1
+        LDI R2, KBDR
+POLL2   LDI R1, KBSR
+        BRzp POLL2
+        LDI R3, KBDR
+        LDI R4, KBSR
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END",
+        keys: b"ok",
+        registers: &["R2=x6F", "R3=x6B", "R4=0"],
+        ..HALTS
+    },
+    Case {
+        name: "display-polling",
+        source: "
+        .ORIG x3000
+        LD R0, CHAR
+POLL    LDI R1, DSR
+        BRzp POLL
+        STI R0, DDR
+        HALT
+CHAR    .FILL x41
+DSR     .FILL xFE04
+DDR     .FILL xFE06
+        .END",
+        output: "AHALT",
+        ..HALTS
+    },
+    Case {
+        name: "mcr-stops-the-machine",
+        source: "
+        .ORIG x3000
+        AND R0, R0, #0
+        STI R0, MCR
+        ADD R1, R1, #1
+        HALT
+MCR     .FILL xFFFE
+        .END",
+        registers: &["R1=0"],
+        output: "",
+        ..HALTS
+    },
+    Case {
+        // Enough laps for the JIT to compile the loop
+        name: "hot-loop",
+        source: "
+        .ORIG x3000
+        LD R0, LAPS
+LOOP    ADD R1, R1, #2
+        ADD R0, R0, #-1
+        BRp LOOP
+        HALT
+LAPS    .FILL #1000
+        .END",
+        registers: &["R0=0", "R1=2000"],
+        ..HALTS
+    },
+    Case {
+        // The second lap runs the instruction the first one stored, not the one decoded before
+        name: "self-modifying-code",
+        source: "
+        .ORIG x3000
+        AND R2, R2, #0
+        ADD R2, R2, #2
+PATCH   ADD R1, R1, #1
+        LD R0, NEW
+        ST R0, PATCH
+        ADD R2, R2, #-1
+        BRp PATCH
+        HALT
+NEW     ADD R1, R1, #5
+        .END",
+        registers: &["R1=6"],
+        memory: &["PATCH=x1265"],
+        ..HALTS
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(engine: Engine) -> Vec<String> {
+        run_all(engine)
+            .iter()
+            .filter(|outcome| !outcome.passed())
+            .map(CaseOutcome::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn every_case_passes_in_the_interpreter() {
+        assert_eq!(failed(Engine::Interpreter), Vec::<String>::new());
+    }
+
+    #[test]
+    fn every_case_passes_with_the_decode_cache_and_the_jit() {
+        assert_eq!(failed(Engine::Fast), Vec::<String>::new());
+    }
+
+    #[test]
+    fn cases_have_unique_names() {
+        let mut names: Vec<&str> = CASES.iter().map(|case| case.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), CASES.len());
+    }
+
+    #[test]
+    fn a_case_reports_every_way_it_differs() {
+        let case = Case {
+            name: "wrong",
+            source: "
+        .ORIG x3000
+        ADD R0, R0, #1
+        OUT
+        TRAP x27
+        .END",
+            registers: &["R0=2"],
+            memory: &["x3000=0"],
+            ..HALTS
+        };
+        let outcome = run_case(&case, Engine::Interpreter);
+        assert_eq!(
+            outcome.to_string(),
+            "FAIL wrong: exited with 1 instead of halted, printed \"\\u{1}\" instead of \"HALT\", \
+             FAIL R0=x0002 (found x0001), FAIL x3000=x0000 (found x1021)"
+        );
+    }
+
+    #[test]
+    fn a_case_that_doesnt_assemble_fails() {
+        let case = Case {
+            name: "broken",
+            source: ".ORIG x3000\nADD R9, R0, #1\n.END",
+            ..HALTS
+        };
+        let outcome = run_case(&case, Engine::Interpreter);
+        assert!(!outcome.passed());
+        assert_eq!(outcome.failures.len(), 1);
+    }
+}
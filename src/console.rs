@@ -0,0 +1,489 @@
+use crate::InterruptHandle;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "cli")]
+use {
+    std::io::{Read, stdin},
+    std::sync::mpsc::{Receiver, RecvTimeoutError, channel, sync_channel},
+    std::thread::{self, JoinHandle},
+};
+
+/// Keys read from the terminal that the program hasn't consumed yet
+#[cfg(feature = "cli")]
+const KEY_BUFFER: usize = 64;
+
+/// Source of the bytes typed on the keyboard.
+/// GETC and IN wait for the next byte while the keyboard status register only checks if one is available
+pub trait Input {
+    /// Wait for the next byte, `None` when the input has ended
+    fn read_byte(&mut self) -> Option<u8>;
+    /// Take the next byte only if it is available right now
+    fn poll_byte(&mut self) -> Option<u8>;
+    /// Wait for the next byte at most `timeout`, for `--input-timeout`. By default the wait never times out, like
+    /// [`Input::read_byte`]: an input that never waits for the host doesn't need to
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        let _ = timeout;
+        KeyWait::from(self.read_byte())
+    }
+    /// Take the bytes already received that the program never consumed
+    fn take_pending(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Keys lost because they arrived with the buffer full, `None` for an input that never drops any
+    fn keys_dropped(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// What waiting for a key with [`Input::read_byte_timeout`] gave
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyWait {
+    Key(u8),
+    /// The input ended, or an interrupt was requested meanwhile, when [`Input::read_byte`] gives `None`
+    Ended,
+    TimedOut,
+}
+
+impl From<Option<u8>> for KeyWait {
+    fn from(key: Option<u8>) -> KeyWait {
+        match key {
+            Some(key) => KeyWait::Key(key),
+            None => KeyWait::Ended,
+        }
+    }
+}
+
+/// The keyboard of the terminal running the VM.
+/// A background thread, started the first time the keyboard is used, blocks reading stdin and sends every byte through a channel,
+/// so polling the keyboard status register is a `try_recv` instead of a syscall.
+/// The thread reads ahead of the program: up to [`KEY_BUFFER`] typed keys wait in the channel and can be taken with [`Input::take_pending`]
+/// once the program stops, they can't be given back to the terminal.
+/// Once the input is dropped the thread stops after the next key it reads, or when stdin ends.
+/// Only built with the `cli` feature
+#[cfg(feature = "cli")]
+#[derive(Default)]
+pub struct StdinInput {
+    keys: Option<Receiver<u8>>,
+}
+
+#[cfg(feature = "cli")]
+impl StdinInput {
+    fn keys(&mut self) -> &Receiver<u8> {
+        self.keys.get_or_insert_with(|| spawn_reader(stdin()).keys)
+    }
+}
+
+/// A thread reading a byte at a time and sending them through [`KeyReader::keys`]
+#[cfg(feature = "cli")]
+#[cfg_attr(not(any(unix, windows)), allow(dead_code))] // Only the terminal input joins it
+pub(crate) struct KeyReader {
+    pub(crate) keys: Receiver<u8>,
+    /// Disconnected once the thread is about to end
+    pub(crate) finished: Receiver<()>,
+    pub(crate) thread: JoinHandle<()>,
+}
+
+/// The thread ends when the reader fails or ends, or after the next byte once the keys receiver is dropped
+#[cfg(feature = "cli")]
+pub(crate) fn spawn_reader(mut reader: impl Read + Send + 'static) -> KeyReader {
+    let (sender, keys) = sync_channel(KEY_BUFFER);
+    let (finished_sender, finished) = channel::<()>();
+    let thread = thread::spawn(move || {
+        let _finished = finished_sender;
+        let mut buffer = [0_u8];
+        while reader.read_exact(&mut buffer).is_ok() && sender.send(buffer[0]).is_ok() {}
+    });
+    KeyReader {
+        keys,
+        finished,
+        thread,
+    }
+}
+
+#[cfg(feature = "cli")]
+impl Input for StdinInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.keys().recv().ok()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.keys().try_recv().ok()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        match self.keys().recv_timeout(timeout) {
+            Ok(key) => KeyWait::Key(key),
+            Err(RecvTimeoutError::Timeout) => KeyWait::TimedOut,
+            Err(RecvTimeoutError::Disconnected) => KeyWait::Ended,
+        }
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        match &self.keys {
+            Some(keys) => keys.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Stdin where there is no terminal nor threads to read it in the background, like under WASI.
+/// GETC and IN block reading the next byte and the keyboard registers never have a key, since there is no way to know
+/// whether one is available without waiting for it. Only built with the `cli` feature
+#[cfg(feature = "cli")]
+pub struct BlockingStdinInput;
+
+#[cfg(feature = "cli")]
+impl Input for BlockingStdinInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0_u8];
+        stdin().read_exact(&mut byte).ok().map(|_| byte[0])
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// A keyboard nobody types on: a key is never ready and GETC or IN fail right away instead of waiting
+pub struct NoInput;
+
+impl Input for NoInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+/// Bytes known in advance, every one of them is available right away
+impl Input for VecDeque<u8> {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.pop_front()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.pop_front()
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        self.drain(..).collect()
+    }
+}
+
+/// The keyboard of a machine without a terminal (a web page, a Python script, an editor plugin, a test):
+/// the keys are pushed through this handle and GETC, IN and the keyboard registers take them in order, each exactly once.
+/// Clones share the same keys, so a handle can push from another thread, or from JavaScript, while the VM runs.
+/// GETC and IN never wait for a key: without one the run stops through the interrupt handle, the trap goes back
+/// so it runs again once the run is resumed. See [`crate::State::attach_keyboard_buffer`]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+#[derive(Clone)]
+pub struct KeyboardBuffer {
+    keys: Arc<Mutex<VecDeque<u8>>>,
+    waiting: InterruptHandle,
+}
+
+impl KeyboardBuffer {
+    pub(crate) fn new(waiting: InterruptHandle) -> KeyboardBuffer {
+        KeyboardBuffer {
+            keys: Arc::default(),
+            waiting,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+impl KeyboardBuffer {
+    /// Queue a key
+    pub fn push_key(&self, key: u8) {
+        self.keys.lock().unwrap().push_back(key);
+    }
+
+    /// Queue every byte of the text, in order
+    pub fn push_str(&self, text: &str) {
+        self.keys.lock().unwrap().extend(text.bytes());
+    }
+
+    /// Keys queued that the program hasn't read yet
+    pub fn pending(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    /// Drop every queued key
+    pub fn clear(&self) {
+        self.keys.lock().unwrap().clear();
+    }
+}
+
+impl Input for KeyboardBuffer {
+    fn read_byte(&mut self) -> Option<u8> {
+        let key = self.poll_byte();
+        if key.is_none() {
+            self.waiting.request();
+        }
+        key
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.keys.lock().unwrap().pop_front()
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        self.keys.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// Keys a [`KeyboardFifo`] holds without `--kb-buffer`
+pub const DEFAULT_KEYBOARD_DEPTH: usize = 16;
+
+/// What a full [`KeyboardFifo`] does with a key that arrives
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyboardOverflow {
+    /// The key that arrived is lost
+    #[default]
+    DropNew,
+    /// The key waiting the longest is lost to make room
+    DropOld,
+}
+
+/// Type-ahead of a fixed depth in front of another input, what `--kb-buffer` and `--kb-overflow` set up.
+/// Every time the program looks at the keyboard, the keys the other input has right now arrive in the buffer and
+/// those that don't fit are dropped and counted. A script is all there from the start, so past the depth it is the
+/// overflow that decides which of its bytes the program sees. See [`crate::State::buffer_keyboard`]
+pub struct KeyboardFifo {
+    input: Box<dyn Input + Send>,
+    keys: VecDeque<u8>,
+    depth: usize,
+    overflow: KeyboardOverflow,
+    dropped: u64,
+}
+
+impl KeyboardFifo {
+    pub fn new(
+        input: Box<dyn Input + Send>,
+        depth: usize,
+        overflow: KeyboardOverflow,
+    ) -> KeyboardFifo {
+        KeyboardFifo {
+            input,
+            keys: VecDeque::with_capacity(depth),
+            depth,
+            overflow,
+            dropped: 0,
+        }
+    }
+
+    /// Take every key available in the other input, dropping those that don't fit
+    fn receive(&mut self) {
+        while let Some(key) = self.input.poll_byte() {
+            if self.keys.len() < self.depth {
+                self.keys.push_back(key);
+                continue;
+            }
+            self.dropped += 1;
+            if self.overflow == KeyboardOverflow::DropOld && self.depth > 0 {
+                self.keys.pop_front();
+                self.keys.push_back(key);
+            }
+        }
+    }
+}
+
+impl Input for KeyboardFifo {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.receive();
+        self.keys.pop_front().or_else(|| self.input.read_byte())
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.receive();
+        self.keys.pop_front()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        self.receive();
+        match self.keys.pop_front() {
+            Some(key) => KeyWait::Key(key),
+            None => self.input.read_byte_timeout(timeout),
+        }
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        let mut pending: Vec<u8> = self.keys.drain(..).collect();
+        pending.extend(self.input.take_pending());
+        pending
+    }
+
+    fn keys_dropped(&self) -> Option<u64> {
+        Some(self.dropped)
+    }
+}
+
+/// What the program printed, kept in memory until whoever embeds the VM drains it, a bit at a time while it runs or
+/// all at once at the end. Clones share the same bytes, so one can be given to the state and the other kept to read them
+#[derive(Clone, Default)]
+pub struct MemoryOutput(Arc<Mutex<Captured>>);
+
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    /// Bytes kept between drains, `None` for as many as the program prints
+    capacity: Option<usize>,
+    /// Bytes printed while it was full
+    overflowed: u64,
+}
+
+impl MemoryOutput {
+    /// Keep at most `capacity` bytes between drains, so a program printing in a loop can't take all the memory. The
+    /// bytes printed past it are dropped, see [`MemoryOutput::overflowed`]
+    pub fn bounded(capacity: usize) -> MemoryOutput {
+        let output = MemoryOutput::default();
+        output.set_capacity(Some(capacity));
+        output
+    }
+
+    /// See [`MemoryOutput::bounded`], `None` keeps everything. Bytes already kept past a smaller capacity stay
+    pub fn set_capacity(&self, capacity: Option<usize>) {
+        self.0.lock().unwrap().capacity = capacity;
+    }
+
+    /// Everything printed since the last drain
+    pub fn drain_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap().bytes)
+    }
+
+    /// Like [`MemoryOutput::drain_output`] with every byte as the character with that code
+    pub fn drain_text(&mut self) -> String {
+        self.drain_output().into_iter().map(char::from).collect()
+    }
+
+    /// Bytes waiting to be drained
+    pub fn output_len(&self) -> usize {
+        self.0.lock().unwrap().bytes.len()
+    }
+
+    /// Bytes dropped because the output was full, since it was created
+    pub fn overflowed(&self) -> u64 {
+        self.0.lock().unwrap().overflowed
+    }
+}
+
+impl Write for MemoryOutput {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut captured = self.0.lock().unwrap();
+        let room = match captured.capacity {
+            Some(capacity) => capacity.saturating_sub(captured.bytes.len()),
+            None => usize::MAX,
+        };
+        let kept = bytes.len().min(room);
+        captured.bytes.extend_from_slice(&bytes[..kept]);
+        captured.overflowed += (bytes.len() - kept) as u64;
+        // The program never sees the output fill up, it would stop on an error
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Bytes kept before writing them even without a newline
+const FLUSH_THRESHOLD: usize = 4096;
+
+/// Output for the terminal. Bytes are kept until a newline, until there are [`FLUSH_THRESHOLD`] of them
+/// or until it is flushed, then they are written at once, so printing a character takes the stdout lock once per flush instead of once per character.
+/// The VM flushes it before waiting for a key, when polling the keyboard and on HALT so prompts are always visible
+pub struct BufferedOutput<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BufferedOutput<W> {
+    pub fn new(inner: W) -> BufferedOutput<W> {
+        BufferedOutput {
+            inner,
+            buffer: Vec::with_capacity(FLUSH_THRESHOLD),
+        }
+    }
+}
+
+impl<W: Write> Write for BufferedOutput<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if bytes.contains(&b'\n') || self.buffer.len() >= FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.inner.flush()
+    }
+}
+
+/// Whatever is left is written when the VM goes away, even if it stopped with an error
+impl<W: Write> Drop for BufferedOutput<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Records every write the buffer makes to the inner writer
+    #[derive(Clone, Default)]
+    struct Writes(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl Write for Writes {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().push(bytes.to_vec());
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_on_newline_threshold_flush_and_drop() {
+        let writes = Writes::default();
+        let mut output = BufferedOutput::new(writes.clone());
+        output.write_all(b"ab").unwrap();
+        output.write_all(b"c\n").unwrap();
+        output.write_all(b"prompt").unwrap();
+        assert_eq!(*writes.0.lock().unwrap(), vec![b"abc\n".to_vec()]);
+        output.flush().unwrap();
+        output.flush().unwrap();
+        assert_eq!(writes.0.lock().unwrap().len(), 2);
+        output.write_all(&[b'x'; FLUSH_THRESHOLD]).unwrap();
+        assert_eq!(writes.0.lock().unwrap().len(), 3);
+        output.write_all(b"left").unwrap();
+        drop(output);
+        assert_eq!(writes.0.lock().unwrap().last().unwrap(), b"left");
+    }
+
+    #[test]
+    fn a_bounded_output_drops_and_counts_what_does_not_fit() {
+        let mut output = MemoryOutput::bounded(4);
+        output.write_all(b"abc").unwrap();
+        output.write_all(b"def").unwrap();
+        assert_eq!(output.output_len(), 4);
+        assert_eq!(output.overflowed(), 2);
+        assert_eq!(output.drain_output(), b"abcd");
+        output.write_all(b"gh").unwrap();
+        assert_eq!(output.drain_text(), "gh");
+        assert_eq!(output.overflowed(), 2);
+    }
+}
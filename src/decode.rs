@@ -0,0 +1,81 @@
+use crate::operations::*;
+use crate::{DEVICE_PAGE, Error, MEM_MAX, Operations, State};
+
+/// What executes an instruction, the same routines [`crate::run_step`] dispatches to
+type Handler = fn(u16, &mut State) -> Result<(), Error>;
+
+/// An instruction already decoded: its routine is picked once instead of on every execution
+#[derive(Clone, Copy)]
+pub(crate) struct Instruction {
+    word: u16,
+    handler: Handler,
+}
+
+impl Instruction {
+    pub(crate) fn decode(word: u16) -> Instruction {
+        let operation = Operations::try_from(word >> 12).unwrap(); // A 4 bit op code always maps to an operation
+        let handler: Handler = match operation {
+            Operations::Br => |instruction, state| {
+                conditional_branch(instruction, state);
+                Ok(())
+            },
+            Operations::Add => add,
+            Operations::Ld => load,
+            Operations::St => store,
+            Operations::Jsr => jump_to_subrutine,
+            Operations::And => and,
+            Operations::Ldr => load_register,
+            Operations::Str => store_register,
+            Operations::Rti => crate::irq::return_from_interrupt,
+            Operations::Not => not,
+            Operations::Ldi => load_indirect,
+            Operations::Sti => store_indirect,
+            Operations::Jmp => jump,
+            Operations::Res => crate::irq::reserved,
+            Operations::Lea => load_effective_address,
+            Operations::Trap => trap,
+        };
+        Instruction { word, handler }
+    }
+
+    pub(crate) fn word(self) -> u16 {
+        self.word
+    }
+
+    #[inline]
+    pub(crate) fn execute(self, state: &mut State) -> Result<(), Error> {
+        (self.handler)(self.word, state)
+    }
+}
+
+/// Decoded instructions by address, filled the first time each address is executed.
+/// Writing to an address forgets its instruction so self-modifying code keeps working.
+/// The device registers change without being written, so they are never cached
+pub(crate) struct DecodeCache {
+    instructions: Vec<Option<Instruction>>,
+}
+
+impl DecodeCache {
+    pub(crate) fn new() -> DecodeCache {
+        DecodeCache {
+            instructions: vec![None; MEM_MAX],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, address: usize) -> Option<Instruction> {
+        self.instructions[address]
+    }
+
+    #[inline]
+    pub(crate) fn insert(&mut self, address: usize, instruction: Instruction) {
+        if address < DEVICE_PAGE {
+            self.instructions[address] = Some(instruction);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        self.instructions[address] = None;
+    }
+}
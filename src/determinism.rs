@@ -0,0 +1,157 @@
+//! What `--deterministic` does: every run of the same program with the same options gives the same trace, output and
+//! summary, byte for byte. Everything that could make two runs differ is declared in a [`RunConfig`] by the option
+//! bringing it, and [`RunConfig::validate`] decides in a single place what happens to it under `--deterministic`:
+//! * the time of the host gives way to a [`crate::clock::VirtualClock`], the devices looked at every
+//!   [`crate::device::DEFAULT_TICK_GRANULE`] instructions
+//! * the measures of the host, like the wall time of `--json-summary`, are left out of the reports
+//! * a keyboard somebody types on, a limit in wall-clock time like `--timeout` or a device reading the host, like a
+//!   plugin, have no reproducible form: the run is refused
+//!
+//! A new option reading the host must declare itself here, the run then can't be reproducible by accident.
+//!
+//! The regions the run reserves beside the images are in it too, for the checks of [`crate::layout::LoadMap::validate`]
+
+use std::fmt;
+
+use crate::Error;
+use crate::device::DEFAULT_TICK_GRANULE;
+
+/// Something that makes two runs of the same program differ, see the module documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nondeterminism {
+    /// The devices and the time traps read the time of the host, see [`crate::clock::RealClock`]
+    HostClock,
+    /// The keys come from whoever types them instead of a file
+    Keyboard,
+    /// A limit measured on the host, the program stops wherever it happens to be
+    HostLimit,
+    /// A device reading the host, what it gives the program can't be known
+    HostDevice,
+    /// A measure of the host in a report, like a wall time
+    HostMeasure,
+}
+
+impl Nondeterminism {
+    /// Whether [`RunConfig::validate`] can force it into a reproducible form instead of refusing the run
+    pub fn reproducible(self) -> bool {
+        matches!(
+            self,
+            Nondeterminism::HostClock | Nondeterminism::HostMeasure
+        )
+    }
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Nondeterminism::HostClock => "reads the time of the host",
+            Nondeterminism::Keyboard => "takes the keys somebody types",
+            Nondeterminism::HostLimit => "stops the program after a time of the host",
+            Nondeterminism::HostDevice => "adds a device reading the host",
+            Nondeterminism::HostMeasure => "reports measures of the host",
+        })
+    }
+}
+
+/// The sources of nondeterminism of a run, each with the option that brought it, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunConfig {
+    /// Refuse or force into their reproducible form the sources declared, what `--deterministic` turns on
+    pub deterministic: bool,
+    declared: Vec<(String, Nondeterminism)>,
+    /// The inclusive region of `--stack`
+    pub stack: Option<(u16, u16)>,
+    /// The inclusive region the block of `--arg` and `--env` is written to, see [`crate::arguments`]
+    pub arguments: Option<(u16, u16)>,
+}
+
+/// How [`RunConfig::validate`] says the run must go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunPlan {
+    /// Give the devices a [`crate::clock::VirtualClock`] instead of the time of the host
+    pub virtual_clock: bool,
+    /// Look at the devices every that many instructions, see [`crate::State::set_tick_granule`]
+    pub tick_granule: Option<u64>,
+    /// Keep the measures of the host in the reports
+    pub host_measures: bool,
+}
+
+impl RunConfig {
+    pub fn new(deterministic: bool) -> RunConfig {
+        RunConfig {
+            deterministic,
+            declared: Vec::new(),
+            stack: None,
+            arguments: None,
+        }
+    }
+
+    /// `option` brings `source` to the run
+    pub fn declare(&mut self, option: impl Into<String>, source: Nondeterminism) {
+        self.declared.push((option.into(), source));
+    }
+
+    /// The sources declared so far with their options
+    pub fn declared(&self) -> &[(String, Nondeterminism)] {
+        &self.declared
+    }
+
+    /// How the run goes: as declared, or without anything nondeterministic under
+    /// [`RunConfig::deterministic`]. Fails with the first option that can't be made reproducible
+    pub fn validate(&self) -> Result<RunPlan, Error> {
+        if !self.deterministic {
+            return Ok(RunPlan {
+                virtual_clock: false,
+                tick_granule: None,
+                host_measures: true,
+            });
+        }
+        if let Some((option, source)) = self
+            .declared
+            .iter()
+            .find(|(_, source)| !source.reproducible())
+        {
+            return Err(Error::BadArgument(format!(
+                "--deterministic with {}, it {}",
+                option, source
+            )));
+        }
+        Ok(RunPlan {
+            virtual_clock: true,
+            tick_granule: Some(DEFAULT_TICK_GRANULE),
+            host_measures: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_clock_and_the_measures_can_be_made_reproducible() {
+        let mut config = RunConfig::new(false);
+        config.declare("the clock", Nondeterminism::HostClock);
+        config.declare("--json-summary", Nondeterminism::HostMeasure);
+        assert!(config.validate().unwrap().host_measures);
+        config.deterministic = true;
+        assert_eq!(
+            config.validate().unwrap(),
+            RunPlan {
+                virtual_clock: true,
+                tick_granule: Some(DEFAULT_TICK_GRANULE),
+                host_measures: false,
+            }
+        );
+        config.declare("--timeout", Nondeterminism::HostLimit);
+        config.declare("--device", Nondeterminism::HostDevice);
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            Error::BadArgument(
+                "--deterministic with --timeout, it stops the program after a time of the host"
+                    .to_string()
+            )
+            .to_string()
+        );
+    }
+}
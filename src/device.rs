@@ -0,0 +1,142 @@
+//! Devices mapped on the device page (xFE00 to xFFFF): loads and stores to their registers reach the device instead of memory.
+//! The keyboard (KBSR at xFE00 and KBDR at xFE02) is built in, any other device is added with [`crate::State::add_device`]
+//!
+//! A device is ticked before every read and write of its registers. One that has to do something on its own meanwhile,
+//! like a timer firing its interrupt, says when with [`Device::next_tick`]: the run loops then tick it once it is due,
+//! looking every [`DEFAULT_TICK_GRANULE`] instructions (see [`crate::State::set_tick_granule`]) instead of after every
+//! instruction. A device that doesn't ask is never ticked by them, and without devices they don't even look
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use crate::State;
+use crate::irq::InterruptRequest;
+
+pub mod console;
+pub mod counter;
+#[cfg(all(feature = "plugins", any(unix, windows)))]
+pub mod plugin;
+pub mod serial;
+pub mod timer;
+
+/// Instructions between the looks of the run loops at the devices that asked for a tick
+pub const DEFAULT_TICK_GRANULE: u64 = 64;
+
+/// A device with registers on the device page
+pub trait Device: Send {
+    /// The addresses of its registers, all of them on the device page. It is asked once, when the device is added
+    fn range(&self) -> RangeInclusive<u16>;
+    /// A program loaded one of its registers
+    fn read(&mut self, address: u16) -> u16;
+    /// A program stored to one of its registers
+    fn write(&mut self, address: u16, value: u16);
+    /// The time of the machine, given before every read and write. A device that depends on the time must take it
+    /// from here instead of the host, see [`crate::clock`]
+    fn tick(&mut self, _now: Duration) {}
+    /// The instructions the machine retired, given before every read and write after the time. Exact only for a device
+    /// that [`Device::counts_instructions`]
+    fn retired(&mut self, _instructions: u64) {}
+    /// Whether the device needs the exact count of [`Device::retired`]. The run loops count a chunk of instructions at
+    /// a time, with such a device they count every one instead, which is slower. Asked once, when the device is added
+    fn counts_instructions(&self) -> bool {
+        false
+    }
+    /// When the device needs a tick without any read or write of its registers, in the time of the machine. The run
+    /// loops tick it at the first look past that, so up to a granule late. `None`, the default, while it doesn't.
+    /// Asked at every look
+    fn next_tick(&self) -> Option<Duration> {
+        None
+    }
+    /// The interrupt the device asks for, after a tick. Only asked between instructions with an interrupt controller,
+    /// see [`crate::irq`]. The request should stay up until the program clears it through a register
+    fn interrupt(&mut self) -> Option<InterruptRequest> {
+        None
+    }
+    /// What the device is called in the reports, like the table of `--report-writes` (see [`crate::write_log`])
+    fn name(&self) -> &str {
+        "device"
+    }
+    /// What the device prints, taken after every store to it and written to the output of the machine
+    fn take_output(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// A device and the registers it was added with
+pub(crate) struct MappedDevice {
+    pub(crate) range: RangeInclusive<u16>,
+    pub(crate) device: Box<dyn Device>,
+    /// The last word read from each register, what [`crate::State::peek`] gives without asking the device
+    pub(crate) latched: Vec<u16>,
+}
+
+impl MappedDevice {
+    pub(crate) fn new(range: RangeInclusive<u16>, device: Box<dyn Device>) -> MappedDevice {
+        let latched = vec![0; range.len()];
+        MappedDevice {
+            range,
+            device,
+            latched,
+        }
+    }
+
+    /// Read a register through the device, latching the word
+    pub(crate) fn read(&mut self, address: u16) -> u16 {
+        let value = self.device.read(address);
+        self.latched[(address - self.range.start()) as usize] = value;
+        value
+    }
+}
+
+impl State {
+    /// Look at the devices that asked for a tick every `instructions` instructions, at least one.
+    /// [`DEFAULT_TICK_GRANULE`] by default
+    pub fn set_tick_granule(&mut self, instructions: u64) {
+        self.tick_granule = instructions.max(1);
+        self.schedule_ticks();
+    }
+
+    /// Tick the devices that are due, if it is time to look at them. Between instructions
+    #[inline]
+    pub(crate) fn tick_devices_if_due(&mut self) {
+        if self.executed >= self.next_tick_at {
+            self.tick_devices();
+        }
+    }
+
+    /// Instructions the run loops can go through before [`State::tick_devices_if_due`], at least one. Only one with
+    /// a device that [`Device::counts_instructions`]
+    #[inline]
+    pub(crate) fn instructions_until_tick(&self) -> u64 {
+        match self.exact_count {
+            true => 1,
+            false => self.next_tick_at.saturating_sub(self.executed).max(1),
+        }
+    }
+
+    #[cold]
+    fn tick_devices(&mut self) {
+        let now = self.now();
+        for mapped in &mut self.devices {
+            if mapped.device.next_tick().is_some_and(|due| due <= now) {
+                mapped.device.tick(now);
+            }
+        }
+        self.dump_watched_if_due();
+        self.schedule_ticks();
+    }
+
+    /// When to look at the devices next: in a granule, never without devices. A device can ask for a tick in any
+    /// store, the run loops must look before that store is more than a granule old. Sooner if a row of the watch
+    /// dump is due before, see [`State::set_watch_dump`]
+    pub(crate) fn schedule_ticks(&mut self) {
+        let devices = match self.devices.is_empty() {
+            true => u64::MAX,
+            false => self.executed.saturating_add(self.tick_granule),
+        };
+        self.next_tick_at = self
+            .watch_dump
+            .as_ref()
+            .map_or(devices, |dump| devices.min(dump.due()));
+    }
+}
@@ -0,0 +1,132 @@
+//! The size of the terminal and its cursor for full screen programs, what `--console-device` adds, so they don't print
+//! ANSI sequences themselves. Its registers:
+//! * CROWS (xFE0C) and CCOLS (xFE0E): the rows and the columns of the terminal, read only. They follow the terminal
+//!   when it is resized
+//! * CCURSOR (xFE10): storing `row << 8 | column` moves the cursor there, from 0
+//! * CCLEAR (xFE12): any store clears the screen, leaving the cursor at the top left
+//!
+//! The stores print ANSI sequences to the output of the machine. Without a terminal ([`ConsoleDevice::headless`])
+//! they do nothing and the size is 24x80
+
+use std::ops::RangeInclusive;
+
+use super::Device;
+
+pub const CROWS: u16 = 0xFE0C;
+pub const CCOLS: u16 = 0xFE0E;
+pub const CCURSOR: u16 = 0xFE10;
+pub const CCLEAR: u16 = 0xFE12;
+/// The rows and the columns without a terminal
+pub const HEADLESS_SIZE: (u16, u16) = (24, 80);
+
+/// Where the size comes from
+#[derive(Clone, Copy, Debug)]
+enum Screen {
+    /// Nothing to draw on
+    Headless,
+    /// A screen that never changes its size
+    Fixed,
+    /// The terminal the VM runs in
+    #[cfg(all(feature = "cli", any(unix, windows)))]
+    Terminal,
+}
+
+/// See the module documentation
+#[derive(Debug)]
+pub struct ConsoleDevice {
+    screen: Screen,
+    /// Rows and columns
+    size: (u16, u16),
+    /// The sequences not taken by the machine yet
+    printed: Vec<u8>,
+}
+
+impl ConsoleDevice {
+    pub fn headless() -> ConsoleDevice {
+        ConsoleDevice {
+            screen: Screen::Headless,
+            size: HEADLESS_SIZE,
+            printed: Vec::new(),
+        }
+    }
+
+    /// A screen of `rows` and `columns` that never changes, the stores print their sequences
+    pub fn with_size(rows: u16, columns: u16) -> ConsoleDevice {
+        ConsoleDevice {
+            screen: Screen::Fixed,
+            size: (rows, columns),
+            printed: Vec::new(),
+        }
+    }
+
+    /// The terminal on stdout, 24x80 while it doesn't say its size. Only built with the `cli` feature
+    #[cfg(all(feature = "cli", any(unix, windows)))]
+    pub fn terminal() -> ConsoleDevice {
+        ConsoleDevice {
+            screen: Screen::Terminal,
+            size: HEADLESS_SIZE,
+            printed: Vec::new(),
+        }
+    }
+
+    fn size(&mut self) -> (u16, u16) {
+        #[cfg(all(feature = "cli", any(unix, windows)))]
+        if let Screen::Terminal = self.screen
+            && crate::terminal::resized()
+        {
+            self.size = crate::terminal::size().unwrap_or(HEADLESS_SIZE);
+        }
+        self.size
+    }
+}
+
+impl Device for ConsoleDevice {
+    fn range(&self) -> RangeInclusive<u16> {
+        CROWS..=CCLEAR
+    }
+
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            CROWS => self.size().0,
+            CCOLS => self.size().1,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        if let Screen::Headless = self.screen {
+            return;
+        }
+        match address {
+            CCURSOR => {
+                let (row, column) = (value >> 8, value & 0xFF);
+                self.printed
+                    .extend(format!("\x1b[{};{}H", row + 1, column + 1).bytes());
+            }
+            CCLEAR => self.printed.extend(b"\x1b[2J\x1b[H"),
+            _ => {}
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.printed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headless_it_has_a_fixed_size_and_prints_nothing() {
+        let mut console = ConsoleDevice::headless();
+        assert_eq!((console.read(CROWS), console.read(CCOLS)), (24, 80));
+        console.write(CCURSOR, 0x0102);
+        console.write(CCLEAR, 1);
+        assert!(console.take_output().is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+//! The instructions the machine retired, what `--instruction-counter` adds, so a program can measure what a piece of
+//! it costs. The count is 32 bits in two read-only registers:
+//! * ICLO (xFE16): the low word. Reading it latches the whole count
+//! * ICHI (xFE18): the high word of the count latched by the last read of ICLO
+//!
+//! So a program reads ICLO then ICHI and the two words always belong together, even if the low word wrapped in
+//! between. The instruction reading ICLO isn't counted yet: two reads of ICLO differ by the instructions from the
+//! first one to right before the second. Stores to them are ignored.
+//!
+//! The run loops count a chunk of instructions at a time, with the counter mapped they count every one, so a program
+//! runs a bit slower with it and the JIT never enters a block
+
+use std::ops::RangeInclusive;
+
+use super::Device;
+
+pub const ICLO: u16 = 0xFE16;
+pub const ICHI: u16 = 0xFE18;
+
+/// See the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct InstructionCounter {
+    retired: u64,
+    latched: u32,
+}
+
+impl Device for InstructionCounter {
+    fn range(&self) -> RangeInclusive<u16> {
+        ICLO..=ICHI
+    }
+
+    fn name(&self) -> &str {
+        "instruction counter"
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            ICLO => {
+                self.latched = self.retired as u32;
+                self.latched as u16
+            }
+            ICHI => (self.latched >> 16) as u16,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    fn retired(&mut self, instructions: u64) {
+        self.retired = instructions;
+    }
+
+    fn counts_instructions(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_high_word_is_the_one_latched_with_the_low_word() {
+        let mut counter = InstructionCounter::default();
+        counter.retired(0x1_FFFF);
+        assert_eq!(counter.read(ICLO), 0xFFFF);
+        counter.retired(0x2_0003);
+        assert_eq!(counter.read(ICHI), 1);
+        assert_eq!(counter.read(ICLO), 3);
+        assert_eq!(counter.read(ICHI), 2);
+        counter.write(ICLO, 0);
+        assert_eq!(counter.read(ICLO), 3);
+    }
+}
@@ -0,0 +1,178 @@
+//! Devices in shared libraries, what `--device <library>` loads. Only built with the `plugins` feature.
+//!
+//! The ABI is in `include/lc3_device.h`: the library exports `lc3_device_register`, which fills in an [`Lc3Device`]
+//! with the ABI version, the addresses of its registers and the callbacks [`PluginDevice`] calls as a [`Device`].
+//! What a plugin does inside its callbacks is up to it, the VM only checks what it can: the version, that the callbacks
+//! are there and, when the device is added, its addresses
+
+use std::ffi::{c_int, c_void};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use libloading::{Library, Symbol};
+
+use super::Device;
+use crate::Error;
+
+/// The version of `include/lc3_device.h` this VM implements
+pub const ABI_VERSION: u32 = 1;
+
+/// The name of the function every plugin exports
+const REGISTER_SYMBOL: &[u8] = b"lc3_device_register";
+
+/// `Lc3Device` of `include/lc3_device.h`
+#[repr(C)]
+pub struct Lc3Device {
+    pub abi_version: u32,
+    pub first_address: u16,
+    pub last_address: u16,
+    pub context: *mut c_void,
+    pub read: Option<ReadCallback>,
+    pub write: Option<WriteCallback>,
+    pub unload: Option<extern "C" fn(*mut c_void)>,
+}
+
+impl Default for Lc3Device {
+    fn default() -> Lc3Device {
+        Lc3Device {
+            abi_version: 0,
+            first_address: 0,
+            last_address: 0,
+            context: std::ptr::null_mut(),
+            read: None,
+            write: None,
+            unload: None,
+        }
+    }
+}
+
+type RegisterFunction = unsafe extern "C" fn(*mut Lc3Device) -> c_int;
+type ReadCallback = extern "C" fn(*mut c_void, u16) -> u16;
+type WriteCallback = extern "C" fn(*mut c_void, u16, u16);
+
+/// A device registered by a plugin. Dropping it calls its `unload` and closes the library
+pub struct PluginDevice {
+    device: Lc3Device,
+    read: ReadCallback,
+    write: WriteCallback,
+    /// The file name of the library without its extension, see [`Device::name`]
+    name: String,
+    /// Only closed once the device is unloaded, see the drop
+    _library: Library,
+}
+
+// The context is only used by the callbacks, and always from the thread running the VM
+unsafe impl Send for PluginDevice {}
+
+impl PluginDevice {
+    /// Open the library and register its device
+    pub fn load(path: &Path) -> Result<PluginDevice, Error> {
+        let name = path.display();
+        // Loading a library runs its initializers, trusting it is the point of `--device`
+        let library = unsafe { Library::new(path) }
+            .map_err(|error| Error::DevicePlugin(format!("{}: {}", name, error)))?;
+        let mut device = Lc3Device::default();
+        let registered = unsafe {
+            let register: Symbol<RegisterFunction> = library
+                .get(REGISTER_SYMBOL)
+                .map_err(|error| Error::DevicePlugin(format!("{}: {}", name, error)))?;
+            register(&mut device)
+        };
+        if registered != 0 {
+            return Err(Error::DevicePlugin(format!(
+                "{}: lc3_device_register failed with {}",
+                name, registered
+            )));
+        }
+        let (read, write) = check(&device)
+            .map_err(|reason| Error::DevicePlugin(format!("{}: {}", name, reason)))?;
+        Ok(PluginDevice {
+            device,
+            read,
+            write,
+            name: path.file_stem().map_or_else(
+                || name.to_string(),
+                |stem| stem.to_string_lossy().into_owned(),
+            ),
+            _library: library,
+        })
+    }
+}
+
+/// The callbacks of a registered device if it was built for this ABI
+fn check(device: &Lc3Device) -> Result<(ReadCallback, WriteCallback), String> {
+    // Nothing else in the struct means anything for another version
+    if device.abi_version != ABI_VERSION {
+        return Err(format!(
+            "it was built for device ABI version {}, this VM supports version {}",
+            device.abi_version, ABI_VERSION
+        ));
+    }
+    match (device.read, device.write) {
+        (Some(read), Some(write)) => Ok((read, write)),
+        _ => Err("it has no read or write callback".to_string()),
+    }
+}
+
+impl Device for PluginDevice {
+    fn range(&self) -> RangeInclusive<u16> {
+        self.device.first_address..=self.device.last_address
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        (self.read)(self.device.context, address)
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        (self.write)(self.device.context, address, value)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for PluginDevice {
+    fn drop(&mut self) {
+        if let Some(unload) = self.device.unload {
+            unload(self.device.context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn read(_: *mut c_void, address: u16) -> u16 {
+        address
+    }
+
+    extern "C" fn write(_: *mut c_void, _: u16, _: u16) {}
+
+    #[test]
+    fn a_device_for_another_abi_is_rejected() {
+        let mut device = Lc3Device {
+            abi_version: ABI_VERSION + 1,
+            read: Some(read),
+            write: Some(write),
+            ..Lc3Device::default()
+        };
+        assert_eq!(
+            check(&device).unwrap_err(),
+            "it was built for device ABI version 2, this VM supports version 1"
+        );
+        device.abi_version = ABI_VERSION;
+        assert!(check(&device).is_ok());
+        device.write = None;
+        assert!(check(&device).is_err());
+    }
+
+    #[test]
+    fn a_missing_library_is_an_error() {
+        assert!(matches!(
+            PluginDevice::load(Path::new("./no-such-plugin.so")),
+            Err(Error::DevicePlugin(_))
+        ));
+    }
+}
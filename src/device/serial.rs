@@ -0,0 +1,117 @@
+//! A serial port, a UART of its own for each machine of `run --instances`, two of them connected back to back with
+//! `--serial-pair 0:1`: what one sends the other receives. Its three registers:
+//! * SSR (xFE1A): bit 15 is set while a byte received waits in SRDR, bit 14 while the line can take a byte
+//! * SRDR (xFE1C): the next byte received, reading it takes it. 0 when there is none
+//! * STDR (xFE1E): a store sends its low byte, lost if the line was full
+//!
+//! Each direction is a line of [`LINE_CAPACITY`] bytes. The lines don't depend on the time, two machines taking turns
+//! in one thread exchange the same bytes at the same instructions on every run, see [`crate::scheduler`]
+
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::Device;
+
+pub const SSR: u16 = 0xFE1A;
+pub const SRDR: u16 = 0xFE1C;
+pub const STDR: u16 = 0xFE1E;
+/// Bytes sent and not received yet a line holds
+pub const LINE_CAPACITY: usize = 64;
+
+const RECEIVED: u16 = 1 << 15;
+const CAN_SEND: u16 = 1 << 14;
+
+type Line = Arc<Mutex<VecDeque<u8>>>;
+
+/// One end of a connection, see the module documentation
+pub struct SerialPort {
+    receiving: Line,
+    sending: Line,
+}
+
+impl SerialPort {
+    /// Two ports connected back to back
+    pub fn pair() -> (SerialPort, SerialPort) {
+        let (first, second) = (Line::default(), Line::default());
+        (
+            SerialPort {
+                receiving: first.clone(),
+                sending: second.clone(),
+            },
+            SerialPort {
+                receiving: second,
+                sending: first,
+            },
+        )
+    }
+}
+
+fn lock(line: &Line) -> std::sync::MutexGuard<'_, VecDeque<u8>> {
+    line.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Device for SerialPort {
+    fn range(&self) -> RangeInclusive<u16> {
+        SSR..=STDR
+    }
+
+    fn name(&self) -> &str {
+        "serial port"
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            SSR => {
+                let received = match lock(&self.receiving).is_empty() {
+                    true => 0,
+                    false => RECEIVED,
+                };
+                let can_send = match lock(&self.sending).len() < LINE_CAPACITY {
+                    true => CAN_SEND,
+                    false => 0,
+                };
+                received | can_send
+            }
+            SRDR => lock(&self.receiving).pop_front().unwrap_or(0) as u16,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        let mut sending = lock(&self.sending);
+        if address == STDR && sending.len() < LINE_CAPACITY {
+            sending.push_back(value as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn what_one_end_sends_the_other_receives() {
+        let (mut first, mut second) = SerialPort::pair();
+        assert_eq!(second.read(SSR), CAN_SEND);
+        first.write(STDR, 0x1241);
+        first.write(STDR, 0x42);
+        assert_eq!(second.read(SSR), RECEIVED | CAN_SEND);
+        assert_eq!(second.read(SRDR), 0x41);
+        assert_eq!(second.read(SRDR), 0x42);
+        assert_eq!(second.read(SRDR), 0);
+        assert_eq!(first.read(SSR), CAN_SEND);
+    }
+
+    #[test]
+    fn a_full_line_loses_what_is_sent() {
+        let (mut first, mut second) = SerialPort::pair();
+        for byte in 0..=LINE_CAPACITY as u16 {
+            first.write(STDR, byte);
+        }
+        assert_eq!(first.read(SSR), 0);
+        let received: Vec<u16> = (0..=LINE_CAPACITY).map(|_| second.read(SRDR)).collect();
+        assert_eq!(received[LINE_CAPACITY - 1], LINE_CAPACITY as u16 - 1);
+        assert_eq!(received[LINE_CAPACITY], 0);
+    }
+}
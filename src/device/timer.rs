@@ -0,0 +1,128 @@
+//! A periodic timer, what `--timer` adds. Its two registers:
+//! * TSR (xFE08): bit 15 is set once the interval passed since it was last read, reading clears it. Bit 14 enables
+//!   its interrupt ([`TIMER_VECTOR`], [`TIMER_PRIORITY`]), the only bit a store changes
+//! * TIR (xFE0A): the interval in milliseconds, 0 stops the timer. A store starts counting again from then
+//!
+//! It runs on the time of the machine (see [`crate::clock`]), with a virtual clock it fires at the same instruction
+//! on every run. With its interrupt enabled the run loops tick it when it is due, at most a granule of instructions
+//! late (see [`crate::device`])
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use super::Device;
+use crate::irq::InterruptRequest;
+
+pub const TSR: u16 = 0xFE08;
+pub const TIR: u16 = 0xFE0A;
+pub const TIMER_VECTOR: u8 = 0x81;
+/// Above the keyboard, a tick isn't late behind a key
+pub const TIMER_PRIORITY: u8 = 5;
+
+const FIRED: u16 = 1 << 15;
+const INTERRUPT_ENABLE: u16 = 1 << 14;
+
+/// See the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct Timer {
+    interval: u16,
+    /// When it fires next, `None` while stopped
+    deadline: Option<Duration>,
+    now: Duration,
+    fired: bool,
+    interrupt_enabled: bool,
+}
+
+impl Device for Timer {
+    fn range(&self) -> RangeInclusive<u16> {
+        TSR..=TIR
+    }
+
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        match address {
+            TSR => {
+                let mut status = 0;
+                if std::mem::take(&mut self.fired) {
+                    status |= FIRED;
+                }
+                if self.interrupt_enabled {
+                    status |= INTERRUPT_ENABLE;
+                }
+                status
+            }
+            TIR => self.interval,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        match address {
+            TSR => self.interrupt_enabled = value & INTERRUPT_ENABLE != 0,
+            TIR => {
+                self.interval = value;
+                self.deadline = (value != 0).then(|| self.now + self.period());
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, now: Duration) {
+        self.now = now;
+        if let Some(deadline) = self.deadline
+            && now >= deadline
+        {
+            self.fired = true;
+            // Ticks missed while the machine wasn't looking are a single one
+            let periods = ((now - deadline).as_nanos() / self.period().as_nanos()) as u32 + 1;
+            self.deadline = Some(deadline + self.period() * periods);
+        }
+    }
+
+    /// Only to interrupt, a program polling TSR ticks it reading it
+    fn next_tick(&self) -> Option<Duration> {
+        self.deadline.filter(|_| self.interrupt_enabled)
+    }
+
+    fn interrupt(&mut self) -> Option<InterruptRequest> {
+        (self.fired && self.interrupt_enabled).then_some(InterruptRequest {
+            vector: TIMER_VECTOR,
+            priority: TIMER_PRIORITY,
+        })
+    }
+}
+
+impl Timer {
+    fn period(&self) -> Duration {
+        Duration::from_millis(self.interval as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_fires_every_interval_until_stopped() {
+        let mut timer = Timer::default();
+        timer.write(TSR, 0xFFFF);
+        timer.write(TIR, 2);
+        timer.tick(Duration::from_millis(1));
+        assert_eq!(timer.interrupt(), None);
+        timer.tick(Duration::from_millis(2));
+        assert_eq!(timer.interrupt().unwrap().vector, TIMER_VECTOR);
+        assert_eq!(timer.read(TSR), FIRED | INTERRUPT_ENABLE);
+        assert_eq!(timer.read(TSR), INTERRUPT_ENABLE);
+        timer.tick(Duration::from_millis(9));
+        assert!(timer.interrupt().is_some());
+        timer.read(TSR);
+        timer.tick(Duration::from_micros(9_900)); // Those missed since 2 ms only fired once
+        assert_eq!(timer.interrupt(), None);
+        timer.write(TIR, 0);
+        timer.tick(Duration::from_secs(1));
+        assert_eq!(timer.interrupt(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! What the VM itself says on stderr: warnings, the trace, the DBG and ASSERT lines, the reports at exit and the
+//! errors. Everything goes through [`diag!`](crate::diag!) so that, on a terminal, those lines are dim yellow and can't
+//! be mistaken for what the program printed, which is left alone on stdout.
+//! The color is off when stderr isn't a terminal, when `NO_COLOR` is set to anything or after [`disable_color`]
+
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Dim yellow
+const COLOR: &[u8] = b"\x1b[2;33m";
+const RESET: &[u8] = b"\x1b[0m";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Print the VM messages without color from now on, what `--no-color` does
+pub fn disable_color() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether the VM messages on stderr are colored, see the module documentation
+pub fn color_enabled() -> bool {
+    static DETECTED: OnceLock<bool> = OnceLock::new();
+    !color_disabled()
+        && *DETECTED.get_or_init(|| {
+            io::stderr().is_terminal() && env_allows_color(std::env::var_os("NO_COLOR"))
+        })
+}
+
+/// Whether [`disable_color`] was called
+pub(crate) fn color_disabled() -> bool {
+    DISABLED.load(Ordering::Relaxed)
+}
+
+/// `NO_COLOR` turns the color off with any value but an empty one, see https://no-color.org
+pub(crate) fn env_allows_color(no_color: Option<std::ffi::OsString>) -> bool {
+    no_color.is_none_or(|value| value.is_empty())
+}
+
+/// Write `message` to stderr ending the line, each of its lines colored when [`color_enabled`]. An empty message
+/// writes nothing. Use [`diag!`](crate::diag!) instead
+pub fn emit(message: fmt::Arguments) {
+    let message = message.to_string();
+    if message.is_empty() {
+        return;
+    }
+    let mut stderr = stderr();
+    let _ = stderr.write_all(message.as_bytes());
+    if !message.ends_with('\n') {
+        let _ = stderr.write_all(b"\n");
+    }
+    let _ = stderr.flush();
+}
+
+/// A writer to stderr colored when [`color_enabled`], for the trace
+pub fn stderr() -> DiagnosticWriter<io::StderrLock<'static>> {
+    DiagnosticWriter::new(io::stderr().lock(), color_enabled())
+}
+
+/// Print a line of the VM to stderr, like `eprintln!` but colored on a terminal, see [`crate::diag`]
+#[macro_export]
+macro_rules! diag {
+    ($($arg:tt)*) => {
+        $crate::diag::emit(format_args!($($arg)*))
+    };
+}
+
+/// Colors every line written to it, for the VM messages written a bit at a time like the trace. A line is only
+/// written once it ends, or when flushed
+pub struct DiagnosticWriter<W: Write> {
+    inner: W,
+    colored: bool,
+    line: Vec<u8>,
+}
+
+impl<W: Write> DiagnosticWriter<W> {
+    pub fn new(inner: W, colored: bool) -> DiagnosticWriter<W> {
+        DiagnosticWriter {
+            inner,
+            colored,
+            line: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let ends_line = self.line.last() == Some(&b'\n');
+        let text = match ends_line {
+            true => &self.line[..self.line.len() - 1],
+            false => &self.line[..],
+        };
+        if self.colored {
+            self.inner.write_all(COLOR)?;
+            self.inner.write_all(text)?;
+            self.inner.write_all(RESET)?;
+        } else {
+            self.inner.write_all(text)?;
+        }
+        if ends_line {
+            self.inner.write_all(b"\n")?;
+        }
+        self.line.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for DiagnosticWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(line);
+            if line.ends_with(b"\n") {
+                self.write_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_line()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for DiagnosticWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(colored: bool, parts: &[&str]) -> String {
+        let mut output = Vec::new();
+        let mut writer = DiagnosticWriter::new(&mut output, colored);
+        for part in parts {
+            writer.write_all(part.as_bytes()).unwrap();
+        }
+        drop(writer);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn every_line_is_colored_on_its_own() {
+        assert_eq!(
+            written(true, &["x3000 ", "ADD\nx3001 HALT\nunfin", "ished"]),
+            "\x1b[2;33mx3000 ADD\x1b[0m\n\x1b[2;33mx3001 HALT\x1b[0m\n\x1b[2;33munfinished\x1b[0m"
+        );
+        assert_eq!(written(false, &["x3000 ", "ADD\n"]), "x3000 ADD\n");
+    }
+
+    #[test]
+    fn no_color_with_any_value_turns_it_off() {
+        assert!(env_allows_color(None));
+        assert!(env_allows_color(Some("".into())));
+        assert!(!env_allows_color(Some("1".into())));
+    }
+}
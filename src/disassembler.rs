@@ -0,0 +1,408 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::assembler::TRAP_ALIASES;
+use crate::operations::sign_extend;
+use crate::trace::InstructionClass;
+
+pub mod listing;
+
+/// A line of a disassembled image, see [`disassemble_lines`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledLine {
+    pub address: u16,
+    /// The words the line assembles to, several for a `.STRINGZ`
+    pub words: Vec<u16>,
+    /// `L_xxxx` when something refers to the address
+    pub label: Option<String>,
+    /// The source of the line, like `LEA R0, L_3003` or `.FILL x8000`
+    pub text: String,
+    /// The class of an instruction, `None` for data
+    pub class: Option<InstructionClass>,
+}
+
+/// Disassemble a single word into assembler syntax, offsets are rendered as decimal numbers relative to the incremented PC.
+/// The assembler aliases (RET, NOP, HALT, ...) are used whenever they apply.
+/// Words that the assembler wouldn't produce back (reserved op code, unused bits set, BR without flags) are rendered as `.FILL`
+pub fn disassemble(word: u16) -> String {
+    render(word, &|offset| format!("#{}", offset))
+}
+
+/// Disassemble a whole image into source that assembles back to the same words: a .ORIG header, one line per word and a .END.
+/// `code` tells which words are instructions, the rest is data rendered as `.FILL`, or as `.STRINGZ` for printable runs ending in zero.
+/// Every address inside the image that is the target of a PC relative instruction (BR, JSR, LD, LDI, LEA, ST, STI)
+/// gets a `L_xxxx` label and the instructions refer to it, targets outside the image are kept as offsets.
+/// RTI is rendered as `.FILL` since programs can't use it
+pub fn disassemble_program(origin: u16, words: &[u16], code: &[bool]) -> String {
+    let mut source = format!("{:8}.ORIG x{:04X}\n", "", origin);
+    for line in disassemble_lines(origin, words, code) {
+        let _ = writeln!(
+            source,
+            "{:8}{}",
+            line.label.as_deref().unwrap_or(""),
+            line.text
+        );
+    }
+    source.push_str(&format!("{:8}.END\n", ""));
+    source
+}
+
+/// The lines of [`disassemble_program`] without the `.ORIG` and `.END`, for the renderers needing the address and the
+/// words of each, like [`listing::render_listing`]
+pub fn disassemble_lines(origin: u16, words: &[u16], code: &[bool]) -> Vec<DisassembledLine> {
+    let end = origin as i32 + words.len() as i32;
+    let target = |address: i32, word: u16| {
+        let target = address + 1 + pc_offset(word)? as i32;
+        (origin as i32..end).contains(&target).then_some(target)
+    };
+    let labels: BTreeSet<i32> = words
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| code[*index])
+        .filter_map(|(index, word)| target(origin as i32 + index as i32, *word))
+        .collect();
+    let mut lines = Vec::new();
+    let mut index = 0;
+    while index < words.len() {
+        let address = origin as i32 + index as i32;
+        let name = labels.contains(&address).then(|| label(address));
+        let word = words[index];
+        let start = index;
+        let instruction = code[index] && word != 0x8000;
+        let text = if instruction {
+            render(word, &|offset| {
+                let target = address + 1 + offset as i32;
+                match labels.contains(&target) {
+                    true => label(target),
+                    false => format!("#{}", offset),
+                }
+            })
+        } else if let Some(length) = string_length(&words[index..], &code[index..], |offset| {
+            labels.contains(&(address + offset as i32))
+        }) {
+            let text = stringz(&words[index..index + length - 1]);
+            index += length - 1;
+            text
+        } else {
+            fill(word)
+        };
+        let data = text.starts_with('.');
+        index += 1;
+        lines.push(DisassembledLine {
+            address: address as u16,
+            words: words[start..index].to_vec(),
+            label: name,
+            text,
+            class: (instruction && !data)
+                .then(|| InstructionClass::of(word))
+                .flatten(),
+        });
+    }
+    lines
+}
+
+/// Mark the words reachable from the roots following fall-through, branch, JSR and trap return edges.
+/// The targets of JMP, JSRR and RET can't be known without running the program so they are not followed
+pub fn reachable_code(origin: u16, words: &[u16], roots: &[u16]) -> Vec<bool> {
+    let mut code = vec![false; words.len()];
+    let index_of = |address: i32| {
+        let index = address - origin as i32;
+        (0..words.len() as i32)
+            .contains(&index)
+            .then_some(index as usize)
+    };
+    let mut pending: Vec<usize> = roots
+        .iter()
+        .filter_map(|root| index_of(*root as i32))
+        .collect();
+    while let Some(index) = pending.pop() {
+        if code[index] {
+            continue;
+        }
+        code[index] = true;
+        let word = words[index];
+        let next = origin as i32 + index as i32 + 1;
+        let target = pc_offset(word).map(|offset| next + offset as i32);
+        let edges = match word >> 12 {
+            // BRnzp always jumps, the other branches can also fall through. A zero word is a NOP
+            0x0 if word == 0 => vec![Some(next)],
+            0x0 if (word >> 9) & 0x7 == 0x7 => vec![target],
+            0x0 if (word >> 9) & 0x7 != 0 => vec![target, Some(next)],
+            0x4 => vec![target, Some(next)],
+            0x0 | 0x8 | 0xC | 0xD => vec![],
+            0xF if word & 0xFF == 0x25 => vec![],
+            _ => vec![Some(next)],
+        };
+        pending.extend(edges.into_iter().flatten().filter_map(index_of));
+    }
+    code
+}
+
+/// Length (with the terminating zero) of the printable run of data at the start of `words`,
+/// it can't go over a labeled address since the label would be lost inside the string
+fn string_length(
+    words: &[u16],
+    code: &[bool],
+    is_labeled: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    let mut length = 0;
+    while length < words.len() && !code[length] && (length == 0 || !is_labeled(length)) {
+        match words[length] {
+            0 if length > 0 => return Some(length + 1),
+            0x20..=0x7E | 0x0A | 0x09 => length += 1,
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn stringz(characters: &[u16]) -> String {
+    let text: String = characters
+        .iter()
+        .map(|character| match *character as u8 {
+            b'\n' => "\\n".to_string(),
+            b'\t' => "\\t".to_string(),
+            b'"' => "\\\"".to_string(),
+            b'\\' => "\\\\".to_string(),
+            printable => (printable as char).to_string(),
+        })
+        .collect();
+    format!(".STRINGZ \"{}\"", text)
+}
+
+fn label(address: i32) -> String {
+    format!("L_{:04X}", address)
+}
+
+/// Offset of the PC relative instructions, the ones that can refer to a label
+fn pc_offset(word: u16) -> Option<i16> {
+    match word >> 12 {
+        0x0 if (word >> 9) & 0x7 != 0 => Some(offset(word, 9)),
+        0x2 | 0x3 | 0xA | 0xB | 0xE => Some(offset(word, 9)),
+        0x4 if (word >> 11) & 1 == 1 => Some(offset(word, 11)),
+        _ => None,
+    }
+}
+
+/// Disassemble a word, `target` renders the PC relative offsets
+fn render(word: u16, target: &dyn Fn(i16) -> String) -> String {
+    let register_1 = (word >> 9) & 0x7;
+    let register_2 = (word >> 6) & 0x7;
+    let offset_9 = target(offset(word, 9));
+    match word >> 12 {
+        0x0 if word == 0 => "NOP".to_string(),
+        0x0 if register_1 == 0 => fill(word),
+        0x0 => {
+            let flags: String = [(0x4, 'n'), (0x2, 'z'), (0x1, 'p')]
+                .iter()
+                .filter(|(bit, _)| register_1 & bit != 0)
+                .map(|(_, flag)| flag)
+                .collect();
+            format!("BR{} {}", flags, offset_9)
+        }
+        op_code @ (0x1 | 0x5) => {
+            let mnemonic = if op_code == 0x1 { "ADD" } else { "AND" };
+            if (word >> 5) & 1 == 1 {
+                format!(
+                    "{} R{}, R{}, #{}",
+                    mnemonic,
+                    register_1,
+                    register_2,
+                    offset(word, 5)
+                )
+            } else if (word >> 3) & 0x3 != 0 {
+                fill(word)
+            } else {
+                format!(
+                    "{} R{}, R{}, R{}",
+                    mnemonic,
+                    register_1,
+                    register_2,
+                    word & 0x7
+                )
+            }
+        }
+        op_code @ (0x2 | 0x3 | 0xA | 0xB | 0xE) => {
+            let mnemonic = match op_code {
+                0x2 => "LD",
+                0x3 => "ST",
+                0xA => "LDI",
+                0xB => "STI",
+                _ => "LEA",
+            };
+            format!("{} R{}, {}", mnemonic, register_1, offset_9)
+        }
+        0x4 if (word >> 11) & 1 == 1 => format!("JSR {}", target(offset(word, 11))),
+        0x4 if word & 0x0E3F != 0 => fill(word),
+        0x4 => format!("JSRR R{}", register_2),
+        op_code @ (0x6 | 0x7) => {
+            let mnemonic = if op_code == 0x6 { "LDR" } else { "STR" };
+            format!(
+                "{} R{}, R{}, #{}",
+                mnemonic,
+                register_1,
+                register_2,
+                offset(word, 6)
+            )
+        }
+        0x8 if word == 0x8000 => "RTI".to_string(),
+        0x9 if word & 0x3F == 0x3F => format!("NOT R{}, R{}", register_1, register_2),
+        0xC if word & 0x0E3F != 0 => fill(word),
+        0xC if register_2 == 7 => "RET".to_string(),
+        0xC => format!("JMP R{}", register_2),
+        0xF if word & 0x0F00 != 0 => fill(word),
+        0xF => match TRAP_ALIASES
+            .iter()
+            .find(|(_, vector)| *vector == word & 0xFF)
+        {
+            Some((alias, _)) => alias.to_string(),
+            None => format!("TRAP x{:02X}", word & 0xFF),
+        },
+        _ => fill(word),
+    }
+}
+
+fn fill(word: u16) -> String {
+    format!(".FILL x{:04X}", word)
+}
+
+/// Sign extend the lowest bits of the word
+fn offset(word: u16, bits: u16) -> i16 {
+    sign_extend(word & ((1 << bits) - 1), bits) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::file_management::read_image;
+
+    #[test]
+    fn aliases() {
+        assert_eq!(disassemble(0xC1C0), "RET");
+        assert_eq!(disassemble(0x0000), "NOP");
+        assert_eq!(disassemble(0xF020), "GETC");
+        assert_eq!(disassemble(0xF021), "OUT");
+        assert_eq!(disassemble(0xF022), "PUTS");
+        assert_eq!(disassemble(0xF023), "IN");
+        assert_eq!(disassemble(0xF024), "PUTSP");
+        assert_eq!(disassemble(0xF025), "HALT");
+        assert_eq!(disassemble(0xF026), "TRAP x26");
+        assert_eq!(disassemble(0xF027), "EXIT");
+        assert_eq!(disassemble(0xF028), "DBG");
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(disassemble(0x1E3F), "ADD R7, R0, #-1");
+        assert_eq!(disassemble(0x5F46), "AND R7, R5, R6");
+        assert_eq!(disassemble(0x0FFB), "BRnzp #-5");
+        assert_eq!(disassemble(0x0405), "BRz #5");
+        assert_eq!(disassemble(0xAA27), "LDI R5, #39");
+        assert_eq!(disassemble(0x4FFB), "JSR #-5");
+        assert_eq!(disassemble(0x4140), "JSRR R5");
+        assert_eq!(disassemble(0x7B3B), "STR R5, R4, #-5");
+        assert_eq!(disassemble(0x977F), "NOT R3, R5");
+        assert_eq!(disassemble(0xC140), "JMP R5");
+        assert_eq!(disassemble(0x8000), "RTI");
+        assert_eq!(disassemble(0xD000), ".FILL xD000");
+        assert_eq!(disassemble(0x1018), ".FILL x1018");
+        assert_eq!(disassemble(0x0005), ".FILL x0005");
+    }
+
+    #[test]
+    fn every_word_round_trips_through_the_assembler() {
+        let mut source = String::from(".ORIG x0000\n");
+        for word in 0..=u16::MAX {
+            source.push_str(&disassemble(word));
+            source.push('\n');
+        }
+        source.push_str(".END\n");
+        let program = assemble(&source).unwrap();
+        assert_eq!(program.words, (0..=u16::MAX).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn program_with_labels() {
+        let words = [0xE002, 0xF022, 0x0FFD, 0x8000, 0x21FA];
+        assert_eq!(
+            disassemble_program(0x3000, &words, &[true; 5]),
+            "        .ORIG x3000
+L_3000  LEA R0, L_3003
+        PUTS
+        BRnzp L_3000
+L_3003  .FILL x8000
+        LD R0, #-6
+        .END
+"
+        );
+    }
+
+    #[test]
+    fn fixture_images_round_trip_through_the_assembler() {
+        for path in [
+            "tests/fixtures/directives.obj",
+            "images/2048.obj",
+            "images/rogue.obj",
+        ] {
+            let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), path);
+            let (origin, words) = read_image(&path).unwrap();
+            for code in [
+                vec![true; words.len()],
+                reachable_code(origin, &words, &[origin]),
+            ] {
+                let program = assemble(&disassemble_program(origin, &words, &code)).unwrap();
+                assert_eq!(program.origin, origin, "{}", path);
+                assert_eq!(program.words, words, "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn strings_are_not_disassembled_as_code() {
+        let source = ".ORIG x3000
+            LEA R0, MSG
+            JSR PRINT
+            HALT
+PRINT       PUTS
+            BRz DONE
+            ADD R0, R0, #0
+DONE        RET
+MSG         .STRINGZ \"Hi \\\"you\\\"\\n\"
+            .FILL x0041
+            .END";
+        let program = assemble(source).unwrap();
+        let code = reachable_code(program.origin, &program.words, &[program.origin]);
+        assert_eq!(
+            disassemble_program(program.origin, &program.words, &code),
+            "        .ORIG x3000
+        LEA R0, L_3007
+        JSR L_3003
+        HALT
+L_3003  PUTS
+        BRz L_3006
+        ADD R0, R0, #0
+L_3006  RET
+L_3007  .STRINGZ \"Hi \\\"you\\\"\\n\"
+        .FILL x0041
+        .END
+"
+        );
+        let all_code = disassemble_program(program.origin, &program.words, &[true; 18]);
+        assert!(!all_code.contains(".STRINGZ"));
+    }
+
+    #[test]
+    fn extra_roots_mark_indirect_targets_as_code() {
+        // JMP R1 to the instruction at x3002, only reachable through the register
+        let words = [0xE201, 0xC040, 0xF025];
+        assert_eq!(
+            reachable_code(0x3000, &words, &[0x3000]),
+            [true, true, false]
+        );
+        assert_eq!(
+            reachable_code(0x3000, &words, &[0x3000, 0x3002]),
+            [true, true, true]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! The disassembly of `disasm` on a terminal, laid out like `objdump`: the address, the word in hexadecimal, the label
+//! and the instruction, its mnemonic colored by class. Data gets a row per word with the character it holds, if any:
+//! ```text
+//! x3000  E002  L_3000  LEA R0, L_3003
+//! x3001  F022          PUTS
+//! x3002  0FFD          BRnzp L_3000
+//! x3003  0048  L_3003  .FILL x0048             |H|
+//! ```
+//! It is only for reading, the plain source of [`super::disassemble_program`] is what assembles back to the image.
+//! `--color` picks between them (see [`ColorChoice`]), a listing piped to another program is that plain source
+
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal};
+
+use super::DisassembledLine;
+use crate::diag;
+use crate::trace::InstructionClass;
+
+const BRANCH: &str = "\x1b[33m";
+const MEMORY: &str = "\x1b[36m";
+const TRAP: &str = "\x1b[35m";
+const RESET: &str = "\x1b[0m";
+
+/// Width of the instruction column, the character of a data word goes right after it
+const TEXT_WIDTH: usize = 24;
+
+/// What `--color` of `disasm` asks for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// The colored listing on a terminal, unless `NO_COLOR` or `--no-color` say otherwise, the plain source elsewhere
+    #[default]
+    Auto,
+    /// The colored listing wherever it goes
+    Always,
+    /// The plain source
+    Never,
+}
+
+impl ColorChoice {
+    /// `auto`, `always` or `never`
+    pub fn from_name(name: &str) -> Option<ColorChoice> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    /// Whether the colored listing goes to stdout
+    pub fn colored(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                !diag::color_disabled()
+                    && io::stdout().is_terminal()
+                    && diag::env_allows_color(std::env::var_os("NO_COLOR"))
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Lay out `lines` as in the module documentation, the mnemonics colored if `colored`
+pub fn render_listing(lines: &[DisassembledLine], colored: bool) -> String {
+    let mut listing = String::new();
+    for line in lines {
+        // A `.STRINGZ` gets a row per character like `.FILL`s, the label on the first one
+        for (offset, &word) in line.words.iter().enumerate() {
+            let label = match offset {
+                0 => line.label.as_deref().unwrap_or(""),
+                _ => "",
+            };
+            let _ = write!(
+                listing,
+                "x{:04X}  {:04X}  {:8}",
+                line.address.wrapping_add(offset as u16),
+                word,
+                label
+            );
+            match (line.class, line.words.len()) {
+                (Some(class), _) => push_instruction(&mut listing, &line.text, class, colored),
+                (None, 1) => push_data(&mut listing, &line.text, word),
+                (None, _) => push_data(&mut listing, &super::fill(word), word),
+            }
+            listing.push('\n');
+        }
+    }
+    listing
+}
+
+fn push_instruction(listing: &mut String, text: &str, class: InstructionClass, colored: bool) {
+    let color = match class {
+        InstructionClass::Branches => Some(BRANCH),
+        InstructionClass::Loads | InstructionClass::Stores => Some(MEMORY),
+        InstructionClass::Traps => Some(TRAP),
+        InstructionClass::Alu => None,
+    };
+    match color.filter(|_| colored) {
+        Some(color) => {
+            let (mnemonic, operands) = text.split_once(' ').unwrap_or((text, ""));
+            let space = if operands.is_empty() { "" } else { " " };
+            let _ = write!(
+                listing,
+                "{}{}{}{}{}",
+                color, mnemonic, RESET, space, operands
+            );
+        }
+        None => listing.push_str(text),
+    }
+}
+
+/// The `.FILL` and the character of the word between bars, a dot for anything but printable ASCII
+fn push_data(listing: &mut String, text: &str, word: u16) {
+    let character = match word {
+        0x20..=0x7E => word as u8 as char,
+        _ => '.',
+    };
+    let _ = write!(
+        listing,
+        "{:width$}|{}|",
+        text,
+        character,
+        width = TEXT_WIDTH
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::disassembler::{disassemble_lines, reachable_code};
+
+    fn lines(source: &str) -> Vec<DisassembledLine> {
+        let program = assemble(source).unwrap();
+        let code = reachable_code(program.origin, &program.words, &[program.origin]);
+        disassemble_lines(program.origin, &program.words, &code)
+    }
+
+    #[test]
+    fn rows_have_the_address_the_word_and_the_character_of_data() {
+        let lines = lines(
+            ".ORIG x3000
+            LEA R0, MSG
+            PUTS
+            ADD R0, R0, #1
+            HALT
+MSG         .STRINGZ \"Hi\"
+            .FILL x0100
+            .END",
+        );
+        assert_eq!(
+            render_listing(&lines, false),
+            "x3000  E003          LEA R0, L_3004
+x3001  F022          PUTS
+x3002  1021          ADD R0, R0, #1
+x3003  F025          HALT
+x3004  0048  L_3004  .FILL x0048             |H|
+x3005  0069          .FILL x0069             |i|
+x3006  0000          .FILL x0000             |.|
+x3007  0100          .FILL x0100             |.|
+"
+        );
+    }
+
+    #[test]
+    fn mnemonics_are_colored_by_class() {
+        let lines = lines(
+            ".ORIG x3000
+LOOP        LDR R1, R2, #0
+            ADD R1, R1, #1
+            BRp LOOP
+            HALT
+            .END",
+        );
+        assert_eq!(
+            render_listing(&lines, true),
+            "x3000  6280  L_3000  \x1b[36mLDR\x1b[0m R1, R2, #0
+x3001  1261          ADD R1, R1, #1
+x3002  03FD          \x1b[33mBRp\x1b[0m L_3000
+x3003  F025          \x1b[35mHALT\x1b[0m
+"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! The errors of the VM. [`Error`] groups them by where they happen: loading an image ([`LoadError`]), running it
+//! ([`RuntimeError`]) and the terminal around it ([`TerminalError`]), the rest (arguments, assembly, devices, plain I/O)
+//! are variants of their own. [`Error::category`] tells them apart without matching every variant, it is what scripts
+//! and exit codes should look at rather than the messages.
+//!
+//! Every enum is `#[non_exhaustive]`, a match needs a `_` arm so new errors aren't breaking changes
+
+use std::io;
+use std::time::Duration;
+
+use crate::Traps;
+use crate::address::ParseError;
+use crate::assembler::AssemblyError;
+use crate::fault::Fault;
+use crate::hang::Hang;
+use crate::stack::StackViolation;
+use crate::warning::Warning;
+
+/// Any error of the VM, see the module documentation
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error(transparent)]
+    Load(#[from] LoadError),
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+    #[error(transparent)]
+    Terminal(#[from] TerminalError),
+    #[error("Not enough arguments")]
+    FewArguments,
+    #[error("Bad argument: `{0}`")]
+    BadArgument(String),
+    /// An address of an option, see [`crate::address`]
+    #[error("Bad address: {0}")]
+    BadAddress(#[from] ParseError),
+    #[error(transparent)]
+    Assembly(#[from] AssemblyError),
+    #[error("{0} file(s) would be reformatted")]
+    NotFormatted(usize),
+    #[error("The traces diverge at step {0}")]
+    TracesDiverge(usize),
+    /// See [`crate::compare`]
+    #[error("The machines diverge at step {0}")]
+    MachinesDiverge(u64),
+    #[error("The states differ in {0} register(s) and {1} word(s)")]
+    StatesDiffer(usize, usize),
+    #[error("{0} assertion(s) failed")]
+    AssertionsFailed(usize),
+    #[error("{0} job(s) failed")]
+    JobsFailed(usize),
+    /// See [`crate::conformance`]
+    #[error("{0} self-test case(s) failed")]
+    CasesFailed(usize),
+    /// See [`crate::scheduler`]
+    #[error("{0} instance(s) failed")]
+    InstancesFailed(usize),
+    /// The jobs file of `batch` couldn't be read, see [`crate::batch`]
+    #[error("Bad jobs file: {0}")]
+    BadJobs(String),
+    #[error("The device registers x{0:04X} to x{1:04X} aren't free on the device page")]
+    DeviceRange(u16, u16),
+    #[error("Bad device plugin: {0}")]
+    DevicePlugin(String),
+    /// Reading or writing anything but an image, like the output of the program or a trace
+    #[error("Bad file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The image couldn't be loaded
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum LoadError {
+    #[error("Bad file: {0}")]
+    File(#[source] io::Error),
+    #[error("Bad image size")]
+    BadImageSize,
+    #[error("Unsupported lc3tools object version: expected {expected}, found {found}")]
+    ObjectVersion { expected: String, found: String },
+    #[error("The image has {0} sections, only a single one can be used here")]
+    SeveralSections(usize),
+    #[error("Bad snapshot: {0}")]
+    BadSnapshot(String),
+    #[error("Bad recording: {0}")]
+    BadRecording(String),
+    #[error("Bad trace: {0}")]
+    BadTrace(String),
+    /// The block of `--arg` and `--env` has no room, see [`crate::arguments`]
+    #[error("Bad program arguments: {0}")]
+    BadArguments(String),
+    /// An image was loaded where `--builtin-os` goes, see [`crate::os`]
+    #[error("The program overlaps the builtin OS at x{0:04X}")]
+    OverlapsSystem(u16),
+    /// A segment installed over another or an image, see [`crate::segment`]
+    #[error("The words at x{start:04X}-x{end:04X} overlap those already loaded at x{address:04X}")]
+    Overlap { start: u16, end: u16, address: u16 },
+}
+
+/// The program couldn't go on, or was stopped
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum RuntimeError {
+    #[error("Bad register: `{0} does not exist!`")]
+    BadRegisterReference(u16),
+    #[error("Bad operation code x{0:X}")]
+    BadOpCode(u16),
+    #[error("Bad trap code x{0:02X}")]
+    BadTrapCode(u16),
+    #[error("Bad trap `{0:?}`")]
+    Trap(Traps),
+    #[error("The program didn't halt after {0} instructions")]
+    BudgetExhausted(u64),
+    #[error("Interrupted")]
+    Interrupted,
+    #[error("The program didn't halt within {0:?}")]
+    TimedOut(Duration),
+    #[error("Executing x{0:04X}, nothing was loaded or written there")]
+    UninitializedExec(u16),
+    #[error("{0}")]
+    Stack(StackViolation),
+    #[error(
+        "Self-modifying code: the store at x{writer:04X} modified the instruction at x{target:04X}"
+    )]
+    SelfModify { writer: u16, target: u16 },
+    /// A store to a loaded word with [`crate::State::set_code_protection`]
+    #[error("The store at x{pc:04X} writes x{address:04X}, which is code loaded from an image")]
+    WriteToCode { pc: u16, address: u16 },
+    #[error("{0}")]
+    Hang(Hang),
+    /// An interrupt or an exception was taken with the supervisor stack full, see [`crate::irq`]
+    #[error("The supervisor stack overflowed at x{pc:04X}, pushing to x{address:04X}")]
+    SupervisorStackOverflow { pc: u16, address: u16 },
+    /// An ASSERT trap failed with [`crate::State::set_halt_on_assert_fail`]
+    #[error("ASSERT FAIL: {0}")]
+    AssertFailed(String),
+    /// An input trap waited for a key longer than [`crate::State::set_input_timeout`]
+    #[error("{trap:?} at x{pc:04X} waited for a key longer than the input timeout")]
+    InputTimeout { trap: Traps, pc: u16 },
+    /// The program didn't consume the recorded keys like the recorded run did
+    #[error("The replay diverges at instruction {instruction}, x{pc:04X}: {reason}")]
+    ReplayDiverges {
+        instruction: u64,
+        pc: u16,
+        reason: String,
+    },
+    /// A warning of a code denied with [`crate::State::set_warning_level`]
+    #[error("Denied warning {}: {}", .0.code(), .0)]
+    WarningDenied(Warning),
+    /// An instruction failed, the error comes with where it happened
+    #[error("{0}")]
+    Fault(Box<Fault>),
+}
+
+/// The terminal couldn't be switched to raw mode or back
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum TerminalError {
+    #[error("Couldn't disable input buffering")]
+    DisableInputBuffering(#[source] io::Error),
+    #[error("Couldn't restore input buffering")]
+    RestoreInputBuffering(#[source] io::Error),
+}
+
+/// The kind of an [`Error`], coarse enough to be matched exhaustively by the caller
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Category {
+    /// The command line was wrong
+    #[serde(rename = "usage")]
+    Usage,
+    #[serde(rename = "load")]
+    Load,
+    #[serde(rename = "assembly")]
+    Assembly,
+    #[serde(rename = "device")]
+    Device,
+    #[serde(rename = "runtime")]
+    Runtime,
+    #[serde(rename = "terminal")]
+    Terminal,
+    /// A check like `fmt --check`, `diff-trace`, `diff`, an assertion or a `batch` job found a difference
+    #[serde(rename = "check")]
+    Check,
+    #[serde(rename = "io")]
+    Io,
+}
+
+impl Error {
+    pub fn category(&self) -> Category {
+        match self {
+            Error::Load(_) => Category::Load,
+            Error::Runtime(_) => Category::Runtime,
+            Error::Terminal(_) => Category::Terminal,
+            Error::FewArguments
+            | Error::BadArgument(_)
+            | Error::BadAddress(_)
+            | Error::BadJobs(_) => Category::Usage,
+            Error::Assembly(_) => Category::Assembly,
+            Error::NotFormatted(_)
+            | Error::TracesDiverge(_)
+            | Error::MachinesDiverge(_)
+            | Error::StatesDiffer(..)
+            | Error::AssertionsFailed(_)
+            | Error::JobsFailed(_)
+            | Error::CasesFailed(_)
+            | Error::InstancesFailed(_) => Category::Check,
+            Error::DeviceRange(..) | Error::DevicePlugin(_) => Category::Device,
+            Error::Io(_) => Category::Io,
+        }
+    }
+
+    /// The error itself, without the [`RuntimeError::Fault`] around it if it came from an instruction
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Runtime(RuntimeError::Fault(fault)) => &fault.error,
+            error => error,
+        }
+    }
+
+    /// Whether the run was stopped through its [`crate::InterruptHandle`], or GETC found no key yet, see [`RuntimeError::Interrupted`]
+    pub fn is_interrupted(&self) -> bool {
+        matches!(self, Error::Runtime(RuntimeError::Interrupted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn errors_are_grouped_in_categories() {
+        let errors: [(Error, Category); 6] = [
+            (LoadError::BadImageSize.into(), Category::Load),
+            (RuntimeError::BadTrapCode(0x26).into(), Category::Runtime),
+            (
+                TerminalError::DisableInputBuffering(io::ErrorKind::Other.into()).into(),
+                Category::Terminal,
+            ),
+            (Error::BadArgument("--nope".to_string()), Category::Usage),
+            (Error::TracesDiverge(3), Category::Check),
+            (
+                io::Error::from(io::ErrorKind::BrokenPipe).into(),
+                Category::Io,
+            ),
+        ];
+        for (error, category) in errors {
+            assert_eq!(error.category(), category, "{}", error);
+        }
+    }
+
+    #[test]
+    fn io_errors_are_kept_as_the_source() {
+        let error: Error = LoadError::File(io::ErrorKind::NotFound.into()).into();
+        let source = error.source().unwrap();
+        assert_eq!(
+            source.downcast_ref::<io::Error>().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+        let error: Error =
+            TerminalError::RestoreInputBuffering(io::ErrorKind::Unsupported.into()).into();
+        assert!(error.source().unwrap().is::<io::Error>());
+    }
+}
@@ -0,0 +1,94 @@
+//! Where a runtime error happened: the run loops wrap the error of an instruction in a [`Fault`] with the address it was
+//! fetched from, its word and the registers it uses, so the message says where to look:
+//! ```text
+//! Bad trap code xFF at x304A (TRAP xFF); R0=x0041
+//! ```
+
+use std::fmt;
+
+use crate::disassembler::disassemble;
+use crate::{Error, Registers, RuntimeError, State};
+
+/// A runtime error and the instruction that caused it, see [`RuntimeError::Fault`]
+#[derive(Debug)]
+pub struct Fault {
+    pub error: Error,
+    /// The address the instruction was fetched from, the PC already points past it
+    pub pc: u16,
+    pub word: u16,
+    /// The registers the instruction uses by number, with their values when it failed
+    pub registers: Vec<(u16, u16)>,
+}
+
+impl Fault {
+    /// The error of the instruction `word` fetched from `pc`, with the context to report it.
+    /// [`RuntimeError::Interrupted`] isn't a fault, it is only a pause of the program, so it is returned as it is
+    pub(crate) fn wrap(error: Error, pc: u16, word: u16, state: &State) -> Error {
+        if error.is_interrupted() {
+            return error;
+        }
+        let registers = used_registers(word)
+            .into_iter()
+            .map(|number| (number, state.register_read(Registers::from_bits(number))))
+            .collect();
+        RuntimeError::Fault(Box::new(Fault {
+            error,
+            pc,
+            word,
+            registers,
+        }))
+        .into()
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "{} at x{:04X} ({})",
+            self.error,
+            self.pc,
+            disassemble(self.word)
+        )?;
+        for (index, (number, value)) in self.registers.iter().enumerate() {
+            let separator = if index == 0 { "; " } else { " " };
+            write!(formatter, "{}R{}=x{:04X}", separator, number, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The general purpose registers an instruction reads or writes, in order and without repeats.
+/// The traps all work on R0
+fn used_registers(word: u16) -> Vec<u16> {
+    let register_1 = (word >> 9) & 0x7;
+    let register_2 = (word >> 6) & 0x7;
+    let mut registers = match word >> 12 {
+        // ADD and AND with a register as the second operand
+        0x1 | 0x5 if (word >> 5) & 1 == 0 => vec![register_1, register_2, word & 0x7],
+        0x1 | 0x5 | 0x6 | 0x7 | 0x9 => vec![register_1, register_2],
+        0x2 | 0x3 | 0xA | 0xB | 0xE => vec![register_1],
+        0x4 if (word >> 11) & 1 == 0 => vec![register_2],
+        0xC => vec![register_2],
+        0xF => vec![0],
+        _ => Vec::new(),
+    };
+    registers.sort_unstable();
+    registers.dedup();
+    registers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_registers_of_the_instruction_are_used() {
+        assert_eq!(used_registers(0x1283), vec![1, 2, 3]); // ADD R1, R2, R3
+        assert_eq!(used_registers(0x1265), vec![1]); // ADD R1, R1, #5
+        assert_eq!(used_registers(0x6A81), vec![2, 5]); // LDR R5, R2, #1
+        assert_eq!(used_registers(0xC1C0), vec![7]); // RET
+        assert_eq!(used_registers(0xF026), vec![0]); // TRAP x26
+        assert!(used_registers(0x0FFF).is_empty()); // BRnzp #-1
+    }
+}
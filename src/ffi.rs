@@ -0,0 +1,382 @@
+//! A flat C API, only built with the `ffi` feature. Its header is `include/lc3.h`, cbindgen writes it from this file
+//! on every build with the feature (see `build.rs` and `cbindgen.toml`).
+//!
+//! Every function takes a machine made by [`lc3_new`]. Running it never blocks: the host hands over the keys with its input callback
+//! and gets every printed byte with its output callback, a program waiting for a key the callback doesn't have stops the run
+//! with [`Lc3Status::WaitingForInput`]. Failures return a negative status and leave a message for [`lc3_last_error_message`].
+//! A panic never crosses into the host, every entry point catches it and returns [`Lc3Status::Panic`]
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{CString, c_char, c_void};
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::console::{BufferedOutput, Input};
+use crate::file_management::read_bytes_to_memory;
+use crate::{Error, InterruptHandle, LoadError, Registers, RuntimeError, State, run_with_budget};
+
+/// Index of the PC for [`lc3_read_reg`] and [`lc3_write_reg`], 0 to 7 are R0 to R7
+pub const LC3_REGISTER_PC: u32 = 8;
+/// Index of the condition codes for [`lc3_read_reg`] and [`lc3_write_reg`]
+pub const LC3_REGISTER_COND: u32 = 9;
+
+/// What a call did, the failures are the negative ones
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lc3Status {
+    Ok = 0,
+    /// The program ran HALT
+    Halted = 1,
+    /// The program ran all the instructions it was given and can keep going
+    Running = 2,
+    /// The program is in GETC or IN and the input callback had no key, run it again once it has one
+    WaitingForInput = 3,
+    NullPointer = -1,
+    /// The image is too short or doesn't fit in memory
+    BadImage = -2,
+    BadOpCode = -3,
+    BadTrapCode = -4,
+    /// A register index above [`LC3_REGISTER_COND`]
+    BadRegister = -5,
+    /// A trap routine couldn't finish
+    Trap = -6,
+    /// The VM panicked, the machine may be left halfway through an instruction
+    Panic = -7,
+    Other = -8,
+}
+
+/// Called for the next key: a byte from 0 to 255, or a negative number if there is no key right now. Null when there is none
+pub type Lc3InputCallback = Option<extern "C" fn(user_data: *mut c_void) -> i32>;
+/// Called with every byte the program prints. Null when there is none
+pub type Lc3OutputCallback = Option<extern "C" fn(user_data: *mut c_void, byte: u8)>;
+
+/// An LC-3 machine, only used through a pointer from [`lc3_new`]
+pub struct Lc3Vm {
+    state: State,
+}
+
+/// The pointer the host gave with its callback, given back to it on every call.
+/// The VM only calls the callbacks from the thread running it, it is up to the host that this is fine
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+/// Keys from the input callback, without one there are never any.
+/// GETC and IN without a key stop the run through the interrupt handle, the trap goes back so it runs again once the run is resumed
+struct CallbackInput {
+    callback: Lc3InputCallback,
+    user_data: UserData,
+    waiting: InterruptHandle,
+}
+
+impl Input for CallbackInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        let key = self.poll_byte();
+        if key.is_none() {
+            self.waiting.request();
+        }
+        key
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        let callback = self.callback?;
+        u8::try_from(callback(self.user_data.0)).ok()
+    }
+}
+
+struct CallbackOutput {
+    callback: extern "C" fn(user_data: *mut c_void, byte: u8),
+    user_data: UserData,
+}
+
+impl Write for CallbackOutput {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for byte in bytes {
+            (self.callback)(self.user_data.0, *byte);
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// The message of the last failure on this thread, see [`lc3_last_error_message`]
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Keep the message for [`lc3_last_error_message`] and return the status
+fn fail(status: Lc3Status, message: &str) -> Lc3Status {
+    let message = CString::new(message.replace('\0', "")).unwrap(); // Every NUL was just removed
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+fn fail_with(error: Error) -> Lc3Status {
+    let status = match error.root() {
+        Error::Load(LoadError::BadImageSize) => Lc3Status::BadImage,
+        Error::Runtime(RuntimeError::BadOpCode(_)) => Lc3Status::BadOpCode,
+        Error::Runtime(RuntimeError::BadTrapCode(_)) => Lc3Status::BadTrapCode,
+        Error::Runtime(RuntimeError::BadRegisterReference(_)) => Lc3Status::BadRegister,
+        Error::Runtime(RuntimeError::Trap(_)) => Lc3Status::Trap,
+        _ => Lc3Status::Other,
+    };
+    fail(status, &error.to_string())
+}
+
+/// Run the body of an entry point, a panic becomes [`Lc3Status::Panic`] instead of unwinding into the host
+fn guarded(body: impl FnOnce() -> Lc3Status) -> Lc3Status {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(status) => status,
+        Err(payload) => fail(Lc3Status::Panic, &panic_message(payload)),
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => format!("The VM panicked: {}", message),
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => format!("The VM panicked: {}", message),
+            Err(_) => "The VM panicked".to_string(),
+        },
+    }
+}
+
+/// Run the body with the machine behind the pointer, a null one is [`Lc3Status::NullPointer`]
+///
+/// # Safety
+/// The pointer must be null or come from [`lc3_new`] and not be freed yet
+unsafe fn with_vm(vm: *mut Lc3Vm, body: impl FnOnce(&mut Lc3Vm) -> Lc3Status) -> Lc3Status {
+    guarded(|| match unsafe { vm.as_mut() } {
+        Some(vm) => body(vm),
+        None => fail(Lc3Status::NullPointer, "The machine is a null pointer"),
+    })
+}
+
+fn register(index: u32) -> Result<Registers, Lc3Status> {
+    match index {
+        LC3_REGISTER_PC => Ok(Registers::Pc),
+        LC3_REGISTER_COND => Ok(Registers::Flags),
+        general => u16::try_from(general)
+            .ok()
+            .and_then(|general| Registers::try_from(general).ok())
+            .ok_or_else(|| {
+                fail(
+                    Lc3Status::BadRegister,
+                    &format!("There is no register {}", index),
+                )
+            }),
+    }
+}
+
+fn run(vm: &mut Lc3Vm, budget: u64) -> Lc3Status {
+    if !vm.state.is_running() {
+        return Lc3Status::Halted;
+    }
+    match run_with_budget(&mut vm.state, budget) {
+        Ok(()) => Lc3Status::Halted,
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Lc3Status::Running,
+        Err(Error::Runtime(RuntimeError::Interrupted)) => Lc3Status::WaitingForInput,
+        Err(error) => fail_with(error),
+    }
+}
+
+/// A new machine with nothing loaded, free it with [`lc3_free`].
+/// Until the callbacks are set there are no keys and the program prints to the standard output. Null if it couldn't be made
+#[unsafe(no_mangle)]
+pub extern "C" fn lc3_new() -> *mut Lc3Vm {
+    panic::catch_unwind(|| {
+        let mut state = State::default();
+        state.set_input(CallbackInput {
+            callback: None,
+            user_data: UserData(ptr::null_mut()),
+            waiting: state.interrupt_handle(),
+        });
+        Box::into_raw(Box::new(Lc3Vm { state }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Free a machine from [`lc3_new`], null does nothing
+///
+/// # Safety
+/// The pointer must be null or come from [`lc3_new`] and not be freed yet, it can't be used afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_free(vm: *mut Lc3Vm) {
+    if !vm.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(vm) })));
+    }
+}
+
+/// Load an object image: its origin followed by its words, big endian
+///
+/// # Safety
+/// `vm` as in [`lc3_free`], `image` must point to `length` readable bytes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_load_image(
+    vm: *mut Lc3Vm,
+    image: *const u8,
+    length: usize,
+) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| {
+            if image.is_null() {
+                return fail(Lc3Status::NullPointer, "The image is a null pointer");
+            }
+            let image = std::slice::from_raw_parts(image, length);
+            match read_bytes_to_memory(image, &mut vm.state) {
+                Ok(()) => Lc3Status::Ok,
+                Err(error) => fail_with(error),
+            }
+        })
+    }
+}
+
+/// Run a single instruction
+///
+/// # Safety
+/// `vm` as in [`lc3_free`]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_step(vm: *mut Lc3Vm) -> Lc3Status {
+    unsafe { with_vm(vm, |vm| run(vm, 1)) }
+}
+
+/// Run until the program halts, waits for a key or `max` instructions ran
+///
+/// # Safety
+/// `vm` as in [`lc3_free`]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_run(vm: *mut Lc3Vm, max: u64) -> Lc3Status {
+    unsafe { with_vm(vm, |vm| run(vm, max)) }
+}
+
+/// Write the register with that index in `value`, see [`LC3_REGISTER_PC`]
+///
+/// # Safety
+/// `vm` as in [`lc3_free`], `value` must be null or writable
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_read_reg(vm: *mut Lc3Vm, index: u32, value: *mut u16) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| {
+            let register = match register(index) {
+                Ok(register) => register,
+                Err(status) => return status,
+            };
+            match value.as_mut() {
+                Some(value) => *value = vm.state.register_read(register),
+                None => return fail(Lc3Status::NullPointer, "The value is a null pointer"),
+            }
+            Lc3Status::Ok
+        })
+    }
+}
+
+/// Set the register with that index, see [`LC3_REGISTER_PC`]
+///
+/// # Safety
+/// `vm` as in [`lc3_free`]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_write_reg(vm: *mut Lc3Vm, index: u32, value: u16) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| match register(index) {
+            Ok(register) => {
+                vm.state.register_write(register, value);
+                Lc3Status::Ok
+            }
+            Err(status) => status,
+        })
+    }
+}
+
+/// Write the word at that address in `value`. The device registers aren't polled like a LDR would, they give the
+/// word the program last read from them
+///
+/// # Safety
+/// `vm` as in [`lc3_free`], `value` must be null or writable
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_read_mem(vm: *mut Lc3Vm, address: u16, value: *mut u16) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| match value.as_mut() {
+            Some(value) => {
+                *value = vm.state.peek(address as usize);
+                Lc3Status::Ok
+            }
+            None => fail(Lc3Status::NullPointer, "The value is a null pointer"),
+        })
+    }
+}
+
+/// Set the word at that address
+///
+/// # Safety
+/// `vm` as in [`lc3_free`]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_write_mem(vm: *mut Lc3Vm, address: u16, value: u16) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| {
+            vm.state.memory_write(address as usize, value);
+            Lc3Status::Ok
+        })
+    }
+}
+
+/// Take the keys from `callback`, it gets `user_data` on every call. Null means there are no keys
+///
+/// # Safety
+/// `vm` as in [`lc3_free`], `callback` must be fine to call with `user_data` while the machine runs
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_set_input_callback(
+    vm: *mut Lc3Vm,
+    callback: Lc3InputCallback,
+    user_data: *mut c_void,
+) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| {
+            let waiting = vm.state.interrupt_handle();
+            vm.state.set_input(CallbackInput {
+                callback,
+                user_data: UserData(user_data),
+                waiting,
+            });
+            Lc3Status::Ok
+        })
+    }
+}
+
+/// Give every printed byte to `callback`, it gets `user_data` on every call. Null prints to the standard output again
+///
+/// # Safety
+/// `vm` as in [`lc3_free`], `callback` must be fine to call with `user_data` while the machine runs
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lc3_set_output_callback(
+    vm: *mut Lc3Vm,
+    callback: Lc3OutputCallback,
+    user_data: *mut c_void,
+) -> Lc3Status {
+    unsafe {
+        with_vm(vm, |vm| {
+            match callback {
+                Some(callback) => vm.state.set_output(CallbackOutput {
+                    callback,
+                    user_data: UserData(user_data),
+                }),
+                None => vm.state.set_output(BufferedOutput::new(io::stdout())),
+            }
+            Lc3Status::Ok
+        })
+    }
+}
+
+/// The message of the last failure on this thread, null if nothing failed yet.
+/// It is valid until the next failure on the thread
+#[unsafe(no_mangle)]
+pub extern "C" fn lc3_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
@@ -1,38 +1,126 @@
 use std::{fs::File, io::Read, path::Path};
 
-use crate::{Errors, State};
-/// Given a file path open the file and write its instruction in little endian in the memory
-pub(crate) fn read_file_to_memory(string_path: &String, state: &mut State) -> Result<(), Errors> {
+use crate::{Error, LoadError, MEM_MAX, State};
+
+pub mod lc3tools;
+
+/// Largest possible image: the origin and a word for every memory address
+const MAX_IMAGE_BYTES: usize = 2 + 2 * MEM_MAX;
+/// Largest file read, lc3tools object files keep the source line of every word so they can be much bigger than an image
+const MAX_FILE_BYTES: u64 = 1 << 26;
+
+/// Given a file path open the file and return its origin and its words, see [`load_image_bytes`].
+/// An lc3tools object file must have a single section
+pub fn read_image(string_path: &String) -> Result<(u16, Vec<u16>), Error> {
+    let mut sections = read_sections(string_path)?;
+    match sections.len() {
+        0 => Err(LoadError::BadImageSize.into()),
+        1 => Ok(sections.remove(0)),
+        amount => Err(LoadError::SeveralSections(amount).into()),
+    }
+}
+
+/// Given a file path open the file and return the origin and the words of every section, see [`load_sections`]
+pub fn read_sections(string_path: &String) -> Result<Vec<(u16, Vec<u16>)>, Error> {
     // Open file on that path
     let path = Path::new(string_path);
-    let mut file = File::open(path)?;
-    // Initialize a BufReader and a line iterator to read the file line by line
+    let file = File::open(path).map_err(LoadError::File)?;
     let mut buffer = Vec::new();
-    let read_amount = file.read_to_end(&mut buffer)?;
-    let origin = u16::from_be_bytes([buffer[0], buffer[1]]) as usize;
-    let max_memory = state.memory.len() - origin;
-    let mut buffer_offset = 2;
-    let mut memory_offset = 0;
-    loop {
-        if buffer_offset == read_amount - 1 {
-            state.memory_write(
-                origin + memory_offset,
-                u16::from_be_bytes([buffer[buffer_offset], 0]),
-            );
-            break;
-        }
-        if memory_offset >= max_memory {
-            return Err(Errors::BadImageSize);
-        }
-        if buffer_offset >= read_amount {
-            break;
-        }
-        state.memory_write(
-            origin + memory_offset,
-            u16::from_be_bytes([buffer[buffer_offset], buffer[buffer_offset + 1]]),
-        );
-        memory_offset += 1;
-        buffer_offset += 2;
+    // A byte more than the largest file is enough to know that the file is too big
+    file.take(MAX_FILE_BYTES + 1)
+        .read_to_end(&mut buffer)
+        .map_err(LoadError::File)?;
+    if buffer.len() as u64 > MAX_FILE_BYTES {
+        return Err(LoadError::BadImageSize.into());
+    }
+    load_sections(&buffer)
+}
+
+/// The sections of an image: the only one of a plain image (see [`load_image_bytes`]) or those of an lc3tools object file,
+/// told apart by its magic bytes (see [`lc3tools`])
+pub fn load_sections(bytes: &[u8]) -> Result<Vec<(u16, Vec<u16>)>, Error> {
+    match lc3tools::is_lc3tools(bytes) {
+        true => lc3tools::parse(bytes),
+        false => Ok(vec![load_image_bytes(bytes)?]),
+    }
+}
+
+/// Split the bytes of an image in its origin and its words, both stored in big endian.
+/// If there is an odd amount of bytes the last byte is taken as the most significant byte of the last word.
+/// The image must fit in memory starting at its origin, that is checked before allocating anything
+pub fn load_image_bytes(bytes: &[u8]) -> Result<(u16, Vec<u16>), Error> {
+    if bytes.len() < 2 || bytes.len() > MAX_IMAGE_BYTES {
+        return Err(LoadError::BadImageSize.into());
+    }
+    let origin = u16::from_be_bytes([bytes[0], bytes[1]]);
+    if origin as usize + (bytes.len() - 2).div_ceil(2) > MEM_MAX {
+        return Err(LoadError::BadImageSize.into());
+    }
+    let words = bytes[2..]
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]))
+        .collect();
+    Ok((origin, words))
+}
+
+/// The bytes of a plain image, the inverse of [`load_image_bytes`]
+pub fn image_bytes(origin: u16, words: &[u16]) -> Vec<u8> {
+    let mut bytes = origin.to_be_bytes().to_vec();
+    for word in words {
+        bytes.extend_from_slice(&word.to_be_bytes());
+    }
+    bytes
+}
+
+/// Given a file path open the file and write its instruction in little endian in the memory.
+/// Plain images and lc3tools object files are both loaded
+pub fn read_file_to_memory(string_path: &String, state: &mut State) -> Result<(), Error> {
+    for (origin, words) in read_sections(string_path)? {
+        state.load_image(origin, &words);
+    }
+    Ok(())
+}
+
+/// Like [`read_file_to_memory`] for an image that is already in memory, see [`load_sections`]
+pub fn read_bytes_to_memory(bytes: &[u8], state: &mut State) -> Result<(), Error> {
+    for (origin, words) in load_sections(bytes)? {
+        state.load_image(origin, &words);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn image_bytes() {
+        assert_eq!(
+            load_image_bytes(&[0x30, 0x00, 0x12, 0x34, 0xAB]).unwrap(),
+            (0x3000, vec![0x1234, 0xAB00])
+        );
+        assert_eq!(
+            load_image_bytes(&[0xFF, 0xFF, 0, 1]).unwrap(),
+            (0xFFFF, vec![1])
+        );
+        assert!(load_image_bytes(&[0x30]).is_err());
+        assert!(load_image_bytes(&[0xFF, 0xFF, 0, 1, 0]).is_err());
+        assert!(load_image_bytes(&vec![0; MAX_IMAGE_BYTES + 1]).is_err());
+    }
+
+    /// Inputs that broke the loader while fuzzing, they must keep loading (or failing) without a panic
+    #[test]
+    fn fuzz_regressions() {
+        let directory = format!("{}/fuzz/regressions/load_image", env!("CARGO_MANIFEST_DIR"));
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+        for entry in entries {
+            let bytes = fs::read(entry.unwrap().path()).unwrap();
+            if let Ok((origin, words)) = load_image_bytes(&bytes) {
+                assert!(origin as usize + words.len() <= MEM_MAX);
+            }
+        }
+    }
+}
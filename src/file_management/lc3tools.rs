@@ -0,0 +1,148 @@
+//! The object files of [lc3tools](https://github.com/chiragsakhuja/lc3tools), the simulator the graders use.
+//!
+//! A file starts with [`MAGIC`] and the format [`VERSION`] (major and minor), then comes an entry per word:
+//! the word (2 bytes), whether it is an origin (1 byte), and the source line it came from (its length in 4 bytes, then its text).
+//! The numbers are little endian. An origin entry isn't loaded, the words after it are, starting at that address,
+//! so a file can have several sections
+
+use crate::{Error, LoadError, MEM_MAX};
+
+/// The first bytes of every lc3tools object file, how it is told apart from a plain image
+pub const MAGIC: [u8; 4] = [0x1C, 0x30, 0x15, 0xC0];
+/// The version of the format read and written here
+pub const VERSION: [u8; 2] = [0x01, 0x01];
+
+/// Whether the bytes are an lc3tools object file. A plain image at x1C30 starting with the word x15C0 would look like one
+pub fn is_lc3tools(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// The sections of an lc3tools object file: the origin and the words of each, every one must fit in memory
+pub fn parse(bytes: &[u8]) -> Result<Vec<(u16, Vec<u16>)>, Error> {
+    let rest = bytes.strip_prefix(&MAGIC).ok_or(LoadError::BadImageSize)?;
+    let (version, mut rest) = rest.split_at_checked(2).ok_or(LoadError::BadImageSize)?;
+    if version != VERSION {
+        return Err(LoadError::ObjectVersion {
+            expected: version_name(&VERSION),
+            found: version_name(version),
+        }
+        .into());
+    }
+    let mut sections: Vec<(u16, Vec<u16>)> = Vec::new();
+    while !rest.is_empty() {
+        let (entry, after) = rest.split_at_checked(7).ok_or(LoadError::BadImageSize)?;
+        let word = u16::from_le_bytes([entry[0], entry[1]]);
+        let line_length = u32::from_le_bytes([entry[3], entry[4], entry[5], entry[6]]);
+        // The source line is only there for lc3tools' debugger
+        rest = after
+            .get(line_length as usize..)
+            .ok_or(LoadError::BadImageSize)?;
+        match entry[2] != 0 {
+            true => sections.push((word, Vec::new())),
+            false => {
+                let (origin, words) = sections.last_mut().ok_or(LoadError::BadImageSize)?; // A word before any origin
+                if *origin as usize + words.len() >= MEM_MAX {
+                    return Err(LoadError::BadImageSize.into());
+                }
+                words.push(word);
+            }
+        }
+    }
+    Ok(sections)
+}
+
+/// An lc3tools object file with the sections, its source lines are empty
+pub fn write(sections: &[(u16, Vec<u16>)]) -> Vec<u8> {
+    let mut bytes = Vec::from(MAGIC);
+    bytes.extend_from_slice(&VERSION);
+    for (origin, words) in sections {
+        push_entry(&mut bytes, *origin, true);
+        for word in words {
+            push_entry(&mut bytes, *word, false);
+        }
+    }
+    bytes
+}
+
+fn push_entry(bytes: &mut Vec<u8>, word: u16, is_origin: bool) {
+    bytes.extend_from_slice(&word.to_le_bytes());
+    bytes.push(is_origin as u8);
+    bytes.extend_from_slice(&0_u32.to_le_bytes());
+}
+
+/// A version written like `1.1`
+fn version_name(version: &[u8]) -> String {
+    version
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!(
+            "{}/tests/fixtures/lc3tools/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        fs::read(path).unwrap()
+    }
+
+    #[test]
+    fn an_lc3tools_file_has_the_words_of_the_plain_image() {
+        let bytes = fixture("hello.obj");
+        assert!(is_lc3tools(&bytes));
+        let plain = fs::read(format!(
+            "{}/tests/programs/hello.obj",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        assert_eq!(
+            parse(&bytes).unwrap(),
+            vec![crate::file_management::load_image_bytes(&plain).unwrap()]
+        );
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        let sections = parse(&fixture("two_sections.obj")).unwrap();
+        assert_eq!(
+            sections,
+            vec![
+                (0x3000, vec![0xA002, 0xF021, 0xF025, 0x4000]),
+                (0x4000, vec![0x0041])
+            ]
+        );
+        assert_eq!(parse(&write(&sections)).unwrap(), sections);
+    }
+
+    #[test]
+    fn another_version_is_named_in_the_error() {
+        let mut bytes = fixture("hello.obj");
+        bytes[5] = 0x00;
+        assert_eq!(
+            parse(&bytes).unwrap_err().to_string(),
+            "Unsupported lc3tools object version: expected 1.1, found 1.0"
+        );
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let bytes = fixture("hello.obj");
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse(&bytes[..3]).is_err());
+        // A word without an origin before it
+        let mut orphan = Vec::from(MAGIC);
+        orphan.extend_from_slice(&VERSION);
+        push_entry(&mut orphan, 0x1234, false);
+        assert!(parse(&orphan).is_err());
+        // A section running past xFFFF
+        let overflowing = write(&[(0xFFFF, vec![1, 2])]);
+        assert!(parse(&overflowing).is_err());
+    }
+}
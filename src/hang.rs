@@ -0,0 +1,181 @@
+//! Detection of a program spinning without doing anything, what `--detect-hang` turns on. Like `HERE BRnzp HERE`, or
+//! a loop polling the keyboard once the scripted keys ran out.
+//!
+//! The check keeps the registers (PC included) at an anchor address. When the PC is back there with the very same
+//! registers and nothing in between stored to memory, ran a trap or read a device, the machine is where it was and
+//! will keep coming back: every such lap counts, [`HangCheck::threshold`] laps in a row is a hang. If the PC doesn't
+//! come back within [`HangCheck::window`] instructions the anchor moves to wherever it is.
+//!
+//! A device can change on its own, like the keyboard while someone types, so reading one is progress. Only with
+//! `scripted_input`, when the keys are all known from the start, the device registers are left out and a program
+//! polling the keyboard after the last key is a hang
+
+use std::fmt;
+
+use crate::disassembler::disassemble;
+use crate::{DEVICE_PAGE, Operations, Registers, State};
+
+/// How many laps and how long a lap can be, see the module documentation and [`crate::State::set_hang_check`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HangCheck {
+    /// Laps in a row without progress before the program is stopped
+    pub threshold: u64,
+    /// Longest loop seen, in instructions
+    pub window: u64,
+    /// The keys are all known from the start, reading the device registers isn't progress
+    pub scripted_input: bool,
+    /// The registers the last time the PC was at the anchor
+    anchor: Option<[u16; Registers::InstRet as usize]>,
+    /// Instructions since the anchor
+    since_anchor: u64,
+    /// Laps in a row that changed nothing
+    laps: u64,
+    /// Something happened since the anchor that the registers don't show
+    progress: bool,
+}
+
+impl HangCheck {
+    /// A loop repeated more than a thousand times is hung
+    pub const DEFAULT_THRESHOLD: u64 = 1000;
+    /// Loops as long as a poll of the keyboard with some bookkeeping around it
+    pub const DEFAULT_WINDOW: u64 = 64;
+
+    pub fn new(threshold: u64, window: u64, scripted_input: bool) -> HangCheck {
+        HangCheck {
+            threshold,
+            window,
+            scripted_input,
+            anchor: None,
+            since_anchor: 0,
+            laps: 0,
+            progress: false,
+        }
+    }
+
+    /// The instruction at the PC of `registers` is about to run, `progress` if it may change something else
+    /// (see [`HangCheck::progress`]). True once the program is hung
+    pub(crate) fn step(
+        &mut self,
+        registers: &[u16; Registers::InstRet as usize],
+        progress: bool,
+    ) -> bool {
+        let pc = registers[Registers::Pc as usize];
+        let hung = match &self.anchor {
+            Some(anchor) if anchor[Registers::Pc as usize] == pc => {
+                if !self.progress && anchor == registers {
+                    self.laps += 1;
+                } else {
+                    self.laps = 0;
+                }
+                self.reanchor(registers);
+                self.laps > self.threshold
+            }
+            Some(_) if self.since_anchor < self.window => {
+                self.since_anchor += 1;
+                false
+            }
+            _ => {
+                self.laps = 0;
+                self.reanchor(registers);
+                false
+            }
+        };
+        self.progress |= progress;
+        hung
+    }
+
+    fn reanchor(&mut self, registers: &[u16; Registers::InstRet as usize]) {
+        self.anchor = Some(*registers);
+        self.since_anchor = 0;
+        self.progress = false;
+    }
+
+    /// Whether running `word` may change something besides the registers: a store, a trap or, unless the input is
+    /// scripted, a read of a device register
+    pub(crate) fn progress(&self, word: u16, state: &State) -> bool {
+        let pc = state.registers[Registers::Pc as usize].wrapping_add(1);
+        let offset_9 = crate::operations::sign_extend(word & 0x1FF, 9);
+        let address = match word >> 12 {
+            operation
+                if operation == Operations::St as u16
+                    || operation == Operations::Sti as u16
+                    || operation == Operations::Str as u16
+                    || operation == Operations::Trap as u16 =>
+            {
+                return true;
+            }
+            operation if operation == Operations::Ld as u16 => pc.wrapping_add(offset_9),
+            operation if operation == Operations::Ldr as u16 => {
+                let base = state.registers[((word >> 6) & 0x7) as usize];
+                base.wrapping_add(crate::operations::sign_extend(word & 0x3F, 6))
+            }
+            operation if operation == Operations::Ldi as u16 => {
+                let pointer = pc.wrapping_add(offset_9);
+                if pointer as usize >= DEVICE_PAGE {
+                    pointer
+                } else {
+                    state.memory[pointer as usize]
+                }
+            }
+            _ => return false,
+        };
+        address as usize >= DEVICE_PAGE && !self.scripted_input
+    }
+}
+
+/// Where a program was found hung, see [`crate::RuntimeError::Hang`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hang {
+    pub pc: u16,
+    pub word: u16,
+    /// R0 to R7
+    pub registers: [u16; 8],
+}
+
+impl fmt::Display for Hang {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            formatter,
+            "The program appears hung at x{:04X} ({});",
+            self.pc,
+            disassemble(self.word)
+        )?;
+        for (number, value) in self.registers.iter().enumerate() {
+            write!(formatter, " R{}=x{:04X}", number, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(pc: u16, r0: u16) -> [u16; Registers::InstRet as usize] {
+        let mut registers = [0; Registers::InstRet as usize];
+        registers[Registers::Pc as usize] = pc;
+        registers[Registers::R0 as usize] = r0;
+        registers
+    }
+
+    #[test]
+    fn the_same_registers_lap_after_lap_is_a_hang() {
+        let mut check = HangCheck::new(3, 4, false);
+        // A two instruction loop at x3000, the first lap only sets the anchor
+        let hung: Vec<bool> = (0..5)
+            .flat_map(|_| [registers(0x3000, 0), registers(0x3001, 0)])
+            .map(|registers| check.step(&registers, false))
+            .collect();
+        assert_eq!(hung.iter().position(|&hung| hung), Some(8));
+    }
+
+    #[test]
+    fn progress_or_other_registers_restart_the_count() {
+        let mut check = HangCheck::new(1, 4, false);
+        assert!(!check.step(&registers(0x3000, 0), true));
+        assert!(!check.step(&registers(0x3000, 0), false)); // There was a store in that lap
+        assert!(!check.step(&registers(0x3000, 1), false)); // R0 changed
+        assert!(!check.step(&registers(0x3000, 1), false));
+        assert!(check.step(&registers(0x3000, 1), false));
+    }
+}
@@ -0,0 +1,194 @@
+//! Files of the host for the programs, the traps x30 to x33 that `--fs-root <dir>` makes available:
+//! * x30 open: R0 points to the path (a character per word ending in NUL, like PUTS), R1 is the [`OpenMode`] bits.
+//!   R0 is the handle
+//! * x31 read: R0 is the handle, R1 the address of the buffer and R2 how many bytes. R0 is how many were read
+//! * x32 write: the same registers as read. R0 is how many bytes were written
+//! * x33 close: R0 is the handle. R0 is 0
+//!
+//! The bytes are packed two per word like PUTSP, the first one in the low byte. Every trap sets the flags from R0 and
+//! a negative R0 is one of the [`HostFileError`] codes.
+//!
+//! The paths are relative to the sandbox directory and can't leave it, neither with `..` nor through a symbolic link.
+//! Without a sandbox every trap fails with [`HostFileError::Disabled`]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Files a program can have open at the same time
+pub const MAX_HANDLES: usize = 8;
+
+/// Why a file trap failed, the code is the negative value left in R0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostFileError {
+    /// The VM runs without a sandbox, see [`crate::State::set_host_files`]
+    Disabled = -1,
+    /// The path is absolute or leaves the sandbox
+    OutsideRoot = -2,
+    NotFound = -3,
+    /// [`MAX_HANDLES`] files are open already
+    NoHandle = -4,
+    /// The handle isn't an open file
+    BadHandle = -5,
+    /// Any other error of the host, like a missing permission
+    Io = -6,
+}
+
+impl HostFileError {
+    /// The word left in R0
+    pub fn code(self) -> u16 {
+        self as i16 as u16
+    }
+}
+
+impl From<io::Error> for HostFileError {
+    fn from(error: io::Error) -> HostFileError {
+        match error.kind() {
+            io::ErrorKind::NotFound => HostFileError::NotFound,
+            _ => HostFileError::Io,
+        }
+    }
+}
+
+/// The bits of R1 for open. Writing creates the file, and truncates it unless appending
+pub struct OpenMode;
+
+impl OpenMode {
+    pub const READ: u16 = 1;
+    pub const WRITE: u16 = 2;
+    pub const APPEND: u16 = 4;
+}
+
+/// The sandbox directory and the files open in it, see the module documentation
+#[derive(Debug)]
+pub struct HostFiles {
+    /// Canonical, what every path resolved must start with
+    root: PathBuf,
+    handles: [Option<File>; MAX_HANDLES],
+}
+
+impl HostFiles {
+    /// A sandbox in `root`, which must be an existing directory
+    pub fn new(root: impl AsRef<Path>) -> io::Result<HostFiles> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{} isn't a directory", root.display()),
+            ));
+        }
+        Ok(HostFiles {
+            root,
+            handles: Default::default(),
+        })
+    }
+
+    /// Where `path` is on the host, if it is in the sandbox
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, HostFileError> {
+        let mut resolved = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir => {}
+                Component::ParentDir if resolved != self.root => {
+                    resolved.pop();
+                }
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(HostFileError::OutsideRoot);
+                }
+            }
+        }
+        // A symbolic link could point anywhere, what exists of the path is followed to check where it really is
+        let existing = match fs::canonicalize(&resolved) {
+            Ok(canonical) => canonical,
+            Err(_) => match resolved.parent().map(fs::canonicalize) {
+                Some(Ok(parent)) => parent,
+                _ => return Ok(resolved), // Opening it fails with NotFound
+            },
+        };
+        match existing.starts_with(&self.root) {
+            true => Ok(resolved),
+            false => Err(HostFileError::OutsideRoot),
+        }
+    }
+
+    /// Open the file at `path` with the [`OpenMode`] bits, returning its handle
+    pub fn open(&mut self, path: &str, mode: u16) -> Result<u16, HostFileError> {
+        let resolved = self.resolve(path)?;
+        let handle = self
+            .handles
+            .iter()
+            .position(Option::is_none)
+            .ok_or(HostFileError::NoHandle)?;
+        let write = mode & (OpenMode::WRITE | OpenMode::APPEND) != 0;
+        let file = OpenOptions::new()
+            .read(mode & OpenMode::READ != 0 || !write)
+            .write(write)
+            .create(write)
+            .append(mode & OpenMode::APPEND != 0)
+            .truncate(mode & OpenMode::WRITE != 0 && mode & OpenMode::APPEND == 0)
+            .open(resolved)?;
+        self.handles[handle] = Some(file);
+        Ok(handle as u16)
+    }
+
+    /// Read up to `length` bytes, fewer at the end of the file
+    pub fn read(&mut self, handle: u16, length: usize) -> Result<Vec<u8>, HostFileError> {
+        let file = self.file(handle)?;
+        let mut bytes = Vec::with_capacity(length);
+        file.take(length as u64).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn write(&mut self, handle: u16, bytes: &[u8]) -> Result<(), HostFileError> {
+        Ok(self.file(handle)?.write_all(bytes)?)
+    }
+
+    pub fn close(&mut self, handle: u16) -> Result<(), HostFileError> {
+        // Dropping the file closes it
+        self.handles
+            .get_mut(handle as usize)
+            .and_then(Option::take)
+            .map(drop)
+            .ok_or(HostFileError::BadHandle)
+    }
+
+    fn file(&mut self, handle: u16) -> Result<&mut File, HostFileError> {
+        self.handles
+            .get_mut(handle as usize)
+            .and_then(Option::as_mut)
+            .ok_or(HostFileError::BadHandle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn paths_cant_leave_the_sandbox() {
+        let root = env::temp_dir().join(format!("lc3-host-fs-{}", std::process::id()));
+        fs::create_dir_all(root.join("inner")).unwrap();
+        let files = HostFiles::new(&root).unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        assert_eq!(files.resolve("a.txt"), Ok(root.join("a.txt")));
+        assert_eq!(files.resolve("inner/../b.txt"), Ok(root.join("b.txt")));
+        assert_eq!(files.resolve("./inner/c.txt"), Ok(root.join("inner/c.txt")));
+        for outside in ["../a.txt", "inner/../../a.txt", "/etc/passwd"] {
+            assert_eq!(
+                files.resolve(outside),
+                Err(HostFileError::OutsideRoot),
+                "{}",
+                outside
+            );
+        }
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn errors_are_negative_codes() {
+        assert_eq!(HostFileError::Disabled.code(), 0xFFFF);
+        assert_eq!(HostFileError::Io.code(), 0xFFFA);
+    }
+}
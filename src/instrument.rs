@@ -0,0 +1,256 @@
+//! Collectors watching every instruction of a run, like the profile of `--stats-out`. Instead of one more `if` in the
+//! run loop for each of them, [`crate::run_instrumented`] is compiled for the type of its [`Instrumentation`]: with
+//! [`NoInstrumentation`], what [`crate::run_with_budget`] runs without a profile, the hooks are empty and inlined away
+//! and the loop is the same as without any. Collectors are composed with a tuple, or with a `Vec` of boxes when which
+//! ones run is only known at run time:
+//! ```
+//! # use lc3::instrument::Instrumentation;
+//! # use lc3::{State, run_instrumented};
+//! /// Counts the instructions at x3000 and up
+//! #[derive(Default)]
+//! struct UserInstructions(u64);
+//!
+//! impl Instrumentation for UserInstructions {
+//!     fn before_instruction(&mut self, pc: u16, _state: &State) {
+//!         self.0 += u64::from(pc >= 0x3000);
+//!     }
+//! }
+//!
+//! let mut state = State::default();
+//! state.load_image(0x3000, &[0x1021, 0xF025]); // ADD R0, R0, #1 and HALT
+//! let mut collectors = (UserInstructions::default(), UserInstructions::default());
+//! run_instrumented(&mut state, 100, &mut collectors).unwrap();
+//! assert_eq!((collectors.0.0, collectors.1.0), (2, 2));
+//! ```
+//! The hooks only see the state, a collector can't change what the program does. The JIT never runs instrumented,
+//! a compiled block would run its instructions without them
+
+use crate::State;
+
+/// The hooks [`crate::run_instrumented`] calls around every instruction, all of them doing nothing by default
+pub trait Instrumentation {
+    /// Whether the hooks do anything, the JIT only runs without them. Only asked once at the start of a run
+    fn active(&self) -> bool {
+        true
+    }
+
+    /// The instruction at `pc` is about to be fetched
+    #[inline(always)]
+    fn before_instruction(&mut self, _pc: u16, _state: &State) {}
+
+    /// The instruction `word` fetched from `pc` ran, the PC already moved on. Not called for one that failed
+    #[inline(always)]
+    fn after_instruction(&mut self, _pc: u16, _word: u16, _state: &State) {}
+
+    /// The run stopped, whether the program halted, failed or ran out of budget
+    fn finished(&mut self, _state: &State) {}
+}
+
+/// No hooks at all, the run loop compiled for it has no trace of them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoInstrumentation;
+
+impl Instrumentation for NoInstrumentation {
+    fn active(&self) -> bool {
+        false
+    }
+}
+
+impl<I: Instrumentation + ?Sized> Instrumentation for &mut I {
+    fn active(&self) -> bool {
+        (**self).active()
+    }
+
+    #[inline(always)]
+    fn before_instruction(&mut self, pc: u16, state: &State) {
+        (**self).before_instruction(pc, state);
+    }
+
+    #[inline(always)]
+    fn after_instruction(&mut self, pc: u16, word: u16, state: &State) {
+        (**self).after_instruction(pc, word, state);
+    }
+
+    fn finished(&mut self, state: &State) {
+        (**self).finished(state);
+    }
+}
+
+impl<I: Instrumentation + ?Sized> Instrumentation for Box<I> {
+    fn active(&self) -> bool {
+        (**self).active()
+    }
+
+    #[inline(always)]
+    fn before_instruction(&mut self, pc: u16, state: &State) {
+        (**self).before_instruction(pc, state);
+    }
+
+    #[inline(always)]
+    fn after_instruction(&mut self, pc: u16, word: u16, state: &State) {
+        (**self).after_instruction(pc, word, state);
+    }
+
+    fn finished(&mut self, state: &State) {
+        (**self).finished(state);
+    }
+}
+
+/// Both, the first one's hook first
+impl<A: Instrumentation, B: Instrumentation> Instrumentation for (A, B) {
+    fn active(&self) -> bool {
+        self.0.active() || self.1.active()
+    }
+
+    #[inline(always)]
+    fn before_instruction(&mut self, pc: u16, state: &State) {
+        self.0.before_instruction(pc, state);
+        self.1.before_instruction(pc, state);
+    }
+
+    #[inline(always)]
+    fn after_instruction(&mut self, pc: u16, word: u16, state: &State) {
+        self.0.after_instruction(pc, word, state);
+        self.1.after_instruction(pc, word, state);
+    }
+
+    fn finished(&mut self, state: &State) {
+        self.0.finished(state);
+        self.1.finished(state);
+    }
+}
+
+/// Every one of them in order, for a set picked at run time
+impl<I: Instrumentation> Instrumentation for Vec<I> {
+    fn active(&self) -> bool {
+        self.iter().any(Instrumentation::active)
+    }
+
+    #[inline]
+    fn before_instruction(&mut self, pc: u16, state: &State) {
+        for instrumentation in self {
+            instrumentation.before_instruction(pc, state);
+        }
+    }
+
+    #[inline]
+    fn after_instruction(&mut self, pc: u16, word: u16, state: &State) {
+        for instrumentation in self {
+            instrumentation.after_instruction(pc, word, state);
+        }
+    }
+
+    fn finished(&mut self, state: &State) {
+        for instrumentation in self {
+            instrumentation.finished(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_util::state_with_snippet;
+    use crate::{RuntimeError, run_instrumented};
+
+    /// Writes down every hook called, tagged with its name
+    struct Calls {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Instrumentation for Calls {
+        fn before_instruction(&mut self, pc: u16, _state: &State) {
+            let call = format!("{} before x{:04X}", self.name, pc);
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn after_instruction(&mut self, pc: u16, word: u16, state: &State) {
+            let call = format!(
+                "{} after x{:04X} x{:04X} -> x{:04X}",
+                self.name,
+                pc,
+                word,
+                state.registers[crate::Registers::Pc]
+            );
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn finished(&mut self, state: &State) {
+            let call = format!(
+                "{} finished after {}",
+                self.name,
+                state.instructions_executed()
+            );
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    #[test]
+    fn every_collector_of_a_set_gets_every_hook() {
+        let mut state = state_with_snippet(
+            "
+        .ORIG x3000
+        BRnzp SKIP
+        HALT
+SKIP    ADD R0, R0, #1
+        HALT
+        .END",
+        );
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let collector = |name| Calls {
+            name,
+            calls: calls.clone(),
+        };
+        let mut set = (
+            collector("a"),
+            vec![Box::new(collector("b")) as Box<dyn Instrumentation>],
+        );
+        assert!(set.active());
+        run_instrumented(&mut state, 100, &mut set).unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "a before x3000",
+                "b before x3000",
+                "a after x3000 x0E01 -> x3002",
+                "b after x3000 x0E01 -> x3002",
+                "a before x3002",
+                "b before x3002",
+                "a after x3002 x1021 -> x3003",
+                "b after x3002 x1021 -> x3003",
+                "a before x3003",
+                "b before x3003",
+                "a after x3003 xF025 -> x3004",
+                "b after x3003 xF025 -> x3004",
+                "a finished after 3",
+                "b finished after 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_run_out_of_budget_is_finished_too() {
+        let mut state = state_with_snippet(
+            "
+        .ORIG x3000
+LOOP    BRnzp LOOP
+        .END",
+        );
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut collector = Calls {
+            name: "a",
+            calls: calls.clone(),
+        };
+        let error = run_instrumented(&mut state, 2, &mut collector).unwrap_err();
+        assert!(matches!(
+            error,
+            crate::Error::Runtime(RuntimeError::BudgetExhausted(2))
+        ));
+        assert_eq!(calls.lock().unwrap().len(), 5);
+        assert!(!NoInstrumentation.active());
+        assert!(!vec![NoInstrumentation].active());
+    }
+}
@@ -0,0 +1,420 @@
+//! Interrupts of the devices, what `--interrupts` turns on (see [`crate::State::set_interrupt_controller`]). They
+//! follow the extended LC-3 model:
+//! * a source asks for an interrupt with a vector and a priority from 0 to 7: the keyboard when a key is ready and
+//!   bit 14 of KBSR is set ([`KEYBOARD_VECTOR`], [`KEYBOARD_PRIORITY`]), any other device through
+//!   [`crate::device::Device::interrupt`]
+//! * between instructions the request with the highest priority is taken if it is above the priority of the processor,
+//!   ties going to the keyboard, then to the devices in the order they were added. The priority of a source can be
+//!   changed without touching the device, see [`InterruptController::set_priority`] and `--irq timer=3`
+//! * taking it switches to the supervisor stack (R6 is saved for the return), pushes the PSR and the PC, then runs
+//!   in supervisor mode at the priority of the request with the condition codes cleared, from the address at
+//!   [`VECTOR_TABLE`] + vector
+//! * RTI pops the PC and the PSR back, and the user stack if it goes back to user mode. A PSR with several condition
+//!   codes set, or none, is normalized: N wins over Z, Z over P, and none is Z
+//!
+//! Exceptions are taken the same way at the priority the processor already has: RTI in user mode
+//! ([`PRIVILEGE_VECTOR`]) and the reserved opcode ([`ILLEGAL_OPCODE_VECTOR`]), both with the PC past the instruction.
+//! Accessing the system space from user mode isn't an exception here, user programs keep using the device registers.
+//!
+//! A TRAP whose entry in [`TRAP_TABLE`] isn't 0 is taken the same way too, the routine there returns with RTI. Those
+//! left at 0 run in the VM like without a controller, like the traps of the VM itself under
+//! [`crate::os::build_minimal_os`].
+//!
+//! A request stays up until the handler clears it, like by reading KBDR, a lower one waits for the RTI of a higher one.
+//! Taking an interrupt or an exception that would push below the supervisor stack stops the program with
+//! [`RuntimeError::SupervisorStackOverflow`] instead of writing over what is below, like the vector table.
+//! Without a controller the machine stays in user mode and RTI and the reserved opcode are bad instructions, like they
+//! always were.
+//!
+//! The controller counts for every vector the interrupts taken and those deferred, asked for while the processor was
+//! at a priority as high or behind a higher request. A request waiting over many instructions is deferred once
+
+use std::ops::RangeInclusive;
+
+use crate::device::timer::TIMER_VECTOR;
+use crate::{Error, Flags, MemoryMappedRegisters, Operations, Registers, RuntimeError, State};
+
+/// Where the addresses of the trap routines are, x0000 to x00FF
+pub const TRAP_TABLE: u16 = 0x0000;
+/// Where the addresses of the handlers are, x0100 to x01FF: the exceptions first, the interrupts from x0180
+pub const VECTOR_TABLE: u16 = 0x0100;
+pub const PRIVILEGE_VECTOR: u8 = 0x00;
+pub const ILLEGAL_OPCODE_VECTOR: u8 = 0x01;
+pub const KEYBOARD_VECTOR: u8 = 0x80;
+pub const KEYBOARD_PRIORITY: u8 = 4;
+/// The supervisor stack without `--supervisor-stack`: from x2FFF down to right above the vector table, it starts
+/// empty at x3000
+pub const DEFAULT_SUPERVISOR_STACK: RangeInclusive<u16> = 0x0200..=0x2FFF;
+
+/// Bit 14 of KBSR, the keyboard interrupts when it is set and a key is ready
+pub(crate) const KEYBOARD_INTERRUPT_ENABLE: u16 = 1 << 14;
+/// Bit 15 of KBSR, a key is waiting in KBDR
+pub(crate) const KEYBOARD_READY: u16 = 1 << 15;
+
+/// An interrupt a source asks for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptRequest {
+    /// The handler is at [`VECTOR_TABLE`] + vector
+    pub vector: u8,
+    /// 0 to 7, the interrupt is taken when it is higher than the priority of the processor
+    pub priority: u8,
+}
+
+/// A source of interrupts whose priority [`InterruptController::set_priority`] changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceId {
+    Keyboard,
+    /// The timer of [`crate::device::timer`]
+    Timer,
+    /// Any device, by the vector it interrupts with
+    Vector(u8),
+}
+
+impl DeviceId {
+    /// The device named `keyboard`, `timer` or by its vector like `x82`, as `--irq` takes them
+    pub fn from_name(name: &str) -> Option<DeviceId> {
+        match name.to_ascii_lowercase().as_str() {
+            "keyboard" => Some(DeviceId::Keyboard),
+            "timer" => Some(DeviceId::Timer),
+            vector => {
+                let digits = vector.strip_prefix('x')?;
+                u8::from_str_radix(digits, 16).ok().map(DeviceId::Vector)
+            }
+        }
+    }
+
+    pub fn vector(self) -> u8 {
+        match self {
+            DeviceId::Keyboard => KEYBOARD_VECTOR,
+            DeviceId::Timer => TIMER_VECTOR,
+            DeviceId::Vector(vector) => vector,
+        }
+    }
+}
+
+/// The interrupts of a vector so far, see [`InterruptController::interrupt_counts`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterruptCounts {
+    pub taken: u64,
+    pub deferred: u64,
+}
+
+/// The mode of the processor and the stack it isn't using, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterruptController {
+    supervisor: bool,
+    priority: u8,
+    /// What the interrupts and the exceptions can push to
+    supervisor_stack: RangeInclusive<u16>,
+    /// R6 of the supervisor while the user runs
+    saved_ssp: u16,
+    /// R6 of the user while the supervisor runs
+    saved_usp: u16,
+    /// The vectors whose priority was changed, with the one they get instead
+    priorities: Vec<(u8, u8)>,
+    counts: Box<[InterruptCounts; 256]>,
+    /// A bit per vector asked for at the last arbitration, so a request waiting is counted once
+    asked: [u64; 4],
+}
+
+impl Default for InterruptController {
+    /// With the [`DEFAULT_SUPERVISOR_STACK`]
+    fn default() -> InterruptController {
+        InterruptController::new(DEFAULT_SUPERVISOR_STACK)
+    }
+}
+
+impl InterruptController {
+    /// User mode at priority 0, with the supervisor stack empty in `supervisor_stack`
+    pub fn new(supervisor_stack: RangeInclusive<u16>) -> InterruptController {
+        InterruptController {
+            supervisor: false,
+            priority: 0,
+            saved_ssp: supervisor_stack.end().wrapping_add(1),
+            supervisor_stack,
+            saved_usp: 0,
+            priorities: Vec::new(),
+            counts: Box::new([InterruptCounts::default(); 256]),
+            asked: [0; 4],
+        }
+    }
+
+    /// Give the interrupts of `device` `priority` instead of the one it asks for, whatever it is. Fails unless the
+    /// priority is 0 to 7, 0 never being above the processor
+    pub fn set_priority(&mut self, device: DeviceId, priority: u8) -> Result<(), Error> {
+        if priority > 7 {
+            return Err(Error::BadArgument(format!(
+                "interrupt priority {} isn't 0 to 7",
+                priority
+            )));
+        }
+        let vector = device.vector();
+        self.priorities.retain(|&(known, _)| known != vector);
+        self.priorities.push((vector, priority));
+        Ok(())
+    }
+
+    /// The priorities changed in `previous`, if any, for a controller replacing it
+    pub(crate) fn keeping_priorities(
+        mut self,
+        previous: Option<&InterruptController>,
+    ) -> InterruptController {
+        if let Some(previous) = previous {
+            self.priorities = previous.priorities.clone();
+        }
+        self
+    }
+
+    /// The priority the interrupts of `device` are taken at, if it was changed
+    pub fn priority_of(&self, device: DeviceId) -> Option<u8> {
+        let vector = device.vector();
+        self.priorities
+            .iter()
+            .find(|&&(known, _)| known == vector)
+            .map(|&(_, priority)| priority)
+    }
+
+    /// The vectors that interrupted or were deferred at least once and how many times, in order
+    pub fn interrupt_counts(&self) -> Vec<(u8, InterruptCounts)> {
+        (0..=u8::MAX)
+            .zip(self.counts.iter().copied())
+            .filter(|(_, counts)| *counts != InterruptCounts::default())
+            .collect()
+    }
+
+    /// `request` at the priority it was given, if it was changed
+    fn arbitrated(&self, request: InterruptRequest) -> InterruptRequest {
+        match self
+            .priorities
+            .iter()
+            .find(|&&(vector, _)| vector == request.vector)
+        {
+            Some(&(_, priority)) => InterruptRequest {
+                priority,
+                ..request
+            },
+            None => request,
+        }
+    }
+
+    /// Count an arbitration between the vectors `asked`, a bit each, `taken` winning if any. The request taken stays
+    /// up while its handler runs, it isn't deferred meanwhile
+    fn count(&mut self, asked: [u64; 4], taken: Option<u8>) {
+        let mut waiting = self.asked;
+        if let Some(vector) = taken {
+            self.counts[vector as usize].taken += 1;
+            waiting[vector as usize / 64] |= 1 << (vector % 64);
+        }
+        for (word, bits) in asked.iter().enumerate() {
+            let mut newly = bits & !waiting[word];
+            while newly != 0 {
+                let bit = newly.trailing_zeros() as usize;
+                self.counts[word * 64 + bit].deferred += 1;
+                newly &= newly - 1;
+            }
+        }
+        self.asked = asked;
+    }
+
+    /// Start in supervisor mode, like a machine booting its operating system. `user_stack` is R6 once it goes to
+    /// user mode
+    pub fn in_supervisor_mode(mut self, user_stack: u16) -> InterruptController {
+        self.supervisor = true;
+        self.saved_usp = user_stack;
+        self
+    }
+
+    pub fn supervisor_stack_range(&self) -> RangeInclusive<u16> {
+        self.supervisor_stack.clone()
+    }
+
+    pub fn is_supervisor(&self) -> bool {
+        self.supervisor
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+}
+
+impl State {
+    /// Take the device interrupts, see [`crate::irq`]. `None` stops taking them, leaving the machine as it is
+    pub fn set_interrupt_controller(&mut self, controller: Option<InterruptController>) {
+        self.irq = controller.map(Box::new);
+    }
+
+    pub fn interrupt_controller(&self) -> Option<&InterruptController> {
+        self.irq.as_deref()
+    }
+
+    /// See [`InterruptController::set_priority`]. Fails without an interrupt controller too
+    pub fn set_irq_priority(&mut self, device: DeviceId, priority: u8) -> Result<(), Error> {
+        match &mut self.irq {
+            Some(irq) => irq.set_priority(device, priority),
+            None => Err(Error::BadArgument(
+                "an interrupt priority without an interrupt controller".to_string(),
+            )),
+        }
+    }
+
+    /// The processor status register: bit 15 set in user mode, the priority in bits 10 to 8 and the condition codes
+    /// in bits 2 to 0
+    pub fn psr(&self) -> u16 {
+        let (supervisor, priority) = self
+            .irq
+            .as_ref()
+            .map_or((false, 0), |irq| (irq.supervisor, irq.priority));
+        (!supervisor as u16) << 15 | (priority as u16) << 8 | self.registers[Registers::Flags] & 0x7
+    }
+
+    /// Take the highest interrupt asked for, if it is above the priority of the processor. Between instructions
+    #[inline]
+    pub(crate) fn service_interrupts(&mut self) -> Result<(), Error> {
+        if self.irq.is_none() {
+            return Ok(());
+        }
+        let keyboard = self.keyboard_interrupt();
+        let Some(irq) = self.irq.as_deref_mut() else {
+            return Ok(());
+        };
+        let mut asked = [0_u64; 4];
+        let mut highest = None;
+        // The devices were ticked by the run loop, see [`crate::device`]
+        let requests = keyboard.into_iter().chain(
+            self.devices
+                .iter_mut()
+                .filter_map(|mapped| mapped.device.interrupt()),
+        );
+        for request in requests {
+            let request = irq.arbitrated(request);
+            asked[request.vector as usize / 64] |= 1 << (request.vector % 64);
+            if highest.is_none_or(|highest: InterruptRequest| request.priority > highest.priority) {
+                highest = Some(request);
+            }
+        }
+        let taken = highest.filter(|request| request.priority > irq.priority);
+        irq.count(asked, taken.map(|request| request.vector));
+        match taken {
+            Some(request) => self.take_interrupt(request),
+            None => Ok(()),
+        }
+    }
+
+    /// With its interrupt enabled the keyboard is polled between instructions, a key waits in KBDR until it is read
+    fn keyboard_interrupt(&mut self) -> Option<InterruptRequest> {
+        let status = self.memory[MemoryMappedRegisters::Kbsr];
+        if status & KEYBOARD_INTERRUPT_ENABLE == 0 {
+            return None;
+        }
+        if status & KEYBOARD_READY == 0 {
+            let key = match &mut self.replay {
+                Some(replay) => replay.poll(),
+                None => self.input.poll_byte(),
+            }?;
+            self.key_consumed(key);
+            self.memory[MemoryMappedRegisters::Kbsr] |= KEYBOARD_READY;
+            self.memory[MemoryMappedRegisters::Kbdr] = key as u16;
+        }
+        Some(InterruptRequest {
+            vector: KEYBOARD_VECTOR,
+            priority: KEYBOARD_PRIORITY,
+        })
+    }
+
+    fn take_interrupt(&mut self, request: InterruptRequest) -> Result<(), Error> {
+        self.enter_supervisor(VECTOR_TABLE + request.vector as u16, request.priority)
+    }
+
+    /// Push the PSR and the PC on the supervisor stack and go to the handler whose address is at `entry` at `priority`
+    fn enter_supervisor(&mut self, entry: u16, priority: u8) -> Result<(), Error> {
+        let psr = self.psr();
+        let pc = self.registers[Registers::Pc];
+        let Some(irq) = &mut self.irq else {
+            return Ok(());
+        };
+        let stack_pointer = match irq.supervisor {
+            true => self.registers[Registers::R6],
+            false => irq.saved_ssp,
+        };
+        let top = stack_pointer.wrapping_sub(2);
+        if !irq.supervisor_stack.contains(&top) || !irq.supervisor_stack.contains(&(top + 1)) {
+            return Err(RuntimeError::SupervisorStackOverflow { pc, address: top }.into());
+        }
+        if !irq.supervisor {
+            irq.saved_usp = self.registers[Registers::R6];
+        }
+        irq.supervisor = true;
+        irq.priority = priority;
+        self.registers[Registers::R6] = top;
+        self.memory_write(top as usize + 1, psr);
+        self.memory_write(top as usize, pc);
+        self.registers[Registers::Flags] = 0;
+        self.registers[Registers::Pc] = self.memory_read(entry as usize);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> u16 {
+        let stack_pointer = self.registers[Registers::R6];
+        self.registers[Registers::R6] = stack_pointer.wrapping_add(1);
+        self.memory_read(stack_pointer as usize)
+    }
+}
+
+/// RTI: back to where the interrupt or the exception was taken. Only the supervisor can run it
+pub(crate) fn return_from_interrupt(_instruction: u16, state: &mut State) -> Result<(), Error> {
+    match &state.irq {
+        None => return Err(RuntimeError::BadOpCode(Operations::Rti as u16).into()),
+        Some(irq) if !irq.supervisor => {
+            let priority = irq.priority;
+            return state.enter_supervisor(VECTOR_TABLE + PRIVILEGE_VECTOR as u16, priority);
+        }
+        Some(_) => {}
+    }
+    let pc = state.pop();
+    let psr = state.pop();
+    state.registers[Registers::Pc] = pc;
+    state.registers[Registers::Flags] = condition_codes(psr);
+    let Some(irq) = &mut state.irq else {
+        return Ok(());
+    };
+    irq.priority = (psr >> 8 & 0x7) as u8;
+    if psr & 1 << 15 != 0 {
+        irq.supervisor = false;
+        irq.saved_ssp = state.registers[Registers::R6];
+        state.registers[Registers::R6] = irq.saved_usp;
+    }
+    Ok(())
+}
+
+/// TRAP to the routine in the [`TRAP_TABLE`], false if there is none and the VM runs it
+pub(crate) fn trap_through_table(state: &mut State, vector: u8) -> Result<bool, Error> {
+    let entry = TRAP_TABLE + vector as u16;
+    match &state.irq {
+        Some(irq) if state.memory[entry as usize] != 0 => {
+            let priority = irq.priority;
+            state.enter_supervisor(entry, priority)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// The reserved opcode
+pub(crate) fn reserved(_instruction: u16, state: &mut State) -> Result<(), Error> {
+    match &state.irq {
+        Some(irq) => {
+            let priority = irq.priority;
+            state.enter_supervisor(VECTOR_TABLE + ILLEGAL_OPCODE_VECTOR as u16, priority)
+        }
+        None => Err(RuntimeError::BadOpCode(Operations::Res as u16).into()),
+    }
+}
+
+/// The condition codes of a PSR, exactly one of them even if it was corrupted on the stack
+fn condition_codes(psr: u16) -> u16 {
+    [Flags::Neg, Flags::Zro, Flags::Pos]
+        .into_iter()
+        .map(|flag| flag as u16)
+        .find(|&flag| psr & flag != 0)
+        .unwrap_or(Flags::Zro as u16)
+}
@@ -0,0 +1,526 @@
+//! Native code for hot loops, only built with the `jit` feature.
+//!
+//! Every address the interpreter executes counts how many times it was reached, once one reaches [`HOT_THRESHOLD`]
+//! the straight run of ADD, AND, NOT and LEA starting there (and the BR ending it, if any) is compiled with cranelift.
+//! Those instructions only touch registers, so a compiled block never reads memory: device registers, traps, loads,
+//! stores and jumps are always left to the interpreter. A block whose BR jumps back to its first instruction loops
+//! without leaving the native code. Writing to a compiled word drops the block, so self-modifying code still works.
+//!
+//! The code of a block only depends on its address and its words, so it is compiled once for the whole process and
+//! shared by every machine: running a program again, or many instances of it, doesn't pay for compiling its loops
+//! again. Cranelift takes a good 60us for the tiniest block, longer than many short runs take in the interpreter
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    AbiParam, Block, InstBuilder, MemFlagsData, UserFuncName, Value, types,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+
+use crate::operations::sign_extend;
+use crate::{DEVICE_PAGE, Flags, MEM_MAX, Operations, Registers};
+
+/// Times an address is executed by the interpreter before the block starting there is compiled
+const HOT_THRESHOLD: u32 = 64;
+/// Longest block compiled, also how far back a memory write looks for the blocks it breaks
+const MAX_BLOCK_LENGTH: usize = 64;
+/// Hotness of an address whose block can't be compiled, so it is never tried again
+const NOT_COMPILABLE: u32 = u32::MAX;
+/// Most blocks kept in [`CODE`], a program rewriting its loops over and over doesn't make it grow past that
+const MAX_SHARED_BLOCKS: usize = 1 << 14;
+
+/// The code compiled in the process, `None` until the first machine enables the JIT. Never freed, the machines keep
+/// pointers into it
+static CODE: Mutex<Option<Code>> = Mutex::new(None);
+
+/// A compiled block: gets the registers (R0-R7, PC and flags, as in `State`) and the amount of instructions it may run.
+/// Returns the instructions it ran, always at least the length of the block and never more than the budget
+/// once it loops
+type BlockFunction = unsafe extern "C" fn(*mut u16, u64) -> u64;
+
+#[derive(Clone, Copy)]
+struct CompiledBlock {
+    function: BlockFunction,
+    /// Instructions in the block, it is only entered if the budget has room for all of them
+    length: u16,
+}
+
+/// The blocks of every machine, see [`CODE`]
+struct Code {
+    module: JITModule,
+    context: FunctionBuilderContext,
+    /// By the address of the block and its words
+    blocks: HashMap<(usize, Vec<u16>), BlockFunction>,
+}
+
+/// The compiled blocks of a [`crate::State`], see [`crate::State::enable_jit`]
+pub(crate) struct Jit {
+    blocks: Vec<Option<CompiledBlock>>,
+    hotness: Vec<u32>,
+    /// Addresses that are part of a compiled block, a write to one of them has to look for the blocks it breaks
+    compiled_words: Vec<bool>,
+}
+
+impl Jit {
+    /// `None` if cranelift doesn't support this machine
+    pub(crate) fn new() -> Option<Jit> {
+        let mut code = CODE.lock().unwrap();
+        if code.is_none() {
+            *code = Some(Code::new()?);
+        }
+        Some(Jit {
+            blocks: vec![None; MEM_MAX],
+            hotness: vec![0; MEM_MAX],
+            compiled_words: vec![false; MEM_MAX],
+        })
+    }
+
+    /// Run the block at the PC if it is compiled, compiling it first if the address just became hot.
+    /// Returns the instructions it ran, `None` if the interpreter has to execute the next instruction
+    #[inline]
+    pub(crate) fn run(
+        &mut self,
+        registers: &mut [u16; Registers::InstRet as usize],
+        memory: &[u16; MEM_MAX],
+        budget: u64,
+    ) -> Option<u64> {
+        let pc = registers[Registers::Pc] as usize;
+        if self.blocks[pc].is_none() {
+            let hotness = &mut self.hotness[pc];
+            if *hotness == NOT_COMPILABLE {
+                return None;
+            }
+            *hotness += 1;
+            if *hotness < HOT_THRESHOLD {
+                return None;
+            }
+            self.compile(pc, memory)?;
+        }
+        self.run_compiled(registers, budget)
+    }
+
+    /// Like [`Jit::run`] without counting the address, `None` if its block isn't compiled yet
+    #[inline]
+    pub(crate) fn run_compiled(
+        &self,
+        registers: &mut [u16; Registers::InstRet as usize],
+        budget: u64,
+    ) -> Option<u64> {
+        let block = self.blocks[registers[Registers::Pc] as usize]?;
+        if block.length as u64 > budget {
+            return None;
+        }
+        // The block only reads and writes the ten registers it is given
+        Some(unsafe { (block.function)(registers.as_mut_ptr(), budget) })
+    }
+
+    /// Drop the blocks that compiled the word at the address
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        if !self.compiled_words[address] {
+            return;
+        }
+        for start in address.saturating_sub(MAX_BLOCK_LENGTH - 1)..=address {
+            if let Some(block) = self.blocks[start]
+                && start + block.length as usize > address
+            {
+                self.blocks[start] = None;
+                self.hotness[start] = 0;
+            }
+        }
+    }
+
+    fn compile(&mut self, start: usize, memory: &[u16; MEM_MAX]) -> Option<CompiledBlock> {
+        let words = block_words(start, memory);
+        if words.is_empty() {
+            self.hotness[start] = NOT_COMPILABLE;
+            return None;
+        }
+        let function = CODE
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|code| code.get_or_build(start, &words));
+        let Some(function) = function else {
+            self.hotness[start] = NOT_COMPILABLE;
+            return None;
+        };
+        let block = CompiledBlock {
+            function,
+            length: words.len() as u16,
+        };
+        self.blocks[start] = Some(block);
+        self.compiled_words[start..start + words.len()].fill(true);
+        Some(block)
+    }
+}
+
+impl Code {
+    fn new() -> Option<Code> {
+        let mut flags = settings::builder();
+        // The blocks are short and straight, optimizing them costs far more than it saves
+        flags.set("opt_level", "none").ok()?;
+        // They are built the same way every time too, checking them costs more than compiling them
+        flags.set("enable_verifier", "false").ok()?;
+        let isa = cranelift_native::builder()
+            .ok()?
+            .finish(settings::Flags::new(flags))
+            .ok()?;
+        Some(Code {
+            module: JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())),
+            context: FunctionBuilderContext::new(),
+            blocks: HashMap::new(),
+        })
+    }
+
+    fn get_or_build(&mut self, start: usize, words: &[u16]) -> Option<BlockFunction> {
+        let key = (start, words.to_vec());
+        if let Some(function) = self.blocks.get(&key) {
+            return Some(*function);
+        }
+        if self.blocks.len() >= MAX_SHARED_BLOCKS {
+            return None;
+        }
+        let function = self.build(start, words)?;
+        self.blocks.insert(key, function);
+        Some(function)
+    }
+
+    fn build(&mut self, start: usize, words: &[u16]) -> Option<BlockFunction> {
+        let module = &mut self.module;
+        let pointer = module.target_config().pointer_type();
+        let mut context = module.make_context();
+        context.func.signature.params.push(AbiParam::new(pointer));
+        context
+            .func
+            .signature
+            .params
+            .push(AbiParam::new(types::I64));
+        context
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(types::I64));
+        let id = module
+            .declare_anonymous_function(&context.func.signature)
+            .ok()?;
+        context.func.name = UserFuncName::user(0, id.as_u32());
+
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut self.context);
+        let registers: [Variable; Registers::InstRet as usize] =
+            std::array::from_fn(|_| builder.declare_var(types::I16));
+        // The value the flags were last set from, they are only worked out when the block exits
+        let result = builder.declare_var(types::I16);
+        let executed = builder.declare_var(types::I64);
+        let (read, written) = used_registers(words);
+        // Every instruction but the BR sets the flags, a BR alone tests the ones it is given
+        let sets_flags = words.len() > 1 || words[0] >> 12 != Operations::Br as u16;
+
+        let entry = builder.create_block();
+        let body = builder.create_block();
+        let exit = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let registers_pointer = builder.block_params(entry)[0];
+        let budget = builder.block_params(entry)[1];
+        for (index, register) in registers.iter().enumerate() {
+            let loaded = match index {
+                0..8 => read[index] || written[index],
+                _ => index == Registers::Flags as usize && !sets_flags,
+            };
+            if loaded {
+                let value = builder.ins().load(
+                    types::I16,
+                    MemFlagsData::trusted(),
+                    registers_pointer,
+                    (index * 2) as i32,
+                );
+                builder.def_var(*register, value);
+            }
+        }
+        let length = words.len() as i64;
+        // Another pass fits if it has run at most this many instructions, the block is only entered with room for one
+        let last_pass = builder.ins().iadd_imm_s(budget, -length);
+        let zero = builder.ins().iconst(types::I64, 0);
+        builder.def_var(executed, zero);
+        let zero = builder.ins().iconst(types::I16, 0);
+        builder.def_var(result, zero);
+        builder.ins().jump(body, &[]);
+
+        builder.switch_to_block(body);
+        let count = builder.use_var(executed);
+        let count = builder.ins().iadd_imm_s(count, length);
+        builder.def_var(executed, count);
+        for (index, &word) in words.iter().enumerate() {
+            // The PC the instruction sees, already pointing at the next one
+            let next = (start + index + 1) as u16;
+            let value = match Operations::try_from(word >> 12).ok()? {
+                Operations::Add | Operations::And => {
+                    let source = builder.use_var(registers[((word >> 6) & 0x7) as usize]);
+                    let operand = match (word >> 5) & 0x1 {
+                        1 => builder
+                            .ins()
+                            .iconst(types::I16, sign_extend(word & 0x1F, 5) as i16 as i64),
+                        _ => builder.use_var(registers[(word & 0x7) as usize]),
+                    };
+                    match word >> 12 == Operations::Add as u16 {
+                        true => builder.ins().iadd(source, operand),
+                        false => builder.ins().band(source, operand),
+                    }
+                }
+                Operations::Not => {
+                    let source = builder.use_var(registers[((word >> 6) & 0x7) as usize]);
+                    builder.ins().bnot(source)
+                }
+                Operations::Lea => {
+                    let address = next.wrapping_add(sign_extend(word & 0x1FF, 9));
+                    builder.ins().iconst(types::I16, address as i16 as i64)
+                }
+                Operations::Br => {
+                    let target = next.wrapping_add(sign_extend(word & 0x1FF, 9));
+                    let condition = match sets_flags {
+                        true => {
+                            let value = builder.use_var(result);
+                            tested(&mut builder, word, value)
+                        }
+                        // A single flag is always set
+                        false => match (word >> 9) & 0x7 {
+                            0 => Condition::Never,
+                            0x7 => Condition::Always,
+                            mask => {
+                                let flags = builder.use_var(registers[Registers::Flags as usize]);
+                                let tested = builder.ins().band_imm_u(flags, mask as i64);
+                                Condition::When(builder.ins().icmp_imm_s(
+                                    IntCC::NotEqual,
+                                    tested,
+                                    0,
+                                ))
+                            }
+                        },
+                    };
+                    // Loop natively while the budget has room for another pass
+                    let again = (target as usize == start).then(|| builder.create_block());
+                    let taken_exit = builder.create_block();
+                    let not_taken = builder.create_block();
+                    let taken = again.unwrap_or(taken_exit);
+                    match condition {
+                        Condition::Always => builder.ins().jump(taken, &[]),
+                        Condition::Never => builder.ins().jump(not_taken, &[]),
+                        Condition::When(taken_if) => {
+                            builder.ins().brif(taken_if, taken, &[], not_taken, &[])
+                        }
+                    };
+                    if let Some(again) = again {
+                        builder.switch_to_block(again);
+                        let count = builder.use_var(executed);
+                        let fits =
+                            builder
+                                .ins()
+                                .icmp(IntCC::SignedLessThanOrEqual, count, last_pass);
+                        builder.ins().brif(fits, body, &[], taken_exit, &[]);
+                    }
+                    // Only the ways out the block can take, a block nothing jumps to would still be compiled
+                    if again.is_some() || !matches!(condition, Condition::Never) {
+                        exit_at(&mut builder, &registers, taken_exit, exit, target);
+                    }
+                    if !matches!(condition, Condition::Always) {
+                        exit_at(&mut builder, &registers, not_taken, exit, next);
+                    }
+                    break;
+                }
+                _ => return None,
+            };
+            builder.def_var(registers[((word >> 9) & 0x7) as usize], value);
+            builder.def_var(result, value);
+            if index == words.len() - 1 {
+                let pc = builder.ins().iconst(types::I16, next as i16 as i64);
+                builder.def_var(registers[Registers::Pc as usize], pc);
+                builder.ins().jump(exit, &[]);
+            }
+        }
+
+        builder.switch_to_block(exit);
+        if sets_flags {
+            let value = builder.use_var(result);
+            let flags = flags_of(&mut builder, value);
+            builder.def_var(registers[Registers::Flags as usize], flags);
+        }
+        for (index, register) in registers.iter().enumerate() {
+            let stored = match index {
+                0..8 => written[index],
+                _ => index == Registers::Pc as usize || sets_flags,
+            };
+            if stored {
+                let value = builder.use_var(*register);
+                builder.ins().store(
+                    MemFlagsData::trusted(),
+                    value,
+                    registers_pointer,
+                    (index * 2) as i32,
+                );
+            }
+        }
+        let count = builder.use_var(executed);
+        builder.ins().return_(&[count]);
+        builder.seal_all_blocks();
+        builder.finalize(module.target_config());
+
+        module.define_function(id, &mut context).ok()?;
+        module.clear_context(&mut context);
+        module.finalize_definitions().ok()?;
+        let code = module.get_finalized_function(id);
+        // The signature declared above is the one of `BlockFunction`
+        Some(unsafe { std::mem::transmute::<*const u8, BlockFunction>(code) })
+    }
+}
+
+/// The words of the block starting at the address: the instructions that only touch registers,
+/// up to and including the first BR. Empty if the first one already needs the interpreter
+fn block_words(start: usize, memory: &[u16; MEM_MAX]) -> Vec<u16> {
+    let mut words = Vec::new();
+    let end = (start + MAX_BLOCK_LENGTH).min(DEVICE_PAGE).max(start);
+    for &word in &memory[start..end] {
+        match Operations::try_from(word >> 12) {
+            Ok(Operations::Add | Operations::And | Operations::Not | Operations::Lea) => {
+                words.push(word)
+            }
+            Ok(Operations::Br) => {
+                words.push(word);
+                break;
+            }
+            _ => break,
+        }
+    }
+    words
+}
+
+/// The registers R0-R7 the instructions of a block read, and those they write
+fn used_registers(words: &[u16]) -> ([bool; 8], [bool; 8]) {
+    let (mut read, mut written) = ([false; 8], [false; 8]);
+    for &word in words {
+        let operation = word >> 12;
+        if operation == Operations::Br as u16 {
+            continue;
+        }
+        written[((word >> 9) & 0x7) as usize] = true;
+        if operation != Operations::Lea as u16 {
+            read[((word >> 6) & 0x7) as usize] = true;
+        }
+        if operation != Operations::Not as u16 && (word >> 5) & 0x1 == 0 {
+            read[(word & 0x7) as usize] = true;
+        }
+    }
+    (read, written)
+}
+
+/// Whether a BR is taken, known when the block is compiled for the masks testing every flag or none
+enum Condition {
+    Always,
+    Never,
+    When(Value),
+}
+
+/// Whether the BR `word` is taken after an instruction that set the flags from `value`, without working them out
+fn tested(builder: &mut FunctionBuilder, word: u16, value: Value) -> Condition {
+    let n = Flags::Neg as u16;
+    let z = Flags::Zro as u16;
+    let p = Flags::Pos as u16;
+    let compared = match (word >> 9) & 0x7 {
+        0 => return Condition::Never,
+        mask if mask == n | z | p => return Condition::Always,
+        mask if mask == n => IntCC::SignedLessThan,
+        mask if mask == z => IntCC::Equal,
+        mask if mask == p => IntCC::SignedGreaterThan,
+        mask if mask == n | z => IntCC::SignedLessThanOrEqual,
+        mask if mask == z | p => IntCC::SignedGreaterThanOrEqual,
+        _ => IntCC::NotEqual,
+    };
+    Condition::When(builder.ins().icmp_imm_s(compared, value, 0))
+}
+
+/// The flags an instruction leaving `value` in its destination sets
+fn flags_of(builder: &mut FunctionBuilder, value: Value) -> Value {
+    let is_zero = builder.ins().icmp_imm_s(IntCC::Equal, value, 0);
+    let is_negative = builder.ins().icmp_imm_s(IntCC::SignedLessThan, value, 0);
+    let zero = builder.ins().iconst(types::I16, Flags::Zro as i64);
+    let negative = builder.ins().iconst(types::I16, Flags::Neg as i64);
+    let positive = builder.ins().iconst(types::I16, Flags::Pos as i64);
+    let sign = builder.ins().select(is_negative, negative, positive);
+    builder.ins().select(is_zero, zero, sign)
+}
+
+/// Fill `block` with leaving the block at `pc`
+fn exit_at(
+    builder: &mut FunctionBuilder,
+    registers: &[Variable; Registers::InstRet as usize],
+    block: Block,
+    exit: Block,
+    pc: u16,
+) {
+    builder.switch_to_block(block);
+    let pc = builder.ins().iconst(types::I16, pc as i16 as i64);
+    builder.def_var(registers[Registers::Pc as usize], pc);
+    builder.ins().jump(exit, &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Registers;
+    use crate::test_util::TestVm;
+
+    const REGISTERS: [Registers; 10] = [
+        Registers::R0,
+        Registers::R1,
+        Registers::R2,
+        Registers::R3,
+        Registers::R4,
+        Registers::R5,
+        Registers::R6,
+        Registers::R7,
+        Registers::Pc,
+        Registers::Flags,
+    ];
+
+    /// The registers after running the program with or without the JIT
+    fn run(source: &str, jit: bool) -> [u16; 10] {
+        let mut vm = TestVm::with_snippet(source).budget(1_000_000);
+        if jit {
+            vm.state_mut().enable_jit();
+        }
+        vm.run().unwrap();
+        REGISTERS.map(|register| vm.state().register_read(register))
+    }
+
+    fn assert_same_registers(source: &str) {
+        assert_eq!(run(source, true), run(source, false));
+    }
+
+    #[test]
+    fn counting_loop_matches_the_interpreter() {
+        assert_same_registers(
+            ".ORIG x3000\nLD R1, COUNT\nLOOP ADD R2, R2, #3\nNOT R3, R2\nAND R4, R3, R1\nADD R1, R1, #-1\nBRp LOOP\nHALT\nCOUNT .FILL #5000\n.END",
+        );
+    }
+
+    #[test]
+    fn loops_stop_when_the_budget_runs_out() {
+        let source = ".ORIG x3000\nLOOP ADD R1, R1, #1\nBRnzp LOOP\n.END";
+        let mut vm = TestVm::with_snippet(source).budget(10_001);
+        vm.state_mut().enable_jit();
+        assert!(vm.run().is_err());
+        // Two instructions a pass, the last pass only ran its ADD
+        assert_eq!(vm.state_mut().register_read(Registers::R1), 5_001);
+    }
+
+    #[test]
+    fn writing_a_compiled_word_drops_its_block() {
+        // After 100 passes the loop rewrites its own ADD #1 into ADD #2
+        assert_same_registers(
+            ".ORIG x3000\nLD R1, COUNT\nLOOP ADD R2, R2, #1\nADD R1, R1, #-1\nBRz PATCH\nBRnzp LOOP\nPATCH LD R3, NEW\nST R3, LOOP\nLD R1, COUNT\nADD R5, R5, #1\nADD R6, R5, #-2\nBRn LOOP\nHALT\nCOUNT .FILL #100\nNEW ADD R2, R2, #2\n.END",
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! What `--keymap` does: the special keys a terminal sends as escape sequences, like `ESC [ A` for the up arrow, reach
+//! the program as a single byte of its choice instead, a GETC per key:
+//! ```text
+//! --keymap up=x11,down=x12,left=x13,right=x14
+//! ```
+//! The keys are named after [`SpecialKey`], each taking every sequence terminals send for it. A key left out of the map
+//! keeps its sequence, like every key without `--keymap`.
+//!
+//! [`TranslatedInput`] matches the bytes as they arrive without ever waiting on a clock: a byte that can't continue a
+//! sequence of the map lets the bytes held so far through as they were. An Escape on its own is held until the next
+//! key tells whether it started a sequence, what is held when the input ends is left in [`Input::take_pending`]
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::Error;
+use crate::address::{SymbolTable, parse_address};
+use crate::console::{Input, KeyWait};
+
+/// The keys with a name in a keymap, see [`SpecialKey::sequences`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecialKey {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+impl SpecialKey {
+    const ALL: [SpecialKey; 22] = [
+        SpecialKey::Up,
+        SpecialKey::Down,
+        SpecialKey::Right,
+        SpecialKey::Left,
+        SpecialKey::Home,
+        SpecialKey::End,
+        SpecialKey::Insert,
+        SpecialKey::Delete,
+        SpecialKey::PageUp,
+        SpecialKey::PageDown,
+        SpecialKey::F1,
+        SpecialKey::F2,
+        SpecialKey::F3,
+        SpecialKey::F4,
+        SpecialKey::F5,
+        SpecialKey::F6,
+        SpecialKey::F7,
+        SpecialKey::F8,
+        SpecialKey::F9,
+        SpecialKey::F10,
+        SpecialKey::F11,
+        SpecialKey::F12,
+    ];
+
+    /// `up`, `pageup`, `f5`: the name of the variant in lowercase
+    pub fn name(self) -> &'static str {
+        match self {
+            SpecialKey::Up => "up",
+            SpecialKey::Down => "down",
+            SpecialKey::Right => "right",
+            SpecialKey::Left => "left",
+            SpecialKey::Home => "home",
+            SpecialKey::End => "end",
+            SpecialKey::Insert => "insert",
+            SpecialKey::Delete => "delete",
+            SpecialKey::PageUp => "pageup",
+            SpecialKey::PageDown => "pagedown",
+            SpecialKey::F1 => "f1",
+            SpecialKey::F2 => "f2",
+            SpecialKey::F3 => "f3",
+            SpecialKey::F4 => "f4",
+            SpecialKey::F5 => "f5",
+            SpecialKey::F6 => "f6",
+            SpecialKey::F7 => "f7",
+            SpecialKey::F8 => "f8",
+            SpecialKey::F9 => "f9",
+            SpecialKey::F10 => "f10",
+            SpecialKey::F11 => "f11",
+            SpecialKey::F12 => "f12",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SpecialKey> {
+        SpecialKey::ALL
+            .into_iter()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+    }
+
+    /// What xterm-like terminals send for the key, in normal and in application cursor mode. Those of the arrows, Home
+    /// and End are the ones [`crate::terminal`] gives on every platform
+    pub fn sequences(self) -> &'static [&'static [u8]] {
+        match self {
+            SpecialKey::Up => &[b"\x1b[A", b"\x1bOA"],
+            SpecialKey::Down => &[b"\x1b[B", b"\x1bOB"],
+            SpecialKey::Right => &[b"\x1b[C", b"\x1bOC"],
+            SpecialKey::Left => &[b"\x1b[D", b"\x1bOD"],
+            SpecialKey::Home => &[b"\x1b[H", b"\x1bOH", b"\x1b[1~", b"\x1b[7~"],
+            SpecialKey::End => &[b"\x1b[F", b"\x1bOF", b"\x1b[4~", b"\x1b[8~"],
+            SpecialKey::Insert => &[b"\x1b[2~"],
+            SpecialKey::Delete => &[b"\x1b[3~"],
+            SpecialKey::PageUp => &[b"\x1b[5~"],
+            SpecialKey::PageDown => &[b"\x1b[6~"],
+            SpecialKey::F1 => &[b"\x1bOP", b"\x1b[11~"],
+            SpecialKey::F2 => &[b"\x1bOQ", b"\x1b[12~"],
+            SpecialKey::F3 => &[b"\x1bOR", b"\x1b[13~"],
+            SpecialKey::F4 => &[b"\x1bOS", b"\x1b[14~"],
+            SpecialKey::F5 => &[b"\x1b[15~"],
+            SpecialKey::F6 => &[b"\x1b[17~"],
+            SpecialKey::F7 => &[b"\x1b[18~"],
+            SpecialKey::F8 => &[b"\x1b[19~"],
+            SpecialKey::F9 => &[b"\x1b[20~"],
+            SpecialKey::F10 => &[b"\x1b[21~"],
+            SpecialKey::F11 => &[b"\x1b[23~"],
+            SpecialKey::F12 => &[b"\x1b[24~"],
+        }
+    }
+}
+
+/// The byte each special key of a map becomes, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keymap {
+    /// Every sequence of the keys in the map, with the byte it becomes
+    sequences: Vec<(&'static [u8], u8)>,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap::default()
+    }
+
+    /// `up=x11,down=x12`, the keys named like [`SpecialKey::name`] and the bytes written like addresses, in
+    /// hexadecimal with an `x` or `0x` or in decimal
+    pub fn parse(text: &str) -> Result<Keymap, Error> {
+        let bad = || Error::BadArgument(text.to_string());
+        let mut keymap = Keymap::new();
+        for entry in text.split(',') {
+            let (name, byte) = entry.split_once('=').ok_or_else(bad)?;
+            let key = SpecialKey::from_name(name.trim()).ok_or_else(bad)?;
+            let byte = parse_address(byte.trim(), &SymbolTable::new())?;
+            keymap.map(key, u8::try_from(byte).map_err(|_| bad())?);
+        }
+        Ok(keymap)
+    }
+
+    /// Make `key` arrive as `byte`, instead of what it was given before if it was in the map already
+    pub fn map(&mut self, key: SpecialKey, byte: u8) {
+        let sequences = key.sequences();
+        self.sequences
+            .retain(|(sequence, _)| !sequences.contains(sequence));
+        self.sequences
+            .extend(sequences.iter().map(|&sequence| (sequence, byte)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// What `held` is to the map: a whole sequence, the start of one or neither
+    fn lookup(&self, held: &[u8]) -> Match {
+        let mut partial = false;
+        for (sequence, byte) in &self.sequences {
+            if *sequence == held {
+                return Match::Whole(*byte);
+            }
+            partial |= sequence.starts_with(held);
+        }
+        match partial {
+            true => Match::Partial,
+            false => Match::None,
+        }
+    }
+}
+
+enum Match {
+    Whole(u8),
+    Partial,
+    None,
+}
+
+/// Another input with the special keys of a [`Keymap`] translated, what `--keymap` puts in front of the keyboard. See
+/// [`crate::State::translate_keys`]
+pub struct TranslatedInput {
+    input: Box<dyn Input + Send>,
+    keymap: Keymap,
+    /// The bytes of the sequence begun so far
+    held: Vec<u8>,
+    /// What the program gets next, translated or let through
+    ready: VecDeque<u8>,
+}
+
+impl TranslatedInput {
+    pub fn new(input: Box<dyn Input + Send>, keymap: Keymap) -> TranslatedInput {
+        TranslatedInput {
+            input,
+            keymap,
+            held: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Take a byte from the input, in what it held it can complete a sequence, continue one or end it
+    fn receive(&mut self, byte: u8) {
+        self.held.push(byte);
+        while !self.held.is_empty() {
+            match self.keymap.lookup(&self.held) {
+                Match::Whole(translated) => {
+                    self.ready.push_back(translated);
+                    self.held.clear();
+                }
+                Match::Partial => return,
+                // The first byte goes as it was, the rest may still start a sequence
+                Match::None => {
+                    let first = self.held.remove(0);
+                    self.ready.push_back(first);
+                }
+            }
+        }
+    }
+}
+
+impl Input for TranslatedInput {
+    /// `None` when the input ended or an interrupt woke it up, keeping what it held in case it was the interrupt
+    fn read_byte(&mut self) -> Option<u8> {
+        while self.ready.is_empty() {
+            let byte = self.input.read_byte()?;
+            self.receive(byte);
+        }
+        self.ready.pop_front()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        while self.ready.is_empty() {
+            let byte = self.input.poll_byte()?;
+            self.receive(byte);
+        }
+        self.ready.pop_front()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        while self.ready.is_empty() {
+            match self.input.read_byte_timeout(timeout) {
+                KeyWait::Key(byte) => self.receive(byte),
+                wait => return wait,
+            }
+        }
+        KeyWait::from(self.ready.pop_front())
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        let mut pending: Vec<u8> = self.ready.drain(..).chain(self.held.drain(..)).collect();
+        pending.extend(self.input.take_pending());
+        pending
+    }
+
+    fn keys_dropped(&self) -> Option<u64> {
+        self.input.keys_dropped()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the program reads, and what is left held once the input ended
+    fn translated(keymap: &str, bytes: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let input = Box::new(VecDeque::from(bytes.to_vec()));
+        let mut input = TranslatedInput::new(input, Keymap::parse(keymap).unwrap());
+        let read = std::iter::from_fn(|| input.read_byte()).collect();
+        (read, input.take_pending())
+    }
+
+    #[test]
+    fn every_sequence_of_a_key_in_the_map_becomes_its_byte() {
+        assert_eq!(
+            translated(
+                "up=x11,down=0x12,home=1,f5=x20",
+                b"a\x1b[A\x1bOB\x1b[H\x1b[1~\x1b[15~b"
+            ),
+            (b"a\x11\x12\x01\x01\x20b".to_vec(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn what_doesnt_continue_a_sequence_goes_through_as_it_was() {
+        // Left isn't in the map, an Escape on its own isn't a sequence and a second Escape starts one again
+        assert_eq!(
+            translated("up=x11", b"\x1b[D\x1bx\x1b\x1b[A\x1b["),
+            (b"\x1b[D\x1bx\x1b\x11".to_vec(), b"\x1b[".to_vec())
+        );
+    }
+
+    #[test]
+    fn polling_holds_the_start_of_a_sequence_until_it_is_decided() {
+        let mut input =
+            TranslatedInput::new(Box::new(VecDeque::new()), Keymap::parse("up=x11").unwrap());
+        let mut bytes = VecDeque::from(b"\x1b[".to_vec());
+        while let Some(byte) = bytes.pop_front() {
+            input.receive(byte);
+        }
+        assert_eq!(input.poll_byte(), None);
+        input.receive(b'A');
+        assert_eq!(input.poll_byte(), Some(0x11));
+        input.receive(0x1b);
+        assert_eq!(input.take_pending(), b"\x1b");
+    }
+
+    #[test]
+    fn a_bad_keymap_is_refused() {
+        for text in ["up", "north=x11", "up=x100", "up=x11,"] {
+            assert!(Keymap::parse(text).is_err(), "{}", text);
+        }
+        assert_eq!(SpecialKey::from_name("PageUp"), Some(SpecialKey::PageUp));
+    }
+}
@@ -0,0 +1,245 @@
+//! The address space of a run as it will be before the first instruction, what `--layout-check` prints and checks:
+//! the segments of the images, the stack of `--stack`, the block of `--arg` and `--env` (see [`crate::arguments`]), the
+//! device page, the vector tables and the operating system below x3000, and the entry point.
+//!
+//! [`LoadMap::validate`] finds what can't work out, each a [`Warning::Layout`] (W005) that goes through the warning
+//! system like the others: printed, allowed with `--allow W005` or made an error with `--deny W005`:
+//! * the stack overlapping a segment, a push would overwrite the program
+//! * the block of arguments overlapping a segment
+//! * the entry point outside every segment, the first instruction wasn't loaded
+//! * a segment in the device page, its words go to the device registers
+//!
+//! `batch` checks the layout of every job too
+
+use std::fmt::{self, Write as _};
+
+use crate::determinism::RunConfig;
+use crate::warning::Warning;
+use crate::{DEVICE_PAGE, MEM_MAX};
+
+/// The vector tables and the operating system, what is below the programs
+const SYSTEM_AREAS: [(u16, u16, &str); 3] = [
+    (0x0000, 0x00FF, "trap vectors"),
+    (0x0100, 0x01FF, "interrupt vectors"),
+    (0x0200, 0x2FFF, "operating system"),
+];
+
+/// Something in the layout that can't work out, see the module documentation. The ranges are inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LayoutConflict {
+    StackOverlapsCode {
+        stack: (u16, u16),
+        segment: (u16, u16),
+    },
+    ArgumentsOverlapCode {
+        arguments: (u16, u16),
+        segment: (u16, u16),
+    },
+    EntryOutsideSegments(u16),
+    SegmentInDevicePage((u16, u16)),
+}
+
+impl fmt::Display for LayoutConflict {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutConflict::StackOverlapsCode { stack, segment } => write!(
+                formatter,
+                "the stack at x{:04X}-x{:04X} overlaps the segment at x{:04X}-x{:04X}",
+                stack.0, stack.1, segment.0, segment.1
+            ),
+            LayoutConflict::ArgumentsOverlapCode { arguments, segment } => write!(
+                formatter,
+                "the arguments at x{:04X}-x{:04X} overlap the segment at x{:04X}-x{:04X}",
+                arguments.0, arguments.1, segment.0, segment.1
+            ),
+            LayoutConflict::EntryOutsideSegments(entry) => write!(
+                formatter,
+                "the entry point x{:04X} is outside every segment",
+                entry
+            ),
+            LayoutConflict::SegmentInDevicePage(segment) => write!(
+                formatter,
+                "the segment at x{:04X}-x{:04X} reaches the device page",
+                segment.0, segment.1
+            ),
+        }
+    }
+}
+
+/// The segments loaded and where the program starts, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadMap {
+    /// Inclusive, in the order they are loaded
+    segments: Vec<(u16, u16)>,
+    entry: u16,
+}
+
+impl LoadMap {
+    pub fn new(entry: u16) -> LoadMap {
+        LoadMap {
+            segments: Vec::new(),
+            entry,
+        }
+    }
+
+    /// The map of the sections of images, like [`crate::file_management::read_sections`] gives them
+    pub fn from_sections(sections: &[(u16, Vec<u16>)], entry: u16) -> LoadMap {
+        let mut map = LoadMap::new(entry);
+        for (origin, words) in sections {
+            map.add_segment(*origin, words.len());
+        }
+        map
+    }
+
+    /// `length` words from `start` on, nothing for none
+    pub fn add_segment(&mut self, start: u16, length: usize) {
+        if length > 0 {
+            let end = (start as usize + length - 1).min(MEM_MAX - 1);
+            self.segments.push((start, end as u16));
+        }
+    }
+
+    pub fn segments(&self) -> &[(u16, u16)] {
+        &self.segments
+    }
+
+    pub fn entry(&self) -> u16 {
+        self.entry
+    }
+
+    /// Every conflict of the map with the stack and the arguments of `config`, see the module documentation
+    pub fn validate(&self, config: &RunConfig) -> Vec<Warning> {
+        let mut conflicts = Vec::new();
+        for &segment in &self.segments {
+            if let Some(stack) = config.stack.filter(|&stack| overlap(stack, segment)) {
+                conflicts.push(LayoutConflict::StackOverlapsCode { stack, segment });
+            }
+            if let Some(arguments) = config
+                .arguments
+                .filter(|&arguments| overlap(arguments, segment))
+            {
+                conflicts.push(LayoutConflict::ArgumentsOverlapCode { arguments, segment });
+            }
+        }
+        if !self
+            .segments
+            .iter()
+            .any(|&(start, end)| (start..=end).contains(&self.entry))
+        {
+            conflicts.push(LayoutConflict::EntryOutsideSegments(self.entry));
+        }
+        for &segment in &self.segments {
+            if segment.1 as usize >= DEVICE_PAGE {
+                conflicts.push(LayoutConflict::SegmentInDevicePage(segment));
+            }
+        }
+        conflicts.into_iter().map(Warning::Layout).collect()
+    }
+
+    /// A line per region by address, then the entry point:
+    /// ```text
+    /// Layout:
+    ///   x0000-x00FF  trap vectors
+    ///   x3000-x3010  segment
+    ///   xFE00-xFFFF  device page
+    ///   entry x3000
+    /// ```
+    pub fn table(&self, config: &RunConfig) -> String {
+        let mut regions: Vec<(u16, u16, &str)> = SYSTEM_AREAS.to_vec();
+        regions.extend(
+            self.segments
+                .iter()
+                .map(|&(start, end)| (start, end, "segment")),
+        );
+        regions.extend(config.stack.map(|(start, end)| (start, end, "stack")));
+        regions.extend(
+            config
+                .arguments
+                .map(|(start, end)| (start, end, "arguments")),
+        );
+        regions.push((DEVICE_PAGE as u16, (MEM_MAX - 1) as u16, "device page"));
+        regions.sort_by_key(|&(start, end, _)| (start, end));
+        let mut table = "Layout:\n".to_string();
+        for (start, end, name) in regions {
+            let _ = writeln!(table, "  x{:04X}-x{:04X}  {}", start, end, name);
+        }
+        let _ = writeln!(table, "  entry x{:04X}", self.entry);
+        table
+    }
+}
+
+fn overlap((start, end): (u16, u16), (other_start, other_end): (u16, u16)) -> bool {
+    start <= other_end && other_start <= end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflicts(map: &LoadMap, config: &RunConfig) -> Vec<LayoutConflict> {
+        map.validate(config)
+            .into_iter()
+            .map(|warning| match warning {
+                Warning::Layout(conflict) => conflict,
+                warning => panic!("{:?}", warning),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_layout_that_works_out_has_no_conflict() {
+        let mut map = LoadMap::new(0x3000);
+        map.add_segment(0x3000, 0x20);
+        let mut config = RunConfig::new(false);
+        config.stack = Some((0xE000, 0xEFFF));
+        config.arguments = Some((0xDFF0, 0xDFFF));
+        assert!(map.validate(&config).is_empty());
+        assert_eq!(
+            map.table(&config),
+            "Layout:\n  x0000-x00FF  trap vectors\n  x0100-x01FF  interrupt vectors\n  \
+             x0200-x2FFF  operating system\n  x3000-x301F  segment\n  xDFF0-xDFFF  arguments\n  \
+             xE000-xEFFF  stack\n  xFE00-xFFFF  device page\n  entry x3000\n"
+        );
+    }
+
+    #[test]
+    fn the_stack_and_the_arguments_overlapping_code_are_found() {
+        let map =
+            LoadMap::from_sections(&[(0x3000, vec![0; 0x10]), (0x4000, vec![0; 0x10])], 0x3000);
+        let mut config = RunConfig::new(false);
+        config.stack = Some((0x3008, 0x3FFF));
+        config.arguments = Some((0x4008, 0x4020));
+        assert_eq!(
+            conflicts(&map, &config),
+            [
+                LayoutConflict::StackOverlapsCode {
+                    stack: (0x3008, 0x3FFF),
+                    segment: (0x3000, 0x300F)
+                },
+                LayoutConflict::ArgumentsOverlapCode {
+                    arguments: (0x4008, 0x4020),
+                    segment: (0x4000, 0x400F)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn an_entry_outside_the_segments_and_a_segment_in_the_device_page_are_found() {
+        let mut map = LoadMap::new(0x3000);
+        map.add_segment(0x4000, 0x10);
+        map.add_segment(0xFDF0, 0x20);
+        map.add_segment(0x5000, 0);
+        assert_eq!(
+            conflicts(&map, &RunConfig::new(false)),
+            [
+                LayoutConflict::EntryOutsideSegments(0x3000),
+                LayoutConflict::SegmentInDevicePage((0xFDF0, 0xFE0F)),
+            ]
+        );
+        assert_eq!(
+            Warning::Layout(LayoutConflict::EntryOutsideSegments(0x3000)).to_string(),
+            "the entry point x3000 is outside every segment"
+        );
+    }
+}
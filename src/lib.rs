@@ -0,0 +1,1422 @@
+use assertion::TrapAssertion;
+use bank::Banks;
+use clock::Clock;
+#[cfg(feature = "cli")]
+use console::StdinInput;
+use console::{
+    BufferedOutput, Input, KeyboardBuffer, KeyboardFifo, KeyboardOverflow, MemoryOutput, NoInput,
+};
+use decode::{DecodeCache, Instruction};
+use determinism::RunConfig;
+use device::{Device, MappedDevice};
+pub use error::{Category, Error, LoadError, RuntimeError, TerminalError};
+use fault::Fault;
+use hang::{Hang, HangCheck};
+use host_fs::HostFiles;
+use instrument::{Instrumentation, NoInstrumentation};
+use irq::InterruptController;
+use keymap::{Keymap, TranslatedInput};
+use layout::LoadMap;
+use operations::*;
+use protect::CodeProtection;
+use recording::{Recorder, Replay};
+use self_modify::{SelfModification, SelfModifyCheck};
+use stack::{StackCheck, StackViolation};
+use stats::Stats;
+use std::fmt::Debug;
+use std::io;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut, RangeInclusive};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use trap_table::TrapTable;
+use warning::{Warning, WarningCode, WarningLevel, Warnings};
+use watch_dump::WatchDump;
+use write_log::WriteLog;
+pub mod address;
+pub mod arguments;
+pub mod assembler;
+pub mod assertion;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod bank;
+#[cfg(feature = "cli")]
+pub mod batch;
+pub mod clock;
+pub mod compare;
+pub mod conformance;
+pub mod console;
+mod decode;
+pub mod determinism;
+pub mod device;
+pub mod diag;
+pub mod disassembler;
+mod error;
+pub mod fault;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod file_management;
+pub mod hang;
+pub mod host_fs;
+pub mod instrument;
+pub mod irq;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod keymap;
+pub mod layout;
+mod operations;
+pub mod os;
+pub mod output_file;
+pub mod protect;
+pub mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recording;
+pub mod scheduler;
+pub mod search;
+pub mod segment;
+pub mod self_modify;
+pub mod snapshot;
+pub mod stack;
+pub mod stats;
+pub mod step;
+pub mod summary;
+#[cfg(all(feature = "cli", any(unix, windows)))]
+pub mod terminal;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(test)]
+mod tests;
+pub mod trace;
+pub mod trap_table;
+pub mod warning;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watch_dump;
+pub mod write_log;
+
+static MEM_MAX: usize = 1 << 16;
+static PC_START: u16 = 0x3000;
+/// First address of the device registers, everything below is plain memory
+const DEVICE_PAGE: usize = 0xFE00;
+
+// Special registers that are in memory
+pub enum MemoryMappedRegisters {
+    Kbsr = 0xFE00, // Keyboard Status Register, identifies when a key is pressed
+    Kbdr = 0xFE02, // Keyboard Data Register, identifies what key was pressed
+    Dsr = 0xFE04, // Display Status Register, bit 15 is set when the display takes a character, always
+    Ddr = 0xFE06, // Display Data Register, storing to it prints its low byte
+    Mcr = 0xFFFE, // Machine Control Register, clearing bit 15 stops the machine
+}
+
+/// Bit 15 of DSR and MCR
+const DEVICE_READY: u16 = 1 << 15;
+
+/// Traps are predefined routines, each trap in the enum represents a routine at its vector unless the
+/// [`trap_table::TrapTable`] moves it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Traps {
+    Getc = 0x20,
+    Out = 0x21,
+    Puts = 0x22,
+    In = 0x23,
+    Putsp = 0x24,
+    Halt = 0x25,
+    /// Not a trap of the spec: R0 nonzero passes, R1 points to the message, see [`State::trap_assertions`]
+    Assert = 0x26,
+    /// Not a trap of the spec: halts with the low byte of R0 as the exit code, see [`State::exit_code`]
+    Exit = 0x27,
+    /// Not a trap of the spec: prints the registers to stderr, and the memory at R0 unless it is zero
+    Dbg = 0x28,
+    /// Not a trap of the spec: reads a line into the buffer at R0, of at most R1 characters
+    ReadLine = 0x29,
+    /// Not traps of the spec: the files of the host in the sandbox of [`State::set_host_files`], see [`host_fs`]
+    FileOpen = 0x30,
+    FileRead = 0x31,
+    FileWrite = 0x32,
+    FileClose = 0x33,
+    /// Not a trap of the spec: the milliseconds since the machine started, see [`State::now`]
+    Time = 0x34,
+    /// Not a trap of the spec: sleeps for the milliseconds in R0, see [`clock::Clock::sleep`]
+    Sleep = 0x35,
+}
+
+impl TryFrom<u16> for Traps {
+    type Error = Error;
+    fn try_from(value: u16) -> Result<Traps, Self::Error> {
+        match value {
+            0x20 => Ok(Traps::Getc),
+            0x21 => Ok(Traps::Out),
+            0x22 => Ok(Traps::Puts),
+            0x23 => Ok(Traps::In),
+            0x24 => Ok(Traps::Putsp),
+            0x25 => Ok(Traps::Halt),
+            0x26 => Ok(Traps::Assert),
+            0x27 => Ok(Traps::Exit),
+            0x28 => Ok(Traps::Dbg),
+            0x29 => Ok(Traps::ReadLine),
+            0x30 => Ok(Traps::FileOpen),
+            0x31 => Ok(Traps::FileRead),
+            0x32 => Ok(Traps::FileWrite),
+            0x33 => Ok(Traps::FileClose),
+            0x34 => Ok(Traps::Time),
+            0x35 => Ok(Traps::Sleep),
+            badcode => Err(RuntimeError::BadTrapCode(badcode).into()),
+        }
+    }
+}
+#[derive(Clone, Copy)]
+pub enum Registers {
+    R0,      // Register 0
+    R1,      // Register 1
+    R2,      // Register 2
+    R3,      // Register 3
+    R4,      // Register 4
+    R5,      // Register 5
+    R6,      // Register 6
+    R7,      // Register 7
+    Pc,      // Program Counter
+    Flags,   // Flags
+    InstRet, // Amount of registers
+}
+
+impl Registers {
+    /// The general purpose register in the lowest 3 bits, like the register fields of an instruction.
+    /// Every value maps to a register, so unlike [`Registers::try_from`] it can't fail
+    #[inline]
+    pub fn from_bits(bits: u16) -> Registers {
+        // A match rather than a table, the compiler sees the number is the register and drops it
+        match bits & 0x7 {
+            0 => Registers::R0,
+            1 => Registers::R1,
+            2 => Registers::R2,
+            3 => Registers::R3,
+            4 => Registers::R4,
+            5 => Registers::R5,
+            6 => Registers::R6,
+            _ => Registers::R7,
+        }
+    }
+}
+
+/// For register numbers that come from outside of an instruction, anything but 0 to 7 is an error
+impl TryFrom<u16> for Registers {
+    type Error = Error;
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Registers::R0),
+            1 => Ok(Registers::R1),
+            2 => Ok(Registers::R2),
+            3 => Ok(Registers::R3),
+            4 => Ok(Registers::R4),
+            5 => Ok(Registers::R5),
+            6 => Ok(Registers::R6),
+            7 => Ok(Registers::R7),
+            register => Err(RuntimeError::BadRegisterReference(register).into()),
+        }
+    }
+}
+
+impl<T> Index<MemoryMappedRegisters> for [T; MEM_MAX] {
+    type Output = T;
+    fn index(&self, index: MemoryMappedRegisters) -> &Self::Output {
+        &self[index as usize]
+    }
+}
+
+impl<T> IndexMut<MemoryMappedRegisters> for [T; MEM_MAX] {
+    fn index_mut(&mut self, index: MemoryMappedRegisters) -> &mut Self::Output {
+        &mut self[index as usize]
+    }
+}
+
+impl<T> Index<Registers> for [T; Registers::InstRet as usize] {
+    type Output = T;
+    fn index(&self, index: Registers) -> &Self::Output {
+        &self[index as usize]
+    }
+}
+
+impl<T> IndexMut<Registers> for [T; Registers::InstRet as usize] {
+    fn index_mut(&mut self, index: Registers) -> &mut Self::Output {
+        &mut self[index as usize]
+    }
+}
+
+pub enum Flags {
+    Pos = 1 << 0,
+    Zro = 1 << 1,
+    Neg = 1 << 2,
+}
+
+#[derive(Clone, Copy)]
+pub enum Operations {
+    Br,   // Branch
+    Add,  // Add
+    Ld,   // Load
+    St,   // Store
+    Jsr,  // Jump register
+    And,  // And
+    Ldr,  // Load register
+    Str,  // Store register
+    Rti,  // unused
+    Not,  // Not
+    Ldi,  // Load indirect
+    Sti,  // Store indirect
+    Jmp,  // Jump
+    Res,  // unused
+    Lea,  // Load effective address
+    Trap, // Execute trap
+}
+
+impl TryFrom<u16> for Operations {
+    type Error = Error;
+    fn try_from(value: u16) -> Result<Operations, Self::Error> {
+        match value {
+            0 => Ok(Operations::Br),
+            1 => Ok(Operations::Add),
+            2 => Ok(Operations::Ld),
+            3 => Ok(Operations::St),
+            4 => Ok(Operations::Jsr),
+            5 => Ok(Operations::And),
+            6 => Ok(Operations::Ldr),
+            7 => Ok(Operations::Str),
+            8 => Ok(Operations::Rti),
+            9 => Ok(Operations::Not),
+            10 => Ok(Operations::Ldi),
+            11 => Ok(Operations::Sti),
+            12 => Ok(Operations::Jmp),
+            13 => Ok(Operations::Res),
+            14 => Ok(Operations::Lea),
+            15 => Ok(Operations::Trap),
+            op_code => Err(RuntimeError::BadOpCode(op_code).into()),
+        }
+    }
+}
+
+/// The whole machine: memory, registers and the console the traps and the keyboard registers talk to.
+/// By default the console is the terminal, [`State::set_input`] and [`State::set_output`] replace it
+pub struct State {
+    memory: Box<[u16; MEM_MAX]>,
+    registers: [u16; Registers::InstRet as usize],
+    running: bool,
+    input: Box<dyn Input + Send>,
+    output: Box<dyn Write + Send>,
+    /// The devices besides the keyboard, see [`State::add_device`]
+    devices: Vec<MappedDevice>,
+    /// Only there once [`State::enable_decode_cache`] was called
+    decoded: Option<DecodeCache>,
+    interrupt: InterruptHandle,
+    /// Instructions that ran to completion, see [`State::instructions_executed`]
+    executed: u64,
+    /// Every address loaded or written, see [`State::set_uninitialized_exec`]
+    written: AddressSet,
+    /// Every address loaded with [`State::load_image`], see [`State::set_self_modify_check`]
+    loaded: AddressSet,
+    uninitialized_exec: UninitializedExec,
+    /// Only there once [`State::set_stack_check`] was called
+    stack: Option<StackCheck>,
+    /// The time the devices see, see [`State::set_clock`]
+    clock: Box<dyn Clock>,
+    /// See [`State::set_input_timeout`]
+    input_timeout: Option<Duration>,
+    /// Only there between [`State::start_recording`] and [`State::stop_recording`]
+    recorder: Option<Box<Recorder>>,
+    /// Only there between [`State::start_replay`] and [`State::finish_replay`], the input is left alone meanwhile
+    replay: Option<Box<Replay>>,
+    /// Only there once [`State::enable_stats`] was called
+    stats: Option<Box<Stats>>,
+    /// Only there once [`State::set_hang_check`] was called
+    hang: Option<HangCheck>,
+    /// Only there once [`State::set_self_modify_check`] was called
+    self_modify: Option<Box<SelfModifyCheck>>,
+    /// Only there once [`State::set_code_protection`] was called
+    protection: Option<Box<CodeProtection>>,
+    /// Every warning goes through it, see [`State::set_warning_level`]
+    warnings: Warnings,
+    /// Every ASSERT trap that ran, see [`State::trap_assertions`]
+    trap_assertions: Vec<TrapAssertion>,
+    /// See [`State::set_halt_on_assert_fail`]
+    halt_on_assert_fail: bool,
+    /// See [`State::set_getc_nonblocking`]
+    getc_nonblocking: bool,
+    /// Only there once the program halted with the EXIT trap
+    exit_code: Option<u8>,
+    /// See [`State::set_quiet`]
+    quiet: bool,
+    /// Only there once [`State::set_host_files`] was called
+    host_files: Option<Box<HostFiles>>,
+    /// Only there once [`State::set_interrupt_controller`] was called
+    irq: Option<Box<InterruptController>>,
+    /// Only there once [`State::enable_banks`] was called
+    banks: Option<Box<Banks>>,
+    /// Only there once [`State::trap_table_mut`] was called, every routine at its own vector until then
+    traps: Option<Box<TrapTable>>,
+    /// Only there once [`State::set_watch_dump`] was called
+    watch_dump: Option<Box<WatchDump>>,
+    /// The ranges of [`State::install_program`] and [`State::install_data`]
+    segments: Vec<RangeInclusive<u16>>,
+    /// Only there once [`State::set_write_log`] was called
+    write_log: Option<Box<WriteLog>>,
+    /// Instructions between the looks at the devices, see [`State::set_tick_granule`]
+    tick_granule: u64,
+    /// The instruction count the run loops look at the devices at, `u64::MAX` without devices
+    next_tick_at: u64,
+    /// Whether a device needs the run loops to count every instruction, see [`Device::counts_instructions`]
+    exact_count: bool,
+    /// Only there once [`State::enable_jit`] was called
+    #[cfg(feature = "jit")]
+    jit: Option<jit::Jit>,
+}
+
+/// What happens when the PC reaches an address nothing was loaded to nor written, like after a program without a HALT
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UninitializedExec {
+    /// The zeroed word runs like any other, a BR that never branches
+    #[default]
+    Allow,
+    /// Print a warning with the address to stderr the first time and keep running
+    Warn,
+    /// Stop with [`RuntimeError::UninitializedExec`]
+    Fail,
+}
+
+/// A bit for every address, like the addresses loaded or written. 8 KiB for the whole memory
+pub(crate) struct AddressSet(Box<[u64; MEM_MAX / 64]>);
+
+impl AddressSet {
+    pub(crate) fn new() -> AddressSet {
+        AddressSet(vec![0; MEM_MAX / 64].into_boxed_slice().try_into().unwrap()) // The vector has exactly MEM_MAX / 64 words
+    }
+
+    #[inline]
+    pub(crate) fn mark(&mut self, address: usize) {
+        self.0[address / 64] |= 1 << (address % 64);
+    }
+
+    #[inline]
+    pub(crate) fn contains(&self, address: usize) -> bool {
+        self.0[address / 64] & (1 << (address % 64)) != 0
+    }
+
+    /// The addresses in the set below `end`, a multiple of 64
+    pub(crate) fn count_below(&self, end: usize) -> usize {
+        self.0[..end / 64]
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+}
+
+/// Allocate the memory straight on the heap, `Box::new([0; MEM_MAX])` would build it on the stack first
+fn zeroed_memory() -> Box<[u16; MEM_MAX]> {
+    vec![0_u16; MEM_MAX].into_boxed_slice().try_into().unwrap() // The vector has exactly MEM_MAX words
+}
+
+/// Keys from stdin and output to stdout. Without the `cli` feature nobody types on the keyboard, see [`NoInput`]
+impl Default for State {
+    fn default() -> State {
+        let mut state = State {
+            memory: zeroed_memory(),
+            registers: [0_u16; Registers::InstRet as usize],
+            running: true,
+            #[cfg(feature = "cli")]
+            input: Box::new(StdinInput::default()),
+            #[cfg(not(feature = "cli"))]
+            input: Box::new(NoInput),
+            output: Box::new(BufferedOutput::new(io::stdout())),
+            devices: Vec::new(),
+            decoded: None,
+            interrupt: InterruptHandle::default(),
+            executed: 0,
+            written: AddressSet::new(),
+            loaded: AddressSet::new(),
+            uninitialized_exec: UninitializedExec::Allow,
+            stack: None,
+            #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+            clock: Box::new(clock::RealClock::default()),
+            #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+            clock: Box::new(clock::VirtualClock::default()),
+            input_timeout: None,
+            recorder: None,
+            replay: None,
+            stats: None,
+            hang: None,
+            self_modify: None,
+            protection: None,
+            warnings: Warnings::new(),
+            trap_assertions: Vec::new(),
+            halt_on_assert_fail: false,
+            getc_nonblocking: false,
+            exit_code: None,
+            quiet: false,
+            host_files: None,
+            irq: None,
+            banks: None,
+            traps: None,
+            watch_dump: None,
+            segments: Vec::new(),
+            write_log: None,
+            tick_granule: device::DEFAULT_TICK_GRANULE,
+            next_tick_at: u64::MAX,
+            exact_count: false,
+            #[cfg(feature = "jit")]
+            jit: None,
+        };
+        state.register_write(Registers::Pc, PC_START);
+        state.register_write(Registers::Flags, Flags::Zro as u16);
+        state
+    }
+}
+
+impl State {
+    /// A state that never touches the terminal: nobody types on its keyboard (see [`NoInput`])
+    /// and what the program prints is kept in the returned [`MemoryOutput`]
+    pub fn headless() -> (State, MemoryOutput) {
+        let mut state = State::default();
+        let output = MemoryOutput::default();
+        state.set_input(NoInput);
+        state.set_output(output.clone());
+        (state, output)
+    }
+
+    /// Write a word of data, a store to the registers of a device added with [`State::add_device`] goes to it instead
+    pub fn memory_write(&mut self, address: usize, value: u16) {
+        if address == MemoryMappedRegisters::Kbsr as usize {
+            // Only the interrupt enable bit can be written, whether a key is ready is up to the keyboard
+            self.memory[address] =
+                value & irq::KEYBOARD_INTERRUPT_ENABLE | self.memory[address] & irq::KEYBOARD_READY;
+            self.written.mark(address);
+            return;
+        }
+        if address == bank::BSR as usize && self.banks.is_some() {
+            self.select_bank(value);
+            return;
+        }
+        if address == MemoryMappedRegisters::Ddr as usize {
+            let _ = self.output.write_all(&[value as u8]);
+        }
+        if address == MemoryMappedRegisters::Mcr as usize && value & DEVICE_READY == 0 {
+            let _ = self.output.flush();
+            self.running = false;
+        }
+        if address >= DEVICE_PAGE
+            && let now = self.now()
+            && let executed = self.executed
+            && let Some(mapped) = self.device_at(address)
+        {
+            mapped.device.tick(now);
+            mapped.device.retired(executed);
+            mapped.device.write(address as u16, value);
+            let printed = mapped.device.take_output();
+            if !printed.is_empty() {
+                let _ = self.output.write_all(&printed);
+            }
+            return;
+        }
+        self.memory[address] = value;
+        self.written.mark(address);
+        if let Some(decoded) = &mut self.decoded {
+            decoded.invalidate(address);
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.invalidate(address);
+        }
+    }
+
+    /// Emit a warning for every conflict of the layout of `map` with the regions of `config`, see
+    /// [`crate::layout`]. Fails at the first one denied
+    pub fn check_layout(&mut self, map: &LoadMap, config: &RunConfig) -> Result<(), Error> {
+        map.validate(config)
+            .into_iter()
+            .try_for_each(|warning| self.warn(warning))
+    }
+
+    /// [`State::load_image`], warning with [`Warning::LoadOverlap`] when it overwrites words another image loaded
+    pub fn load_image_checked(&mut self, origin: u16, words: &[u16]) -> Result<(), Error> {
+        let end = origin as usize + words.len();
+        if let Some(address) = (origin as usize..end).find(|&address| self.loaded.contains(address))
+        {
+            self.warn(Warning::LoadOverlap {
+                start: origin,
+                end: (end - 1) as u16,
+                address: address as u16,
+            })?;
+        }
+        self.load_image(origin, words);
+        Ok(())
+    }
+
+    /// Write the words of an image from `origin` on, they are the program the checks of [`State::set_self_modify_check`] look at
+    pub fn load_image(&mut self, origin: u16, words: &[u16]) {
+        for (offset, word) in words.iter().enumerate() {
+            let address = origin as usize + offset;
+            self.memory_write(address, *word);
+            self.loaded.mark(address);
+        }
+    }
+
+    /// A store of the program, the instruction doing it was just fetched
+    #[inline]
+    pub(crate) fn store(&mut self, address: usize, value: u16) -> Result<(), Error> {
+        if let Some(protection) = &self.protection
+            && self.loaded.contains(address)
+            && !protection.allows(address as u16)
+        {
+            let pc = self.registers[Registers::Pc].wrapping_sub(1);
+            return Err(RuntimeError::WriteToCode {
+                pc,
+                address: address as u16,
+            }
+            .into());
+        }
+        if let Some(check) = &mut self.self_modify {
+            let writer = self.registers[Registers::Pc].wrapping_sub(1);
+            if check.stored(writer, address as u16, self.loaded.contains(address)) {
+                self.self_modified(writer, address as u16)?;
+            }
+        }
+        if self.write_log.is_some() {
+            self.log_write(address as u16, value);
+        }
+        self.memory_write(address, value);
+        Ok(())
+    }
+
+    /// Read a word of data like the program does. Below the device page (xFE00) it is plain memory,
+    /// reading a device register lets its device update it first and reading KBSR polls the keyboard, taking a key.
+    /// To look at the memory without any of that see [`State::peek`]
+    #[inline]
+    pub fn memory_read(&mut self, address: usize) -> u16 {
+        if address < DEVICE_PAGE {
+            return self.memory[address];
+        }
+        self.read_device(address)
+    }
+
+    /// A word as the program last saw it, without side effects: below the device page it is plain memory, a device
+    /// register gives the word last read from it (for the keyboard, what the last poll found). What a debugger, a
+    /// summary or an assertion looks at, [`State::memory_read`] is the read of the program
+    pub fn peek(&self, address: usize) -> u16 {
+        if address == bank::BSR as usize
+            && let Some(banks) = &self.banks
+        {
+            return banks.selected();
+        }
+        match self
+            .devices
+            .iter()
+            .find(|mapped| mapped.range.contains(&(address as u16)))
+        {
+            Some(mapped) => mapped.latched[address - *mapped.range.start() as usize],
+            None => self.memory[address],
+        }
+    }
+
+    /// Fetch the word of an instruction, it never goes through the devices since nobody executes a device register
+    #[inline]
+    pub fn read_instruction(&self, address: usize) -> u16 {
+        self.memory[address]
+    }
+
+    fn read_device(&mut self, address: usize) -> u16 {
+        let status = self.memory[MemoryMappedRegisters::Kbsr];
+        let interrupts = status & irq::KEYBOARD_INTERRUPT_ENABLE;
+        // A key waits in KBDR until it is read, KBSR only polls for the next one after that
+        if address == MemoryMappedRegisters::Kbsr as usize && status & irq::KEYBOARD_READY == 0 {
+            // A program polling the keyboard is waiting for the user, what it printed must be visible
+            let _ = self.output.flush();
+            let key = match &mut self.replay {
+                Some(replay) => replay.poll(),
+                None => self.input.poll_byte(),
+            };
+            match key {
+                Some(key) => {
+                    self.key_consumed(key);
+                    self.memory[MemoryMappedRegisters::Kbsr] = irq::KEYBOARD_READY | interrupts;
+                    self.memory[MemoryMappedRegisters::Kbdr] = key as u16
+                }
+                None => self.memory[MemoryMappedRegisters::Kbsr] = interrupts,
+            };
+        }
+        if address == MemoryMappedRegisters::Kbdr as usize {
+            self.memory[MemoryMappedRegisters::Kbsr] &= !irq::KEYBOARD_READY;
+        }
+        // The display never makes the program wait and the machine runs while the program reads MCR
+        if address == MemoryMappedRegisters::Dsr as usize
+            || address == MemoryMappedRegisters::Mcr as usize
+        {
+            self.memory[address] |= DEVICE_READY;
+        }
+        if address == bank::BSR as usize
+            && let Some(banks) = &self.banks
+        {
+            return banks.selected();
+        }
+        let now = self.now();
+        let executed = self.executed;
+        if let Some(mapped) = self.device_at(address) {
+            mapped.device.tick(now);
+            mapped.device.retired(executed);
+            return mapped.read(address as u16);
+        }
+        self.memory[address]
+    }
+
+    fn device_at(&mut self, address: usize) -> Option<&mut MappedDevice> {
+        self.devices
+            .iter_mut()
+            .find(|mapped| mapped.range.contains(&(address as u16)))
+    }
+
+    /// Map a device on the device page. Its registers can't be outside of the page,
+    /// nor share an address with the keyboard, the display, MCR, BSR with banks or a device added before
+    pub fn add_device(&mut self, device: impl Device + 'static) -> Result<(), Error> {
+        self.add_boxed_device(Box::new(device))
+    }
+
+    /// Like [`State::add_device`] for a device that is already boxed, like a plugin
+    pub fn add_boxed_device(&mut self, device: Box<dyn Device>) -> Result<(), Error> {
+        let range = device.range();
+        let (first, last) = (*range.start(), *range.end());
+        let builtin = [
+            MemoryMappedRegisters::Kbsr as u16,
+            MemoryMappedRegisters::Kbdr as u16,
+            MemoryMappedRegisters::Dsr as u16,
+            MemoryMappedRegisters::Ddr as u16,
+            MemoryMappedRegisters::Mcr as u16,
+        ];
+        let taken = builtin.iter().any(|register| range.contains(register))
+            || self.banks.is_some() && range.contains(&bank::BSR)
+            || self
+                .devices
+                .iter()
+                .any(|mapped| first <= *mapped.range.end() && *mapped.range.start() <= last);
+        if (first as usize) < DEVICE_PAGE || first > last || taken {
+            return Err(Error::DeviceRange(first, last));
+        }
+        self.exact_count |= device.counts_instructions();
+        self.devices.push(MappedDevice::new(range, device));
+        self.schedule_ticks();
+        Ok(())
+    }
+
+    pub fn set_input(&mut self, input: impl Input + Send + 'static) {
+        self.input = Box::new(input);
+    }
+
+    pub fn set_output(&mut self, output: impl Write + Send + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Make a [`KeyboardBuffer`] the keyboard, the only place the keys come from, and return a handle to push them
+    pub fn attach_keyboard_buffer(&mut self) -> KeyboardBuffer {
+        let keys = KeyboardBuffer::new(self.interrupt_handle());
+        self.set_input(keys.clone());
+        keys
+    }
+
+    /// Decode every instruction once and reuse it the next times its address is executed by [`run_loop`] and [`run_with_budget`].
+    /// Writing to memory through [`State::memory_write`] drops the decoded instruction, so self-modifying code still works
+    pub fn enable_decode_cache(&mut self) {
+        self.decoded = Some(DecodeCache::new());
+    }
+
+    /// Give the devices their time from `clock`, the real time by default (virtual in a browser)
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Stop the program with [`RuntimeError::InputTimeout`] when GETC, IN or READLINE waits for a key longer than
+    /// `timeout`, or when GETC or IN find the input ended and would wait forever. Under a clock that doesn't follow the host
+    /// (see [`Clock::follows_host`]) waiting takes no time, so only the end of the input times out and a replay stops
+    /// at the same instruction on every run
+    pub fn set_input_timeout(&mut self, timeout: Option<Duration>) {
+        self.input_timeout = timeout;
+    }
+
+    /// The time of the machine, what the devices get. The run loops count the instructions a chunk at a time, so
+    /// under a [`clock::VirtualClock`] it advances in steps, the same ones on every run
+    pub fn now(&self) -> Duration {
+        self.clock.now(self.executed)
+    }
+
+    /// Write every key the program consumes to `recorder` from now on, see [`recording`]
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(Box::new(recorder));
+    }
+
+    /// Stop recording and flush the recording, with the first error writing it if there was one
+    pub fn stop_recording(&mut self) -> Result<(), Error> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Give the program the keys of `replay` instead of the input, each at the instruction it was recorded at
+    pub fn start_replay(&mut self, replay: Replay) {
+        self.replay = Some(Box::new(replay));
+    }
+
+    /// Stop replaying, a divergence if the program didn't consume every recorded key
+    pub fn finish_replay(&mut self) -> Result<(), Error> {
+        let Some(replay) = self.replay.take() else {
+            return Ok(());
+        };
+        match replay.unconsumed() {
+            Some(reason) => Err(RuntimeError::ReplayDiverges {
+                instruction: replay.current(),
+                pc: self.registers[Registers::Pc],
+                reason,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The keyboard registers or a trap took `key` from the input
+    #[inline]
+    pub(crate) fn key_consumed(&mut self, key: u8) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.key(key);
+        }
+    }
+
+    /// Count what the program does besides executing instructions, see [`State::stats`]
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(Box::new(Stats::new()));
+    }
+
+    /// Like [`State::enable_stats`], counting every instruction by opcode and the BR taken too. The program then runs
+    /// in the loop with the checks, without the JIT
+    pub fn enable_profile(&mut self) {
+        self.stats = Some(Box::new(Stats::with_profile()));
+    }
+
+    /// The counters since [`State::enable_stats`] was called, `None` before
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_deref()
+    }
+
+    /// Compile the loops [`run_loop`] and [`run_with_budget`] execute often to native code, see the `jit` module.
+    /// Anything the compiled code can't do is still interpreted, so the program behaves the same.
+    /// Returns false, and keeps interpreting everything, if cranelift doesn't support this machine
+    #[cfg(feature = "jit")]
+    pub fn enable_jit(&mut self) -> bool {
+        self.jit = jit::Jit::new();
+        self.jit.is_some()
+    }
+
+    /// What to do when the PC reaches an address nothing was loaded to nor written through [`State::memory_write`].
+    /// Checked when an instruction is fetched, by default nothing is
+    pub fn set_uninitialized_exec(&mut self, policy: UninitializedExec) {
+        self.uninitialized_exec = policy;
+    }
+
+    /// Whether the instruction at the PC can be fetched, see [`UninitializedExec`]
+    #[inline(always)]
+    fn check_fetch(&mut self, address: u16) -> Result<(), Error> {
+        if self.uninitialized_exec != UninitializedExec::Allow
+            && !self.written.contains(address as usize)
+        {
+            self.uninitialized_fetch(address)?;
+        }
+        if let Some(check) = &mut self.self_modify
+            && let Some(writer) = check.executing(address)
+        {
+            self.self_modified(writer, address)?;
+        }
+        Ok(())
+    }
+
+    #[cold]
+    fn uninitialized_fetch(&mut self, address: u16) -> Result<(), Error> {
+        match self.uninitialized_exec {
+            UninitializedExec::Fail => Err(RuntimeError::UninitializedExec(address).into()),
+            _ => {
+                // Only the first time, the rest of the zeroed memory would warn on every word
+                self.uninitialized_exec = UninitializedExec::Allow;
+                self.warn(Warning::UninitializedExec(address))
+            }
+        }
+    }
+
+    /// Check how the program uses R6 as the stack pointer, see [`StackCheck`]. `None` stops checking
+    pub fn set_stack_check(&mut self, stack: Option<StackCheck>) {
+        self.stack = stack;
+    }
+
+    /// Whether any check needs [`State::before_instruction`] and [`State::after_instruction`] around every instruction
+    fn checked(&self) -> bool {
+        self.uninitialized_exec != UninitializedExec::Allow
+            || self.stack.is_some()
+            || self.hang.is_some()
+            || self.recorder.is_some()
+            || self.replay.is_some()
+            || self.self_modify.is_some()
+            || self.irq.is_some()
+    }
+
+    /// The checks before the instruction at `pc` is fetched. Returns R6, for [`State::after_instruction`]
+    #[inline(always)]
+    pub(crate) fn before_instruction(&mut self, pc: u16) -> Result<u16, Error> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.instruction();
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.instruction();
+        }
+        self.check_fetch(pc)?;
+        if let Some(stats) = &mut self.stats {
+            stats.instruction(self.memory[pc as usize], self.registers[Registers::Flags]);
+        }
+        let stack_pointer = self.registers[Registers::R6];
+        if let Some(stack) = &self.stack
+            && let Some(violation) = stack.before(pc, self.memory[pc as usize], stack_pointer)
+        {
+            self.stack_violation(violation)?;
+        }
+        if self.hang.is_some() {
+            self.check_hang(pc)?;
+        }
+        Ok(stack_pointer)
+    }
+
+    /// The checks after the instruction at `pc` ran, with R6 from before it
+    #[inline(always)]
+    pub(crate) fn after_instruction(&mut self, pc: u16, stack_pointer: u16) -> Result<(), Error> {
+        if let Some(stack) = &self.stack
+            && let Some(violation) = stack.after(pc, stack_pointer, self.registers[Registers::R6])
+        {
+            self.stack_violation(violation)?;
+        }
+        Ok(())
+    }
+
+    #[cold]
+    fn stack_violation(&mut self, violation: StackViolation) -> Result<(), Error> {
+        let Some(stack) = &mut self.stack else {
+            return Ok(());
+        };
+        if stack.fail {
+            return Err(RuntimeError::Stack(violation).into());
+        }
+        self.warn(Warning::Stack(violation))
+    }
+
+    /// Stop a program spinning without doing anything with [`RuntimeError::Hang`], see [`HangCheck`]. `None` stops checking
+    pub fn set_hang_check(&mut self, check: Option<HangCheck>) {
+        self.hang = check;
+    }
+
+    #[inline]
+    fn check_hang(&mut self, pc: u16) -> Result<(), Error> {
+        let word = self.memory[pc as usize];
+        let Some(hang) = &self.hang else {
+            return Ok(());
+        };
+        let progress = hang.progress(word, self);
+        let registers = self.registers;
+        match self
+            .hang
+            .as_mut()
+            .is_some_and(|hang| hang.step(&registers, progress))
+        {
+            true => Err(RuntimeError::Hang(Hang {
+                pc,
+                word,
+                registers: registers[..8].try_into().unwrap(), // The first eight are R0 to R7
+            })
+            .into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Report stores of the program to its own instructions, see [`SelfModifyCheck`]. `None` stops checking
+    pub fn set_self_modify_check(&mut self, check: Option<SelfModifyCheck>) {
+        self.self_modify = check.map(Box::new);
+    }
+
+    /// Stop the program storing to a word loaded from an image, see [`CodeProtection`]. `None` stops checking
+    pub fn set_code_protection(&mut self, protection: Option<CodeProtection>) {
+        self.protection = protection.map(Box::new);
+    }
+
+    /// The stores that modified the program so far, empty without [`State::set_self_modify_check`]
+    pub fn self_modifications(&self) -> Vec<SelfModification> {
+        self.self_modify
+            .as_ref()
+            .map(|check| check.modifications())
+            .unwrap_or_default()
+    }
+
+    /// Every ASSERT trap (x26) that ran so far, in order. Failed ones don't stop the program unless
+    /// [`State::set_halt_on_assert_fail`]
+    pub fn trap_assertions(&self) -> &[TrapAssertion] {
+        &self.trap_assertions
+    }
+
+    /// Stop with [`RuntimeError::AssertFailed`] at the first ASSERT trap that fails instead of going on
+    pub fn set_halt_on_assert_fail(&mut self, halt: bool) {
+        self.halt_on_assert_fail = halt;
+    }
+
+    /// Make GETC return 0, with the flags Z, right away when no key is pending instead of waiting for one, like some
+    /// other simulators do. IN still waits. The empty returns are counted in [`State::stats`]
+    pub fn set_getc_nonblocking(&mut self, nonblocking: bool) {
+        self.getc_nonblocking = nonblocking;
+    }
+
+    /// Don't print anything for the DBG trap (x28), it does nothing at all then
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Let the program use the files in a sandbox directory through the traps x30 to x33, see [`host_fs`].
+    /// `None` closes every file and makes the traps fail again
+    pub fn set_host_files(&mut self, files: Option<HostFiles>) {
+        self.host_files = files.map(Box::new);
+    }
+
+    #[cold]
+    fn self_modified(&mut self, writer: u16, target: u16) -> Result<(), Error> {
+        if self.self_modify.as_ref().is_some_and(|check| check.fail) {
+            return Err(RuntimeError::SelfModify { writer, target }.into());
+        }
+        self.warn(Warning::SelfModify { writer, target })
+    }
+
+    /// Print or count the warning, or stop the program with it, see [`Warnings::emit`]. What the program printed so
+    /// far goes first
+    #[cold]
+    fn warn(&mut self, warning: Warning) -> Result<(), Error> {
+        let _ = self.output.flush();
+        self.warnings.emit(warning)
+    }
+
+    /// Ignore the warnings of `code`, stop the program at the first one or print them, see [`crate::warning`]
+    pub fn set_warning_level(&mut self, code: WarningCode, level: WarningLevel) {
+        self.warnings.set_level(code, level);
+    }
+
+    /// The warnings emitted so far, see [`Warnings::emitted`] and [`Warnings::report`]
+    pub fn warnings(&self) -> &Warnings {
+        &self.warnings
+    }
+
+    /// Keys that arrived but the program never read, see [`Input::take_pending`]
+    pub fn take_pending_input(&mut self) -> Vec<u8> {
+        self.input.take_pending()
+    }
+
+    /// Put a [`KeyboardFifo`] of `depth` keys in front of the input set now
+    pub fn buffer_keyboard(&mut self, depth: usize, overflow: KeyboardOverflow) {
+        let input = std::mem::replace(&mut self.input, Box::new(NoInput));
+        self.input = Box::new(KeyboardFifo::new(input, depth, overflow));
+    }
+
+    /// Put a [`TranslatedInput`] in front of the input set now, the special keys of `keymap` reaching the program as
+    /// their single byte
+    pub fn translate_keys(&mut self, keymap: Keymap) {
+        let input = std::mem::replace(&mut self.input, Box::new(NoInput));
+        self.input = Box::new(TranslatedInput::new(input, keymap));
+    }
+
+    /// Keys lost to a full buffer, see [`Input::keys_dropped`]
+    pub fn keys_dropped(&self) -> Option<u64> {
+        self.input.keys_dropped()
+    }
+
+    /// Something another thread, like a Ctrl-C handler, can use to stop the program, see [`InterruptHandle`]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// How many instructions the run loops executed since the state was created, an instruction that failed isn't counted
+    pub fn instructions_executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// How many words of memory were loaded or written, the device registers aside
+    pub fn memory_footprint(&self) -> usize {
+        self.written.count_below(DEVICE_PAGE)
+    }
+
+    /// False once the program has halted
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// The low byte of R0 if the program halted with the EXIT trap (x27), `None` for HALT or while it runs
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn register_read(&self, address: Registers) -> u16 {
+        self.registers[address]
+    }
+
+    pub fn register_write(&mut self, address: Registers, value: u16) {
+        self.registers[address] = value;
+    }
+
+    /// The PC wraps around to x0000 after xFFFF
+    pub fn increment_pc(&mut self) {
+        self.registers[Registers::Pc] = self.registers[Registers::Pc].wrapping_add(1);
+    }
+}
+
+/// Asks a running [`State`] to stop. [`run_loop`] and [`run_with_budget`] return [`RuntimeError::Interrupted`] within a few
+/// thousand instructions, or right away if the program is waiting for a key with an input that watches the handle
+/// (see `terminal::TerminalInput::wake_on`). The PC is left at the next instruction to run, so the program can be resumed
+#[derive(Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// True if an interrupt was requested, which is then considered handled
+    fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+pub fn run_loop(state: &mut State) -> Result<(), Error> {
+    match run_with_budget(state, u64::MAX) {
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Ok(()), // Unreachable in practice, but still not a failure
+        result => result,
+    }
+}
+
+/// Like [`run_loop`] but fails if the program doesn't halt within the given amount of instructions
+pub fn run_with_budget(state: &mut State, budget: u64) -> Result<(), Error> {
+    // The profile is the instrumentation of the run while it runs, only a traced or stepped run counts it in the checks
+    if let Some(mut profile) = state.stats.as_mut().and_then(|stats| stats.take_profile()) {
+        let result = run_instrumented(state, budget, &mut profile);
+        if let Some(stats) = &mut state.stats {
+            stats.restore_profile(profile);
+        }
+        return result;
+    }
+    run_instrumented(state, budget, &mut NoInstrumentation)
+}
+
+/// Like [`run_with_budget`], calling the hooks of `instrumentation` around every instruction, see [`instrument`]. The
+/// loop is compiled for `I`, with [`NoInstrumentation`] it has nothing more than without any
+pub fn run_instrumented<I: Instrumentation>(
+    state: &mut State,
+    budget: u64,
+    instrumentation: &mut I,
+) -> Result<(), Error> {
+    let result = run_picked(state, budget, instrumentation);
+    instrumentation.finished(state);
+    result
+}
+
+/// The way instructions are fetched is picked once here instead of on every instruction
+fn run_picked<I: Instrumentation>(
+    state: &mut State,
+    budget: u64,
+    instrumentation: &mut I,
+) -> Result<(), Error> {
+    // Compiled blocks come from addresses the interpreter fetched already, but they can move R6 unseen, loop
+    // without the hang check seeing a single lap and run instructions a recording, a replay or a collector doesn't
+    // count
+    #[cfg(feature = "jit")]
+    if state.jit.is_some()
+        && state.stack.is_none()
+        && state.hang.is_none()
+        && state.recorder.is_none()
+        && state.replay.is_none()
+        && state.irq.is_none()
+        && !instrumentation.active()
+    {
+        return run_jitted(state, budget);
+    }
+    // Without any check the loop doesn't even test whether there is one
+    let checked = state.checked();
+    match (state.decoded.is_some(), checked) {
+        (true, false) => run_instructions::<DecodeCached, I>(state, budget, instrumentation),
+        (false, false) => run_instructions::<Interpreted, I>(state, budget, instrumentation),
+        (true, true) => {
+            run_instructions::<Checked<DecodeCached>, I>(state, budget, instrumentation)
+        }
+        (false, true) => {
+            run_instructions::<Checked<Interpreted>, I>(state, budget, instrumentation)
+        }
+    }
+}
+
+/// Instructions run between budget checks
+const CHUNK: u64 = 1024;
+
+/// How the next instruction is fetched and executed, see [`run_instructions`]
+trait Dispatch {
+    /// Get the instruction at `pc`, the PC of `state`, increment the PC by one and execute it. Returns the executed word and
+    /// the PC after it, so the run loop doesn't read the PC back from `state` after every instruction
+    fn fetch_and_run(state: &mut State, pc: u16) -> Result<(u16, u16), Error>;
+}
+
+/// Every instruction is decoded each time it is executed
+struct Interpreted;
+
+impl Dispatch for Interpreted {
+    #[inline(always)]
+    fn fetch_and_run(state: &mut State, pc: u16) -> Result<(u16, u16), Error> {
+        let instruction = state.read_instruction(pc as usize);
+        let next = pc.wrapping_add(1);
+        state.registers[Registers::Pc] = next;
+        // The branches and the adds of a loop don't go through `run_step`, so they never need the PC read back
+        let next = match instruction >> 12 {
+            op if op == Operations::Br as u16 => {
+                let next = branch_target(instruction, state.registers[Registers::Flags], next);
+                state.registers[Registers::Pc] = next;
+                next
+            }
+            op if op == Operations::Add as u16 => {
+                add(instruction, state)?;
+                next
+            }
+            _ => {
+                run_step(instruction, state)
+                    .map_err(|error| Fault::wrap(error, pc, instruction, state))?;
+                state.registers[Registers::Pc]
+            }
+        };
+        Ok((instruction, next))
+    }
+}
+
+/// An address already executed reuses its decoded instruction, see [`State::enable_decode_cache`]
+struct DecodeCached;
+
+impl Dispatch for DecodeCached {
+    #[inline(always)]
+    fn fetch_and_run(state: &mut State, pc: u16) -> Result<(u16, u16), Error> {
+        let memory_address = pc as usize;
+        let Some(decoded) = &mut state.decoded else {
+            return Interpreted::fetch_and_run(state, pc);
+        };
+        let instruction = match decoded.get(memory_address) {
+            Some(instruction) => instruction,
+            None => {
+                let instruction = Instruction::decode(state.memory[memory_address]);
+                decoded.insert(memory_address, instruction);
+                instruction
+            }
+        };
+        state.increment_pc();
+        instruction.execute(state).map_err(|error| {
+            Fault::wrap(error, memory_address as u16, instruction.word(), state)
+        })?;
+        Ok((instruction.word(), state.registers[Registers::Pc]))
+    }
+}
+
+/// Runs the checks around every instruction, see [`State::set_uninitialized_exec`] and [`State::set_stack_check`]
+struct Checked<D>(PhantomData<D>);
+
+impl<D: Dispatch> Dispatch for Checked<D> {
+    #[inline(always)]
+    fn fetch_and_run(state: &mut State, _: u16) -> Result<(u16, u16), Error> {
+        // An interrupt moves the PC to its handler
+        state.service_interrupts()?;
+        let pc = state.register_read(Registers::Pc);
+        let stack_pointer = state.before_instruction(pc)?;
+        let (instruction, _) = D::fetch_and_run(state, pc)?;
+        state.after_instruction(pc, stack_pointer)?;
+        Ok((instruction, state.registers[Registers::Pc]))
+    }
+}
+
+/// The run loop. The budget and the interrupt flag are only checked every [`CHUNK`] instructions and whether the program halted only after traps
+/// and stores (see [`may_stop`]), so executing an instruction doesn't pay for any of them.
+/// The executed instructions are counted a chunk at a time too. The hooks of `instrumentation` are inlined around every
+/// instruction, nothing for [`NoInstrumentation`]
+#[inline(never)]
+fn run_instructions<D: Dispatch, I: Instrumentation>(
+    state: &mut State,
+    budget: u64,
+    instrumentation: &mut I,
+) -> Result<(), Error> {
+    let mut remaining = budget;
+    while state.running && remaining > 0 {
+        let chunk = remaining.min(CHUNK).min(state.instructions_until_tick());
+        let mut pc = state.registers[Registers::Pc];
+        for done in 0..chunk {
+            instrumentation.before_instruction(pc, state);
+            let (instruction, next) = match D::fetch_and_run(state, pc) {
+                Ok(executed) => executed,
+                Err(error) => {
+                    state.executed += done;
+                    return Err(error);
+                }
+            };
+            instrumentation.after_instruction(pc, instruction, state);
+            pc = next;
+            if may_stop(instruction) && !state.running {
+                state.executed += done + 1;
+                return Ok(());
+            }
+        }
+        state.executed += chunk;
+        remaining -= chunk;
+        state.tick_devices_if_due();
+        if state.interrupt.take() {
+            return Err(RuntimeError::Interrupted.into());
+        }
+    }
+    match state.running {
+        true => Err(RuntimeError::BudgetExhausted(budget).into()),
+        false => Ok(()),
+    }
+}
+
+/// Whether `instruction` can stop the machine: a trap like HALT, or a store clearing the MCR
+#[inline(always)]
+fn may_stop(instruction: u16) -> bool {
+    const STOPPING: u16 = 1 << Operations::Trap as u16
+        | 1 << Operations::St as u16
+        | 1 << Operations::Sti as u16
+        | 1 << Operations::Str as u16;
+    STOPPING >> (instruction >> 12) & 1 != 0
+}
+
+/// Most instructions a compiled block runs before the JIT loop looks at the interrupt flag again
+#[cfg(feature = "jit")]
+const JIT_SLICE: u64 = 1 << 20;
+
+/// Run the block compiled at the PC, if there is one that fits in `budget`, and the devices due after it. The
+/// instructions it ran, `None` if the next one has to be interpreted. What [`compare::run_in_lockstep`] steps the JIT
+/// with
+#[cfg(feature = "jit")]
+pub(crate) fn run_compiled_block(state: &mut State, budget: u64) -> Option<u64> {
+    let budget = budget.min(state.instructions_until_tick());
+    let executed = state
+        .jit
+        .as_ref()?
+        .run_compiled(&mut state.registers, budget)?;
+    state.executed += executed;
+    state.tick_devices_if_due();
+    Some(executed)
+}
+
+/// The run loop with the JIT: a compiled block runs as many instructions as it can at once,
+/// anything else is interpreted one instruction at a time
+#[cfg(feature = "jit")]
+fn run_jitted(state: &mut State, budget: u64) -> Result<(), Error> {
+    let mut remaining = budget;
+    while state.running && remaining > 0 {
+        if state.interrupt.take() {
+            return Err(RuntimeError::Interrupted.into());
+        }
+        // A compiled loop only stops at its budget, the slice bounds how long an interrupt waits. Its words all ran in
+        // the interpreter before it got hot, so the self-modifying code check already saw them executed
+        let slice = remaining
+            .min(JIT_SLICE)
+            .min(state.instructions_until_tick());
+        if let Some(jit) = &mut state.jit
+            && let Some(executed) = jit.run(&mut state.registers, &state.memory, slice)
+        {
+            state.executed += executed;
+            remaining -= executed;
+            state.tick_devices_if_due();
+            continue;
+        }
+        let pc = state.register_read(Registers::Pc);
+        state.check_fetch(pc)?;
+        let (instruction, _) = match state.decoded.is_some() {
+            true => DecodeCached::fetch_and_run(state, pc)?,
+            false => Interpreted::fetch_and_run(state, pc)?,
+        };
+        state.executed += 1;
+        remaining -= 1;
+        state.tick_devices_if_due();
+        if may_stop(instruction) && !state.running {
+            return Ok(());
+        }
+    }
+    match state.running {
+        true => Err(RuntimeError::BudgetExhausted(budget).into()),
+        false => Ok(()),
+    }
+}
+
+/// Execute a single instruction, the PC must already point at the next one
+pub fn run_step(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let op_code = instruction >> 12;
+    let operation_code = Operations::try_from(op_code).unwrap(); // Since op_code is an u16 that was right shifted 12 bits, its maximum value is 15 (1111) that will always map in the try_from, so it will never fail, that's why the unwrap is used
+    match operation_code {
+        Operations::Br => conditional_branch(instruction, state),
+        Operations::Add => add(instruction, state)?,
+        Operations::Ld => load(instruction, state)?,
+        Operations::St => store(instruction, state)?,
+        Operations::Jsr => jump_to_subrutine(instruction, state)?,
+        Operations::And => and(instruction, state)?,
+        Operations::Ldr => load_register(instruction, state)?,
+        Operations::Str => store_register(instruction, state)?,
+        Operations::Rti => irq::return_from_interrupt(instruction, state)?,
+        Operations::Not => not(instruction, state)?,
+        Operations::Ldi => load_indirect(instruction, state)?,
+        Operations::Sti => store_indirect(instruction, state)?,
+        Operations::Jmp => jump(instruction, state)?,
+        Operations::Res => irq::reserved(instruction, state)?,
+        Operations::Lea => load_effective_address(instruction, state)?,
+        Operations::Trap => trap(instruction, state)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::state_with_snippet;
+    use crate::*;
+
+    #[test]
+    fn loop_test() {
+        let mut state = state_with_snippet(
+            "
+        .ORIG x3000
+        LDI R5, POINTER     ; R5 = 25, read through POINTER
+        LD R3, FIFTY        ; R3 = 50
+        ADD R1, R3, R5      ; R1 = 75
+        AND R3, R3, #0      ; Clear R3, the Z flag is set
+        BRz SKIP
+        HALT                ; Skipped by the branch
+SKIP    NOT R3, R3          ; R3 = xFFFF
+        LEA R5, JUMPED
+        JMP R5
+        HALT                ; Skipped by the jump
+JUMPED  LDR R1, R5, #SUB_POINTER-JUMPED
+        JSRR R1             ; R7 = x300C
+        HALT
+SUB     STI R1, RESULT      ; Save the address of SUB at x4000
+        ST R7, RETURN       ; Save the return address
+        STR R5, R1, #0      ; Overwrite the first instruction of SUB with the address of JUMPED
+        RET
+POINTER .FILL VALUE
+VALUE   .FILL #25
+FIFTY   .FILL #50
+SUB_POINTER .FILL SUB
+RESULT  .FILL x4000
+RETURN  .BLKW 1
+        .END",
+        );
+        let _ = run_loop(&mut state);
+        assert_eq!(state.memory_read(0x4000), 0x300D);
+        assert_eq!(state.memory_read(0x3016), 0x300C);
+        assert_eq!(state.memory_read(0x300D), 0x300A);
+        assert_eq!(state.register_read(Registers::R7), 0x300C);
+        assert_eq!(state.register_read(Registers::R3), 0xFFFF);
+    }
+}
@@ -1,359 +1,1928 @@
-use operations::*;
-use std::fmt::Debug;
-use std::io::{Read, stdin};
-use std::ops::{Index, IndexMut};
-use std::time::Duration;
-use std::{env, io};
-use termios::*;
-pub mod file_management;
-mod operations;
-mod tests;
-use std::os::fd::AsRawFd;
-use thiserror::Error;
-use timeout_readwrite::TimeoutReadExt;
-
-static MEM_MAX: usize = 1 << 16;
-static PC_START: u16 = 0x3000;
-
-// Special registers that are in memory
-pub enum MemoryMappedRegisters {
-    Kbsr = 0xFE00, // Keyboard Status Register, identifies when a key is pressed
-    Kbdr = 0xFE02, // Keyboard Data Register, identifies what key was pressed
-}
-
-/// Traps are predefined routines, each trap in the enum represents a routine
-#[derive(Debug)]
-pub enum Traps {
-    Getc = 0x20,
-    Out = 0x21,
-    Puts = 0x22,
-    In = 0x23,
-    Putsp = 0x24,
-    Halt = 0x25,
-}
-
-impl TryFrom<u16> for Traps {
-    type Error = Errors;
-    fn try_from(value: u16) -> Result<Traps, Self::Error> {
-        match value {
-            0x20 => Ok(Traps::Getc),
-            0x21 => Ok(Traps::Out),
-            0x22 => Ok(Traps::Puts),
-            0x23 => Ok(Traps::In),
-            0x24 => Ok(Traps::Putsp),
-            0x25 => Ok(Traps::Halt),
-            badcode => Err(Errors::BadTrapCode(badcode)),
-        }
-    }
-}
-#[derive(Error, Debug)]
-pub enum Errors {
-    #[error("Bad register: `{0} does not exist!`")]
-    BadRegisterReference(u16),
-    #[error("Bad operation code: `{0}` does not exist!")]
-    BadOpCode(u16),
-    #[error("Bad file: {0}")]
-    BadFile(#[from] std::io::Error),
-    #[error("Couldn't disable input buffering")]
-    DisableInputBuffering,
-    #[error("Couldn't restore input buffering")]
-    RestoreInputBuffering,
-    #[error("Bad trap code: `{0}`")]
-    BadTrapCode(u16),
-    #[error("Bad trap `{0:?}`")]
-    Trap(Traps),
-    #[error("Not enough arguments")]
-    FewArguments,
-    #[error("Couldn't initialize termios")]
-    BadTermios,
-    #[error("Bad image size")]
-    BadImageSize,
-}
-#[derive(Clone, Copy)]
-enum Registers {
-    R0,      // Register 0
-    R1,      // Register 1
-    R2,      // Register 2
-    R3,      // Register 3
-    R4,      // Register 4
-    R5,      // Register 5
-    R6,      // Register 6
-    R7,      // Register 7
-    Pc,      // Program Counter
-    Flags,   // Flags
-    InstRet, // Amount of registers
-}
-
-impl TryFrom<u16> for Registers {
-    type Error = Errors;
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Registers::R0),
-            1 => Ok(Registers::R1),
-            2 => Ok(Registers::R2),
-            3 => Ok(Registers::R3),
-            4 => Ok(Registers::R4),
-            5 => Ok(Registers::R5),
-            6 => Ok(Registers::R6),
-            7 => Ok(Registers::R7),
-            register => Err(Errors::BadRegisterReference(register)),
-        }
-    }
-}
-
-impl<T> Index<MemoryMappedRegisters> for [T; MEM_MAX] {
-    type Output = T;
-    fn index(&self, index: MemoryMappedRegisters) -> &Self::Output {
-        &self[index as usize]
-    }
-}
-
-impl<T> IndexMut<MemoryMappedRegisters> for [T; MEM_MAX] {
-    fn index_mut(&mut self, index: MemoryMappedRegisters) -> &mut Self::Output {
-        &mut self[index as usize]
-    }
-}
-
-impl<T> Index<Registers> for [T; Registers::InstRet as usize] {
-    type Output = T;
-    fn index(&self, index: Registers) -> &Self::Output {
-        &self[index as usize]
-    }
-}
-
-impl<T> IndexMut<Registers> for [T; Registers::InstRet as usize] {
-    fn index_mut(&mut self, index: Registers) -> &mut Self::Output {
-        &mut self[index as usize]
-    }
-}
-
-enum Flags {
-    Pos = 1 << 0,
-    Zro = 1 << 1,
-    Neg = 1 << 2,
-}
-
-enum Operations {
-    Br,   // Branch
-    Add,  // Add
-    Ld,   // Load
-    St,   // Store
-    Jsr,  // Jump register
-    And,  // And
-    Ldr,  // Load register
-    Str,  // Store register
-    Rti,  // unused
-    Not,  // Not
-    Ldi,  // Load indirect
-    Sti,  // Store indirect
-    Jmp,  // Jump
-    Res,  // unused
-    Lea,  // Load effective address
-    Trap, // Execute trap
-}
-
-impl TryFrom<u16> for Operations {
-    type Error = Errors;
-    fn try_from(value: u16) -> Result<Operations, Self::Error> {
-        match value {
-            0 => Ok(Operations::Br),
-            1 => Ok(Operations::Add),
-            2 => Ok(Operations::Ld),
-            3 => Ok(Operations::St),
-            4 => Ok(Operations::Jsr),
-            5 => Ok(Operations::And),
-            6 => Ok(Operations::Ldr),
-            7 => Ok(Operations::Str),
-            8 => Ok(Operations::Rti),
-            9 => Ok(Operations::Not),
-            10 => Ok(Operations::Ldi),
-            11 => Ok(Operations::Sti),
-            12 => Ok(Operations::Jmp),
-            13 => Ok(Operations::Res),
-            14 => Ok(Operations::Lea),
-            15 => Ok(Operations::Trap),
-            op_code => Err(Errors::BadOpCode(op_code)),
-        }
+// A browser has no command line, a wasm32-unknown-unknown build is only the library, see `lc3::wasm`. WASI runs it
+#![cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), no_main)]
+#![cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+
+use lc3::address::{SymbolTable, parse_address, parse_address_range};
+use lc3::arguments::{DEFAULT_STACK_START, ProgramArguments};
+use lc3::assembler::format::{FormatOptions, format};
+use lc3::assertion::Assertion;
+use lc3::batch;
+use lc3::clock::VirtualClock;
+use lc3::compare;
+use lc3::conformance::{self, Engine};
+#[cfg(not(any(unix, windows)))]
+use lc3::console::BlockingStdinInput;
+use lc3::console::{DEFAULT_KEYBOARD_DEPTH, KeyboardOverflow};
+use lc3::determinism::{Nondeterminism, RunConfig};
+use lc3::device::console::ConsoleDevice;
+use lc3::device::counter::InstructionCounter;
+#[cfg(all(feature = "plugins", any(unix, windows)))]
+use lc3::device::plugin::PluginDevice;
+use lc3::device::serial::SerialPort;
+use lc3::device::timer::Timer;
+use lc3::diag;
+use lc3::disassembler::listing::{self, ColorChoice};
+use lc3::hang::HangCheck;
+use lc3::host_fs::HostFiles;
+use lc3::irq::{DeviceId, InterruptController};
+use lc3::keymap::Keymap;
+use lc3::layout::LoadMap;
+use lc3::os;
+use lc3::output_file::{self, AtomicWriter};
+use lc3::protect::CodeProtection;
+use lc3::recording::{Recorder, Replay};
+use lc3::scheduler::{self, Scheduler};
+use lc3::search;
+use lc3::self_modify::SelfModifyCheck;
+use lc3::snapshot;
+use lc3::stack::StackCheck;
+use lc3::stats;
+use lc3::step::Stepper;
+use lc3::summary::{self, RunSummary};
+#[cfg(any(unix, windows))]
+use lc3::terminal::{TerminalGuard, TerminalInput, TerminalRestore};
+use lc3::trace::jsonl::{self, JsonlDiffOptions};
+use lc3::trace::{InstructionClass, TraceFilter, TraceFormat};
+use lc3::warning::{WarningCode, WarningLevel};
+use lc3::watch_dump::{WatchDump, WatchDumpFormat};
+use lc3::write_log::WriteLog;
+use lc3::{
+    Error, Flags, LoadError, Registers, RuntimeError, State, Traps, UninitializedExec, assembler,
+    disassembler, file_management, protocol, run_with_budget, trace, trap_table,
+};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(unix, windows))]
+use std::sync::{Mutex, Once, PoisonError};
+use std::time::{Duration, Instant};
+use std::{env, fs, io, thread};
+
+/// Exit code after a Ctrl-C, the shell convention for a process stopped by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+/// Highest exit code for failed assertions, their number is the exit code up to here
+const MAX_ASSERTIONS_EXIT_CODE: usize = 125;
+/// A second Ctrl-C this soon after the first exits right away, for a program the first one couldn't stop
+#[cfg(any(unix, windows))]
+const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    // For every command, whatever the position
+    if args.iter().any(|argument| argument == "--no-color") {
+        args.retain(|argument| argument != "--no-color");
+        diag::disable_color();
     }
+    if args.iter().any(|argument| argument == "--durable") {
+        args.retain(|argument| argument != "--durable");
+        output_file::set_durable(true);
+    }
+    // Unwinding only drops the writers of the thread that panicked, and none of them with `panic = "abort"`
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        output_file::remove_pending();
+        default_hook(info);
+    }));
+    // The commands give the exit code of a program they ran, with EXIT
+    let result = match args.get(1).map(String::as_str) {
+        Some("asm") => assemble_file(&args[2..]),
+        Some("disasm") => disassemble_file(&args[2..]).map(|()| 0),
+        Some("fmt") => format_files(&args[2..]).map(|()| 0),
+        Some("dump") => dump_images(&args[2..]).map(|()| 0),
+        Some("diff-trace") => diff_trace_files(&args[2..]).map(|()| 0),
+        Some("diff") => diff_snapshots(&args[2..]).map(|()| 0),
+        Some("find") => find_words(&args[2..]).map(|()| 0),
+        Some("find-str") => find_text(&args[2..]).map(|()| 0),
+        Some("batch") => run_batch(&args[2..]).map(|()| 0),
+        Some("selftest") => self_test(&args[2..]).map(|()| 0),
+        Some("run") => vm(&args[2..]),
+        Some("--serve-stdio") => protocol::serve(io::stdin(), io::stdout()).map(|()| 0),
+        _ => vm(&args[1..]),
+    };
+    // Every guard of the command was dropped on its way out, the terminal is restored and the output flushed
+    exit(match result {
+        Ok(code) => code as i32,
+        // The state was already reported
+        Err(Error::Runtime(RuntimeError::Interrupted)) => INTERRUPTED_EXIT_CODE,
+        Err(Error::AssertionsFailed(failed)) => {
+            diag!("{}", Error::AssertionsFailed(failed));
+            failed.min(MAX_ASSERTIONS_EXIT_CODE) as i32
+        }
+        Err(e) => {
+            diag!("{}", e);
+            1
+        }
+    })
 }
 
-struct State {
-    memory: [u16; MEM_MAX],
-    registers: [u16; Registers::InstRet as usize],
-    running: bool,
+/// The only way out of the process, from the end of [`main`] or from the Ctrl-C handler which restores the terminal
+/// first: the output files not committed are removed (see [`output_file::remove_pending`]) before exiting with `code`.
+/// What is left in the buffer of stdout is flushed by [`std::process::exit`], without waiting on a thread writing to it
+fn exit(code: i32) -> ! {
+    output_file::remove_pending();
+    std::process::exit(code)
 }
 
-impl State {
-    pub fn default() -> State {
-        let mut state = State {
-            memory: [0_u16; MEM_MAX],
-            registers: [0_u16; Registers::InstRet as usize],
-            running: true,
-        };
-        state.register_write(Registers::Pc, PC_START);
-        state.register_write(Registers::Flags, Flags::Zro as u16);
-        state
+/// Assemble the source file into an object file, by default it is written next to the source with the .obj extension.
+/// The symbol table is written next to the object file with the .sym extension.
+/// Every diagnostic is printed to stderr, the object file is only written if none of them is an error
+/// With `--listing` the listing (address, word, source line and text of every word plus the symbol table) is written too.
+/// `--run` runs the program straight from memory once it assembled, with the options of a run after `--`
+/// (see [`run_images`]). The object file is then only written with `-o`
+/// * Usage: asm <source.asm> [-o <output.obj>] [--listing <output.lst>] [--run [-- <run options>...]]
+fn assemble_file(args: &[String]) -> Result<u8, Error> {
+    let source_path = args.first().ok_or(Error::FewArguments)?;
+    let mut output_path = None;
+    let mut listing_path = None;
+    let mut run = false;
+    let mut run_options: &[String] = &[];
+    let mut options = args[1..].iter().enumerate();
+    while let Some((index, option)) = options.next() {
+        match option.as_str() {
+            "--run" => run = true,
+            "--" => {
+                run_options = &args[index + 2..];
+                break;
+            }
+            "-o" => output_path = Some(PathBuf::from(options.next().ok_or(Error::FewArguments)?.1)),
+            "--listing" => listing_path = Some(options.next().ok_or(Error::FewArguments)?.1),
+            _ => return Err(Error::BadArgument(option.clone())),
+        }
+    }
+    if !run && !run_options.is_empty() {
+        return Err(Error::BadArgument("-- without --run".to_string()));
+    }
+    let (program, source) = assemble_source(source_path)?;
+    let output_path = match (output_path, run) {
+        (Some(path), _) => Some(path),
+        (None, false) => Some(Path::new(source_path).with_extension("obj")),
+        (None, true) => None,
+    };
+    if let Some(output_path) = output_path {
+        output_file::write(&output_path, program.to_object_bytes())?;
+        output_file::write(output_path.with_extension("sym"), program.symbol_table())?;
     }
+    if let Some(listing_path) = listing_path {
+        output_file::write(listing_path, program.listing(&source))?;
+    }
+    if !run {
+        return Ok(0);
+    }
+    Ok(run_assembled(vec![(source_path.clone(), program)], run_options)?.unwrap_or(0))
+}
 
-    pub fn memory_write(&mut self, address: usize, value: u16) {
-        self.memory[address] = value;
+/// Assemble the source file at `source_path`, printing its diagnostics to stderr. With the source, for the listing
+fn assemble_source(source_path: &str) -> Result<(assembler::Program, String), Error> {
+    let source = fs::read_to_string(source_path)?;
+    let program = match assembler::assemble(&source) {
+        Ok(program) => program,
+        Err(error) => {
+            for diagnostic in &error.diagnostics {
+                diag!("{}", diagnostic.render(source_path, &source));
+            }
+            return Err(error.into());
+        }
+    };
+    for warning in &program.warnings {
+        diag!("{}", warning.render(source_path, &source));
     }
+    Ok((program, source))
+}
 
-    pub fn memory_read(&mut self, address: usize) -> u16 {
-        if address == MemoryMappedRegisters::Kbsr as usize {
-            match check_key() {
-                Ok(rv) => {
-                    self.memory[MemoryMappedRegisters::Kbsr] = 1 << 15;
-                    self.memory[MemoryMappedRegisters::Kbdr] = rv
-                }
-                Err(_) => self.memory[MemoryMappedRegisters::Kbsr] = 0,
-            };
+/// Disassemble an object file into source that assembles back to the same words, with labels for the PC relative targets.
+/// Only the words reachable from the origin and the `--root` addresses are disassembled as instructions, the rest is data.
+/// `--all-code` disassembles every word instead, for programs that reach their code through JMP or JSRR.
+/// The source is printed unless an output path is given, on a terminal as a colored listing with the addresses and the
+/// words instead (see [`lc3::disassembler::listing`]), `--color` picks one or the other
+/// * Usage: disasm <image.obj> [-o <output.asm>] [--root <address>]... [--all-code] [--color auto|always|never]
+fn disassemble_file(args: &[String]) -> Result<(), Error> {
+    let path = args.first().ok_or(Error::FewArguments)?;
+    let mut output_path = None;
+    let mut roots = Vec::new();
+    let mut all_code = false;
+    let mut color = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "-o" => output_path = Some(options.next().ok_or(Error::FewArguments)?),
+            "--root" => roots.push(options.next().ok_or(Error::FewArguments)?),
+            "--all-code" => all_code = true,
+            "--color" => {
+                let choice = options.next().ok_or(Error::FewArguments)?;
+                color = Some(
+                    ColorChoice::from_name(choice)
+                        .ok_or_else(|| Error::BadArgument(choice.clone()))?,
+                );
+            }
+            _ => return Err(Error::BadArgument(option.clone())),
         }
-        self.memory[address]
     }
+    let (origin, words) = file_management::read_image(path)?;
+    let symbols = SymbolTable::read_beside(path)?;
+    let mut roots = roots
+        .into_iter()
+        .map(|root| parse_address(root, &symbols))
+        .collect::<Result<Vec<u16>, _>>()?;
+    roots.push(origin);
+    let code = match all_code {
+        true => vec![true; words.len()],
+        false => disassembler::reachable_code(origin, &words, &roots),
+    };
+    match (output_path, color.unwrap_or_default()) {
+        (Some(_), ColorChoice::Always) => {
+            return Err(Error::BadArgument("--color always with -o".to_string()));
+        }
+        (Some(output_path), _) => output_file::write(
+            output_path,
+            disassembler::disassemble_program(origin, &words, &code),
+        )?,
+        (None, color) if color.colored() => {
+            let lines = disassembler::disassemble_lines(origin, &words, &code);
+            print!("{}", listing::render_listing(&lines, true));
+        }
+        (None, _) => print!(
+            "{}",
+            disassembler::disassemble_program(origin, &words, &code)
+        ),
+    }
+    Ok(())
+}
 
-    pub fn register_read(&self, address: Registers) -> u16 {
-        self.registers[address]
+/// Write the images as a single object file. With `--format lc3tools` it is an lc3tools object file where every image is a section,
+/// the default `obj` format is a plain image so it needs a single section.
+/// Either kind of image can be read, the file is written to stdout unless an output path is given
+/// * Usage: dump <image.obj>... [--format obj|lc3tools] [-o <output.obj>]
+fn dump_images(args: &[String]) -> Result<(), Error> {
+    let mut paths = Vec::new();
+    let mut lc3tools = false;
+    let mut output_path = None;
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--format" => match options.next().ok_or(Error::FewArguments)?.as_str() {
+                "obj" => lc3tools = false,
+                "lc3tools" => lc3tools = true,
+                format => return Err(Error::BadArgument(format.to_string())),
+            },
+            "-o" => output_path = Some(options.next().ok_or(Error::FewArguments)?),
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path => paths.push(path.to_string()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    let mut sections = Vec::new();
+    for path in &paths {
+        sections.extend(file_management::read_sections(path)?);
     }
+    let bytes = match (lc3tools, sections.as_slice()) {
+        (true, _) => file_management::lc3tools::write(&sections),
+        (false, [(origin, words)]) => file_management::image_bytes(*origin, words),
+        (false, _) => return Err(LoadError::SeveralSections(sections.len()).into()),
+    };
+    match output_path {
+        Some(output_path) => output_file::write(output_path, bytes)?,
+        None => io::stdout().write_all(&bytes)?,
+    }
+    Ok(())
+}
 
-    pub fn register_write(&mut self, address: Registers, value: u16) {
-        self.registers[address] = value;
+/// Format assembler source files in place.
+/// `--0x` writes hexadecimal literals as `0x1F` and `--label-lines` puts every label on its own line.
+/// With `--check` nothing is written, the files that would change are printed and it fails if there is any
+/// * Usage: fmt <source.asm>... [--check] [--0x] [--label-lines]
+fn format_files(args: &[String]) -> Result<(), Error> {
+    let mut options = FormatOptions::default();
+    let mut check = false;
+    let mut paths = Vec::new();
+    for argument in args {
+        match argument.as_str() {
+            "--check" => check = true,
+            "--0x" => options.zero_x_hex = true,
+            "--label-lines" => options.labels_on_own_line = true,
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    let mut unformatted = 0;
+    for path in paths {
+        let source = fs::read_to_string(path)?;
+        let formatted = format(&source, &options);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            unformatted += 1;
+        } else {
+            output_file::write(path, formatted)?;
+        }
+    }
+    match unformatted {
+        0 => Ok(()),
+        amount => Err(Error::NotFormatted(amount)),
     }
+}
 
-    pub fn increment_pc(&mut self) {
-        self.registers[Registers::Pc] += 1;
+/// A duration like `5s`, `200ms` or `1.5` (seconds)
+fn parse_duration(text: &str) -> Result<Duration, Error> {
+    let (number, unit) = match text.strip_suffix("ms") {
+        Some(number) => (number, 1e-3),
+        None => (text.strip_suffix('s').unwrap_or(text), 1.0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * unit).ok())
+        .ok_or_else(|| Error::BadArgument(text.to_string()))
+}
+
+/// `timer=6` of `--irq`, the source of interrupts and its priority
+fn parse_irq_priority(text: &str) -> Result<(DeviceId, u8), Error> {
+    let bad = || Error::BadArgument(text.to_string());
+    let (device, priority) = text.split_once('=').ok_or_else(bad)?;
+    let device = DeviceId::from_name(device).ok_or_else(bad)?;
+    Ok((device, priority.parse().map_err(|_| bad())?))
+}
+
+/// `x4000:x4010@100000` of `--watch-dump`, the range and the instructions between the rows
+fn parse_watch_dump(text: &str, symbols: &SymbolTable) -> Result<(u16, u16, u64), Error> {
+    let bad = || Error::BadArgument(text.to_string());
+    let (range, every) = text.split_once('@').ok_or_else(bad)?;
+    let (start, end) = parse_address_range(range, symbols)?;
+    let every = every
+        .parse()
+        .ok()
+        .filter(|&every| every > 0)
+        .ok_or_else(bad)?;
+    Ok((start, end, every))
+}
+
+/// A watch dump appending to `path`, the CSV header first if the file is new or empty. What it writes only lands in
+/// `path` once the file returned with it is committed
+fn watch_dump(
+    path: &Path,
+    (start, end, every): (u16, u16, u64),
+    format: WatchDumpFormat,
+) -> Result<(WatchDump, AtomicWriter), Error> {
+    let mut file = AtomicWriter::append(path)?;
+    if format == WatchDumpFormat::Csv && file.existing_len() == 0 {
+        file.write_all(WatchDump::csv_header(start..=end).as_bytes())?;
     }
+    let output = Box::new(file.shared());
+    Ok((WatchDump::new(start..=end, every, format, output), file))
+}
+
+/// `x40=puts` of `--trap`, the vector and the routine of the VM it runs
+fn parse_trap_remap(text: &str) -> Result<(u8, Traps), Error> {
+    let bad = || Error::BadArgument(text.to_string());
+    let (vector, routine) = text.split_once('=').ok_or_else(bad)?;
+    let vector = u8::try_from(parse_address(vector, &SymbolTable::new())?).map_err(|_| bad())?;
+    Ok((vector, trap_table::native_trap(routine).ok_or_else(bad)?))
 }
 
-fn disable_input_buffering(termio: &mut Termios) -> Result<(), Errors> {
-    let new_tio = termio;
-    new_tio.c_lflag &= !ICANON & !ECHO;
-    match tcsetattr(io::stdin().as_raw_fd(), TCSANOW, new_tio) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(Errors::DisableInputBuffering),
+/// Compare two traces written with `--trace-format ref`, the first divergent step is printed with the state of both machines.
+/// Two `.jsonl` traces, like those of `--trace-format jsonl`, are compared field by field instead (see
+/// [`lc3::trace::jsonl`]), the first divergent step printed with `--context` lines before it from both. The wall-clock
+/// fields never count, every `--ignore` leaves out another one
+/// * Usage: diff-trace <ours.log> <theirs.log>
+/// * Usage: diff-trace <ours.jsonl> <theirs.jsonl> [--context <lines>] [--ignore <field>]...
+fn diff_trace_files(args: &[String]) -> Result<(), Error> {
+    let mut paths = Vec::new();
+    let mut options = JsonlDiffOptions::default();
+    let mut jsonl_options = false;
+    let mut arguments = args.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--context" => {
+                jsonl_options = true;
+                let lines = arguments.next().ok_or(Error::FewArguments)?;
+                options.context = lines
+                    .parse()
+                    .map_err(|_| Error::BadArgument(lines.clone()))?;
+            }
+            "--ignore" => {
+                jsonl_options = true;
+                let field = arguments.next().ok_or(Error::FewArguments)?;
+                options.ignored.push(field.clone());
+            }
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path => paths.push(path),
+        }
+    }
+    let [ours, theirs] = paths[..] else {
+        return Err(Error::FewArguments);
+    };
+    let jsonl = [ours, theirs].iter().all(|path| {
+        Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "jsonl")
+    });
+    let (ours, theirs) = (fs::read_to_string(ours)?, fs::read_to_string(theirs)?);
+    let divergence = match jsonl {
+        true => jsonl::diff_jsonl_traces(&ours, &theirs, &options)?,
+        false if jsonl_options => {
+            return Err(Error::BadArgument(
+                "--context or --ignore without two .jsonl traces".to_string(),
+            ));
+        }
+        false => trace::diff_traces(&ours, &theirs),
+    };
+    match divergence {
+        None => Ok(()),
+        Some((step, report)) => {
+            print!("{}", report);
+            Err(Error::TracesDiverge(step))
+        }
     }
 }
 
-fn restore_input_buffering(termio: &mut Termios) -> Result<(), Errors> {
-    match tcsetattr(io::stdin().as_raw_fd(), TCSANOW, termio) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(Errors::RestoreInputBuffering),
+/// Compare two snapshots written by `run --snapshot`, printing the registers and the ranges of memory that differ.
+/// With `--brief` only the counts are printed. Fails if they differ, like `diff-trace`
+/// * Usage: diff <ours.lc3snap> <theirs.lc3snap> [--brief]
+fn diff_snapshots(args: &[String]) -> Result<(), Error> {
+    let (brief, paths): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|argument| *argument == "--brief");
+    let [ours, theirs] = paths[..] else {
+        return Err(Error::FewArguments);
+    };
+    let ours = State::from_snapshot(&fs::read(ours).map_err(LoadError::File)?)?;
+    let theirs = State::from_snapshot(&fs::read(theirs).map_err(LoadError::File)?)?;
+    let diff = ours.diff(&theirs);
+    if diff.is_empty() {
+        return Ok(());
+    }
+    match brief.is_empty() {
+        true => print!("{}", diff),
+        false => print!("{}", diff.brief()),
     }
+    Err(Error::StatesDiffer(diff.registers.len(), diff.words()))
 }
 
-fn run_loop(state: &mut State) -> Result<(), Errors> {
-    while state.running {
-        // Get next instruction from memory, increment the PC by one and get the OP_CODE
-        let memory_address = state.register_read(Registers::Pc) as usize;
-        let instruction = state.memory_read(memory_address);
-        state.increment_pc();
-        run_step(instruction, state)?;
+/// Look for a sequence of words in the memory of an image or a snapshot written by `run --snapshot`, without running
+/// anything, and print every match with a few words around it, see [`lc3::search`]. The range and the words can use
+/// the labels of the `.sym` next to the image
+/// * Usage: find <image.obj|state.lc3snap> <start>:<end> <word>...
+fn find_words(args: &[String]) -> Result<(), Error> {
+    let [path, range, words @ ..] = args else {
+        return Err(Error::FewArguments);
+    };
+    if words.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    let (state, symbols) = memory_of(path)?;
+    let range = parse_address_range(range, &symbols)?;
+    let words = words
+        .iter()
+        .map(|word| parse_address(word, &symbols))
+        .collect::<Result<Vec<u16>, _>>()?;
+    for hit in search::find_words(&state, range, &words) {
+        println!("{}", search::describe(&state, &hit));
     }
     Ok(())
 }
 
-fn run_step(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let op_code = instruction >> 12;
-    let operation_code = Operations::try_from(op_code).unwrap(); // Since op_code is an u16 that was right shifted 12 bits, its maximum value is 15 (1111) that will always map in the try_from, so it will never fail, that's why the unwrap is used
-    match operation_code {
-        Operations::Br => conditional_branch(instruction, state),
-        Operations::Add => add(instruction, state)?,
-        Operations::Ld => load(instruction, state)?,
-        Operations::St => store(instruction, state)?,
-        Operations::Jsr => jump_to_subrutine(instruction, state)?,
-        Operations::And => and(instruction, state)?,
-        Operations::Ldr => load_register(instruction, state)?,
-        Operations::Str => store_register(instruction, state)?,
-        Operations::Rti => return Err(Errors::BadOpCode(Operations::Rti as u16)),
-        Operations::Not => not(instruction, state)?,
-        Operations::Ldi => load_indirect(instruction, state)?,
-        Operations::Sti => store_indirect(instruction, state)?,
-        Operations::Jmp => jump(instruction, state)?,
-        Operations::Res => return Err(Errors::BadOpCode(Operations::Res as u16)),
-        Operations::Lea => load_effective_address(instruction, state)?,
-        Operations::Trap => trap(instruction, state)?,
+/// [`find_words`] for a string, a character per word like `.STRINGZ` and packed two per word like PUTSP
+/// * Usage: find-str <image.obj|state.lc3snap> <start>:<end> <text>
+fn find_text(args: &[String]) -> Result<(), Error> {
+    let [path, range, text] = args else {
+        return Err(Error::FewArguments);
+    };
+    let bytes = text
+        .chars()
+        .map(u8::try_from)
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or_else(|| Error::BadArgument(text.clone()))?;
+    let (state, symbols) = memory_of(path)?;
+    let range = parse_address_range(range, &symbols)?;
+    for hit in search::find_text(&state, range, &bytes) {
+        println!("{}", search::describe(&state, &hit));
     }
     Ok(())
 }
 
-pub fn check_key() -> Result<u16, &'static str> {
-    let mut buffer = [0; 1];
-    match stdin()
-        .with_timeout(Duration::new(0, 0))
-        .read_exact(&mut buffer)
+/// A machine with the memory of a snapshot, or with an image loaded and the symbols beside it
+fn memory_of(path: &String) -> Result<(State, SymbolTable), Error> {
+    let bytes = fs::read(path).map_err(LoadError::File)?;
+    if snapshot::is_snapshot(&bytes) {
+        return Ok((State::from_snapshot(&bytes)?, SymbolTable::new()));
+    }
+    let (mut state, _) = State::headless();
+    for (origin, words) in file_management::load_sections(&bytes)? {
+        state.load_image(origin, &words);
+    }
+    Ok((state, SymbolTable::read_beside(path)?))
+}
+
+/// Run every job of a jobs file (see [`lc3::batch`]), each in a fresh machine, printing a table of how they went and
+/// writing them to a JSON report, by default next to the jobs file with the `.report.json` extension.
+/// With `--jobs` that many of them run at the same time. `--stats-out` appends a row of counters per job to a CSV file,
+/// see [`lc3::stats`]. Fails if any job failed
+/// * Usage: batch <jobs.toml> [--report <report.json>] [--jobs <count>] [--stats-out <stats.csv>]
+fn run_batch(args: &[String]) -> Result<(), Error> {
+    let mut jobs_file = None;
+    let mut report = None;
+    let mut stats_out: Option<PathBuf> = None;
+    let mut threads = 1;
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--report" => report = Some(options.next().ok_or(Error::FewArguments)?.into()),
+            "--stats-out" => stats_out = Some(options.next().ok_or(Error::FewArguments)?.into()),
+            "--jobs" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                threads = count
+                    .parse()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| Error::BadArgument(count.clone()))?;
+            }
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path if jobs_file.is_none() => jobs_file = Some(Path::new(path)),
+            path => return Err(Error::BadArgument(path.to_string())),
+        }
+    }
+    let jobs_file = jobs_file.ok_or(Error::FewArguments)?;
+    let directory = jobs_file.parent().unwrap_or(Path::new("."));
+    let mut jobs = batch::parse_jobs(&fs::read_to_string(jobs_file)?, directory)?;
+    for job in &mut jobs {
+        job.profile = stats_out.is_some();
+    }
+    let reports = batch::run_jobs(&jobs, threads);
+    if let Some(path) = stats_out {
+        let rows: Vec<String> = reports
+            .iter()
+            .filter_map(|report| report.stats_row.clone())
+            .collect();
+        stats::append_csv(&path, &rows)?;
+    }
+    print!("{}", batch::summary_table(&reports));
+    let report = report.unwrap_or_else(|| jobs_file.with_extension("report.json"));
+    output_file::write(report, batch::report_json(&reports))?;
+    match reports.iter().filter(|report| !report.passed).count() {
+        0 => Ok(()),
+        failed => Err(Error::JobsFailed(failed)),
+    }
+}
+
+/// Run the built-in conformance cases (see [`lc3::conformance`]) and print how each of them went, failing if any did.
+/// `--fast` runs them with the decode cache and the JIT, which must pass them just the same
+/// * Usage: selftest [--fast]
+fn self_test(args: &[String]) -> Result<(), Error> {
+    let engine = match args {
+        [] => Engine::Interpreter,
+        [fast] if fast == "--fast" => Engine::Fast,
+        [argument, ..] => return Err(Error::BadArgument(argument.clone())),
+    };
+    let outcomes = conformance::run_all(engine);
+    for outcome in &outcomes {
+        println!("{}", outcome);
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::CasesFailed(failed)),
+    }
+}
+
+/// [`run_images`], with the code of an EXIT trap as the exit code of the process
+fn vm(args: &[String]) -> Result<u8, Error> {
+    if args
+        .iter()
+        .any(|argument| argument == "--compare" || argument == "--compare-self")
     {
-        Ok(_) => Ok(buffer[0] as u16),
-        Err(_) => Err("Failed to read the value"),
+        return compare_images(args).map(|()| 0);
+    }
+    if args.iter().any(|argument| argument == "--watch") {
+        return watch_images(args).map(|()| 0);
     }
+    if args.iter().any(|argument| argument == "--instances") {
+        return run_instances(args).map(|()| 0);
+    }
+    Ok(run_images(args)?.unwrap_or(0))
 }
 
-fn main() {
-    match vm() {
-        Ok(_) => {}
-        Err(e) => {
-            println!("{}", e);
-            std::process::exit(1)
+/// Run several machines in this one process, an image each, taking turns of `--slice` instructions (1000 by default),
+/// see [`lc3::scheduler`]. `--serial-pair 0:1` connects the serial ports of the first and the second machine back to
+/// back, see [`lc3::device::serial`], a machine can only be in one pair. Every line a machine prints starts with its
+/// index like `[0] `, and what each one did goes after it: its error if it failed, its `--time` banner, its row of
+/// `--stats-out` and its summary in the JSON array of `--json-summary`. `--deterministic` gives each machine the
+/// virtual clock and leaves the wall time out, the keyboard is never read. Fails if any machine failed
+/// * Usage: [run] --instances <image.obj>,<image.obj>... [--serial-pair <index>:<index>]... [--slice <count>]
+///   [--max-steps <count>] [--time] [--stats-out <stats.csv>] [--json-summary <summaries.json>] [--deterministic]
+fn run_instances(args: &[String]) -> Result<(), Error> {
+    let mut paths: Vec<String> = Vec::new();
+    let mut pairs = Vec::new();
+    let mut slice = scheduler::DEFAULT_SLICE;
+    let mut max_steps = u64::MAX;
+    let mut time = false;
+    let mut stats_out: Option<PathBuf> = None;
+    let mut json_summary = None;
+    let mut deterministic = false;
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--instances" => {
+                let list = options.next().ok_or(Error::FewArguments)?;
+                paths.extend(
+                    list.split(',')
+                        .filter(|path| !path.is_empty())
+                        .map(String::from),
+                );
+            }
+            "--serial-pair" => {
+                let pair = options.next().ok_or(Error::FewArguments)?;
+                let bad = || Error::BadArgument(pair.clone());
+                let (first, second) = pair.split_once(':').ok_or_else(bad)?;
+                let first: usize = first.parse().map_err(|_| bad())?;
+                let second: usize = second.parse().map_err(|_| bad())?;
+                pairs.push((first, second, pair));
+            }
+            "--slice" | "--max-steps" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                let count = count
+                    .parse()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| Error::BadArgument(count.clone()))?;
+                match argument == "--slice" {
+                    true => slice = count,
+                    false => max_steps = count,
+                }
+            }
+            "--time" => time = true,
+            "--stats-out" => stats_out = Some(options.next().ok_or(Error::FewArguments)?.into()),
+            "--json-summary" => json_summary = Some(options.next().ok_or(Error::FewArguments)?),
+            "--deterministic" => deterministic = true,
+            flag => return Err(Error::BadArgument(flag.to_string())),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    let mut paired = vec![false; paths.len()];
+    for &(first, second, pair) in &pairs {
+        if first == second || first >= paths.len() || second >= paths.len() {
+            return Err(Error::BadArgument(pair.clone()));
         }
+        if paired[first] || paired[second] {
+            return Err(Error::BadArgument(pair.clone()));
+        }
+        paired[first] = true;
+        paired[second] = true;
+    }
+    let mut scheduler = Scheduler::new(slice);
+    for path in &paths {
+        let (mut state, output) = State::headless();
+        for (origin, words) in file_management::read_sections(path)? {
+            state.load_image_checked(origin, &words)?;
+        }
+        if deterministic {
+            state.set_clock(VirtualClock::default());
+        }
+        if stats_out.is_some() {
+            state.enable_profile();
+        } else if time {
+            state.enable_stats();
+        }
+        scheduler.add(path.clone(), state, output);
+    }
+    for (first, second, _) in pairs {
+        let (first_port, second_port) = SerialPort::pair();
+        scheduler.instances_mut()[first]
+            .state
+            .add_device(first_port)?;
+        scheduler.instances_mut()[second]
+            .state
+            .add_device(second_port)?;
+    }
+    let start = Instant::now();
+    scheduler.run(max_steps, &mut io::stdout().lock())?;
+    let elapsed = start.elapsed();
+    let mut failed = 0;
+    let mut rows = Vec::new();
+    let mut summaries = Vec::new();
+    // Every one of them ended by now
+    let running = Ok(());
+    for (index, instance) in scheduler.instances().iter().enumerate() {
+        let result = instance.result().unwrap_or(&running);
+        if let Err(error) = result {
+            diag!("[{}] {}", index, error);
+            failed += 1;
+        }
+        if time {
+            diag!("[{}] {}", index, time_banner(&instance.state, elapsed));
+        }
+        let wall_time = (!deterministic).then_some(elapsed);
+        rows.push(stats::csv_row(&instance.name, &instance.state, wall_time));
+        let summary = RunSummary::new(&instance.state, result, elapsed, &[]);
+        summaries.push(match deterministic {
+            true => summary.without_host_measures(),
+            false => summary,
+        });
+    }
+    // A report that can't be written fails the run once the others, the summaries first, were written
+    let mut reported = Ok(());
+    if let Some(path) = json_summary {
+        reported = output_file::write(path, summary::to_json_array(&summaries));
+    }
+    if let Some(path) = stats_out {
+        reported = reported.and(stats::append_csv(&path, &rows));
+    }
+    match failed {
+        0 => reported,
+        failed => Err(Error::InstancesFailed(failed)),
     }
 }
 
-fn vm() -> Result<(), Errors> {
-    let mut termio = Termios::from_fd(io::stdin().as_raw_fd()).map_err(|_| Errors::BadTermios)?;
-    let _ = ctrlc::set_handler(move || {
-        let _ = restore_input_buffering(&mut termio);
-        std::process::exit(1);
-    });
-    disable_input_buffering(&mut termio)?;
+/// How often `--watch` looks at the images by default
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Run the images like [`run_images`], then wait for one of them to change and run them again on a new machine, with a
+/// divider between the runs. The images are the `.obj` and `.asm` paths, a source is assembled again every run.
+/// The failure of a run is printed and the watch goes on, the terminal is only in raw mode while a program runs and a
+/// Ctrl-C while waiting exits. The modification times are looked at every `--watch-interval` (`500ms` by default)
+/// * Usage: [run] <image.obj>... --watch [--watch-interval <duration>] [the options of a run]
+fn watch_images(args: &[String]) -> Result<(), Error> {
+    let mut interval = DEFAULT_WATCH_INTERVAL;
+    let mut run_args = Vec::new();
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--watch" => {}
+            "--watch-interval" => {
+                interval = parse_duration(options.next().ok_or(Error::FewArguments)?)?
+            }
+            _ => run_args.push(argument.clone()),
+        }
+    }
+    let images: Vec<&String> = run_args
+        .iter()
+        .filter(|argument| {
+            !argument.starts_with("--")
+                && Path::new(argument)
+                    .extension()
+                    .is_some_and(|extension| extension == "obj" || extension == "asm")
+        })
+        .collect();
+    if images.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    loop {
+        let before = modification_times(&images);
+        match run_images(&run_args) {
+            Ok(None | Some(0)) => {}
+            Ok(Some(code)) => diag!("Exit code {}", code),
+            Err(Error::Runtime(RuntimeError::Interrupted)) => {}
+            // Every run would fail the same way
+            Err(error @ (Error::FewArguments | Error::BadArgument(_))) => return Err(error),
+            Err(error) => diag!("{}", error),
+        }
+        #[cfg(any(unix, windows))]
+        release_ctrl_c();
+        let changed = loop {
+            thread::sleep(interval);
+            let now = modification_times(&images);
+            if let Some(index) =
+                (0..images.len()).find(|&index| now[index].is_some() && now[index] != before[index])
+            {
+                break index;
+            }
+        };
+        diag!("---- {} changed, running again ----", images[changed]);
+    }
+}
+
+/// When every file was last modified, `None` for one that can't be read right now, like while it is written again
+fn modification_times(paths: &[&String]) -> Vec<Option<std::time::SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Run the images on two machines in lockstep, stopping at the first instruction after which they differ with both
+/// machines printed to stderr, see [`lc3::compare`]. The second machine runs the `--compare` images, or the same ones
+/// with `--compare-self`, always in the plain interpreter: `--fast` and the JIT only change the first one. It gets the
+/// keys the first one consumed and what it prints is thrown away. Fails if they diverge
+/// * Usage: [run] <image.obj>... (--compare <reference.obj>... | --compare-self) [--fast] [--no-jit]
+///   [--stdin-file <keys>] [--max-steps <count>]
+fn compare_images(args: &[String]) -> Result<(), Error> {
+    let mut paths = Vec::new();
+    let mut reference = Vec::new();
+    let mut compare_self = false;
+    let mut fast = false;
+    let mut jit = cfg!(feature = "jit");
+    let mut stdin_file = None;
+    let mut max_steps = u64::MAX;
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--compare" => reference.push(options.next().ok_or(Error::FewArguments)?.clone()),
+            "--compare-self" => compare_self = true,
+            "--fast" => fast = true,
+            "--no-jit" => jit = false,
+            "--stdin-file" => stdin_file = Some(options.next().ok_or(Error::FewArguments)?),
+            "--max-steps" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                max_steps = count
+                    .parse()
+                    .map_err(|_| Error::BadArgument(count.clone()))?;
+            }
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path => paths.push(path.to_string()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    if compare_self && !reference.is_empty() {
+        return Err(Error::BadArgument(
+            "--compare with --compare-self".to_string(),
+        ));
+    }
+    let reference = match compare_self {
+        true => paths.clone(),
+        false => reference,
+    };
+    let mut ours = State::default();
+    let mut theirs = State::default();
+    theirs.set_output(io::sink());
+    // Before the terminal goes in raw mode, like a run
+    for path in &paths {
+        file_management::read_file_to_memory(path, &mut ours)?;
+    }
+    for path in &reference {
+        file_management::read_file_to_memory(path, &mut theirs)?;
+    }
+    let terminal = match stdin_file {
+        Some(path) => {
+            let (leading, mirrored) = compare::mirrored_input(VecDeque::from(fs::read(path)?));
+            ours.set_input(leading);
+            theirs.set_input(mirrored);
+            None
+        }
+        None => {
+            let (keys, guard) = terminal_keys(&ours)?;
+            let (leading, mirrored) = compare::mirrored_input(keys);
+            ours.set_input(leading);
+            theirs.set_input(mirrored);
+            Some(guard)
+        }
+    };
+    if fast {
+        ours.enable_decode_cache();
+    }
+    #[cfg(feature = "jit")]
+    if jit {
+        ours.enable_jit();
+    }
+    #[cfg(not(feature = "jit"))]
+    let _ = jit;
+    #[cfg(any(unix, windows))]
+    handle_ctrl_c(&ours, terminal.as_ref().map(TerminalGuard::restore_handle));
+    #[cfg(not(any(unix, windows)))]
+    let _ = terminal;
+    match compare::run_in_lockstep(&mut ours, &mut theirs, max_steps) {
+        Ok(None) => Ok(()),
+        Ok(Some(divergence)) => {
+            diag!("{}", divergence);
+            Err(Error::MachinesDiverge(divergence.step))
+        }
+        // Without `--max-steps` there is no budget to run out of
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) if max_steps == u64::MAX => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Load the images and run them.
+/// With `--trace-format ref` a line per executed instruction is written to stderr in the reference simulator format,
+/// with `--trace-format jsonl` a JSON object per instruction, see [`lc3::trace::jsonl`].
+/// `--fast` decodes every instruction only once, see [`State::enable_decode_cache`].
+/// Built with the `jit` feature the hot loops are compiled to native code, `--no-jit` interprets everything.
+/// `--stdin-file` gives the program the bytes of a file as its keys instead of the terminal, all of them available right away.
+/// `--max-steps` stops a program still running after that many instructions and `--timeout` one still running after that many seconds.
+/// `--getc-nonblocking` makes GETC return 0 with the flags Z when no key is pending instead of waiting, IN still waits.
+/// `--input-timeout` (`5s`, `200ms`) stops a program whose GETC, IN or READLINE waits longer than that for a key, or
+/// whose GETC or IN finds the keys of `--stdin-file` or `--replay` over, see [`State::set_input_timeout`].
+/// Every `--device` loads a device plugin (see `include/lc3_device.h`), only with the `plugins` feature.
+/// `--json-summary` writes how the run ended as JSON (see [`lc3::summary`]) whatever the outcome,
+/// with the words of every `--report-mem` range in it.
+/// Reaching an address nothing was loaded to nor written prints a warning the first time, `--strict-exec` stops the program instead.
+/// `--stack` checks the use of R6 as the stack pointer in that region (see [`lc3::stack`]), printing warnings or,
+/// with `--strict-stack`, stopping the program.
+/// A store to an instruction of the image that runs before or after it is reported as self-modifying code (see
+/// [`lc3::self_modify`]) and listed at exit, `--forbid-self-modify` stops the program instead.
+/// Every warning has a code (see [`lc3::warning`]), the same one is printed once and the number of each is listed at
+/// exit. `--allow W003` ignores the warnings of that code and `--deny W003` stops the program at the first one. An
+/// image loaded over another is warned about too.
+/// `--protect-code` stops a program storing to any word loaded from an image but those in a `--writable` range, see
+/// [`lc3::protect`].
+/// `--detect-hang` stops a program going around a loop that changes nothing more than `--hang-threshold` times in a
+/// row, a loop being at most `--hang-window` instructions long (see [`lc3::hang`]). Polling the keyboard only counts
+/// as changing nothing with `--stdin-file`.
+/// `--time` prints a line of counters to stderr once the program stops, see [`time_banner`].
+/// `--stats-out` appends a row with the counters of the run, every opcode included, to a CSV file, see [`lc3::stats`].
+/// `--report-writes` lists every address the program stored to at exit, with the last value, how many stores and
+/// the last instruction storing there, leaving the `--stack` out. `--report-writes-json` writes it as JSON instead,
+/// see [`lc3::write_log`].
+/// `--snapshot` writes the registers and memory once the program stops, whatever happened, for `diff`.
+/// `--record` writes every key the program consumes with the instruction that consumed it, see [`lc3::recording`].
+/// Every `--assert-reg` and `--assert-mem` is checked once the program halts or runs out of time (see
+/// [`lc3::assertion`]), a line per assertion says whether it passed and the exit code is the number that failed.
+/// The failed ASSERT traps of the program count too, `--halt-on-assert-fail` stops it at the first one.
+/// A program that halts with the EXIT trap returns its exit code, unless an assertion failed.
+/// The DBG trap prints the registers to stderr, `--quiet` silences it.
+/// `--fs-root` lets the program open the files in that directory with the traps x30 to x33, see [`lc3::host_fs`].
+/// `--interrupts` lets the devices interrupt the program, see [`lc3::irq`], and `--timer` adds a timer that can,
+/// see [`lc3::device::timer`]. `--supervisor-stack` is where the handlers push, going past it stops the program.
+/// Every `--irq` gives a source of interrupts (`keyboard`, `timer` or a vector like `x82`) a priority from 0 to 7,
+/// see [`lc3::irq::InterruptController::set_priority`]. `--time` counts the interrupts taken and deferred.
+/// Every `--arg` and `--env` is written to memory for the program once the images are loaded, R0 and R1 pointing at
+/// them. They go at `--args-at` or right below the stack, see [`lc3::arguments`].
+/// `--console-device` adds the terminal size and cursor registers of [`lc3::device::console`], doing nothing when
+/// stdout isn't a terminal.
+/// `--builtin-os` boots the system of [`lc3::os`] before the program, the standard traps run in LC-3 there
+/// and it takes the interrupts and the exceptions.
+/// `--kb-buffer` keeps up to that many keys the program hasn't read yet, 16 with only `--kb-overflow`, which says
+/// whether a key arriving with the buffer full is lost or makes room by dropping the oldest, see
+/// [`lc3::console::KeyboardFifo`].
+/// `--keymap up=x11,down=x12` gives the program a single byte for each of those special keys instead of the escape
+/// sequence of the terminal, the others going through as they were, see [`lc3::keymap`].
+/// `--replay` gives the program the keys of such a recording instead of the keyboard, each at its instruction, and
+/// fails if the images aren't the recorded ones or the program doesn't consume the keys the same way. The devices get
+/// the virtual time of [`lc3::clock::VirtualClock`] meanwhile.
+/// `--banks` maps that many banks of 16K words at x8000 to xBFFF, the program selects one storing its number to BSR
+/// (xFE14), see [`lc3::bank`]. An `--assert-mem` names the bank it checks before its range, `2:x9000:x9003=...`.
+/// `--instruction-counter` maps the count of the instructions retired at ICLO (xFE16) and ICHI (xFE18), see
+/// [`lc3::device::counter`].
+/// `--trace-filter` only writes the lines of the instructions fetched from those ranges, `--trace-only` those of some
+/// classes of instructions (`branches`, `traps`, `stores`, `loads`, `alu`) and `--trace-after` those after that many
+/// instructions, see [`trace::TraceFilter`].
+/// `--step` waits for a key after every instruction, printing its line of the trace: space or Enter runs the next one,
+/// `c` the rest of the program and `q` stops it, see [`lc3::step`].
+/// `--watch-dump x4000:x4010@100000` appends the words of that range to `--watch-dump-out` (`watch-dump.txt` by
+/// default) every 100000 instructions, a row labelled with the instruction count. In hexadecimal or, with
+/// `--watch-dump-format csv`, as CSV, see [`lc3::watch_dump`].
+/// `--trap x40=puts` runs the PUTS of the VM for TRAP x40 too, see [`lc3::trap_table`].
+/// `--layout-check` prints the regions of the address space before the run, and warns with W005 about the stack or the
+/// arguments overlapping code, an entry point outside the images and an image in the device page, see [`lc3::layout`].
+/// `--deterministic` makes every run the same: the devices get the virtual time of [`lc3::clock::VirtualClock`] and
+/// `--json-summary` and `--stats-out` leave the wall time out. The keys must come from `--stdin-file` or `--replay`,
+/// and `--timeout`, `--device` and `--console-device` can't be used, see [`lc3::determinism`].
+/// What the VM says on stderr is dim yellow on a terminal, see [`lc3::diag`], `--no-color` (for every command)
+/// leaves it plain.
+/// Every file written goes under its name whole or not at all, see [`lc3::output_file`], `--durable` (for every
+/// command) syncs it to the disk too
+/// * Usage: [run] <image.obj>... [--trace-format ref|jsonl [--trace-filter <start>:<end>]... [--trace-only <classes>]
+///   [--trace-after <count>]] [--step] [--fast] [--no-jit] [--stdin-file <keys>] [--max-steps <count>]
+///   [--timeout <seconds>] [--input-timeout <duration>] [--getc-nonblocking] [--device <library>]... [--json-summary <summary.json>] [--report-mem <start>:<end>]...
+///   [--strict-exec] [--stack <start>:<end> [--strict-stack]] [--forbid-self-modify] [--allow <code>]... [--deny <code>]...
+///   [--protect-code [--writable <start>:<end>]...]
+///   [--detect-hang [--hang-threshold <laps>] [--hang-window <instructions>]] [--time]
+///   [--stats-out <stats.csv>]
+///   [--watch-dump <start>:<end>@<instructions> [--watch-dump-format hex|csv] [--watch-dump-out <file>]]
+///   [--report-writes] [--report-writes-json <writes.json>] [--snapshot <state.lc3snap>] [--record <session.lc3rec>]
+///   [--replay <session.lc3rec>] [--assert-reg <register>=<value>]... [--assert-mem <address>[:<end>]=<values>]...
+///   [--halt-on-assert-fail] [--quiet] [--fs-root <dir>] [--arg <text>]... [--env <key>=<value>]...
+///   [--args-at <address>] [--interrupts [--supervisor-stack <start>:<end>] [--irq <source>=<priority>]...] [--timer]
+///   [--kb-buffer <keys>] [--kb-overflow drop-new|drop-old] [--keymap <key>=<byte>,...] [--builtin-os]
+///   [--console-device] [--instruction-counter] [--banks <count>] [--trap <vector>=<routine>]... [--deterministic]
+///   [--layout-check] [--no-color] [--durable]
+/// * Usage: [run] <image.obj>... --watch [--watch-interval <duration>] [the options of a run], see [`watch_images`]
+/// * Usage: [run] --instances <image.obj>,<image.obj>... [--serial-pair <index>:<index>]..., see [`run_instances`]
+fn run_images(args: &[String]) -> Result<Option<u8>, Error> {
+    run_assembled(Vec::new(), args)
+}
+
+/// [`run_images`] with sources the built-in assembler already gave, each with its path. They are loaded first, then the
+/// images of `args`. A `.asm` image is assembled before the terminal goes in raw mode, its errors printed as usual
+fn run_assembled(
+    assembled: Vec<(String, assembler::Program)>,
+    args: &[String],
+) -> Result<Option<u8>, Error> {
+    let mut paths: Vec<String> = assembled.iter().map(|(path, _)| path.clone()).collect();
+    let mut traced = None;
+    let mut step = false;
+    let mut trace_filter = TraceFilter::new();
+    let mut trace_ranges = Vec::new();
+    let mut filtered = false;
+    let mut fast = false;
+    let mut jit = cfg!(feature = "jit");
+    let mut stdin_file = None;
+    let mut max_steps = u64::MAX;
+    let mut timeout = None;
+    let mut input_timeout = None;
+    let mut getc_nonblocking = false;
+    let mut json_summary = None;
+    let mut report_memory = Vec::new();
+    let mut devices = Vec::new();
+    let mut uninitialized_exec = UninitializedExec::Warn;
+    let mut stack = None;
+    let mut strict_stack = false;
+    let mut forbid_self_modify = false;
+    let mut warning_levels = Vec::new();
+    let mut protect_code = false;
+    let mut writable = Vec::new();
+    let mut detect_hang = false;
+    let mut time = false;
+    let mut stats_out: Option<PathBuf> = None;
+    let mut watched = None;
+    let mut watch_dump_format = None;
+    let mut watch_dump_out: Option<PathBuf> = None;
+    let mut report_writes = false;
+    let mut report_writes_json = None;
+    let mut snapshot = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut assertions = Vec::new();
+    let mut halt_on_assert_fail = false;
+    let mut quiet = false;
+    let mut fs_root = None;
+    let mut arguments = ProgramArguments::default();
+    let mut arguments_at = None;
+    let mut interrupts = false;
+    let mut supervisor_stack = None;
+    let mut irq_priorities = Vec::new();
+    let mut timer = false;
+    let mut keyboard_depth = None;
+    let mut builtin_os = false;
+    let mut layout_check = false;
+    let mut console_device = false;
+    let mut instruction_counter = false;
+    let mut banks = None;
+    let mut trap_remaps = Vec::new();
+    let mut keyboard_overflow = None;
+    let mut keymap = None;
+    let mut hang_threshold = None;
+    let mut hang_window = None;
+    let mut deterministic = false;
+    let mut options = args.iter();
+    while let Some(argument) = options.next() {
+        match argument.as_str() {
+            "--trace-format" => match options.next().ok_or(Error::FewArguments)?.as_str() {
+                "ref" => traced = Some(TraceFormat::Reference),
+                "jsonl" => traced = Some(TraceFormat::Jsonl),
+                format => return Err(Error::BadArgument(format.to_string())),
+            },
+            "--fast" => fast = true,
+            "--no-jit" => jit = false,
+            "--step" => step = true,
+            "--trace-filter" => {
+                trace_ranges.push(options.next().ok_or(Error::FewArguments)?);
+                filtered = true;
+            }
+            "--trace-only" => {
+                for name in options.next().ok_or(Error::FewArguments)?.split(',') {
+                    let class = InstructionClass::from_name(name)
+                        .ok_or_else(|| Error::BadArgument(name.to_string()))?;
+                    trace_filter = trace_filter.only(class);
+                }
+                filtered = true;
+            }
+            "--trace-after" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                trace_filter = trace_filter.after(
+                    count
+                        .parse()
+                        .map_err(|_| Error::BadArgument(count.clone()))?,
+                );
+                filtered = true;
+            }
+            "--strict-exec" => uninitialized_exec = UninitializedExec::Fail,
+            "--stack" => stack = Some(options.next().ok_or(Error::FewArguments)?),
+            "--strict-stack" => strict_stack = true,
+            "--forbid-self-modify" => forbid_self_modify = true,
+            "--allow" | "--deny" => {
+                let code = options.next().ok_or(Error::FewArguments)?;
+                let code =
+                    WarningCode::from_code(code).ok_or_else(|| Error::BadArgument(code.clone()))?;
+                let level = match argument.as_str() {
+                    "--allow" => WarningLevel::Allow,
+                    _ => WarningLevel::Deny,
+                };
+                warning_levels.push((code, level));
+            }
+            "--protect-code" => protect_code = true,
+            "--writable" => writable.push(options.next().ok_or(Error::FewArguments)?),
+            "--detect-hang" => detect_hang = true,
+            "--time" => time = true,
+            "--stats-out" => stats_out = Some(options.next().ok_or(Error::FewArguments)?.into()),
+            "--watch-dump" => watched = Some(options.next().ok_or(Error::FewArguments)?),
+            "--watch-dump-format" => match options.next().ok_or(Error::FewArguments)?.as_str() {
+                "hex" => watch_dump_format = Some(WatchDumpFormat::Hex),
+                "csv" => watch_dump_format = Some(WatchDumpFormat::Csv),
+                format => return Err(Error::BadArgument(format.to_string())),
+            },
+            "--watch-dump-out" => {
+                watch_dump_out = Some(options.next().ok_or(Error::FewArguments)?.into())
+            }
+            "--report-writes" => report_writes = true,
+            "--report-writes-json" => {
+                report_writes_json = Some(options.next().ok_or(Error::FewArguments)?)
+            }
+            "--snapshot" => snapshot = Some(options.next().ok_or(Error::FewArguments)?),
+            "--record" => record = Some(options.next().ok_or(Error::FewArguments)?),
+            "--replay" => replay = Some(options.next().ok_or(Error::FewArguments)?),
+            "--assert-reg" | "--assert-mem" => {
+                assertions.push((argument, options.next().ok_or(Error::FewArguments)?))
+            }
+            "--halt-on-assert-fail" => halt_on_assert_fail = true,
+            "--quiet" => quiet = true,
+            "--fs-root" => fs_root = Some(options.next().ok_or(Error::FewArguments)?),
+            "--arg" => arguments
+                .arguments
+                .push(options.next().ok_or(Error::FewArguments)?.clone()),
+            "--env" => {
+                let variable = options.next().ok_or(Error::FewArguments)?;
+                if !variable.contains('=') {
+                    return Err(Error::BadArgument(variable.clone()));
+                }
+                arguments.variables.push(variable.clone());
+            }
+            "--interrupts" => interrupts = true,
+            "--irq" => irq_priorities.push(parse_irq_priority(
+                options.next().ok_or(Error::FewArguments)?,
+            )?),
+            "--supervisor-stack" => {
+                supervisor_stack = Some(options.next().ok_or(Error::FewArguments)?)
+            }
+            "--timer" => timer = true,
+            "--builtin-os" => builtin_os = true,
+            "--layout-check" => layout_check = true,
+            "--console-device" => console_device = true,
+            "--instruction-counter" => instruction_counter = true,
+            "--banks" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                banks = Some(
+                    count
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or_else(|| Error::BadArgument(count.clone()))?,
+                );
+            }
+            "--trap" => trap_remaps.push(parse_trap_remap(
+                options.next().ok_or(Error::FewArguments)?,
+            )?),
+            "--kb-buffer" => {
+                let keys = options.next().ok_or(Error::FewArguments)?;
+                keyboard_depth = Some(keys.parse().map_err(|_| Error::BadArgument(keys.clone()))?);
+            }
+            "--kb-overflow" => match options.next().ok_or(Error::FewArguments)?.as_str() {
+                "drop-new" => keyboard_overflow = Some(KeyboardOverflow::DropNew),
+                "drop-old" => keyboard_overflow = Some(KeyboardOverflow::DropOld),
+                overflow => return Err(Error::BadArgument(overflow.to_string())),
+            },
+            "--keymap" => keymap = Some(Keymap::parse(options.next().ok_or(Error::FewArguments)?)?),
+            "--args-at" => arguments_at = Some(options.next().ok_or(Error::FewArguments)?),
+            "--hang-threshold" => {
+                let laps = options.next().ok_or(Error::FewArguments)?;
+                hang_threshold = Some(laps.parse().map_err(|_| Error::BadArgument(laps.clone()))?);
+            }
+            "--hang-window" => {
+                let instructions = options.next().ok_or(Error::FewArguments)?;
+                hang_window = Some(
+                    instructions
+                        .parse()
+                        .map_err(|_| Error::BadArgument(instructions.clone()))?,
+                );
+            }
+            "--stdin-file" => stdin_file = Some(options.next().ok_or(Error::FewArguments)?),
+            "--max-steps" => {
+                let count = options.next().ok_or(Error::FewArguments)?;
+                max_steps = count
+                    .parse()
+                    .map_err(|_| Error::BadArgument(count.clone()))?;
+            }
+            "--timeout" => {
+                let seconds = options.next().ok_or(Error::FewArguments)?;
+                let limit = seconds
+                    .parse()
+                    .ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| Error::BadArgument(seconds.clone()))?;
+                timeout = Some(limit);
+            }
+            "--getc-nonblocking" => getc_nonblocking = true,
+            "--deterministic" => deterministic = true,
+            "--input-timeout" => {
+                input_timeout = Some(parse_duration(options.next().ok_or(Error::FewArguments)?)?)
+            }
+            "--json-summary" => json_summary = Some(options.next().ok_or(Error::FewArguments)?),
+            "--device" => devices.push(options.next().ok_or(Error::FewArguments)?),
+            "--report-mem" => report_memory.push(options.next().ok_or(Error::FewArguments)?),
+            flag if flag.starts_with("--") => return Err(Error::BadArgument(flag.to_string())),
+            path => paths.push(path.to_string()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(Error::FewArguments);
+    }
+    let mut images: Vec<(String, Option<assembler::Program>)> = assembled
+        .into_iter()
+        .map(|(path, program)| (path, Some(program)))
+        .collect();
+    for path in &paths[images.len()..] {
+        let program = match Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension == "asm")
+        {
+            true => Some(assemble_source(path)?.0),
+            false => None,
+        };
+        images.push((path.clone(), program));
+    }
+    // The addresses of the options can name the labels of the images, known now
+    let mut symbols = SymbolTable::new();
+    for (path, program) in &images {
+        symbols.merge(match program {
+            Some(program) => SymbolTable::from(program),
+            None => SymbolTable::read_beside(path)?,
+        });
+    }
+    for range in trace_ranges {
+        let (start, end) = parse_address_range(range, &symbols)?;
+        trace_filter = trace_filter.address_range(start..=end);
+    }
+    let parse_range = |text: &String| parse_address_range(text, &symbols);
+    let stack = stack.map(parse_range).transpose()?;
+    let supervisor_stack = supervisor_stack.map(parse_range).transpose()?;
+    let writable = writable
+        .into_iter()
+        .map(parse_range)
+        .collect::<Result<Vec<_>, _>>()?;
+    let report_memory = report_memory
+        .into_iter()
+        .map(parse_range)
+        .collect::<Result<Vec<_>, _>>()?;
+    let arguments_at = arguments_at
+        .map(|text| parse_address(text, &symbols))
+        .transpose()?;
+    let watched = watched
+        .map(|text| parse_watch_dump(text, &symbols))
+        .transpose()?;
+    let assertions = assertions
+        .into_iter()
+        .map(|(option, text)| match option.as_str() {
+            "--assert-reg" => Assertion::parse_register(text),
+            _ => Assertion::parse_memory(text, &symbols),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if filtered && traced.is_none() {
+        return Err(Error::BadArgument(
+            "--trace-filter, --trace-only or --trace-after without --trace-format".to_string(),
+        ));
+    }
+    if watched.is_none() && (watch_dump_format.is_some() || watch_dump_out.is_some()) {
+        return Err(Error::BadArgument(
+            "--watch-dump-format or --watch-dump-out without --watch-dump".to_string(),
+        ));
+    }
+    if !irq_priorities.is_empty() && !interrupts {
+        return Err(Error::BadArgument("--irq without --interrupts".to_string()));
+    }
+    if strict_stack && stack.is_none() {
+        return Err(Error::BadArgument(
+            "--strict-stack without --stack".to_string(),
+        ));
+    }
+    if !protect_code && !writable.is_empty() {
+        return Err(Error::BadArgument(
+            "--writable without --protect-code".to_string(),
+        ));
+    }
+    if !detect_hang && (hang_threshold.is_some() || hang_window.is_some()) {
+        return Err(Error::BadArgument(
+            "--hang-threshold or --hang-window without --detect-hang".to_string(),
+        ));
+    }
+    let mut config = RunConfig::new(deterministic);
+    if replay.is_none() {
+        // A replay already runs under a virtual clock
+        config.declare("the clock", Nondeterminism::HostClock);
+        if stdin_file.is_none() {
+            config.declare(
+                "the keyboard (neither --stdin-file nor --replay)",
+                Nondeterminism::Keyboard,
+            );
+        }
+    }
+    if timeout.is_some() {
+        config.declare("--timeout", Nondeterminism::HostLimit);
+    }
+    for library in &devices {
+        config.declare(format!("--device {}", library), Nondeterminism::HostDevice);
+    }
+    if console_device {
+        config.declare("--console-device", Nondeterminism::HostDevice);
+    }
+    if json_summary.is_some() {
+        config.declare("--json-summary", Nondeterminism::HostMeasure);
+    }
+    if stats_out.is_some() {
+        config.declare("--stats-out", Nondeterminism::HostMeasure);
+    }
+    let plan = config.validate()?;
+    let replay = match replay {
+        Some(path) => {
+            let replay = Replay::parse(&fs::read_to_string(path).map_err(LoadError::File)?)?;
+            let images = read_images(&paths)?;
+            replay.check_images(&named_images(&paths, &images))?;
+            Some(replay)
+        }
+        None => None,
+    };
+    let scripted_input = stdin_file.is_some() || replay.is_some();
+    // Read now, a missing or broken image fails before the terminal goes in raw mode
+    let mut sections = Vec::new();
+    for (path, program) in &images {
+        match program {
+            Some(program) => sections.push((program.origin, program.words.clone())),
+            None => sections.extend(file_management::read_sections(path)?),
+        }
+    }
     // Initialize default state
     let mut state = State::default();
-    // Read file
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        return Err(Errors::FewArguments);
-    }
-    let paths = &args[1..].to_vec();
-    for p in paths {
-        file_management::read_file_to_memory(p, &mut state)?;
-    }
-    // Run the program
-    run_loop(&mut state)?;
-    restore_input_buffering(&mut termio)?;
+    if let Some(path) = stdin_file {
+        state.set_input(VecDeque::from(fs::read(path)?));
+    }
+    if timer {
+        state.add_device(Timer::default())?;
+    }
+    if console_device {
+        state.add_device(console_device_for_stdout())?;
+    }
+    if instruction_counter {
+        state.add_device(InstructionCounter::default())?;
+    }
+    if let Some(count) = banks {
+        state.enable_banks(count)?;
+    }
+    let bank_count = state.bank_count().unwrap_or(0);
+    if let Some(bank) = assertions
+        .iter()
+        .filter_map(Assertion::bank)
+        .find(|&bank| bank >= bank_count)
+    {
+        return Err(Error::BadArgument(format!(
+            "--assert-mem in bank {}, there are {} bank(s)",
+            bank, bank_count
+        )));
+    }
+    add_devices(&mut state, &devices)?;
+    if interrupts {
+        state.set_interrupt_controller(Some(match supervisor_stack {
+            Some((start, end)) => InterruptController::new(start..=end),
+            None => InterruptController::default(),
+        }));
+    }
+    for (device, priority) in irq_priorities {
+        state.set_irq_priority(device, priority)?;
+    }
+    state.set_uninitialized_exec(uninitialized_exec);
+    for (code, level) in warning_levels {
+        state.set_warning_level(code, level);
+    }
+    state.set_stack_check(stack.map(|(start, end)| StackCheck::new(start, end, strict_stack)));
+    state.set_self_modify_check(Some(SelfModifyCheck::new(forbid_self_modify)));
+    if report_writes || report_writes_json.is_some() {
+        state.set_write_log(Some(match stack {
+            Some((start, end)) => WriteLog::new().excluding(start..=end),
+            None => WriteLog::new(),
+        }));
+    }
+    if protect_code {
+        let protection = writable
+            .into_iter()
+            .fold(CodeProtection::new(), |protection, (start, end)| {
+                protection.writable(start..=end)
+            });
+        state.set_code_protection(Some(protection));
+    }
+    for (vector, routine) in trap_remaps {
+        state.trap_table_mut().set(vector, routine);
+    }
+    state.set_hang_check(detect_hang.then(|| {
+        HangCheck::new(
+            hang_threshold.unwrap_or(HangCheck::DEFAULT_THRESHOLD),
+            hang_window.unwrap_or(HangCheck::DEFAULT_WINDOW),
+            scripted_input,
+        )
+    }));
+    state.set_halt_on_assert_fail(halt_on_assert_fail);
+    state.set_quiet(quiet);
+    if let Some(root) = fs_root {
+        state.set_host_files(Some(HostFiles::new(root)?));
+    }
+    if stats_out.is_some() {
+        state.enable_profile();
+    } else if time {
+        state.enable_stats();
+    }
+    // The files written all along the run, committed once it is over
+    let mut streamed = Vec::new();
+    if let Some(watched) = watched {
+        let path = watch_dump_out.unwrap_or_else(|| "watch-dump.txt".into());
+        let (dump, file) = watch_dump(&path, watched, watch_dump_format.unwrap_or_default())?;
+        state.set_watch_dump(Some(dump));
+        streamed.push(file);
+    }
+    if let Some(path) = record {
+        let (recorder, file) = recorder(path, &paths)?;
+        state.start_recording(recorder);
+        streamed.push(file);
+    }
+    if let Some(replay) = replay {
+        state.start_replay(replay);
+        // A device reading the time of the host would run differently than when it was recorded
+        state.set_clock(VirtualClock::default());
+    }
+    if plan.virtual_clock {
+        state.set_clock(VirtualClock::default());
+    }
+    if let Some(granule) = plan.tick_granule {
+        state.set_tick_granule(granule);
+    }
+    state.set_input_timeout(input_timeout);
+    state.set_getc_nonblocking(getc_nonblocking);
+    if fast {
+        state.enable_decode_cache();
+    }
+    #[cfg(feature = "jit")]
+    if jit {
+        state.enable_jit(); // Without support for this machine everything is interpreted
+    }
+    #[cfg(not(feature = "jit"))]
+    let _ = jit;
+    let arguments_at = match arguments_at {
+        Some(start) => start,
+        None => arguments.start_below(stack.map_or(DEFAULT_STACK_START, |(start, _)| start))?,
+    };
+    if layout_check {
+        config.stack = stack;
+        config.arguments = (!arguments.is_empty()).then(|| {
+            (
+                arguments_at,
+                arguments_at.saturating_add(arguments.size() as u16 - 1),
+            )
+        });
+        let map = LoadMap::from_sections(&sections, state.register_read(Registers::Pc));
+        diag!("{}", map.table(&config));
+        state.check_layout(&map, &config)?;
+    }
+    // As late as it can be, whatever failed until here left the terminal alone. From here on the guards restore it on
+    // any way out, even a panic. The keys all come from the recording with a replay, the keyboard isn't read
+    let terminal = match scripted_input {
+        true => None,
+        false => Some(attach_terminal(&mut state)?),
+    };
+    // Without the keyboard the program's, `--step` reads the commands from a terminal of its own
+    let (mode, command_terminal) = match (step, &terminal) {
+        (false, _) => match traced {
+            Some(format) => (RunMode::Traced(trace_filter, format), None),
+            None => (RunMode::Free, None),
+        },
+        (true, Some(_)) => (RunMode::Stepped(Stepper::new()), None),
+        (true, None) => {
+            let (keys, guard) = terminal_keys(&state)?;
+            (RunMode::Stepped(Stepper::with_commands(keys)), Some(guard))
+        }
+    };
+    // Translated before the buffer, a key it holds is a single byte
+    if let Some(keymap) = keymap {
+        state.translate_keys(keymap);
+    }
+    if keyboard_depth.is_some() || keyboard_overflow.is_some() {
+        state.buffer_keyboard(
+            keyboard_depth.unwrap_or(DEFAULT_KEYBOARD_DEPTH),
+            keyboard_overflow.unwrap_or_default(),
+        );
+    }
+    // Only in debug builds, what `cargo test` runs, a release binary doesn't panic on request
+    #[cfg(debug_assertions)]
+    if env::var_os("LC3_PANIC_FOR_TESTS").is_some() {
+        panic!("Deliberate panic asked by LC3_PANIC_FOR_TESTS");
+    }
+    #[cfg(any(unix, windows))]
+    handle_ctrl_c(
+        &state,
+        terminal
+            .as_ref()
+            .or(command_terminal.as_ref())
+            .map(TerminalGuard::restore_handle),
+    );
+    #[cfg(not(any(unix, windows)))]
+    let _ = (terminal, command_terminal);
+    let timed_out = match timeout {
+        Some(limit) => Some((limit, start_timer(&state, limit)?)),
+        None => None,
+    };
+    let start = Instant::now();
+    let result = load_and_run(
+        &mut state,
+        &sections,
+        &arguments,
+        arguments_at,
+        builtin_os,
+        mode,
+        max_steps,
+    )
+    .and_then(|_| state.finish_replay());
+    let result = match (result, &timed_out) {
+        (Err(Error::Runtime(RuntimeError::Interrupted)), Some((limit, expired)))
+            if expired.load(Ordering::Relaxed) =>
+        {
+            Err(RuntimeError::TimedOut(*limit).into())
+        }
+        (result, _) => result,
+    };
+    if result.as_ref().is_err_and(Error::is_interrupted) {
+        diag!("{}", interrupt_report(&state));
+    }
+    diag!("{}", self_modify_report(&state));
+    diag!("{}", state.warnings().report());
+    // A report that can't be written fails the run once the others, the summary first, were written
+    let mut reported = Ok(());
+    if let Some(log) = state.write_log() {
+        match &report_writes_json {
+            Some(path) => reported = output_file::write(path, log.to_json()),
+            None => diag!("{}", log),
+        }
+    }
+    if time {
+        diag!("{}", time_banner(&state, start.elapsed()));
+    }
+    if let Some(path) = stats_out {
+        let elapsed = plan.host_measures.then(|| start.elapsed());
+        let row = stats::csv_row(&paths.join(" "), &state, elapsed);
+        reported = reported.and(stats::append_csv(&path, &[row]));
+    }
+    // Written whatever happened, a grader reads it for the failed runs too
+    if let Some(path) = json_summary {
+        let summary = RunSummary::new(&state, &result, start.elapsed(), &report_memory);
+        let summary = match plan.host_measures {
+            true => summary,
+            false => summary.without_host_measures(),
+        };
+        output_file::write(path, summary.to_json())?;
+    }
+    if let Some(path) = snapshot {
+        output_file::write(path, state.to_snapshot())?;
+    }
+    let recorded = state.stop_recording();
+    let dumped = state.finish_watch_dump();
+    // Whatever happened to the program, as long as the recording and the dump were written whole
+    let committed = match (&recorded, &dumped) {
+        (Ok(()), Ok(())) => streamed.into_iter().try_for_each(AtomicWriter::commit),
+        _ => Ok(()),
+    };
+    let result = result
+        .and(reported)
+        .and(recorded)
+        .and(dumped)
+        .and(committed);
+    let failed_traps = state
+        .trap_assertions()
+        .iter()
+        .filter(|assertion| !assertion.passed)
+        .count();
+    let result = match result {
+        Ok(())
+        | Err(Error::Runtime(RuntimeError::BudgetExhausted(_) | RuntimeError::TimedOut(_)))
+            if !assertions.is_empty() || failed_traps > 0 =>
+        {
+            check_assertions(&state, &assertions, failed_traps).and(result)
+        }
+        result => result,
+    };
+    result.map(|()| state.exit_code())
+}
+
+/// Print whether every assertion passed, failing with how many didn't counting the `failed_traps` ASSERT traps
+fn check_assertions(
+    state: &State,
+    assertions: &[Assertion],
+    failed_traps: usize,
+) -> Result<(), Error> {
+    let mut failed = failed_traps;
+    for assertion in assertions {
+        let outcome = assertion.check(state);
+        if !outcome.passed() {
+            failed += 1;
+        }
+        diag!("{}", outcome);
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::AssertionsFailed(failed)),
+    }
+}
+
+/// A recorder writing to `path`, its header lists the images with their hashes. The recording only lands in `path`
+/// once the file returned with it is committed
+fn recorder(path: &str, paths: &[String]) -> Result<(Recorder, AtomicWriter), Error> {
+    let images = read_images(paths)?;
+    let file = AtomicWriter::create(path)?;
+    let recorder = Recorder::new(Box::new(file.shared()), &named_images(paths, &images))?;
+    Ok((recorder, file))
+}
+
+/// The bytes of every image, for their hashes
+fn read_images(paths: &[String]) -> Result<Vec<Vec<u8>>, Error> {
+    Ok(paths
+        .iter()
+        .map(fs::read)
+        .collect::<Result<_, _>>()
+        .map_err(LoadError::File)?)
+}
+
+/// Every image with its path, like [`Recorder::new`] and [`Replay::check_images`] take them
+fn named_images<'a>(paths: &'a [String], images: &'a [Vec<u8>]) -> Vec<(&'a str, &'a [u8])> {
+    paths
+        .iter()
+        .map(String::as_str)
+        .zip(images.iter().map(Vec::as_slice))
+        .collect()
+}
+
+/// What `--time` prints, `key=value` pairs on a single line that scripts can split on spaces. The keys never change
+/// meaning, new ones only go at the end:
+/// ```text
+/// time: instructions=13 wall_ms=0.215 mips=0.06 traps=1 footprint=6 trap_x25=1
+/// ```
+/// `footprint` is the words loaded or written, and there is a `trap_x..` for every vector executed. With
+/// `--kb-buffer` or `--kb-overflow` `keys_dropped` is the keys lost to a full buffer, and `getc_empty` the GETC of
+/// `--getc-nonblocking` that found no key, if any. With `--interrupts` `irq_x.._taken` and `irq_x.._deferred` count
+/// the interrupts of every vector that asked for one
+fn time_banner(state: &State, elapsed: Duration) -> String {
+    let instructions = state.instructions_executed();
+    let seconds = elapsed.as_secs_f64();
+    let mips = match seconds > 0.0 {
+        true => instructions as f64 / seconds / 1e6,
+        false => 0.0,
+    };
+    let mut banner = format!(
+        "time: instructions={} wall_ms={:.3} mips={:.2}",
+        instructions,
+        seconds * 1e3,
+        mips
+    );
+    if let Some(stats) = state.stats() {
+        let _ = write!(
+            banner,
+            " traps={} footprint={}",
+            stats.traps(),
+            state.memory_footprint()
+        );
+        for (vector, count) in stats.traps_by_vector() {
+            let _ = write!(banner, " trap_x{:02X}={}", vector, count);
+        }
+        if let Some(dropped) = state.keys_dropped() {
+            let _ = write!(banner, " keys_dropped={}", dropped);
+        }
+        if stats.empty_getcs() > 0 {
+            let _ = write!(banner, " getc_empty={}", stats.empty_getcs());
+        }
+        let interrupts = state
+            .interrupt_controller()
+            .map(InterruptController::interrupt_counts)
+            .unwrap_or_default();
+        for (vector, counts) in interrupts {
+            let _ = write!(
+                banner,
+                " irq_x{:02X}_taken={} irq_x{:02X}_deferred={}",
+                vector, counts.taken, vector, counts.deferred
+            );
+        }
+    }
+    banner
+}
+
+/// Every store that modified the program and how many times, nothing if the program never did
+fn self_modify_report(state: &State) -> String {
+    let modifications = state.self_modifications();
+    let mut report = String::new();
+    if modifications.is_empty() {
+        return report;
+    }
+    report.push_str("Self-modifying code:\n");
+    for modification in modifications {
+        let _ = writeln!(
+            report,
+            "  x{:04X} modified the instruction at x{:04X} {} time(s)",
+            modification.writer, modification.target, modification.times
+        );
+    }
+    report
+}
+
+/// Load the device plugins, they are unloaded when the state is dropped
+#[cfg(all(feature = "plugins", any(unix, windows)))]
+fn add_devices(state: &mut State, libraries: &[&String]) -> Result<(), Error> {
+    for library in libraries {
+        let device = PluginDevice::load(Path::new(library))?;
+        state.add_device(device)?;
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use crate::*;
+#[cfg(not(all(feature = "plugins", any(unix, windows))))]
+fn add_devices(_state: &mut State, libraries: &[&String]) -> Result<(), Error> {
+    match libraries.first() {
+        Some(_) => Err(Error::BadArgument("--device".to_string())),
+        None => Ok(()),
+    }
+}
 
-    #[test]
-    fn loop_test() {
-        let mut state = State {
-            memory: [0_u16; MEM_MAX],
-            registers: [0_u16; Registers::InstRet as usize],
-            running: true,
-        };
-        state.memory_write(50, 25689);
-        state.memory_write(25689, 25);
-        state.memory_write(56, 777);
-        state.memory_write(9, 50);
-        state.register_write(Registers::Pc, 10);
-        state.memory_write(10, 0xAA27); // Load indirect 25 to R5
-        state.memory_write(11, 0x27FD); // Load 50 to R3
-        state.memory_write(12, 0x12C5); // Add R3 + R5 into R1
-        state.memory_write(13, 0x56E0); // Clear R3 by doing R3 AND 0x0
-        state.memory_write(14, 0x0405); // Branch to 20 if flag Z = 1
-        state.memory_write(20, 0x96FF); // Negate R3
-        state.memory_write(21, 0xC140); // Jump to the value at R5 PC = 25
-        state.memory_write(25, 0x635F); // Load register R1 with R5 + 40
-        state.memory_write(26, 0x4048); // Jump to the value at register 1, R7 = 27, PC = 777
-        state.memory_write(777, 0xB34C); // Save at memory address 0 the value from register 1
-        state.memory_write(778, 0x3E03); // Save R7 into 782
-        state.memory_write(779, 0x7A40); // Save R5 into 777
-        state.memory_write(780, 0xF025); // Halt
-        let _ = run_loop(&mut state);
-        assert_eq!(state.memory_read(0), 777);
-        assert_eq!(state.memory_read(782), 27);
-        assert_eq!(state.memory_read(777), 25);
-        assert_eq!(state.register_read(Registers::R7), 27);
+/// How [`load_and_run`] runs the program
+enum RunMode {
+    Free,
+    /// With a line on stderr per instruction the filter lets through
+    Traced(TraceFilter, TraceFormat),
+    /// With a line per instruction on stderr, waiting for a key after each, see [`lc3::step`]
+    Stepped(Stepper),
+}
+
+/// Load the sections of the images, then the arguments of the program at their address (nothing without any), and run it
+fn load_and_run(
+    state: &mut State,
+    sections: &[(u16, Vec<u16>)],
+    arguments: &ProgramArguments,
+    arguments_at: u16,
+    builtin_os: bool,
+    mode: RunMode,
+    max_steps: u64,
+) -> Result<(), Error> {
+    for (origin, words) in sections {
+        state.load_image_checked(*origin, words)?;
+    }
+    if !arguments.is_empty() {
+        arguments.write(state, arguments_at)?;
+    }
+    if builtin_os {
+        let start = state.register_read(Registers::Pc);
+        os::boot_minimal_os(state, start)?;
+    }
+    let result = match mode {
+        RunMode::Free => run_with_budget(state, max_steps),
+        RunMode::Traced(filter, format) => {
+            trace::run_filtered_with_budget(state, &mut diag::stderr(), &filter, format, max_steps)
+        }
+        RunMode::Stepped(mut stepper) => stepper.run(state, &mut diag::stderr(), max_steps),
+    };
+    match result {
+        // Without `--max-steps` there is no budget to run out of
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) if max_steps == u64::MAX => Ok(()),
+        result => result,
+    }
+}
+
+/// The terminal size and cursor registers, headless if stdout is redirected
+#[cfg(any(unix, windows))]
+fn console_device_for_stdout() -> ConsoleDevice {
+    use std::io::IsTerminal;
+    match io::stdout().is_terminal() {
+        true => ConsoleDevice::terminal(),
+        false => ConsoleDevice::headless(),
+    }
+}
+
+/// Without a terminal to ask, like under WASI, the registers have the fixed size
+#[cfg(not(any(unix, windows)))]
+fn console_device_for_stdout() -> ConsoleDevice {
+    ConsoleDevice::headless()
+}
+
+/// Interrupt the program once `limit` has passed, the returned flag tells the interrupt apart from a Ctrl-C
+fn start_timer(state: &State, limit: Duration) -> Result<Arc<AtomicBool>, Error> {
+    let interrupt = state.interrupt_handle();
+    let expired = Arc::new(AtomicBool::new(false));
+    let timer_expired = expired.clone();
+    thread::Builder::new()
+        .name("timeout".to_string())
+        .spawn(move || {
+            thread::sleep(limit);
+            timer_expired.store(true, Ordering::Relaxed);
+            interrupt.request();
+        })
+        // Without threads, like under WASI, there is nothing to wake the run loop
+        .map_err(|_| Error::BadArgument("--timeout".to_string()))?;
+    Ok(expired)
+}
+
+/// Put the terminal in raw mode and read the program's keys from it, it is restored when the guard is dropped
+#[cfg(any(unix, windows))]
+fn attach_terminal(state: &mut State) -> Result<TerminalGuard, Error> {
+    let (terminal, guard) = terminal_keys(state)?;
+    state.set_input(terminal);
+    Ok(guard)
+}
+
+/// The keyboard of the terminal in raw mode, waking up `state` when it is interrupted. The terminal is restored when
+/// the guard is dropped
+#[cfg(any(unix, windows))]
+fn terminal_keys(state: &State) -> Result<(TerminalInput, TerminalGuard), Error> {
+    let mut terminal = TerminalInput::stdin()?;
+    let guard = TerminalGuard::new(&terminal);
+    terminal.wake_on(state.interrupt_handle());
+    Ok((terminal, guard))
+}
+
+/// Without a terminal to put in raw mode, like under WASI, the keys are read from stdin as they come, see [`BlockingStdinInput`]
+#[cfg(not(any(unix, windows)))]
+fn attach_terminal(state: &mut State) -> Result<(), Error> {
+    state.set_input(BlockingStdinInput);
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_keys(_state: &State) -> Result<(BlockingStdinInput, ()), Error> {
+    Ok((BlockingStdinInput, ()))
+}
+
+/// The machine a Ctrl-C stops and the terminal it gives back, see [`handle_ctrl_c`]. None while `--watch` waits
+#[cfg(any(unix, windows))]
+static CTRL_C_TARGET: Mutex<Option<(lc3::InterruptHandle, Option<TerminalRestore>)>> =
+    Mutex::new(None);
+
+/// The first Ctrl-C stops the program where it is, the run loop returns and the state is reported.
+/// A second one soon after gives the terminal back and exits, like one while nothing runs (see [`release_ctrl_c`]).
+/// The handler is installed once, every run of `--watch` points it at its own machine
+#[cfg(any(unix, windows))]
+fn handle_ctrl_c(state: &State, restore: Option<TerminalRestore>) {
+    *CTRL_C_TARGET.lock().unwrap_or_else(PoisonError::into_inner) =
+        Some((state.interrupt_handle(), restore));
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let mut first_interrupt: Option<Instant> = None;
+        let _ = ctrlc::set_handler(move || {
+            let target = CTRL_C_TARGET.lock().unwrap_or_else(PoisonError::into_inner);
+            let Some((interrupt, restore)) = &*target else {
+                exit(INTERRUPTED_EXIT_CODE);
+            };
+            if first_interrupt.is_some_and(|first| first.elapsed() < INTERRUPT_GRACE) {
+                if let Some(restore) = restore {
+                    restore.restore();
+                }
+                exit(INTERRUPTED_EXIT_CODE);
+            }
+            first_interrupt = Some(Instant::now());
+            interrupt.request();
+        });
+    });
+}
+
+/// The run is over and its terminal given back, a Ctrl-C exits right away
+#[cfg(any(unix, windows))]
+fn release_ctrl_c() {
+    *CTRL_C_TARGET.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Where a program stopped by Ctrl-C was: the next instruction to run and the registers
+fn interrupt_report(state: &State) -> String {
+    let pc = state.register_read(Registers::Pc);
+    let instruction = state.read_instruction(pc as usize);
+    let mut report = format!(
+        "\nInterrupted at x{:04X}: {} (x{:04X})\n",
+        pc,
+        disassembler::disassemble(instruction),
+        instruction
+    );
+    for (index, register) in [
+        Registers::R0,
+        Registers::R1,
+        Registers::R2,
+        Registers::R3,
+        Registers::R4,
+        Registers::R5,
+        Registers::R6,
+        Registers::R7,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let _ = write!(report, "R{}=x{:04X} ", index, state.register_read(register));
     }
+    let condition_codes = match state.register_read(Registers::Flags) {
+        flags if flags == Flags::Neg as u16 => 'N',
+        flags if flags == Flags::Zro as u16 => 'Z',
+        _ => 'P',
+    };
+    let _ = writeln!(report, "PC=x{:04X} CC={}", pc, condition_codes);
+    report
 }
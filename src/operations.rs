@@ -1,20 +1,26 @@
-use crate::{Errors, Flags, Registers, State, Traps};
-use std::{
-    char,
-    io::{Read, Write, stdin, stdout},
-};
+use crate::assertion::TrapAssertion;
+use crate::console::KeyWait;
+use crate::host_fs::{HostFileError, HostFiles};
+use crate::trace::condition_codes;
+use crate::trap_table::TrapRoutine;
+use crate::{Error, Flags, MEM_MAX, Registers, RuntimeError, State, Traps, irq};
+use std::fmt::Write as _;
+use std::time::Duration;
+use std::{char, io::Write};
 
 const NULL_WORD: u16 = 0x0;
+/// Words of memory the DBG trap prints
+const DBG_WORDS: u16 = 8;
 
 /// Binary ADD operation with 2 possible encodings
 /// The number between the () indicates the amount of bits of that field or its value
 /// * Register mode:    |OP_Code (0001)|DR (3)|SR1 (3)|0|00|SR2 (3)|
 /// * Immediate mode:   |OP_Code (0001)|DR (3)|SR1 (3)|1| IMMR5 (5)|<br>
 ///   When finished update flags
-pub(crate) fn add(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn add(instruction: u16, state: &mut State) -> Result<(), Error> {
     // Shift right so that the 3 dr bits are in the less significant position, do an binary and operation with 3 ones (0x7) to take their value
-    let destination_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-    let source_register_1 = Registers::try_from((instruction >> 6) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let destination_register = Registers::from_bits(instruction >> 9);
+    let source_register_1 = Registers::from_bits(instruction >> 6);
     let mode = (instruction >> 5) & 0x1;
     if mode == 1 {
         let value_to_add = sign_extend((instruction) & 0x1F, 5);
@@ -23,7 +29,7 @@ pub(crate) fn add(instruction: u16, state: &mut State) -> Result<(), Errors> {
             u16::wrapping_add(state.register_read(source_register_1), value_to_add),
         );
     } else {
-        let source_register_2 = Registers::try_from(instruction & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+        let source_register_2 = Registers::from_bits(instruction);
         state.register_write(
             destination_register,
             u16::wrapping_add(
@@ -39,11 +45,11 @@ pub(crate) fn add(instruction: u16, state: &mut State) -> Result<(), Errors> {
 /// The number between the () indicates the amount of bits of that field or its value
 /// * Instruction: | OP_Code (1010)| DR (3)| PCOffset9 (9)|<br>
 ///   When finished update flags
-pub(crate) fn load_indirect(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let destination_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Take the 3 DR bits, Can't break because its maximum value is 8 (111)
+pub(crate) fn load_indirect(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let destination_register = Registers::from_bits(instruction >> 9);
     let pc_offset = sign_extend(instruction & 0x1FF, 9); // Take the 9 PCOffset bits and sign_extend them
     let memory_index = u16::wrapping_add(state.register_read(Registers::Pc), pc_offset) as usize;
-    let actual_index = state.memory_read(memory_index as usize) as usize;
+    let actual_index = state.memory_read(memory_index) as usize;
     let value = state.memory_read(actual_index);
     state.register_write(destination_register, value);
     update_flags(destination_register, &mut state.registers);
@@ -54,14 +60,14 @@ pub(crate) fn load_indirect(instruction: u16, state: &mut State) -> Result<(), E
 /// * Register mode:    |OP_Code (0101)|DR (3)|SR1 (3)|0|00|SR2 (3)|
 /// * Immediate mode:   |OP_Code (0101)|DR (3)|SR1 (3)|1| IMMR5 (5)|<br>
 ///   When finished update flags
-pub(crate) fn and(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let destination_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-    let source_register_1 = Registers::try_from((instruction >> 6) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+pub(crate) fn and(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let destination_register = Registers::from_bits(instruction >> 9);
+    let source_register_1 = Registers::from_bits(instruction >> 6);
     let mode = (instruction >> 5) & 0x1;
     let value_to_and = if mode == 1 {
         sign_extend((instruction) & 0x1F, 5)
     } else {
-        let source_register_2 = Registers::try_from(instruction & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+        let source_register_2 = Registers::from_bits(instruction);
         state.register_read(source_register_2)
     };
     let value = state.register_read(source_register_1) & value_to_and;
@@ -80,26 +86,30 @@ pub(crate) fn and(instruction: u16, state: &mut State) -> Result<(), Errors> {
 /// If the flag tested is has the value 1, then the sign extended PCOffset9 is added to the Program counter<br>
 /// Only one of the flags will have the value 1 at each moment, so if multiple flags are tested only one needs to be in 1 for the branch to occure
 pub(crate) fn conditional_branch(instruction: u16, state: &mut State) {
-    let negative_indicator = (instruction >> 11) & 1;
-    let zero_indicator = (instruction >> 10) & 1;
-    let positive_indicator = (instruction >> 9) & 1;
-    let current_flags = state.register_read(Registers::Flags);
-    let is_negative = (negative_indicator & current_flags >> 2) == 1;
-    let is_zero = (zero_indicator & current_flags >> 1) == 1;
-    let is_positive = (positive_indicator & current_flags) == 1;
-    if is_negative || is_zero || is_positive {
-        let pc_offset = sign_extend(instruction & 0x1FF, 9);
-        state.register_write(
-            Registers::Pc,
-            u16::wrapping_add(state.register_read(Registers::Pc), pc_offset),
-        );
+    let pc = branch_target(
+        instruction,
+        state.register_read(Registers::Flags),
+        state.register_read(Registers::Pc),
+    );
+    state.register_write(Registers::Pc, pc);
+}
+
+/// The PC after the branch `instruction` with `flags` set, `pc` being the PC past it. What [`conditional_branch`] does
+/// without reading the registers, for the run loop that already has them
+#[inline(always)]
+pub(crate) fn branch_target(instruction: u16, flags: u16, pc: u16) -> u16 {
+    // n, z and p are in the same order as the bits of Neg, Zro and Pos
+    let tested = (instruction >> 9) & 0x7;
+    match tested & flags != 0 {
+        true => u16::wrapping_add(pc, sign_extend(instruction & 0x1FF, 9)),
+        false => pc,
     }
 }
 
 /// Set the program counter to the value of the base register
 /// * Instruction: |OP_Code (1100)|000| BaseR (3)|000000|
-pub(crate) fn jump(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let base_register = Registers::try_from((instruction >> 6) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+pub(crate) fn jump(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let base_register = Registers::from_bits(instruction >> 6);
     state.register_write(Registers::Pc, state.register_read(base_register));
     Ok(())
 }
@@ -108,28 +118,27 @@ pub(crate) fn jump(instruction: u16, state: &mut State) -> Result<(), Errors> {
 /// by an singn extended offset or set its value to the one in the base register
 /// * Immediate mode (JSR):    |OP_Code (0100)|1 (Mode)|PCOffset (11)|
 /// * Register mode (JSRR):    |OP_Code (0100)|0 (Mode)|00|BaseR (3)|000000|
-pub(crate) fn jump_to_subrutine(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    state.register_write(Registers::R7, state.register_read(Registers::Pc));
+pub(crate) fn jump_to_subrutine(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let return_address = state.register_read(Registers::Pc);
     let mode = (instruction >> 11) & 1;
-    if mode == 1 {
+    // The target first, `JSRR R7` jumps to where R7 pointed before it gets the return address
+    let target = if mode == 1 {
         let offset = sign_extend(instruction & 0x7FF, 11);
-        state.register_write(
-            Registers::Pc,
-            u16::wrapping_add(state.register_read(Registers::Pc), offset),
-        );
+        u16::wrapping_add(return_address, offset)
     } else {
-        let base_register = Registers::try_from((instruction >> 6) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-        state.register_write(Registers::Pc, state.register_read(base_register));
-    }
+        state.register_read(Registers::from_bits(instruction >> 6))
+    };
+    state.register_write(Registers::R7, return_address);
+    state.register_write(Registers::Pc, target);
     Ok(())
 }
 
 /// Read the value from the memory location at progam counter + sign extended offset and write it in the destination registry
 /// * Instruction: |OP_Code (0010)|DR (3)|PCOffset (9)|<br>
 ///   When finished update flags
-pub(crate) fn load(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn load(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x1FF, 9);
-    let destination_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let destination_register = Registers::from_bits(instruction >> 9);
     let memory_index =
         u16::wrapping_add(state.register_read(Registers::Pc), sign_extended_offset) as usize;
     let value = state.memory_read(memory_index);
@@ -142,10 +151,10 @@ pub(crate) fn load(instruction: u16, state: &mut State) -> Result<(), Errors> {
 /// The memory direction is given by the value inside the base register and the sign extended offset
 /// * Instruction: |OP_Code (0110)|DR (3)|BaseR (3)|Offset (6)|<br>
 ///   When finished update flags
-pub(crate) fn load_register(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn load_register(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x3F, 6);
-    let base_register = Registers::try_from(instruction >> 6 & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-    let destination_register = Registers::try_from(instruction >> 9 & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let base_register = Registers::from_bits(instruction >> 6);
+    let destination_register = Registers::from_bits(instruction >> 9);
     let memory_index =
         u16::wrapping_add(state.register_read(base_register), sign_extended_offset) as usize;
     let value = state.memory_read(memory_index);
@@ -157,9 +166,9 @@ pub(crate) fn load_register(instruction: u16, state: &mut State) -> Result<(), E
 /// Load a memory address into a register
 /// * Instruction: |OP_Code (1110)|DR (3)|Offset (9)|<br>
 ///   When finished update flags
-pub(crate) fn load_effective_address(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn load_effective_address(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x1FF, 9);
-    let destination_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let destination_register = Registers::from_bits(instruction >> 9);
     let address = u16::wrapping_add(state.register_read(Registers::Pc), sign_extended_offset);
     state.register_write(destination_register, address);
     update_flags(destination_register, &mut state.registers);
@@ -169,9 +178,9 @@ pub(crate) fn load_effective_address(instruction: u16, state: &mut State) -> Res
 /// Calculate the bitwise complement of the source registry and save it in the destination registry
 /// * Instruction: |OP_Code (1001)|DR (3)|SR (3)|1|11111|<br>
 ///   When finished update flags
-pub(crate) fn not(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let source_registry = Registers::try_from((instruction >> 6) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-    let destination_registry = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+pub(crate) fn not(instruction: u16, state: &mut State) -> Result<(), Error> {
+    let source_registry = Registers::from_bits(instruction >> 6);
+    let destination_registry = Registers::from_bits(instruction >> 9);
     state.register_write(
         destination_registry,
         !(state.register_read(source_registry)),
@@ -182,141 +191,455 @@ pub(crate) fn not(instruction: u16, state: &mut State) -> Result<(), Errors> {
 
 /// Store the contents of a source register into a specific location in memory
 /// * Instruction: |OP_Code (0011)|SR (3)|PCOffset (9)|<br>
-pub(crate) fn store(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn store(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x1FF, 9);
-    let source_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let source_register = Registers::from_bits(instruction >> 9);
     let memory_address =
         u16::wrapping_add(state.register_read(Registers::Pc), sign_extended_offset) as usize;
-    state.memory_write(memory_address, state.register_read(source_register));
-    Ok(())
+    state.store(memory_address, state.register_read(source_register))
 }
 
 /// The instruction takes the memory address containing the memory location where the source register's value should be stored and stores it.
 /// * Instruction: |OP_Code (1011)|SR (3)|PCOffset (9)|<br>
-pub(crate) fn store_indirect(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn store_indirect(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x1FF, 9);
-    let source_register = Registers::try_from((instruction >> 9) & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let source_register = Registers::from_bits(instruction >> 9);
     let memory_address =
         u16::wrapping_add(state.register_read(Registers::Pc), sign_extended_offset) as usize;
     let actual_address = state.memory_read(memory_address) as usize;
-    state.memory_write(actual_address, state.register_read(source_register));
-    Ok(())
+    state.store(actual_address, state.register_read(source_register))
 }
 /// Store the register in memory, the address is calculated using the base register's content and a sign extended offset
 /// * Instruction: |OP_Code (0111)|SR (3)|BaseR (3)|Offset (6)|<br>
-pub(crate) fn store_register(instruction: u16, state: &mut State) -> Result<(), Errors> {
+pub(crate) fn store_register(instruction: u16, state: &mut State) -> Result<(), Error> {
     let sign_extended_offset = sign_extend(instruction & 0x3F, 6);
-    let base_register = Registers::try_from(instruction >> 6 & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
-    let source_register = Registers::try_from(instruction >> 9 & 0x7).unwrap(); // Can't break because its maximum value is 8 (111)
+    let base_register = Registers::from_bits(instruction >> 6);
+    let source_register = Registers::from_bits(instruction >> 9);
     let memory_address =
         u16::wrapping_add(state.register_read(base_register), sign_extended_offset) as usize;
-    state.memory_write(memory_address, state.register_read(source_register));
-    Ok(())
+    state.store(memory_address, state.register_read(source_register))
 }
 
 /// Given a trap instruction call the correct routine
 /// * Instruction: |OP_Code (1111)|0000|TrapVect (8)|<br>
-pub(crate) fn trap(instruction: u16, state: &mut State) -> Result<(), Errors> {
-    let routine = Traps::try_from(instruction & 0xFF)?;
+pub(crate) fn trap(instruction: u16, state: &mut State) -> Result<(), Error> {
+    if irq::trap_through_table(state, instruction as u8)? {
+        if let Some(stats) = &mut state.stats {
+            stats.trap(instruction as u8);
+        }
+        return Ok(());
+    }
+    let routine = match state.trap_routine(instruction as u8) {
+        Some(TrapRoutine::Native(routine)) => routine,
+        Some(TrapRoutine::Custom(routine)) => {
+            if let Some(stats) = &mut state.stats {
+                stats.trap(instruction as u8);
+            }
+            return routine(state);
+        }
+        None => return Err(RuntimeError::BadTrapCode(instruction & 0xFF).into()),
+    };
+    if let Some(stats) = &mut state.stats {
+        stats.trap(instruction as u8);
+    }
     match routine {
         Traps::Getc => trap_routine_getc(state)?,
         Traps::Out => trap_routine_out(state)?,
-        Traps::Puts => trap_routine_puts(state),
+        Traps::Puts => trap_routine_puts(state)?,
         Traps::In => trap_routine_in(state)?,
-        Traps::Putsp => trap_routine_putsp(state),
-        Traps::Halt => trap_routine_halt(state),
+        Traps::Putsp => trap_routine_putsp(state)?,
+        Traps::Halt => trap_routine_halt(state)?,
+        Traps::Assert => trap_routine_assert(state)?,
+        Traps::Exit => trap_routine_exit(state)?,
+        Traps::Dbg => trap_routine_dbg(state)?,
+        Traps::ReadLine => trap_routine_read_line(state)?,
+        Traps::Time => trap_routine_time(state),
+        Traps::Sleep => trap_routine_sleep(state)?,
+        Traps::FileOpen | Traps::FileRead | Traps::FileWrite | Traps::FileClose => {
+            trap_routine_file(state, routine)?
+        }
     };
     Ok(())
 }
 
 /// Prints HALT and stops executing the program
-fn trap_routine_halt(state: &mut State) {
-    print!("HALT");
+fn trap_routine_halt(state: &mut State) -> Result<(), Error> {
+    write!(state.output, "HALT")?;
+    stop(state)
+}
+
+/// Stops executing the program like HALT without printing anything, the low byte of R0 is the exit code
+fn trap_routine_exit(state: &mut State) -> Result<(), Error> {
+    state.exit_code = Some(state.register_read(Registers::R0) as u8);
+    stop(state)
+}
+
+/// The milliseconds since the machine started as 32 bits, the low word in R0 and the high one in R1. It wraps around
+/// after about 49 days. The flags are left alone
+fn trap_routine_time(state: &mut State) {
+    let milliseconds = state.now().as_millis() as u32;
+    state.register_write(Registers::R0, milliseconds as u16);
+    state.register_write(Registers::R1, (milliseconds >> 16) as u16);
+}
+
+/// Sleep for the milliseconds in R0 (unsigned) through the clock of the machine, instantly under a virtual one.
+/// An interrupt wakes it up and goes back to the trap like GETC, resuming sleeps the whole time again
+fn trap_routine_sleep(state: &mut State) -> Result<(), Error> {
+    state.output.flush()?;
+    let duration = Duration::from_millis(state.register_read(Registers::R0) as u64);
+    if !state.clock.sleep(duration, &state.interrupt) {
+        state.interrupt.take();
+        state.register_write(
+            Registers::Pc,
+            state.register_read(Registers::Pc).wrapping_sub(1),
+        );
+        return Err(RuntimeError::Interrupted.into());
+    }
+    Ok(())
+}
+
+/// A string of a character per word ending in NUL from `address` on, laid out like PUTS takes it. Unlike PUTS, which
+/// prints the lower byte of a word, a word is the whole character
+fn read_string(state: &mut State, mut address: u16) -> String {
+    let mut string = String::new();
+    // Like PUTS, a string without NUL stops after going once over the whole memory
+    for _ in 0..MEM_MAX {
+        let character = state.memory_read(address as usize);
+        match char::from_u32(character as u32) {
+            Some(character) if character != '\0' => string.push(character),
+            _ => break,
+        }
+        address = address.wrapping_add(1);
+    }
+    string
+}
+
+/// The file traps x30 to x33, see [`crate::host_fs`]. R0 is the result or the negative code of the error, and the
+/// flags are set from it
+fn trap_routine_file(state: &mut State, routine: Traps) -> Result<(), Error> {
+    let result = match state.host_files.take() {
+        Some(mut files) => {
+            let result = file_trap(state, &mut files, routine);
+            state.host_files = Some(files);
+            result?
+        }
+        None => Err(HostFileError::Disabled),
+    };
+    let value = result.unwrap_or_else(HostFileError::code);
+    state.register_write(Registers::R0, value);
+    update_flags(Registers::R0, &mut state.registers);
+    Ok(())
+}
+
+/// The longest read or write, a longer length in R2 is cut to it so the count returned in R0 stays positive
+const MAX_FILE_TRANSFER: u16 = 0x7FFF;
+
+fn file_trap(
+    state: &mut State,
+    files: &mut HostFiles,
+    routine: Traps,
+) -> Result<Result<u16, HostFileError>, Error> {
+    let handle = state.register_read(Registers::R0);
+    let buffer = state.register_read(Registers::R1);
+    let length = state.register_read(Registers::R2).min(MAX_FILE_TRANSFER);
+    Ok(match routine {
+        Traps::FileOpen => {
+            let path = read_string(state, handle);
+            files.open(&path, state.register_read(Registers::R1))
+        }
+        Traps::FileRead => match files.read(handle, length as usize) {
+            Ok(bytes) => {
+                // Two bytes per word like PUTSP, the first one in the low byte
+                for (offset, pair) in bytes.chunks(2).enumerate() {
+                    let word = pair[0] as u16 | (pair.get(1).copied().unwrap_or(0) as u16) << 8;
+                    state.store(buffer.wrapping_add(offset as u16) as usize, word)?;
+                }
+                Ok(bytes.len() as u16)
+            }
+            Err(error) => Err(error),
+        },
+        Traps::FileWrite => {
+            let bytes: Vec<u8> = (0..length)
+                .map(|index| {
+                    let word = state.memory_read(buffer.wrapping_add(index / 2) as usize);
+                    (word >> (8 * (index % 2))) as u8
+                })
+                .collect();
+            files.write(handle, &bytes).map(|()| length)
+        }
+        _ => files.close(handle).map(|()| 0),
+    })
+}
+
+/// Print the registers on a line to stderr, with the 8 words at the address in R0 unless it is zero:
+/// `DBG x3004: R0=x4000 ... R7=x0000 PC=x3005 COND=P [x4000] x0001 x0002 ...`.
+/// Nothing is written to the registers, and the memory is read without going through the devices
+fn trap_routine_dbg(state: &mut State) -> Result<(), Error> {
+    if state.quiet {
+        return Ok(());
+    }
+    let mut line = format!(
+        "DBG x{:04X}:",
+        state.register_read(Registers::Pc).wrapping_sub(1)
+    );
+    for (number, value) in state.registers[..8].iter().enumerate() {
+        let _ = write!(line, " R{}=x{:04X}", number, value);
+    }
+    let _ = write!(
+        line,
+        " PC=x{:04X} COND={}",
+        state.register_read(Registers::Pc),
+        condition_codes(state.register_read(Registers::Flags))
+    );
+    let address = state.register_read(Registers::R0);
+    if address != 0 {
+        let _ = write!(line, " [x{:04X}]", address);
+        for offset in 0..DBG_WORDS {
+            let word = state.peek(address.wrapping_add(offset) as usize);
+            let _ = write!(line, " x{:04X}", word);
+        }
+    }
+    // After what the program printed so far
+    state.output.flush()?;
+    crate::diag!("{}", line);
+    Ok(())
+}
+
+/// Every way a program halts goes through here, what it printed is flushed first
+fn stop(state: &mut State) -> Result<(), Error> {
+    state.output.flush()?;
     state.running = false;
+    Ok(())
+}
+
+/// Record an assertion of the program: R0 nonzero passes, R1 points to its message. Prints a line to stderr either way,
+/// a failure stops the program only with [`State::set_halt_on_assert_fail`]
+fn trap_routine_assert(state: &mut State) -> Result<(), Error> {
+    let message = read_string(state, state.register_read(Registers::R1));
+    let assertion = TrapAssertion {
+        pc: state.register_read(Registers::Pc).wrapping_sub(1),
+        passed: state.register_read(Registers::R0) != 0,
+        message,
+    };
+    // Printed after what the program printed so far
+    state.output.flush()?;
+    crate::diag!("{}", assertion);
+    let failed = (!assertion.passed).then(|| assertion.message.clone());
+    state.trap_assertions.push(assertion);
+    match failed {
+        Some(message) if state.halt_on_assert_fail => {
+            Err(RuntimeError::AssertFailed(message).into())
+        }
+        _ => Ok(()),
+    }
 }
 
 /// Output a string in big endian, for doing this take the memory address from the R0 register,
 /// read the value in that memory position, if its different from 0x0 then print the less significant byte first
-/// and if the more significant byte is different from 0x0 print it. It continues reading from the next memory position until it finds a 0x0
-fn trap_routine_putsp(state: &mut State) {
-    let mut address = state.register_read(Registers::R0) as usize;
-    let mut character = state.memory_read(address);
-    while character != NULL_WORD {
-        if let Some(char1) = char::from_u32((character & 0xFF) as u32) {
-            print!("{}", char1);
-        } else {
+/// and if the more significant byte is different from 0x0 print it. It continues reading from the next memory position until it finds a 0x0.
+/// The bytes are written as they are, like OUT does
+fn trap_routine_putsp(state: &mut State) -> Result<(), Error> {
+    let mut address = state.register_read(Registers::R0);
+    let mut character = state.memory_read(address as usize);
+    // A string without NUL stops after going once over the whole memory instead of looping forever
+    for _ in 0..MEM_MAX {
+        if character == NULL_WORD {
             break;
-        };
+        }
+        state.output.write_all(&[character as u8])?;
         let char2 = character >> 8;
         if char2 != NULL_WORD {
-            if let Some(c2) = char::from_u32(char2 as u32) {
-                print!("{}", c2);
-            }
+            state.output.write_all(&[char2 as u8])?;
         }
-        // Fetch next character
-        address += 1;
-        character = state.memory_read(address);
+        // Fetch next character, the address wraps around at the end of the memory
+        address = address.wrapping_add(1);
+        character = state.memory_read(address as usize);
     }
+    state.output.flush()?;
+    Ok(())
 }
 
 /// Prompt for input character.
-/// Print a line asking the user to enter a character, read the character, save it in register 0 and update the flags.
-fn trap_routine_in(state: &mut State) -> Result<(), Errors> {
-    print!("Enter character: ");
-    let input = 0_u8;
-    match stdin().read_exact(&mut [input]) {
-        Ok(_) => print!("{}", input),
-        Err(_) => return Err(Errors::Trap(Traps::In)),
-    };
+/// Print a line asking the user to enter a character, read the character, echo it, save it in register 0 and update the flags.
+fn trap_routine_in(state: &mut State) -> Result<(), Error> {
+    write!(state.output, "Enter character: ")?;
+    state.output.flush()?;
+    let input = read_key(state, Traps::In)?;
+    state.output.write_all(&[input])?;
+    state.output.flush()?;
     state.register_write(Registers::R0, input as u16);
     update_flags(Registers::R0, &mut state.registers);
     Ok(())
 }
 
-/// Reads a character from register 0 and prints it
-fn trap_routine_out(state: &State) -> Result<(), Errors> {
+/// Reads a character from register 0 and prints it, only its lower 8 bits are printed
+fn trap_routine_out(state: &mut State) -> Result<(), Error> {
     let character = state.register_read(Registers::R0);
-    if let Some(char) = char::from_u32(character as u32) {
-        print!("{}", char);
-    } else {
-        return Err(Errors::Trap(Traps::Out));
+    state.output.write_all(&[character as u8])?;
+    Ok(())
+}
+
+/// Read a line into memory, a character per word ending in NUL like PUTS takes it. R0 is the address of the buffer and
+/// R1 the most characters it takes, the buffer needs R1 + 1 words. The line ends at a newline, which isn't stored, or
+/// where the input ends. Every character is echoed and R0 is the length of the line, with the flags updated.
+/// A longer line keeps its first R1 characters, the rest of it is read and dropped, and R0 is -1 (xFFFF, the flags N)
+/// instead of the length. The buffer is written like a store of the program, the checks of stores apply.
+/// An interrupt while waiting goes back to the trap like GETC, the characters read until then are lost
+fn trap_routine_read_line(state: &mut State) -> Result<(), Error> {
+    let buffer = state.register_read(Registers::R0);
+    let capacity = state.register_read(Registers::R1);
+    let mut length: u16 = 0;
+    let mut truncated = false;
+    state.output.flush()?;
+    while let Some(key) = next_key(state, Traps::ReadLine)? {
+        if key == b'\n' || key == b'\r' {
+            state.output.write_all(b"\n")?;
+            break;
+        }
+        state.output.write_all(&[key])?;
+        if length < capacity {
+            state.store(buffer.wrapping_add(length) as usize, key as u16)?;
+            length += 1;
+        } else {
+            truncated = true;
+        }
+    }
+    state.output.flush()?;
+    state.store(buffer.wrapping_add(length) as usize, NULL_WORD)?;
+    let result = match truncated {
+        true => 0xFFFF,
+        false => length,
     };
+    state.register_write(Registers::R0, result);
+    update_flags(Registers::R0, &mut state.registers);
     Ok(())
 }
 
-/// Reads a single character from the keyboard and save it in the Register 0
-fn trap_routine_getc(state: &mut State) -> Result<(), Errors> {
-    let mut input = [0u8];
-    match stdin().read_exact(&mut input) {
-        Ok(_) => state.register_write(Registers::R0, input[0] as u16),
-        Err(_) => return Err(Errors::Trap(Traps::Getc)),
+/// Reads a single character from the keyboard and save it in the Register 0.
+/// The output is flushed first so a prompt printed with OUT is visible while waiting.
+/// Non-blocking (see [`State::set_getc_nonblocking`]) it takes the key pending, if any, and 0 without one
+fn trap_routine_getc(state: &mut State) -> Result<(), Error> {
+    state.output.flush()?;
+    let input = match state.getc_nonblocking {
+        true => pending_key(state),
+        false => read_key(state, Traps::Getc)?,
     };
+    state.register_write(Registers::R0, input as u16);
     update_flags(Registers::R0, &mut state.registers);
     Ok(())
 }
 
+/// Wait for the next key. If the wait ended because of an interrupt the PC goes back to the trap,
+/// so resuming the program waits again
+fn read_key(state: &mut State, routine: Traps) -> Result<u8, Error> {
+    if let Some(replay) = &mut state.replay {
+        let instruction = replay.current();
+        let pc = state.registers[Registers::Pc].wrapping_sub(1);
+        let key = match replay.read() {
+            Ok(key) => key,
+            Err(_) if replay.exhausted() && state.input_timeout.is_some() => {
+                return Err(input_timeout(state, routine));
+            }
+            Err(reason) => {
+                return Err(RuntimeError::ReplayDiverges {
+                    instruction,
+                    pc,
+                    reason,
+                }
+                .into());
+            }
+        };
+        state.key_consumed(key);
+        return Ok(key);
+    }
+    next_key(state, routine)?.ok_or_else(|| match state.input_timeout {
+        Some(_) => input_timeout(state, routine),
+        None => RuntimeError::Trap(routine).into(),
+    })
+}
+
+/// The key available right now for a non-blocking GETC, 0 counted in the stats without one
+fn pending_key(state: &mut State) -> u8 {
+    let key = match &mut state.replay {
+        Some(replay) => replay.poll(),
+        None => state.input.poll_byte(),
+    };
+    match key {
+        Some(key) => {
+            state.key_consumed(key);
+            key
+        }
+        None => {
+            if let Some(stats) = &mut state.stats {
+                stats.empty_getc();
+            }
+            0
+        }
+    }
+}
+
+/// Wait for the next key of a trap that reads until the input ends, `None` once it did. Replaying, the input ends
+/// after the keys recorded for the trap. An interrupt goes back to the trap like [`read_key`]. With an input timeout
+/// and a clock following the host, waiting longer fails, see [`State::set_input_timeout`]
+fn next_key(state: &mut State, routine: Traps) -> Result<Option<u8>, Error> {
+    let key = match (&mut state.replay, state.input_timeout) {
+        (Some(replay), _) => replay.poll(),
+        (None, Some(timeout)) if state.clock.follows_host() => {
+            match state.input.read_byte_timeout(timeout) {
+                KeyWait::Key(key) => Some(key),
+                KeyWait::Ended => None,
+                KeyWait::TimedOut => return Err(input_timeout(state, routine)),
+            }
+        }
+        (None, _) => state.input.read_byte(),
+    };
+    match key {
+        Some(key) => {
+            state.key_consumed(key);
+            Ok(Some(key))
+        }
+        None if state.replay.is_none() && state.interrupt.take() => {
+            state.register_write(
+                Registers::Pc,
+                state.register_read(Registers::Pc).wrapping_sub(1),
+            );
+            Err(RuntimeError::Interrupted.into())
+        }
+        None => Ok(None),
+    }
+}
+
+fn input_timeout(state: &State, routine: Traps) -> Error {
+    RuntimeError::InputTimeout {
+        trap: routine,
+        pc: state.registers[Registers::Pc].wrapping_sub(1),
+    }
+    .into()
+}
+
 /// Print a string from memory
 /// Each memory position will represent one char, start reading memory at the address in the register R0, print the read character
-/// and continue reading the next memory position
-fn trap_routine_puts(state: &mut State) {
-    let mut address = state.register_read(Registers::R0) as usize;
-    let mut character = state.memory_read(address);
-    while character != NULL_WORD {
-        if let Some(char_char) = char::from_u32(character as u32) {
-            print!("{}", char_char);
-        } else {
+/// and continue reading the next memory position. The lower 8 bits of a word are written as a byte, like OUT does
+fn trap_routine_puts(state: &mut State) -> Result<(), Error> {
+    let mut address = state.register_read(Registers::R0);
+    let mut character = state.memory_read(address as usize);
+    // A string without NUL stops after going once over the whole memory instead of looping forever
+    for _ in 0..MEM_MAX {
+        if character == NULL_WORD {
             break;
-        };
-        // Fetch next character
-        address += 1;
-        character = state.memory_read(address);
+        }
+        state.output.write_all(&[character as u8])?;
+        // Fetch next character, the address wraps around at the end of the memory
+        address = address.wrapping_add(1);
+        character = state.memory_read(address as usize);
     }
-    let _ = stdout().flush();
+    state.output.flush()?;
+    Ok(())
 }
 
 /// Receives a register and the current registers status.
 /// Update the RCond register acording to the value of the register passed by argument
-fn update_flags(register: Registers, registers: &mut [u16; 10]) {
+pub(crate) fn update_flags(register: Registers, registers: &mut [u16; 10]) {
     if registers[register] == 0 {
         registers[Registers::Flags] = Flags::Zro as u16;
     }
@@ -328,7 +651,7 @@ fn update_flags(register: Registers, registers: &mut [u16; 10]) {
     }
 }
 
-fn sign_extend(value: u16, bit_count: u16) -> u16 {
+pub(crate) fn sign_extend(value: u16, bit_count: u16) -> u16 {
     let mut x = value;
     // If the value is negative add 1s to complete the 16 bytes, if not do nothing
     if (value >> (bit_count - 1)) == 1 {
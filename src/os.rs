@@ -0,0 +1,282 @@
+//! A minimal operating system, what `--builtin-os` boots before the program. It is assembled from the source in here,
+//! so there is no image of it to find nor to ship:
+//! * the trap table points GETC, OUT, PUTS, IN, PUTSP and HALT at routines in LC-3 polling KBSR, KBDR, DSR and DDR,
+//!   HALT stops the machine clearing bit 15 of MCR. They run in supervisor mode, see [`crate::irq`], and leave every
+//!   register as they found it but R0 for GETC and IN. The other traps, those of the VM, aren't in the table and
+//!   still run in the VM
+//! * every exception and interrupt vector points at a handler printing what happened and stopping the machine
+//! * booting sets up the supervisor stack and goes to the program in user mode, with the registers the VM gave it
+//!
+//! The code starts at [`OS_CODE`], right above the vector tables. The supervisor stack goes from right above the
+//! code to x2FFF
+
+use crate::assembler::{Program, assemble};
+use crate::irq::{InterruptController, TRAP_TABLE, VECTOR_TABLE};
+use crate::{Error, LoadError, Registers, State, Traps};
+
+/// Where the code of the system is, after the trap and the interrupt vector tables
+pub const OS_CODE: u16 = 0x0200;
+
+const MINIMAL_OS: &str = r#"
+        .ORIG x0200
+USER_PC .FILL x3000             ; Where the program starts, written when booting
+USER_PSR .FILL x8002            ; User mode at priority 0
+
+; Push the PSR and the PC of the program and RTI to it. R0 is kept for the program on the stack meanwhile
+BOOT    LD R6, SSP
+        STR R0, R6, #-3
+        LD R0, USER_PSR
+        STR R0, R6, #-1
+        LD R0, USER_PC
+        STR R0, R6, #-2
+        LDR R0, R6, #-3
+        ADD R6, R6, #-2
+        RTI
+
+TRAP_GETC
+        ADD R6, R6, #-1
+        STR R1, R6, #0
+GETC_WAIT
+        LDI R1, OS_KBSR
+        BRzp GETC_WAIT
+        LDI R0, OS_KBDR
+        BRnzp SET_CC
+
+TRAP_IN ADD R6, R6, #-1
+        STR R7, R6, #0
+        LEA R0, PROMPT
+        JSR PRINT
+        LDR R7, R6, #0
+        STR R1, R6, #0          ; The same slot as R1 in GETC
+IN_WAIT LDI R1, OS_KBSR
+        BRzp IN_WAIT
+        LDI R0, OS_KBDR
+IN_ECHO LDI R1, OS_DSR
+        BRzp IN_ECHO
+        STI R0, OS_DDR
+
+; The condition codes of R0 in the PSR the trap pushed, RTI sets them. R1 is on the stack, above it the PC and the PSR
+SET_CC  LDR R1, R6, #2
+        AND R1, R1, #-8
+        ADD R0, R0, #0
+        BRn CC_N
+        BRz CC_Z
+        ADD R1, R1, #1
+        BRnzp CC_SET
+CC_Z    ADD R1, R1, #2
+        BRnzp CC_SET
+CC_N    ADD R1, R1, #4
+CC_SET  STR R1, R6, #2
+        LDR R1, R6, #0
+        ADD R6, R6, #1
+        RTI
+
+TRAP_OUT
+        ADD R6, R6, #-1
+        STR R1, R6, #0
+OUT_WAIT
+        LDI R1, OS_DSR
+        BRzp OUT_WAIT
+        STI R0, OS_DDR
+        LDR R1, R6, #0
+        ADD R6, R6, #1
+        RTI
+
+TRAP_PUTS
+        ADD R6, R6, #-1
+        STR R7, R6, #0
+        JSR PRINT
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RTI
+
+; Two characters per word, the low byte first. The high byte is shifted down a bit at a time into R2
+TRAP_PUTSP
+        ADD R6, R6, #-5
+        STR R0, R6, #0
+        STR R1, R6, #1
+        STR R2, R6, #2
+        STR R3, R6, #3
+        STR R4, R6, #4
+PUTSP_NEXT
+        LDR R1, R0, #0
+        LD R2, LOW_BYTE
+        AND R2, R1, R2
+        BRz PUTSP_DONE
+PUTSP_LOW
+        LDI R3, OS_DSR
+        BRzp PUTSP_LOW
+        STI R2, OS_DDR
+        AND R2, R2, #0
+        AND R4, R4, #0
+        ADD R4, R4, #8
+PUTSP_SHIFT
+        ADD R2, R2, R2
+        ADD R1, R1, #0
+        BRzp PUTSP_ZERO
+        ADD R2, R2, #1
+PUTSP_ZERO
+        ADD R1, R1, R1
+        ADD R4, R4, #-1
+        BRp PUTSP_SHIFT
+        ADD R2, R2, #0
+        BRz PUTSP_DONE
+PUTSP_HIGH
+        LDI R3, OS_DSR
+        BRzp PUTSP_HIGH
+        STI R2, OS_DDR
+        ADD R0, R0, #1
+        BRnzp PUTSP_NEXT
+PUTSP_DONE
+        LDR R0, R6, #0
+        LDR R1, R6, #1
+        LDR R2, R6, #2
+        LDR R3, R6, #3
+        LDR R4, R6, #4
+        ADD R6, R6, #5
+        RTI
+
+TRAP_HALT
+        LEA R0, HALT_TEXT
+        BRnzp STOP
+PRIVILEGE
+        LEA R0, PRIVILEGE_TEXT
+        BRnzp STOP
+ILLEGAL LEA R0, ILLEGAL_TEXT
+        BRnzp STOP
+UNEXPECTED
+        LEA R0, UNEXPECTED_TEXT
+STOP    JSR PRINT
+        LDI R0, OS_MCR
+        LD R1, CLOCK_OFF
+        AND R0, R0, R1
+        STI R0, OS_MCR
+        BRnzp STOP              ; The machine stopped already
+
+; Print the string at R0, every register but R7 is left alone
+PRINT   ADD R6, R6, #-3
+        STR R0, R6, #0
+        STR R1, R6, #1
+        STR R2, R6, #2
+PRINT_NEXT
+        LDR R1, R0, #0
+        BRz PRINT_DONE
+PRINT_WAIT
+        LDI R2, OS_DSR
+        BRzp PRINT_WAIT
+        STI R1, OS_DDR
+        ADD R0, R0, #1
+        BRnzp PRINT_NEXT
+PRINT_DONE
+        LDR R0, R6, #0
+        LDR R1, R6, #1
+        LDR R2, R6, #2
+        ADD R6, R6, #3
+        RET
+
+SSP     .FILL x3000
+OS_KBSR .FILL xFE00
+OS_KBDR .FILL xFE02
+OS_DSR  .FILL xFE04
+OS_DDR  .FILL xFE06
+OS_MCR  .FILL xFFFE
+LOW_BYTE .FILL x00FF
+CLOCK_OFF .FILL x7FFF
+PROMPT  .STRINGZ "Enter character: "
+HALT_TEXT .STRINGZ "HALT"
+PRIVILEGE_TEXT .STRINGZ "\n--- Privilege mode violation ---\n"
+ILLEGAL_TEXT .STRINGZ "\n--- Illegal opcode ---\n"
+UNEXPECTED_TEXT .STRINGZ "\n--- Unexpected interrupt ---\n"
+        .END
+"#;
+
+/// The code of the system, assembled
+fn minimal_os() -> Program {
+    assemble(MINIMAL_OS).expect("the minimal OS assembles")
+}
+
+/// The words of the system from x0000: the trap table, the interrupt vector table and the code, see the module
+/// documentation. It goes to a program at x3000
+pub fn build_minimal_os() -> Vec<u16> {
+    let os = minimal_os();
+    let mut words = vec![0; OS_CODE as usize];
+    for (vector, routine) in [
+        (Traps::Getc, "TRAP_GETC"),
+        (Traps::Out, "TRAP_OUT"),
+        (Traps::Puts, "TRAP_PUTS"),
+        (Traps::In, "TRAP_IN"),
+        (Traps::Putsp, "TRAP_PUTSP"),
+        (Traps::Halt, "TRAP_HALT"),
+    ] {
+        words[(TRAP_TABLE + vector as u16) as usize] = os.symbols[routine];
+    }
+    for vector in VECTOR_TABLE..OS_CODE {
+        words[vector as usize] = os.symbols["UNEXPECTED"];
+    }
+    words[VECTOR_TABLE as usize] = os.symbols["PRIVILEGE"];
+    words[VECTOR_TABLE as usize + 1] = os.symbols["ILLEGAL"];
+    words.extend(os.words);
+    words
+}
+
+/// Load the system under the program loaded already and boot it, the program starting at `program_start`. It takes
+/// the interrupts from then on, with the supervisor stack right above the system and the priorities of the interrupt
+/// controller already there, if any
+pub fn boot_minimal_os(state: &mut State, program_start: u16) -> Result<(), Error> {
+    let os = minimal_os();
+    let mut words = build_minimal_os();
+    words[os.symbols["USER_PC"] as usize] = program_start;
+    if let Some(address) = (0..words.len()).find(|&address| state.loaded.contains(address)) {
+        return Err(LoadError::OverlapsSystem(address as u16).into());
+    }
+    state.load_image(TRAP_TABLE, &words);
+    let user_stack = state.register_read(Registers::R6);
+    let supervisor_stack = words.len() as u16..=0x2FFF;
+    state.set_interrupt_controller(Some(
+        InterruptController::new(supervisor_stack)
+            .in_supervisor_mode(user_stack)
+            .keeping_priorities(state.interrupt_controller()),
+    ));
+    state.register_write(Registers::Pc, os.symbols["BOOT"]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{ScriptedInput, TestVm, state_with_snippet};
+
+    #[test]
+    fn the_system_assembles_without_warnings() {
+        assert!(minimal_os().warnings.is_empty());
+        let words = build_minimal_os();
+        assert_eq!(words[0x25], minimal_os().symbols["TRAP_HALT"]);
+        assert_eq!(words[0x26], 0);
+        assert_eq!(words[0x1FF], minimal_os().symbols["UNEXPECTED"]);
+    }
+
+    #[test]
+    fn the_traps_run_in_the_system() {
+        let mut state = state_with_snippet(
+            ".ORIG x3000\nLEA R0, HI\nPUTS\nLEA R0, PACKED\nPUTSP\nIN\nADD R2, R0, #0\nGETC\nOUT\nHALT\nHI .STRINGZ \"hi \"\nPACKED .FILL x6261\n.FILL x0063\n.END",
+        );
+        state.register_write(Registers::R5, 0x1234);
+        boot_minimal_os(&mut state, 0x3000).unwrap();
+        let mut vm = TestVm::new(state).input(ScriptedInput::new("xy"));
+        vm.run().unwrap();
+        assert_eq!(vm.output().as_string(), "hi abcEnter character: xyHALT");
+        assert_eq!(vm.state().register_read(Registers::R2), b'x' as u16);
+        assert_eq!(vm.state().register_read(Registers::R5), 0x1234);
+        // Stopped in the HALT routine
+        assert!(vm.state().interrupt_controller().unwrap().is_supervisor());
+    }
+
+    #[test]
+    fn an_exception_stops_the_machine() {
+        let mut state = state_with_snippet(".ORIG x3000\n.FILL xD000\n.END");
+        boot_minimal_os(&mut state, 0x3000).unwrap();
+        let mut vm = TestVm::new(state);
+        vm.run().unwrap();
+        assert_eq!(vm.output().as_string(), "\n--- Illegal opcode ---\n");
+    }
+}
@@ -0,0 +1,302 @@
+//! Files the VM writes, written so that a run failing or killed halfway never leaves half of one behind. An
+//! [`AtomicWriter`] writes to a temporary file next to the final one, `.summary.json.1234-0.tmp` for `summary.json`,
+//! and only [`AtomicWriter::commit`] renames it to its name: the file is there whole or not at all, and one written by
+//! an earlier run stays as it was until then. A file appended to, like the CSV of `--stats-out`, is copied to the
+//! temporary file first.
+//!
+//! The temporary files not committed yet are known to the process: one dropped without a commit is removed, and
+//! [`remove_pending`] removes all of them where nothing gets dropped, like a panic hook or an exit on a second Ctrl-C.
+//! A process killed outright leaves the temporary file, never a half-written one under the final name.
+//!
+//! With [`set_durable`] (what `--durable` does) the file is synced to the disk before the rename, and the directory
+//! after it, so the commit survives a power failure too
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::Error;
+
+static DURABLE: AtomicBool = AtomicBool::new(false);
+
+/// The temporary files created and neither committed nor removed yet
+static PENDING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Sync every file to the disk before committing it, see the module documentation
+pub fn set_durable(durable: bool) {
+    DURABLE.store(durable, Ordering::Relaxed);
+}
+
+/// Remove every temporary file not committed yet, for the ways out of the process that drop nothing. Committing one
+/// of them fails from then on
+pub fn remove_pending() {
+    remove_pending_where(|_| true);
+}
+
+fn remove_pending_where(removed: impl Fn(&Path) -> bool) {
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    pending.retain(|path| {
+        if !removed(path) {
+            return true;
+        }
+        let _ = fs::remove_file(path);
+        false
+    });
+}
+
+/// Write `contents` to `path` in one go, see [`AtomicWriter`]
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    let mut writer = AtomicWriter::create(path)?;
+    writer.write_all(contents.as_ref())?;
+    writer.commit()
+}
+
+/// A file written under a temporary name until [`AtomicWriter::commit`], see the module documentation. It can be
+/// written to directly, or through the [`SharedWriter`]s of [`AtomicWriter::shared`] for the writers wanting to own
+/// theirs, like a recording
+pub struct AtomicWriter {
+    path: PathBuf,
+    temporary: PathBuf,
+    file: Arc<Mutex<Option<BufWriter<File>>>>,
+    /// The length of the file appended to, 0 for a new one
+    existing: u64,
+    committed: bool,
+}
+
+/// Writes to the temporary file of an [`AtomicWriter`], an error once it was committed or dropped
+#[derive(Clone)]
+pub struct SharedWriter(Arc<Mutex<Option<BufWriter<File>>>>);
+
+impl AtomicWriter {
+    /// Write `path` anew, what was there stays until the commit
+    pub fn create(path: impl AsRef<Path>) -> Result<AtomicWriter, Error> {
+        AtomicWriter::open(path.as_ref(), false)
+    }
+
+    /// Write `path` with what it has first, as if it was open to append. It starts empty if there is no such file
+    pub fn append(path: impl AsRef<Path>) -> Result<AtomicWriter, Error> {
+        AtomicWriter::open(path.as_ref(), true)
+    }
+
+    fn open(path: &Path, append: bool) -> Result<AtomicWriter, Error> {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        let temporary = path.with_file_name(format!(
+            ".{}.{}-{}.tmp",
+            name.to_string_lossy(),
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&temporary)?;
+        PENDING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(temporary.clone());
+        // Registered first, dropping it removes the temporary file whatever fails next
+        let mut writer = AtomicWriter {
+            path: path.to_path_buf(),
+            temporary,
+            file: Arc::new(Mutex::new(Some(BufWriter::new(file)))),
+            existing: 0,
+            committed: false,
+        };
+        if append {
+            match File::open(path) {
+                Ok(mut existing) => writer.existing = io::copy(&mut existing, &mut writer)?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(writer)
+    }
+
+    /// The length of the file [`AtomicWriter::append`] found, 0 if it was new or empty
+    pub fn existing_len(&self) -> u64 {
+        self.existing
+    }
+
+    /// Another writer to the same temporary file
+    pub fn shared(&self) -> SharedWriter {
+        SharedWriter(self.file.clone())
+    }
+
+    /// Flush and close the temporary file and give it the final name, synced first with [`set_durable`]
+    pub fn commit(mut self) -> Result<(), Error> {
+        let file = self
+            .file
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let Some(file) = file else {
+            return Err(closed().into());
+        };
+        let durable = DURABLE.load(Ordering::Relaxed);
+        // Closed at the end of the block, an open file can't be renamed on Windows
+        {
+            let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+            if durable {
+                file.sync_all()?;
+            }
+        }
+        fs::rename(&self.temporary, &self.path)?;
+        self.committed = true;
+        forget_pending(&self.temporary);
+        if durable {
+            sync_directory(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.shared().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.shared().flush()
+    }
+}
+
+/// Not committed, the temporary file goes
+impl Drop for AtomicWriter {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        // Closed first, an open file can't be removed on Windows
+        drop(
+            self.file
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take(),
+        );
+        let _ = fs::remove_file(&self.temporary);
+        forget_pending(&self.temporary);
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        match &mut *self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(file) => file.write(bytes),
+            None => Err(closed()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(file) => file.flush(),
+            None => Err(closed()),
+        }
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::other("the output file was already committed or removed")
+}
+
+fn forget_pending(temporary: &Path) {
+    PENDING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|pending| pending != temporary);
+}
+
+/// Make the rename of a file in the directory of `path` durable, only needed and only possible on Unix
+fn sync_directory(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("lc3-output-file-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn files(directory: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn the_file_only_gets_its_name_once_committed() {
+        let directory = directory("commit");
+        let path = directory.join("summary.json");
+        fs::write(&path, "old").unwrap();
+        let mut writer = AtomicWriter::create(&path).unwrap();
+        let mut shared = writer.shared();
+        writer.write_all(b"new ").unwrap();
+        shared.write_all(b"summary").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(files(&directory).len(), 2);
+        writer.commit().unwrap();
+        assert!(shared.write_all(b"late").is_err());
+        let contents = fs::read_to_string(&path).unwrap();
+        let left = files(&directory);
+        let _ = fs::remove_dir_all(&directory);
+        assert_eq!(contents, "new summary");
+        assert_eq!(left, ["summary.json"]);
+    }
+
+    #[test]
+    fn a_writer_dropped_or_pending_leaves_nothing() {
+        let directory = directory("drop");
+        let mut dropped = AtomicWriter::create(directory.join("a.json")).unwrap();
+        dropped.write_all(b"half").unwrap();
+        drop(dropped);
+        let after_drop = files(&directory);
+        let mut pending = AtomicWriter::create(directory.join("b.json")).unwrap();
+        pending.write_all(b"half").unwrap();
+        // Only those of this test, the others run in the same process
+        remove_pending_where(|path| path.starts_with(&directory));
+        let after_removal = files(&directory);
+        let committed = pending.commit();
+        let _ = fs::remove_dir_all(&directory);
+        assert_eq!(after_drop, Vec::<String>::new());
+        assert_eq!(after_removal, Vec::<String>::new());
+        assert!(committed.is_err());
+    }
+
+    #[test]
+    fn appending_starts_with_what_the_file_had() {
+        let directory = directory("append");
+        let path = directory.join("stats.csv");
+        let mut new = AtomicWriter::append(&path).unwrap();
+        assert_eq!(new.existing_len(), 0);
+        new.write_all(b"header\n").unwrap();
+        new.commit().unwrap();
+        let mut existing = AtomicWriter::append(&path).unwrap();
+        assert_eq!(existing.existing_len(), 7);
+        existing.write_all(b"row\n").unwrap();
+        existing.commit().unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&directory);
+        assert_eq!(contents, "header\nrow\n");
+    }
+}
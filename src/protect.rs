@@ -0,0 +1,65 @@
+//! Read-only code, what `--protect-code` does: a store of the program to a word loaded from an image stops it with
+//! [`crate::RuntimeError::WriteToCode`], catching a store with the wrong offset before it corrupts the instructions.
+//! The data assembled along with the code is loaded too, so the ranges it is in have to be made writable with
+//! [`CodeProtection::writable`]. Nothing to do with the privilege of [`crate::irq`], the system stores like the program
+
+use std::ops::RangeInclusive;
+
+/// The loaded ranges stores can still go to, see [`crate::State::set_code_protection`]
+#[derive(Clone, Debug, Default)]
+pub struct CodeProtection {
+    /// Sorted and without overlaps, so a store looks it up with a binary search
+    writable: Vec<RangeInclusive<u16>>,
+}
+
+impl CodeProtection {
+    pub fn new() -> CodeProtection {
+        CodeProtection::default()
+    }
+
+    /// Let the program store to `range` even where it was loaded
+    pub fn writable(mut self, range: RangeInclusive<u16>) -> CodeProtection {
+        self.writable.push(range);
+        self.writable.sort_by_key(|range| *range.start());
+        let mut merged: Vec<RangeInclusive<u16>> = Vec::with_capacity(self.writable.len());
+        for range in self.writable {
+            match merged.last_mut() {
+                Some(last) if *range.start() as u32 <= *last.end() as u32 + 1 => {
+                    *last = *last.start()..=*last.end().max(range.end());
+                }
+                _ => merged.push(range),
+            }
+        }
+        self.writable = merged;
+        self
+    }
+
+    /// Whether a loaded word at `address` can be stored to
+    #[inline]
+    pub(crate) fn allows(&self, address: u16) -> bool {
+        let after = self
+            .writable
+            .partition_point(|range| *range.start() <= address);
+        after > 0 && self.writable[after - 1].contains(&address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_writable_ranges_are_merged() {
+        let protection = CodeProtection::new()
+            .writable(0x4000..=0x4010)
+            .writable(0x3000..=0x3000)
+            .writable(0x4011..=0x4020)
+            .writable(0x4005..=0x4008);
+        assert_eq!(protection.writable, [0x3000..=0x3000, 0x4000..=0x4020]);
+        assert!(protection.allows(0x3000) && protection.allows(0x4020));
+        assert!(
+            !protection.allows(0x2FFF) && !protection.allows(0x3001) && !protection.allows(0x4021)
+        );
+        assert!(!CodeProtection::new().allows(0x3000));
+    }
+}
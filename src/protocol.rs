@@ -0,0 +1,659 @@
+//! The protocol an editor plugin uses to drive the VM as a child process, `--serve-stdio` on the command line.
+//! It is independent of the transport: [`serve`] reads the requests from any reader and writes the events to any writer,
+//! a server on a socket only needs to hand its streams over.
+//!
+//! Every message is a frame: its length as 4 bytes in big endian followed by that many bytes of JSON.
+//! A request is a command with an optional `id`, echoed in the event that answers it:
+//! ```json
+//! { "id": 1, "command": "load", "path": "prog.obj" }
+//! { "id": 2, "command": "run", "maxSteps": 1000000 }
+//! { "id": 3, "command": "sendKey", "keys": "y\n" }
+//! { "id": 4, "command": "peek", "address": 12288, "length": 2 }
+//! { "id": 5, "command": "findStr", "start": 12288, "end": 65023, "text": "HELLO" }
+//! ```
+//! A `load` with `"banks": 4` maps that many banks at x8000 like `--banks` (see [`crate::bank`]) before the image
+//! loads, then `peek` and `poke` take a `bank` to reach the window of any of them, the selected one without it:
+//! ```json
+//! { "id": 6, "command": "peek", "bank": 2, "address": 36864 }
+//! ```
+//! Every request gets exactly one answer: `done`, `memory`, `found` (for `find` and `findStr`), `stopped` (for `run`
+//! and `step`) or `error`.
+//! What the program prints arrives in `output` events while it runs, before the `stopped` event of the run.
+//! Requests that arrive while the program runs are answered between slices of [`SLICE`] instructions,
+//! so keys can be sent and memory read without stopping it, but a second `run`, `step` or `load` is an error
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{Receiver, channel};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::console::{KeyboardBuffer, MemoryOutput};
+use crate::file_management::{read_bytes_to_memory, read_file_to_memory};
+use crate::search::{self, Hit};
+use crate::{Error, Registers, RuntimeError, State, run_with_budget};
+
+/// Instructions run before looking at the requests again and sending what the program printed
+pub const SLICE: u64 = 1 << 16;
+
+/// Largest frame read, a bigger length is taken as a broken stream
+const MAX_FRAME: u32 = 1 << 24;
+
+/// A command and the id its answer carries
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    #[serde(rename = "id", default)]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "command")]
+pub enum Command {
+    /// Load an object image from a file or from its bytes
+    #[serde(rename = "load")]
+    Load {
+        #[serde(rename = "path", default)]
+        path: Option<String>,
+        #[serde(rename = "image", default)]
+        image: Option<Vec<u8>>,
+        #[serde(rename = "banks", default)]
+        banks: Option<u16>,
+    },
+    /// Run until the program halts, waits for a key or runs `maxSteps` instructions
+    #[serde(rename = "run")]
+    Run {
+        #[serde(rename = "maxSteps", default)]
+        max_steps: Option<u64>,
+    },
+    /// Run `count` instructions, one by default
+    #[serde(rename = "step")]
+    Step {
+        #[serde(rename = "count", default)]
+        count: Option<u64>,
+    },
+    /// Write words to memory starting at `address`, in the window of `bank` if there is one
+    #[serde(rename = "poke")]
+    Poke {
+        #[serde(rename = "bank", default)]
+        bank: Option<u16>,
+        #[serde(rename = "address")]
+        address: u16,
+        #[serde(rename = "values")]
+        values: Vec<u16>,
+    },
+    /// Read `length` words starting at `address`, one by default, in the window of `bank` if there is one. The device
+    /// registers aren't polled
+    #[serde(rename = "peek")]
+    Peek {
+        #[serde(rename = "bank", default)]
+        bank: Option<u16>,
+        #[serde(rename = "address")]
+        address: u16,
+        #[serde(rename = "length", default)]
+        length: Option<u16>,
+    },
+    /// Look for the words in memory from `start` to `end` included, see [`crate::search::find_words`]
+    #[serde(rename = "find")]
+    Find {
+        #[serde(rename = "start")]
+        start: u16,
+        #[serde(rename = "end")]
+        end: u16,
+        #[serde(rename = "words")]
+        words: Vec<u16>,
+    },
+    /// Look for the text in memory from `start` to `end` included, a character per word and packed, see
+    /// [`crate::search::find_text`]
+    #[serde(rename = "findStr")]
+    FindStr {
+        #[serde(rename = "start")]
+        start: u16,
+        #[serde(rename = "end")]
+        end: u16,
+        #[serde(rename = "text")]
+        text: String,
+    },
+    /// Queue keys for GETC, IN and the keyboard registers, every character is a key
+    #[serde(rename = "sendKey")]
+    SendKey {
+        #[serde(rename = "keys")]
+        keys: String,
+    },
+}
+
+/// Why a run or a step stopped
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    #[serde(rename = "halted")]
+    Halted,
+    /// It ran all the instructions it was given and can keep going
+    #[serde(rename = "running")]
+    Running,
+    /// It is in GETC or IN without a key, run it again after `sendKey`
+    #[serde(rename = "waitingForInput")]
+    WaitingForInput,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event")]
+pub enum Event {
+    /// Characters the program printed, every byte as the character with that code
+    #[serde(rename = "output")]
+    Output {
+        #[serde(rename = "text")]
+        text: String,
+    },
+    /// The answer to `load`, `poke` and `sendKey`
+    #[serde(rename = "done")]
+    Done {
+        #[serde(rename = "id")]
+        id: Option<u64>,
+    },
+    /// The answer to `peek`
+    #[serde(rename = "memory")]
+    Memory {
+        #[serde(rename = "id")]
+        id: Option<u64>,
+        #[serde(rename = "address")]
+        address: u16,
+        #[serde(rename = "values")]
+        values: Vec<u16>,
+    },
+    /// The answer to `find` and `findStr`, every match by address
+    #[serde(rename = "found")]
+    Found {
+        #[serde(rename = "id")]
+        id: Option<u64>,
+        #[serde(rename = "matches")]
+        matches: Vec<Hit>,
+    },
+    /// The answer to `run` and `step`
+    #[serde(rename = "stopped")]
+    Stopped {
+        #[serde(rename = "id")]
+        id: Option<u64>,
+        #[serde(rename = "status")]
+        status: RunStatus,
+        #[serde(rename = "pc")]
+        pc: u16,
+        #[serde(rename = "instructions")]
+        instructions: u64,
+    },
+    /// A request that couldn't be read or done, including a run that failed. `id` is null if the request couldn't be read
+    #[serde(rename = "error")]
+    Error {
+        #[serde(rename = "id")]
+        id: Option<u64>,
+        #[serde(rename = "message")]
+        message: String,
+    },
+}
+
+/// The run or step in progress and what is left of it
+struct Run {
+    id: Option<u64>,
+    remaining: u64,
+}
+
+/// A headless machine and the dispatch of the requests, shared by every transport.
+/// [`Session::handle`] answers a request right away, except `run` and `step` which only start:
+/// [`Session::advance`] runs them a slice at a time until they stop
+pub struct Session {
+    state: State,
+    keys: KeyboardBuffer,
+    output: MemoryOutput,
+    run: Option<Run>,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        let (mut state, output) = State::headless();
+        let keys = state.attach_keyboard_buffer();
+        Session {
+            state,
+            keys,
+            output,
+            run: None,
+        }
+    }
+
+    /// Whether a `run` or a `step` is waiting for [`Session::advance`]
+    pub fn is_running(&self) -> bool {
+        self.run.is_some()
+    }
+
+    /// The events that answer the request, nothing for a `run` or `step` that started
+    pub fn handle(&mut self, request: Request) -> Vec<Event> {
+        let id = request.id;
+        let result = match request.command {
+            Command::Run { .. } | Command::Step { .. } | Command::Load { .. }
+                if self.is_running() =>
+            {
+                Err("a run is in progress".to_string())
+            }
+            Command::Load { path, image, banks } => {
+                self.load(path, image, banks).map(|_| Event::Done { id })
+            }
+            Command::Run { max_steps } => {
+                self.start(id, max_steps.unwrap_or(u64::MAX));
+                return Vec::new();
+            }
+            Command::Step { count } => {
+                self.start(id, count.unwrap_or(1));
+                return Vec::new();
+            }
+            Command::Poke {
+                bank,
+                address,
+                values,
+            } => self.poke(bank, address, values).map(|_| Event::Done { id }),
+            Command::Peek {
+                bank,
+                address,
+                length,
+            } => self
+                .peek(bank, address, length.unwrap_or(1))
+                .map(|values| Event::Memory {
+                    id,
+                    address,
+                    values,
+                }),
+            Command::Find { start, end, words } => self
+                .find(start, end, |state, range| {
+                    search::find_words(state, range, &words)
+                })
+                .map(|matches| Event::Found { id, matches }),
+            Command::FindStr { start, end, text } => bytes_of(&text)
+                .and_then(|text| {
+                    self.find(start, end, |state, range| {
+                        search::find_text(state, range, &text)
+                    })
+                })
+                .map(|matches| Event::Found { id, matches }),
+            Command::SendKey { keys } => self.send_keys(&keys).map(|_| Event::Done { id }),
+        };
+        vec![result.unwrap_or_else(|message| Event::Error { id, message })]
+    }
+
+    /// Run a slice of the run in progress. The events are what the program printed and, once the run stopped, its answer
+    pub fn advance(&mut self) -> Vec<Event> {
+        let Some(run) = &mut self.run else {
+            return Vec::new();
+        };
+        let slice = run.remaining.min(SLICE);
+        let result = match self.state.is_running() {
+            true => run_with_budget(&mut self.state, slice),
+            false => Ok(()),
+        };
+        run.remaining -= slice;
+        let status = match result {
+            Ok(()) => Some(Ok(RunStatus::Halted)),
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) if run.remaining == 0 => {
+                Some(Ok(RunStatus::Running))
+            }
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => None,
+            Err(Error::Runtime(RuntimeError::Interrupted)) => Some(Ok(RunStatus::WaitingForInput)),
+            Err(error) => Some(Err(error.to_string())),
+        };
+        let mut events = Vec::new();
+        let text = self.output.drain_text();
+        if !text.is_empty() {
+            events.push(Event::Output { text });
+        }
+        let Some(status) = status else {
+            return events;
+        };
+        let id = run.id;
+        self.run = None;
+        events.push(match status {
+            Ok(status) => Event::Stopped {
+                id,
+                status,
+                pc: self.state.register_read(Registers::Pc),
+                instructions: self.state.instructions_executed(),
+            },
+            Err(message) => Event::Error { id, message },
+        });
+        events
+    }
+
+    fn start(&mut self, id: Option<u64>, budget: u64) {
+        self.run = Some(Run {
+            id,
+            remaining: budget,
+        });
+    }
+
+    fn load(
+        &mut self,
+        path: Option<String>,
+        image: Option<Vec<u8>>,
+        banks: Option<u16>,
+    ) -> Result<(), String> {
+        if let Some(count) = banks {
+            self.state
+                .enable_banks(count)
+                .map_err(|error| error.to_string())?;
+        }
+        let result = match (path, image) {
+            (Some(path), None) => read_file_to_memory(&path, &mut self.state),
+            (None, Some(image)) => read_bytes_to_memory(&image, &mut self.state),
+            _ => return Err("load needs either a path or an image".to_string()),
+        };
+        result.map_err(|error| error.to_string())
+    }
+
+    fn poke(&mut self, bank: Option<u16>, address: u16, values: Vec<u16>) -> Result<(), String> {
+        self.check_bank(bank)?;
+        for (offset, value) in values.into_iter().enumerate() {
+            let address = address.wrapping_add(offset as u16);
+            match bank {
+                Some(bank) => {
+                    self.state.poke_bank(bank, address, value);
+                }
+                None => self.state.memory_write(address as usize, value),
+            }
+        }
+        Ok(())
+    }
+
+    fn peek(&self, bank: Option<u16>, address: u16, length: u16) -> Result<Vec<u16>, String> {
+        self.check_bank(bank)?;
+        Ok((0..length)
+            .map(|offset| {
+                let address = address.wrapping_add(offset);
+                match bank {
+                    Some(bank) => self.state.peek_bank(bank, address).unwrap_or(0),
+                    None => self.state.peek(address as usize),
+                }
+            })
+            .collect())
+    }
+
+    /// An error for a bank that isn't mapped, before any word is written
+    fn check_bank(&self, bank: Option<u16>) -> Result<(), String> {
+        match bank {
+            Some(bank) if bank >= self.state.bank_count().unwrap_or(0) => Err(format!(
+                "there is no bank {}, there are {} bank(s)",
+                bank,
+                self.state.bank_count().unwrap_or(0)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn find(
+        &self,
+        start: u16,
+        end: u16,
+        search: impl Fn(&State, (u16, u16)) -> Vec<Hit>,
+    ) -> Result<Vec<Hit>, String> {
+        match start <= end {
+            true => Ok(search(&self.state, (start, end))),
+            false => Err(format!(
+                "the range x{:04X}:x{:04X} is backwards",
+                start, end
+            )),
+        }
+    }
+
+    fn send_keys(&mut self, keys: &str) -> Result<(), String> {
+        for key in bytes_of(keys)? {
+            self.keys.push_key(key);
+        }
+        Ok(())
+    }
+}
+
+/// Every character of `text` as the byte with its code
+fn bytes_of(text: &str) -> Result<Vec<u8>, String> {
+    text.chars()
+        .map(|character| {
+            u8::try_from(character).map_err(|_| format!("`{}` isn't a single byte", character))
+        })
+        .collect()
+}
+
+/// Read the next frame, `None` if the stream ended before it
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0_u8; 4];
+    match reader.read_exact(&mut length) {
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_be_bytes(length);
+    if length > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a frame of {} bytes", length),
+        ));
+    }
+    let mut frame = vec![0; length as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// Write a frame and flush it, the other side is waiting for it
+pub fn write_frame(writer: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too big"))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Serve a [`Session`] until the requests end: a thread reads them so they are answered while the program runs.
+/// A frame that isn't a request is answered with an error event
+pub fn serve(
+    mut requests: impl Read + Send + 'static,
+    mut events: impl Write,
+) -> Result<(), Error> {
+    let (sender, received) = channel();
+    thread::Builder::new()
+        .name("requests".to_string())
+        .spawn(move || {
+            while let Ok(Some(frame)) = read_frame(&mut requests) {
+                let request =
+                    serde_json::from_slice::<Request>(&frame).map_err(|error| Event::Error {
+                        id: None,
+                        message: error.to_string(),
+                    });
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+        })?;
+    let mut session = Session::new();
+    loop {
+        let answers = match next_request(&session, &received) {
+            Some(Ok(request)) => session.handle(request),
+            Some(Err(error)) => vec![error],
+            None if session.is_running() => session.advance(),
+            None => return Ok(()),
+        };
+        for event in answers {
+            let frame = serde_json::to_vec(&event).unwrap(); // Events only have numbers, strings and lists and objects of them
+            write_frame(&mut events, &frame)?;
+        }
+    }
+}
+
+/// The next request: one already received while the program runs, otherwise wait for it.
+/// `None` if there is none yet while running or once the requests ended
+fn next_request(
+    session: &Session,
+    received: &Receiver<Result<Request, Event>>,
+) -> Option<Result<Request, Event>> {
+    match session.is_running() {
+        true => received.try_recv().ok(),
+        false => received.recv().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> Request {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn requests_keep_their_schema() {
+        assert_eq!(
+            request(r#"{"id": 7, "command": "run", "maxSteps": 100}"#),
+            Request {
+                id: Some(7),
+                command: Command::Run {
+                    max_steps: Some(100)
+                }
+            }
+        );
+        assert_eq!(
+            request(r#"{"command": "poke", "address": 16384, "values": [1, 2]}"#).command,
+            Command::Poke {
+                bank: None,
+                address: 0x4000,
+                values: vec![1, 2]
+            }
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command": "reboot"}"#).is_err());
+    }
+
+    #[test]
+    fn events_keep_their_schema() {
+        let stopped = Event::Stopped {
+            id: Some(2),
+            status: RunStatus::WaitingForInput,
+            pc: 0x3003,
+            instructions: 12,
+        };
+        assert_eq!(
+            serde_json::to_string(&stopped).unwrap(),
+            r#"{"event":"stopped","id":2,"status":"waitingForInput","pc":12291,"instructions":12}"#
+        );
+        let output = Event::Output {
+            text: "hi".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"event":"output","text":"hi"}"#
+        );
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"{}").unwrap();
+        write_frame(&mut stream, b"[1]").unwrap();
+        assert_eq!(&stream[..4], &[0, 0, 0, 2]);
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"{}");
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"[1]");
+        assert!(read_frame(&mut reader).unwrap().is_none());
+        // The stream ended in the middle of a frame
+        assert!(read_frame(&mut &[0, 0, 0, 5, b'{'][..]).is_err());
+    }
+
+    #[test]
+    fn a_second_run_while_running_is_an_error() {
+        let mut session = Session::new();
+        session.handle(request(
+            r#"{"command": "poke", "address": 12288, "values": [4095]}"#,
+        )); // BRnzp #-1
+        assert!(
+            session
+                .handle(request(r#"{"id": 1, "command": "run"}"#))
+                .is_empty()
+        );
+        assert!(session.advance().is_empty());
+        assert!(matches!(
+            session.handle(request(r#"{"id": 2, "command": "step"}"#))[..],
+            [Event::Error { id: Some(2), .. }]
+        ));
+        assert_eq!(
+            session.handle(request(r#"{"id": 3, "command": "peek", "address": 12288}"#)),
+            vec![Event::Memory {
+                id: Some(3),
+                address: 0x3000,
+                values: vec![0x0FFF]
+            }]
+        );
+    }
+
+    #[test]
+    fn peek_and_poke_reach_the_bank_they_name() {
+        let mut session = Session::new();
+        assert!(matches!(
+            session.handle(request(
+                r#"{"id": 1, "command": "poke", "bank": 1, "address": 36864, "values": [7]}"#
+            ))[..],
+            [Event::Error { id: Some(1), .. }]
+        ));
+        session.handle(request(
+            r#"{"command": "load", "image": [48, 0], "banks": 2}"#,
+        ));
+        session.handle(request(
+            r#"{"command": "poke", "bank": 1, "address": 36864, "values": [7, 8]}"#,
+        ));
+        session.handle(request(
+            r#"{"command": "poke", "address": 36864, "values": [5]}"#,
+        ));
+        assert_eq!(
+            session.handle(request(
+                r#"{"id": 2, "command": "peek", "bank": 1, "address": 36864, "length": 2}"#
+            )),
+            vec![Event::Memory {
+                id: Some(2),
+                address: 0x9000,
+                values: vec![7, 8]
+            }]
+        );
+        assert_eq!(
+            session.handle(request(r#"{"id": 3, "command": "peek", "address": 36864}"#)),
+            vec![Event::Memory {
+                id: Some(3),
+                address: 0x9000,
+                values: vec![5]
+            }]
+        );
+        assert!(matches!(
+            session.handle(request(
+                r#"{"id": 4, "command": "peek", "bank": 2, "address": 36864}"#
+            ))[..],
+            [Event::Error { id: Some(4), .. }]
+        ));
+    }
+
+    #[test]
+    fn find_answers_with_every_match() {
+        let mut session = Session::new();
+        session.handle(request(
+            r#"{"command": "poke", "address": 12288, "values": [72, 73, 18760]}"#,
+        ));
+        let found = session.handle(request(
+            r#"{"id": 1, "command": "findStr", "start": 12288, "end": 12290, "text": "HI"}"#,
+        ));
+        assert_eq!(
+            serde_json::to_string(&found).unwrap(),
+            concat!(
+                r#"[{"event":"found","id":1,"matches":[{"address":12288,"length":2,"layout":"words"},"#,
+                r#"{"address":12290,"length":1,"layout":"packed"}]}]"#
+            )
+        );
+        assert!(matches!(
+            session.handle(request(
+                r#"{"id": 2, "command": "find", "start": 12290, "end": 12288, "words": [72]}"#
+            ))[..],
+            [Event::Error { id: Some(2), .. }]
+        ));
+    }
+}
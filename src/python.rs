@@ -0,0 +1,354 @@
+//! Python bindings, only built with the `python` feature. `maturin develop` (see `pyproject.toml`) installs them as the `lc3vm` module.
+//!
+//! A [`Vm`] is fed like the web one: keys are queued with `send_key` and what the program printed is taken with `read_output`,
+//! a program waiting for a key stops the run with [`RunStatus::WaitingForInput`] instead of blocking the script.
+//! The GIL is released while the interpreter runs, Ctrl-C stops a long run between slices of [`SLICE`] instructions
+//! with a `KeyboardInterrupt` and the machine can keep going afterwards
+
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyList};
+
+use crate::console::{KeyboardBuffer, MemoryOutput};
+use crate::file_management::{read_bytes_to_memory, read_file_to_memory};
+use crate::{Error, LoadError, Registers, RuntimeError, State, run_with_budget};
+
+/// Instructions run with the GIL released before looking at the signals Python received
+const SLICE: u64 = 1 << 20;
+
+/// The names of the registers in `Vm.regs`, in order
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+create_exception!(
+    lc3vm,
+    VmError,
+    PyException,
+    "Base class of every error of the VM"
+);
+create_exception!(
+    lc3vm,
+    BadOpCodeError,
+    VmError,
+    "The program ran an opcode the VM doesn't have"
+);
+create_exception!(
+    lc3vm,
+    BadTrapCodeError,
+    VmError,
+    "The program ran a trap the VM doesn't have"
+);
+create_exception!(
+    lc3vm,
+    BadRegisterError,
+    VmError,
+    "An instruction named a register that doesn't exist"
+);
+create_exception!(lc3vm, TrapError, VmError, "A trap routine couldn't finish");
+create_exception!(
+    lc3vm,
+    BadImageError,
+    VmError,
+    "The object image doesn't fit in memory"
+);
+create_exception!(
+    lc3vm,
+    BudgetExhaustedError,
+    VmError,
+    "`run` reached `max_steps` before the program halted"
+);
+
+/// Why [`Vm::run`] or [`Vm::step`] returned
+#[pyclass(module = "lc3vm", eq, eq_int, from_py_object)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    Halted,
+    /// It ran all the instructions it was given and can keep going
+    Running,
+    /// It is in GETC or IN without a key, run it again after `send_key`
+    WaitingForInput,
+}
+
+/// What `Vm.load` takes: the bytes of an object file or its path
+#[derive(FromPyObject)]
+enum Image {
+    Bytes(Vec<u8>),
+    Path(PathBuf),
+}
+
+/// What `Vm.send_key` takes: a character code or a string, sent one character at a time
+#[derive(FromPyObject)]
+enum Keys {
+    Code(u8),
+    Text(String),
+}
+
+/// An LC-3 machine for a Python script
+#[pyclass(module = "lc3vm", unsendable)]
+pub struct Vm {
+    state: State,
+    keys: KeyboardBuffer,
+    output: MemoryOutput,
+}
+
+#[pymethods]
+impl Vm {
+    #[new]
+    fn new() -> Vm {
+        let (mut state, output) = State::headless();
+        let keys = state.attach_keyboard_buffer();
+        Vm {
+            state,
+            keys,
+            output,
+        }
+    }
+
+    /// Load an object image, given as its bytes or as the path of the file
+    fn load(&mut self, image: Image) -> PyResult<()> {
+        let result = match image {
+            Image::Bytes(bytes) => read_bytes_to_memory(&bytes, &mut self.state),
+            Image::Path(path) => {
+                read_file_to_memory(&path.to_string_lossy().into_owned(), &mut self.state)
+            }
+        };
+        result.map_err(to_python)
+    }
+
+    /// Run at most `n` instructions
+    #[pyo3(signature = (n = 1))]
+    fn step(&mut self, py: Python<'_>, n: u64) -> PyResult<RunStatus> {
+        self.run_slices(py, n)
+    }
+
+    /// Run until the program halts or waits for a key, raises `BudgetExhaustedError` if it is still running after `max_steps` instructions
+    #[pyo3(signature = (max_steps = None))]
+    fn run(&mut self, py: Python<'_>, max_steps: Option<u64>) -> PyResult<RunStatus> {
+        let budget = max_steps.unwrap_or(u64::MAX);
+        match self.run_slices(py, budget)? {
+            RunStatus::Running => Err(to_python(RuntimeError::BudgetExhausted(budget).into())),
+            status => Ok(status),
+        }
+    }
+
+    /// R0 to R7, `PC` and `COND` by name, they can be read and written like a dict
+    #[getter]
+    fn regs(slf: &Bound<'_, Self>) -> RegisterView {
+        RegisterView {
+            vm: slf.clone().unbind(),
+        }
+    }
+
+    /// The memory by address, reading it doesn't poll the keyboard like a LDR of the device registers would
+    #[getter]
+    fn mem(slf: &Bound<'_, Self>) -> MemoryView {
+        MemoryView {
+            vm: slf.clone().unbind(),
+        }
+    }
+
+    /// Queue a key, or every character of a string, for GETC, IN and the keyboard registers
+    fn send_key(&mut self, keys: Keys) -> PyResult<()> {
+        match keys {
+            Keys::Code(code) => self.keys.push_key(code),
+            Keys::Text(text) => {
+                for character in text.chars() {
+                    let code = u8::try_from(character).map_err(|_| {
+                        PyValueError::new_err(format!("`{}` isn't a single byte", character))
+                    })?;
+                    self.keys.push_key(code);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// What the program printed since the last call, every byte as the character with that code
+    fn read_output(&mut self) -> String {
+        self.output.drain_text()
+    }
+
+    /// Like `read_output` as the bytes
+    fn drain_output<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.output.drain_output())
+    }
+
+    /// Bytes printed since the last `read_output` or `drain_output`
+    fn output_len(&self) -> usize {
+        self.output.output_len()
+    }
+
+    /// Keep at most `capacity` bytes between reads, `None` keeps all of them. The bytes printed past it are dropped and
+    /// counted in `output_overflowed`
+    #[pyo3(signature = (capacity = None))]
+    fn set_output_capacity(&mut self, capacity: Option<usize>) {
+        self.output.set_capacity(capacity);
+    }
+
+    /// Bytes dropped because the output was full
+    #[getter]
+    fn output_overflowed(&self) -> u64 {
+        self.output.overflowed()
+    }
+}
+
+impl Vm {
+    /// Run the budget a [`SLICE`] at a time with the GIL released, the signals are checked between them
+    fn run_slices(&mut self, py: Python<'_>, budget: u64) -> PyResult<RunStatus> {
+        let mut remaining = budget;
+        while self.state.is_running() && remaining > 0 {
+            let slice = remaining.min(SLICE);
+            let state = &mut self.state;
+            match py.detach(|| run_with_budget(state, slice)) {
+                Ok(()) => return Ok(RunStatus::Halted),
+                Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => remaining -= slice,
+                Err(Error::Runtime(RuntimeError::Interrupted)) => {
+                    return Ok(RunStatus::WaitingForInput);
+                }
+                Err(error) => return Err(to_python(error)),
+            }
+            py.check_signals()?;
+        }
+        match self.state.is_running() {
+            true => Ok(RunStatus::Running),
+            false => Ok(RunStatus::Halted),
+        }
+    }
+}
+
+/// `Vm.regs`, it reads and writes the registers of the machine it came from
+#[pyclass(module = "lc3vm")]
+pub struct RegisterView {
+    vm: Py<Vm>,
+}
+
+#[pymethods]
+impl RegisterView {
+    fn __getitem__(&self, py: Python<'_>, name: &str) -> PyResult<u16> {
+        Ok(self.vm.borrow(py).state.register_read(register(name)?))
+    }
+
+    fn __setitem__(&self, py: Python<'_>, name: &str, value: u16) -> PyResult<()> {
+        let register = register(name)?;
+        self.vm.borrow_mut(py).state.register_write(register, value);
+        Ok(())
+    }
+
+    fn __contains__(&self, name: &str) -> bool {
+        register(name).is_ok()
+    }
+
+    fn __len__(&self) -> usize {
+        REGISTER_NAMES.len()
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(PyList::new(py, REGISTER_NAMES)?.try_iter()?.into_any())
+    }
+
+    fn keys(&self) -> Vec<&'static str> {
+        REGISTER_NAMES.to_vec()
+    }
+
+    fn values(&self, py: Python<'_>) -> Vec<u16> {
+        self.items(py).into_iter().map(|(_, value)| value).collect()
+    }
+
+    fn items(&self, py: Python<'_>) -> Vec<(&'static str, u16)> {
+        let vm = self.vm.borrow(py);
+        REGISTER_NAMES
+            .iter()
+            .map(|name| (*name, vm.state.register_read(register(name).unwrap()))) // Every name is a register
+            .collect()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> String {
+        let items: Vec<String> = self
+            .items(py)
+            .into_iter()
+            .map(|(name, value)| format!("'{}': {}", name, value))
+            .collect();
+        format!("{{{}}}", items.join(", "))
+    }
+}
+
+/// The register with that name, case doesn't matter
+fn register(name: &str) -> PyResult<Registers> {
+    match name.to_ascii_uppercase().as_str() {
+        "PC" => Ok(Registers::Pc),
+        "COND" => Ok(Registers::Flags),
+        general => general
+            .strip_prefix('R')
+            .and_then(|number| number.parse::<u16>().ok())
+            .and_then(|number| Registers::try_from(number).ok())
+            .ok_or_else(|| PyKeyError::new_err(name.to_string())),
+    }
+}
+
+/// `Vm.mem`, it reads and writes the memory of the machine it came from
+#[pyclass(module = "lc3vm")]
+pub struct MemoryView {
+    vm: Py<Vm>,
+}
+
+#[pymethods]
+impl MemoryView {
+    fn __getitem__(&self, py: Python<'_>, address: u16) -> u16 {
+        self.vm.borrow(py).state.peek(address as usize)
+    }
+
+    fn __setitem__(&self, py: Python<'_>, address: u16, value: u16) {
+        self.vm
+            .borrow_mut(py)
+            .state
+            .memory_write(address as usize, value);
+    }
+
+    fn __len__(&self) -> usize {
+        crate::MEM_MAX
+    }
+}
+
+/// Files that can't be read raise the `OSError` Python would, every other error a subclass of `VmError`.
+/// An error of an instruction raises the exception of its own error, with the message saying where it happened
+fn to_python(error: Error) -> PyErr {
+    let message = error.to_string();
+    let error = match error {
+        Error::Load(LoadError::File(error)) | Error::Io(error) => return error.into(),
+        error => error,
+    };
+    match error.root() {
+        Error::Load(LoadError::File(error)) | Error::Io(error) => {
+            std::io::Error::new(error.kind(), message).into()
+        }
+        Error::Runtime(RuntimeError::BadOpCode(_)) => BadOpCodeError::new_err(message),
+        Error::Runtime(RuntimeError::BadTrapCode(_)) => BadTrapCodeError::new_err(message),
+        Error::Runtime(RuntimeError::BadRegisterReference(_)) => BadRegisterError::new_err(message),
+        Error::Runtime(RuntimeError::Trap(_)) => TrapError::new_err(message),
+        Error::Load(LoadError::BadImageSize) => BadImageError::new_err(message),
+        Error::Runtime(RuntimeError::BudgetExhausted(_)) => BudgetExhaustedError::new_err(message),
+        _ => VmError::new_err(message),
+    }
+}
+
+#[pymodule]
+fn lc3vm(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = module.py();
+    module.add_class::<Vm>()?;
+    module.add_class::<RunStatus>()?;
+    module.add_class::<RegisterView>()?;
+    module.add_class::<MemoryView>()?;
+    module.add("VmError", py.get_type::<VmError>())?;
+    module.add("BadOpCodeError", py.get_type::<BadOpCodeError>())?;
+    module.add("BadTrapCodeError", py.get_type::<BadTrapCodeError>())?;
+    module.add("BadRegisterError", py.get_type::<BadRegisterError>())?;
+    module.add("TrapError", py.get_type::<TrapError>())?;
+    module.add("BadImageError", py.get_type::<BadImageError>())?;
+    module.add(
+        "BudgetExhaustedError",
+        py.get_type::<BudgetExhaustedError>(),
+    )?;
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! Recordings of the keys a program consumed, what `--record` writes so a run can be replayed exactly.
+//!
+//! A recording (`.lc3rec`) is text, a line per entry:
+//! ```text
+//! lc3rec 1
+//! image 9c2a6a2a0cf4e2d1 2048.obj
+//! key 10532 119
+//! key 20871 10
+//! ```
+//! The first line is the version of the format. Every image loaded follows with its [`image_hash`] and its path, then
+//! every key consumed, through the keyboard registers or GETC and IN, with the byte and the instruction it was consumed
+//! by: how many instructions ran before it. Counting instructions instead of the time is what makes a replay
+//! deterministic, [`Replay`] gives the program every key at the very instruction it was recorded at
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::{Error, LoadError};
+
+/// The version [`Recorder`] writes, bumped with any change to the format
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Writes a recording as the program runs, see [`crate::State::start_recording`]
+pub struct Recorder {
+    output: Box<dyn Write + Send>,
+    /// Instructions started so far, the one running is `instructions - 1`
+    instructions: u64,
+    /// The first write that failed, the program goes on and [`crate::State::stop_recording`] reports it
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Write the header: the version and every image with its hash
+    pub fn new(
+        mut output: Box<dyn Write + Send>,
+        images: &[(&str, &[u8])],
+    ) -> Result<Recorder, Error> {
+        writeln!(output, "lc3rec {}", RECORDING_VERSION)?;
+        for (path, bytes) in images {
+            writeln!(output, "image {:016x} {}", image_hash(bytes), path)?;
+        }
+        Ok(Recorder {
+            output,
+            instructions: 0,
+            error: None,
+        })
+    }
+
+    /// An instruction is about to run
+    #[inline]
+    pub(crate) fn instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    /// The running instruction consumed `key`
+    pub(crate) fn key(&mut self, key: u8) {
+        let instruction = self.instructions.saturating_sub(1);
+        if let Err(error) = writeln!(self.output, "key {} {}", instruction, key)
+            && self.error.is_none()
+        {
+            self.error = Some(error);
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error.into());
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// A recording read back, what `--replay` gives the program instead of the keyboard, see [`crate::State::start_replay`].
+/// Polling the keyboard finds a key only at the instruction it was recorded at, and GETC or IN anywhere else is a
+/// divergence
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    /// The recorded images, paths and hashes
+    images: Vec<(String, u64)>,
+    /// The keys not consumed yet with the instruction that consumes them, in order
+    keys: VecDeque<(u64, u8)>,
+    /// Instructions started so far, like [`Recorder`]
+    instructions: u64,
+}
+
+impl Replay {
+    pub fn parse(text: &str) -> Result<Replay, Error> {
+        let bad = |line: usize, reason: &str| {
+            LoadError::BadRecording(format!("line {}: {}", line, reason))
+        };
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line));
+        match lines.next() {
+            Some((_, header)) if header == format!("lc3rec {}", RECORDING_VERSION) => {}
+            Some((_, header)) if header.starts_with("lc3rec ") => {
+                return Err(bad(1, &format!("this VM reads version {}", RECORDING_VERSION)).into());
+            }
+            _ => return Err(bad(1, "it doesn't start with lc3rec").into()),
+        }
+        let mut replay = Replay {
+            images: Vec::new(),
+            keys: VecDeque::new(),
+            instructions: 0,
+        };
+        for (number, line) in lines {
+            match line.split_once(' ') {
+                Some(("image", rest)) => {
+                    let (hash, path) = rest
+                        .split_once(' ')
+                        .ok_or_else(|| bad(number, "an image needs a hash and a path"))?;
+                    let hash =
+                        u64::from_str_radix(hash, 16).map_err(|_| bad(number, "bad hash"))?;
+                    replay.images.push((path.to_string(), hash));
+                }
+                Some(("key", rest)) => {
+                    let key = rest
+                        .split_once(' ')
+                        .and_then(|(instruction, byte)| {
+                            Some((instruction.parse().ok()?, byte.parse().ok()?))
+                        })
+                        .ok_or_else(|| bad(number, "a key needs an instruction and a byte"))?;
+                    if replay.keys.back().is_some_and(|last| last.0 > key.0) {
+                        return Err(bad(number, "the keys aren't in order").into());
+                    }
+                    replay.keys.push_back(key);
+                }
+                _ => return Err(bad(number, "unknown entry").into()),
+            }
+        }
+        Ok(replay)
+    }
+
+    /// Fail unless `images` are the recorded ones, by their hashes and in the same order. The paths can differ
+    pub fn check_images(&self, images: &[(&str, &[u8])]) -> Result<(), Error> {
+        if images.len() != self.images.len() {
+            return Err(LoadError::BadRecording(format!(
+                "{} image(s) were recorded, {} given",
+                self.images.len(),
+                images.len()
+            ))
+            .into());
+        }
+        for ((path, bytes), (recorded_path, hash)) in images.iter().zip(&self.images) {
+            if image_hash(bytes) != *hash {
+                return Err(LoadError::BadRecording(format!(
+                    "{} isn't the recorded image {}, their hashes differ",
+                    path, recorded_path
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// An instruction is about to run
+    #[inline]
+    pub(crate) fn instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    /// The instruction running, counted from 0
+    pub(crate) fn current(&self) -> u64 {
+        self.instructions.saturating_sub(1)
+    }
+
+    /// The keyboard status register was read: the key recorded for the running instruction, if there is one
+    pub(crate) fn poll(&mut self) -> Option<u8> {
+        let current = self.current();
+        self.keys
+            .front()
+            .is_some_and(|&(instruction, _)| instruction == current)
+            .then(|| self.keys.pop_front().unwrap().1) // There is a front
+    }
+
+    /// GETC or IN wait for a key: it must have been recorded for the running instruction
+    pub(crate) fn read(&mut self) -> Result<u8, String> {
+        self.poll().ok_or_else(|| match self.keys.front() {
+            Some((instruction, _)) => format!(
+                "the program waits for a key, the next one was recorded for instruction {}",
+                instruction
+            ),
+            None => "the program waits for a key, none is left".to_string(),
+        })
+    }
+
+    /// Whether every recorded key was consumed
+    pub(crate) fn exhausted(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Why the replay isn't over, if keys are left
+    pub(crate) fn unconsumed(&self) -> Option<String> {
+        let (instruction, _) = self.keys.front()?;
+        Some(format!(
+            "{} recorded key(s) were never consumed, the first one at instruction {}",
+            self.keys.len(),
+            instruction
+        ))
+    }
+}
+
+/// The 64 bit FNV-1a hash of an image file, enough to tell a replay it got another image than the one recorded
+pub fn image_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_hashed_with_fnv_1a() {
+        assert_eq!(image_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(image_hash(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn a_recording_is_checked_as_it_is_read() {
+        let replay = Replay::parse("lc3rec 1\nimage af63dc4c8601ec8c a.obj\nkey 3 97\n").unwrap();
+        assert!(replay.check_images(&[("b.obj", b"a")]).is_ok());
+        assert!(replay.check_images(&[("a.obj", b"b")]).is_err());
+        assert!(replay.check_images(&[]).is_err());
+        for bad in [
+            "",
+            "lc3rec 2\n",
+            "lc3rec 1\nkey 3\n",
+            "lc3rec 1\nkey 3 97\nkey 2 98\n",
+        ] {
+            assert!(
+                matches!(
+                    Replay::parse(bad),
+                    Err(Error::Load(LoadError::BadRecording(_)))
+                ),
+                "{:?}",
+                bad
+            );
+        }
+    }
+}
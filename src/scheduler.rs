@@ -0,0 +1,202 @@
+//! Several machines in one process taking turns, what `run --instances a.obj,b.obj` does: each runs a slice of
+//! [`Scheduler::slice`] instructions with [`run_with_budget`], then the next one, round-robin, until every one of them
+//! stopped or ran its budget. It all happens in one thread: two machines talking through a pair of
+//! [`SerialPort`](crate::device::serial::SerialPort)s see the same bytes at the same instructions on every run, and
+//! with a virtual clock (see [`crate::clock`]) the whole run is deterministic.
+//!
+//! What each machine prints is written a whole line at a time, after a prefix with its index like `[0] `, so the lines
+//! of the two don't mix
+
+use std::io::{self, Write};
+
+use crate::console::MemoryOutput;
+use crate::{Error, RuntimeError, State, run_with_budget};
+
+/// Instructions each machine runs before the next one takes its turn
+pub const DEFAULT_SLICE: u64 = 1000;
+
+/// A machine of the scheduler and how it ended
+pub struct Instance {
+    /// What the reports call it, like the path of its image
+    pub name: String,
+    pub state: State,
+    output: MemoryOutput,
+    /// What it printed since the end of its last line
+    line: Vec<u8>,
+    /// `None` while it runs
+    result: Option<Result<(), Error>>,
+}
+
+impl Instance {
+    /// How it ended, `None` while it still runs
+    pub fn result(&self) -> Option<&Result<(), Error>> {
+        self.result.as_ref()
+    }
+
+    /// Run a slice, `None` if it is still running after it
+    fn turn(&mut self, slice: u64, budget: u64) -> Option<Result<(), Error>> {
+        let left = budget.saturating_sub(self.state.instructions_executed());
+        if left == 0 {
+            return Some(Err(RuntimeError::BudgetExhausted(budget).into()));
+        }
+        match run_with_budget(&mut self.state, slice.min(left)) {
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => None,
+            result => Some(result),
+        }
+    }
+
+    /// Write the lines it ended, and what is left of the last one once it stopped
+    fn write_lines(
+        &mut self,
+        index: usize,
+        output: &mut impl Write,
+        stopped: bool,
+    ) -> io::Result<()> {
+        self.line.extend(self.output.drain_output());
+        while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+            write!(output, "[{}] ", index)?;
+            output.write_all(&self.line[..=end])?;
+            self.line.drain(..=end);
+        }
+        if stopped && !self.line.is_empty() {
+            write!(output, "[{}] ", index)?;
+            output.write_all(&self.line)?;
+            output.write_all(b"\n")?;
+            self.line.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Runs its machines in turn, see the module documentation
+pub struct Scheduler {
+    instances: Vec<Instance>,
+    slice: u64,
+}
+
+impl Scheduler {
+    /// A scheduler giving `slice` instructions to each machine at its turn, at least one
+    pub fn new(slice: u64) -> Scheduler {
+        Scheduler {
+            instances: Vec::new(),
+            slice: slice.max(1),
+        }
+    }
+
+    pub fn slice(&self) -> u64 {
+        self.slice
+    }
+
+    /// Add a machine made with [`State::headless`] and its output, it gets the next index
+    pub fn add(&mut self, name: impl Into<String>, state: State, output: MemoryOutput) -> usize {
+        self.instances.push(Instance {
+            name: name.into(),
+            state,
+            output,
+            line: Vec::new(),
+            result: None,
+        });
+        self.instances.len() - 1
+    }
+
+    /// The machines by index
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn instances_mut(&mut self) -> &mut [Instance] {
+        &mut self.instances
+    }
+
+    /// Run the machines in turn until each one stopped or ran `budget` instructions, one that didn't halt by then
+    /// ends with [`RuntimeError::BudgetExhausted`]. What they print goes to `output` a line at a time, see the module
+    /// documentation. A machine that fails doesn't stop the others
+    pub fn run(&mut self, budget: u64, output: &mut impl Write) -> io::Result<()> {
+        while self
+            .instances
+            .iter()
+            .any(|instance| instance.result.is_none())
+        {
+            for (index, instance) in self.instances.iter_mut().enumerate() {
+                if instance.result.is_some() {
+                    continue;
+                }
+                instance.result = instance.turn(self.slice, budget);
+                instance.write_lines(index, output, instance.result.is_some())?;
+            }
+        }
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::state_with_snippet;
+
+    fn instance(source: &str) -> (State, MemoryOutput) {
+        let mut state = state_with_snippet(source);
+        let output = MemoryOutput::default();
+        state.set_output(output.clone());
+        (state, output)
+    }
+
+    #[test]
+    fn the_machines_take_turns_and_their_lines_dont_mix() {
+        // Prints "A" then, 1000 instructions later, "B" and a newline. The other prints a whole line right away
+        let slow = "
+            .ORIG x3000
+            LD R0, A
+            OUT
+            LD R1, COUNT
+LOOP        ADD R1, R1, #-1
+            BRp LOOP
+            LD R0, B
+            OUT
+            AND R0, R0, #0
+            ADD R0, R0, #10
+            OUT
+            HALT
+A           .FILL x41
+B           .FILL x42
+COUNT       .FILL #500
+            .END";
+        let fast = ".ORIG x3000\nLEA R0, TEXT\nPUTS\nHALT\nTEXT .STRINGZ \"fast\\npartial\"\n.END";
+        let mut scheduler = Scheduler::new(10);
+        let (state, output) = instance(slow);
+        scheduler.add("slow", state, output);
+        let (state, output) = instance(fast);
+        scheduler.add("fast", state, output);
+        let mut printed = Vec::new();
+        scheduler.run(u64::MAX, &mut printed).unwrap();
+        assert_eq!(
+            String::from_utf8(printed).unwrap(),
+            "[1] fast\n[1] partialHALT\n[0] AB\n[0] HALT\n"
+        );
+        assert!(
+            scheduler
+                .instances()
+                .iter()
+                .all(|instance| matches!(instance.result(), Some(Ok(()))))
+        );
+    }
+
+    #[test]
+    fn a_machine_out_of_budget_stops_alone() {
+        let mut scheduler = Scheduler::new(7);
+        let (state, output) = instance(".ORIG x3000\nLOOP BRnzp LOOP\n.END");
+        scheduler.add("spin", state, output);
+        let (state, output) = instance(".ORIG x3000\nHALT\n.END");
+        scheduler.add("halt", state, output);
+        scheduler.run(100, &mut io::sink()).unwrap();
+        let [spin, halt] = scheduler.instances() else {
+            unreachable!()
+        };
+        assert!(matches!(
+            spin.result(),
+            Some(Err(Error::Runtime(RuntimeError::BudgetExhausted(100))))
+        ));
+        assert_eq!(spin.state.instructions_executed(), 100);
+        assert!(matches!(halt.result(), Some(Ok(()))));
+    }
+}
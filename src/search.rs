@@ -0,0 +1,208 @@
+//! Searching the memory of a machine for a sequence of words or a string, what `find` and `find-str` do on an image
+//! or a snapshot and what the `find` and `findStr` requests of [`crate::protocol`] do on the machine being debugged.
+//!
+//! A string is looked for in both layouts the traps print: a character per word like `.STRINGZ` and PUTS, and two
+//! per word like PUTSP, the first one in the low byte. A packed string can start in either byte of a word. Every
+//! match is reported, those that overlap too, but only those that lie whole in the range: one going past its end
+//! isn't a match
+
+use std::fmt::Write as _;
+
+use serde::Serialize;
+
+use crate::State;
+
+/// Words shown on each side of a match by [`describe`]
+pub const CONTEXT: u16 = 2;
+
+/// How the words of a match hold what was looked for
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layout {
+    /// Word for word, a character per word for a string
+    #[serde(rename = "words")]
+    Words,
+    /// Two characters per word, starting in the low byte of the first one
+    #[serde(rename = "packed")]
+    Packed,
+    /// Two characters per word, starting in the high byte of the first one
+    #[serde(rename = "packedHigh")]
+    PackedHigh,
+}
+
+impl Layout {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Words => "words",
+            Layout::Packed => "packed",
+            Layout::PackedHigh => "packed (high byte)",
+        }
+    }
+}
+
+/// Where something was found
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    #[serde(rename = "address")]
+    pub address: u16,
+    /// The words it takes from `address` on
+    #[serde(rename = "length")]
+    pub length: u16,
+    #[serde(rename = "layout")]
+    pub layout: Layout,
+}
+
+/// Every place `words` is at in the inclusive range `start` to `end`, nothing for no words. The device registers
+/// aren't polled
+pub fn find_words(state: &State, (start, end): (u16, u16), words: &[u16]) -> Vec<Hit> {
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let memory = read_range(state, start, end);
+    memory
+        .windows(words.len())
+        .enumerate()
+        .filter(|(_, window)| *window == words)
+        .map(|(offset, _)| Hit {
+            address: start + offset as u16,
+            length: words.len() as u16,
+            layout: Layout::Words,
+        })
+        .collect()
+}
+
+/// Every place `text` is at in the inclusive range `start` to `end` in either layout (see the module documentation),
+/// by address. Nothing for an empty text
+pub fn find_text(state: &State, (start, end): (u16, u16), text: &[u8]) -> Vec<Hit> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let characters: Vec<u16> = text.iter().map(|&character| character as u16).collect();
+    let mut hits = find_words(state, (start, end), &characters);
+    let bytes: Vec<u8> = read_range(state, start, end)
+        .into_iter()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    hits.extend(
+        bytes
+            .windows(text.len())
+            .enumerate()
+            .filter(|(_, window)| *window == text)
+            .map(|(offset, _)| {
+                let last = (offset + text.len() - 1) / 2;
+                Hit {
+                    address: start + (offset / 2) as u16,
+                    length: (last - offset / 2 + 1) as u16,
+                    layout: match offset % 2 {
+                        0 => Layout::Packed,
+                        _ => Layout::PackedHigh,
+                    },
+                }
+            }),
+    );
+    hits.sort_by_key(|hit| (hit.address, hit.layout));
+    hits
+}
+
+/// A line for the hit: its address and layout, then its words in brackets with up to [`CONTEXT`] words on each side,
+/// `x3010 words  x0000 x0000 [x0048 x0049] x0000 x0000`
+pub fn describe(state: &State, hit: &Hit) -> String {
+    let first = hit.address.saturating_sub(CONTEXT);
+    let end = hit.address as usize + hit.length as usize;
+    let last = (end - 1 + CONTEXT as usize).min(u16::MAX as usize);
+    let mut line = format!("x{:04X} {:<18}", hit.address, hit.layout.name());
+    for address in first as usize..=last {
+        let open = match address == hit.address as usize {
+            true => "[",
+            false => "",
+        };
+        let close = match address == end - 1 {
+            true => "]",
+            false => "",
+        };
+        let _ = write!(line, " {}x{:04X}{}", open, state.peek(address), close);
+    }
+    line
+}
+
+fn read_range(state: &State, start: u16, end: u16) -> Vec<u16> {
+    (start..=end)
+        .map(|address| state.peek(address as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(origin: u16, words: &[u16]) -> State {
+        let (mut state, _) = State::headless();
+        state.load_image(origin, words);
+        state
+    }
+
+    fn addresses(hits: &[Hit]) -> Vec<(u16, Layout)> {
+        hits.iter().map(|hit| (hit.address, hit.layout)).collect()
+    }
+
+    #[test]
+    fn overlapping_matches_are_all_found() {
+        let state = machine(0x3000, &[7, 7, 7, 1, 7, 7]);
+        let hits = find_words(&state, (0x3000, 0x3005), &[7, 7]);
+        assert_eq!(
+            addresses(&hits),
+            [
+                (0x3000, Layout::Words),
+                (0x3001, Layout::Words),
+                (0x3004, Layout::Words)
+            ]
+        );
+        assert!(find_words(&state, (0x3000, 0x3005), &[]).is_empty());
+    }
+
+    #[test]
+    fn a_match_going_past_the_end_of_the_range_isnt_one() {
+        let state = machine(0x3000, &[7, 7, 7]);
+        let hits = find_words(&state, (0x3000, 0x3001), &[7, 7]);
+        assert_eq!(addresses(&hits), [(0x3000, Layout::Words)]);
+        assert!(find_words(&state, (0x3002, 0x3002), &[7, 7]).is_empty());
+        // Nor going past xFFFF
+        let state = machine(0xFFFE, &[7, 7]);
+        assert_eq!(find_words(&state, (0xFFFE, 0xFFFF), &[7, 7]).len(), 1);
+        assert!(find_words(&state, (0xFFFF, 0xFFFF), &[7, 7]).is_empty());
+    }
+
+    #[test]
+    fn text_is_found_in_both_layouts() {
+        // "ABC" a character per word, packed from the low byte and packed from the high byte
+        let state = machine(0x3000, &[0x41, 0x42, 0x43, 0x4241, 0x0043, 0x4100, 0x4342]);
+        let hits = find_text(&state, (0x3000, 0x3006), b"ABC");
+        assert_eq!(
+            hits,
+            [
+                Hit {
+                    address: 0x3000,
+                    length: 3,
+                    layout: Layout::Words
+                },
+                Hit {
+                    address: 0x3003,
+                    length: 2,
+                    layout: Layout::Packed
+                },
+                Hit {
+                    address: 0x3005,
+                    length: 2,
+                    layout: Layout::PackedHigh
+                },
+            ]
+        );
+        assert_eq!(
+            describe(&state, &hits[1]),
+            "x3003 packed             x0042 x0043 [x4241 x0043] x4100 x4342"
+        );
+        assert_eq!(
+            describe(&state, &hits[2]),
+            "x3005 packed (high byte) x4241 x0043 [x4100 x4342] x0000 x0000"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Code and data written straight to memory, for tests building a machine without an image. Instead of a
+//! [`crate::State::memory_write`] per word at addresses written by hand, [`crate::State::install_program`] and
+//! [`crate::State::install_data`] write a block at a time and give back a handle to find its words again:
+//! ```
+//! # use lc3::State;
+//! let mut state = State::default();
+//! let table = state.install_data(0x4000, &[1, 2, 3]).unwrap();
+//! assert_eq!(table.addr_of(2), 0x4002);
+//! assert_eq!(state.peek(table.addr_of(2) as usize), 3);
+//! ```
+//! A program is loaded like an image, the checks of [`crate::State::set_self_modify_check`] and
+//! [`crate::State::set_code_protection`] see it. Data is only written. Two blocks can't overlap, nor a block and an
+//! image
+
+use std::marker::PhantomData;
+
+use crate::{DEVICE_PAGE, Error, LoadError, State};
+
+/// A block of words installed with [`crate::State::install_program`] or [`crate::State::install_data`], `K` says which
+#[derive(Debug)]
+pub struct Segment<K> {
+    start: u16,
+    len: usize,
+    kind: PhantomData<K>,
+}
+
+/// The kind of [`ProgramHandle`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {}
+
+/// The kind of [`DataHandle`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Data {}
+
+/// Instructions installed with [`crate::State::install_program`]
+pub type ProgramHandle = Segment<Code>;
+/// Words installed with [`crate::State::install_data`]
+pub type DataHandle = Segment<Data>;
+
+impl<K> Segment<K> {
+    /// The address of the first word
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    /// How many words there are
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The address of the word `offset` words from the start. Panics past the end, like indexing a slice
+    pub fn addr_of(&self, offset: usize) -> u16 {
+        assert!(
+            offset < self.len,
+            "offset {} of a segment of {} words",
+            offset,
+            self.len
+        );
+        self.start + offset as u16
+    }
+}
+
+// Derived, they would need `K` to be `Clone` and `PartialEq` too
+impl<K> Clone for Segment<K> {
+    fn clone(&self) -> Segment<K> {
+        *self
+    }
+}
+
+impl<K> Copy for Segment<K> {}
+
+impl<K> PartialEq for Segment<K> {
+    fn eq(&self, other: &Segment<K>) -> bool {
+        self.start == other.start && self.len == other.len
+    }
+}
+
+impl<K> Eq for Segment<K> {}
+
+impl State {
+    /// Load the instructions of `code` from `origin` on, like [`State::load_image`]. It fails if they would overlap
+    /// another segment or an image, or reach the devices
+    pub fn install_program(&mut self, origin: u16, code: &[u16]) -> Result<ProgramHandle, Error> {
+        let segment = self.claim(origin, code.len())?;
+        self.load_image(origin, code);
+        Ok(segment)
+    }
+
+    /// Write the words of `words` from `origin` on. It fails if they would overlap another segment or an image, or
+    /// reach the devices
+    pub fn install_data(&mut self, origin: u16, words: &[u16]) -> Result<DataHandle, Error> {
+        let segment = self.claim(origin, words.len())?;
+        for (offset, word) in words.iter().enumerate() {
+            self.memory_write(origin as usize + offset, *word);
+        }
+        Ok(segment)
+    }
+
+    /// Record the segment of `len` words at `origin` if nothing is there yet
+    fn claim<K>(&mut self, origin: u16, len: usize) -> Result<Segment<K>, Error> {
+        let end = origin as usize + len;
+        if end > DEVICE_PAGE {
+            return Err(LoadError::BadImageSize.into());
+        }
+        let overlapped = (origin as usize..end).find(|&address| {
+            self.loaded.contains(address)
+                || self
+                    .segments
+                    .iter()
+                    .any(|segment| segment.contains(&(address as u16)))
+        });
+        if let Some(address) = overlapped {
+            return Err(LoadError::Overlap {
+                start: origin,
+                end: (end - 1) as u16,
+                address: address as u16,
+            }
+            .into());
+        }
+        if len > 0 {
+            self.segments.push(origin..=(end - 1) as u16);
+        }
+        Ok(Segment {
+            start: origin,
+            len,
+            kind: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_segment_overlapping_another_or_an_image_is_refused() {
+        let mut state = State::default();
+        state.load_image(0x3000, &[0xF025]);
+        let data = state.install_data(0x4000, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            (data.start(), data.len(), data.addr_of(2)),
+            (0x4000, 3, 0x4002)
+        );
+        for (origin, address) in [(0x3FFE, 0x4000), (0x4002, 0x4002), (0x2FFF, 0x3000)] {
+            let error = state.install_program(origin, &[0; 3]).unwrap_err();
+            assert!(
+                matches!(error, Error::Load(LoadError::Overlap { address: at, .. }) if at == address),
+                "{}",
+                error
+            );
+        }
+        let program = state.install_program(0x4003, &[0xF025]).unwrap();
+        assert_eq!(program.addr_of(0), 0x4003);
+        assert!(matches!(
+            state.install_data(0xFDFF, &[0; 2]),
+            Err(Error::Load(LoadError::BadImageSize))
+        ));
+    }
+}
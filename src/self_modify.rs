@@ -0,0 +1,119 @@
+//! Detection of self-modifying code, what the command line VM reports and `--forbid-self-modify` forbids.
+//! A store modifies the program when it writes a word that was loaded from the image and is executed, either before
+//! the store or after it. Stores to loaded data are left alone, it is the executing that tells code from data
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::AddressSet;
+
+/// Tracks the executed words and the stores to loaded words, see [`crate::State::set_self_modify_check`]
+pub struct SelfModifyCheck {
+    /// Stop with [`crate::RuntimeError::SelfModify`] instead of printing a warning
+    pub fail: bool,
+    executed: AddressSet,
+    /// Loaded words stored to before they ever ran, with the address of the store
+    patched: HashMap<u16, u16>,
+    /// How many times every store modified every instruction
+    modifications: BTreeMap<(u16, u16), u64>,
+}
+
+/// A store at `writer` that modified the instruction at `target`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfModification {
+    pub writer: u16,
+    pub target: u16,
+    pub times: u64,
+}
+
+impl SelfModifyCheck {
+    pub fn new(fail: bool) -> SelfModifyCheck {
+        SelfModifyCheck {
+            fail,
+            executed: AddressSet::new(),
+            patched: HashMap::new(),
+            modifications: BTreeMap::new(),
+        }
+    }
+
+    /// The instruction at `pc` is about to run. Returns the store that modified it, if it is the first time it runs since
+    #[inline]
+    pub(crate) fn executing(&mut self, pc: u16) -> Option<u16> {
+        if self.executed.contains(pc as usize) {
+            return None;
+        }
+        self.executed.mark(pc as usize);
+        let writer = self.patched.remove(&pc)?;
+        Some(writer).filter(|&writer| self.record(writer, pc))
+    }
+
+    /// The store at `writer` wrote `target`, which was `loaded` from the image or not.
+    /// True if it modified an instruction that already ran, and it is the first time that store did it
+    pub(crate) fn stored(&mut self, writer: u16, target: u16, loaded: bool) -> bool {
+        if !loaded {
+            return false;
+        }
+        if !self.executed.contains(target as usize) {
+            self.patched.insert(target, writer);
+            return false;
+        }
+        self.record(writer, target)
+    }
+
+    /// Count a modification, true the first time
+    fn record(&mut self, writer: u16, target: u16) -> bool {
+        let times = self.modifications.entry((writer, target)).or_default();
+        *times += 1;
+        *times == 1
+    }
+
+    /// Every modification so far, by the address of the store
+    pub fn modifications(&self) -> Vec<SelfModification> {
+        self.modifications
+            .iter()
+            .map(|(&(writer, target), &times)| SelfModification {
+                writer,
+                target,
+                times,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_stores_arent_modifications() {
+        let mut check = SelfModifyCheck::new(false);
+        assert!(!check.stored(0x3000, 0x3010, false));
+        assert!(!check.stored(0x3000, 0x3010, true));
+        // Never executed, it was data
+        assert!(check.modifications().is_empty());
+    }
+
+    #[test]
+    fn a_store_before_or_after_running_the_word_is_a_modification() {
+        let mut check = SelfModifyCheck::new(false);
+        assert_eq!(check.executing(0x3001), None);
+        assert!(check.stored(0x3005, 0x3001, true));
+        assert!(!check.stored(0x3005, 0x3001, true)); // Reported once
+        assert!(!check.stored(0x3006, 0x3002, true));
+        assert_eq!(check.executing(0x3002), Some(0x3006));
+        assert_eq!(
+            check.modifications(),
+            vec![
+                SelfModification {
+                    writer: 0x3005,
+                    target: 0x3001,
+                    times: 2
+                },
+                SelfModification {
+                    writer: 0x3006,
+                    target: 0x3002,
+                    times: 1
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,438 @@
+//! Snapshots of the machine and the differences between two of them, what `run --snapshot` writes and `diff` compares.
+//!
+//! A snapshot (`.lc3snap`) is big endian: the magic `LC3SNAP\0`, the version, the ten registers (R0 to R7, PC and the
+//! flags), the 65536 words of memory and a bit for every address loaded from an image, which is what tells code from
+//! the rest when the differences are printed. With banks (see [`crate::bank`]) it is version 2 and goes on with the
+//! number of banks, the one selected and, for every bank, its 16K words and a bit for every one of them loaded. The
+//! selected bank is there twice, in the memory and with the others
+
+use std::fmt::{self, Write as _};
+
+use crate::bank::{BANK_WINDOW_START, BANK_WORDS};
+use crate::disassembler::disassemble;
+use crate::{DEVICE_PAGE, Error, LoadError, MEM_MAX, Registers, State};
+
+const MAGIC: &[u8; 8] = b"LC3SNAP\0";
+/// The version written, bumped with any change to the layout
+pub const SNAPSHOT_VERSION: u16 = 1;
+/// The version written with banks, the layout of [`SNAPSHOT_VERSION`] with the banks after it
+pub const BANKED_SNAPSHOT_VERSION: u16 = 2;
+const BANK_SIZE: usize = BANK_WORDS * 2 + BANK_WORDS / 8;
+const REGISTERS: usize = Registers::InstRet as usize;
+const SNAPSHOT_SIZE: usize = MAGIC.len() + 2 + REGISTERS * 2 + MEM_MAX * 2 + MEM_MAX / 8;
+pub(crate) const REGISTER_NAMES: [&str; REGISTERS] =
+    ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+/// Whether `bytes` look like a snapshot rather than an image, from the magic they start with
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+impl State {
+    /// The registers, the memory and what was loaded, see the module documentation
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SNAPSHOT_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        let version = match self.banks {
+            Some(_) => BANKED_SNAPSHOT_VERSION,
+            None => SNAPSHOT_VERSION,
+        };
+        bytes.extend_from_slice(&version.to_be_bytes());
+        for word in self.registers.iter().chain(self.memory.iter()) {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        for bits in self.loaded.0.iter() {
+            bytes.extend_from_slice(&bits.to_be_bytes());
+        }
+        if let (Some(count), Some(selected)) = (self.bank_count(), self.selected_bank()) {
+            bytes.extend_from_slice(&count.to_be_bytes());
+            bytes.extend_from_slice(&selected.to_be_bytes());
+            for bank in 0..count {
+                let (words, loaded) = self.bank_contents(bank);
+                for word in words {
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                for bits in loaded {
+                    bytes.extend_from_slice(&bits.to_be_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// A state with the registers and memory of a snapshot, without devices and with the terminal as its console
+    pub fn from_snapshot(bytes: &[u8]) -> Result<State, Error> {
+        let Some([high, low, ..]) = bytes.strip_prefix(MAGIC) else {
+            return Err(LoadError::BadSnapshot("it doesn't start with LC3SNAP".to_string()).into());
+        };
+        let version = u16::from_be_bytes([*high, *low]);
+        if version != SNAPSHOT_VERSION && version != BANKED_SNAPSHOT_VERSION {
+            return Err(LoadError::BadSnapshot(format!(
+                "version {}, this VM reads versions {} and {}",
+                version, SNAPSHOT_VERSION, BANKED_SNAPSHOT_VERSION
+            ))
+            .into());
+        }
+        let banks = match (version, bytes.get(SNAPSHOT_SIZE..SNAPSHOT_SIZE + 4)) {
+            (SNAPSHOT_VERSION, _) => None,
+            (_, Some(&[count_high, count_low, selected_high, selected_low])) => Some((
+                u16::from_be_bytes([count_high, count_low]),
+                u16::from_be_bytes([selected_high, selected_low]),
+            )),
+            _ => return Err(LoadError::BadSnapshot("the banks are missing".to_string()).into()),
+        };
+        let size = match banks {
+            Some((count, _)) => SNAPSHOT_SIZE + 4 + count as usize * BANK_SIZE,
+            None => SNAPSHOT_SIZE,
+        };
+        if bytes.len() != size {
+            return Err(LoadError::BadSnapshot(format!(
+                "{} bytes instead of {}",
+                bytes.len(),
+                size
+            ))
+            .into());
+        }
+        if let Some((count, selected)) = banks
+            && selected >= count
+        {
+            return Err(LoadError::BadSnapshot(format!(
+                "bank {} is selected out of {}",
+                selected, count
+            ))
+            .into());
+        }
+        let rest = &bytes[MAGIC.len() + 2..SNAPSHOT_SIZE];
+        let mut words = rest
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        let mut state = State::default();
+        for register in state.registers.iter_mut() {
+            *register = words.next().unwrap(); // The size was checked
+        }
+        for (word, value) in state.memory.iter_mut().zip(&mut words) {
+            *word = value;
+        }
+        let bitmap = &rest[REGISTERS * 2 + MEM_MAX * 2..];
+        for (bits, bytes) in state.loaded.0.iter_mut().zip(bitmap.chunks_exact(8)) {
+            *bits = u64::from_be_bytes(bytes.try_into().unwrap()); // Chunks of exactly eight
+        }
+        // What was written at runtime isn't in the snapshot, any word that isn't zero was
+        for address in 0..MEM_MAX {
+            if state.loaded.contains(address) || state.memory[address] != 0 {
+                state.written.mark(address);
+            }
+        }
+        if let Some((count, selected)) = banks {
+            state.enable_banks(count)?;
+            state.set_selected_bank(selected);
+            for (bank, contents) in bytes[SNAPSHOT_SIZE + 4..]
+                .chunks_exact(BANK_SIZE)
+                .enumerate()
+            {
+                let (words, loaded) = contents.split_at(BANK_WORDS * 2);
+                let words: Vec<u16> = words
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                let loaded: Vec<u64> = loaded
+                    .chunks_exact(8)
+                    .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap())) // Chunks of exactly eight
+                    .collect();
+                state.restore_bank(bank as u16, &words, &loaded);
+            }
+        }
+        Ok(state)
+    }
+
+    /// What differs between this state and `other`: the registers, and the memory below the device page grouped in
+    /// ranges of consecutive addresses. With banks on either side the window is compared in every bank, a bank one
+    /// side doesn't have being all zeros, and without banks the window is bank 0
+    pub fn diff(&self, other: &State) -> StateDiff {
+        let registers = (0..REGISTERS)
+            .filter(|&index| self.registers[index] != other.registers[index])
+            .map(|index| RegisterDifference {
+                register: REGISTER_NAMES[index],
+                ours: self.registers[index],
+                theirs: other.registers[index],
+            })
+            .collect();
+        let mut memory: Vec<MemoryDifference> = Vec::new();
+        let window = BANK_WINDOW_START as usize..BANK_WINDOW_START as usize + BANK_WORDS;
+        let banks = self.bank_count().max(other.bank_count());
+        for address in 0..DEVICE_PAGE {
+            match banks {
+                Some(count) if window.contains(&address) => {
+                    if address != window.start {
+                        continue;
+                    }
+                    for bank in 0..count {
+                        for address in window.clone() {
+                            let ours = self.banked_word(bank, address);
+                            let theirs = other.banked_word(bank, address);
+                            add_difference(&mut memory, Some(bank), address, ours, theirs);
+                        }
+                    }
+                }
+                _ => {
+                    let ours = (self.memory[address], self.loaded.contains(address));
+                    let theirs = (other.memory[address], other.loaded.contains(address));
+                    add_difference(&mut memory, None, address, ours, theirs);
+                }
+            }
+        }
+        StateDiff { registers, memory }
+    }
+
+    /// The word at `address` in the window of `bank` and whether it was loaded from an image
+    fn banked_word(&self, bank: u16, address: usize) -> (u16, bool) {
+        let offset = address - BANK_WINDOW_START as usize;
+        match self.bank_count() {
+            Some(count) if bank < count => {
+                let (words, loaded) = self.bank_contents(bank);
+                (
+                    words[offset],
+                    loaded[offset / 64] & (1 << (offset % 64)) != 0,
+                )
+            }
+            None if bank == 0 => (self.memory[address], self.loaded.contains(address)),
+            _ => (0, false),
+        }
+    }
+}
+
+/// Add the word to the last range if it follows it in the same bank, to a new range otherwise. Nothing if both sides
+/// have the same word
+fn add_difference(
+    memory: &mut Vec<MemoryDifference>,
+    bank: Option<u16>,
+    address: usize,
+    (ours, ours_loaded): (u16, bool),
+    (theirs, theirs_loaded): (u16, bool),
+) {
+    if ours == theirs {
+        return;
+    }
+    let code = ours_loaded || theirs_loaded;
+    match memory.last_mut() {
+        Some(range) if range.bank == bank && range.start as usize + range.ours.len() == address => {
+            range.ours.push(ours);
+            range.theirs.push(theirs);
+            range.code.push(code);
+        }
+        _ => memory.push(MemoryDifference {
+            bank,
+            start: address as u16,
+            ours: vec![ours],
+            theirs: vec![theirs],
+            code: vec![code],
+        }),
+    }
+}
+
+/// The differences between two states, see [`State::diff`]. Empty if they are the same
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterDifference>,
+    pub memory: Vec<MemoryDifference>,
+}
+
+/// A register with another value on each side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterDifference {
+    /// R0 to R7, PC or COND
+    pub register: &'static str,
+    pub ours: u16,
+    pub theirs: u16,
+}
+
+/// Consecutive addresses that differ, from `start` on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryDifference {
+    /// The bank the addresses are in, `None` outside the window or without banks
+    pub bank: Option<u16>,
+    pub start: u16,
+    pub ours: Vec<u16>,
+    pub theirs: Vec<u16>,
+    /// For each address, whether it was loaded from an image on either side. Those are disassembled
+    pub code: Vec<bool>,
+}
+
+impl MemoryDifference {
+    /// The last address of the range
+    pub fn end(&self) -> u16 {
+        self.start + self.ours.len() as u16 - 1
+    }
+
+    /// `2:` before the addresses of a range in bank 2, like `--assert-mem` takes them
+    fn prefix(&self) -> String {
+        self.bank
+            .map(|bank| format!("{}:", bank))
+            .unwrap_or_default()
+    }
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty()
+    }
+
+    /// Words of memory that differ, in every range
+    pub fn words(&self) -> usize {
+        self.memory.iter().map(|range| range.ours.len()).sum()
+    }
+
+    /// Just the counts, what `diff --brief` prints
+    pub fn brief(&self) -> String {
+        format!(
+            "{} register(s) and {} word(s) in {} range(s) differ\n",
+            self.registers.len(),
+            self.words(),
+            self.memory.len()
+        )
+    }
+}
+
+/// A line per register and per address, the loaded ones with the instructions on both sides and those of a bank after
+/// its number
+impl fmt::Display for StateDiff {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.registers {
+            writeln!(
+                formatter,
+                "{:<4} x{:04X} x{:04X}",
+                difference.register, difference.ours, difference.theirs
+            )?;
+        }
+        for range in &self.memory {
+            let prefix = range.prefix();
+            writeln!(
+                formatter,
+                "{}x{:04X}:x{:04X} ({} word(s))",
+                prefix,
+                range.start,
+                range.end(),
+                range.ours.len()
+            )?;
+            for (offset, (ours, theirs)) in range.ours.iter().zip(&range.theirs).enumerate() {
+                let mut line = format!(
+                    "  {}x{:04X} x{:04X} x{:04X}",
+                    prefix,
+                    range.start as usize + offset,
+                    ours,
+                    theirs
+                );
+                if range.code[offset] {
+                    let _ = write!(line, "  {} | {}", disassemble(*ours), disassemble(*theirs));
+                }
+                writeln!(formatter, "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_addresses_are_one_range() {
+        let mut ours = State::default();
+        let mut theirs = State::default();
+        ours.load_image(0x3000, &[0x1261, 0x1262, 0xF025]);
+        theirs.load_image(0x3000, &[0x1261, 0x1263, 0xF025]);
+        for address in 0x4000..0x4003 {
+            theirs.memory_write(address, 7);
+        }
+        theirs.memory_write(0x4010, 1);
+        theirs.register_write(Registers::R1, 4);
+        let diff = ours.diff(&theirs);
+        assert_eq!(
+            diff.registers,
+            vec![RegisterDifference {
+                register: "R1",
+                ours: 0,
+                theirs: 4
+            }]
+        );
+        let ranges: Vec<(u16, u16)> = diff
+            .memory
+            .iter()
+            .map(|range| (range.start, range.end()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![(0x3001, 0x3001), (0x4000, 0x4002), (0x4010, 0x4010)]
+        );
+        assert_eq!(diff.words(), 5);
+        assert_eq!(
+            diff.to_string().lines().take(3).collect::<Vec<_>>(),
+            vec![
+                "R1   x0000 x0004",
+                "x3001:x3001 (1 word(s))",
+                "  x3001 x1262 x1263  ADD R1, R1, #2 | ADD R1, R1, #3"
+            ]
+        );
+        assert_eq!(
+            diff.brief(),
+            "1 register(s) and 5 word(s) in 3 range(s) differ\n"
+        );
+        assert!(ours.diff(&ours).is_empty());
+    }
+
+    #[test]
+    fn a_snapshot_gives_the_same_state_back() {
+        let mut state = State::default();
+        state.load_image(0x3000, &[0x1261, 0xF025]);
+        state.memory_write(0x4000, 9);
+        state.register_write(Registers::Pc, 0x3002);
+        let snapshot = state.to_snapshot();
+        let restored = State::from_snapshot(&snapshot).unwrap();
+        assert!(state.diff(&restored).is_empty());
+        assert!(restored.loaded.contains(0x3001));
+        assert!(!restored.loaded.contains(0x4000));
+        assert!(matches!(
+            State::from_snapshot(&snapshot[..100]),
+            Err(Error::Load(LoadError::BadSnapshot(_)))
+        ));
+    }
+
+    #[test]
+    fn every_bank_is_compared() {
+        let mut ours = State::default();
+        let mut theirs = State::default();
+        ours.enable_banks(2).unwrap();
+        theirs.enable_banks(2).unwrap();
+        theirs.poke_bank(1, 0x9000, 7);
+        theirs.poke_bank(1, 0x9001, 8);
+        theirs.memory_write(0xC000, 1);
+        let diff = ours.diff(&theirs);
+        let ranges: Vec<(Option<u16>, u16, u16)> = diff
+            .memory
+            .iter()
+            .map(|range| (range.bank, range.start, range.end()))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![(Some(1), 0x9000, 0x9001), (None, 0xC000, 0xC000)]
+        );
+        assert_eq!(
+            diff.to_string().lines().take(2).collect::<Vec<_>>(),
+            vec!["1:x9000:x9001 (2 word(s))", "  1:x9000 x0000 x0007"]
+        );
+        // A bank only one side has is compared with zeros, the window without banks is bank 0
+        let mut plain = State::default();
+        plain.memory_write(0x9000, 5);
+        let ranges: Vec<(Option<u16>, u16)> = plain
+            .diff(&theirs)
+            .memory
+            .iter()
+            .map(|range| (range.bank, range.start))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![(Some(0), 0x9000), (Some(1), 0x9000), (None, 0xC000)]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Checks of the stack discipline around R6, what `--stack <start>:<end>` turns on. The stack grows down from the end
+//! of the region towards its start, R6 pointing at the last pushed word (or just past the end when it is empty).
+//! Three mistakes are caught:
+//! * a store through R6 (`STR _, R6, #n`) outside of the region
+//! * R6 going from the region to below its start, an overflow
+//! * a load through R6 (`LDR _, R6, #n`) above the end of the region, popping more than was pushed
+
+use std::fmt;
+
+use crate::Operations;
+
+/// The stack region and what a mistake does, see [`crate::State::set_stack_check`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackCheck {
+    /// Lowest address the stack can grow to
+    pub start: u16,
+    /// Highest address of the stack, the first word pushed
+    pub end: u16,
+    /// Stop with [`crate::RuntimeError::Stack`] instead of printing a warning
+    pub fail: bool,
+}
+
+impl StackCheck {
+    /// The stack from `start` to `end`, both included
+    pub fn new(start: u16, end: u16, fail: bool) -> StackCheck {
+        StackCheck { start, end, fail }
+    }
+
+    /// The address an LDR or STR through R6 is about to access, if it breaks the discipline
+    pub(crate) fn before(&self, pc: u16, word: u16, stack_pointer: u16) -> Option<StackViolation> {
+        let operation = word >> 12;
+        let through_stack_pointer = (word >> 6) & 0x7 == 6;
+        if !through_stack_pointer
+            || (operation != Operations::Ldr as u16 && operation != Operations::Str as u16)
+        {
+            return None;
+        }
+        let offset = crate::operations::sign_extend(word & 0x3F, 6);
+        let address = stack_pointer.wrapping_add(offset);
+        match operation == Operations::Str as u16 {
+            true if address < self.start || address > self.end => {
+                Some(StackViolation::StoreOutside { pc, address })
+            }
+            false if address > self.end => Some(StackViolation::Underflow { pc, address }),
+            _ => None,
+        }
+    }
+
+    /// Whether R6 left the stack through its start while the instruction at `pc` ran
+    pub(crate) fn after(&self, pc: u16, before: u16, after: u16) -> Option<StackViolation> {
+        // Until R6 is in the region there is no stack yet, an empty stack points just past its end
+        let was_inside = before >= self.start && before as u32 <= self.end as u32 + 1;
+        (was_inside && after < self.start).then_some(StackViolation::Overflow {
+            pc,
+            stack_pointer: after,
+        })
+    }
+}
+
+/// A break of the stack discipline by the instruction at `pc`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StackViolation {
+    StoreOutside { pc: u16, address: u16 },
+    Overflow { pc: u16, stack_pointer: u16 },
+    Underflow { pc: u16, address: u16 },
+}
+
+impl fmt::Display for StackViolation {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackViolation::StoreOutside { pc, address } => write!(
+                formatter,
+                "Store through R6 outside of the stack at x{:04X}: x{:04X}",
+                pc, address
+            ),
+            StackViolation::Overflow { pc, stack_pointer } => write!(
+                formatter,
+                "Stack overflow at x{:04X}: R6=x{:04X} is below the stack",
+                pc, stack_pointer
+            ),
+            StackViolation::Underflow { pc, address } => write!(
+                formatter,
+                "Stack underflow at x{:04X}: loading x{:04X}, above the top of the stack",
+                pc, address
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_accesses_through_r6_are_checked() {
+        let stack = StackCheck::new(0x4000, 0x40FF, false);
+        // STR R7, R6, #-1 with R6 at the start of the stack
+        assert_eq!(
+            stack.before(0x3000, 0x7FBF, 0x4000),
+            Some(StackViolation::StoreOutside {
+                pc: 0x3000,
+                address: 0x3FFF
+            })
+        );
+        // STR R7, R5, #-1 isn't through the stack pointer
+        assert_eq!(stack.before(0x3000, 0x7F7F, 0x4000), None);
+        // LDR R7, R6, #0 right past the end pops from an empty stack
+        assert_eq!(
+            stack.before(0x3000, 0x6F80, 0x4100),
+            Some(StackViolation::Underflow {
+                pc: 0x3000,
+                address: 0x4100
+            })
+        );
+        assert_eq!(stack.before(0x3000, 0x6F80, 0x40FF), None);
+    }
+
+    #[test]
+    fn r6_only_overflows_once_it_was_in_the_stack() {
+        let stack = StackCheck::new(0x4000, 0x40FF, false);
+        assert_eq!(stack.after(0x3000, 0, 0x0100), None);
+        assert_eq!(stack.after(0x3000, 0x4100, 0x40FF), None);
+        assert_eq!(
+            stack.after(0x3005, 0x4000, 0x3FFF),
+            Some(StackViolation::Overflow {
+                pc: 0x3005,
+                stack_pointer: 0x3FFF
+            })
+        );
+    }
+}
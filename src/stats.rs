@@ -0,0 +1,246 @@
+//! Counters of a run beyond the instructions executed, what `--time` prints. Keeping them costs a little on every trap,
+//! so they are only there once [`crate::State::enable_stats`] was called. [`crate::State::enable_profile`] counts every
+//! instruction by opcode and every BR taken too, which runs the program in the checked loop without the JIT.
+//!
+//! `--stats-out` writes them as a row of CSV (see [`csv_row`]) so the runs of a whole course can be aggregated in a
+//! spreadsheet: the image, the instructions, one column per opcode, one per trap routine, the non-blocking GETC that found no key, the ratio of BR taken and
+//! the wall time. A file that already has rows gets one more, its header is written only once
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::instrument::Instrumentation;
+use crate::output_file::AtomicWriter;
+use crate::{Error, Operations, Registers, State};
+
+/// The counters of a run, see [`crate::State::stats`]
+pub struct Stats {
+    /// Times every trap vector was executed
+    traps: Box<[u64; 256]>,
+    /// GETC that found no key, see [`crate::State::set_getc_nonblocking`]
+    empty_getcs: u64,
+    /// Only there with [`crate::State::enable_profile`]
+    profile: Option<Box<Profile>>,
+}
+
+/// What [`crate::State::enable_profile`] counts. [`crate::run_with_budget`] runs with it as the instrumentation, the
+/// checks of a traced or stepped run count with [`Stats::instruction`]
+#[derive(Default)]
+pub(crate) struct Profile {
+    /// Instructions executed by opcode
+    opcodes: [u64; 16],
+    /// BR that jumped, those that didn't are the rest of `opcodes[0]`
+    branches_taken: u64,
+}
+
+impl Profile {
+    #[inline]
+    fn count(&mut self, instruction: u16, flags: u16) {
+        let opcode = instruction >> 12;
+        self.opcodes[opcode as usize] += 1;
+        if opcode == Operations::Br as u16 && (instruction >> 9) & flags & 0b111 != 0 {
+            self.branches_taken += 1;
+        }
+    }
+}
+
+impl Instrumentation for Profile {
+    #[inline]
+    fn before_instruction(&mut self, pc: u16, state: &State) {
+        self.count(state.memory[pc as usize], state.registers[Registers::Flags]);
+    }
+}
+
+impl Stats {
+    pub(crate) fn new() -> Stats {
+        Stats {
+            traps: Box::new([0; 256]),
+            empty_getcs: 0,
+            profile: None,
+        }
+    }
+
+    pub(crate) fn with_profile() -> Stats {
+        Stats {
+            profile: Some(Box::default()),
+            ..Stats::new()
+        }
+    }
+
+    #[inline]
+    pub(crate) fn trap(&mut self, vector: u8) {
+        self.traps[vector as usize] += 1;
+    }
+
+    #[inline]
+    pub(crate) fn empty_getc(&mut self) {
+        self.empty_getcs += 1;
+    }
+
+    /// The profile for the run loop to count with, [`Stats::restore_profile`] gives it back
+    pub(crate) fn take_profile(&mut self) -> Option<Box<Profile>> {
+        self.profile.take()
+    }
+
+    pub(crate) fn restore_profile(&mut self, profile: Box<Profile>) {
+        self.profile = Some(profile);
+    }
+
+    /// Count `instruction`, about to run with the condition codes `flags`
+    #[inline]
+    pub(crate) fn instruction(&mut self, instruction: u16, flags: u16) {
+        if let Some(profile) = &mut self.profile {
+            profile.count(instruction, flags);
+        }
+    }
+
+    /// Traps executed, of any vector
+    pub fn traps(&self) -> u64 {
+        self.traps.iter().sum()
+    }
+
+    /// The vectors executed at least once and how many times, in order
+    pub fn traps_by_vector(&self) -> Vec<(u8, u64)> {
+        (0..=u8::MAX)
+            .zip(self.traps.iter().copied())
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    /// Non-blocking GETC that returned 0 because no key was pending
+    pub fn empty_getcs(&self) -> u64 {
+        self.empty_getcs
+    }
+
+    /// Instructions executed by opcode, indexed by [`Operations`]. Only with [`crate::State::enable_profile`]
+    pub fn opcodes(&self) -> Option<[u64; 16]> {
+        self.profile.as_ref().map(|profile| profile.opcodes)
+    }
+
+    /// The share of the BR executed that jumped, from 0 to 1. Only with [`crate::State::enable_profile`] and once a BR
+    /// ran
+    pub fn branch_taken_ratio(&self) -> Option<f64> {
+        let profile = self.profile.as_ref()?;
+        let branches = profile.opcodes[Operations::Br as usize];
+        (branches > 0).then(|| profile.branches_taken as f64 / branches as f64)
+    }
+}
+
+/// The column of every opcode, in the order of [`Operations`]
+const OPCODE_COLUMNS: [&str; 16] = [
+    "br", "add", "ld", "st", "jsr", "and", "ldr", "str", "rti", "not", "ldi", "sti", "jmp", "res",
+    "lea", "trap",
+];
+
+/// The trap routines with a column of their own, any other vector goes to `trap_other`
+const TRAP_COLUMNS: [(u8, &str); 6] = [
+    (0x20, "getc"),
+    (0x21, "out"),
+    (0x22, "puts"),
+    (0x23, "in"),
+    (0x24, "putsp"),
+    (0x25, "halt"),
+];
+
+/// The header row of [`csv_row`], with its line end
+pub fn csv_header() -> String {
+    let mut header = "image,instructions".to_string();
+    for opcode in OPCODE_COLUMNS {
+        let _ = write!(header, ",op_{}", opcode);
+    }
+    for (_, name) in TRAP_COLUMNS {
+        let _ = write!(header, ",trap_{}", name);
+    }
+    header.push_str(",trap_other,getc_empty,branch_taken_ratio,wall_ms\n");
+    header
+}
+
+/// A row of CSV with the counters of the run of `image` on `state`, with its line end. The columns of the opcodes
+/// and the ratio are empty without [`crate::State::enable_profile`], those of the traps without
+/// [`crate::State::enable_stats`], that of the wall time without `elapsed`
+pub fn csv_row(image: &str, state: &State, elapsed: Option<Duration>) -> String {
+    let mut row = format!("{},{}", csv_field(image), state.instructions_executed());
+    let stats = state.stats();
+    let opcodes = stats.and_then(Stats::opcodes);
+    for index in 0..OPCODE_COLUMNS.len() {
+        row.push(',');
+        if let Some(opcodes) = opcodes {
+            let _ = write!(row, "{}", opcodes[index]);
+        }
+    }
+    for (vector, _) in TRAP_COLUMNS {
+        row.push(',');
+        if let Some(stats) = stats {
+            let _ = write!(row, "{}", stats.traps[vector as usize]);
+        }
+    }
+    row.push(',');
+    if let Some(stats) = stats {
+        let named = TRAP_COLUMNS
+            .iter()
+            .map(|&(vector, _)| stats.traps[vector as usize])
+            .sum::<u64>();
+        let _ = write!(row, "{},{}", stats.traps() - named, stats.empty_getcs);
+    } else {
+        row.push(',');
+    }
+    row.push(',');
+    if let Some(ratio) = stats.and_then(Stats::branch_taken_ratio) {
+        let _ = write!(row, "{:.4}", ratio);
+    }
+    row.push(',');
+    if let Some(elapsed) = elapsed {
+        let _ = write!(row, "{:.3}", elapsed.as_secs_f64() * 1e3);
+    }
+    row.push('\n');
+    row
+}
+
+/// `field` as it is, or between double quotes with theirs doubled if it has a comma, a quote or a line end
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Append `rows` to the CSV file at `path`, the header first if the file is new or empty
+pub fn append_csv(path: &Path, rows: &[String]) -> Result<(), Error> {
+    let mut file = AtomicWriter::append(path)?;
+    let mut text = match file.existing_len() {
+        0 => csv_header(),
+        _ => String::new(),
+    };
+    for row in rows {
+        text.push_str(row);
+    }
+    file.write_all(text.as_bytes())?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{CaptureOutput, state_with_snippet};
+
+    #[test]
+    fn the_row_has_a_column_for_every_one_of_the_header() {
+        let mut state = state_with_snippet(
+            ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #2\nLOOP ADD R0, R0, #-1\nBRp LOOP\nHALT\n.END",
+        );
+        state.set_output(CaptureOutput::default());
+        state.enable_profile();
+        crate::run_with_budget(&mut state, 100).unwrap();
+        let row = csv_row("a, \"b\".obj", &state, Some(Duration::from_millis(2)));
+        assert!(row.starts_with(r#""a, ""b"".obj",7,2,3,"#), "{}", row);
+        assert!(row.ends_with(",1,0,0,0.5000,2.000\n"), "{}", row);
+        let columns = |line: &str| {
+            line.replace(r#""a, ""b"".obj""#, "image")
+                .split(',')
+                .count()
+        };
+        assert_eq!(columns(&row), columns(&csv_header()));
+    }
+}
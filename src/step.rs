@@ -0,0 +1,168 @@
+//! Running a program an instruction at a time, what `--step` does. After every instruction its line of the trace (see
+//! [`crate::trace`]) goes to stderr and the VM waits for a key:
+//! * space or Enter runs the next instruction
+//! * `c` runs the rest of the program without stopping
+//! * `q` stops it like Ctrl-C
+//!
+//! Any other key is ignored. The keys come from the keyboard of the program unless [`Stepper::with_commands`] gives
+//! another input. The VM only waits between instructions: a GETC or IN waiting for a key is inside one, so the next
+//! key typed goes to the program whatever it is, and the VM waits for a command once the trap returned. A key typed
+//! while the VM waits is never the program's
+
+use std::io::Write;
+
+use crate::console::Input;
+use crate::{Error, RuntimeError, State, run_with_budget, trace};
+
+/// What a key typed between two instructions does, see [`crate::step`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepCommand {
+    Step,
+    Continue,
+    Quit,
+}
+
+impl StepCommand {
+    /// The command of `key`, `None` if it isn't one
+    pub fn from_key(key: u8) -> Option<StepCommand> {
+        match key {
+            b' ' | b'\n' | b'\r' => Some(StepCommand::Step),
+            b'c' | b'C' => Some(StepCommand::Continue),
+            b'q' | b'Q' => Some(StepCommand::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Runs a machine a step at a time, see [`crate::step`]
+#[derive(Default)]
+pub struct Stepper {
+    /// `None` to read the commands from the input of the machine
+    commands: Option<Box<dyn Input + Send>>,
+}
+
+impl Stepper {
+    /// A stepper reading the commands from the input of the machine it runs
+    pub fn new() -> Stepper {
+        Stepper::default()
+    }
+
+    /// A stepper reading the commands from `commands`, the program keeps its input to itself
+    pub fn with_commands(commands: impl Input + Send + 'static) -> Stepper {
+        Stepper {
+            commands: Some(Box::new(commands)),
+        }
+    }
+
+    /// Run the program, writing the line of every instruction to `trace` and waiting for a command after it. Once the
+    /// commands end the program runs without stopping. Fails with [`RuntimeError::Interrupted`] on `q`, and if the
+    /// program doesn't halt within `budget` instructions like [`run_with_budget`]
+    pub fn run(
+        &mut self,
+        state: &mut State,
+        trace: &mut impl Write,
+        budget: u64,
+    ) -> Result<(), Error> {
+        for ran in 1..=budget {
+            if !state.is_running() {
+                return Ok(());
+            }
+            trace::step_traced(
+                state,
+                trace,
+                &trace::TraceFilter::new(),
+                trace::TraceFormat::Reference,
+            )?;
+            trace.flush()?;
+            if !state.is_running() {
+                return Ok(());
+            }
+            match self.next_command(state) {
+                StepCommand::Step => {}
+                StepCommand::Continue => {
+                    return run_with_budget(state, budget - ran).map_err(|error| match error {
+                        Error::Runtime(RuntimeError::BudgetExhausted(_)) => {
+                            RuntimeError::BudgetExhausted(budget).into()
+                        }
+                        error => error,
+                    });
+                }
+                StepCommand::Quit => return Err(RuntimeError::Interrupted.into()),
+            }
+        }
+        match state.is_running() {
+            true => Err(RuntimeError::BudgetExhausted(budget).into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Wait for a command, what the program printed so far shown first. `q` if an interrupt was requested meanwhile
+    /// and `c` once the commands end
+    fn next_command(&mut self, state: &mut State) -> StepCommand {
+        let _ = state.output.flush();
+        loop {
+            let key = match &mut self.commands {
+                Some(commands) => commands.read_byte(),
+                None => state.input.read_byte(),
+            };
+            match key.map(StepCommand::from_key) {
+                Some(Some(command)) => return command,
+                Some(None) => {}
+                None if state.interrupt.take() => return StepCommand::Quit,
+                None => return StepCommand::Continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{CaptureOutput, ScriptedInput, state_with_snippet};
+
+    const ECHO: &str = ".ORIG x3000\nGETC\nOUT\nHALT\n.END";
+
+    fn step(input: &str, stepper: &mut Stepper) -> (Result<(), Error>, String, String) {
+        let mut state = state_with_snippet(ECHO);
+        let output = CaptureOutput::default();
+        state.set_input(ScriptedInput::new(input));
+        state.set_output(output.clone());
+        let mut trace = Vec::new();
+        let result = stepper.run(&mut state, &mut trace, 100);
+        (
+            result,
+            output.as_string(),
+            String::from_utf8(trace).unwrap(),
+        )
+    }
+
+    #[test]
+    fn the_key_typed_during_a_getc_is_the_programs() {
+        // GETC takes the q, then a space steps to OUT and a q quits before HALT
+        let (result, output, trace) = step("q q", &mut Stepper::new());
+        assert!(matches!(
+            result,
+            Err(Error::Runtime(RuntimeError::Interrupted))
+        ));
+        assert_eq!(output, "q");
+        assert_eq!(trace.lines().count(), 2);
+        assert!(trace.starts_with("PC=x3000 IR=xF020 R0=x0071"), "{}", trace);
+    }
+
+    #[test]
+    fn other_keys_are_ignored_and_the_steps_end_with_halt() {
+        let (result, output, trace) = step("ax\n ", &mut Stepper::new());
+        result.unwrap();
+        assert_eq!(output, "aHALT");
+        assert_eq!(trace.lines().count(), 3);
+    }
+
+    #[test]
+    fn continuing_runs_the_rest_without_a_trace() {
+        let mut stepper = Stepper::with_commands(ScriptedInput::new("c"));
+        let (result, output, trace) = step("z", &mut stepper);
+        result.unwrap();
+        assert_eq!(output, "zHALT");
+        assert_eq!(trace.lines().count(), 1);
+    }
+}
@@ -0,0 +1,311 @@
+//! The machine-readable result of a run, what the command line VM writes with `--json-summary`:
+//! ```json
+//! {
+//!   "outcome": "halted",
+//!   "instructions": 1234,
+//!   "wall_time_ms": 3,
+//!   "error": null,
+//!   "registers": { "R0": 0, "R1": 17, ..., "PC": 12291, "COND": 2 },
+//!   "requested_memory": { "x4000": 17, "x4001": 0 },
+//!   "warnings": [{ "code": "W003", "message": "self-modifying code: ...", "count": 1 }]
+//! }
+//! ```
+//! `wall_time_ms` is left out under `--deterministic`. A program that halted with the EXIT trap has the outcome `exited`, with its exit code in a `code` field right after.
+//! Scripts and graders parse it, so every field is renamed explicitly: renaming a Rust field must never change the JSON
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::warning::WarningSummary;
+use crate::{Category, Error, LoadError, Registers, RuntimeError, State, TerminalError};
+
+/// How the run ended
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    #[serde(rename = "halted")]
+    Halted,
+    /// It halted with the EXIT trap, the summary has its `code`
+    #[serde(rename = "exited")]
+    Exited,
+    /// Any error, including a Ctrl-C
+    #[serde(rename = "error")]
+    Error,
+    /// It was still running when the time limit passed, see [`RuntimeError::TimedOut`]
+    #[serde(rename = "timeout")]
+    Timeout,
+    /// It was still running after the instruction budget, see [`RuntimeError::BudgetExhausted`]
+    #[serde(rename = "budget")]
+    Budget,
+}
+
+/// The error of a run that failed
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ErrorSummary {
+    /// The name of the [`Error`] variant, like `BadTrapCode`
+    #[serde(rename = "kind")]
+    pub kind: &'static str,
+    /// See [`Error::category`]
+    #[serde(rename = "category")]
+    pub category: Category,
+    /// The message the command line VM prints
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+/// The registers when the run ended
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterSummary {
+    #[serde(rename = "R0")]
+    pub r0: u16,
+    #[serde(rename = "R1")]
+    pub r1: u16,
+    #[serde(rename = "R2")]
+    pub r2: u16,
+    #[serde(rename = "R3")]
+    pub r3: u16,
+    #[serde(rename = "R4")]
+    pub r4: u16,
+    #[serde(rename = "R5")]
+    pub r5: u16,
+    #[serde(rename = "R6")]
+    pub r6: u16,
+    #[serde(rename = "R7")]
+    pub r7: u16,
+    #[serde(rename = "PC")]
+    pub pc: u16,
+    #[serde(rename = "COND")]
+    pub cond: u16,
+}
+
+impl RegisterSummary {
+    pub fn of(state: &State) -> RegisterSummary {
+        RegisterSummary {
+            r0: state.register_read(Registers::R0),
+            r1: state.register_read(Registers::R1),
+            r2: state.register_read(Registers::R2),
+            r3: state.register_read(Registers::R3),
+            r4: state.register_read(Registers::R4),
+            r5: state.register_read(Registers::R5),
+            r6: state.register_read(Registers::R6),
+            r7: state.register_read(Registers::R7),
+            pc: state.register_read(Registers::Pc),
+            cond: state.register_read(Registers::Flags),
+        }
+    }
+}
+
+/// Everything `--json-summary` writes, see the module documentation
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    #[serde(rename = "outcome")]
+    pub outcome: Outcome,
+    /// The exit code of the EXIT trap, only there when the outcome is [`Outcome::Exited`]
+    #[serde(rename = "code", skip_serializing_if = "Option::is_none")]
+    pub code: Option<u8>,
+    #[serde(rename = "instructions")]
+    pub instructions: u64,
+    /// Left out by [`RunSummary::without_host_measures`]
+    #[serde(rename = "wall_time_ms", skip_serializing_if = "Option::is_none")]
+    pub wall_time_ms: Option<u64>,
+    /// Only there when the outcome is [`Outcome::Error`]
+    #[serde(rename = "error")]
+    pub error: Option<ErrorSummary>,
+    #[serde(rename = "registers")]
+    pub registers: RegisterSummary,
+    /// Every word of the requested ranges by address, written like `x4000`
+    #[serde(rename = "requested_memory")]
+    pub requested_memory: BTreeMap<String, u16>,
+    /// Every warning printed, with how many times it was emitted, see [`crate::warning`]
+    #[serde(rename = "warnings")]
+    pub warnings: Vec<WarningSummary>,
+}
+
+impl RunSummary {
+    /// The summary of a run that ended with `result` after `wall_time`, with the words of the inclusive `memory` ranges.
+    /// The memory is peeked (see [`State::peek`]), reading the summary doesn't take a key
+    pub fn new(
+        state: &State,
+        result: &Result<(), Error>,
+        wall_time: Duration,
+        memory: &[(u16, u16)],
+    ) -> RunSummary {
+        let (outcome, error) = match result {
+            Ok(()) if state.exit_code().is_some() => (Outcome::Exited, None),
+            Ok(()) => (Outcome::Halted, None),
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => (Outcome::Budget, None),
+            Err(Error::Runtime(RuntimeError::TimedOut(_))) => (Outcome::Timeout, None),
+            Err(error) => (
+                Outcome::Error,
+                Some(ErrorSummary {
+                    kind: error_kind(error.root()),
+                    category: error.root().category(),
+                    message: error.to_string(),
+                }),
+            ),
+        };
+        let requested_memory = memory
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .map(|address| (format!("x{:04X}", address), state.peek(address as usize)))
+            .collect();
+        RunSummary {
+            outcome,
+            code: state.exit_code().filter(|_| outcome == Outcome::Exited),
+            instructions: state.instructions_executed(),
+            wall_time_ms: Some(wall_time.as_millis().try_into().unwrap_or(u64::MAX)),
+            error,
+            registers: RegisterSummary::of(state),
+            requested_memory,
+            warnings: state.warnings().emitted().to_vec(),
+        }
+    }
+
+    /// The summary without the wall time, the same for every run of the program, see [`crate::determinism`]
+    pub fn without_host_measures(self) -> RunSummary {
+        RunSummary {
+            wall_time_ms: None,
+            ..self
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap() // Every field is a number, a string or a map with string keys, it can't fail
+    }
+}
+
+/// The summaries of several runs in a JSON array, like those of `run --instances`
+pub fn to_json_array(summaries: &[RunSummary]) -> String {
+    serde_json::to_string_pretty(summaries).unwrap() // Like RunSummary::to_json
+}
+
+/// The name of the variant, stable for scripts unlike the message. A [`RuntimeError::Fault`] is named after its error
+pub(crate) fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::Load(error) => match error {
+            LoadError::File(_) => "BadFile",
+            LoadError::BadImageSize => "BadImageSize",
+            LoadError::ObjectVersion { .. } => "ObjectVersion",
+            LoadError::SeveralSections(_) => "SeveralSections",
+            LoadError::BadSnapshot(_) => "BadSnapshot",
+            LoadError::BadRecording(_) => "BadRecording",
+            LoadError::BadTrace(_) => "BadTrace",
+            LoadError::BadArguments(_) => "BadArguments",
+            LoadError::OverlapsSystem(_) => "OverlapsSystem",
+            LoadError::Overlap { .. } => "Overlap",
+        },
+        Error::Runtime(error) => match error {
+            RuntimeError::BadRegisterReference(_) => "BadRegisterReference",
+            RuntimeError::BadOpCode(_) => "BadOpCode",
+            RuntimeError::BadTrapCode(_) => "BadTrapCode",
+            RuntimeError::Trap(_) => "Trap",
+            RuntimeError::BudgetExhausted(_) => "BudgetExhausted",
+            RuntimeError::Interrupted => "Interrupted",
+            RuntimeError::TimedOut(_) => "TimedOut",
+            RuntimeError::UninitializedExec(_) => "UninitializedExec",
+            RuntimeError::Stack(_) => "Stack",
+            RuntimeError::SelfModify { .. } => "SelfModify",
+            RuntimeError::WriteToCode { .. } => "WriteToCode",
+            RuntimeError::Hang(_) => "Hang",
+            RuntimeError::SupervisorStackOverflow { .. } => "SupervisorStackOverflow",
+            RuntimeError::AssertFailed(_) => "AssertFailed",
+            RuntimeError::ReplayDiverges { .. } => "ReplayDiverges",
+            RuntimeError::InputTimeout { .. } => "InputTimeout",
+            RuntimeError::WarningDenied(_) => "WarningDenied",
+            RuntimeError::Fault(fault) => error_kind(&fault.error),
+        },
+        Error::Terminal(error) => match error {
+            TerminalError::DisableInputBuffering(_) => "DisableInputBuffering",
+            TerminalError::RestoreInputBuffering(_) => "RestoreInputBuffering",
+        },
+        Error::FewArguments => "FewArguments",
+        Error::BadArgument(_) => "BadArgument",
+        Error::BadAddress(_) => "BadAddress",
+        Error::Assembly(_) => "Assembly",
+        Error::NotFormatted(_) => "NotFormatted",
+        Error::TracesDiverge(_) => "TracesDiverge",
+        Error::MachinesDiverge(_) => "MachinesDiverge",
+        Error::StatesDiffer(..) => "StatesDiffer",
+        Error::AssertionsFailed(_) => "AssertionsFailed",
+        Error::JobsFailed(_) => "JobsFailed",
+        Error::CasesFailed(_) => "CasesFailed",
+        Error::InstancesFailed(_) => "InstancesFailed",
+        Error::BadJobs(_) => "BadJobs",
+        Error::DeviceRange(..) => "DeviceRange",
+        Error::DevicePlugin(_) => "DevicePlugin",
+        Error::Io(_) => "Io",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn summary(result: Result<(), Error>) -> serde_json::Value {
+        let mut state = State::default();
+        state.register_write(Registers::R1, 17);
+        state.register_write(Registers::Pc, 0x3005);
+        state.memory_write(0x4000, 0x0011);
+        let summary = RunSummary::new(
+            &state,
+            &result,
+            Duration::from_millis(12),
+            &[(0x4000, 0x4001)],
+        );
+        serde_json::from_str(&summary.to_json()).unwrap()
+    }
+
+    #[test]
+    fn the_summary_of_a_halted_run_keeps_its_schema() {
+        assert_eq!(
+            summary(Ok(())),
+            json!({
+                "outcome": "halted",
+                "instructions": 0,
+                "wall_time_ms": 12,
+                "error": null,
+                "registers": {
+                    "R0": 0, "R1": 17, "R2": 0, "R3": 0, "R4": 0, "R5": 0, "R6": 0, "R7": 0,
+                    "PC": 0x3005, "COND": 2
+                },
+                "requested_memory": { "x4000": 17, "x4001": 0 },
+                "warnings": []
+            })
+        );
+    }
+
+    #[test]
+    fn a_failed_run_names_its_error() {
+        let summary = summary(Err(RuntimeError::BadTrapCode(0x26).into()));
+        assert_eq!(summary["outcome"], "error");
+        assert_eq!(
+            summary["error"],
+            json!({ "kind": "BadTrapCode", "category": "runtime", "message": "Bad trap code x26" })
+        );
+    }
+
+    #[test]
+    fn budget_and_timeout_are_outcomes_not_errors() {
+        let budget = summary(Err(RuntimeError::BudgetExhausted(10).into()));
+        assert_eq!(budget["outcome"], "budget");
+        assert_eq!(budget["error"], json!(null));
+        let timeout = summary(Err(RuntimeError::TimedOut(Duration::from_secs(1)).into()));
+        assert_eq!(timeout["outcome"], "timeout");
+        assert_eq!(timeout["error"], json!(null));
+    }
+
+    #[test]
+    fn an_exit_trap_has_its_code() {
+        let mut state = crate::test_util::state_with_snippet(
+            ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #3\nEXIT\n.END",
+        );
+        state.set_output(Vec::new());
+        let result = crate::run_loop(&mut state);
+        let summary = RunSummary::new(&state, &result, Duration::ZERO, &[]);
+        let summary: serde_json::Value = serde_json::from_str(&summary.to_json()).unwrap();
+        assert_eq!(summary["outcome"], "exited");
+        assert_eq!(summary["code"], 3);
+    }
+}
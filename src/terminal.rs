@@ -0,0 +1,286 @@
+//! The platform side of the keyboard: putting the terminal in raw mode and reading its keys.
+//! [`TerminalInput`] runs on top of any [`Terminal`], termios on Unix and the console API on Windows.
+//! Only built with the `cli` feature
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::mem;
+use std::panic;
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use crate::console::{Input, KeyReader, KeyWait, spawn_reader};
+use crate::{Error, InterruptHandle};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(unix)]
+pub use unix::{UnixTerminal, resized, size};
+#[cfg(windows)]
+pub use windows::{WindowsTerminal, resized, size};
+
+/// How long dropping a [`TerminalInput`] waits for its reader thread before leaving it behind
+const READER_JOIN_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often a [`TerminalInput`] waiting for a key looks at its interrupt handle
+const INTERRUPT_CHECK: Duration = Duration::from_millis(50);
+
+/// ANSI escape sequences of the extended keys, the bytes a Unix terminal sends for them.
+/// Windows reports these keys without a character, they are translated to the same bytes so programs see the same input everywhere
+pub const ARROW_UP: &[u8] = b"\x1b[A";
+pub const ARROW_DOWN: &[u8] = b"\x1b[B";
+pub const ARROW_RIGHT: &[u8] = b"\x1b[C";
+pub const ARROW_LEFT: &[u8] = b"\x1b[D";
+pub const HOME: &[u8] = b"\x1b[H";
+pub const END: &[u8] = b"\x1b[F";
+
+/// A keyboard that can be switched to raw mode: keys arrive one at a time, as soon as they are typed and without echo
+pub trait Terminal: Send + Sync {
+    fn enter_raw_mode(&self) -> Result<(), Error>;
+    /// Give the terminal back its previous settings, it does nothing if it isn't in raw mode
+    fn leave_raw_mode(&self) -> Result<(), Error>;
+    /// Wait for the next key and add its bytes, the same on every platform: Enter is `\n` (x0A),
+    /// the arrows, Home and End are the sequences above and any other key without a character is dropped.
+    /// Returns false once the input has ended
+    fn read_key(&self, bytes: &mut Vec<u8>) -> io::Result<bool>;
+}
+
+/// The terminal the VM runs in
+pub fn stdin() -> Result<Box<dyn Terminal>, Error> {
+    #[cfg(unix)]
+    return Ok(Box::new(UnixTerminal::new(io::stdin())?));
+    #[cfg(windows)]
+    return Ok(Box::new(WindowsTerminal::stdin()));
+}
+
+/// The keyboard of the terminal for the lifetime of a program: it puts the [`Terminal`] in raw mode (no echo, keys arrive
+/// without waiting for Enter), reads the keys on a background thread like [`crate::console::StdinInput`] and gives the terminal back on drop.
+///
+/// Dropping it restores the terminal settings and waits up to [`READER_JOIN_TIMEOUT`] for the reader thread, a thread
+/// still blocked reading is left behind so exiting never hangs on it.
+/// The Ctrl-C handler and the panic hook can't wait for the drop, they restore the terminal with a [`TerminalRestore`]
+pub struct TerminalInput {
+    reader: Option<KeyReader>,
+    restore: TerminalRestore,
+    /// Stops waiting for a key when requested, see [`TerminalInput::wake_on`]
+    interrupt: Option<InterruptHandle>,
+}
+
+impl TerminalInput {
+    pub fn new(terminal: Box<dyn Terminal>) -> Result<TerminalInput, Error> {
+        let terminal: Arc<dyn Terminal> = Arc::from(terminal);
+        terminal.enter_raw_mode()?;
+        Ok(TerminalInput {
+            reader: Some(spawn_reader(KeyStream {
+                terminal: terminal.clone(),
+                bytes: VecDeque::new(),
+            })),
+            restore: TerminalRestore(terminal),
+            interrupt: None,
+        })
+    }
+
+    /// The keyboard of the terminal running the VM
+    pub fn stdin() -> Result<TerminalInput, Error> {
+        TerminalInput::new(stdin()?)
+    }
+
+    /// Something that gives the terminal back from a Ctrl-C handler or a panic hook
+    pub fn restore_handle(&self) -> TerminalRestore {
+        self.restore.clone()
+    }
+
+    /// Take the next key only if it was already typed
+    pub fn poll(&mut self) -> Option<u8> {
+        self.reader.as_ref()?.keys.try_recv().ok()
+    }
+
+    /// Stop waiting for a key once an interrupt is requested, so a program blocked in GETC can be interrupted too
+    pub fn wake_on(&mut self, interrupt: InterruptHandle) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Wait for the next key, `None` once the input has ended or an interrupt was requested while waiting
+    pub fn read_blocking(&mut self) -> Option<u8> {
+        let keys = &self.reader.as_ref()?.keys;
+        let Some(interrupt) = &self.interrupt else {
+            return keys.recv().ok();
+        };
+        loop {
+            match keys.recv_timeout(INTERRUPT_CHECK) {
+                Ok(key) => return Some(key),
+                Err(RecvTimeoutError::Timeout) if !interrupt.is_requested() => {}
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+impl Input for TerminalInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.read_blocking()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.poll()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> KeyWait {
+        let Some(reader) = &self.reader else {
+            return KeyWait::Ended;
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return KeyWait::TimedOut;
+            }
+            match reader.keys.recv_timeout(remaining.min(INTERRUPT_CHECK)) {
+                Ok(key) => return KeyWait::Key(key),
+                Err(RecvTimeoutError::Timeout)
+                    if !self
+                        .interrupt
+                        .as_ref()
+                        .is_some_and(InterruptHandle::is_requested) => {}
+                Err(_) => return KeyWait::Ended,
+            }
+        }
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        match &self.reader {
+            Some(reader) => reader.keys.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        self.restore.restore();
+        let Some(reader) = self.reader.take() else {
+            return;
+        };
+        // Without the receiver the thread stops after its next key, if one ever comes
+        let finished = reader.finished;
+        mem::drop(reader.keys);
+        match finished.recv_timeout(READER_JOIN_TIMEOUT) {
+            Err(RecvTimeoutError::Timeout) => {} // Still blocked reading, it dies with the process
+            _ => {
+                let _ = reader.thread.join();
+            }
+        }
+    }
+}
+
+/// The keys of a terminal read as a stream of bytes
+struct KeyStream {
+    terminal: Arc<dyn Terminal>,
+    /// Bytes of the last key not read yet, an arrow is more than one
+    bytes: VecDeque<u8>,
+}
+
+impl Read for KeyStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        while self.bytes.is_empty() {
+            let mut key = Vec::new();
+            if !self.terminal.read_key(&mut key)? {
+                return Ok(0);
+            }
+            self.bytes.extend(key);
+        }
+        let count = buffer.len().min(self.bytes.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.bytes.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+/// Takes the terminal of a [`TerminalInput`] out of raw mode. Only the first restore does something,
+/// so the drop, the Ctrl-C handler and the panic hook can all call it
+#[derive(Clone)]
+pub struct TerminalRestore(Arc<dyn Terminal>);
+
+impl TerminalRestore {
+    pub fn restore(&self) {
+        let _ = self.0.leave_raw_mode();
+    }
+}
+
+/// Gives the terminal back when it goes out of scope, however the program leaves: returning, failing or panicking.
+/// Creating it also installs a panic hook that restores the terminal before the panic message is printed,
+/// otherwise the message would be written to a terminal without echo and never see it restored until the unwinding reaches the guard
+pub struct TerminalGuard {
+    restore: TerminalRestore,
+}
+
+impl TerminalGuard {
+    pub fn new(input: &TerminalInput) -> TerminalGuard {
+        let restore = input.restore_handle();
+        let hook_restore = restore.clone();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook_restore.restore();
+            default_hook(info);
+        }));
+        TerminalGuard { restore }
+    }
+
+    /// For the places that can't wait for the guard to drop, like a Ctrl-C handler that exits right away
+    pub fn restore_handle(&self) -> TerminalRestore {
+        self.restore.clone()
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.restore.restore();
+    }
+}
+
+// The tests read from pipes, there is no console to give them on Windows
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn terminal_input(reader: io::PipeReader) -> TerminalInput {
+        TerminalInput::new(Box::new(UnixTerminal::new(reader).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn terminal_input_reads_a_pipe() {
+        let (reader, mut writer) = io::pipe().unwrap();
+        let mut input = terminal_input(reader);
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(input.read_blocking(), Some(b'a'));
+        assert_eq!(input.read_blocking(), Some(b'b'));
+        assert_eq!(input.poll(), None);
+        drop(writer);
+        assert_eq!(input.read_blocking(), None);
+    }
+
+    #[test]
+    fn dropping_terminal_input_doesnt_wait_for_a_blocked_read() {
+        // The writer stays open, so the reader thread is blocked in read(2) with nothing to read
+        let (reader, _writer) = io::pipe().unwrap();
+        let input = terminal_input(reader);
+        let start = std::time::Instant::now();
+        drop(input);
+        assert!(start.elapsed() < READER_JOIN_TIMEOUT * 10);
+    }
+
+    #[test]
+    fn restoring_a_pipe_does_nothing() {
+        let (reader, _writer) = io::pipe().unwrap();
+        let input = terminal_input(reader);
+        let restore = input.restore_handle();
+        restore.restore();
+        drop(input);
+        restore.restore();
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::fd::{AsFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use termios::{ECHO, ICANON, TCSANOW, Termios, tcsetattr};
+
+use super::Terminal;
+use crate::{Error, TerminalError};
+
+/// A terminal driven with termios. It can read anything with a file descriptor, if it isn't a terminal
+/// (a pipe in the tests, a redirected file) raw mode does nothing and the bytes are just read.
+/// Enter already arrives as `\n` since raw mode keeps the translation of carriage returns
+pub struct UnixTerminal {
+    /// A copy of the descriptor it was created with, so it stays open as long as the terminal
+    file: File,
+    /// The settings before raw mode, `None` outside of it
+    saved: Mutex<Option<Termios>>,
+}
+
+impl UnixTerminal {
+    pub fn new(descriptor: impl AsFd) -> Result<UnixTerminal, Error> {
+        Ok(UnixTerminal {
+            file: File::from(descriptor.as_fd().try_clone_to_owned()?),
+            saved: Mutex::new(None),
+        })
+    }
+}
+
+impl Terminal for UnixTerminal {
+    fn enter_raw_mode(&self) -> Result<(), Error> {
+        let fd = self.file.as_raw_fd();
+        let Ok(settings) = Termios::from_fd(fd) else {
+            return Ok(()); // Not a terminal
+        };
+        let mut raw = settings;
+        raw.c_lflag &= !ICANON & !ECHO;
+        tcsetattr(fd, TCSANOW, &raw).map_err(TerminalError::DisableInputBuffering)?;
+        let mut saved = self
+            .saved
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        saved.get_or_insert(settings);
+        Ok(())
+    }
+
+    fn leave_raw_mode(&self) -> Result<(), Error> {
+        // A panic while holding the lock must not stop the terminal from being restored
+        let mut saved = self
+            .saved
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match saved.take() {
+            Some(settings) => tcsetattr(self.file.as_raw_fd(), TCSANOW, &settings)
+                .map_err(|error| TerminalError::RestoreInputBuffering(error).into()),
+            None => Ok(()),
+        }
+    }
+
+    fn read_key(&self, bytes: &mut Vec<u8>) -> io::Result<bool> {
+        let mut buffer = [0_u8];
+        match (&self.file).read(&mut buffer)? {
+            0 => Ok(false),
+            _ => {
+                bytes.push(buffer[0]);
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Set by the SIGWINCH handler, see [`resized`]
+static RESIZED: AtomicBool = AtomicBool::new(true);
+
+extern "C" fn on_resize(_signal: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+/// The rows and the columns of the terminal on stdout, `None` if it isn't one
+pub fn size() -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_row > 0 && size.ws_col > 0 => Some((size.ws_row, size.ws_col)),
+        _ => None,
+    }
+}
+
+/// Whether the terminal changed its size since the last call, true the first time. The first call starts watching
+pub fn resized() -> bool {
+    static WATCHING: Once = Once::new();
+    WATCHING.call_once(|| unsafe {
+        libc::signal(libc::SIGWINCH, on_resize as *const () as libc::sighandler_t);
+    });
+    RESIZED.swap(false, Ordering::Relaxed)
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read};
+use std::sync::Mutex;
+use windows_sys::Win32::Foundation::HANDLE;
+use windows_sys::Win32::System::Console::{
+    CONSOLE_SCREEN_BUFFER_INFO, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, GetConsoleMode,
+    GetConsoleScreenBufferInfo, GetStdHandle, INPUT_RECORD, KEY_EVENT, ReadConsoleInputW,
+    STD_INPUT_HANDLE, STD_OUTPUT_HANDLE, SetConsoleMode,
+};
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    VK_DOWN, VK_END, VK_HOME, VK_LEFT, VK_RIGHT, VK_UP,
+};
+
+use super::{ARROW_DOWN, ARROW_LEFT, ARROW_RIGHT, ARROW_UP, END, HOME, Terminal};
+use crate::{Error, TerminalError};
+
+/// A terminal driven with the Windows console API (cmd, PowerShell, Windows Terminal).
+/// The console reports keys, not bytes: Enter comes as `\r` and is turned into `\n`, the extended keys
+/// are translated to the ANSI sequences a Unix terminal sends. If stdin isn't a console it is just read
+pub struct WindowsTerminal {
+    /// The stdin handle as an address, a raw `HANDLE` can't be shared between threads
+    handle: usize,
+    /// The console mode before raw mode, `None` outside of it
+    saved: Mutex<Option<u32>>,
+}
+
+impl WindowsTerminal {
+    pub fn stdin() -> WindowsTerminal {
+        WindowsTerminal {
+            handle: unsafe { GetStdHandle(STD_INPUT_HANDLE) } as usize,
+            saved: Mutex::new(None),
+        }
+    }
+
+    fn handle(&self) -> HANDLE {
+        self.handle as HANDLE
+    }
+
+    /// The console mode, `None` if stdin isn't a console
+    fn mode(&self) -> Option<u32> {
+        let mut mode = 0;
+        match unsafe { GetConsoleMode(self.handle(), &mut mode) } {
+            0 => None,
+            _ => Some(mode),
+        }
+    }
+}
+
+impl Terminal for WindowsTerminal {
+    fn enter_raw_mode(&self) -> Result<(), Error> {
+        let Some(mode) = self.mode() else {
+            return Ok(()); // Not a console
+        };
+        // Ctrl-C keeps being a signal, only the line editing and the echo go away
+        if unsafe {
+            SetConsoleMode(
+                self.handle(),
+                mode & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT),
+            )
+        } == 0
+        {
+            return Err(TerminalError::DisableInputBuffering(io::Error::last_os_error()).into());
+        }
+        let mut saved = self
+            .saved
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        saved.get_or_insert(mode);
+        Ok(())
+    }
+
+    fn leave_raw_mode(&self) -> Result<(), Error> {
+        // A panic while holding the lock must not stop the console from being restored
+        let mut saved = self
+            .saved
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match saved.take() {
+            Some(mode) if unsafe { SetConsoleMode(self.handle(), mode) } == 0 => {
+                Err(TerminalError::RestoreInputBuffering(io::Error::last_os_error()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn read_key(&self, bytes: &mut Vec<u8>) -> io::Result<bool> {
+        if self.mode().is_none() {
+            let mut buffer = [0_u8];
+            return match io::stdin().read(&mut buffer)? {
+                0 => Ok(false),
+                _ => {
+                    bytes.push(buffer[0]);
+                    Ok(true)
+                }
+            };
+        }
+        let start = bytes.len();
+        // Key releases, mouse and focus events carry no key, keep waiting until one does
+        while bytes.len() == start {
+            let mut record = INPUT_RECORD::default();
+            let mut read = 0;
+            if unsafe { ReadConsoleInputW(self.handle(), &mut record, 1, &mut read) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if read == 0 || record.EventType as u32 != KEY_EVENT {
+                continue;
+            }
+            let key = unsafe { record.Event.KeyEvent };
+            if key.bKeyDown == 0 {
+                continue;
+            }
+            let character = unsafe { key.uChar.UnicodeChar };
+            let sequence: &[u8] = match (character, key.wVirtualKeyCode) {
+                (0x0D, _) => b"\n",
+                (0, VK_UP) => ARROW_UP,
+                (0, VK_DOWN) => ARROW_DOWN,
+                (0, VK_RIGHT) => ARROW_RIGHT,
+                (0, VK_LEFT) => ARROW_LEFT,
+                (0, VK_HOME) => HOME,
+                (0, VK_END) => END,
+                (0, _) => continue, // Shift, function keys and the rest of the keys without a character
+                // The LC-3 console is 8 bits wide, anything outside of Latin-1 can't be typed
+                (character, _) => match u8::try_from(character) {
+                    Ok(byte) => &[byte],
+                    Err(_) => continue,
+                },
+            };
+            for _ in 0..key.wRepeatCount.max(1) {
+                bytes.extend_from_slice(sequence);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The rows and the columns of the window of the console on stdout, `None` if it isn't one
+pub fn size() -> Option<(u16, u16)> {
+    let mut info = CONSOLE_SCREEN_BUFFER_INFO::default();
+    if unsafe { GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) } == 0 {
+        return None;
+    }
+    let window = info.srWindow;
+    Some((
+        (window.Bottom - window.Top + 1) as u16,
+        (window.Right - window.Left + 1) as u16,
+    ))
+}
+
+/// The console doesn't signal its changes, it is asked every time
+pub fn resized() -> bool {
+    true
+}
@@ -0,0 +1,267 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::assembler::{Program, assemble};
+use crate::console::Input;
+use crate::{Error, Registers, State, run_with_budget};
+
+/// Instructions a [`TestVm`] runs before giving up, unless [`TestVm::budget`] says otherwise
+const DEFAULT_BUDGET: u64 = 1_000_000;
+
+/// Assemble a snippet of LC-3 source and return its words, so tests can be written in assembly instead of hand encoded words.
+/// Panics with every diagnostic if the snippet doesn't assemble
+pub fn assemble_snippet(source: &str) -> Vec<u16> {
+    assemble_or_panic(source).words
+}
+
+/// Assemble a snippet and load it into a fresh [`State`] at its origin, with the PC pointing at the first word
+pub fn state_with_snippet(source: &str) -> State {
+    let program = assemble_or_panic(source);
+    let mut state = State::default();
+    state.load_image(program.origin, &program.words);
+    state.register_write(Registers::Pc, program.origin);
+    state
+}
+
+fn assemble_or_panic(source: &str) -> Program {
+    match assemble(source) {
+        Ok(program) => program,
+        Err(error) => {
+            let diagnostics: String = error
+                .diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.render("snippet", source))
+                .collect();
+            panic!("the snippet doesn't assemble:\n{}", diagnostics)
+        }
+    }
+}
+
+/// What a [`ScriptedInput`] does once every byte has been read
+#[derive(Clone, Copy, Default)]
+pub enum EndOfInput {
+    /// There are no more bytes, GETC and IN fail
+    #[default]
+    Fail,
+    /// The same byte forever, like `x04` (Ctrl-D) for programs that stop reading on it
+    Repeat(u8),
+}
+
+/// Keyboard input known in advance.
+/// With [`ScriptedInput::with_delay`] every key is only available after the keyboard status register
+/// was polled that many times, so polling loops can be tested without real time passing
+#[derive(Default)]
+pub struct ScriptedInput {
+    bytes: VecDeque<u8>,
+    delay: u32,
+    polls: u32,
+    end: EndOfInput,
+}
+
+impl ScriptedInput {
+    pub fn new(bytes: impl AsRef<[u8]>) -> ScriptedInput {
+        ScriptedInput {
+            bytes: bytes.as_ref().iter().copied().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Polls of the keyboard status register that find no key before each key is available
+    pub fn with_delay(mut self, polls: u32) -> ScriptedInput {
+        self.delay = polls;
+        self
+    }
+
+    pub fn on_end(mut self, end: EndOfInput) -> ScriptedInput {
+        self.end = end;
+        self
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        self.polls = 0;
+        match (self.bytes.pop_front(), self.end) {
+            (Some(byte), _) => Some(byte),
+            (None, EndOfInput::Fail) => None,
+            (None, EndOfInput::Repeat(byte)) => Some(byte),
+        }
+    }
+}
+
+impl Input for ScriptedInput {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.next_byte()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        if self.polls < self.delay {
+            self.polls += 1;
+            return None;
+        }
+        self.next_byte()
+    }
+
+    fn take_pending(&mut self) -> Vec<u8> {
+        self.bytes.drain(..).collect()
+    }
+}
+
+/// Collects everything the program prints. Clones share the bytes,
+/// so a clone can be given to [`State::set_output`] and the original inspected afterwards
+#[derive(Clone, Default)]
+pub struct CaptureOutput(Arc<Mutex<Vec<u8>>>);
+
+impl CaptureOutput {
+    /// The printed bytes so far, the ones that aren't UTF-8 are replaced
+    pub fn as_string(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+
+    /// Take the printed bytes so far, leaving the capture empty
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl Write for CaptureOutput {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`State`] wired to a [`ScriptedInput`] and a [`CaptureOutput`] that runs with an instruction budget,
+/// so a test never touches the terminal and never hangs:
+/// ```
+/// use lc3::test_util::{ScriptedInput, TestVm};
+///
+/// let mut vm = TestVm::with_snippet(".ORIG x3000\nGETC\nOUT\nHALT\n.END")
+///     .input(ScriptedInput::new("a"))
+///     .budget(100);
+/// vm.run().unwrap();
+/// assert_eq!(vm.output().as_string(), "aHALT");
+/// ```
+pub struct TestVm {
+    state: State,
+    output: CaptureOutput,
+    budget: u64,
+}
+
+impl TestVm {
+    /// Wrap a state that already has its program loaded, it gets no input until [`TestVm::input`]
+    pub fn new(mut state: State) -> TestVm {
+        let output = CaptureOutput::default();
+        state.set_input(ScriptedInput::default());
+        state.set_output(output.clone());
+        TestVm {
+            state,
+            output,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    /// See [`state_with_snippet`]
+    pub fn with_snippet(source: &str) -> TestVm {
+        TestVm::new(state_with_snippet(source))
+    }
+
+    pub fn input(mut self, input: impl Input + Send + 'static) -> TestVm {
+        self.state.set_input(input);
+        self
+    }
+
+    pub fn budget(mut self, budget: u64) -> TestVm {
+        self.budget = budget;
+        self
+    }
+
+    /// Run until the program halts, fails or runs out of budget
+    pub fn run(&mut self) -> Result<(), Error> {
+        run_with_budget(&mut self.state, self.budget)
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn output(&self) -> &CaptureOutput {
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RuntimeError;
+
+    const ECHO_UNTIL_ZERO: &str = "
+        .ORIG x3000
+LOOP    GETC
+        ADD R0, R0, #0
+        BRz DONE
+        OUT
+        BR LOOP
+DONE    HALT
+        .END";
+
+    #[test]
+    fn end_of_input_policies() {
+        let mut vm = TestVm::with_snippet(ECHO_UNTIL_ZERO).input(ScriptedInput::new("ab"));
+        assert!(matches!(
+            vm.run().unwrap_err().root(),
+            Error::Runtime(RuntimeError::Trap(_))
+        ));
+        assert_eq!(vm.output().take(), b"ab");
+
+        let mut vm = TestVm::with_snippet(ECHO_UNTIL_ZERO)
+            .input(ScriptedInput::new("ab").on_end(EndOfInput::Repeat(0)));
+        vm.run().unwrap();
+        assert_eq!(vm.output().as_string(), "abHALT");
+    }
+
+    #[test]
+    fn unread_keys_are_pending() {
+        let mut vm =
+            TestVm::with_snippet(".ORIG x3000\nGETC\nHALT\n.END").input(ScriptedInput::new("xyz"));
+        vm.run().unwrap();
+        assert_eq!(vm.state_mut().take_pending_input(), b"yz");
+        assert_eq!(vm.state_mut().take_pending_input(), b"");
+    }
+
+    #[test]
+    fn delayed_keys_need_polls() {
+        let mut vm = TestVm::with_snippet(
+            "
+        .ORIG x3000
+WAIT    ADD R1, R1, #1
+        LDI R2, KBSR
+        BRzp WAIT
+        LDI R0, KBDR
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END",
+        )
+        .input(ScriptedInput::new("k").with_delay(3));
+        vm.run().unwrap();
+        assert_eq!(vm.state().register_read(Registers::R0), b'k' as u16);
+        assert_eq!(vm.state().register_read(Registers::R1), 4);
+    }
+
+    #[test]
+    fn budget_stops_endless_programs() {
+        let mut vm = TestVm::with_snippet(".ORIG x3000\nSELF BR SELF\n.END").budget(10);
+        assert!(matches!(
+            vm.run(),
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(10)))
+        ));
+    }
+}
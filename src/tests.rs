@@ -1,232 +1,2292 @@
-#[cfg(test)]
-pub mod tests {
+use crate::hang::{Hang, HangCheck};
+use crate::protect::CodeProtection;
+use crate::recording::{self, Recorder, Replay};
+use crate::self_modify::{SelfModification, SelfModifyCheck};
+use crate::stack::{StackCheck, StackViolation};
+use crate::test_util::{CaptureOutput, ScriptedInput, TestVm, state_with_snippet};
+use crate::watch_dump::{WatchDump, WatchDumpFormat};
+use crate::*;
+
+#[test]
+fn add_test_mode_0() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    let _ = add(0x1E41, &mut state);
+    assert_eq!(state.registers[7], 0);
+    assert_eq!(state.registers[Registers::Flags], Flags::Zro as u16);
+    state.registers[1] = 2;
+    let _ = add(0x1E01, &mut state);
+    assert_eq!(state.registers[7], 2);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn add_test_mode_1() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    let _ = add(0x1E61, &mut state);
+    assert_eq!(state.registers[7], 1);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+    let _ = add(0x1E3F, &mut state);
+    assert_eq!(state.registers[7], 0xFFFF);
+    assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
+}
+
+#[test]
+fn load_indirect_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        BRnzp START
+BEFORE  .FILL VALUE
+START   LDI R0, BEFORE
+        LDI R2, AFTER
+        HALT
+AFTER   .FILL VALUE
+VALUE   .FILL #5
+        .END",
+    );
+    // LDI R0 with a PCoffset9 of -2
+    assert_eq!(state.memory[0x3002], 0xA1FE);
+    let _ = run_loop(&mut state);
+    assert_eq!(state.registers[Registers::R2], 5);
+    assert_eq!(state.registers[Registers::R0], 5);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn and_test_mode_0() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::R5] = 0xFFFF;
+    state.registers[Registers::R6] = 0x000F;
+    let _ = and(0x5F46, &mut state);
+    assert_eq!(state.registers[Registers::R7], 0x000F);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn and_test_mode_1() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::R5] = 0xFFFF;
+    let _ = and(0x5F66, &mut state);
+    assert_eq!(state.registers[Registers::R7], 0x0006);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+    let _ = and(0x5F76, &mut state);
+    assert_eq!(state.registers[Registers::R7], 0xFFF6);
+    assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
+}
+
+#[test]
+fn conditional_branch_test() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::Flags] = Flags::Neg as u16; // Flag Neg = 1
+    conditional_branch(0x805, &mut state); // Test Flag Neg
+    conditional_branch(0x405, &mut state); // Test Flag Zero
+    conditional_branch(0x205, &mut state); // Test Flag Pos
+    assert_eq!(state.registers[Registers::Pc], 5);
+    state.registers[Registers::Flags] = Flags::Zro as u16; // Flag Zro = 1
+    conditional_branch(0x805, &mut state); // Test Flag Neg
+    conditional_branch(0x405, &mut state); // Test Flag Zero
+    conditional_branch(0x205, &mut state); // Test Flag Pos
+    assert_eq!(state.registers[Registers::Pc], 10);
+    state.registers[Registers::Flags] = Flags::Pos as u16; // Flag Pos = 1
+    conditional_branch(0x805, &mut state); // Test Flag Neg
+    conditional_branch(0x405, &mut state); // Test Flag Zero
+    conditional_branch(0x205, &mut state); // Test Flag Pos
+    assert_eq!(state.registers[Registers::Pc], 15);
+    conditional_branch(0xFFB, &mut state); // Add -5 if any of the flags is active
+    assert_eq!(state.registers[Registers::Pc], 10);
+}
+
+#[test]
+fn branch_target_test() {
+    assert_eq!(branch_target(0x805, Flags::Neg as u16, 10), 15);
+    assert_eq!(branch_target(0x805, Flags::Pos as u16, 10), 10);
+    assert_eq!(branch_target(0x7FB, Flags::Zro as u16, 10), 5); // BRzp -5
+    assert_eq!(branch_target(0xE01, Flags::Pos as u16, 0xFFFF), 0); // Wraps around
+}
+
+#[test]
+fn jump_test() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::R5] = 25;
+    let _ = jump(0xC140, &mut state);
+    assert_eq!(state.registers[Registers::Pc], 25);
+}
+
+#[test]
+fn jump_to_subrutine_test() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::Pc] = 15;
+    let _ = jump_to_subrutine(0x4FFB, &mut state);
+    assert_eq!(state.registers[Registers::Pc], 10);
+    assert_eq!(state.registers[Registers::R7], 15);
+    state.registers[Registers::R5] = 50;
+    let _ = jump_to_subrutine(0x4140, &mut state);
+    assert_eq!(state.registers[Registers::R7], 10);
+    assert_eq!(state.registers[Registers::Pc], 50);
+}
+
+#[test]
+fn load_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LD R7, DATA
+        HALT
+DATA    .FILL #70
+        .END",
+    );
+    let _ = run_loop(&mut state);
+    assert_eq!(state.registers[Registers::R7], 70);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn load_register_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LEA R2, TABLE
+        LDR R5, R2, #2
+        HALT
+TABLE   .FILL #10
+        .FILL #20
+        .FILL #78
+        .END",
+    );
+    let _ = run_loop(&mut state);
+    assert_eq!(state.registers[Registers::R5], 78);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn load_effective_address_test() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::Pc] = 15;
+    let _ = load_effective_address(0xE21F, &mut state);
+    assert_eq!(state.registers[Registers::R1], 46);
+}
+
+#[test]
+fn not_test() {
+    let mut state = State {
+        registers: [0; Registers::InstRet as usize],
+        running: true,
+        ..Default::default()
+    };
+    state.registers[Registers::R5] = 0x00FF;
+    let _ = not(0x977F, &mut state);
+    assert_eq!(state.registers[Registers::R3], 0xFF00);
+    assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
+    let _ = not(0x96FF, &mut state);
+    assert_eq!(state.registers[Registers::R3], 0xFF);
+    assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
+}
+
+#[test]
+fn store_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LD R4, VALUE
+        ST R4, RESULT
+        HALT
+VALUE   .FILL #777
+RESULT  .BLKW 1
+        .END",
+    );
+    let _ = run_loop(&mut state);
+    assert_eq!(state.memory[0x3004], 777);
+}
+
+#[test]
+fn store_indirect_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LD R4, VALUE
+        STI R4, POINTER
+        HALT
+VALUE   .FILL #777
+POINTER .FILL x4000
+        .END",
+    );
+    let _ = run_loop(&mut state);
+    assert_eq!(state.memory[0x4000], 777);
+}
+
+#[test]
+fn store_register_test() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LEA R4, TABLE
+        AND R5, R5, #0
+        ADD R5, R5, #15
+        STR R5, R4, #-5     ; Stores before TABLE, over the AND
+        STR R5, R4, #1
+        HALT
+TABLE   .BLKW 2
+        .END",
+    );
+    let _ = run_loop(&mut state);
+    assert_eq!(state.memory[0x3001], 15);
+    assert_eq!(state.memory[0x3007], 15);
+}
+
+#[test]
+fn state_fits_in_a_small_thread_stack() {
+    // The memory lives on the heap, a VM doesn't need 128 KiB of stack
+    let thread = std::thread::Builder::new()
+        .stack_size(64 * 1024)
+        .spawn(|| {
+            let mut vm = TestVm::with_snippet(".ORIG x3000\nADD R1, R1, #7\nHALT\n.END");
+            vm.run().unwrap();
+            vm.state().registers[Registers::R1]
+        })
+        .unwrap();
+    assert_eq!(thread.join().unwrap(), 7);
+}
+
+#[test]
+fn every_instruction_word_executes_without_panicking() {
+    let mut vm = TestVm::new(State::default());
+    let state = vm.state_mut();
+    // The sleep trap doesn't wait under it
+    state.set_clock(clock::VirtualClock::default());
+    // Garbage everywhere, with a few zeros, so loads and the string traps see every kind of value
+    for address in 0..MEM_MAX {
+        state.memory[address] = (address as u16).wrapping_mul(0x9E37) & 0xFFF7;
+    }
+    state.registers[Registers::Pc] = 0xFFFF;
+    for word in 0..=u16::MAX {
+        state.registers[Registers::R0] = word.wrapping_mul(0x61C9);
+        state.increment_pc();
+        if let Err(error) = run_step(word, state) {
+            assert!(!error.to_string().is_empty());
+        }
+    }
+}
+
+#[test]
+fn decode_cache_sees_self_modifying_code() {
+    // Every round increments the immediate of the ADD at PATCH, so R1 = 1 + 2 + 3
+    let source = "
+        .ORIG x3000
+        AND R1, R1, #0
+        AND R3, R3, #0
+        ADD R3, R3, #3
+PATCH   ADD R1, R1, #1
+        LD R4, PATCH
+        ADD R4, R4, #1
+        ST R4, PATCH
+        ADD R3, R3, #-1
+        BRp PATCH
+        HALT
+        .END";
+    for cached in [false, true] {
+        let mut vm = TestVm::with_snippet(source);
+        if cached {
+            vm.state_mut().enable_decode_cache();
+        }
+        vm.run().unwrap();
+        assert_eq!(vm.state().registers[Registers::R1], 6);
+    }
+}
+
+#[test]
+fn device_reads_are_intercepted_only_on_the_device_page() {
+    let mut vm = TestVm::new(State::default()).input(ScriptedInput::new("ab"));
+    let state = vm.state_mut();
+    state.memory[0xFDFF] = 0x1234;
+    state.memory[MemoryMappedRegisters::Kbsr] = 0x0321; // Bit 14 would enable the keyboard interrupt
+    // Right below the device page it is plain memory
+    assert_eq!(state.memory_read(0xFDFF), 0x1234);
+    // Fetching an instruction never polls the keyboard
+    assert_eq!(state.read_instruction(0xFE00), 0x0321);
+    assert_eq!(state.memory_read(0xFE00), 1 << 15);
+    assert_eq!(state.memory_read(0xFE02), b'a' as u16);
+    assert_eq!(state.memory_read(0xFE00), 1 << 15);
+    assert_eq!(state.memory_read(0xFE02), b'b' as u16);
+    assert_eq!(state.memory_read(0xFE00), 0);
+}
+
+#[test]
+fn register_fields_always_map_to_a_register() {
+    for bits in 0..=u16::MAX {
+        let register = Registers::from_bits(bits);
+        assert_eq!(register as u16, bits & 0x7);
+        assert_eq!(
+            Registers::try_from(bits & 0x7).unwrap() as u16,
+            register as u16
+        );
+    }
+    assert!(Registers::try_from(8).is_err());
+}
+
+#[test]
+fn an_interrupt_stops_an_endless_loop() {
+    let mut vm = TestVm::with_snippet(".ORIG x3000\nLOOP BRnzp LOOP\n.END").budget(u64::MAX);
+    vm.state().interrupt_handle().request();
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+    assert_eq!(vm.state().register_read(Registers::Pc), 0x3000);
+    // The interrupt was handled, running again only stops at the budget
+    let mut vm = vm.budget(10_000);
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(10_000)))
+    ));
+}
+
+#[test]
+fn an_interrupted_getc_waits_again_when_resumed() {
+    let mut vm = TestVm::with_snippet(".ORIG x3000\nADD R1, R1, #1\nGETC\nHALT\n.END");
+    vm.state().interrupt_handle().request();
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+    assert_eq!(vm.state().register_read(Registers::Pc), 0x3001);
+    let mut vm = vm.input(ScriptedInput::new("a"));
+    vm.run().unwrap();
+    assert_eq!(vm.state().register_read(Registers::R0), b'a' as u16);
+    assert_eq!(vm.state().register_read(Registers::R1), 1);
+}
+
+#[test]
+fn draining_the_output_between_slices_gives_it_all_back_in_order() {
+    let (mut state, mut output) = State::headless();
+    let words = test_util::assemble_snippet(
+        ".ORIG x3000\nLD R1, COUNT\nLD R0, LETTER\nLOOP OUT\nADD R0, R0, #1\nADD R1, R1, #-1\nBRp LOOP\nHALT\nCOUNT .FILL #26\nLETTER .FILL x61\n.END",
+    );
+    let program = state.install_program(0x3000, &words).unwrap();
+    state.register_write(Registers::Pc, program.start());
+    let mut printed = Vec::new();
+    let mut drains = 0;
+    loop {
+        let result = run_with_budget(&mut state, 7);
+        let waiting = output.output_len();
+        let drained = output.drain_output();
+        assert_eq!(drained.len(), waiting);
+        drains += !drained.is_empty() as u32;
+        printed.extend(drained);
+        match result {
+            Ok(()) => break,
+            Err(error) => assert!(matches!(
+                error,
+                Error::Runtime(RuntimeError::BudgetExhausted(7))
+            )),
+        }
+    }
+    assert_eq!(printed, b"abcdefghijklmnopqrstuvwxyzHALT");
+    assert!(drains > 10);
+    assert_eq!(output.output_len(), 0);
+}
+
+#[test]
+fn a_headless_state_keeps_its_output_and_never_waits_for_a_key() {
+    let (mut state, mut output) = State::headless();
+    let words = test_util::assemble_snippet(
+        ".ORIG x3000\nLEA R0, TEXT\nPUTS\nLDI R1, KBSR\nGETC\nHALT\nKBSR .FILL xFE00\nTEXT .STRINGZ \"hi\"\n.END",
+    );
+    for (offset, word) in words.into_iter().enumerate() {
+        state.memory_write(0x3000 + offset, word);
+    }
+    assert!(matches!(
+        run_with_budget(&mut state, 100).unwrap_err().root(),
+        Error::Runtime(RuntimeError::Trap(Traps::Getc))
+    ));
+    assert_eq!(state.register_read(Registers::R1), 0);
+    assert_eq!(output.drain_output(), b"hi");
+    assert_eq!(output.drain_text(), "");
+}
+
+#[test]
+fn every_run_loop_counts_the_instructions_it_executed() {
+    let snippet =
+        ".ORIG x3000\nAND R1, R1, #0\nLOOP ADD R1, R1, #1\nADD R2, R1, #-5\nBRn LOOP\nHALT\n.END";
+    for cached in [false, true] {
+        let mut state = state_with_snippet(snippet);
+        if cached {
+            state.enable_decode_cache();
+        }
+        run_loop(&mut state).unwrap();
+        // AND, five times the loop and HALT
+        assert_eq!(state.instructions_executed(), 1 + 5 * 3 + 1);
+    }
+    let mut state = state_with_snippet(".ORIG x3000\nLOOP BRnzp LOOP\n.END");
+    let _ = run_with_budget(&mut state, 2500);
+    assert_eq!(state.instructions_executed(), 2500);
+    // The faulting instruction isn't counted
+    let mut state = state_with_snippet(".ORIG x3000\nADD R1, R1, #1\n.FILL xD000\n.END");
+    assert!(matches!(
+        run_loop(&mut state).unwrap_err().root(),
+        Error::Runtime(RuntimeError::BadOpCode(_))
+    ));
+    assert_eq!(state.instructions_executed(), 1);
+    let mut state = state_with_snippet(".ORIG x3000\nADD R1, R1, #1\nHALT\n.END");
+    trace::run_traced(&mut state, &mut Vec::new()).unwrap();
+    assert_eq!(state.instructions_executed(), 2);
+}
+
+/// Remembers the last value stored and answers loads with it plus one
+struct Latch(u16);
+
+impl device::Device for Latch {
+    fn range(&self) -> std::ops::RangeInclusive<u16> {
+        0xFE20..=0xFE21
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        self.0 + 1
+    }
+
+    fn write(&mut self, _address: u16, value: u16) {
+        self.0 = value;
+    }
+}
+
+#[test]
+fn loads_and_stores_reach_an_added_device() {
+    let mut state = state_with_snippet(
+        ".ORIG x3000\nAND R1, R1, #0\nADD R1, R1, #9\nSTI R1, LATCH\nLDI R2, LATCH\nHALT\nLATCH .FILL xFE21\n.END",
+    );
+    state.add_device(Latch(0)).unwrap();
+    run_loop(&mut state).unwrap();
+    assert_eq!(state.register_read(Registers::R2), 10);
+    // The store went to the device, not to memory
+    assert_eq!(state.read_instruction(0xFE21), 0);
+}
+
+#[test]
+fn peeking_kbsr_leaves_the_key_for_the_program() {
+    let mut state = State::default();
+    state.set_input(std::collections::VecDeque::from(b"k".to_vec()));
+    let kbsr = MemoryMappedRegisters::Kbsr as usize;
+    let kbdr = MemoryMappedRegisters::Kbdr as usize;
+    assert_eq!(state.peek(kbsr), 0);
+    assert_eq!(state.peek(kbdr), 0);
+    // The key is still there for a read, it stays ready until KBDR is read
+    assert_eq!(state.memory_read(kbsr), 1 << 15);
+    assert_eq!(state.peek(kbdr), b'k' as u16);
+    assert_eq!(state.memory_read(kbsr), 1 << 15);
+    assert_eq!(state.memory_read(kbdr), b'k' as u16);
+    assert_eq!(state.memory_read(kbsr), 0);
+    assert_eq!(state.peek(kbsr), 0);
+}
+
+#[test]
+fn peeking_a_device_gives_the_word_last_read() {
+    let mut state = State::default();
+    state.add_device(Latch(4)).unwrap();
+    assert_eq!(state.peek(0xFE21), 0);
+    assert_eq!(state.memory_read(0xFE21), 5);
+    assert_eq!(state.peek(0xFE21), 5);
+    // The device isn't asked, it would answer 8 now
+    state.memory_write(0xFE21, 7);
+    assert_eq!(state.peek(0xFE21), 5);
+    assert_eq!(state.memory_read(0xFE21), 8);
+    assert_eq!(state.peek(0xFE21), 8);
+}
+
+#[test]
+fn the_console_device_prints_cursor_moves_and_clears() {
+    let mut state = state_with_snippet(
+        ".ORIG x3000\nLDI R1, ROWS\nLDI R2, COLS\nLD R0, AT\nSTI R0, CURSOR\nSTI R0, CLEAR\nLEA R0, TEXT\nPUTS\nHALT\nROWS .FILL xFE0C\nCOLS .FILL xFE0E\nCURSOR .FILL xFE10\nCLEAR .FILL xFE12\nAT .FILL x0204\nTEXT .STRINGZ \"x\"\n.END",
+    );
+    let output = CaptureOutput::default();
+    state.set_output(output.clone());
+    state
+        .add_device(device::console::ConsoleDevice::with_size(50, 132))
+        .unwrap();
+    run_loop(&mut state).unwrap();
+    assert_eq!(state.register_read(Registers::R1), 50);
+    assert_eq!(state.register_read(Registers::R2), 132);
+    // Row 2 and column 4 from 0 are 3 and 5 for the terminal
+    assert_eq!(output.as_string(), "\x1b[3;5H\x1b[2J\x1b[HxHALT");
+}
+
+/// Writes 11 at x9000 of bank 1, 22 of bank 2 and 10 of bank 0, then reads them back in R1, R2 and R3 and BSR in R4.
+/// x4000, outside of the window, is the same in every bank
+const BANKS: &str = ".ORIG x3000
+AND R0, R0, #0
+ADD R0, R0, #1
+STI R0, BSR
+LD R1, ELEVEN
+STI R1, AT
+STI R1, FIXED
+ADD R0, R0, #1
+STI R0, BSR
+ADD R1, R1, R1
+STI R1, AT
+AND R0, R0, #0
+STI R0, BSR
+AND R1, R1, #0
+ADD R1, R1, #10
+STI R1, AT
+ADD R0, R0, #1
+STI R0, BSR
+LDI R1, AT
+ADD R0, R0, #1
+STI R0, BSR
+LDI R2, AT
+LDI R5, FIXED
+AND R0, R0, #0
+STI R0, BSR
+LDI R3, AT
+LDI R4, BSR
+HALT
+BSR .FILL xFE14
+AT .FILL x9000
+FIXED .FILL x4000
+ELEVEN .FILL #11
+.END";
+
+#[test]
+fn the_same_address_reads_another_word_in_every_bank() {
+    let mut state = state_with_snippet(BANKS);
+    state.set_output(CaptureOutput::default());
+    state.enable_decode_cache();
+    state.enable_banks(3).unwrap();
+    run_loop(&mut state).unwrap();
+    let registers = [
+        Registers::R1,
+        Registers::R2,
+        Registers::R3,
+        Registers::R4,
+        Registers::R5,
+    ];
+    assert_eq!(
+        registers.map(|register| state.register_read(register)),
+        [11, 22, 10, 0, 11]
+    );
+    assert_eq!(
+        (0..3)
+            .map(|bank| state.peek_bank(bank, 0x9000))
+            .collect::<Vec<_>>(),
+        [Some(10), Some(11), Some(22)]
+    );
+    assert_eq!(state.peek_bank(2, 0x4000), Some(11));
+    assert_eq!(state.peek_bank(3, 0x9000), None);
+    // The bank number wraps around
+    state.memory_write(bank::BSR as usize, 5);
+    assert_eq!(state.selected_bank(), Some(2));
+    assert_eq!(state.peek(0x9000), 22);
+
+    // Without banks BSR is only memory and the stores all go to the same word
+    let mut state = state_with_snippet(BANKS);
+    state.set_output(CaptureOutput::default());
+    run_loop(&mut state).unwrap();
+    assert_eq!(state.register_read(Registers::R1), 10);
+    assert_eq!(state.peek_bank(0, 0x9000), None);
+}
+
+#[test]
+fn the_code_of_another_bank_runs_once_it_is_selected() {
+    // JMP to x8000, where bank 0 sets R1 and selects bank 1, which sets R2 at the same address
+    let mut state =
+        state_with_snippet(".ORIG x3000\nLD R0, WINDOW\nJMP R0\nWINDOW .FILL x8000\n.END");
+    state.enable_decode_cache();
+    state.set_output(CaptureOutput::default());
+    state.enable_banks(2).unwrap();
+    // ADD R1, R1, #1 ; AND R0, R0, #0 ; ADD R0, R0, #1 ; STI R0, BSR (x8005) ; .FILL x0000 ; .FILL xFE14
+    let bank_0 = [0x1261, 0x5020, 0x1021, 0xB001, 0x0000, 0xFE14];
+    state.load_image(0x8000, &bank_0);
+    state.memory_write(bank::BSR as usize, 1);
+    // Right after the STI: ADD R2, R2, #1 ; HALT
+    state.load_image(0x8004, &[0x14A1, 0xF025]);
+    state.memory_write(bank::BSR as usize, 0);
+    run_loop(&mut state).unwrap();
+    assert_eq!(state.register_read(Registers::R1), 1);
+    assert_eq!(state.register_read(Registers::R2), 1);
+    assert_eq!(state.selected_bank(), Some(1));
+}
+
+#[test]
+fn a_snapshot_keeps_every_bank() {
+    let mut state = state_with_snippet(BANKS);
+    state.set_output(CaptureOutput::default());
+    state.enable_banks(3).unwrap();
+    state.load_image(0x8000, &[0x1234]);
+    run_loop(&mut state).unwrap();
+    state.memory_write(bank::BSR as usize, 1);
+    let restored = State::from_snapshot(&state.to_snapshot()).unwrap();
+    assert_eq!(restored.bank_count(), Some(3));
+    assert_eq!(restored.selected_bank(), Some(1));
+    for bank in 0..3 {
+        for address in [0x8000, 0x9000, 0x4000] {
+            assert_eq!(
+                restored.peek_bank(bank, address),
+                state.peek_bank(bank, address)
+            );
+        }
+    }
+    assert!(restored.loaded.contains(0x3000));
+    assert_eq!(restored.to_snapshot(), state.to_snapshot());
+    // Without banks it is still version 1
+    assert_eq!(State::default().to_snapshot()[8..10], [0, 1]);
+}
+
+#[test]
+fn devices_only_get_free_registers_on_the_device_page() {
+    struct At(u16, u16);
+    impl device::Device for At {
+        fn range(&self) -> std::ops::RangeInclusive<u16> {
+            self.0..=self.1
+        }
+        fn read(&mut self, _address: u16) -> u16 {
+            0
+        }
+        fn write(&mut self, _address: u16, _value: u16) {}
+    }
+    let mut state = State::default();
+    assert!(matches!(
+        state.add_device(At(0xFDFF, 0xFE10)),
+        Err(Error::DeviceRange(0xFDFF, 0xFE10))
+    ));
+    assert!(state.add_device(At(0xFE01, 0xFE03)).is_err()); // KBDR
+    state.add_device(At(0xFE10, 0xFE1F)).unwrap();
+    assert!(state.add_device(At(0xFE1F, 0xFE20)).is_err());
+    state.add_device(At(0xFE20, 0xFE20)).unwrap();
+}
+
+/// The time of the machine in microseconds at xFE10, what it got from the clock
+struct Timer(std::time::Duration);
+
+impl device::Device for Timer {
+    fn range(&self) -> std::ops::RangeInclusive<u16> {
+        0xFE10..=0xFE10
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        self.0.as_micros() as u16
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    fn tick(&mut self, now: std::time::Duration) {
+        self.0 = now;
+    }
+}
+
+/// Counts in R1 the laps around a loop until the timer reaches 50 microseconds
+const WAIT_FOR_TIMER: &str = "
+        .ORIG x3000
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        LDI R0, TIMER
+        LD R2, LIMIT
+        ADD R0, R0, R2
+        BRn LOOP
+        HALT
+TIMER   .FILL xFE10
+LIMIT   .FILL #-50
+        .END";
+
+fn trace_wait_for_timer(clock: impl clock::Clock + 'static) -> (Vec<u8>, State) {
+    let mut state = state_with_snippet(WAIT_FOR_TIMER);
+    state.add_device(Timer(std::time::Duration::ZERO)).unwrap();
+    state.set_clock(clock);
+    let mut trace = Vec::new();
+    trace::run_traced_with_budget(&mut state, &mut trace, 1_000_000).unwrap();
+    (trace, state)
+}
+
+#[test]
+fn the_virtual_clock_gives_the_same_run_every_time() {
+    let (first, state) = trace_wait_for_timer(clock::VirtualClock::default());
+    let (second, _) = trace_wait_for_timer(clock::VirtualClock::default());
+    assert_eq!(first, second);
+    // Five instructions a lap at a microsecond each, the 11th lap reads the timer after 52 instructions
+    assert_eq!(state.register_read(Registers::R1), 11);
+    // The real time still gets there, a browser has none
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        let (_, state) = trace_wait_for_timer(clock::RealClock::default());
+        assert!(!state.is_running());
+    }
+}
+
+/// Copies every key to x4000 and up, polling the keyboard registers
+const KEY_COPIER: &str = "
+        .ORIG x3000
+        LD R2, BUFFER
+LOOP    LDI R1, KBSR
+        BRzp LOOP
+        LDI R0, KBDR
+        STR R0, R2, #0
+        ADD R2, R2, #1
+        BRnzp LOOP
+BUFFER  .FILL x4000
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END";
+
+#[test]
+fn keys_pushed_between_slices_arrive_once_and_in_order() {
+    let mut state = state_with_snippet(KEY_COPIER);
+    let keyboard = state.attach_keyboard_buffer();
+    for round in 0..10_u8 {
+        keyboard.push_key(round);
+        keyboard.push_str("ab");
+        assert_eq!(keyboard.pending(), 3);
+        let _ = run_with_budget(&mut state, 500);
+        assert_eq!(keyboard.pending(), 0);
+    }
+    for round in 0..10 {
+        let address = 0x4000 + round * 3;
+        assert_eq!(state.read_instruction(address), round as u16);
+        assert_eq!(state.read_instruction(address + 1), b'a' as u16);
+        assert_eq!(state.read_instruction(address + 2), b'b' as u16);
+    }
+    assert_eq!(state.read_instruction(0x4000 + 30), 0);
+    keyboard.push_str("lost");
+    keyboard.clear();
+    let _ = run_with_budget(&mut state, 500);
+    assert_eq!(state.read_instruction(0x4000 + 30), 0);
+}
+
+/// What [`KEY_COPIER`] copied of a script of 6 keys through a buffer of 4, and how many were dropped
+fn copied_through_a_full_buffer(overflow: console::KeyboardOverflow) -> (Vec<u8>, Option<u64>) {
+    let mut state = state_with_snippet(KEY_COPIER);
+    state.set_input(std::collections::VecDeque::from(b"abcdef".to_vec()));
+    state.buffer_keyboard(4, overflow);
+    let _ = run_with_budget(&mut state, 500);
+    let copied = (0x4000..0x4006)
+        .map(|address| state.peek(address) as u8)
+        .collect();
+    (copied, state.keys_dropped())
+}
+
+#[test]
+fn a_full_keyboard_buffer_drops_the_newest_keys_or_the_oldest() {
+    assert_eq!(
+        copied_through_a_full_buffer(console::KeyboardOverflow::DropNew),
+        (b"abcd\0\0".to_vec(), Some(2))
+    );
+    assert_eq!(
+        copied_through_a_full_buffer(console::KeyboardOverflow::DropOld),
+        (b"cdef\0\0".to_vec(), Some(2))
+    );
+    assert_eq!(State::default().keys_dropped(), None);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn keys_pushed_from_another_thread_while_running_arrive_once_and_in_order() {
+    let mut state = state_with_snippet(KEY_COPIER);
+    let keyboard = state.attach_keyboard_buffer();
+    let typist = std::thread::spawn(move || {
+        for key in 1..=200_u8 {
+            keyboard.push_key(key);
+            std::thread::yield_now();
+        }
+    });
+    let mut slices = 0;
+    while state.read_instruction(0x4000 + 199) == 0 {
+        let _ = run_with_budget(&mut state, 1000);
+        slices += 1;
+        assert!(slices < 1_000_000, "the keys never all arrived");
+    }
+    typist.join().unwrap();
+    let copied: Vec<u16> = (0..201)
+        .map(|offset| state.read_instruction(0x4000 + offset))
+        .collect();
+    let expected: Vec<u16> = (1..=200).chain([0]).collect();
+    assert_eq!(copied, expected);
+}
+
+#[test]
+fn getc_waits_for_the_keyboard_buffer() {
+    let mut vm = TestVm::with_snippet(".ORIG x3000\nGETC\nGETC\nHALT\n.END");
+    let keyboard = vm.state_mut().attach_keyboard_buffer();
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+    keyboard.push_key(b'x');
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+    assert_eq!(vm.state().register_read(Registers::R0), b'x' as u16);
+    assert_eq!(vm.state().register_read(Registers::Pc), 0x3001);
+    keyboard.push_key(b'y');
+    vm.run().unwrap();
+    assert_eq!(vm.state().register_read(Registers::R0), b'y' as u16);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod properties {
+    use crate::*;
+    use proptest::prelude::*;
+
+    /// State with the given general purpose registers
+    fn state_with_registers(registers: [u16; 8]) -> State {
+        let mut state = State::default();
+        state.registers[..8].copy_from_slice(&registers);
+        state
+    }
+
+    /// The flag that a value should set, from its sign as i16
+    fn expected_flag(value: u16) -> u16 {
+        match (value as i16).signum() {
+            -1 => Flags::Neg as u16,
+            0 => Flags::Zro as u16,
+            _ => Flags::Pos as u16,
+        }
+    }
+
+    /// Encode ADD or AND, the last operand is a register or a 5 bit immediate
+    fn encode(op_code: u16, destination: u16, source: u16, immediate: bool, last: u16) -> u16 {
+        let base = (op_code << 12) | (destination << 9) | (source << 6);
+        match immediate {
+            true => base | 0x20 | (last & 0x1F),
+            false => base | (last & 0x7),
+        }
+    }
+
+    /// Value of the last operand computed with i16 arithmetic instead of bit twiddling
+    fn last_operand(registers: &[u16; 8], immediate: bool, last: u16) -> i16 {
+        match immediate {
+            true => ((last & 0x1F) as i16) << 11 >> 11,
+            false => registers[(last & 0x7) as usize] as i16,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn sign_extend_matches_i16_cast(value: u16, bits in 1_u16..=15) {
+            let masked = value & ((1 << bits) - 1);
+            let shift = 16 - bits;
+            let expected = (((masked << shift) as i16) >> shift) as u16;
+            prop_assert_eq!(sign_extend(masked, bits), expected);
+        }
+
+        #[test]
+        fn update_flags_sets_exactly_one_flag(value: u16) {
+            let mut registers = [0_u16; Registers::InstRet as usize];
+            registers[Registers::R3] = value;
+            update_flags(Registers::R3, &mut registers);
+            let flags = registers[Registers::Flags];
+            prop_assert_eq!(flags.count_ones(), 1);
+            prop_assert_eq!(flags, expected_flag(value));
+        }
+
+        #[test]
+        fn add_wraps_like_i16(
+            registers: [u16; 8],
+            destination in 0_u16..8,
+            source in 0_u16..8,
+            immediate: bool,
+            last in 0_u16..32,
+        ) {
+            let mut state = state_with_registers(registers);
+            add(encode(0x1, destination, source, immediate, last), &mut state).unwrap();
+            let expected = (registers[source as usize] as i16)
+                .wrapping_add(last_operand(&registers, immediate, last)) as u16;
+            let mut expected_registers = registers;
+            expected_registers[destination as usize] = expected;
+            prop_assert_eq!(&state.registers[..8], &expected_registers[..]);
+            prop_assert_eq!(state.registers[Registers::Flags], expected_flag(expected));
+        }
+
+        #[test]
+        fn and_matches_bitwise_and(
+            registers: [u16; 8],
+            destination in 0_u16..8,
+            source in 0_u16..8,
+            immediate: bool,
+            last in 0_u16..32,
+        ) {
+            let mut state = state_with_registers(registers);
+            and(encode(0x5, destination, source, immediate, last), &mut state).unwrap();
+            let expected = registers[source as usize] & last_operand(&registers, immediate, last) as u16;
+            let mut expected_registers = registers;
+            expected_registers[destination as usize] = expected;
+            prop_assert_eq!(&state.registers[..8], &expected_registers[..]);
+            prop_assert_eq!(state.registers[Registers::Flags], expected_flag(expected));
+        }
+    }
+}
+
+mod traps {
+    use crate::test_util::{ScriptedInput, TestVm};
     use crate::*;
 
+    /// Run a single trap with R0 and the memory set up, return the VM to check its output and registers
+    fn run_trap(vector: u16, r0: u16, memory: &[(usize, u16)], input: &[u8]) -> TestVm {
+        let mut vm = TestVm::new(State::default()).input(ScriptedInput::new(input));
+        let state = vm.state_mut();
+        state.registers[Registers::R0] = r0;
+        for (address, value) in memory {
+            state.memory[*address] = *value;
+        }
+        state.increment_pc();
+        run_step(0xF000 | vector, state).unwrap();
+        vm
+    }
+
+    /// Every register after the trap, the PC was incremented once from x3000
+    fn assert_registers(vm: &TestVm, r0: u16, flags: Flags) {
+        let mut expected = [0; Registers::InstRet as usize];
+        expected[Registers::R0] = r0;
+        expected[Registers::Pc] = 0x3001;
+        expected[Registers::Flags] = flags as u16;
+        assert_eq!(vm.state().registers, expected);
+    }
+
+    #[test]
+    fn getc_stores_the_byte_and_sets_the_flags() {
+        let vm = run_trap(0x20, 0, &[], b"a");
+        assert_eq!(vm.output().take(), b"");
+        assert_registers(&vm, b'a' as u16, Flags::Pos);
+    }
+
+    #[test]
+    fn getc_zero_extends_high_bytes() {
+        let vm = run_trap(0x20, 0, &[], &[0xE9]);
+        assert_registers(&vm, 0x00E9, Flags::Pos);
+        let vm = run_trap(0x20, 7, &[], &[0]);
+        assert_registers(&vm, 0, Flags::Zro);
+    }
+
+    #[test]
+    fn getc_fails_without_input() {
+        let mut vm = TestVm::new(State::default());
+        assert!(matches!(
+            run_step(0xF020, vm.state_mut()),
+            Err(Error::Runtime(RuntimeError::Trap(Traps::Getc)))
+        ));
+    }
+
+    #[test]
+    fn out_prints_the_lower_8_bits() {
+        let vm = run_trap(0x21, 0x0141, &[], b"");
+        assert_eq!(vm.output().take(), b"A");
+        assert_registers(&vm, 0x0141, Flags::Zro);
+        let vm = run_trap(0x21, 0x00E9, &[], b"");
+        assert_eq!(vm.output().take(), [0xE9]);
+    }
+
+    #[test]
+    fn puts_stops_at_nul() {
+        let string = [
+            (0x4000, b'h' as u16),
+            (0x4001, b'i' as u16),
+            (0x4003, b'!' as u16),
+        ];
+        let vm = run_trap(0x22, 0x4000, &string, b"");
+        assert_eq!(vm.output().take(), b"hi");
+        assert_registers(&vm, 0x4000, Flags::Zro);
+    }
+
+    #[test]
+    fn puts_prints_the_lower_8_bits() {
+        let string = [(0x4000, 0x00E9), (0x4001, 0x0141), (0x4002, 0xD800)];
+        let vm = run_trap(0x22, 0x4000, &string, b"");
+        assert_eq!(vm.output().take(), [0xE9, b'A', 0x00]);
+    }
+
+    #[test]
+    fn puts_of_an_empty_string_prints_nothing() {
+        let vm = run_trap(0x22, 0x4000, &[(0x4001, b'x' as u16)], b"");
+        assert_eq!(vm.output().take(), b"");
+        assert_registers(&vm, 0x4000, Flags::Zro);
+    }
+
+    #[test]
+    fn putsp_handles_odd_lengths() {
+        // "abc": the last word only has its lower byte
+        let string = [(0x4000, 0x6261), (0x4001, 0x0063)];
+        let vm = run_trap(0x24, 0x4000, &string, b"");
+        assert_eq!(vm.output().take(), b"abc");
+        assert_registers(&vm, 0x4000, Flags::Zro);
+    }
+
+    #[test]
+    fn putsp_prints_high_bytes_as_they_are() {
+        let string = [(0x4000, 0xE9E8), (0x4001, 0x00FF)];
+        let vm = run_trap(0x24, 0x4000, &string, b"");
+        assert_eq!(vm.output().take(), [0xE8, 0xE9, 0xFF]);
+    }
+
+    #[test]
+    fn putsp_continues_after_a_zero_high_byte() {
+        // A zero high byte in the middle is skipped, only a zero word ends the string
+        let string = [(0x4000, 0x0061), (0x4001, 0x6362), (0x4003, 0x0064)];
+        let vm = run_trap(0x24, 0x4000, &string, b"");
+        assert_eq!(vm.output().take(), b"abc");
+        assert_registers(&vm, 0x4000, Flags::Zro);
+    }
+
+    #[test]
+    fn in_prompts_echoes_and_stores() {
+        let vm = run_trap(0x23, 0, &[], b"z");
+        assert_eq!(vm.output().take(), b"Enter character: z");
+        assert_registers(&vm, b'z' as u16, Flags::Pos);
+    }
+
+    #[test]
+    fn halt_stops_without_touching_the_registers() {
+        let vm = run_trap(0x25, 0x1234, &[], b"");
+        assert_eq!(vm.output().take(), b"HALT");
+        assert!(!vm.state().is_running());
+        assert_registers(&vm, 0x1234, Flags::Zro);
+    }
+
+    /// READ LINE into x4000 with room for `capacity` characters, the words of the buffer up to its NUL
+    fn read_line(capacity: u16, input: &[u8]) -> (TestVm, Vec<u16>) {
+        let mut vm = TestVm::new(State::default()).input(ScriptedInput::new(input));
+        let state = vm.state_mut();
+        state.memory[0x4000..0x4010].fill(0xAAAA);
+        state.registers[Registers::R0] = 0x4000;
+        state.registers[Registers::R1] = capacity;
+        state.increment_pc();
+        run_step(0xF029, state).unwrap();
+        let end = (0x4000..0x4010).find(|&address| state.memory[address] == 0);
+        let buffer = state.memory[0x4000..=end.unwrap()].to_vec();
+        (vm, buffer)
+    }
+
+    #[test]
+    fn read_line_fills_an_exact_fit() {
+        let (vm, buffer) = read_line(3, b"abc\nd");
+        assert_eq!(buffer, [0x61, 0x62, 0x63, 0]);
+        assert_eq!(vm.output().take(), b"abc\n");
+        assert_eq!(vm.state().registers[Registers::R0], 3);
+        assert_eq!(vm.state().registers[Registers::Flags], Flags::Pos as u16);
+        assert_eq!(vm.state().registers[Registers::R1], 3);
+    }
+
+    #[test]
+    fn read_line_truncates_a_long_line() {
+        let (mut vm, buffer) = read_line(2, b"abcd\nz");
+        assert_eq!(buffer, [0x61, 0x62, 0]);
+        assert_eq!(vm.output().take(), b"abcd\n");
+        assert_eq!(vm.state().registers[Registers::R0], 0xFFFF);
+        assert_eq!(vm.state().registers[Registers::Flags], Flags::Neg as u16);
+        // The rest of the line was dropped, the next line is where it was
+        run_step(0xF020, vm.state_mut()).unwrap();
+        assert_eq!(vm.state().registers[Registers::R0], b'z' as u16);
+    }
+
+    #[test]
+    fn read_line_ends_with_the_input() {
+        let (vm, buffer) = read_line(8, b"ab");
+        assert_eq!(buffer, [0x61, 0x62, 0]);
+        assert_eq!(vm.output().take(), b"ab");
+        assert_eq!(vm.state().registers[Registers::R0], 2);
+        let (vm, buffer) = read_line(8, b"");
+        assert_eq!(buffer, [0]);
+        assert_eq!(vm.state().registers[Registers::Flags], Flags::Zro as u16);
+    }
+
+    #[test]
+    fn dbg_changes_nothing() {
+        let vm = run_trap(0x28, 0x4000, &[(0x4000, 0x1234)], b"");
+        assert_eq!(vm.output().take(), b"");
+        assert_registers(&vm, 0x4000, Flags::Zro);
+        assert_eq!(vm.state().memory[0x4000], 0x1234);
+    }
+
     #[test]
-    fn add_test_mode_0() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        let _ = add(0x1E41, &mut state);
-        assert_eq!(state.registers[7], 0);
-        assert_eq!(state.registers[Registers::Flags], Flags::Zro as u16);
-        state.registers[1] = 2;
-        let _ = add(0x1E01, &mut state);
-        assert_eq!(state.registers[7], 2);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn add_test_mode_1() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        let _ = add(0x1E61, &mut state);
-        assert_eq!(state.registers[7], 1);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-        let _ = add(0x1E3F, &mut state);
-        assert_eq!(state.registers[7], 0xFFFF);
-        assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
-    }
-
-    #[test]
-    fn load_indirect_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; 10],
-            running: true,
-        };
-        state.memory[20] = 7890;
-        state.memory[7890] = 5;
-        state.registers[Registers::Pc] = 5;
-        let _ = load_indirect(0xA40F, &mut state);
-        assert_eq!(state.registers[Registers::R2], 5);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-        state.registers[Registers::Pc] = 25;
-        let _ = load_indirect(0xA1FB, &mut state);
-        assert_eq!(state.registers[Registers::R0], 5);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn and_test_mode_0() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R5] = 0xFFFF;
-        state.registers[Registers::R6] = 0x000F;
-        let _ = and(0x5F46, &mut state);
-        assert_eq!(state.registers[Registers::R7], 0x000F);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn and_test_mode_1() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R5] = 0xFFFF;
-        let _ = and(0x5F66, &mut state);
-        assert_eq!(state.registers[Registers::R7], 0x0006);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-        let _ = and(0x5F76, &mut state);
-        assert_eq!(state.registers[Registers::R7], 0xFFF6);
-        assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
-    }
-
-    #[test]
-    fn conditional_branch_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::Flags] = Flags::Neg as u16; // Flag Neg = 1
-        conditional_branch(0x805, &mut state); // Test Flag Neg
-        conditional_branch(0x405, &mut state); // Test Flag Zero
-        conditional_branch(0x205, &mut state); // Test Flag Pos
-        assert_eq!(state.registers[Registers::Pc], 5);
-        state.registers[Registers::Flags] = Flags::Zro as u16; // Flag Zro = 1
-        conditional_branch(0x805, &mut state); // Test Flag Neg
-        conditional_branch(0x405, &mut state); // Test Flag Zero
-        conditional_branch(0x205, &mut state); // Test Flag Pos
-        assert_eq!(state.registers[Registers::Pc], 10);
-        state.registers[Registers::Flags] = Flags::Pos as u16; // Flag Pos = 1
-        conditional_branch(0x805, &mut state); // Test Flag Neg
-        conditional_branch(0x405, &mut state); // Test Flag Zero
-        conditional_branch(0x205, &mut state); // Test Flag Pos
-        assert_eq!(state.registers[Registers::Pc], 15);
-        conditional_branch(0xFFB, &mut state); // Add -5 if any of the flags is active
-        assert_eq!(state.registers[Registers::Pc], 10);
-    }
-
-    #[test]
-    fn jump_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R5] = 25;
-        let _ = jump(0xC140, &mut state);
-        assert_eq!(state.registers[Registers::Pc], 25);
-    }
-
-    #[test]
-    fn jump_to_subrutine_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::Pc] = 15;
-        let _ = jump_to_subrutine(0x4FFB, &mut state);
-        assert_eq!(state.registers[Registers::Pc], 10);
-        assert_eq!(state.registers[Registers::R7], 15);
-        state.registers[Registers::R5] = 50;
-        let _ = jump_to_subrutine(0x4140, &mut state);
-        assert_eq!(state.registers[Registers::R7], 10);
-        assert_eq!(state.registers[Registers::Pc], 50);
-    }
-
-    #[test]
-    fn load_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.memory[50] = 70;
-        let _ = load(0x2E32, &mut state);
-        assert_eq!(state.registers[Registers::R7], 70);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn load_register_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.memory[50] = 78;
-        state.registers[Registers::R2] = 25;
-        let _ = load_register(0x6A99, &mut state);
-        assert_eq!(state.registers[Registers::R5], 78);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn load_effective_address_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::Pc] = 15;
-        let _ = load_effective_address(0xE21F, &mut state);
-        assert_eq!(state.registers[Registers::R1], 46);
-    }
-
-    #[test]
-    fn not_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R5] = 0x00FF;
-        let _ = not(0x977F, &mut state);
-        assert_eq!(state.registers[Registers::R3], 0xFF00);
-        assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
-        let _ = not(0x96FF, &mut state);
-        assert_eq!(state.registers[Registers::R3], 0xFF);
-        assert_eq!(state.registers[Registers::Flags], Flags::Pos as u16);
-    }
-
-    #[test]
-    fn store_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R4] = 777;
-        let _ = store(0x3819, &mut state);
-        assert_eq!(state.memory[25], 777);
-    }
-
-    #[test]
-    fn store_indirect_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.memory[25] = 50;
-        state.registers[Registers::R4] = 777;
-        let _ = store_indirect(0x3819, &mut state);
-        assert_eq!(state.memory[50], 777);
-    }
-
-    #[test]
-    fn store_register_test() {
-        let mut state = State {
-            memory: [0; MEM_MAX],
-            registers: [0; Registers::InstRet as usize],
-            running: true,
-        };
-        state.registers[Registers::R4] = 20;
-        state.registers[Registers::R5] = 50;
-        let _ = store_register(0x7B3B, &mut state);
-        assert_eq!(state.memory[15], 50);
+    fn assert_records_the_message_and_leaves_the_registers() {
+        let message = [(0x4000, b'o' as u16), (0x4001, b'k' as u16)];
+        let mut vm = TestVm::new(State::default());
+        let state = vm.state_mut();
+        for (address, value) in message {
+            state.memory[address] = value;
+        }
+        state.registers[Registers::R1] = 0x4000;
+        state.increment_pc();
+        state.registers[Registers::R0] = 1;
+        run_step(0xF026, state).unwrap();
+        state.registers[Registers::R0] = 0;
+        run_step(0xF026, state).unwrap();
+        assert_eq!(
+            state.trap_assertions(),
+            [
+                assertion::TrapAssertion {
+                    pc: 0x3000,
+                    passed: true,
+                    message: "ok".to_string()
+                },
+                assertion::TrapAssertion {
+                    pc: 0x3000,
+                    passed: false,
+                    message: "ok".to_string()
+                }
+            ]
+        );
+        assert_eq!(state.registers[Registers::R1], 0x4000);
+        state.set_halt_on_assert_fail(true);
+        assert!(matches!(
+            run_step(0xF026, state),
+            Err(Error::Runtime(RuntimeError::AssertFailed(message))) if message == "ok"
+        ));
+        assert!(state.is_running());
+    }
+}
+
+#[test]
+fn a_bad_trap_says_where_it_happened() {
+    let snippet = ".ORIG x3000\nLD R0, LETTER\nTRAP xFF\nLETTER .FILL x0041\n.END";
+    for cached in [false, true] {
+        let mut state = state_with_snippet(snippet);
+        if cached {
+            state.enable_decode_cache();
+        }
+        let error = run_loop(&mut state).unwrap_err();
+        assert!(matches!(
+            error.root(),
+            Error::Runtime(RuntimeError::BadTrapCode(0xFF))
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Bad trap code xFF at x3001 (TRAP xFF); R0=x0041"
+        );
+    }
+    let mut state = state_with_snippet(snippet);
+    let error = trace::run_traced(&mut state, &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Bad trap code xFF at x3001 (TRAP xFF); R0=x0041"
+    );
+}
+
+#[test]
+fn a_reserved_instruction_says_where_it_happened() {
+    let mut state = state_with_snippet(".ORIG x3000\nADD R1, R1, #2\n.FILL xD000\n.END");
+    assert_eq!(
+        run_loop(&mut state).unwrap_err().to_string(),
+        "Bad operation code xD at x3001 (.FILL xD000)"
+    );
+}
+
+#[test]
+fn an_interrupted_instruction_isnt_a_fault() {
+    let mut vm = TestVm::with_snippet(".ORIG x3000\nGETC\nHALT\n.END");
+    vm.state().interrupt_handle().request();
+    assert!(matches!(
+        vm.run(),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+}
+
+#[test]
+fn errors_of_loading_and_running_have_their_categories() {
+    let missing = file_management::read_image(&"./no-such-image.obj".to_string()).unwrap_err();
+    assert_eq!(missing.category(), Category::Load);
+    assert!(matches!(missing, Error::Load(LoadError::File(_))));
+    let mut state = state_with_snippet(".ORIG x3000\nTRAP xFF\n.END");
+    let fault = run_loop(&mut state).unwrap_err();
+    assert_eq!(fault.category(), Category::Runtime);
+    assert_eq!(fault.root().category(), Category::Runtime);
+    let mut state = state_with_snippet(".ORIG x3000\nLOOP BRnzp LOOP\n.END");
+    assert_eq!(
+        run_with_budget(&mut state, 10).unwrap_err().category(),
+        Category::Runtime
+    );
+}
+
+#[test]
+fn strict_exec_stops_after_a_missing_halt() {
+    let snippet = ".ORIG x3000\nADD R1, R1, #1\nADD R1, R1, #1\n.END";
+    for cached in [false, true] {
+        let mut state = state_with_snippet(snippet);
+        if cached {
+            state.enable_decode_cache();
+        }
+        state.set_uninitialized_exec(UninitializedExec::Fail);
+        let error = run_with_budget(&mut state, 100).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Runtime(RuntimeError::UninitializedExec(0x3002))
+        ));
+        assert_eq!(state.register_read(Registers::Pc), 0x3002);
+        assert_eq!(state.register_read(Registers::R1), 2);
+    }
+    #[cfg(feature = "jit")]
+    {
+        let mut state = state_with_snippet(snippet);
+        state.enable_jit();
+        state.set_uninitialized_exec(UninitializedExec::Fail);
+        assert!(matches!(
+            run_with_budget(&mut state, 100),
+            Err(Error::Runtime(RuntimeError::UninitializedExec(0x3002)))
+        ));
+    }
+    let mut state = state_with_snippet(snippet);
+    state.set_uninitialized_exec(UninitializedExec::Fail);
+    assert!(matches!(
+        trace::run_traced(&mut state, &mut Vec::new()),
+        Err(Error::Runtime(RuntimeError::UninitializedExec(0x3002)))
+    ));
+}
+
+#[test]
+fn words_written_at_runtime_can_be_executed() {
+    // Stores a HALT right after its last instruction and falls into it
+    let mut state = state_with_snippet(
+        ".ORIG x3000\nBRnzp START\nCODE .FILL xF025\nTARGET .FILL x3005\nSTART LD R0, CODE\nSTI R0, TARGET\n.END",
+    );
+    state.set_uninitialized_exec(UninitializedExec::Fail);
+    run_with_budget(&mut state, 100).unwrap();
+    assert!(!state.is_running());
+}
+
+#[test]
+fn the_uninitialized_warning_only_stops_checking() {
+    let mut state = state_with_snippet(".ORIG x3000\nADD R1, R1, #1\n.END");
+    state.set_uninitialized_exec(UninitializedExec::Warn);
+    assert!(matches!(
+        run_with_budget(&mut state, 10),
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(10)))
+    ));
+    // Warned once, the policy is back to allowing everything
+    assert_eq!(state.uninitialized_exec, UninitializedExec::Allow);
+}
+
+/// Takes its branch at x3002 once, then patches its offset from x3005 so the next time it falls into the HALT
+const PATCHED_BRANCH: &str = "
+        .ORIG x3000
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+JUMP    BRnzp AGAIN
+        HALT
+AGAIN   LD R0, PATCH
+        ST R0, JUMP
+        BRnzp LOOP
+PATCH   BRnzp #0
+        .END";
+
+#[test]
+fn patching_a_branch_that_ran_is_self_modifying_code() {
+    for cached in [false, true] {
+        let mut state = state_with_snippet(PATCHED_BRANCH);
+        if cached {
+            state.enable_decode_cache();
+        }
+        state.set_self_modify_check(Some(SelfModifyCheck::new(false)));
+        run_with_budget(&mut state, 100).unwrap();
+        assert_eq!(state.register_read(Registers::R1), 2);
+        assert_eq!(
+            state.self_modifications(),
+            vec![SelfModification {
+                writer: 0x3005,
+                target: 0x3002,
+                times: 1
+            }]
+        );
+    }
+    let mut state = state_with_snippet(PATCHED_BRANCH);
+    state.set_self_modify_check(Some(SelfModifyCheck::new(false)));
+    trace::run_traced(&mut state, &mut Vec::new()).unwrap();
+    assert_eq!(state.self_modifications().len(), 1);
+}
+
+#[test]
+fn forbidden_self_modifying_code_stops_before_the_store() {
+    let mut state = state_with_snippet(PATCHED_BRANCH);
+    state.set_self_modify_check(Some(SelfModifyCheck::new(true)));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::SelfModify {
+            writer: 0x3005,
+            target: 0x3002
+        })
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Self-modifying code: the store at x3005 modified the instruction at x3002 at x3005 (ST R0, #-4); R0=x0E00"
+    );
+    assert_eq!(state.memory_read(0x3002), 0x0E01); // Still BRnzp AGAIN
+}
+
+#[test]
+fn stores_to_data_arent_self_modifying_code() {
+    let mut state = state_with_snippet(
+        ".ORIG x3000\nLD R0, COUNT\nADD R0, R0, #1\nST R0, COUNT\nHALT\nCOUNT .FILL #4\n.END",
+    );
+    state.set_self_modify_check(Some(SelfModifyCheck::new(true)));
+    run_with_budget(&mut state, 100).unwrap();
+    assert_eq!(state.memory_read(0x3004), 5);
+    assert!(state.self_modifications().is_empty());
+}
+
+#[test]
+fn protected_code_stops_a_store_to_an_instruction() {
+    let mut state = state_with_snippet(PATCHED_BRANCH);
+    state.set_code_protection(Some(CodeProtection::new()));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::WriteToCode {
+            pc: 0x3005,
+            address: 0x3002
+        })
+    ));
+    assert_eq!(
+        error.to_string(),
+        "The store at x3005 writes x3002, which is code loaded from an image at x3005 (ST R0, #-4); R0=x0E00"
+    );
+    assert_eq!(state.memory_read(0x3002), 0x0E01);
+}
+
+#[test]
+fn protected_code_lets_the_program_store_to_a_writable_range() {
+    let counter = ".ORIG x3000\nLD R0, COUNT\nADD R0, R0, #1\nST R0, COUNT\nST R0, COPY\nHALT\nCOUNT .FILL #4\nCOPY .BLKW 1\n.END";
+    let mut state = state_with_snippet(counter);
+    state.set_code_protection(Some(CodeProtection::new()));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::WriteToCode {
+            pc: 0x3002,
+            address: 0x3005
+        })
+    ));
+    let mut state = state_with_snippet(counter);
+    state.set_output(CaptureOutput::default());
+    state.set_code_protection(Some(CodeProtection::new().writable(0x3005..=0x3006)));
+    run_with_budget(&mut state, 100).unwrap();
+    assert_eq!(state.memory_read(0x3005), 5);
+    assert_eq!(state.memory_read(0x3006), 5);
+}
+
+#[test]
+fn protected_code_comes_before_the_self_modifying_code_check() {
+    let mut state = state_with_snippet(PATCHED_BRANCH);
+    state.set_self_modify_check(Some(SelfModifyCheck::new(true)));
+    state.set_code_protection(Some(CodeProtection::new()));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::WriteToCode { .. })
+    ));
+    assert!(state.self_modifications().is_empty());
+    // Writable code is still self-modifying code
+    let mut state = state_with_snippet(PATCHED_BRANCH);
+    state.set_output(CaptureOutput::default());
+    state.set_self_modify_check(Some(SelfModifyCheck::new(false)));
+    state.set_code_protection(Some(CodeProtection::new().writable(0x3002..=0x3002)));
+    run_with_budget(&mut state, 100).unwrap();
+    assert_eq!(
+        state.self_modifications(),
+        [SelfModification {
+            writer: 0x3005,
+            target: 0x3002,
+            times: 1
+        }]
+    );
+}
+
+#[test]
+fn a_routine_of_the_vm_runs_at_the_vector_it_was_moved_to() {
+    let mut vm = TestVm::with_snippet(
+        ".ORIG x3000\nLEA R0, TEXT\nTRAP x40\nHALT\nTEXT .STRINGZ \"moved\"\n.END",
+    );
+    vm.state_mut()
+        .trap_table_mut()
+        .set(0x40, Traps::Puts)
+        .remove(0x22);
+    vm.run().unwrap();
+    assert_eq!(vm.output().as_string(), "movedHALT");
+
+    let mut vm =
+        TestVm::with_snippet(".ORIG x3000\nLEA R0, TEXT\nPUTS\nHALT\nTEXT .STRINGZ \"gone\"\n.END");
+    vm.state_mut().trap_table_mut().remove(0x22);
+    assert!(matches!(
+        vm.run().unwrap_err().root(),
+        Error::Runtime(RuntimeError::BadTrapCode(0x22))
+    ));
+}
+
+#[test]
+fn a_custom_trap_gets_the_machine() {
+    let mut vm = TestVm::with_snippet(
+        ".ORIG x3000\nTRAP x41\nTRAP x41\nTRAP x41\nLDI R2, COUNTER\nHALT\nCOUNTER .FILL x4000\n.END",
+    );
+    let counter = vm.state_mut().install_data(0x4000, &[0]).unwrap();
+    vm.state_mut()
+        .trap_table_mut()
+        .set_custom(0x41, move |state| {
+            let count = state.memory_read(counter.addr_of(0) as usize);
+            state.memory_write(counter.addr_of(0) as usize, count + 1);
+            Ok(())
+        });
+    vm.run().unwrap();
+    assert_eq!(vm.state().register_read(Registers::R2), 3);
+    assert_eq!(vm.state().peek(counter.addr_of(0) as usize), 3);
+}
+
+#[test]
+fn branching_to_itself_is_a_hang() {
+    let mut state =
+        state_with_snippet(".ORIG x3000\nAND R1, R1, #0\nADD R1, R1, #3\nHERE BRnzp HERE\n.END");
+    state.set_hang_check(Some(HangCheck::new(10, 4, false)));
+    let error = run_with_budget(&mut state, 1000).unwrap_err();
+    assert!(matches!(error, Error::Runtime(RuntimeError::Hang(_))));
+    assert_eq!(
+        error.to_string(),
+        "The program appears hung at x3002 (BRnzp #-1); R0=x0000 R1=x0003 R2=x0000 R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x0000"
+    );
+    // Only a dozen times around, not the whole budget
+    assert!(state.instructions_executed() < 20);
+}
+
+/// Polls the keyboard until a key is there and halts
+const POLL_KEYBOARD: &str =
+    ".ORIG x3000\nPOLL LDI R0, KBSR\nBRzp POLL\nHALT\nKBSR .FILL xFE00\n.END";
+
+#[test]
+fn polling_the_keyboard_only_hangs_with_scripted_input() {
+    // Someone types after a while, the wait is no hang
+    let mut state = state_with_snippet(POLL_KEYBOARD);
+    state.set_input(ScriptedInput::new("a").with_delay(500));
+    state.set_hang_check(Some(HangCheck::new(10, 4, false)));
+    run_with_budget(&mut state, 10_000).unwrap();
+    // The keys were all there from the start, once they ran out none is coming
+    let mut state = state_with_snippet(POLL_KEYBOARD);
+    state.set_input(ScriptedInput::new(""));
+    state.set_hang_check(Some(HangCheck::new(10, 4, true)));
+    assert!(matches!(
+        run_with_budget(&mut state, 10_000),
+        Err(Error::Runtime(RuntimeError::Hang(Hang { pc: 0x3000, .. })))
+    ));
+    let mut state = state_with_snippet(POLL_KEYBOARD);
+    state.set_input(ScriptedInput::new("a"));
+    state.set_hang_check(Some(HangCheck::new(10, 4, true)));
+    run_with_budget(&mut state, 10_000).unwrap();
+}
+
+/// Takes a key with GETC and another one polling the keyboard, then echoes both
+const KEYS_THEN_POLL: &str = "
+        .ORIG x3000
+        GETC
+        OUT
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END";
+
+#[test]
+fn keys_are_recorded_with_the_instruction_that_consumed_them() {
+    for cached in [false, true] {
+        let mut state = state_with_snippet(KEYS_THEN_POLL);
+        if cached {
+            state.enable_decode_cache();
+        }
+        // The second key is only there on the third poll
+        state.set_input(ScriptedInput::new("ab").with_delay(2));
+        state.set_output(CaptureOutput::default());
+        let recording = CaptureOutput::default();
+        let image: &[u8] = &[0x30, 0x00];
+        let recorder = Recorder::new(Box::new(recording.clone()), &[("a.obj", image)]).unwrap();
+        state.start_recording(recorder);
+        run_with_budget(&mut state, 100).unwrap();
+        state.stop_recording().unwrap();
+        assert_eq!(
+            recording.as_string(),
+            format!(
+                "lc3rec 1\nimage {:016x} a.obj\nkey 0 97\nkey 6 98\n",
+                recording::image_hash(image)
+            )
+        );
+    }
+}
+
+/// Run [`KEYS_THEN_POLL`] with `input`, recording its keys to `recording` or replaying `replay`
+fn run_keys_then_poll(
+    input: ScriptedInput,
+    recording: Option<&CaptureOutput>,
+    replay: Option<&str>,
+) -> (State, CaptureOutput, Result<(), Error>) {
+    let mut state = state_with_snippet(KEYS_THEN_POLL);
+    let output = CaptureOutput::default();
+    state.set_input(input);
+    state.set_output(output.clone());
+    if let Some(recording) = recording {
+        state.start_recording(Recorder::new(Box::new(recording.clone()), &[]).unwrap());
+    }
+    if let Some(replay) = replay {
+        state.start_replay(Replay::parse(replay).unwrap());
     }
+    let result = run_with_budget(&mut state, 1000).and_then(|_| state.finish_replay());
+    state.stop_recording().unwrap();
+    (state, output, result)
+}
+
+#[test]
+fn a_replay_ends_in_the_recorded_state() {
+    let recording = CaptureOutput::default();
+    let (recorded, recorded_output, result) = run_keys_then_poll(
+        ScriptedInput::new("xy").with_delay(5),
+        Some(&recording),
+        None,
+    );
+    result.unwrap();
+    // No key at all is typed, the recording has them
+    let (replayed, replayed_output, result) =
+        run_keys_then_poll(ScriptedInput::new(""), None, Some(&recording.as_string()));
+    result.unwrap();
+    assert_eq!(replayed_output.as_string(), recorded_output.as_string());
+    assert_eq!(replayed_output.as_string(), "xyHALT");
+    assert_eq!(replayed.to_snapshot(), recorded.to_snapshot());
+    // Keys typed meanwhile are ignored, the polls before the recorded instruction find nothing
+    let (replayed, _, result) =
+        run_keys_then_poll(ScriptedInput::new("ab"), None, Some(&recording.as_string()));
+    result.unwrap();
+    assert!(replayed.diff(&recorded).is_empty());
+}
+
+#[test]
+fn a_replay_diverges_where_the_keys_are_consumed_differently() {
+    // GETC at the first instruction has no key
+    let (_, _, result) =
+        run_keys_then_poll(ScriptedInput::new(""), None, Some("lc3rec 1\nkey 5 120\n"));
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error.root(),
+            Error::Runtime(RuntimeError::ReplayDiverges {
+                instruction: 0,
+                pc: 0x3000,
+                ..
+            })
+        ),
+        "{}",
+        error
+    );
+    // The program halts with a key left
+    let (_, _, result) = run_keys_then_poll(
+        ScriptedInput::new(""),
+        None,
+        Some("lc3rec 1\nkey 0 120\nkey 2 121\nkey 900 122\n"),
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "The replay diverges at instruction 6, x3007: 1 recorded key(s) were never consumed, the first one at instruction 900"
+    );
+}
+
+/// Recurses ten times pushing R7 every time, on a stack of four words from x4000 to x4003
+const DEEP_RECURSION: &str = "
+        .ORIG x3000
+        LD R6, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #10
+        JSR RECURSE
+        HALT
+RECURSE ADD R6, R6, #-1
+        STR R7, R6, #0
+        ADD R0, R0, #-1
+        BRz DONE
+        JSR RECURSE
+DONE    LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+STACK   .FILL x4004
+        .END";
+
+#[test]
+fn a_recursion_deeper_than_the_stack_overflows() {
+    for cached in [false, true] {
+        let mut state = state_with_snippet(DEEP_RECURSION);
+        if cached {
+            state.enable_decode_cache();
+        }
+        state.set_stack_check(Some(StackCheck::new(0x4000, 0x4003, true)));
+        let error = run_with_budget(&mut state, 1000).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Runtime(RuntimeError::Stack(StackViolation::Overflow {
+                pc: 0x3005,
+                stack_pointer: 0x3FFF
+            }))
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Stack overflow at x3005: R6=x3FFF is below the stack"
+        );
+    }
+    // Only warned about, the program still gets to its HALT
+    let mut state = state_with_snippet(DEEP_RECURSION);
+    state.set_stack_check(Some(StackCheck::new(0x4000, 0x4003, false)));
+    run_with_budget(&mut state, 1000).unwrap();
+    assert_eq!(state.register_read(Registers::R6), 0x4004);
+    // A stack big enough for it
+    let mut state = state_with_snippet(DEEP_RECURSION);
+    state.set_stack_check(Some(StackCheck::new(0x3FF0, 0x4003, true)));
+    run_with_budget(&mut state, 1000).unwrap();
+}
+
+#[test]
+fn popping_an_empty_stack_underflows() {
+    let mut state = state_with_snippet(
+        ".ORIG x3000\nLD R6, STACK\nLDR R0, R6, #0\nHALT\nSTACK .FILL x4004\n.END",
+    );
+    state.set_stack_check(Some(StackCheck::new(0x4000, 0x4003, true)));
+    assert!(matches!(
+        trace::run_traced(&mut state, &mut Vec::new()),
+        Err(Error::Runtime(RuntimeError::Stack(
+            StackViolation::Underflow {
+                pc: 0x3001,
+                address: 0x4004
+            }
+        )))
+    ));
+}
+
+/// The directory of a file trap test, empty
+fn host_files_root(name: &str) -> std::path::PathBuf {
+    let root = std::env::temp_dir().join(format!("lc3-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+#[test]
+fn a_program_writes_a_file_and_reads_it_back() {
+    let root = host_files_root("file-traps");
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LEA R0, PATH
+        AND R1, R1, #0
+        ADD R1, R1, #2          ; Write
+        TRAP x30
+        ADD R3, R0, #0          ; The handle
+        LEA R1, TEXT
+        AND R2, R2, #0
+        ADD R2, R2, #3
+        TRAP x32
+        ADD R0, R3, #0
+        TRAP x33
+        LEA R0, PATH
+        AND R1, R1, #0
+        ADD R1, R1, #1          ; Read
+        TRAP x30
+        ADD R3, R0, #0
+        LEA R1, BUFFER
+        AND R2, R2, #0
+        ADD R2, R2, #8
+        TRAP x31
+        ADD R4, R0, #0          ; Bytes read
+        ADD R0, R3, #0
+        TRAP x33
+        HALT
+PATH    .STRINGZ \"out.txt\"
+TEXT    .FILL x6968             ; \"hi!\" two bytes per word
+        .FILL x0021
+BUFFER  .BLKW 2
+        .END",
+    );
+    state.set_output(Vec::new());
+    state.set_host_files(Some(host_fs::HostFiles::new(&root).unwrap()));
+    run_loop(&mut state).unwrap();
+    assert_eq!(std::fs::read(root.join("out.txt")).unwrap(), b"hi!");
+    assert_eq!(state.registers[Registers::R4], 3);
+    assert_eq!(state.memory[0x3022..0x3024], [0x6968, 0x0021]); // BUFFER
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[test]
+fn file_traps_fail_outside_the_sandbox() {
+    let root = host_files_root("file-escape");
+    let snippet = "
+        .ORIG x3000
+        LEA R0, PATH
+        AND R1, R1, #0
+        ADD R1, R1, #2
+        TRAP x30
+        HALT
+PATH    .STRINGZ \"../escaped.txt\"
+        .END";
+    let mut state = state_with_snippet(snippet);
+    state.set_output(Vec::new());
+    state.set_host_files(Some(host_fs::HostFiles::new(&root).unwrap()));
+    run_loop(&mut state).unwrap();
+    assert_eq!(
+        state.registers[Registers::R0],
+        host_fs::HostFileError::OutsideRoot.code()
+    );
+    assert_eq!(state.registers[Registers::Flags], Flags::Neg as u16);
+    assert!(!root.parent().unwrap().join("escaped.txt").exists());
+    // Without a sandbox nothing is available
+    let mut state = state_with_snippet(snippet);
+    state.set_output(Vec::new());
+    run_loop(&mut state).unwrap();
+    assert_eq!(
+        state.registers[Registers::R0],
+        host_fs::HostFileError::Disabled.code()
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
+
+/// Reads the time, sleeps twice for 50000 ms and reads it again, the first time in R2/R3
+const TIME_AROUND_SLEEPS: &str = "
+        .ORIG x3000
+        TRAP x34
+        ADD R2, R0, #0
+        ADD R3, R1, #0
+        LD R0, LONG
+        TRAP x35
+        TRAP x35
+        TRAP x34
+        HALT
+LONG    .FILL xC350             ; 50000
+        .END";
+
+#[test]
+fn time_only_moves_forward_across_sleeps_of_the_virtual_clock() {
+    let mut state = state_with_snippet(TIME_AROUND_SLEEPS);
+    state.set_output(Vec::new());
+    state.set_clock(clock::VirtualClock::default());
+    let start = std::time::Instant::now();
+    run_loop(&mut state).unwrap();
+    let milliseconds = |low: Registers, high: Registers| {
+        (state.registers[high] as u32) << 16 | state.registers[low] as u32
+    };
+    let before = milliseconds(Registers::R2, Registers::R3);
+    let after = milliseconds(Registers::R0, Registers::R1);
+    // Past 16 bits, the high word in R1 counts
+    assert!(after >= before + 100_000, "{} then {}", before, after);
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+}
+
+/// The CPU time the thread used so far, in ticks of the kernel
+#[cfg(target_os = "linux")]
+fn thread_cpu_ticks() -> u64 {
+    let stat = std::fs::read_to_string("/proc/thread-self/stat").unwrap();
+    // The name of the command can have spaces, the fields are counted after it
+    let fields: Vec<&str> = stat
+        .rsplit_once(')')
+        .unwrap()
+        .1
+        .split_whitespace()
+        .collect();
+    fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap() // utime and stime
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn sleeping_under_the_real_clock_takes_the_time_without_spinning() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LD R0, DELAY
+        TRAP x35
+        HALT
+DELAY   .FILL #300
+        .END",
+    );
+    state.set_output(Vec::new());
+    state.set_clock(clock::RealClock::default());
+    #[cfg(target_os = "linux")]
+    let ticks = thread_cpu_ticks();
+    let start = std::time::Instant::now();
+    run_loop(&mut state).unwrap();
+    assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+    // Spinning would take the 30 ticks of 10 ms, sleeping a few at most
+    #[cfg(target_os = "linux")]
+    assert!(thread_cpu_ticks() - ticks < 15);
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn an_interrupt_wakes_a_sleeping_program_up() {
+    let mut state = state_with_snippet(
+        "
+        .ORIG x3000
+        LD R0, DELAY
+        TRAP x35
+        HALT
+DELAY   .FILL #30000
+        .END",
+    );
+    state.set_clock(clock::RealClock::default());
+    state.interrupt_handle().request();
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        run_loop(&mut state),
+        Err(Error::Runtime(RuntimeError::Interrupted))
+    ));
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    // Back on the trap, resuming sleeps again
+    assert_eq!(state.registers[Registers::Pc], 0x3001);
+}
+
+/// The timer interrupts, its handler enables the keyboard interrupt with a key already there and logs before and
+/// after. The log at x4000 is 1, 2 and the key when the keyboard waits for the RTI of the timer
+const TIMER_THEN_KEYBOARD: &str = "
+        .ORIG x3000
+        LEA R0, TIMER_ISR
+        STI R0, TIMER_VEC
+        LEA R0, KEY_ISR
+        STI R0, KEY_VEC
+        LD R0, ENABLE
+        STI R0, TSR
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        STI R0, TIR             ; Every millisecond
+WAIT    LD R0, NEXT
+        LD R1, LOG_END
+        ADD R0, R0, R1
+        BRn WAIT
+        HALT
+
+TIMER_ISR
+        ST R0, TIMER_R0
+        ST R1, TIMER_R0+1
+        ST R7, TIMER_R0+2
+        AND R0, R0, #0
+        STI R0, TIR             ; Stopped
+        LDI R0, TSR             ; Cleared
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        JSR LOG
+        LD R0, ENABLE
+        STI R0, KBSR            ; The key is ready, but the keyboard is lower
+        AND R0, R0, #0
+        ADD R0, R0, #2
+        JSR LOG
+        LD R0, TIMER_R0
+        LD R1, TIMER_R0+1
+        LD R7, TIMER_R0+2
+        RTI
+
+KEY_ISR ST R0, KEY_R0
+        ST R1, KEY_R0+1
+        ST R7, KEY_R0+2
+        LDI R0, KBDR
+        JSR LOG
+        AND R0, R0, #0
+        STI R0, KBSR
+        LD R0, KEY_R0
+        LD R1, KEY_R0+1
+        LD R7, KEY_R0+2
+        RTI
+
+LOG     LD R1, NEXT
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        ST R1, NEXT
+        RET
+
+TIMER_VEC .FILL x0181
+KEY_VEC .FILL x0180
+TSR     .FILL xFE08
+TIR     .FILL xFE0A
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+ENABLE  .FILL x4000
+NEXT    .FILL x4000
+LOG_END .FILL #-16387           ; -x4003
+TIMER_R0 .BLKW 3
+KEY_R0  .BLKW 3
+        .END";
+
+/// [`TIMER_THEN_KEYBOARD`] run to the end with the interrupt controller given `priorities`
+fn timer_then_keyboard(priorities: &[(irq::DeviceId, u8)]) -> State {
+    let mut state = state_with_snippet(TIMER_THEN_KEYBOARD);
+    state.set_output(Vec::new());
+    state.set_input(std::collections::VecDeque::from(b"k".to_vec()));
+    state.set_clock(clock::VirtualClock::default());
+    state.add_device(device::timer::Timer::default()).unwrap();
+    state.set_interrupt_controller(Some(irq::InterruptController::default()));
+    for &(device, priority) in priorities {
+        state.set_irq_priority(device, priority).unwrap();
+    }
+    run_with_budget(&mut state, 1_000_000).unwrap();
+    state
+}
+
+#[test]
+fn a_lower_interrupt_waits_for_the_rti_of_a_higher_one() {
+    let state = timer_then_keyboard(&[]);
+    assert_eq!(state.memory[0x4000..0x4003], [1, 2, b'k' as u16]);
+    // Back in user mode on the user stack
+    assert_eq!(state.psr() >> 15, 1);
+    assert_eq!(state.register_read(Registers::R6), 0);
+    let controller = state.interrupt_controller().unwrap();
+    assert!(!controller.is_supervisor());
+    assert_eq!(controller.priority(), 0);
+    let counts = controller.interrupt_counts();
+    let counts = |vector: u8| counts.iter().find(|(known, _)| *known == vector).unwrap().1;
+    // The key waited once for the RTI of the timer
+    assert_eq!(
+        counts(irq::KEYBOARD_VECTOR),
+        irq::InterruptCounts {
+            taken: 1,
+            deferred: 1
+        }
+    );
+    assert_eq!(counts(device::timer::TIMER_VECTOR).taken, 1);
+}
+
+#[test]
+fn swapping_the_priorities_nests_the_keyboard_in_the_timer() {
+    let state = timer_then_keyboard(&[(irq::DeviceId::Keyboard, 6), (irq::DeviceId::Timer, 4)]);
+    assert_eq!(state.memory[0x4000..0x4003], [1, b'k' as u16, 2]);
+    let controller = state.interrupt_controller().unwrap();
+    assert_eq!(controller.priority_of(irq::DeviceId::Keyboard), Some(6));
+    assert!(
+        controller
+            .interrupt_counts()
+            .iter()
+            .all(|(_, counts)| counts.taken == 1 && counts.deferred == 0)
+    );
+}
+
+#[test]
+fn an_interrupt_priority_is_0_to_7() {
+    let mut state = State::default();
+    assert!(state.set_irq_priority(irq::DeviceId::Timer, 3).is_err());
+    state.set_interrupt_controller(Some(irq::InterruptController::default()));
+    state.set_irq_priority(irq::DeviceId::Timer, 7).unwrap();
+    assert!(matches!(
+        state.set_irq_priority(irq::DeviceId::Keyboard, 8),
+        Err(Error::BadArgument(_))
+    ));
+    assert_eq!(
+        irq::DeviceId::from_name("x82"),
+        Some(irq::DeviceId::Vector(0x82))
+    );
+    assert_eq!(
+        irq::DeviceId::from_name("Keyboard"),
+        Some(irq::DeviceId::Keyboard)
+    );
+    assert_eq!(irq::DeviceId::from_name("disk"), None);
+}
+
+/// Starts a timer of 1 ms with its interrupt enabled and spins until the interrupt halts the machine
+const SPIN_UNTIL_TIMER: &str = "
+        .ORIG x3000
+        LEA R0, ISR
+        STI R0, TIMER_VEC
+        LD R0, ENABLE
+        STI R0, TSR
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        STI R0, TIR
+SPIN    BRnzp SPIN
+ISR     HALT
+TIMER_VEC .FILL x0181
+TSR     .FILL xFE08
+TIR     .FILL xFE0A
+ENABLE  .FILL x4000
+        .END";
+
+fn instructions_until_the_timer(granule: Option<u64>) -> u64 {
+    let mut state = state_with_snippet(SPIN_UNTIL_TIMER);
+    state.set_output(CaptureOutput::default());
+    // A microsecond per instruction, the timer is due a thousand instructions after it started
+    state.set_clock(clock::VirtualClock::default());
+    state.add_device(device::timer::Timer::default()).unwrap();
+    state.set_interrupt_controller(Some(irq::InterruptController::default()));
+    if let Some(granule) = granule {
+        state.set_tick_granule(granule);
+    }
+    run_with_budget(&mut state, 100_000).unwrap();
+    state.instructions_executed()
+}
+
+#[test]
+fn the_run_loop_ticks_the_timer_within_a_granule() {
+    // Started by the 7th instruction it is due once 1006 ran, then the HALT of the handler
+    let on_time = 1_006 + 1;
+    let halted = instructions_until_the_timer(None);
+    assert!(
+        (on_time..on_time + device::DEFAULT_TICK_GRANULE).contains(&halted),
+        "{}",
+        halted
+    );
+    assert_eq!(instructions_until_the_timer(Some(1)), on_time);
+}
+
+#[test]
+fn rti_without_a_controller_is_still_a_bad_instruction() {
+    let mut state = state_with_snippet(".ORIG x3000\nRTI\n.END");
+    assert!(matches!(
+        run_loop(&mut state).map_err(|error| error.root().to_string()),
+        Err(message) if message == "Bad operation code x8"
+    ));
+}
+
+/// R3 is the instructions retired from the first read of ICLO to right before the second, around a loop of 1000 laps
+const MEASURED_LOOP: &str = "
+        .ORIG x3000
+        LDI R1, ICLO
+        LDI R2, ICHI
+        AND R0, R0, #0
+        LD R0, LAPS
+LAP     ADD R0, R0, #-1
+        BRp LAP
+        LDI R3, ICLO
+        NOT R1, R1
+        ADD R1, R1, #1
+        ADD R3, R3, R1
+        HALT
+LAPS    .FILL #1000
+ICLO    .FILL xFE16
+ICHI    .FILL xFE18
+        .END";
+
+#[test]
+fn the_instruction_counter_gives_the_exact_cost_of_a_loop() {
+    let speeds: [fn(&mut State); 3] = [
+        |_| {},
+        State::enable_decode_cache,
+        #[cfg(feature = "jit")]
+        |state| {
+            state.enable_jit();
+        },
+        #[cfg(not(feature = "jit"))]
+        |_| {},
+    ];
+    for speed in speeds {
+        let mut state = state_with_snippet(MEASURED_LOOP);
+        state.set_output(CaptureOutput::default());
+        state
+            .add_device(device::counter::InstructionCounter::default())
+            .unwrap();
+        speed(&mut state);
+        run_with_budget(&mut state, 10_000).unwrap();
+        assert_eq!(state.register_read(Registers::R2), 0);
+        // The first LDI of ICLO, LDI ICHI, AND, LD and the laps
+        assert_eq!(state.register_read(Registers::R3), 4 + 2 * 1000);
+    }
+}
+
+/// A keyboard nobody types on that waits like a real one, its keys never come while the sender lives
+struct SilentKeyboard(std::sync::mpsc::Receiver<u8>);
+
+impl Input for SilentKeyboard {
+    fn read_byte(&mut self) -> Option<u8> {
+        self.0.recv().ok()
+    }
+
+    fn poll_byte(&mut self) -> Option<u8> {
+        self.0.try_recv().ok()
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> console::KeyWait {
+        match self.0.recv_timeout(timeout) {
+            Ok(key) => console::KeyWait::Key(key),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => console::KeyWait::TimedOut,
+            Err(_) => console::KeyWait::Ended,
+        }
+    }
+}
+
+#[test]
+fn a_getc_waiting_longer_than_the_input_timeout_stops_the_program() {
+    let (_typist, keys) = std::sync::mpsc::channel();
+    let mut state = state_with_snippet(".ORIG x3000\nAND R0, R0, #0\nIN\nHALT\n.END");
+    state.set_output(CaptureOutput::default());
+    state.set_input(SilentKeyboard(keys));
+    state.set_input_timeout(Some(Duration::from_millis(20)));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(
+        matches!(
+            error.root(),
+            Error::Runtime(RuntimeError::InputTimeout {
+                trap: Traps::In,
+                pc: 0x3001
+            })
+        ),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn under_a_virtual_clock_only_the_end_of_the_input_times_out() {
+    let mut state = state_with_snippet(".ORIG x3000\nGETC\nGETC\nHALT\n.END");
+    state.set_output(CaptureOutput::default());
+    state.set_input(ScriptedInput::new("a"));
+    state.set_clock(clock::VirtualClock::default());
+    state.set_input_timeout(Some(Duration::ZERO));
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(
+        matches!(
+            error.root(),
+            Error::Runtime(RuntimeError::InputTimeout {
+                trap: Traps::Getc,
+                pc: 0x3001
+            })
+        ),
+        "{:?}",
+        error
+    );
+    assert_eq!(state.register_read(Registers::R0), b'a' as u16);
+}
+
+/// Counts in R2 the GETC that return no key, then prints the key
+const POLLING_GETC: &str = "
+        .ORIG x3000
+        AND R2, R2, #0
+LOOP    GETC
+        BRnp GOT
+        ADD R2, R2, #1
+        BRnzp LOOP
+GOT     OUT
+        HALT
+        .END";
+
+#[test]
+fn a_nonblocking_getc_returns_zero_until_a_key_is_pending() {
+    let run = |nonblocking: bool| {
+        let mut state = state_with_snippet(POLLING_GETC);
+        let output = CaptureOutput::default();
+        state.set_output(output.clone());
+        state.set_input(ScriptedInput::new("k").with_delay(3));
+        state.set_getc_nonblocking(nonblocking);
+        state.enable_stats();
+        run_with_budget(&mut state, 1000).unwrap();
+        (state, output.as_string())
+    };
+    let (blocking, output) = run(false);
+    assert_eq!(output, "kHALT");
+    assert_eq!(blocking.register_read(Registers::R2), 0);
+    assert_eq!(blocking.stats().unwrap().empty_getcs(), 0);
+    let (nonblocking, output) = run(true);
+    assert_eq!(output, "kHALT");
+    assert_eq!(nonblocking.register_read(Registers::R2), 3);
+    assert_eq!(nonblocking.stats().unwrap().empty_getcs(), 3);
+}
+
+#[test]
+fn in_still_waits_with_a_nonblocking_getc() {
+    let mut state = state_with_snippet(".ORIG x3000\nIN\nHALT\n.END");
+    state.set_output(CaptureOutput::default());
+    state.set_input(ScriptedInput::new("k").with_delay(3));
+    state.set_getc_nonblocking(true);
+    run_with_budget(&mut state, 100).unwrap();
+    assert_eq!(state.register_read(Registers::R0), b'k' as u16);
+}
+
+/// Four instructions a lap, COUNT (x3004) goes up by one every lap
+const COUNTING_LOOP: &str = "
+.ORIG x3000
+LOOP LD R0, COUNT
+ADD R0, R0, #1
+ST R0, COUNT
+BRnzp LOOP
+COUNT .FILL #0
+.END
+";
+
+/// The rows of a watch dump of COUNT and the word after it every 40 instructions, over 120 instructions
+fn watched_count(format: WatchDumpFormat, speed: fn(&mut State)) -> String {
+    let mut state = state_with_snippet(COUNTING_LOOP);
+    state.set_output(CaptureOutput::default());
+    let rows = CaptureOutput::default();
+    state.set_watch_dump(Some(WatchDump::new(
+        0x3004..=0x3005,
+        40,
+        format,
+        Box::new(rows.clone()),
+    )));
+    speed(&mut state);
+    let result = run_with_budget(&mut state, 120);
+    assert!(matches!(
+        result,
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(120)))
+    ));
+    state.finish_watch_dump().unwrap();
+    rows.as_string()
+}
+
+#[test]
+fn the_watch_dump_writes_a_row_every_so_many_instructions() {
+    let speeds: [fn(&mut State); 3] = [
+        |_| {},
+        State::enable_decode_cache,
+        #[cfg(feature = "jit")]
+        |state| {
+            state.enable_jit();
+        },
+        #[cfg(not(feature = "jit"))]
+        |_| {},
+    ];
+    for speed in speeds {
+        assert_eq!(
+            watched_count(WatchDumpFormat::Hex, speed),
+            "40: [x3004] x000A x0000\n80: [x3004] x0014 x0000\n120: [x3004] x001E x0000\n"
+        );
+    }
+}
+
+#[test]
+fn the_watch_dump_writes_csv_rows_in_decimal() {
+    assert_eq!(
+        WatchDump::csv_header(0x3004..=0x3005),
+        "instructions,x3004,x3005\n"
+    );
+    assert_eq!(
+        watched_count(WatchDumpFormat::Csv, |_| {}),
+        "40,10,0\n80,20,0\n120,30,0\n"
+    );
+}
+
+#[test]
+fn the_watch_dump_keeps_the_devices_ticking() {
+    let mut state = state_with_snippet(COUNTING_LOOP);
+    state.set_output(CaptureOutput::default());
+    state.set_tick_granule(16);
+    let rows = CaptureOutput::default();
+    state.set_watch_dump(Some(WatchDump::new(
+        0x3004..=0x3004,
+        40,
+        WatchDumpFormat::Hex,
+        Box::new(rows.clone()),
+    )));
+    state
+        .add_device(device::counter::InstructionCounter::default())
+        .unwrap();
+    let _ = run_with_budget(&mut state, 80);
+    state.finish_watch_dump().unwrap();
+    assert_eq!(rows.as_string(), "40: [x3004] x000A\n80: [x3004] x0014\n");
 }
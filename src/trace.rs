@@ -0,0 +1,498 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+use crate::fault::Fault;
+
+pub mod jsonl;
+use crate::{
+    AddressSet, Error, Flags, Operations, Registers, RuntimeError, State, run_step, run_with_budget,
+};
+
+/// General purpose registers in the order they are written in a trace
+const GENERAL_REGISTERS: [Registers; 8] = [
+    Registers::R0,
+    Registers::R1,
+    Registers::R2,
+    Registers::R3,
+    Registers::R4,
+    Registers::R5,
+    Registers::R6,
+    Registers::R7,
+];
+
+/// Like [`crate::run_loop`] but writes a line in the reference trace format for every executed instruction:
+/// ```text
+/// PC=x3000 IR=x1261 R1=x0001 CC=P
+/// ```
+/// The address and the word of the instruction followed by the registers it changed, and the condition codes if they changed.
+/// It is the step trace of lc3sim, so both simulators can be compared with [`diff_traces`]
+pub fn run_traced(state: &mut State, trace: &mut impl Write) -> Result<(), Error> {
+    match run_traced_with_budget(state, trace, u64::MAX) {
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Ok(()),
+        result => result,
+    }
+}
+
+/// Like [`run_traced`] but fails if the program doesn't halt within the given amount of instructions, like [`crate::run_with_budget`]
+pub fn run_traced_with_budget(
+    state: &mut State,
+    trace: &mut impl Write,
+    budget: u64,
+) -> Result<(), Error> {
+    run_filtered_with_budget(
+        state,
+        trace,
+        &TraceFilter::new(),
+        TraceFormat::Reference,
+        budget,
+    )
+}
+
+/// Like [`run_traced_with_budget`] writing only the lines of the instructions `filter` lets through, in `format`. The
+/// instructions before [`TraceFilter::after`] run in the run loop, as fast as without a trace
+pub fn run_filtered_with_budget(
+    state: &mut State,
+    trace: &mut impl Write,
+    filter: &TraceFilter,
+    format: TraceFormat,
+    budget: u64,
+) -> Result<(), Error> {
+    let untraced = filter.after.saturating_sub(state.executed).min(budget);
+    if untraced > 0 {
+        match run_with_budget(state, untraced) {
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) if untraced < budget => {}
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => {
+                return Err(RuntimeError::BudgetExhausted(budget).into());
+            }
+            result => return result,
+        }
+    }
+    for _ in untraced..budget {
+        if !state.is_running() {
+            return Ok(());
+        }
+        step_traced(state, trace, filter, format)?;
+    }
+    match state.is_running() {
+        true => Err(RuntimeError::BudgetExhausted(budget).into()),
+        false => Ok(()),
+    }
+}
+
+/// What a trace keeps, `--trace-only`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionClass {
+    /// BR, JMP and RET, JSR and JSRR, RTI
+    Branches,
+    Traps,
+    /// ST, STI and STR
+    Stores,
+    /// LD, LDI and LDR
+    Loads,
+    /// ADD, AND, NOT and LEA
+    Alu,
+}
+
+impl InstructionClass {
+    /// The class with the `name` of `--trace-only`, case insensitive
+    pub fn from_name(name: &str) -> Option<InstructionClass> {
+        [
+            ("branches", InstructionClass::Branches),
+            ("traps", InstructionClass::Traps),
+            ("stores", InstructionClass::Stores),
+            ("loads", InstructionClass::Loads),
+            ("alu", InstructionClass::Alu),
+        ]
+        .into_iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, class)| class)
+    }
+
+    /// The class of the instruction `word`, none for the reserved opcode
+    pub fn of(word: u16) -> Option<InstructionClass> {
+        [
+            InstructionClass::Branches,
+            InstructionClass::Traps,
+            InstructionClass::Stores,
+            InstructionClass::Loads,
+            InstructionClass::Alu,
+        ]
+        .into_iter()
+        .find(|class| class.opcodes() & 1 << (word >> 12) != 0)
+    }
+
+    /// A bit for every opcode of the class
+    fn opcodes(self) -> u16 {
+        let opcodes: &[Operations] = match self {
+            InstructionClass::Branches => &[
+                Operations::Br,
+                Operations::Jmp,
+                Operations::Jsr,
+                Operations::Rti,
+            ],
+            InstructionClass::Traps => &[Operations::Trap],
+            InstructionClass::Stores => &[Operations::St, Operations::Sti, Operations::Str],
+            InstructionClass::Loads => &[Operations::Ld, Operations::Ldi, Operations::Ldr],
+            InstructionClass::Alu => &[
+                Operations::Add,
+                Operations::And,
+                Operations::Not,
+                Operations::Lea,
+            ],
+        };
+        opcodes
+            .iter()
+            .fold(0, |mask, &opcode| mask | 1 << opcode as u16)
+    }
+}
+
+/// Which instructions a trace writes a line for, all of them by default. An instruction has to pass every filter
+/// given: fetched from one of the [`TraceFilter::address_range`]s, of one of the [`TraceFilter::only`] classes and
+/// after [`TraceFilter::after`] instructions. Looking costs a bit test and a mask, a filter can stay on for long runs
+#[derive(Default)]
+pub struct TraceFilter {
+    /// `None` for every address
+    addresses: Option<AddressSet>,
+    /// A bit for every opcode traced
+    opcodes: Option<u16>,
+    after: u64,
+}
+
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        TraceFilter::default()
+    }
+
+    /// Trace the instructions fetched from `range`, and from the other ranges given
+    pub fn address_range(mut self, range: RangeInclusive<u16>) -> TraceFilter {
+        let addresses = self.addresses.get_or_insert_with(AddressSet::new);
+        for address in range {
+            addresses.mark(address as usize);
+        }
+        self
+    }
+
+    /// Trace the instructions of `class`, and of the other classes given
+    pub fn only(mut self, class: InstructionClass) -> TraceFilter {
+        *self.opcodes.get_or_insert(0) |= class.opcodes();
+        self
+    }
+
+    /// Trace from the instruction after the first `instructions` the machine executed
+    pub fn after(mut self, instructions: u64) -> TraceFilter {
+        self.after = instructions;
+        self
+    }
+
+    #[inline]
+    fn allows(&self, pc: u16, instruction: u16, executed: u64) -> bool {
+        executed >= self.after
+            && self
+                .opcodes
+                .is_none_or(|opcodes| opcodes & 1 << (instruction >> 12) != 0)
+            && self
+                .addresses
+                .as_ref()
+                .is_none_or(|addresses| addresses.contains(pc as usize))
+    }
+}
+
+/// How the lines of a trace are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The step trace of lc3sim, see [`run_traced`]
+    #[default]
+    Reference,
+    /// A JSON object per line, see [`jsonl`]
+    Jsonl,
+}
+
+/// Run the next instruction of a running machine writing its line of the trace if `filter` lets it through, for
+/// [`run_filtered_with_budget`] and the stepping of [`crate::step`]
+pub(crate) fn step_traced(
+    state: &mut State,
+    trace: &mut impl Write,
+    filter: &TraceFilter,
+    format: TraceFormat,
+) -> Result<(), Error> {
+    if state.interrupt.take() {
+        return Err(RuntimeError::Interrupted.into());
+    }
+    state.service_interrupts()?;
+    let pc = state.register_read(Registers::Pc);
+    let stack_pointer = state.before_instruction(pc)?;
+    let instruction = state.read_instruction(pc as usize);
+    let traced = filter.allows(pc, instruction, state.executed);
+    let before = GENERAL_REGISTERS.map(|register| state.register_read(register));
+    let flags = state.register_read(Registers::Flags);
+    state.increment_pc();
+    let result = run_step(instruction, state);
+    if traced {
+        let changed: Vec<(usize, u16)> = GENERAL_REGISTERS
+            .into_iter()
+            .enumerate()
+            .map(|(index, register)| (index, state.register_read(register)))
+            .filter(|&(index, value)| value != before[index])
+            .collect();
+        let cc = (state.register_read(Registers::Flags) != flags)
+            .then(|| condition_codes(state.register_read(Registers::Flags)));
+        let line = match format {
+            TraceFormat::Reference => {
+                let mut line = format!("PC=x{:04X} IR=x{:04X}", pc, instruction);
+                for (index, value) in changed {
+                    let _ = write!(line, " R{}=x{:04X}", index, value);
+                }
+                if let Some(cc) = cc {
+                    let _ = write!(line, " CC={}", cc);
+                }
+                line
+            }
+            TraceFormat::Jsonl => jsonl::line(state.executed + 1, pc, instruction, &changed, cc),
+        };
+        writeln!(trace, "{}", line)?;
+    }
+    result.map_err(|error| Fault::wrap(error, pc, instruction, state))?;
+    state.after_instruction(pc, stack_pointer)?;
+    state.executed += 1;
+    state.tick_devices_if_due();
+    Ok(())
+}
+
+/// N, Z or P
+pub(crate) fn condition_codes(flags: u16) -> char {
+    match flags {
+        flags if flags == Flags::Neg as u16 => 'N',
+        flags if flags == Flags::Zro as u16 => 'Z',
+        _ => 'P',
+    }
+}
+
+/// Machine state rebuilt from the lines of a trace
+#[derive(Default, Clone, PartialEq)]
+struct TraceState {
+    registers: [u16; 8],
+    condition_codes: char,
+    pc: u16,
+}
+
+impl TraceState {
+    /// Apply the changes in a trace line, `None` if the line isn't in the reference format
+    fn apply(&mut self, line: &str) -> Option<()> {
+        for field in line.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            let hex = || u16::from_str_radix(value.strip_prefix('x')?, 16).ok();
+            match name {
+                "PC" => self.pc = hex()?,
+                "IR" => {
+                    hex()?;
+                }
+                "CC" => self.condition_codes = value.chars().next()?,
+                register => {
+                    let index: usize = register.strip_prefix('R')?.parse().ok()?;
+                    *self.registers.get_mut(index)? = hex()?;
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!("PC=x{:04X}", self.pc);
+        for (index, value) in self.registers.iter().enumerate() {
+            let _ = write!(description, " R{}=x{:04X}", index, value);
+        }
+        let _ = write!(description, " CC={}", self.condition_codes);
+        description
+    }
+}
+
+/// Compare two reference traces step by step.
+/// Returns `None` if they are equal, otherwise the number of the first divergent step (counting from 1)
+/// and a report with both lines and the machine state each trace had built up to that step
+pub fn diff_traces(ours: &str, theirs: &str) -> Option<(usize, String)> {
+    let mut our_lines = ours.lines();
+    let mut their_lines = theirs.lines();
+    let mut our_state = TraceState {
+        condition_codes: 'Z',
+        ..Default::default()
+    };
+    let mut their_state = our_state.clone();
+    let mut step = 0;
+    loop {
+        step += 1;
+        let (our_line, their_line) = match (our_lines.next(), their_lines.next()) {
+            (None, None) => return None,
+            (our_line, their_line) => (our_line, their_line),
+        };
+        let our_parsed = our_line.and_then(|line| our_state.apply(line));
+        let their_parsed = their_line.and_then(|line| their_state.apply(line));
+        if our_line.map(str::trim) == their_line.map(str::trim) && our_parsed.is_some() {
+            continue;
+        }
+        let mut report = format!("The traces diverge at step {}\n", step);
+        for (name, line, state, parsed) in [
+            ("ours", our_line, &our_state, our_parsed),
+            ("theirs", their_line, &their_state, their_parsed),
+        ] {
+            match (line, parsed) {
+                (None, _) => {
+                    let _ = writeln!(report, "{:>6}: <trace ended>", name);
+                }
+                (Some(line), None) => {
+                    let _ = writeln!(report, "{:>6}: {} <not a reference trace line>", name, line);
+                }
+                (Some(line), Some(())) => {
+                    let _ = writeln!(report, "{:>6}: {}", name, line);
+                }
+            }
+            let _ = writeln!(report, "{:>6}  {}", "", state.describe());
+        }
+        return Some((step, report));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestVm;
+
+    fn trace(source: &str) -> String {
+        let mut vm = TestVm::with_snippet(source);
+        let mut trace = Vec::new();
+        run_traced(vm.state_mut(), &mut trace).unwrap();
+        String::from_utf8(trace).unwrap()
+    }
+
+    #[test]
+    fn lines_have_the_changed_registers() {
+        let trace =
+            trace(".ORIG x3000\nADD R1, R1, #1\nADD R1, R1, #-1\nJSR NEXT\nNEXT HALT\n.END");
+        assert_eq!(
+            trace,
+            "PC=x3000 IR=x1261 R1=x0001 CC=P\n\
+             PC=x3001 IR=x127F R1=x0000 CC=Z\n\
+             PC=x3002 IR=x4800 R7=x3003\n\
+             PC=x3003 IR=xF025\n"
+        );
+    }
+
+    /// 12 instructions: x3000 to x3002, x3004 and x3005 three times, x3006, x3007 and x3003
+    const SUBROUTINE_LOOP: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #3
+        JSR SUB
+        HALT
+SUB     ADD R0, R0, #-1
+        BRp SUB
+        ST R0, SAVE
+        RET
+SAVE    .BLKW 1
+        .END";
+
+    fn filtered(filter: TraceFilter) -> Vec<String> {
+        let mut vm = TestVm::with_snippet(SUBROUTINE_LOOP);
+        let mut trace = Vec::new();
+        run_filtered_with_budget(
+            vm.state_mut(),
+            &mut trace,
+            &filter,
+            TraceFormat::Reference,
+            100,
+        )
+        .unwrap();
+        assert_eq!(vm.state().instructions_executed(), 12);
+        String::from_utf8(trace)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn pcs(lines: &[String]) -> Vec<&str> {
+        lines.iter().map(|line| &line[3..8]).collect()
+    }
+
+    #[test]
+    fn filters_keep_only_the_lines_asked_for() {
+        assert_eq!(filtered(TraceFilter::new()).len(), 12);
+        let subroutine = filtered(TraceFilter::new().address_range(0x3004..=0x3005));
+        assert_eq!(
+            pcs(&subroutine),
+            ["x3004", "x3005", "x3004", "x3005", "x3004", "x3005"]
+        );
+        assert_eq!(subroutine[0], "PC=x3004 IR=x103F R0=x0002");
+        let branches = filtered(TraceFilter::new().only(InstructionClass::Branches));
+        assert_eq!(
+            pcs(&branches),
+            ["x3002", "x3005", "x3005", "x3005", "x3007"]
+        );
+        let traps_and_stores = filtered(
+            TraceFilter::new()
+                .only(InstructionClass::Traps)
+                .only(InstructionClass::Stores),
+        );
+        assert_eq!(traps_and_stores, ["PC=x3006 IR=x3001", "PC=x3003 IR=xF025"]);
+        assert_eq!(
+            pcs(&filtered(TraceFilter::new().after(10))),
+            ["x3007", "x3003"]
+        );
+        let both = TraceFilter::new()
+            .address_range(0x3006..=0x3007)
+            .address_range(0x3000..=0x3000)
+            .only(InstructionClass::Stores)
+            .only(InstructionClass::Alu);
+        assert_eq!(pcs(&filtered(both)), ["x3000", "x3006"]);
+        assert_eq!(
+            InstructionClass::from_name("Traps"),
+            Some(InstructionClass::Traps)
+        );
+    }
+
+    #[test]
+    fn tracing_after_more_instructions_than_the_budget_runs_out_of_it() {
+        let mut vm = TestVm::with_snippet(SUBROUTINE_LOOP);
+        let mut trace = Vec::new();
+        let filter = TraceFilter::new().after(50);
+        let error = run_filtered_with_budget(
+            vm.state_mut(),
+            &mut trace,
+            &filter,
+            TraceFormat::Reference,
+            5,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Runtime(RuntimeError::BudgetExhausted(5))
+        ));
+        assert!(trace.is_empty());
+    }
+
+    #[test]
+    fn equal_traces_dont_diverge() {
+        let trace = trace(".ORIG x3000\nAND R0, R0, #0\nHALT\n.END");
+        assert!(diff_traces(&trace, &trace).is_none());
+    }
+
+    #[test]
+    fn reports_the_first_divergent_step() {
+        let ours = "PC=x3000 IR=x1261 R1=x0001 CC=P\nPC=x3001 IR=x4800 R7=x3002\n";
+        let theirs = "PC=x3000 IR=x1261 R1=x0001 CC=P\nPC=x3001 IR=x4800 R7=x3001\n";
+        let (step, report) = diff_traces(ours, theirs).unwrap();
+        assert_eq!(step, 2);
+        assert!(report.contains("  ours: PC=x3001 IR=x4800 R7=x3002\n"));
+        assert!(report.contains(
+            "        PC=x3001 R0=x0000 R1=x0001 R2=x0000 R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x3001 CC=P\n"
+        ));
+    }
+
+    #[test]
+    fn a_shorter_trace_diverges() {
+        let ours = "PC=x3000 IR=xF025\n";
+        let (step, report) = diff_traces(ours, "").unwrap();
+        assert_eq!(step, 1);
+        assert!(report.contains("theirs: <trace ended>"));
+    }
+}
@@ -0,0 +1,297 @@
+//! Traces with a JSON object per line, what `--trace-format jsonl` writes:
+//! ```text
+//! {"step":1,"pc":12288,"ir":4705,"regs":{"R1":1},"cc":"P"}
+//! ```
+//! The number of the instruction since the machine started, its address and word, the registers it changed and the
+//! condition codes if they changed. [`diff_jsonl_traces`] compares two of them, also written by another simulator
+//! as long as it has `pc`: other fields, like what the instruction printed in `output`, are compared as they are,
+//! but for the wall-clock ones of [`VOLATILE_FIELDS`] and any other ignored
+
+use std::fmt::Write as _;
+
+use serde_json::{Map, Value};
+
+use crate::{Error, LoadError};
+
+/// Fields expected to differ between two runs of the same program, never compared
+pub const VOLATILE_FIELDS: [&str; 4] = ["time", "timestamp", "ts", "elapsed"];
+/// Lines of the longer trace shown past the end of the shorter one
+const TAIL_SHOWN: usize = 20;
+
+/// The line of an instruction, `changed` being the registers it changed and their values
+pub(crate) fn line(
+    step: u64,
+    pc: u16,
+    ir: u16,
+    changed: &[(usize, u16)],
+    cc: Option<char>,
+) -> String {
+    let mut line = format!(r#"{{"step":{},"pc":{},"ir":{},"regs":{{"#, step, pc, ir);
+    for (index, (register, value)) in changed.iter().enumerate() {
+        if index > 0 {
+            line.push(',');
+        }
+        let _ = write!(line, r#""R{}":{}"#, register, value);
+    }
+    line.push('}');
+    if let Some(cc) = cc {
+        let _ = write!(line, r#","cc":"{}""#, cc);
+    }
+    line.push('}');
+    line
+}
+
+/// How [`diff_jsonl_traces`] compares
+#[derive(Clone, Debug)]
+pub struct JsonlDiffOptions {
+    /// Lines before the divergent one shown from each trace
+    pub context: usize,
+    /// Fields never compared, [`VOLATILE_FIELDS`] by default
+    pub ignored: Vec<String>,
+}
+
+impl Default for JsonlDiffOptions {
+    fn default() -> JsonlDiffOptions {
+        JsonlDiffOptions {
+            context: 3,
+            ignored: VOLATILE_FIELDS.map(String::from).to_vec(),
+        }
+    }
+}
+
+/// An instruction of a trace
+struct Record {
+    /// Counting from 1
+    line: usize,
+    text: String,
+    fields: Map<String, Value>,
+}
+
+impl Record {
+    fn step(&self) -> Option<u64> {
+        self.fields.get("step").and_then(Value::as_u64)
+    }
+}
+
+fn parse(trace: &str, name: &str) -> Result<Vec<Record>, Error> {
+    let mut records = Vec::new();
+    for (index, text) in trace.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+        let bad =
+            |reason: &str| LoadError::BadTrace(format!("{} line {}: {}", name, index + 1, reason));
+        let fields = match serde_json::from_str(text) {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => return Err(bad("not a JSON object").into()),
+            Err(error) => return Err(bad(&error.to_string()).into()),
+        };
+        if !fields.contains_key("pc") {
+            return Err(bad("no `pc`").into());
+        }
+        records.push(Record {
+            line: index + 1,
+            text: text.to_string(),
+            fields,
+        });
+    }
+    Ok(records)
+}
+
+/// What differs between two records, `None` if nothing but the ignored fields
+fn difference(ours: &Record, theirs: &Record, ignored: &[String]) -> Option<String> {
+    let compared = |name: &&String| !ignored.contains(name) && name.as_str() != "step";
+    let mut names: Vec<&String> = ours
+        .fields
+        .keys()
+        .chain(theirs.fields.keys())
+        .filter(compared)
+        .collect();
+    names.sort_by_key(|name| match name.as_str() {
+        "pc" => 0,
+        "regs" => 1,
+        "output" => 2,
+        _ => 3,
+    });
+    names.dedup();
+    let name = names
+        .into_iter()
+        .find(|name| ours.fields.get(*name) != theirs.fields.get(*name))?;
+    Some(match name.as_str() {
+        "pc" => "different PC".to_string(),
+        "regs" => "different register writes".to_string(),
+        "output" => "different output".to_string(),
+        field => format!("different `{}`", field),
+    })
+}
+
+/// Compare two JSONL traces, see the module documentation. When both number their steps, the one starting earlier
+/// is skipped to the first step of the other, so a trace started with `--trace-after` lines up with a full one.
+/// Returns `None` if they are equal, otherwise the number of the first divergent step (counting from 1 in the
+/// aligned traces) and a report with the reason and the lines around it. A longer trace diverges at the step right
+/// after the end of the shorter one, its extra lines are in the report. Fails if a line isn't a JSON object with a `pc`
+pub fn diff_jsonl_traces(
+    ours: &str,
+    theirs: &str,
+    options: &JsonlDiffOptions,
+) -> Result<Option<(usize, String)>, Error> {
+    let ours = parse(ours, "ours")?;
+    let theirs = parse(theirs, "theirs")?;
+    let (ours, theirs) = align(&ours, &theirs);
+    let common = ours.len().min(theirs.len());
+    let divergent = (0..common).find_map(|index| {
+        difference(&ours[index], &theirs[index], &options.ignored).map(|reason| (index, reason))
+    });
+    if let Some((index, reason)) = divergent {
+        let mut report = format!("The traces diverge at step {}: {}\n", index + 1, reason);
+        for (name, records) in [("ours", ours), ("theirs", theirs)] {
+            let _ = writeln!(report, "{}:", name);
+            for record in &records[index.saturating_sub(options.context)..=index] {
+                let marker = if record.line == records[index].line {
+                    '>'
+                } else {
+                    ' '
+                };
+                let _ = writeln!(report, "{} {:>6}: {}", marker, record.line, record.text);
+            }
+        }
+        return Ok(Some((index + 1, report)));
+    }
+    if ours.len() == theirs.len() {
+        return Ok(None);
+    }
+    let (name, longer) = match ours.len() > theirs.len() {
+        true => ("ours", ours),
+        false => ("theirs", theirs),
+    };
+    let tail = &longer[common..];
+    let mut report = format!(
+        "The traces diverge at step {}: {} goes on for {} more line(s)\n",
+        common + 1,
+        name,
+        tail.len()
+    );
+    for record in tail.iter().take(TAIL_SHOWN) {
+        let _ = writeln!(report, "  {:>6}: {}", record.line, record.text);
+    }
+    if tail.len() > TAIL_SHOWN {
+        let _ = writeln!(report, "  ... and {} more", tail.len() - TAIL_SHOWN);
+    }
+    Ok(Some((common + 1, report)))
+}
+
+/// Skip the records of the trace starting at an earlier step, if both have steps
+fn align<'a>(ours: &'a [Record], theirs: &'a [Record]) -> (&'a [Record], &'a [Record]) {
+    let (Some(our_start), Some(their_start)) = (
+        ours.first().and_then(Record::step),
+        theirs.first().and_then(Record::step),
+    ) else {
+        return (ours, theirs);
+    };
+    let skip = |records: &'a [Record], start: u64| {
+        let first = records
+            .iter()
+            .position(|record| record.step().is_some_and(|step| step >= start))
+            .unwrap_or(records.len());
+        &records[first..]
+    };
+    match our_start.cmp(&their_start) {
+        std::cmp::Ordering::Less => (skip(ours, their_start), theirs),
+        std::cmp::Ordering::Greater => (ours, skip(theirs, our_start)),
+        std::cmp::Ordering::Equal => (ours, theirs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE: &str = r#"{"step":1,"pc":12288,"ir":4705,"regs":{"R1":1},"cc":"P","time":10}
+{"step":2,"pc":12289,"ir":4735,"regs":{"R1":0},"cc":"Z","time":11}
+{"step":3,"pc":12290,"ir":18432,"regs":{"R7":12291},"time":12}
+{"step":4,"pc":12291,"ir":61477,"regs":{},"output":"HALT","time":13}
+"#;
+
+    fn diff(ours: &str, theirs: &str) -> Option<(usize, String)> {
+        diff_jsonl_traces(ours, theirs, &JsonlDiffOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn the_wall_clock_never_makes_traces_diverge() {
+        let later = TRACE.replace(r#""time":1"#, r#""time":9"#);
+        assert_ne!(later, TRACE);
+        assert_eq!(diff(TRACE, &later), None);
+    }
+
+    #[test]
+    fn the_first_semantic_divergence_is_reported_with_context() {
+        let theirs = TRACE.replace(r#""regs":{"R7":12291}"#, r#""regs":{"R7":12290}"#);
+        let (step, report) = diff(TRACE, &theirs).unwrap();
+        assert_eq!(step, 3);
+        assert!(
+            report.starts_with("The traces diverge at step 3: different register writes\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains(r#"       1: {"step":1"#), "{}", report);
+        assert!(
+            report.contains(r#">      3: {"step":3,"pc":12290,"ir":18432,"regs":{"R7":12290}"#),
+            "{}",
+            report
+        );
+        let theirs = TRACE.replace(r#""output":"HALT""#, r#""output":"HALT!""#);
+        let (step, report) = diff(TRACE, &theirs).unwrap();
+        assert_eq!(step, 4);
+        assert!(report.contains("different output"), "{}", report);
+        let theirs = TRACE.replace(r#""pc":12290"#, r#""pc":12296"#);
+        assert!(diff(TRACE, &theirs).unwrap().1.contains("different PC"));
+    }
+
+    #[test]
+    fn the_tail_of_the_longer_trace_is_reported() {
+        let shorter: String = TRACE
+            .lines()
+            .take(2)
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let (step, report) = diff(&shorter, TRACE).unwrap();
+        assert_eq!(step, 3);
+        assert!(
+            report.starts_with("The traces diverge at step 3: theirs goes on for 2 more line(s)\n")
+        );
+        assert!(report.contains(r#"       4: {"step":4"#), "{}", report);
+    }
+
+    #[test]
+    fn a_trace_starting_later_is_aligned_on_its_steps() {
+        let later: String = TRACE
+            .lines()
+            .skip(1)
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(diff(TRACE, &later), None);
+        assert_eq!(diff(&later, TRACE), None);
+    }
+
+    #[test]
+    fn a_line_that_is_not_an_instruction_fails() {
+        let error = diff_jsonl_traces(TRACE, "{\"pc\":1}\n[1]\n", &JsonlDiffOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Bad trace: theirs line 2: not a JSON object"
+        );
+    }
+
+    #[test]
+    fn lines_have_the_changed_registers() {
+        assert_eq!(
+            line(3, 0x3002, 0x4800, &[(7, 0x3003)], None),
+            r#"{"step":3,"pc":12290,"ir":18432,"regs":{"R7":12291}}"#
+        );
+        assert_eq!(
+            line(1, 0x3000, 0x1261, &[(1, 1), (2, 5)], Some('P')),
+            r#"{"step":1,"pc":12288,"ir":4705,"regs":{"R1":1,"R2":5},"cc":"P"}"#
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Which routine of the VM a TRAP runs, what `--trap x40=puts` changes. Some courses number the traps their own way
+//! or add some: [`TrapTable::set`] puts a routine of the VM at another vector and [`TrapTable::set_custom`] runs a
+//! closure instead. By default every [`Traps`] is at its own vector.
+//!
+//! The trap table of an operating system in memory, see [`crate::irq`], still comes first: a vector with a routine
+//! there goes to it. A vector with nothing in either table stops the program with
+//! [`crate::RuntimeError::BadTrapCode`]
+
+use std::sync::Arc;
+
+use crate::{Error, State, Traps};
+
+/// A trap written in Rust, see [`TrapTable::set_custom`]
+pub type CustomTrap = Arc<dyn Fn(&mut State) -> Result<(), Error> + Send + Sync>;
+
+/// What a vector of the [`TrapTable`] runs
+#[derive(Clone)]
+pub enum TrapRoutine {
+    Native(Traps),
+    Custom(CustomTrap),
+}
+
+/// The name of every routine of the VM for `--trap`, the name of its alias in the assembler where it has one
+const NAMES: [(&str, Traps); 16] = [
+    ("getc", Traps::Getc),
+    ("out", Traps::Out),
+    ("puts", Traps::Puts),
+    ("in", Traps::In),
+    ("putsp", Traps::Putsp),
+    ("halt", Traps::Halt),
+    ("assert", Traps::Assert),
+    ("exit", Traps::Exit),
+    ("dbg", Traps::Dbg),
+    ("readline", Traps::ReadLine),
+    ("fopen", Traps::FileOpen),
+    ("fread", Traps::FileRead),
+    ("fwrite", Traps::FileWrite),
+    ("fclose", Traps::FileClose),
+    ("time", Traps::Time),
+    ("sleep", Traps::Sleep),
+];
+
+/// The routine of the VM every trap vector runs, see [`State::trap_table_mut`]
+#[derive(Clone)]
+pub struct TrapTable {
+    routines: Box<[Option<TrapRoutine>; 256]>,
+}
+
+impl Default for TrapTable {
+    fn default() -> TrapTable {
+        let mut table = TrapTable {
+            routines: Box::new([const { None }; 256]),
+        };
+        for (_, routine) in NAMES {
+            table.set(routine as u8, routine);
+        }
+        table
+    }
+}
+
+impl TrapTable {
+    /// Run the routine of the VM `routine` for the TRAP with `vector`
+    pub fn set(&mut self, vector: u8, routine: Traps) -> &mut TrapTable {
+        self.routines[vector as usize] = Some(TrapRoutine::Native(routine));
+        self
+    }
+
+    /// Run `routine` for the TRAP with `vector`. It gets the machine with the PC already after the TRAP, an error
+    /// stops the program like the one of a routine of the VM
+    pub fn set_custom(
+        &mut self,
+        vector: u8,
+        routine: impl Fn(&mut State) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> &mut TrapTable {
+        self.routines[vector as usize] = Some(TrapRoutine::Custom(Arc::new(routine)));
+        self
+    }
+
+    /// Leave the TRAP with `vector` without a routine
+    pub fn remove(&mut self, vector: u8) -> &mut TrapTable {
+        self.routines[vector as usize] = None;
+        self
+    }
+
+    /// What the TRAP with `vector` runs, if anything
+    pub fn get(&self, vector: u8) -> Option<&TrapRoutine> {
+        self.routines[vector as usize].as_ref()
+    }
+}
+
+/// The routine of the VM with the `name` of `--trap`, case insensitive
+pub fn native_trap(name: &str) -> Option<Traps> {
+    NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, routine)| *routine)
+}
+
+impl State {
+    /// The table of the trap routines, for [`TrapTable::set`] and [`TrapTable::set_custom`]
+    pub fn trap_table_mut(&mut self) -> &mut TrapTable {
+        self.traps.get_or_insert_default()
+    }
+
+    /// What the TRAP with `vector` runs, the default one until [`State::trap_table_mut`]
+    pub(crate) fn trap_routine(&self, vector: u8) -> Option<TrapRoutine> {
+        match &self.traps {
+            Some(table) => table.get(vector).cloned(),
+            None => Traps::try_from(vector as u16).ok().map(TrapRoutine::Native),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_table_runs_every_routine_at_its_vector() {
+        let table = TrapTable::default();
+        for vector in 0..=255_u8 {
+            let native = match table.get(vector) {
+                Some(TrapRoutine::Native(routine)) => Some(*routine as u16),
+                Some(TrapRoutine::Custom(_)) => panic!("a custom trap by default"),
+                None => None,
+            };
+            assert_eq!(
+                native,
+                Traps::try_from(vector as u16).ok().map(|t| t as u16)
+            );
+        }
+        assert_eq!(native_trap("PUTS"), Some(Traps::Puts));
+        assert_eq!(native_trap("nope"), None);
+    }
+}
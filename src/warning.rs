@@ -0,0 +1,355 @@
+//! The warnings of a run, each with a stable code scripts and the command line can name:
+//!
+//! | Code | Warning |
+//! |------|---------|
+//! | W001 | executing a word nothing was loaded to nor written, see [`crate::UninitializedExec::Warn`] |
+//! | W002 | a break of the stack discipline, see [`crate::stack`] |
+//! | W003 | self-modifying code, see [`crate::self_modify`] |
+//! | W004 | an image loaded over the words of another, see [`crate::State::load_image_checked`] |
+//! | W005 | a conflict in the layout of the address space, see [`crate::layout`] |
+//!
+//! They all go through [`Warnings::emit`], which prints a warning to stderr the first time and only counts it after,
+//! a warning being the same as another when it says the same, or for W002 when it is the same kind of break. Each
+//! code can be allowed, what `--allow W003` does, or denied with `--deny W003`: an allowed warning is neither printed
+//! nor counted, a denied one stops the program with [`RuntimeError::WarningDenied`]. What was warned about ends in
+//! [`Warnings::report`] and in the `warnings` of the JSON summary
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+
+use serde::Serialize;
+
+use crate::layout::LayoutConflict;
+use crate::stack::StackViolation;
+use crate::{Error, RuntimeError, diag};
+
+/// The code of a kind of warning, stable from one version to the next
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WarningCode {
+    #[serde(rename = "W001")]
+    UninitializedExec,
+    #[serde(rename = "W002")]
+    Stack,
+    #[serde(rename = "W003")]
+    SelfModify,
+    #[serde(rename = "W004")]
+    LoadOverlap,
+    #[serde(rename = "W005")]
+    Layout,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 5] = [
+        WarningCode::UninitializedExec,
+        WarningCode::Stack,
+        WarningCode::SelfModify,
+        WarningCode::LoadOverlap,
+        WarningCode::Layout,
+    ];
+
+    /// `W001`, what the command line takes
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningCode::UninitializedExec => "W001",
+            WarningCode::Stack => "W002",
+            WarningCode::SelfModify => "W003",
+            WarningCode::LoadOverlap => "W004",
+            WarningCode::Layout => "W005",
+        }
+    }
+
+    /// What the warnings of the code are about, for the report at exit
+    pub fn title(self) -> &'static str {
+        match self {
+            WarningCode::UninitializedExec => "uninitialized execution",
+            WarningCode::Stack => "stack discipline",
+            WarningCode::SelfModify => "self-modifying code",
+            WarningCode::LoadOverlap => "overlap on load",
+            WarningCode::Layout => "layout conflict",
+        }
+    }
+
+    /// The code written like `W003` or `w003`
+    pub fn from_code(code: &str) -> Option<WarningCode> {
+        WarningCode::ALL
+            .into_iter()
+            .find(|known| known.code().eq_ignore_ascii_case(code))
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(self.code())
+    }
+}
+
+/// What happens with the warnings of a code, see [`Warnings::set_level`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WarningLevel {
+    /// Printed the first time and counted
+    #[default]
+    Warn,
+    /// Ignored
+    Allow,
+    /// Stops the program with [`RuntimeError::WarningDenied`]
+    Deny,
+}
+
+/// Something suspicious the program did, see the module documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// The PC reached this address, nothing was loaded nor written there
+    UninitializedExec(u16),
+    Stack(StackViolation),
+    /// The store at `writer` modified the instruction at `target`
+    SelfModify {
+        writer: u16,
+        target: u16,
+    },
+    /// The image loaded from `start` to `end` overwrote words another image loaded, the first at `address`
+    LoadOverlap {
+        start: u16,
+        end: u16,
+        address: u16,
+    },
+    Layout(LayoutConflict),
+}
+
+impl Warning {
+    pub fn code(&self) -> WarningCode {
+        match self {
+            Warning::UninitializedExec(_) => WarningCode::UninitializedExec,
+            Warning::Stack(_) => WarningCode::Stack,
+            Warning::SelfModify { .. } => WarningCode::SelfModify,
+            Warning::LoadOverlap { .. } => WarningCode::LoadOverlap,
+            Warning::Layout(_) => WarningCode::Layout,
+        }
+    }
+
+    /// What tells it apart from the others of its code, a break of the stack discipline only counts by its kind
+    fn identity(&self) -> Warning {
+        match *self {
+            Warning::Stack(violation) => Warning::Stack(match violation {
+                StackViolation::StoreOutside { .. } => {
+                    StackViolation::StoreOutside { pc: 0, address: 0 }
+                }
+                StackViolation::Overflow { .. } => StackViolation::Overflow {
+                    pc: 0,
+                    stack_pointer: 0,
+                },
+                StackViolation::Underflow { .. } => StackViolation::Underflow { pc: 0, address: 0 },
+            }),
+            warning => warning,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UninitializedExec(address) => write!(
+                formatter,
+                "executing x{:04X}, nothing was loaded or written there. Is a HALT missing?",
+                address
+            ),
+            Warning::Stack(violation) => write!(formatter, "{}", violation),
+            Warning::SelfModify { writer, target } => write!(
+                formatter,
+                "self-modifying code: the store at x{:04X} modified the instruction at x{:04X}",
+                writer, target
+            ),
+            Warning::LoadOverlap {
+                start,
+                end,
+                address,
+            } => write!(
+                formatter,
+                "the image at x{:04X}-x{:04X} overwrites words another image loaded, from x{:04X} on",
+                start, end, address
+            ),
+            Warning::Layout(conflict) => write!(formatter, "{}", conflict),
+        }
+    }
+}
+
+/// A warning as the JSON summary lists it, with how many times it was emitted
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct WarningSummary {
+    #[serde(rename = "code")]
+    pub code: WarningCode,
+    /// What was printed the first time
+    #[serde(rename = "message")]
+    pub message: String,
+    #[serde(rename = "count")]
+    pub count: u64,
+}
+
+/// The central emitter of the warnings of a machine, see the module documentation
+#[derive(Default)]
+pub struct Warnings {
+    levels: HashMap<WarningCode, WarningLevel>,
+    /// Every warning emitted, in the order of the first time
+    emitted: Vec<WarningSummary>,
+    /// The index in `emitted` of each warning by [`Warning::identity`]
+    seen: HashMap<Warning, usize>,
+}
+
+impl Warnings {
+    pub fn new() -> Warnings {
+        Warnings::default()
+    }
+
+    pub fn set_level(&mut self, code: WarningCode, level: WarningLevel) {
+        self.levels.insert(code, level);
+    }
+
+    pub fn level(&self, code: WarningCode) -> WarningLevel {
+        self.levels.get(&code).copied().unwrap_or_default()
+    }
+
+    /// Print the warning the first time and count it, nothing if its code is allowed. Fails with
+    /// [`RuntimeError::WarningDenied`] if it is denied
+    pub fn emit(&mut self, warning: Warning) -> Result<(), Error> {
+        match self.level(warning.code()) {
+            WarningLevel::Allow => return Ok(()),
+            WarningLevel::Deny => return Err(RuntimeError::WarningDenied(warning).into()),
+            WarningLevel::Warn => {}
+        }
+        match self.seen.get(&warning.identity()) {
+            Some(&index) => self.emitted[index].count += 1,
+            None => {
+                diag!("warning[{}]: {}", warning.code(), warning);
+                self.seen.insert(warning.identity(), self.emitted.len());
+                self.emitted.push(WarningSummary {
+                    code: warning.code(),
+                    message: warning.to_string(),
+                    count: 1,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Every warning emitted with how many times, in the order of the first time
+    pub fn emitted(&self) -> &[WarningSummary] {
+        &self.emitted
+    }
+
+    /// How many warnings of each code were emitted, the codes without any left out
+    pub fn counts(&self) -> Vec<(WarningCode, u64)> {
+        WarningCode::ALL
+            .into_iter()
+            .map(|code| {
+                let count = self
+                    .emitted
+                    .iter()
+                    .filter(|warning| warning.code == code)
+                    .map(|warning| warning.count)
+                    .sum();
+                (code, count)
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    /// The counts per code for the end of a run, empty without any warning:
+    /// ```text
+    /// Warnings:
+    ///   W002 stack discipline: 3 time(s)
+    /// ```
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let counts = self.counts();
+        if counts.is_empty() {
+            return report;
+        }
+        report.push_str("Warnings:\n");
+        for (code, count) in counts {
+            let _ = writeln!(report, "  {} {}: {} time(s)", code, code.title(), count);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_warning_is_printed_once_and_counted() {
+        let mut warnings = Warnings::new();
+        let modified = Warning::SelfModify {
+            writer: 0x3005,
+            target: 0x3002,
+        };
+        for pc in [0x3000, 0x3001, 0x3002] {
+            warnings
+                .emit(Warning::Stack(StackViolation::Overflow {
+                    pc,
+                    stack_pointer: 0x2FFF,
+                }))
+                .unwrap();
+        }
+        warnings.emit(modified).unwrap();
+        warnings.emit(modified).unwrap();
+        warnings
+            .emit(Warning::SelfModify {
+                writer: 0x3006,
+                target: 0x3002,
+            })
+            .unwrap();
+        let emitted: Vec<(WarningCode, u64)> = warnings
+            .emitted()
+            .iter()
+            .map(|warning| (warning.code, warning.count))
+            .collect();
+        assert_eq!(
+            emitted,
+            [
+                (WarningCode::Stack, 3),
+                (WarningCode::SelfModify, 2),
+                (WarningCode::SelfModify, 1)
+            ]
+        );
+        assert_eq!(
+            warnings.report(),
+            "Warnings:\n  W002 stack discipline: 3 time(s)\n  W003 self-modifying code: 3 time(s)\n"
+        );
+    }
+
+    #[test]
+    fn allowed_warnings_are_ignored_and_denied_ones_fail() {
+        let mut warnings = Warnings::new();
+        warnings.set_level(WarningCode::UninitializedExec, WarningLevel::Allow);
+        warnings.set_level(WarningCode::SelfModify, WarningLevel::Deny);
+        warnings.emit(Warning::UninitializedExec(0x3001)).unwrap();
+        assert!(matches!(
+            warnings.emit(Warning::SelfModify {
+                writer: 0x3005,
+                target: 0x3002
+            }),
+            Err(Error::Runtime(RuntimeError::WarningDenied(
+                Warning::SelfModify { .. }
+            )))
+        ));
+        assert!(warnings.emitted().is_empty());
+        assert_eq!(warnings.report(), "");
+    }
+
+    #[test]
+    fn codes_are_read_in_either_case() {
+        assert_eq!(
+            WarningCode::from_code("w003"),
+            Some(WarningCode::SelfModify)
+        );
+        assert_eq!(
+            WarningCode::from_code("W004"),
+            Some(WarningCode::LoadOverlap)
+        );
+        assert_eq!(WarningCode::from_code("W006"), None);
+        assert_eq!(
+            serde_json::to_string(&WarningCode::Stack).unwrap(),
+            r#""W002""#
+        );
+    }
+}
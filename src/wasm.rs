@@ -0,0 +1,146 @@
+//! JavaScript bindings, only built with the `wasm` feature. Build them with
+//! `wasm-pack build --target web -- --no-default-features --features wasm` (or `cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm`
+//! and `wasm-bindgen`).
+//!
+//! A [`WasmVm`] never blocks: the page gives it the keys with [`WasmVm::key_down`] and takes what it printed with
+//! [`WasmVm::take_output`], a program waiting for a key just stops until the next run
+
+use wasm_bindgen::prelude::*;
+
+use crate::console::{KeyboardBuffer, MemoryOutput};
+use crate::file_management::read_bytes_to_memory;
+use crate::{Error, Registers, RuntimeError, State, run_with_budget};
+
+/// Why [`WasmVm::run`] or [`WasmVm::step`] returned
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    Halted,
+    /// It ran all the instructions it was given and can keep going
+    Running,
+    /// It is in GETC or IN without a key, run it again after [`WasmVm::key_down`]
+    WaitingForInput,
+}
+
+/// An LC-3 machine for a web page
+#[wasm_bindgen]
+pub struct WasmVm {
+    state: State,
+    /// Keys typed in the page
+    keys: KeyboardBuffer,
+    output: MemoryOutput,
+}
+
+impl Default for WasmVm {
+    fn default() -> WasmVm {
+        WasmVm::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmVm {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmVm {
+        let (mut state, output) = State::headless();
+        let keys = state.attach_keyboard_buffer();
+        WasmVm {
+            state,
+            keys,
+            output,
+        }
+    }
+
+    /// Load an object image (its origin followed by its words, big endian) like the command line VM does
+    pub fn load_image(&mut self, image: &[u8]) -> Result<(), JsError> {
+        read_bytes_to_memory(image, &mut self.state).map_err(to_js)
+    }
+
+    /// Run at most `count` instructions
+    pub fn step(&mut self, count: u32) -> Result<RunStatus, JsError> {
+        self.run(count as f64)
+    }
+
+    /// Run until the program halts, waits for a key or `max` instructions ran.
+    /// It is a JavaScript number, so a page can give it any budget without a BigInt
+    pub fn run(&mut self, max: f64) -> Result<RunStatus, JsError> {
+        if !self.state.is_running() {
+            return Ok(RunStatus::Halted);
+        }
+        match run_with_budget(&mut self.state, max as u64) {
+            Ok(()) => Ok(RunStatus::Halted),
+            Err(Error::Runtime(RuntimeError::BudgetExhausted(_))) => Ok(RunStatus::Running),
+            Err(Error::Runtime(RuntimeError::Interrupted)) => Ok(RunStatus::WaitingForInput),
+            Err(error) => Err(to_js(error)),
+        }
+    }
+
+    /// A key typed in the page, GETC, IN and the keyboard registers get them in order
+    pub fn key_down(&mut self, key: u8) {
+        self.keys.push_key(key);
+    }
+
+    /// The keyboard of the machine, a handle the page can keep to push keys without going through the VM
+    pub fn keyboard(&self) -> KeyboardBuffer {
+        self.keys.clone()
+    }
+
+    /// What the program printed since the last call, every byte as the character with that code
+    pub fn take_output(&mut self) -> String {
+        self.output.drain_text()
+    }
+
+    /// Like [`WasmVm::take_output`] as the bytes, a `Uint8Array`
+    pub fn drain_output(&mut self) -> Vec<u8> {
+        self.output.drain_output()
+    }
+
+    /// Bytes printed since the last [`WasmVm::take_output`] or [`WasmVm::drain_output`]
+    pub fn output_len(&self) -> usize {
+        self.output.output_len()
+    }
+
+    /// Keep at most `capacity` bytes between takes, `undefined` keeps all of them. The bytes printed past it are
+    /// dropped and counted in [`WasmVm::output_overflowed`]
+    pub fn set_output_capacity(&mut self, capacity: Option<u32>) {
+        self.output
+            .set_capacity(capacity.map(|capacity| capacity as usize));
+    }
+
+    /// Bytes dropped because the output was full, a JavaScript number like the budget of [`WasmVm::run`]
+    pub fn output_overflowed(&self) -> f64 {
+        self.output.overflowed() as f64
+    }
+
+    /// R0 to R7, the PC and the condition flags
+    pub fn read_registers(&self) -> Vec<u16> {
+        [
+            Registers::R0,
+            Registers::R1,
+            Registers::R2,
+            Registers::R3,
+            Registers::R4,
+            Registers::R5,
+            Registers::R6,
+            Registers::R7,
+            Registers::Pc,
+            Registers::Flags,
+        ]
+        .map(|register| self.state.register_read(register))
+        .to_vec()
+    }
+
+    /// `length` words starting at `address`, wrapping around after xFFFF. The device registers aren't polled, see
+    /// [`State::peek`]
+    pub fn read_memory(&self, address: u16, length: u32) -> Vec<u16> {
+        (0..length)
+            .map(|offset| {
+                let address = address.wrapping_add(offset as u16);
+                self.state.peek(address as usize)
+            })
+            .collect()
+    }
+}
+
+fn to_js(error: Error) -> JsError {
+    JsError::new(&error.to_string())
+}
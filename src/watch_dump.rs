@@ -0,0 +1,147 @@
+//! Snapshots of a range of memory every so many instructions, what `--watch-dump` appends to a file. A row per
+//! snapshot, labelled with the instructions that ran before it, in hexadecimal like the DBG trap prints memory:
+//! ```text
+//! 100000: [x4000] x0001 x0002 x0000 x0000
+//! 200000: [x4000] x0002 x0004 x0000 x0000
+//! ```
+//! or as CSV, a column per address with the words in decimal:
+//! ```text
+//! instructions,x4000,x4001,x4002,x4003
+//! 100000,1,2,0,0
+//! ```
+//! The run loops stop at the instruction a snapshot is due like they stop for the devices (see
+//! [`crate::State::set_watch_dump`]), nothing is checked between the instructions. Counting instructions instead of
+//! the time makes the rows the same run after run
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::{Error, State};
+
+/// How [`WatchDump`] writes its rows, see the module documentation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WatchDumpFormat {
+    #[default]
+    Hex,
+    Csv,
+}
+
+/// Writes the words of a range of memory every [`WatchDump::every`] instructions, see the module documentation
+pub struct WatchDump {
+    range: RangeInclusive<u16>,
+    /// Instructions between the rows, at least one
+    pub every: u64,
+    format: WatchDumpFormat,
+    output: Box<dyn Write + Send>,
+    /// The instruction count the next row is written at
+    next: u64,
+    /// The first write that failed, the program goes on and [`crate::State::finish_watch_dump`] reports it
+    error: Option<io::Error>,
+}
+
+impl WatchDump {
+    /// Rows of the words in `range` every `every` instructions to `output`, without the CSV header (see
+    /// [`WatchDump::csv_header`])
+    pub fn new(
+        range: RangeInclusive<u16>,
+        every: u64,
+        format: WatchDumpFormat,
+        output: Box<dyn Write + Send>,
+    ) -> WatchDump {
+        let every = every.max(1);
+        WatchDump {
+            range,
+            every,
+            format,
+            output,
+            next: every,
+            error: None,
+        }
+    }
+
+    /// The first line of a CSV file of rows of `range`
+    pub fn csv_header(range: RangeInclusive<u16>) -> String {
+        let mut header = "instructions".to_string();
+        for address in range {
+            let _ = write!(header, ",x{:04X}", address);
+        }
+        header.push('\n');
+        header
+    }
+
+    /// The instruction count the next row is due at
+    #[inline]
+    pub(crate) fn due(&self) -> u64 {
+        self.next
+    }
+
+    /// Write the row of `instructions`, the words read from the range, and schedule the next one
+    fn row(&mut self, instructions: u64, words: &[u16]) {
+        let mut line = match self.format {
+            WatchDumpFormat::Hex => format!("{}: [x{:04X}]", instructions, self.range.start()),
+            WatchDumpFormat::Csv => instructions.to_string(),
+        };
+        for word in words {
+            let _ = match self.format {
+                WatchDumpFormat::Hex => write!(line, " x{:04X}", word),
+                WatchDumpFormat::Csv => write!(line, ",{}", word),
+            };
+        }
+        line.push('\n');
+        if let Err(error) = self.output.write_all(line.as_bytes())
+            && self.error.is_none()
+        {
+            self.error = Some(error);
+        }
+        self.next = instructions.saturating_add(self.every);
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error.into());
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+impl State {
+    /// Write a row of `dump` whenever its instructions ran, the first once [`WatchDump::every`] ran from now
+    pub fn set_watch_dump(&mut self, dump: Option<WatchDump>) {
+        self.watch_dump = dump.map(|mut dump| {
+            dump.next = self.executed.saturating_add(dump.every);
+            Box::new(dump)
+        });
+        self.schedule_ticks();
+    }
+
+    /// Stop dumping and flush the rows, with the first error writing them if there was one
+    pub fn finish_watch_dump(&mut self) -> Result<(), Error> {
+        let finished = match self.watch_dump.take() {
+            Some(dump) => dump.finish(),
+            None => Ok(()),
+        };
+        self.schedule_ticks();
+        finished
+    }
+
+    /// Write the row of the watch dump if it is due, memory read without going through the devices
+    pub(crate) fn dump_watched_if_due(&mut self) {
+        let executed = self.executed;
+        let Some(dump) = self.watch_dump.as_deref() else {
+            return;
+        };
+        if executed < dump.due() {
+            return;
+        }
+        let words: Vec<u16> = dump
+            .range
+            .clone()
+            .map(|address| self.peek(address as usize))
+            .collect();
+        if let Some(dump) = self.watch_dump.as_deref_mut() {
+            dump.row(executed, &words);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Every word the program stored to, what `--report-writes` lists at exit. Unlike a trace it is a line per address,
+//! however many times it was written, with the last value, how many stores and the last instruction storing there:
+//! ```text
+//! Memory written at 2 address(es):
+//!   x4000=x0010 2 time(s), last by x300E
+//!   xFE06=x002A 1 time(s), last by x3012 (display)
+//! ```
+//! Graders check the data structure a program built with it. `--report-writes-json` writes it for scripts instead:
+//! ```json
+//! {
+//!   "x4000": { "value": 16, "writes": 2, "last_pc": 12302 },
+//!   "xFE06": { "value": 42, "writes": 1, "last_pc": 12306, "device": "display" }
+//! }
+//! ```
+//! The registers of a device are named after it (see [`crate::device::Device::name`]), and the stack can be left out
+//! with [`WriteLog::excluding`] so the words pushed and popped don't drown the rest
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use serde::Serialize;
+
+use crate::{DEVICE_PAGE, MemoryMappedRegisters, Registers, State, bank};
+
+/// The stores to an address, see the module documentation
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct WrittenWord {
+    /// The word of the last store, what a device register holds may differ
+    #[serde(rename = "value")]
+    pub value: u16,
+    #[serde(rename = "writes")]
+    pub writes: u64,
+    #[serde(rename = "last_pc")]
+    pub last_pc: u16,
+    /// Only there for the registers of a device
+    #[serde(rename = "device", skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+/// The stores of the program by address, see [`crate::State::set_write_log`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteLog {
+    written: HashMap<u16, WrittenWord>,
+    /// The stack, if it is left out
+    excluded: Option<RangeInclusive<u16>>,
+}
+
+impl WriteLog {
+    pub fn new() -> WriteLog {
+        WriteLog::default()
+    }
+
+    /// Leave the stores to `range` out, like the stack
+    pub fn excluding(mut self, range: RangeInclusive<u16>) -> WriteLog {
+        self.excluded = Some(range);
+        self
+    }
+
+    /// The addresses written with their stores, by address
+    pub fn written(&self) -> BTreeMap<u16, &WrittenWord> {
+        self.written
+            .iter()
+            .map(|(address, word)| (*address, word))
+            .collect()
+    }
+
+    /// [`WriteLog::written`] for `--report-writes-json`, the addresses written like `x4000`
+    pub fn to_json(&self) -> String {
+        let written: BTreeMap<String, &WrittenWord> = self
+            .written()
+            .into_iter()
+            .map(|(address, word)| (format!("x{:04X}", address), word))
+            .collect();
+        serde_json::to_string_pretty(&written).unwrap() // Numbers and strings in maps with string keys, it can't fail
+    }
+
+    fn excludes(&self, address: u16) -> bool {
+        self.excluded
+            .as_ref()
+            .is_some_and(|range| range.contains(&address))
+    }
+}
+
+/// The table of `--report-writes`, nothing without any store
+impl fmt::Display for WriteLog {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if self.written.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            formatter,
+            "Memory written at {} address(es):",
+            self.written.len()
+        )?;
+        for (address, word) in self.written() {
+            write!(
+                formatter,
+                "  x{:04X}=x{:04X} {} time(s), last by x{:04X}",
+                address, word.value, word.writes, word.last_pc
+            )?;
+            if let Some(device) = &word.device {
+                write!(formatter, " ({})", device)?;
+            }
+            writeln!(formatter)?;
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Log every store of the program by address, see [`WriteLog`]. `None` stops logging
+    pub fn set_write_log(&mut self, log: Option<WriteLog>) {
+        self.write_log = log.map(Box::new);
+    }
+
+    pub fn write_log(&self) -> Option<&WriteLog> {
+        self.write_log.as_deref()
+    }
+
+    /// The program is storing `value` to `address`, the instruction doing it was just fetched
+    pub(crate) fn log_write(&mut self, address: u16, value: u16) {
+        let last_pc = self.registers[Registers::Pc].wrapping_sub(1);
+        let Some(log) = self.write_log.as_deref() else {
+            return;
+        };
+        if log.excludes(address) {
+            return;
+        }
+        // The device is only looked up for the first store
+        let device = match log.written.contains_key(&address) {
+            false => self.device_name(address),
+            true => None,
+        };
+        let Some(log) = self.write_log.as_deref_mut() else {
+            return;
+        };
+        let word = log.written.entry(address).or_insert(WrittenWord {
+            value,
+            writes: 0,
+            last_pc,
+            device,
+        });
+        word.value = value;
+        word.writes += 1;
+        word.last_pc = last_pc;
+    }
+
+    /// The device with a register at `address`, built in or added with [`State::add_device`]
+    fn device_name(&self, address: u16) -> Option<String> {
+        if (address as usize) < DEVICE_PAGE {
+            return None;
+        }
+        let builtin = match address {
+            _ if address == MemoryMappedRegisters::Kbsr as u16
+                || address == MemoryMappedRegisters::Kbdr as u16 =>
+            {
+                Some("keyboard")
+            }
+            _ if address == MemoryMappedRegisters::Dsr as u16
+                || address == MemoryMappedRegisters::Ddr as u16 =>
+            {
+                Some("display")
+            }
+            _ if address == MemoryMappedRegisters::Mcr as u16 => Some("machine control"),
+            bank::BSR if self.banks.is_some() => Some("banks"),
+            _ => None,
+        };
+        builtin.map(str::to_string).or_else(|| {
+            self.devices
+                .iter()
+                .find(|mapped| mapped.range.contains(&address))
+                .map(|mapped| mapped.device.name().to_string())
+        })
+    }
+}
@@ -0,0 +1,89 @@
+//! `asm --run` assembling a source and running it straight from memory
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// A directory of its own with a copy of the hello fixture
+fn hello_source(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("lc3-asm-run-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("hello.asm");
+    fs::copy(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/hello.asm"),
+        &source,
+    )
+    .unwrap();
+    source
+}
+
+fn assemble_and_run(source: &PathBuf, options: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("asm")
+        .arg(source)
+        .args(options)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn the_program_runs_without_leaving_an_object_file() {
+    let source = hello_source("plain");
+    let output = assemble_and_run(&source, &["--run", "--", "--stdin-file", "/dev/null"]);
+    let files: Vec<_> = fs::read_dir(source.parent().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    let _ = fs::remove_dir_all(source.parent().unwrap());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello, World!\nHALT"
+    );
+    assert_eq!(files, ["hello.asm"]);
+}
+
+#[test]
+fn the_object_file_is_written_with_an_output_path() {
+    let source = hello_source("output");
+    let object = source.with_file_name("out.obj");
+    let output = assemble_and_run(
+        &source,
+        &[
+            "-o",
+            object.to_str().unwrap(),
+            "--run",
+            "--",
+            "--stdin-file",
+            "/dev/null",
+        ],
+    );
+    let written = object.exists() && object.with_extension("sym").exists();
+    let _ = fs::remove_dir_all(source.parent().unwrap());
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello, World!\nHALT"
+    );
+    assert!(written);
+}
+
+#[test]
+fn an_assembly_error_stops_before_running() {
+    let source = hello_source("error");
+    fs::write(&source, ".ORIG x3000\nADD R0, R9, #1\nHALT\n.END\n").unwrap();
+    let output = assemble_and_run(&source, &["--run", "--", "--stdin-file", "/dev/null"]);
+    let _ = fs::remove_dir_all(source.parent().unwrap());
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("hello.asm:2"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,141 @@
+//! Postconditions checked by the VM itself, the way an autograder uses it
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+use lc3::assembler;
+
+#[test]
+fn the_exit_code_is_the_number_of_failed_assertions() {
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+        AND R2, R2, #0
+        ADD R2, R2, #15
+        ADD R2, R2, R2      ; 30
+        LD R1, TARGET
+        STR R2, R1, #0
+        HALT
+TARGET  .FILL x4000
+        .END",
+    )
+    .unwrap();
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("lc3-assertions-{}.obj", id));
+    let keys = directory.join(format!("lc3-assertions-{}.keys", id));
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--assert-reg", "R2=30"])
+        .args(["--assert-mem", "x4000:x4001=x1E,x1"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", errors);
+    assert!(errors.contains("PASS R2=x001E"), "{}", errors);
+    assert!(
+        errors.contains("FAIL x4000:x4001=x001E,x0001 (found x001E,x0000)"),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("1 assertion(s) failed"), "{}", errors);
+}
+
+#[test]
+fn addresses_can_be_labels_of_the_sym_file() {
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+        HALT
+TARGET  .FILL x4000
+        .END",
+    )
+    .unwrap();
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("lc3-assertions-labels-{}.obj", id));
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    fs::write(image.with_extension("sym"), program.symbol_table()).unwrap();
+    let run = |assertion: &str| {
+        Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+            .arg(&image)
+            .args(["--stdin-file", "/dev/null"])
+            .args(["--assert-mem", assertion])
+            .output()
+            .unwrap()
+    };
+    let labelled = run("TARGET-1:TARGET=xF025,x4000");
+    let misspelled = run("TARGTE=x4000");
+    let _ = fs::remove_file(image.with_extension("sym"));
+    let _ = fs::remove_file(image);
+    let errors = String::from_utf8_lossy(&labelled.stderr);
+    assert!(labelled.status.success(), "{}", errors);
+    assert!(
+        errors.contains("PASS x3000:x3001=xF025,x4000"),
+        "{}",
+        errors
+    );
+    let errors = String::from_utf8_lossy(&misspelled.stderr);
+    assert_eq!(misspelled.status.code(), Some(1), "{}", errors);
+    assert!(
+        errors.contains("Bad address: unknown symbol `TARGTE`, did you mean `TARGET`?"),
+        "{}",
+        errors
+    );
+}
+
+#[test]
+fn a_range_can_be_in_a_bank() {
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #7
+        STI R1, WINDOW
+        AND R1, R1, #0
+        ADD R1, R1, #1
+        STI R1, BSR         ; bank 1 in the window
+        ADD R1, R1, #8
+        STI R1, WINDOW
+        HALT
+WINDOW  .FILL x9000
+BSR     .FILL xFE14
+        .END",
+    )
+    .unwrap();
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("lc3-assertions-banks-{}.obj", id));
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    let run = |banks: &str, assertions: &[&str]| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"));
+        command
+            .arg(&image)
+            .args(["--stdin-file", "/dev/null", "--banks", banks]);
+        for assertion in assertions {
+            command.args(["--assert-mem", assertion]);
+        }
+        command.output().unwrap()
+    };
+    let banked = run("2", &["0:x9000:x9000=7", "1:x9000:x9000=9", "x9000=9"]);
+    let missing = run("2", &["2:x9000:x9000=0"]);
+    let _ = fs::remove_file(image);
+    let errors = String::from_utf8_lossy(&banked.stderr);
+    assert!(banked.status.success(), "{}", errors);
+    assert!(errors.contains("PASS 0:x9000:x9000=x0007"), "{}", errors);
+    assert!(errors.contains("PASS 1:x9000:x9000=x0009"), "{}", errors);
+    let errors = String::from_utf8_lossy(&missing.stderr);
+    assert!(!missing.status.success(), "{}", errors);
+    assert!(
+        errors.contains("--assert-mem in bank 2, there are 2 bank(s)"),
+        "{}",
+        errors
+    );
+}
@@ -0,0 +1,75 @@
+//! Machines running as tokio tasks
+#![cfg(feature = "async")]
+
+use lc3::asynchronous::AsyncVm;
+use lc3::{Registers, State, file_management};
+use tokio::sync::mpsc::{Receiver, channel};
+use tokio::task::{JoinSet, yield_now};
+
+fn program(image: &str) -> State {
+    let (mut state, _) = State::headless();
+    let path = format!("{}/tests/programs/{}", env!("CARGO_MANIFEST_DIR"), image);
+    file_management::read_file_to_memory(&path, &mut state).unwrap();
+    state
+}
+
+/// Everything left in the receiver
+fn received(output: &mut Receiver<u8>) -> String {
+    let mut text = String::new();
+    while let Ok(byte) = output.try_recv() {
+        text.push(byte as char);
+    }
+    text
+}
+
+#[tokio::test]
+async fn runs_a_program_waiting_for_keys() {
+    let (key_sender, keys) = channel(8);
+    let (output_sender, mut output) = channel(64);
+    let mut vm = AsyncVm::new(program("echo.obj"), keys, output_sender);
+    let typist = tokio::spawn(async move {
+        for key in b"hi." {
+            yield_now().await;
+            key_sender.send(*key).await.unwrap();
+        }
+    });
+    vm.run_async(16).await.unwrap();
+    typist.await.unwrap();
+    assert_eq!(received(&mut output), "HI\nbye\nHALT");
+    assert_eq!(vm.state().register_read(Registers::R5), 3);
+}
+
+#[tokio::test]
+async fn a_cancelled_run_can_be_resumed() {
+    let (_key_sender, keys) = channel(1);
+    let (output_sender, mut output) = channel(64);
+    let mut vm = AsyncVm::new(program("count.obj"), keys, output_sender);
+    // The run is dropped after a few slices, halfway through the loop
+    tokio::select! {
+        _ = vm.run_async(5) => panic!("the program shouldn't have halted yet"),
+        _ = async { for _ in 0..4 { yield_now().await } } => {}
+    }
+    assert!(vm.state().is_running());
+    let printed = received(&mut output);
+    assert!(!printed.is_empty() && printed.len() < 10, "{}", printed);
+    vm.run_async(5).await.unwrap();
+    assert_eq!(printed + &received(&mut output), "0123456789\nHALT");
+    assert_eq!(vm.state().peek(0x300F), 55);
+}
+
+#[tokio::test]
+async fn many_machines_share_a_thread() {
+    let mut machines = JoinSet::new();
+    for _ in 0..24 {
+        let (_key_sender, keys) = channel(1);
+        let (output_sender, mut output) = channel(64);
+        machines.spawn(async move {
+            let mut vm = AsyncVm::new(program("count.obj"), keys, output_sender);
+            vm.run_async(3).await.unwrap();
+            received(&mut output)
+        });
+    }
+    for output in machines.join_all().await {
+        assert_eq!(output, "0123456789\nHALT");
+    }
+}
@@ -0,0 +1,78 @@
+//! The files of a run only get their name once they are whole, see `lc3::output_file`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+const SPIN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/spin.obj");
+
+fn directory(name: &str) -> PathBuf {
+    let directory =
+        env::temp_dir().join(format!("lc3-atomic-output-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn files(directory: &Path) -> Vec<String> {
+    let mut files: Vec<String> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+fn spin_with_dump(dump: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"));
+    command
+        .arg(SPIN)
+        .args(["--watch-dump", "x3000:x3000@1", "--watch-dump-out"])
+        .arg(dump)
+        .args(["--stdin-file", "/dev/null"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+#[test]
+fn a_run_killed_halfway_leaves_no_dump_under_its_name() {
+    let directory = directory("killed");
+    let dump = directory.join("dump.txt");
+    let mut child = spin_with_dump(&dump).spawn().unwrap();
+    // Killed once it is writing the temporary file
+    let start = Instant::now();
+    while files(&directory).is_empty() && start.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(20));
+    }
+    thread::sleep(Duration::from_millis(200));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let left = files(&directory);
+    let _ = fs::remove_dir_all(&directory);
+    assert!(!dump.exists());
+    assert_eq!(left.len(), 1, "{:?}", left);
+    assert!(
+        left[0].starts_with(".dump.txt.") && left[0].ends_with(".tmp"),
+        "{:?}",
+        left
+    );
+}
+
+#[test]
+fn a_run_that_ends_commits_its_dump() {
+    let directory = directory("ended");
+    let dump = directory.join("dump.txt");
+    let status = spin_with_dump(&dump)
+        .args(["--max-steps", "100"])
+        .status()
+        .unwrap();
+    let left = files(&directory);
+    let contents = fs::read_to_string(&dump).unwrap_or_default();
+    let _ = fs::remove_dir_all(&directory);
+    assert!(!status.success());
+    assert_eq!(left, ["dump.txt"]);
+    assert!(!contents.is_empty());
+}
@@ -0,0 +1,185 @@
+//! A jobs file run by `batch`, where a failed job doesn't stop the ones after it
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+use lc3::assembler;
+
+fn write_image(directory: &std::path::Path, name: &str, source: &str) {
+    let program = assembler::assemble(source).unwrap();
+    fs::write(directory.join(name), program.to_object_bytes()).unwrap();
+}
+
+#[test]
+fn every_job_runs_in_a_fresh_machine_after_a_failure() {
+    let directory = env::temp_dir().join(format!("lc3-batch-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    write_image(
+        &directory,
+        "double.obj",
+        "
+        .ORIG x3000
+        AND R2, R2, #0
+        ADD R2, R2, #15
+        ADD R2, R2, R2      ; 30
+        ST R2, RESULT
+        GETC
+        OUT
+        HALT
+RESULT  .BLKW 1
+        .END",
+    );
+    // RTI in user mode
+    write_image(
+        &directory,
+        "crash.obj",
+        "
+        .ORIG x3000
+        ADD R2, R2, #1
+        RTI
+        .END",
+    );
+    fs::write(directory.join("double.keys"), b"a").unwrap();
+    fs::write(
+        directory.join("jobs.toml"),
+        r#"
+        [[job]]
+        name = "first"
+        image = "double.obj"
+        stdin = "double.keys"
+        assert_reg = ["R2=30"]
+        assert_mem = ["x3007=x1E"]
+
+        [[job]]
+        image = "crash.obj"
+        max_steps = 100
+        assert_reg = ["R2=1"]
+
+        [[job]]
+        name = "again"
+        image = "double.obj"
+        stdin = "double.keys"
+        assert_reg = ["R2=30", "R0=x61"]
+        "#,
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("batch")
+        .arg(directory.join("jobs.toml"))
+        .args(["--jobs", "2"])
+        .output()
+        .unwrap();
+    let report = fs::read_to_string(directory.join("jobs.report.json"));
+    let _ = fs::remove_dir_all(&directory);
+    let table = String::from_utf8_lossy(&output.stdout);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}{}", table, errors);
+    assert!(errors.contains("1 job(s) failed"), "{}", errors);
+    let lines: Vec<Vec<&str>> = table
+        .lines()
+        .skip(1)
+        .take(3)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines[0][..3], ["first", "PASS", "halted"], "{}", table);
+    assert_eq!(lines[1][..3], ["crash", "FAIL", "error"], "{}", table);
+    assert_eq!(lines[2][..3], ["again", "PASS", "halted"], "{}", table);
+    assert!(table.contains("3 job(s), 2 passed, 1 failed"), "{}", table);
+
+    let report: serde_json::Value = serde_json::from_str(&report.unwrap()).unwrap();
+    assert_eq!(report["passed"], 2);
+    assert_eq!(report["failed"], 1);
+    let jobs = report["jobs"].as_array().unwrap();
+    assert_eq!(jobs[1]["outcome"], "error");
+    assert_eq!(jobs[1]["assertions"], serde_json::json!([]));
+    // Each job printed its own key, the output of the first isn't in the third
+    assert_eq!(jobs[0]["output"], "aHALT");
+    assert_eq!(jobs[2]["output"], "aHALT");
+    assert_eq!(jobs[2]["assertions"][1]["passed"], true);
+}
+
+/// The fields of a line of CSV, a quoted one unquoted
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut characters = line.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' if quoted && characters.peek() == Some(&'"') => {
+                characters.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            character => fields.last_mut().unwrap().push(character),
+        }
+    }
+    fields
+}
+
+#[test]
+fn the_stats_of_every_job_go_to_one_csv_file() {
+    let directory = env::temp_dir().join(format!("lc3-batch-stats-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    // 2 ANDs, 4 ADDs and 3 BRs, 2 of them taken, then 2 traps
+    write_image(
+        &directory,
+        "count, \"twice\".obj",
+        "
+        .ORIG x3000
+        AND R0, R0, #0
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        OUT
+        HALT
+        .END",
+    );
+    fs::write(
+        directory.join("jobs.toml"),
+        r#"
+        [[job]]
+        name = "first"
+        image = 'count, "twice".obj'
+
+        [[job]]
+        name = "second"
+        image = 'count, "twice".obj'
+        "#,
+    )
+    .unwrap();
+    let stats = directory.join("stats.csv");
+    for _ in 0..2 {
+        let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+            .arg("batch")
+            .arg(directory.join("jobs.toml"))
+            .arg("--stats-out")
+            .arg(&stats)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let csv = fs::read_to_string(&stats).unwrap();
+    let _ = fs::remove_dir_all(&directory);
+    let rows: Vec<Vec<String>> = csv.lines().map(csv_fields).collect();
+    // The header only once, then a row per job of both batches
+    assert_eq!(rows.len(), 5, "{}", csv);
+    let header = &rows[0];
+    for row in &rows[1..] {
+        assert_eq!(row.len(), header.len(), "{}", csv);
+        let column = |name: &str| &row[header.iter().position(|column| column == name).unwrap()];
+        assert!(column("image").ends_with("count, \"twice\".obj"), "{}", csv);
+        assert_eq!(column("instructions"), "11");
+        assert_eq!(column("op_add"), "4");
+        assert_eq!(column("op_br"), "3");
+        assert_eq!(column("trap_out"), "1");
+        assert_eq!(column("trap_halt"), "1");
+        assert_eq!(column("branch_taken_ratio"), "0.6667");
+    }
+}
@@ -0,0 +1,39 @@
+//! `--no-color` leaves the escape sequences out of what the VM says on stderr, see `lc3::diag`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+/// Counts R0 down from 5, then OUT and HALT
+const COUNTDOWN: [u16; 7] = [0x3000, 0x5020, 0x1025, 0x103F, 0x03FE, 0xF021, 0xF025];
+
+#[test]
+fn no_color_writes_no_escape_bytes() {
+    let directory = env::temp_dir();
+    let image = directory.join(format!("lc3-color-{}.obj", std::process::id()));
+    let keys = directory.join(format!("lc3-color-{}.keys", std::process::id()));
+    let bytes: Vec<u8> = COUNTDOWN
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&image, bytes).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("--no-color")
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--trace-format", "ref", "--time", "--assert-reg", "R0=0"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    // The trace, the assertion and the banner
+    assert!(errors.lines().count() > 14, "{}", errors);
+    assert!(errors.contains("time: "), "{}", errors);
+    assert!(!output.stderr.contains(&0x1b), "{}", errors);
+    // What the program printed is left alone
+    assert_eq!(output.stdout, b"\0HALT");
+}
@@ -0,0 +1,62 @@
+//! `--compare` runs two images in lockstep and stops where they differ, see `lc3::compare`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// Counts R0 down from 5, then OUT and HALT
+const COUNTDOWN: [u16; 7] = [0x3000, 0x5020, 0x1025, 0x103F, 0x03FE, 0xF021, 0xF025];
+
+fn write_image(name: &str, words: &[u16]) -> std::path::PathBuf {
+    let path = env::temp_dir().join(format!("lc3-compare-{}-{}.obj", name, std::process::id()));
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn run(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(["--no-color"])
+        .args(args)
+        .args(["--stdin-file", "/dev/null"])
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn a_bit_of_difference_stops_both_machines() {
+    let ours = write_image("ours", &COUNTDOWN);
+    let mut counting_from_four = COUNTDOWN;
+    // ADD R0, R0, #4
+    counting_from_four[2] = 0x1024;
+    let theirs = write_image("theirs", &counting_from_four);
+    let output = run(&[ours.as_os_str(), "--compare".as_ref(), theirs.as_os_str()]);
+    let _ = fs::remove_file(&ours);
+    let _ = fs::remove_file(theirs);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", errors);
+    assert!(
+        errors.contains("The machines diverge at step 2"),
+        "{}",
+        errors
+    );
+    assert!(errors.contains("x3001 x1025"), "{}", errors);
+    assert!(errors.contains("x3001 x1024"), "{}", errors);
+}
+
+#[test]
+fn the_same_image_never_diverges_with_the_decode_cache() {
+    let image = write_image("self", &COUNTDOWN);
+    let output = run(&[
+        image.as_os_str(),
+        "--compare-self".as_ref(),
+        "--fast".as_ref(),
+    ]);
+    let _ = fs::remove_file(image);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"\0HALT");
+}
@@ -0,0 +1,75 @@
+//! The DBG trap (x28) prints the registers to stderr, leaving the output of the program and its state alone
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+use lc3::assembler;
+
+fn run(flags: &[&str]) -> Output {
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+        LEA R0, WORDS
+        ADD R1, R1, #7
+        DBG
+        ADD R2, R0, #0      ; R0 still points to WORDS
+        LD R0, BANG
+        OUT
+        AND R0, R0, #0
+        DBG
+        HALT
+BANG    .FILL '!'
+WORDS   .FILL x1
+        .FILL x2
+        .FILL x3
+        .END",
+    )
+    .unwrap();
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("lc3-dbg-{}-{}.obj", id, flags.len()));
+    let keys = directory.join(format!("lc3-dbg-{}-{}.keys", id, flags.len()));
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--assert-reg", "R2=x300A", "--assert-reg", "R1=7"])
+        .args(flags)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    output
+}
+
+#[test]
+fn dbg_prints_the_registers_and_the_words_at_r0() {
+    let output = run(&[]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "!HALT");
+    let lines: Vec<&str> = errors
+        .lines()
+        .filter(|line| line.starts_with("DBG"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "DBG x3002: R0=x300A R1=x0007 R2=x0000 R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x0000 \
+             PC=x3003 COND=P [x300A] x0001 x0002 x0003 x0000 x0000 x0000 x0000 x0000",
+            "DBG x3007: R0=x0000 R1=x0007 R2=x300A R3=x0000 R4=x0000 R5=x0000 R6=x0000 R7=x0000 \
+             PC=x3008 COND=Z",
+        ]
+    );
+}
+
+#[test]
+fn quiet_silences_dbg() {
+    let output = run(&["--quiet"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(!errors.contains("DBG"), "{}", errors);
+}
@@ -0,0 +1,92 @@
+//! Runs under `--deterministic` giving the same trace, output and summary every time
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+const TIMED_ECHO: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/timed_echo.obj");
+
+fn run(options: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(TIMED_ECHO)
+        .args(options)
+        .output()
+        .unwrap()
+}
+
+/// The output, the trace and the summary of a run of [`TIMED_ECHO`] typing `ab.`
+fn deterministic_run(name: &str) -> (Vec<u8>, Vec<u8>, String) {
+    let keys = env::temp_dir().join(format!(
+        "lc3-deterministic-{}-{}.keys",
+        name,
+        std::process::id()
+    ));
+    let summary = env::temp_dir().join(format!(
+        "lc3-deterministic-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    fs::write(&keys, b"ab.").unwrap();
+    let output = run(&[
+        "--deterministic",
+        "--stdin-file",
+        keys.to_str().unwrap(),
+        "--trace-format",
+        "jsonl",
+        "--json-summary",
+        summary.to_str().unwrap(),
+    ]);
+    let written = fs::read_to_string(&summary).unwrap();
+    let _ = fs::remove_file(keys);
+    let _ = fs::remove_file(summary);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (output.stdout, output.stderr, written)
+}
+
+#[test]
+fn two_deterministic_runs_are_identical() {
+    let first = deterministic_run("first");
+    let second = deterministic_run("second");
+    assert_eq!(first.0, b"abHALT");
+    assert_eq!(first, second);
+    // The time trap read the virtual time after the second sleep of 7 milliseconds
+    let trace = String::from_utf8(first.1).unwrap();
+    assert!(
+        trace.contains(r#""ir":61492,"regs":{"R0":14}"#),
+        "{}",
+        trace
+    );
+    assert!(!first.2.contains("wall_time_ms"), "{}", first.2);
+}
+
+#[test]
+fn a_nondeterministic_option_is_refused() {
+    let output = run(&["--deterministic"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(
+            "--deterministic with the keyboard (neither --stdin-file nor --replay), it takes the keys somebody types"
+        ),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = run(&[
+        "--deterministic",
+        "--stdin-file",
+        "/dev/null",
+        "--timeout",
+        "5",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains(
+            "--deterministic with --timeout, it stops the program after a time of the host"
+        ),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,95 @@
+//! `--device` with the sample plugin of `examples/counter_device.rs`: it is built, loaded by the command line VM and used by a program
+#![cfg(all(feature = "plugins", feature = "cli", any(unix, windows)))]
+
+use std::env::{self, consts};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use lc3::assembler;
+
+/// Build the example in its own target directory, the test runs from <target>/<profile>/deps
+fn build_plugin() -> PathBuf {
+    let target = env::current_exe()
+        .unwrap()
+        .ancestors()
+        .nth(3)
+        .unwrap()
+        .join("device-plugin");
+    let built = Command::new(env!("CARGO"))
+        .args(["build", "--example", "counter_device", "--target-dir"])
+        .arg(&target)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(built.success());
+    let plugin = target.join("debug/examples").join(format!(
+        "{}counter_device{}",
+        consts::DLL_PREFIX,
+        consts::DLL_SUFFIX
+    ));
+    assert!(plugin.exists(), "{} wasn't built", plugin.display());
+    plugin
+}
+
+#[test]
+fn a_program_uses_the_sample_plugin() {
+    let plugin = build_plugin();
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+        LD R1, START
+        STI R1, COUNTER     ; The counter starts at 40
+        LDI R2, COUNTER
+        LDI R3, COUNTER
+        HALT
+START   .FILL #40
+COUNTER .FILL xFE10
+        .END",
+    )
+    .unwrap();
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("device-plugin-{}.obj", id));
+    let keys = directory.join(format!("device-plugin-{}.keys", id));
+    let summary = directory.join(format!("device-plugin-{}.json", id));
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--device")
+        .arg(&plugin)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .arg("--json-summary")
+        .arg(&summary)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&summary).unwrap()).unwrap();
+    assert_eq!(written["registers"]["R2"], 40);
+    assert_eq!(written["registers"]["R3"], 41);
+    for path in [image, keys, summary] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn a_library_that_isnt_a_plugin_is_rejected() {
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(format!(
+            "{}/tests/programs/hello.obj",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .arg("--device")
+        .arg("./no-such-plugin")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Bad device plugin"));
+}
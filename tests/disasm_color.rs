@@ -0,0 +1,42 @@
+//! `disasm --color`: a colored listing on request, the plain source that assembles back otherwise
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+
+use lc3::disassembler::{disassemble_program, reachable_code};
+use lc3::file_management::read_image;
+
+const HELLO: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/hello.obj");
+
+fn disassemble(flags: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(["disasm", HELLO])
+        .args(flags)
+        .env_remove("NO_COLOR")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn piped_or_never_it_is_the_plain_source() {
+    let (origin, words) = read_image(&HELLO.to_string()).unwrap();
+    let source = disassemble_program(origin, &words, &reachable_code(origin, &words, &[origin]));
+    assert_eq!(disassemble(&[]), source);
+    assert_eq!(disassemble(&["--color", "auto"]), source);
+    assert_eq!(disassemble(&["--color", "never"]), source);
+    assert!(!source.contains('\x1b'));
+}
+
+#[test]
+fn always_colors_the_listing() {
+    let listing = disassemble(&["--color", "always"]);
+    assert!(listing.starts_with("x3000  "), "{}", listing);
+    assert!(listing.contains("\x1b[35mPUTS\x1b[0m"), "{}", listing);
+    assert!(listing.contains("|H|"), "{}", listing);
+}
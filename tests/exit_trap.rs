@@ -0,0 +1,39 @@
+//! The EXIT trap (x27) gives the shell the exit code of the program
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+#[test]
+fn the_low_byte_of_r0_is_the_exit_status() {
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let keys = directory.join(format!("lc3-exit-{}.keys", id));
+    let summary = directory.join(format!("lc3-exit-{}.json", id));
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/exit.obj"
+        ))
+        .arg("--stdin-file")
+        .arg(&keys)
+        .arg("--json-summary")
+        .arg(&summary)
+        .output()
+        .unwrap();
+    let written = fs::read_to_string(&summary);
+    let _ = fs::remove_file(keys);
+    let _ = fs::remove_file(summary);
+    assert_eq!(
+        output.status.code(),
+        Some(42),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Flushed, and no HALT after it
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "exiting\n");
+    let summary: serde_json::Value = serde_json::from_str(&written.unwrap()).unwrap();
+    assert_eq!(summary["outcome"], "exited");
+    assert_eq!(summary["code"], 42);
+}
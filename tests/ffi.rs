@@ -0,0 +1,59 @@
+//! The C API from C: `tests/ffi/smoke.c` is compiled against `include/lc3.h`, linked to the cdylib and run on the echo program
+#![cfg(all(feature = "ffi", unix))]
+
+use std::env::{self, consts};
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn a_c_program_runs_a_machine() {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // Cargo keeps a single cdylib per profile whatever the features, the one next to this test may come from another feature set,
+    // so it is built again in its own directory. The test runs from <target>/<profile>/deps
+    let target = env::current_exe()
+        .unwrap()
+        .ancestors()
+        .nth(3)
+        .unwrap()
+        .join("ffi-smoke");
+    let built = Command::new(env!("CARGO"))
+        .args([
+            "build",
+            "--lib",
+            "--no-default-features",
+            "--features",
+            "ffi",
+            "--target-dir",
+        ])
+        .arg(&target)
+        .current_dir(&manifest)
+        .status()
+        .unwrap();
+    assert!(built.success());
+    let libraries = target.join("debug");
+    let library = libraries.join(format!("{}lc3{}", consts::DLL_PREFIX, consts::DLL_SUFFIX));
+    assert!(library.exists(), "{} wasn't built", library.display());
+    let program = libraries.join("smoke");
+    let compiled = Command::new(env::var("CC").unwrap_or("cc".to_string()))
+        .arg(manifest.join("tests/ffi/smoke.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(&libraries)
+        .arg("-llc3")
+        .status()
+        .unwrap();
+    assert!(compiled.success());
+    // Cargo points the library path of the tests at <target>/<profile>/deps, where the other cdylib is
+    let output = Command::new(&program)
+        .arg(manifest.join("tests/programs/echo.obj"))
+        .env("LD_LIBRARY_PATH", &libraries)
+        .env("DYLD_LIBRARY_PATH", &libraries)
+        .output()
+        .unwrap();
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,92 @@
+/* Runs the echo program through the C API, see tests/ffi.rs. Prints what failed and exits with 1 */
+#include <stdio.h>
+#include <string.h>
+
+#include "lc3.h"
+
+#define CHECK(condition)                                                    \
+    if (!(condition)) {                                                     \
+        fprintf(stderr, "%s:%d: %s failed\n", __FILE__, __LINE__, #condition); \
+        return 1;                                                           \
+    }
+
+struct keys {
+    const char *text;
+    size_t next;
+};
+
+static int32_t next_key(void *user_data) {
+    struct keys *keys = user_data;
+    if (keys->text[keys->next] == '\0') {
+        return -1;
+    }
+    return (unsigned char)keys->text[keys->next++];
+}
+
+struct output {
+    char text[256];
+    size_t length;
+};
+
+static void print_byte(void *user_data, uint8_t byte) {
+    struct output *output = user_data;
+    if (output->length < sizeof(output->text) - 1) {
+        output->text[output->length++] = (char)byte;
+    }
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 2);
+    FILE *file = fopen(argv[1], "rb");
+    CHECK(file != NULL);
+    uint8_t image[1024];
+    size_t length = fread(image, 1, sizeof(image), file);
+    fclose(file);
+
+    Lc3Vm *vm = lc3_new();
+    CHECK(vm != NULL);
+    struct keys keys = {"hi", 0};
+    struct output output = {{0}, 0};
+    CHECK(lc3_set_input_callback(vm, next_key, &keys) == LC3_STATUS_OK);
+    CHECK(lc3_set_output_callback(vm, print_byte, &output) == LC3_STATUS_OK);
+    CHECK(lc3_load_image(vm, image, length) == LC3_STATUS_OK);
+
+    CHECK(lc3_step(vm) == LC3_STATUS_RUNNING);
+    uint16_t pc = 0;
+    CHECK(lc3_read_reg(vm, LC3_REGISTER_PC, &pc) == LC3_STATUS_OK);
+    CHECK(pc == 0x3001);
+    CHECK(lc3_run(vm, 1000) == LC3_STATUS_WAITING_FOR_INPUT);
+    CHECK(strcmp(output.text, "HI") == 0);
+    keys.text = ".";
+    keys.next = 0;
+    CHECK(lc3_run(vm, 1000) == LC3_STATUS_HALTED);
+    CHECK(strcmp(output.text, "HI\nbye\nHALT") == 0);
+    uint16_t count = 0;
+    CHECK(lc3_read_reg(vm, 5, &count) == LC3_STATUS_OK);
+    CHECK(count == 3);
+
+    CHECK(lc3_write_reg(vm, 1, 0xBEEF) == LC3_STATUS_OK);
+    uint16_t value = 0;
+    CHECK(lc3_read_reg(vm, 1, &value) == LC3_STATUS_OK);
+    CHECK(value == 0xBEEF);
+    CHECK(lc3_write_mem(vm, 0x4000, 0x1234) == LC3_STATUS_OK);
+    CHECK(lc3_read_mem(vm, 0x4000, &value) == LC3_STATUS_OK);
+    CHECK(value == 0x1234);
+
+    /* Failures */
+    CHECK(lc3_last_error_message() == NULL);
+    CHECK(lc3_read_reg(vm, 10, &value) == LC3_STATUS_BAD_REGISTER);
+    CHECK(strcmp(lc3_last_error_message(), "There is no register 10") == 0);
+    CHECK(lc3_load_image(vm, image, 1) == LC3_STATUS_BAD_IMAGE);
+    CHECK(lc3_run(NULL, 1) == LC3_STATUS_NULL_POINTER);
+    /* RTI isn't implemented */
+    Lc3Vm *bad = lc3_new();
+    CHECK(lc3_write_mem(bad, 0x3000, 0x8000) == LC3_STATUS_OK);
+    CHECK(lc3_run(bad, 10) == LC3_STATUS_BAD_OP_CODE);
+    lc3_free(bad);
+
+    lc3_free(vm);
+    lc3_free(NULL);
+    puts("ok");
+    return 0;
+}
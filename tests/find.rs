@@ -0,0 +1,78 @@
+//! `find` and `find-str` searching an image or a snapshot, see `lc3::search`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+const STRINGS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/strings.obj");
+
+fn lc3(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The address and layout of every match printed
+fn hits(output: &Output) -> Vec<String> {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.split(" x").next().unwrap().trim_end().to_string())
+        .collect()
+}
+
+#[test]
+fn a_string_is_found_in_every_layout() {
+    let output = lc3(&["find-str", STRINGS, "x3000:xFDFF", "HELLO"]);
+    assert_eq!(
+        hits(&output),
+        ["x3001 words", "x3007 packed", "x300A packed (high byte)"]
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout)
+            .contains("x3007 packed             x004F x0000 [x4548 x4C4C x004F] x4800 x4C45\n")
+    );
+}
+
+#[test]
+fn overlapping_matches_count_and_the_range_end_cuts_them() {
+    let output = lc3(&["find", STRINGS, "x3000:x3010", "x41", "0x0041"]);
+    assert_eq!(hits(&output), ["x300E words", "x300F words"]);
+    let output = lc3(&["find", STRINGS, "x3000:x300F", "x41", "0x0041"]);
+    assert_eq!(hits(&output), ["x300E words"]);
+    // The packed string is cut by the end of the range
+    let output = lc3(&["find-str", STRINGS, "x3000:x3008", "HELLO"]);
+    assert_eq!(hits(&output), ["x3001 words"]);
+}
+
+#[test]
+fn a_snapshot_is_searched_like_an_image() {
+    let snapshot = env::temp_dir().join(format!("lc3-find-{}.lc3snap", std::process::id()));
+    let snapshot = snapshot.to_str().unwrap();
+    let run = lc3(&[STRINGS, "--stdin-file", "/dev/null", "--snapshot", snapshot]);
+    assert!(run.status.success());
+    let output = lc3(&["find-str", snapshot, "x3000:xFFFF", "HELLO"]);
+    let _ = fs::remove_file(snapshot);
+    assert_eq!(
+        hits(&output),
+        ["x3001 words", "x3007 packed", "x300A packed (high byte)"]
+    );
+}
+
+#[test]
+fn bad_arguments_are_usage_errors() {
+    for args in [
+        &["find", STRINGS, "x3000:x3010"][..],
+        &["find-str", STRINGS, "x3010:x3000", "HELLO"],
+        &["find-str", STRINGS, "x3000:x3010", ""],
+    ] {
+        let output = lc3(args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(output.stdout.is_empty());
+    }
+}
@@ -0,0 +1,14 @@
+; Exercises every assembler directive
+        .ORIG x3000
+        LEA R0, MSG             ; Print the message
+        TRAP x22
+        LD R1, VALUE
+        TRAP x25
+VALUE   .FILL #-2               ; Decimal literal
+HEX     .FILL xBEEF             ; Hexadecimal literal
+CHAR    .FILL 'A'               ; Character literal
+NEWLINE .FILL '\n'              ; Escaped character literal
+PTR     .FILL MSG               ; Label reference
+BUFFER  .BLKW 3                 ; Three zeroed words
+MSG     .STRINGZ "Hi\t\"x\"\\\n\0!"
+        .END
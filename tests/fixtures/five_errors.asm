@@ -0,0 +1,9 @@
+        .ORIG x3000
+LOOP    ADD R1, R1, #1
+        AND R2, R2, R9
+LOOP2   MUL R1, R2, R3
+MSG     .STRINGZ "oops
+        TRAP x25
+LOOP    ADD R1, R1, #-1
+        BRnzp LOP
+        .END
@@ -0,0 +1,21 @@
+; Counts down from ten printing the message
+        ; Indented comment
+        .MACRO   PRINT msg
+        LEA      R0, msg
+        PUTS
+        .ENDM
+        .ORIG    x3000
+count   .EQU     #10
+        LD       R1, TEN        ;   load the counter
+LOOP    PRINT    MSG
+        ADD      R1, R1, #-1
+        BRp      LOOP
+
+        HALT
+TEN     .FILL    #10
+SEMI    .FILL    ';'
+AVERYLONGLABEL .FILL xBEEF      ; long label
+MSG     .STRINGZ "Tick; tock\n"
+EMPTY
+        .BLKW    #2
+        .END
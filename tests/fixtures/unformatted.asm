@@ -0,0 +1,21 @@
+; Counts down from ten printing the message
+   ; Indented comment
+.macro print msg
+  lea r0,msg
+ puts
+.endm
+	.orig 0x3000
+count .equ 10
+  ld r1,TEN ;   load the counter
+LOOP print MSG
+    add r1,r1,-1
+  brp LOOP
+
+	halt
+TEN .fill 10
+SEMI .fill ';'
+AVERYLONGLABEL .FILL xbeef ; long label
+MSG .stringz "Tick; tock\n"
+EMPTY
+  .blkw 2
+.end
@@ -0,0 +1,171 @@
+//! End to end tests: real programs run with scripted input, their output and final state are compared with golden files.
+//! Run with `UPDATE_GOLDEN=1` to write the golden files again after an intended change
+
+use std::fmt::Write as _;
+use std::{env, fs};
+
+use lc3::file_management::read_file_to_memory;
+use lc3::os::boot_minimal_os;
+use lc3::test_util::{ScriptedInput, TestVm};
+use lc3::{Registers, State};
+
+const REGISTERS: [(Registers, &str); 10] = [
+    (Registers::R0, "R0"),
+    (Registers::R1, "R1"),
+    (Registers::R2, "R2"),
+    (Registers::R3, "R3"),
+    (Registers::R4, "R4"),
+    (Registers::R5, "R5"),
+    (Registers::R6, "R6"),
+    (Registers::R7, "R7"),
+    (Registers::Pc, "PC"),
+    (Registers::Flags, "COND"),
+];
+
+struct Case {
+    name: &'static str,
+    image: &'static str,
+    input: &'static [u8],
+    budget: u64,
+    /// Memory ranges included in the golden file
+    memory: &'static [(u16, u16)],
+}
+
+/// Run the case and write its report, `jit` compiles its hot loops first (only with the `jit` feature) and
+/// `builtin_os` runs the traps in the system of `lc3::os`
+fn run(case: &Case, jit: bool, builtin_os: bool) -> String {
+    let mut state = State::default();
+    let image = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), case.image);
+    read_file_to_memory(&image, &mut state).unwrap();
+    if builtin_os {
+        let start = state.register_read(Registers::Pc);
+        boot_minimal_os(&mut state, start).unwrap();
+    }
+    #[cfg(feature = "jit")]
+    if jit {
+        assert!(state.enable_jit(), "the JIT doesn't support this machine");
+    }
+    #[cfg(not(feature = "jit"))]
+    let _ = jit;
+    let mut vm = TestVm::new(state)
+        .input(ScriptedInput::new(case.input))
+        .budget(case.budget);
+    let result = vm.run();
+
+    let mut report = String::from("== output ==\n");
+    report.push_str(&vm.output().as_string());
+    report.push_str("\n== result ==\n");
+    match result {
+        Ok(()) => report.push_str("halted\n"),
+        Err(error) => {
+            let _ = writeln!(report, "error: {}", error);
+        }
+    }
+    let state = vm.state_mut();
+    report.push_str("== registers ==\n");
+    for (register, name) in REGISTERS {
+        let _ = writeln!(report, "{} x{:04X}", name, state.register_read(register));
+    }
+    report.push_str("== memory ==\n");
+    for (start, end) in case.memory {
+        for address in *start..=*end {
+            let value = state.memory_read(address as usize);
+            let _ = writeln!(report, "x{:04X} x{:04X}", address, value);
+        }
+    }
+    report
+}
+
+fn check(case: Case) {
+    let actual = run(&case, false, false);
+    let path = format!(
+        "{}/tests/golden/{}.golden",
+        env!("CARGO_MANIFEST_DIR"),
+        case.name
+    );
+    if env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1") {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1 to create it", path));
+    assert_eq!(
+        actual, expected,
+        "{} doesn't match its golden file",
+        case.name
+    );
+    if cfg!(feature = "jit") {
+        assert_eq!(
+            run(&case, true, false),
+            expected,
+            "{} doesn't match its golden file with the JIT",
+            case.name
+        );
+    }
+    // The program prints the same through the system. It stops inside the HALT routine, with the registers that
+    // has, and waits forever for a key instead of failing once the input ran out
+    let booted = run(&case, false, true);
+    assert_eq!(
+        section(&booted, "output"),
+        section(&expected, "output"),
+        "{} prints something else with the builtin OS",
+        case.name
+    );
+    if section(&expected, "result") == "halted\n" {
+        assert_eq!(section(&booted, "result"), "halted\n", "{}", case.name);
+    }
+}
+
+/// The lines of a report under `== name ==`
+fn section<'a>(report: &'a str, name: &str) -> &'a str {
+    let start = report.find(&format!("== {} ==\n", name)).unwrap() + name.len() + 7;
+    let end = report[start..]
+        .find("\n== ")
+        .map_or(report.len(), |end| start + end + 1);
+    &report[start..end]
+}
+
+#[test]
+fn hello_world() {
+    check(Case {
+        name: "hello",
+        image: "tests/programs/hello.obj",
+        input: b"",
+        budget: 1_000,
+        memory: &[],
+    });
+}
+
+#[test]
+fn counting_loop() {
+    check(Case {
+        name: "count",
+        image: "tests/programs/count.obj",
+        input: b"",
+        budget: 1_000,
+        memory: &[(0x300F, 0x300F)],
+    });
+}
+
+#[test]
+fn getc_echo() {
+    check(Case {
+        name: "echo",
+        image: "tests/programs/echo.obj",
+        input: b"Hello lc3.ignored",
+        budget: 10_000,
+        memory: &[],
+    });
+}
+
+#[test]
+fn game_2048() {
+    // Starts a game and makes a few moves, then the input runs out while waiting for the next key
+    check(Case {
+        name: "2048",
+        image: "images/2048.obj",
+        input: b"nwasdwasd",
+        budget: 10_000_000,
+        memory: &[],
+    });
+}
@@ -0,0 +1,37 @@
+//! A program polling the keyboard for a key that never comes, only a hang once the keys are scripted
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+/// `POLL LDI R0, KBSR` and `BRzp POLL` until a key is there, then HALT
+const POLL_KEYBOARD: [u16; 5] = [0x3000, 0xA002, 0x07FE, 0xF025, 0xFE00];
+
+#[test]
+fn polling_after_the_last_scripted_key_is_a_hang() {
+    let directory = env::temp_dir();
+    let image = directory.join(format!("lc3-hang-{}.obj", std::process::id()));
+    let keys = directory.join(format!("lc3-hang-{}.keys", std::process::id()));
+    let bytes: Vec<u8> = POLL_KEYBOARD
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&image, bytes).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--detect-hang", "--hang-threshold", "100"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", errors);
+    assert!(
+        errors.contains("program appears hung at x3000 (LDI R0, #2); R0=x0000"),
+        "{}",
+        errors
+    );
+}
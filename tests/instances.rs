@@ -0,0 +1,113 @@
+//! `run --instances` running several machines in turn, two of them talking through their serial ports. See
+//! `lc3::scheduler`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+const PING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/ping.obj");
+const PONG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/pong.obj");
+
+fn lc3(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn ping_and_pong_answer_each_other() {
+    let summary = env::temp_dir().join(format!("lc3-instances-{}.json", std::process::id()));
+    let instances = format!("{},{}", PING, PONG);
+    let args = [
+        "run",
+        "--instances",
+        &instances,
+        "--serial-pair",
+        "0:1",
+        "--slice",
+        "7",
+        "--deterministic",
+        "--json-summary",
+        summary.to_str().unwrap(),
+    ];
+    let output = lc3(&args);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(stdout.contains("[0] PONG\n"), "{}", stdout);
+    assert!(stdout.contains("[1] PING\n"), "{}", stdout);
+    let written = fs::read_to_string(&summary).unwrap();
+    let summaries: serde_json::Value = serde_json::from_str(&written).unwrap();
+    let outcomes: Vec<&str> = summaries
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|summary| summary["outcome"].as_str().unwrap())
+        .collect();
+    assert_eq!(outcomes, ["halted", "halted"]);
+    assert!(summaries[0]["wall_time_ms"].is_null());
+    // Taking turns in one thread, every run is the same
+    assert_eq!(lc3(&args).stdout, output.stdout);
+    assert_eq!(fs::read_to_string(&summary).unwrap(), written);
+    fs::remove_file(summary).unwrap();
+}
+
+#[test]
+fn a_machine_that_fails_is_reported_with_its_index() {
+    let instances = format!("{},{}", PONG, PING);
+    let output = lc3(&["run", "--instances", &instances, "--max-steps", "300"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("[0] The program didn't halt after 300 instructions"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("[1] The program didn't halt after 300 instructions"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("2 instance(s) failed"), "{}", stderr);
+}
+
+#[test]
+fn a_bad_pair_is_refused() {
+    let instances = format!("{},{}", PING, PONG);
+    for pair in ["0:0", "0:2", "0", "0:1,1:0"] {
+        let mut args = vec!["run", "--instances", &instances];
+        for pair in pair.split(',') {
+            args.extend(["--serial-pair", pair]);
+        }
+        let output = lc3(&args);
+        assert_eq!(output.status.code(), Some(1), "{}", pair);
+        assert!(output.stdout.is_empty(), "{}", pair);
+    }
+}
+
+#[test]
+fn a_summary_that_cant_be_written_doesnt_skip_the_stats() {
+    let stats = env::temp_dir().join(format!("lc3-instances-stats-{}.csv", std::process::id()));
+    let missing = env::temp_dir().join(format!("lc3-no-such-directory-{}", std::process::id()));
+    let instances = format!("{},{}", PING, PONG);
+    let output = lc3(&[
+        "run",
+        "--instances",
+        &instances,
+        "--serial-pair",
+        "0:1",
+        "--json-summary",
+        missing.join("summary.json").to_str().unwrap(),
+        "--stats-out",
+        stats.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let written = fs::read_to_string(&stats);
+    let _ = fs::remove_file(&stats);
+    // The header and a row for each machine
+    assert_eq!(written.unwrap().lines().count(), 3);
+}
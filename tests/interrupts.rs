@@ -0,0 +1,124 @@
+//! Interrupts and exceptions nesting in each other, see `lc3::irq`. The programs are in tests/programs/irq_*.asm,
+//! what they pushed is checked on the supervisor stack: the PSR at x2FFF and the PC at x2FFE for the first one, x2FFD
+//! and x2FFC for the one nested in it
+#![cfg(feature = "test-util")]
+
+use std::collections::{HashMap, VecDeque};
+
+use lc3::assembler::assemble;
+use lc3::clock::VirtualClock;
+use lc3::irq::{DEFAULT_SUPERVISOR_STACK, InterruptController};
+use lc3::test_util::state_with_snippet;
+use lc3::{Error, Flags, Registers, RuntimeError, State, run_with_budget};
+
+/// The program loaded with the key `k` waiting and the controller on, and where its labels are
+fn program(
+    source: &str,
+    supervisor_stack: std::ops::RangeInclusive<u16>,
+) -> (State, HashMap<String, u16>) {
+    let mut state = state_with_snippet(source);
+    state.set_output(Vec::new());
+    state.set_input(VecDeque::from(b"k".to_vec()));
+    state.set_clock(VirtualClock::default());
+    state.set_interrupt_controller(Some(InterruptController::new(supervisor_stack)));
+    (state, assemble(source).unwrap().symbols)
+}
+
+fn words(state: &State, addresses: std::ops::Range<usize>) -> Vec<u16> {
+    addresses.map(|address| state.peek(address)).collect()
+}
+
+fn back_in_user_mode(state: &State) {
+    let controller = state.interrupt_controller().unwrap();
+    assert!(!controller.is_supervisor());
+    assert_eq!(controller.priority(), 0);
+    assert_eq!(state.register_read(Registers::R6), 0);
+}
+
+#[test]
+fn an_interrupt_nests_in_an_exception_handler() {
+    let (mut state, labels) = program(
+        include_str!("programs/irq_exception_then_interrupt.asm"),
+        DEFAULT_SUPERVISOR_STACK,
+    );
+    run_with_budget(&mut state, 10_000).unwrap();
+    assert_eq!(words(&state, 0x4000..0x4003), [1, b'k' as u16, 2]);
+    // User mode at priority 0 with Z, past the RTI
+    assert_eq!(state.peek(0x2FFF), 0x8002);
+    assert_eq!(state.peek(0x2FFE), labels["AFTER_RTI"]);
+    // The handler in supervisor mode at priority 0 with P, past the store that enabled the keyboard
+    assert_eq!(state.peek(0x2FFD), 0x0001);
+    assert_eq!(state.peek(0x2FFC), labels["AFTER_ENABLE"]);
+    back_in_user_mode(&state);
+}
+
+#[test]
+fn an_exception_nests_in_an_interrupt_handler_at_its_priority() {
+    let (mut state, labels) = program(
+        include_str!("programs/irq_exception_in_isr.asm"),
+        DEFAULT_SUPERVISOR_STACK,
+    );
+    run_with_budget(&mut state, 10_000).unwrap();
+    assert_eq!(words(&state, 0x4000..0x4003), [b'k' as u16, 2, 3]);
+    assert_eq!(state.peek(0x2FFF), 0x8001);
+    assert_eq!(state.peek(0x2FFE), labels["AFTER_ENABLE"]);
+    // The keyboard handler at priority 4 with Z, past the reserved opcode
+    assert_eq!(state.peek(0x2FFD), 0x0402);
+    assert_eq!(state.peek(0x2FFC), labels["AFTER_RESERVED"]);
+    back_in_user_mode(&state);
+}
+
+#[test]
+fn a_full_supervisor_stack_stops_the_program() {
+    let source = include_str!("programs/irq_supervisor_overflow.asm");
+    let (mut state, labels) = program(source, DEFAULT_SUPERVISOR_STACK);
+    let error = run_with_budget(&mut state, 100_000).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::SupervisorStackOverflow { pc, address: 0x01FE })
+            if *pc == labels["ILLEGAL"] + 1
+    ));
+    // Nothing was pushed past x0200, the vector table only has what the program wrote
+    let mut vectors = vec![0; 0x100];
+    vectors[1] = labels["ILLEGAL"];
+    assert_eq!(words(&state, 0x0100..0x0200), vectors);
+    assert_eq!(state.register_read(Registers::R6), 0x0200);
+
+    // A stack of two words only has room for the first exception
+    let (mut state, labels) = program(source, 0x2FFE..=0x2FFF);
+    let error = run_with_budget(&mut state, 100).unwrap_err();
+    assert!(matches!(
+        error.root(),
+        Error::Runtime(RuntimeError::SupervisorStackOverflow {
+            address: 0x2FFC,
+            ..
+        })
+    ));
+    assert_eq!(state.peek(0x2FFF), 0x8001);
+    assert_eq!(state.peek(0x2FFE), labels["ILLEGAL"] - 1);
+    assert_eq!(state.peek(0x2FFD), 0);
+}
+
+#[test]
+fn rti_normalizes_a_corrupted_psr() {
+    let (mut state, _) = program(
+        include_str!("programs/irq_corrupted_psr.asm"),
+        DEFAULT_SUPERVISOR_STACK,
+    );
+    run_with_budget(&mut state, 10_000).unwrap();
+    // The program saw N alone
+    assert_eq!(state.peek(0x4000), 0xFFFF);
+    assert_eq!(state.peek(0x2FFF), 0x8007);
+    back_in_user_mode(&state);
+    let (mut state, _) = program(
+        ".ORIG x3000\nLEA R0, H\nSTI R0, V\nRTI\nHALT\nH LD R0, U\nSTR R0, R6, #1\nRTI\nV .FILL x0100\nU .FILL x8000\n.END",
+        DEFAULT_SUPERVISOR_STACK,
+    );
+    // Without any it is Z, stopping right after the RTI of the handler
+    assert!(matches!(
+        run_with_budget(&mut state, 6),
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(_)))
+    ));
+    assert_eq!(state.register_read(Registers::Pc), 0x3003);
+    assert_eq!(state.register_read(Registers::Flags), Flags::Zro as u16);
+}
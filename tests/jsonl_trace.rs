@@ -0,0 +1,94 @@
+//! `--trace-format jsonl` writes a JSON object per instruction and `diff-trace` compares two of them, see
+//! `lc3::trace::jsonl`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// Counts R0 down from 5, then OUT and HALT
+const COUNTDOWN: [u16; 7] = [0x3000, 0x5020, 0x1025, 0x103F, 0x03FE, 0xF021, 0xF025];
+
+fn temp_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("lc3-jsonl-{}-{}", std::process::id(), name))
+}
+
+fn lc3(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("--no-color")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The trace of COUNTDOWN, with `words` changed
+fn trace(name: &str, words: &[(usize, u16)]) -> PathBuf {
+    let mut image = COUNTDOWN;
+    for &(index, word) in words {
+        image[index] = word;
+    }
+    let path = temp_file(&format!("{}.obj", name));
+    let bytes: Vec<u8> = image.iter().flat_map(|word| word.to_be_bytes()).collect();
+    fs::write(&path, bytes).unwrap();
+    let keys = temp_file("keys");
+    fs::write(&keys, b"").unwrap();
+    let output = lc3(&[
+        path.as_os_str(),
+        "--trace-format".as_ref(),
+        "jsonl".as_ref(),
+        "--stdin-file".as_ref(),
+        keys.as_os_str(),
+    ]);
+    let _ = fs::remove_file(path);
+    assert!(output.status.success());
+    let trace = temp_file(&format!("{}.jsonl", name));
+    fs::write(&trace, output.stderr).unwrap();
+    trace
+}
+
+#[test]
+fn the_same_program_gives_the_same_trace() {
+    let ours = trace("same-ours", &[]);
+    let contents = fs::read_to_string(&ours).unwrap();
+    assert!(
+        contents.starts_with(r#"{"step":1,"pc":12288,"ir":20512,"regs":{}}"#),
+        "{}",
+        contents
+    );
+    let theirs = trace("same-theirs", &[]);
+    let output = lc3(&["diff-trace".as_ref(), ours.as_os_str(), theirs.as_os_str()]);
+    let _ = fs::remove_file(ours);
+    let _ = fs::remove_file(theirs);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn a_divergence_is_reported_with_its_context() {
+    let ours = trace("diverge-ours", &[]);
+    // ADD R0, R0, #4
+    let theirs = trace("diverge-theirs", &[(2, 0x1024)]);
+    let output = lc3(&[
+        "diff-trace".as_ref(),
+        ours.as_os_str(),
+        theirs.as_os_str(),
+        "--context".as_ref(),
+        "1".as_ref(),
+        "--ignore".as_ref(),
+        "ir".as_ref(),
+    ]);
+    let _ = fs::remove_file(ours);
+    let _ = fs::remove_file(theirs);
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        report.starts_with("The traces diverge at step 2: different register writes\n"),
+        "{}",
+        report
+    );
+    assert!(
+        report.contains(r#">      2: {"step":2,"pc":12289,"ir":4132,"regs":{"R0":4}"#),
+        "{}",
+        report
+    );
+}
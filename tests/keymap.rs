@@ -0,0 +1,48 @@
+//! `--keymap` turning the escape sequences of special keys into single bytes, see `lc3::keymap`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+const ECHO: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/echo.obj");
+
+fn echo(name: &str, keys: &[u8], options: &[&str]) -> Output {
+    let path = env::temp_dir().join(format!("lc3-keymap-{}-{}.keys", name, std::process::id()));
+    fs::write(&path, keys).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(ECHO)
+        .arg("--stdin-file")
+        .arg(&path)
+        .args(options)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(path);
+    output
+}
+
+#[test]
+fn the_keys_in_the_map_reach_the_program_as_their_byte() {
+    // Left isn't in the map, it goes through as it was
+    let keys = b"\x1b[Ax\x1bOB\x1b[D\x1b[15~.";
+    let output = echo("mapped", keys, &["--keymap", "up=x31,down=0x32,f5=51"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"1X2\x1b[D3\nbye\nHALT");
+}
+
+#[test]
+fn without_a_keymap_the_sequences_go_through() {
+    let output = echo("plain", b"\x1b[A.", &[]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x1b[A\nbye\nHALT");
+}
+
+#[test]
+fn a_bad_keymap_is_a_usage_error() {
+    let output = echo("bad", b".", &["--keymap", "north=x11"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
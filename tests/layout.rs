@@ -0,0 +1,165 @@
+//! `--layout-check` and `batch` finding the conflicts of the address space before the run, see `lc3::layout`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::{env, fs};
+
+use lc3::assembler;
+
+const AT_X3000: &str = "
+        .ORIG x3000
+        LEA R0, TEXT
+        PUTS
+        HALT
+TEXT    .STRINGZ \"ok\"
+        .END
+";
+
+fn source(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lc3-layout-{}-{}.asm", name, std::process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn run(name: &str, text: &str, options: &[&str]) -> Output {
+    let path = source(name, text);
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&path)
+        .args(["--stdin-file", "/dev/null", "--layout-check"])
+        .args(options)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(path);
+    output
+}
+
+#[test]
+fn a_layout_that_works_out_is_printed_and_runs() {
+    let output = run("fine", AT_X3000, &["--stack", "xE000:xEFFF", "--arg", "a"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(!errors.contains("warning["), "{}", errors);
+    // The block of the argument goes right below the stack
+    assert!(
+        errors.contains(
+            "Layout:\n  x0000-x00FF  trap vectors\n  x0100-x01FF  interrupt vectors\n  x0200-x2FFF  operating system\n  \
+             x3000-x3005  segment\n  xDFFB-xDFFF  arguments\n  xE000-xEFFF  stack\n  xFE00-xFFFF  device page\n  \
+             entry x3000\n"
+        ),
+        "{}",
+        errors
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "okHALT");
+}
+
+#[test]
+fn the_stack_overlapping_code_is_a_warning_or_an_error() {
+    let output = run("stack", AT_X3000, &["--stack", "x3004:x30FF"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(
+        errors.contains(
+            "warning[W005]: the stack at x3004-x30FF overlaps the segment at x3000-x3005\n"
+        ),
+        "{}",
+        errors
+    );
+    let output = run(
+        "stack-denied",
+        AT_X3000,
+        &["--stack", "x3004:x30FF", "--deny", "W005"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    // Stopped before the program ran
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Denied warning W005: the stack at x3004-x30FF"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn arguments_overlapping_code_are_found() {
+    let output = run(
+        "arguments",
+        AT_X3000,
+        &["--arg", "abc", "--args-at", "x3002"],
+    );
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        errors.contains(
+            "warning[W005]: the arguments at x3002-x3008 overlap the segment at x3000-x3005\n"
+        ),
+        "{}",
+        errors
+    );
+}
+
+#[test]
+fn an_entry_outside_the_segments_and_a_segment_in_the_device_page_are_found() {
+    let source = "
+        .ORIG xFDFE
+        .FILL #1
+        .FILL #2
+        .FILL #3
+        .END
+";
+    let output = run(
+        "device-page",
+        source,
+        &["--max-steps", "10", "--allow", "W001"],
+    );
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        errors.contains("warning[W005]: the entry point x3000 is outside every segment\n"),
+        "{}",
+        errors
+    );
+    assert!(
+        errors.contains("warning[W005]: the segment at xFDFE-xFE00 reaches the device page\n"),
+        "{}",
+        errors
+    );
+    assert!(
+        errors.contains("  W005 layout conflict: 2 time(s)"),
+        "{}",
+        errors
+    );
+}
+
+#[test]
+fn batch_checks_the_layout_of_every_job() {
+    let directory = env::temp_dir().join(format!("lc3-layout-batch-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    for (name, text) in [
+        ("fine.obj", AT_X3000),
+        ("elsewhere.obj", ".ORIG x4000\nHALT\n.END"),
+    ] {
+        let program = assembler::assemble(text).unwrap();
+        fs::write(directory.join(name), program.to_object_bytes()).unwrap();
+    }
+    fs::write(
+        directory.join("jobs.toml"),
+        "[[job]]\nimage = \"fine.obj\"\n\n[[job]]\nimage = \"elsewhere.obj\"\nmax_steps = 10\n",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("batch")
+        .arg(directory.join("jobs.toml"))
+        .output()
+        .unwrap();
+    let report = fs::read_to_string(directory.join("jobs.report.json"));
+    let _ = fs::remove_dir_all(&directory);
+    assert_eq!(output.status.code(), Some(1));
+    let report: serde_json::Value = serde_json::from_str(&report.unwrap()).unwrap();
+    let jobs = report["jobs"].as_array().unwrap();
+    assert_eq!(jobs[0]["warnings"], serde_json::json!([]));
+    assert_eq!(jobs[1]["warnings"][0]["code"], "W005");
+    assert_eq!(
+        jobs[1]["warnings"][0]["message"],
+        "the entry point x3000 is outside every segment"
+    );
+}
@@ -0,0 +1,64 @@
+//! lc3tools object files are run like plain images and `dump --format lc3tools` writes files it can load
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+use lc3::file_management::{lc3tools, load_sections};
+
+fn fixture(path: &str) -> String {
+    format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), path)
+}
+
+fn lc3(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn run(image: &str) -> String {
+    let output = lc3(&["run", image, "--stdin-file", "/dev/null"]);
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn an_lc3tools_file_runs_like_the_plain_image() {
+    assert_eq!(
+        run(&fixture("fixtures/lc3tools/hello.obj")),
+        run(&fixture("programs/hello.obj"))
+    );
+    assert_eq!(run(&fixture("fixtures/lc3tools/two_sections.obj")), "AHALT");
+}
+
+#[test]
+fn dumped_lc3tools_files_round_trip() {
+    let dumped = env::temp_dir().join(format!("lc3tools-dump-{}.obj", std::process::id()));
+    let dumped = dumped.to_str().unwrap();
+    let sections = fixture("fixtures/lc3tools/two_sections.obj");
+    assert!(
+        lc3(&["dump", &sections, "--format", "lc3tools", "-o", dumped])
+            .status
+            .success()
+    );
+    let written = fs::read(dumped).unwrap();
+    assert!(lc3tools::is_lc3tools(&written));
+    assert_eq!(
+        load_sections(&written).unwrap(),
+        load_sections(&fs::read(&sections).unwrap()).unwrap()
+    );
+    assert_eq!(run(dumped), "AHALT");
+
+    // And back to a plain image, which only has room for one section
+    let hello = fixture("fixtures/lc3tools/hello.obj");
+    assert!(lc3(&["dump", &hello, "-o", dumped]).status.success());
+    assert_eq!(
+        fs::read(dumped).unwrap(),
+        fs::read(fixture("programs/hello.obj")).unwrap()
+    );
+    let failed = lc3(&["dump", &sections]);
+    assert!(!failed.status.success());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("2 sections"));
+    let _ = fs::remove_file(dumped);
+}
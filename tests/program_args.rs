@@ -0,0 +1,53 @@
+//! `--arg` and `--env` written to memory for the program, which reads them back
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+fn run_print_args(flags: &[&str]) -> Output {
+    let keys = env::temp_dir().join(format!("lc3-args-{}.keys", std::process::id()));
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/print_args.obj"
+        ))
+        .arg("--stdin-file")
+        .arg(&keys)
+        .arg("--strict-exec")
+        .args(flags)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(keys);
+    output
+}
+
+#[test]
+fn the_program_prints_its_arguments_then_its_variables() {
+    let output = run_print_args(&["--arg", "foo", "--env", "HOME=/lc3", "--arg", "42"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "foo\n42\nHOME=/lc3\nHALT"
+    );
+}
+
+#[test]
+fn the_arguments_go_at_args_at_unless_they_overlap_the_image() {
+    let output = run_print_args(&["--arg", "there", "--args-at", "x4000"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "there\nHALT");
+    let output = run_print_args(&["--arg", "over", "--args-at", "x3002"]);
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("overlaps the image at x3002"), "{}", errors);
+}
+
+#[test]
+fn a_variable_needs_an_equals_sign() {
+    let output = run_print_args(&["--env", "HOME"]);
+    assert!(!output.status.success());
+}
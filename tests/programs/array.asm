@@ -0,0 +1,28 @@
+; Builds the squares of 0 to 3 at x4000, then overwrites the first with 16. Pushes R3 on a stack at xFD00 and prints
+; a star storing to DDR
+        .ORIG x3000
+        LD R6, STACK
+        LD R2, ARRAY
+        AND R3, R3, #0          ; The square
+        AND R4, R4, #0          ; The next odd number
+        ADD R4, R4, #1
+        AND R5, R5, #0          ; Squares left
+        ADD R5, R5, #4
+LOOP    STR R3, R2, #0
+        ADD R3, R3, R4
+        ADD R4, R4, #2
+        ADD R2, R2, #1
+        ADD R5, R5, #-1
+        BRp LOOP
+        LD R2, ARRAY
+        STR R3, R2, #0
+        ADD R6, R6, #-1
+        STR R3, R6, #0
+        LD R0, STAR
+        STI R0, DDR
+        HALT
+STACK   .FILL xFD00
+ARRAY   .FILL x4000
+STAR    .FILL x2A
+DDR     .FILL xFE06
+        .END
@@ -0,0 +1,25 @@
+; Checks itself with the ASSERT trap: that 2 + 3 is 5 passes, that it is 6 fails
+        .ORIG x3000
+        AND R2, R2, #0
+        ADD R2, R2, #2
+        ADD R2, R2, #3
+        AND R0, R0, #0          ; R0 is 1 if R2 is 5
+        ADD R3, R2, #-5
+        BRnp NOT_FIVE
+        ADD R0, R0, #1
+NOT_FIVE
+        LEA R1, IS_FIVE
+        TRAP x26
+        AND R0, R0, #0          ; R0 is 1 if R2 is 6
+        ADD R3, R2, #-6
+        BRnp NOT_SIX
+        ADD R0, R0, #1
+NOT_SIX LEA R1, IS_SIX
+        TRAP x26
+        LEA R0, DONE
+        PUTS
+        HALT
+IS_FIVE .STRINGZ "2 + 3 is 5"
+IS_SIX  .STRINGZ "2 + 3 is 6"
+DONE    .STRINGZ "done\n"
+        .END
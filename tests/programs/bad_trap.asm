@@ -0,0 +1,5 @@
+; Calls a trap nothing handles, for the tests of a run failing on a terminal
+        .ORIG x3000
+        TRAP x50
+        HALT
+        .END
@@ -0,0 +1,19 @@
+; Prints the digits 0 to 9 and leaves the sum of 1 to 10 at RESULT
+        .ORIG x3000
+        AND R1, R1, #0          ; Counter
+        AND R2, R2, #0          ; Sum
+        LD R3, ZERO
+LOOP    ADD R0, R1, R3
+        OUT
+        ADD R1, R1, #1
+        ADD R2, R2, R1
+        ADD R4, R1, #-10
+        BRn LOOP
+        ST R2, RESULT
+        LD R0, NEWLINE
+        OUT
+        HALT
+ZERO    .FILL '0'
+NEWLINE .FILL '\n'
+RESULT  .BLKW 1
+        .END
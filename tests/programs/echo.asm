@@ -0,0 +1,26 @@
+; Echoes every key in upper case until a '.' is typed
+        .ORIG x3000
+        LD R2, DOT
+        LD R3, CASE
+        AND R5, R5, #0          ; Amount of keys
+LOOP    GETC
+        ADD R5, R5, #1
+        ADD R1, R0, R2
+        BRz DONE
+        ADD R1, R0, #-16        ; Lower case letters are above x60
+        ADD R1, R1, #-16
+        ADD R1, R1, #-16
+        ADD R1, R1, #-16
+        ADD R1, R1, #-16
+        ADD R1, R1, #-16
+        BRnz PRINT
+        ADD R0, R0, R3
+PRINT   OUT
+        BRnzp LOOP
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+DOT     .FILL #-46
+CASE    .FILL #-32
+BYE     .STRINGZ "\nbye\n"
+        .END
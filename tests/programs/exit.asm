@@ -0,0 +1,9 @@
+; Prints a line and exits with the code 42, for scripts to check
+        .ORIG x3000
+        LEA R0, MESSAGE
+        PUTS
+        LD R0, CODE
+        EXIT
+MESSAGE .STRINGZ "exiting\n"
+CODE    .FILL #42
+        .END
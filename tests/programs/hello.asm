@@ -0,0 +1,7 @@
+; Prints a greeting and halts
+        .ORIG x3000
+        LEA R0, GREETING
+        PUTS
+        HALT
+GREETING .STRINGZ "Hello, World!\n"
+        .END
@@ -0,0 +1,22 @@
+; The privilege handler overwrites the PSR on the stack with every condition code set, the program is back in user
+; mode with only N. x4000 is -1 then
+        .ORIG x3000
+        LEA R0, PRIVILEGE
+        STI R0, PRIVILEGE_VEC
+        RTI
+        BRz DONE
+        BRp DONE
+        AND R0, R0, #0
+        ADD R0, R0, #-1
+        STI R0, RESULT
+DONE    HALT
+
+PRIVILEGE
+        LD R0, CORRUPTED
+        STR R0, R6, #1
+        RTI
+
+PRIVILEGE_VEC .FILL x0100
+CORRUPTED .FILL x8007
+RESULT  .FILL x4000
+        .END
@@ -0,0 +1,48 @@
+; The keyboard handler runs the reserved opcode, the exception is taken at the priority of the keyboard and returns
+; to the handler. The log at x4000 is the key, 2 and 3
+        .ORIG x3000
+        LEA R0, ILLEGAL
+        STI R0, ILLEGAL_VEC
+        LEA R0, KEY_ISR
+        STI R0, KEY_VEC
+        LD R0, ENABLE           ; P in the PSR pushed
+        STI R0, KBSR
+AFTER_ENABLE
+        HALT
+
+KEY_ISR ST R0, KEY_R0
+        ST R1, KEY_R0+1
+        ST R7, KEY_R0+2
+        LDI R0, KBDR
+        JSR LOG
+        AND R0, R0, #0          ; Z in the PSR pushed
+        STI R0, KBSR
+        .FILL xD000             ; Reserved
+AFTER_RESERVED
+        AND R0, R0, #0
+        ADD R0, R0, #3
+        JSR LOG
+        LD R0, KEY_R0
+        LD R1, KEY_R0+1
+        LD R7, KEY_R0+2
+        RTI
+
+ILLEGAL AND R0, R0, #0
+        ADD R0, R0, #2
+        JSR LOG
+        RTI
+
+LOG     LD R1, NEXT
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        ST R1, NEXT
+        RET
+
+ILLEGAL_VEC .FILL x0101
+KEY_VEC .FILL x0180
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+ENABLE  .FILL x4000
+NEXT    .FILL x4000
+KEY_R0  .BLKW 3
+        .END
@@ -0,0 +1,50 @@
+; RTI in user mode goes to the privilege handler, which enables the keyboard interrupt with the key already there:
+; the keyboard interrupts the handler. The log at x4000 is 1, the key and 2
+        .ORIG x3000
+        LEA R0, PRIVILEGE
+        STI R0, PRIVILEGE_VEC
+        LEA R0, KEY_ISR
+        STI R0, KEY_VEC
+        AND R0, R0, #0          ; Z in the PSR pushed
+        RTI
+AFTER_RTI
+        HALT
+
+PRIVILEGE
+        AND R0, R0, #0
+        ADD R0, R0, #1
+        JSR LOG
+        LD R0, ENABLE
+        STI R0, KBSR            ; P in the PSR pushed
+AFTER_ENABLE
+        AND R0, R0, #0
+        ADD R0, R0, #2
+        JSR LOG
+        RTI
+
+KEY_ISR ST R0, KEY_R0
+        ST R1, KEY_R0+1
+        ST R7, KEY_R0+2
+        LDI R0, KBDR
+        JSR LOG
+        AND R0, R0, #0
+        STI R0, KBSR
+        LD R0, KEY_R0
+        LD R1, KEY_R0+1
+        LD R7, KEY_R0+2
+        RTI
+
+LOG     LD R1, NEXT
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        ST R1, NEXT
+        RET
+
+PRIVILEGE_VEC .FILL x0100
+KEY_VEC .FILL x0180
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+ENABLE  .FILL x4000
+NEXT    .FILL x4000
+KEY_R0  .BLKW 3
+        .END
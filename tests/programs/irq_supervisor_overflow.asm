@@ -0,0 +1,12 @@
+; The handler of the reserved opcode is the reserved opcode, every exception nests in the one before until the
+; supervisor stack is full
+        .ORIG x3000
+        LEA R0, ILLEGAL
+        STI R0, ILLEGAL_VEC
+        .FILL xD000
+        HALT
+
+ILLEGAL .FILL xD000
+
+ILLEGAL_VEC .FILL x0101
+        .END
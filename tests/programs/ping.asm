@@ -0,0 +1,28 @@
+; Sends PING through the serial port, then prints the 4 bytes the other end answers
+        .ORIG x3000
+        LEA R1, PING
+SEND    LDR R0, R1, #0
+        BRz ANSWER
+WAITTX  LDI R2, SSR             ; Bit 14: the line can take a byte
+        ADD R2, R2, R2
+        BRzp WAITTX
+        STI R0, STDR
+        ADD R1, R1, #1
+        BRnzp SEND
+ANSWER  AND R3, R3, #0
+        ADD R3, R3, #4
+WAITRX  LDI R2, SSR             ; Bit 15: a byte was received
+        BRzp WAITRX
+        LDI R0, SRDR
+        OUT
+        ADD R3, R3, #-1
+        BRp WAITRX
+        AND R0, R0, #0
+        ADD R0, R0, #10
+        OUT
+        HALT
+SSR     .FILL xFE1A
+SRDR    .FILL xFE1C
+STDR    .FILL xFE1E
+PING    .STRINGZ "PING"
+        .END
@@ -0,0 +1,28 @@
+; Prints the 4 bytes received on the serial port, then answers PONG
+        .ORIG x3000
+        AND R3, R3, #0
+        ADD R3, R3, #4
+WAITRX  LDI R2, SSR             ; Bit 15: a byte was received
+        BRzp WAITRX
+        LDI R0, SRDR
+        OUT
+        ADD R3, R3, #-1
+        BRp WAITRX
+        AND R0, R0, #0
+        ADD R0, R0, #10
+        OUT
+        LEA R1, PONG
+SEND    LDR R0, R1, #0
+        BRz DONE
+WAITTX  LDI R2, SSR             ; Bit 14: the line can take a byte
+        ADD R2, R2, R2
+        BRzp WAITTX
+        STI R0, STDR
+        ADD R1, R1, #1
+        BRnzp SEND
+DONE    HALT
+SSR     .FILL xFE1A
+SRDR    .FILL xFE1C
+STDR    .FILL xFE1E
+PONG    .STRINGZ "PONG"
+        .END
@@ -0,0 +1,26 @@
+; Prints every --arg, then every --env, a line each
+        .ORIG x3000
+        ADD R4, R1, #0          ; The variables, PRINT leaves R4 alone
+        JSR PRINT               ; The arguments are at R0 already
+        ADD R0, R4, #0
+        JSR PRINT
+        HALT
+
+; Print the strings of the table at R0: a count, then a pointer per string
+PRINT   ST R7, SAVE_R7
+        ADD R2, R0, #0
+        LDR R3, R2, #0
+NEXT    ADD R3, R3, #0
+        BRz DONE
+        ADD R2, R2, #1
+        LDR R0, R2, #0
+        PUTS
+        LD R0, NEWLINE
+        OUT
+        ADD R3, R3, #-1
+        BR NEXT
+DONE    LD R7, SAVE_R7
+        RET
+SAVE_R7 .BLKW 1
+NEWLINE .FILL x000A
+        .END
@@ -0,0 +1,8 @@
+; Says it is ready and counts forever, for the Ctrl-C tests
+        .ORIG x3000
+        LEA R0, READY
+        PUTS
+LOOP    ADD R1, R1, #1
+        BRnzp LOOP
+READY   .STRINGZ "ready\n"
+        .END
@@ -0,0 +1,15 @@
+; Strings in both layouts for `find-str`, never run
+        .ORIG x3000
+        HALT
+WORDS   .STRINGZ "HELLO"        ; x3001, a character per word
+PACKED  .FILL x4548             ; x3007, "HELLO" packed like PUTSP
+        .FILL x4C4C
+        .FILL x004F
+ODD     .FILL x4800             ; x300A, packed from the high byte
+        .FILL x4C45
+        .FILL x4F4C
+        .FILL x0000
+TRIPLE  .FILL x0041             ; x300E, "AAA" overlapping "AA" twice
+        .FILL x0041
+        .FILL x0041
+        .END
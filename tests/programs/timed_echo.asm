@@ -0,0 +1,18 @@
+; Echoes every key until a '.' is typed, sleeping 7 milliseconds and reading the time (TRAP x34) before each echo,
+; so its trace has the time of the machine in R0 and R1
+        .ORIG x3000
+        LD R3, DOT
+LOOP    GETC
+        ADD R2, R0, #0
+        ADD R4, R2, R3
+        BRz DONE
+        AND R0, R0, #0
+        ADD R0, R0, #7
+        TRAP x35
+        TRAP x34
+        ADD R0, R2, #0
+        OUT
+        BRnzp LOOP
+DONE    HALT
+DOT     .FILL #-46
+        .END
@@ -0,0 +1,8 @@
+; Says it is ready and waits for a key, for the Ctrl-C tests
+        .ORIG x3000
+        LEA R0, READY
+        PUTS
+        GETC
+        HALT
+READY   .STRINGZ "ready\n"
+        .END
@@ -0,0 +1,75 @@
+"""Smoke test of the Python bindings, run with `pytest` after `maturin develop`"""
+
+from pathlib import Path
+
+import pytest
+
+import lc3vm
+
+PROGRAMS = Path(__file__).parent.parent / "programs"
+
+
+def test_runs_a_program_from_its_path():
+    vm = lc3vm.Vm()
+    vm.load(PROGRAMS / "hello.obj")
+    assert vm.run() == lc3vm.RunStatus.Halted
+    assert vm.read_output() == "Hello, World!\nHALT"
+    assert vm.read_output() == ""
+
+
+def test_waits_for_keys_and_resumes():
+    vm = lc3vm.Vm()
+    vm.load((PROGRAMS / "echo.obj").read_bytes())
+    assert vm.run() == lc3vm.RunStatus.WaitingForInput
+    vm.send_key("hi")
+    assert vm.run(max_steps=1000) == lc3vm.RunStatus.WaitingForInput
+    assert vm.read_output() == "HI"
+    vm.send_key(ord("."))
+    assert vm.run() == lc3vm.RunStatus.Halted
+    assert vm.read_output() == "\nbye\nHALT"
+    assert vm.regs["R5"] == 3
+
+
+def test_registers_and_memory_can_be_changed():
+    vm = lc3vm.Vm()
+    # ADD R1, R1, #1 then HALT
+    vm.load(bytes([0x30, 0x00, 0x12, 0x61, 0xF0, 0x25]))
+    vm.regs["R1"] = 41
+    vm.mem[0x3001] = 0x1261
+    assert vm.step() == lc3vm.RunStatus.Running
+    assert vm.step() == lc3vm.RunStatus.Running
+    assert vm.regs["r1"] == 43
+    assert vm.regs["PC"] == 0x3002
+    assert dict(vm.regs)["R1"] == 43
+    assert len(vm.regs) == 10
+    assert vm.mem[0x3000] == 0x1261
+    # The rest of memory is BR with no condition codes, it never branches
+    assert vm.step(10) == lc3vm.RunStatus.Running
+    assert vm.regs["PC"] == 0x300C
+
+
+def test_errors_raise_exceptions():
+    vm = lc3vm.Vm()
+    # BR to itself
+    vm.load(bytes([0x30, 0x00, 0x0F, 0xFF]))
+    with pytest.raises(lc3vm.BudgetExhaustedError):
+        vm.run(max_steps=100)
+    with pytest.raises(lc3vm.VmError):
+        vm.run(max_steps=100)
+    with pytest.raises(lc3vm.BadImageError):
+        vm.load(b"\x30")
+    with pytest.raises(FileNotFoundError):
+        vm.load(PROGRAMS / "missing.obj")
+    with pytest.raises(KeyError):
+        vm.regs["R8"]
+
+
+def test_output_can_be_drained_and_bounded():
+    vm = lc3vm.Vm()
+    vm.load(PROGRAMS / "hello.obj")
+    vm.set_output_capacity(5)
+    assert vm.run() == lc3vm.RunStatus.Halted
+    assert vm.output_len() == 5
+    assert vm.drain_output() == b"Hello"
+    assert vm.output_overflowed == len("Hello, World!\nHALT") - 5
+    assert vm.output_len() == 0
@@ -0,0 +1,103 @@
+//! Differential tests against a reference simulator.
+//! Set `LC3SIM` to a command that runs an image given as its only argument and prints its step trace
+//! in the reference format (see [`lc3::trace::run_traced`]) to stdout, every program is run in both and their traces must match.
+//! Without `LC3SIM` the tests are skipped.
+//!
+//! A traced run is always interpreted, so with the `jit` feature every program also runs with the JIT in lockstep
+//! with the interpreter, compared at every instruction it interprets and where every compiled block exits
+
+use std::env;
+use std::process::Command;
+
+use lc3::State;
+#[cfg(feature = "jit")]
+use lc3::compare::run_in_lockstep;
+use lc3::file_management::read_file_to_memory;
+use lc3::test_util::TestVm;
+#[cfg(feature = "jit")]
+use lc3::test_util::state_with_snippet;
+use lc3::trace::{diff_traces, run_traced};
+
+/// Programs that halt without input
+const CORPUS: [&str; 2] = ["tests/programs/hello.obj", "tests/programs/count.obj"];
+
+/// Loops the JIT compiles, with every kind of BR and a BR alone testing the flags a block left
+#[cfg(feature = "jit")]
+const LOOPS: [&str; 2] = [
+    "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+COUNT   .FILL #30000
+        .END",
+    "
+        .ORIG x3000
+        LD R1, COUNT
+LOOP    ADD R2, R1, #-8
+        BRn A
+A       NOT R3, R2
+        BRzp B
+B       AND R4, R3, R1
+        BRnp C
+C       LEA R5, LOOP
+        BRz D
+D       ADD R1, R1, #-1
+        BRnzp E
+E       BRp LOOP
+        HALT
+COUNT   .FILL #500
+        .END",
+];
+
+#[test]
+fn traces_match_the_reference_simulator() {
+    let Ok(reference) = env::var("LC3SIM") else {
+        eprintln!("LC3SIM isn't set, skipping the differential tests");
+        return;
+    };
+    for image in CORPUS {
+        let image = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), image);
+        let mut state = State::default();
+        read_file_to_memory(&image, &mut state).unwrap();
+        let mut vm = TestVm::new(state);
+        let mut ours = Vec::new();
+        run_traced(vm.state_mut(), &mut ours).unwrap();
+
+        let theirs = Command::new(&reference).arg(&image).output().unwrap();
+        assert!(theirs.status.success(), "{} failed on {}", reference, image);
+        if let Some((_, report)) = diff_traces(
+            &String::from_utf8_lossy(&ours),
+            &String::from_utf8_lossy(&theirs.stdout),
+        ) {
+            panic!("{}:\n{}", image, report);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "jit")]
+fn the_jit_matches_the_interpreter_at_every_block_exit() {
+    // An image of the corpus or the source of a loop
+    let machine = |program: &str| match program.ends_with(".obj") {
+        true => {
+            let mut state = State::default();
+            let image = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), program);
+            read_file_to_memory(&image, &mut state).unwrap();
+            state
+        }
+        false => state_with_snippet(program),
+    };
+    for name in CORPUS.iter().chain(&LOOPS) {
+        let mut ours = machine(name);
+        let mut theirs = machine(name);
+        assert!(ours.enable_jit(), "the JIT doesn't support this machine");
+        ours.set_output(std::io::sink());
+        theirs.set_output(std::io::sink());
+        if let Some(divergence) = run_in_lockstep(&mut ours, &mut theirs, 1_000_000).unwrap() {
+            panic!("{}:\n{}", name, divergence);
+        }
+        assert!(!ours.is_running(), "{} didn't halt", name);
+    }
+}
@@ -0,0 +1,105 @@
+//! Recording a run with scripted keys and replaying it without them
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::Path;
+use std::process::{Command, Output};
+use std::{env, fs};
+
+use lc3::assembler;
+
+/// Echoes a key taken with GETC and another one taken polling the keyboard
+const KEYS_THEN_POLL: &str = "
+        .ORIG x3000
+        GETC
+        OUT
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LDI R0, KBDR
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END";
+
+fn run(image: &Path, keys: &Path, options: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(image)
+        .arg("--stdin-file")
+        .arg(keys)
+        .args(options)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn a_replayed_session_ends_the_same() {
+    let directory = env::temp_dir();
+    let id = std::process::id();
+    let image = directory.join(format!("lc3-replay-{}.obj", id));
+    let other_image = directory.join(format!("lc3-replay-other-{}.obj", id));
+    let keys = directory.join(format!("lc3-replay-{}.keys", id));
+    let no_keys = directory.join(format!("lc3-replay-none-{}.keys", id));
+    let recording = directory.join(format!("lc3-replay-{}.lc3rec", id));
+    let snapshots = [
+        directory.join(format!("lc3-replay-recorded-{}.lc3snap", id)),
+        directory.join(format!("lc3-replay-replayed-{}.lc3snap", id)),
+    ];
+    let program = assembler::assemble(KEYS_THEN_POLL).unwrap();
+    fs::write(&image, program.to_object_bytes()).unwrap();
+    let other =
+        assembler::assemble(&KEYS_THEN_POLL.replace("OUT\nPOLL", "OUT\n OUT\nPOLL")).unwrap();
+    fs::write(&other_image, other.to_object_bytes()).unwrap();
+    fs::write(&keys, b"ok").unwrap();
+    fs::write(&no_keys, b"").unwrap();
+
+    let recorded = run(
+        &image,
+        &keys,
+        &[
+            Path::new("--record"),
+            &recording,
+            Path::new("--snapshot"),
+            &snapshots[0],
+        ],
+    );
+    assert!(
+        recorded.status.success(),
+        "{}",
+        String::from_utf8_lossy(&recorded.stderr)
+    );
+    let replayed = run(
+        &image,
+        &no_keys,
+        &[
+            Path::new("--replay"),
+            &recording,
+            Path::new("--snapshot"),
+            &snapshots[1],
+        ],
+    );
+    assert!(
+        replayed.status.success(),
+        "{}",
+        String::from_utf8_lossy(&replayed.stderr)
+    );
+    assert_eq!(replayed.stdout, recorded.stdout);
+    assert_eq!(String::from_utf8_lossy(&replayed.stdout), "okHALT");
+    assert_eq!(
+        fs::read(&snapshots[0]).unwrap(),
+        fs::read(&snapshots[1]).unwrap()
+    );
+
+    let mismatched = run(&other_image, &no_keys, &[Path::new("--replay"), &recording]);
+    assert_eq!(mismatched.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&mismatched.stderr).contains("their hashes differ"),
+        "{}",
+        String::from_utf8_lossy(&mismatched.stderr)
+    );
+    for path in [image, other_image, keys, no_keys, recording]
+        .into_iter()
+        .chain(snapshots)
+    {
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,81 @@
+//! The table of the addresses a program stored to, for graders checking the data it built
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+fn run_array(flags: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/array.obj"
+        ))
+        .args(["--stdin-file", "/dev/null"])
+        .args(flags)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn every_address_written_is_listed_once_without_the_stack() {
+    let output = run_array(&["--report-writes", "--stack", "xF000:xFCFF"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "*HALT");
+    assert_eq!(
+        errors,
+        "Memory written at 5 address(es):\n  \
+         x4000=x0010 2 time(s), last by x300E\n  \
+         x4001=x0001 1 time(s), last by x3007\n  \
+         x4002=x0004 1 time(s), last by x3007\n  \
+         x4003=x0009 1 time(s), last by x3007\n  \
+         xFE06=x002A 1 time(s), last by x3012 (display)\n"
+    );
+}
+
+#[test]
+fn the_json_report_has_the_stack_without_a_stack_region() {
+    let path = env::temp_dir().join(format!("lc3-report-writes-{}.json", std::process::id()));
+    let output = run_array(&["--report-writes-json", path.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let _ = fs::remove_file(path);
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "x4000": { "value": 16, "writes": 2, "last_pc": 0x300E },
+            "x4001": { "value": 1, "writes": 1, "last_pc": 0x3007 },
+            "x4002": { "value": 4, "writes": 1, "last_pc": 0x3007 },
+            "x4003": { "value": 9, "writes": 1, "last_pc": 0x3007 },
+            "xFCFF": { "value": 16, "writes": 1, "last_pc": 0x3010 },
+            "xFE06": { "value": 42, "writes": 1, "last_pc": 0x3012, "device": "display" }
+        })
+    );
+}
+
+#[test]
+fn a_report_that_cant_be_written_doesnt_skip_the_summary() {
+    let summary = env::temp_dir().join(format!(
+        "lc3-report-writes-summary-{}.json",
+        std::process::id()
+    ));
+    let missing = env::temp_dir().join(format!("lc3-no-such-directory-{}", std::process::id()));
+    let output = run_array(&[
+        "--report-writes-json",
+        missing.join("writes.json").to_str().unwrap(),
+        "--stats-out",
+        missing.join("stats.csv").to_str().unwrap(),
+        "--json-summary",
+        summary.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let written = fs::read_to_string(&summary);
+    let _ = fs::remove_file(&summary);
+    let written: serde_json::Value = serde_json::from_str(&written.unwrap()).unwrap();
+    assert_eq!(written["outcome"], "halted");
+}
@@ -0,0 +1,65 @@
+//! A program patching one of its own branches, reported while it runs and listed at exit
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// Takes its branch at x3002 once, then patches it from x3005 to fall into the HALT
+const PATCHED_BRANCH: [u16; 9] = [
+    0x3000, 0x5260, 0x1261, 0x0E01, 0xF025, 0x2002, 0x31FC, 0x0FFA, 0x0E00,
+];
+
+fn run_patched_branch(name: &str, options: &[&str]) -> Output {
+    let image = env::temp_dir().join(format!(
+        "lc3-self-modify-{}-{}.obj",
+        name,
+        std::process::id()
+    ));
+    let bytes: Vec<u8> = PATCHED_BRANCH
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&image, bytes).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .args(["--stdin-file", "/dev/null"])
+        .args(options)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    output
+}
+
+#[test]
+fn patching_a_branch_is_reported_and_listed_at_exit() {
+    let output = run_patched_branch("warn", &[]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(
+        errors.contains(
+            "warning[W003]: self-modifying code: the store at x3005 modified the instruction at x3002"
+        ),
+        "{}",
+        errors
+    );
+    assert!(
+        errors
+            .contains("Self-modifying code:\n  x3005 modified the instruction at x3002 1 time(s)"),
+        "{}",
+        errors
+    );
+}
+
+#[test]
+fn forbid_self_modify_stops_the_program() {
+    let output = run_patched_branch("forbid", &["--forbid-self-modify"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!errors.contains("warning["), "{}", errors);
+    assert!(
+        errors
+            .contains("Self-modifying code: the store at x3005 modified the instruction at x3002"),
+        "{}",
+        errors
+    );
+}
@@ -0,0 +1,37 @@
+//! `selftest` running the conformance cases of `lc3::conformance`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+
+use lc3::conformance::CASES;
+
+fn self_test(options: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg("selftest")
+        .args(options)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn every_case_passes_and_is_listed() {
+    for options in [&[][..], &["--fast"]] {
+        let output = self_test(options);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(output.status.success(), "{}", stdout);
+        assert!(stdout.contains("ok   jsrr-through-r7\n"), "{}", stdout);
+        assert_eq!(stdout.lines().count(), CASES.len() + 1);
+        assert!(
+            stdout.ends_with(&format!("{} passed, 0 failed\n", CASES.len())),
+            "{}",
+            stdout
+        );
+    }
+}
+
+#[test]
+fn an_unknown_option_is_a_usage_error() {
+    let output = self_test(&["--slow"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
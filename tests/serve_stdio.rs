@@ -0,0 +1,137 @@
+//! `--serve-stdio` driven like an editor plugin would: the VM is a child process and the frames go through its stdin and stdout
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use lc3::assembler;
+use serde_json::{Value, json};
+
+struct Server {
+    child: Child,
+    requests: ChildStdin,
+    events: ChildStdout,
+}
+
+impl Server {
+    fn start() -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+            .arg("--serve-stdio")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Server {
+            requests: child.stdin.take().unwrap(),
+            events: child.stdout.take().unwrap(),
+            child,
+        }
+    }
+
+    fn send(&mut self, request: Value) {
+        let frame = serde_json::to_vec(&request).unwrap();
+        self.requests
+            .write_all(&(frame.len() as u32).to_be_bytes())
+            .unwrap();
+        self.requests.write_all(&frame).unwrap();
+        self.requests.flush().unwrap();
+    }
+
+    fn next_event(&mut self) -> Value {
+        let mut length = [0; 4];
+        self.events.read_exact(&mut length).unwrap();
+        let mut frame = vec![0; u32::from_be_bytes(length) as usize];
+        self.events.read_exact(&mut frame).unwrap();
+        serde_json::from_slice(&frame).unwrap()
+    }
+
+    /// Send the request and read the events until its answer, the text of the output events before it is returned too
+    fn request(&mut self, request: Value) -> (String, Value) {
+        let id = request["id"].clone();
+        self.send(request);
+        let mut output = String::new();
+        loop {
+            let event = self.next_event();
+            match event["event"].as_str() {
+                Some("output") => output.push_str(event["text"].as_str().unwrap()),
+                _ => {
+                    assert_eq!(event["id"], id);
+                    return (output, event);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn an_interactive_session_over_stdio() {
+    let mut server = Server::start();
+    let image = format!("{}/tests/programs/echo.obj", env!("CARGO_MANIFEST_DIR"));
+    let (_, loaded) = server.request(json!({"id": 1, "command": "load", "path": image}));
+    assert_eq!(loaded["event"], "done");
+
+    let (output, stopped) = server.request(json!({"id": 2, "command": "run"}));
+    assert_eq!(output, "");
+    assert_eq!(stopped["event"], "stopped");
+    assert_eq!(stopped["status"], "waitingForInput");
+    assert_eq!(stopped["pc"], 0x3003);
+
+    let (_, sent) = server.request(json!({"id": 3, "command": "sendKey", "keys": "ok"}));
+    assert_eq!(sent["event"], "done");
+    let (output, stopped) = server.request(json!({"id": 4, "command": "run"}));
+    assert_eq!(output, "OK");
+    assert_eq!(stopped["status"], "waitingForInput");
+
+    let (_, memory) =
+        server.request(json!({"id": 5, "command": "peek", "address": 0x3000, "length": 2}));
+    assert_eq!(memory["values"], json!([0x2413, 0x2613]));
+    let (_, stepped) = server.request(json!({"id": 6, "command": "step", "count": 0}));
+    assert_eq!(stepped["status"], "running");
+
+    server.request(json!({"id": 7, "command": "sendKey", "keys": "."}));
+    let (output, stopped) = server.request(json!({"id": 8, "command": "run"}));
+    assert_eq!(output, "\nbye\nHALT");
+    assert_eq!(stopped["status"], "halted");
+
+    let (_, error) = server.request(json!({"id": 9, "command": "load", "path": "missing.obj"}));
+    assert_eq!(error["event"], "error");
+    server.send(json!({"command": "reboot"}));
+    assert_eq!(server.next_event()["event"], "error");
+
+    drop(server.requests);
+    assert!(server.child.wait().unwrap().success());
+}
+
+#[test]
+fn keys_sent_while_running_reach_a_polling_program() {
+    let mut server = Server::start();
+    let program = assembler::assemble(
+        "
+        .ORIG x3000
+LOOP    LDI R1, KBSR        ; Poll until a key is ready
+        BRzp LOOP
+        LDI R0, KBDR
+        OUT
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END",
+    )
+    .unwrap();
+    server.request(json!({"id": 1, "command": "load", "image": program.to_object_bytes()}));
+    server.send(json!({"id": 2, "command": "run"}));
+    // Answered between slices while the program spins
+    let (_, sent) = server.request(json!({"id": 3, "command": "sendKey", "keys": "z"}));
+    assert_eq!(sent["event"], "done");
+    let mut output = String::new();
+    let stopped = loop {
+        let event = server.next_event();
+        match event["event"].as_str() {
+            Some("output") => output.push_str(event["text"].as_str().unwrap()),
+            _ => break event,
+        }
+    };
+    assert_eq!(stopped["id"], 2);
+    assert_eq!(stopped["status"], "halted");
+    assert_eq!(output, "zHALT");
+}
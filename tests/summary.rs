@@ -0,0 +1,88 @@
+//! `--json-summary` writes how the run ended whatever the outcome, these run the command line VM and parse what it wrote
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs};
+
+use serde_json::Value;
+
+/// Run an image from `tests/programs` without keys and parse the summary it wrote
+fn run_with_summary(name: &str, image: &str, options: &[&str]) -> Value {
+    let directory = env::temp_dir();
+    let summary_path = directory.join(format!("lc3-summary-{}-{}.json", name, std::process::id()));
+    let keys_path: PathBuf =
+        directory.join(format!("lc3-summary-{}-{}.keys", name, std::process::id()));
+    fs::write(&keys_path, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(format!(
+            "{}/tests/programs/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            image
+        ))
+        .arg("--stdin-file")
+        .arg(&keys_path)
+        .arg("--json-summary")
+        .arg(&summary_path)
+        .args(options)
+        .output()
+        .unwrap();
+    let summary = fs::read_to_string(&summary_path)
+        .unwrap_or_else(|_| panic!("{}", String::from_utf8_lossy(&output.stderr)));
+    let _ = fs::remove_file(summary_path);
+    let _ = fs::remove_file(keys_path);
+    serde_json::from_str(&summary).unwrap()
+}
+
+#[test]
+fn a_halted_run_reports_its_registers_and_the_requested_memory() {
+    let summary = run_with_summary("halted", "count.obj", &["--report-mem", "x300E:x300F"]);
+    assert_eq!(summary["outcome"], "halted");
+    // Three setup instructions, ten times the six of the loop and the four at the end
+    assert_eq!(summary["instructions"], 3 + 10 * 6 + 4);
+    assert!(summary["wall_time_ms"].is_u64());
+    assert_eq!(summary["error"], Value::Null);
+    assert_eq!(summary["registers"]["R2"], 0x37);
+    assert_eq!(summary["registers"]["PC"], 0x300D);
+    assert_eq!(summary["requested_memory"]["x300E"], '\n' as u64);
+    assert_eq!(summary["requested_memory"]["x300F"], 0x37);
+}
+
+#[test]
+fn a_run_out_of_steps_reports_the_budget() {
+    let summary = run_with_summary("budget", "spin.obj", &["--max-steps", "5000"]);
+    assert_eq!(summary["outcome"], "budget");
+    assert_eq!(summary["instructions"], 5000);
+}
+
+#[test]
+fn a_run_out_of_time_still_writes_its_summary() {
+    let summary = run_with_summary("timeout", "spin.obj", &["--timeout", "0.2"]);
+    assert_eq!(summary["outcome"], "timeout");
+    assert!(summary["instructions"].as_u64().unwrap() > 0);
+    assert!(summary["wall_time_ms"].as_u64().unwrap() >= 200);
+}
+
+#[test]
+fn a_failed_run_names_its_error() {
+    // GETC at the end of the keys fails
+    let summary = run_with_summary("error", "wait.obj", &[]);
+    assert_eq!(summary["outcome"], "error");
+    assert_eq!(summary["error"]["kind"], "Trap");
+    assert_eq!(summary["registers"]["PC"], 0x3003);
+}
+
+#[test]
+fn getc_past_the_keys_times_out_with_an_input_timeout() {
+    let summary = run_with_summary("input-timeout", "wait.obj", &["--input-timeout", "5s"]);
+    assert_eq!(summary["outcome"], "error");
+    assert_eq!(summary["error"]["kind"], "InputTimeout");
+    assert!(
+        summary["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Getc at x3002 waited for a key longer than the input timeout"),
+        "{}",
+        summary["error"]
+    );
+}
@@ -0,0 +1,156 @@
+//! The VM binary run on a pseudo terminal: however it stops, the terminal must get its line editing and echo back
+#![cfg(all(unix, feature = "cli"))]
+
+use std::io::{BufRead, BufReader};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Child, Command, Output, Stdio};
+use std::ptr;
+
+use termios::{ECHO, ICANON, Termios};
+
+/// A new pseudo terminal, the controller side first and the side the VM gets as its stdin second
+fn open_pty() -> (OwnedFd, OwnedFd) {
+    let mut controller = 0;
+    let mut terminal = 0;
+    let result = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut terminal,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    assert_eq!(result, 0, "couldn't open a pseudo terminal");
+    unsafe {
+        (
+            OwnedFd::from_raw_fd(controller),
+            OwnedFd::from_raw_fd(terminal),
+        )
+    }
+}
+
+fn is_cooked(terminal: &OwnedFd) -> bool {
+    let settings = Termios::from_fd(terminal.as_raw_fd()).unwrap();
+    settings.c_lflag & ICANON != 0 && settings.c_lflag & ECHO != 0
+}
+
+/// Start the VM with the pseudo terminal as its stdin, the image is relative to the crate
+fn spawn_on_pty(
+    terminal: &OwnedFd,
+    image: &str,
+    options: &[&str],
+    environment: &[(&str, &str)],
+) -> Child {
+    let image = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), image);
+    Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(image)
+        .args(options)
+        .envs(environment.iter().copied())
+        .stdin(Stdio::from(terminal.try_clone().unwrap()))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn run_on_pty(
+    terminal: &OwnedFd,
+    image: &str,
+    options: &[&str],
+    environment: &[(&str, &str)],
+) -> Output {
+    spawn_on_pty(terminal, image, options, environment)
+        .wait_with_output()
+        .unwrap()
+}
+
+/// Run one of the programs that print `ready` once they run, and press Ctrl-C as soon as they do
+fn interrupt_on_pty(terminal: &OwnedFd, image: &str) -> Output {
+    let mut child = spawn_on_pty(terminal, image, &[], &[]);
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\n");
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    child.wait_with_output().unwrap()
+}
+
+/// The panic is only in debug builds of the binary
+#[cfg(debug_assertions)]
+#[test]
+fn a_panic_restores_the_terminal() {
+    let (_controller, terminal) = open_pty();
+    assert!(is_cooked(&terminal));
+    let output = run_on_pty(
+        &terminal,
+        "tests/programs/hello.obj",
+        &[],
+        &[("LC3_PANIC_FOR_TESTS", "1")],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Deliberate panic"));
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn halting_restores_the_terminal() {
+    let (_controller, terminal) = open_pty();
+    let output = run_on_pty(&terminal, "tests/programs/hello.obj", &[], &[]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Hello, World!"));
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn ctrl_c_stops_a_running_program_and_reports_it() {
+    let (_controller, terminal) = open_pty();
+    let output = interrupt_on_pty(&terminal, "tests/programs/spin.obj");
+    assert_eq!(output.status.code(), Some(130));
+    let report = String::from_utf8_lossy(&output.stderr);
+    assert!(report.contains("Interrupted at x300"), "{}", report);
+    assert!(report.contains("R1=x"), "{}", report);
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn ctrl_c_stops_a_program_waiting_for_a_key() {
+    let (_controller, terminal) = open_pty();
+    let output = interrupt_on_pty(&terminal, "tests/programs/wait.obj");
+    assert_eq!(output.status.code(), Some(130));
+    let report = String::from_utf8_lossy(&output.stderr);
+    assert!(report.contains("Interrupted at x3002: GETC"), "{}", report);
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn a_missing_image_leaves_the_terminal_alone() {
+    let (_controller, terminal) = open_pty();
+    let output = run_on_pty(&terminal, "tests/programs/missing.obj", &[], &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No such file"));
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn a_bad_trap_restores_the_terminal() {
+    let (_controller, terminal) = open_pty();
+    let output = run_on_pty(&terminal, "tests/programs/bad_trap.obj", &[], &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Bad trap code x50"));
+    assert!(is_cooked(&terminal));
+}
+
+#[test]
+fn a_timeout_restores_the_terminal() {
+    let (_controller, terminal) = open_pty();
+    let output = run_on_pty(
+        &terminal,
+        "tests/programs/spin.obj",
+        &["--timeout", "0.2"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("didn't halt within 200ms"));
+    assert!(is_cooked(&terminal));
+}
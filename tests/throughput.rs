@@ -0,0 +1,44 @@
+//! Coarse guard against throughput regressions, like a syscall or an allocation sneaking into `run_step`.
+//! The limit is generous, on a slow machine raise it with `LC3_THROUGHPUT_SECONDS`
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use lc3::test_util::TestVm;
+use lc3::{Error, RuntimeError};
+
+const INSTRUCTIONS: u64 = 10_000_000;
+const DEFAULT_SECONDS: f64 = 2.0;
+
+#[test]
+fn ten_million_instructions_run_in_time() {
+    let limit = env::var("LC3_THROUGHPUT_SECONDS")
+        .map(|seconds| {
+            seconds
+                .parse()
+                .expect("LC3_THROUGHPUT_SECONDS must be a number")
+        })
+        .unwrap_or(DEFAULT_SECONDS);
+    let mut vm = TestVm::with_snippet(".ORIG x3000\nLOOP ADD R1, R1, #1\nBR LOOP\n.END")
+        .budget(INSTRUCTIONS);
+    let start = Instant::now();
+    let result = vm.run();
+    let elapsed = start.elapsed();
+    assert!(matches!(
+        result,
+        Err(Error::Runtime(RuntimeError::BudgetExhausted(INSTRUCTIONS)))
+    ));
+    assert!(
+        elapsed <= Duration::from_secs_f64(limit),
+        "{} instructions took {:?}, more than the {}s limit",
+        INSTRUCTIONS,
+        elapsed,
+        limit
+    );
+    println!(
+        "{} instructions in {:?}: {:.1} MIPS",
+        INSTRUCTIONS,
+        elapsed,
+        INSTRUCTIONS as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
@@ -0,0 +1,75 @@
+//! The line `--time` prints, scripts split it so its format must not change
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::{env, fs};
+
+/// Counts R0 down from 5, then OUT and HALT: 14 instructions
+const COUNTDOWN: [u16; 7] = [0x3000, 0x5020, 0x1025, 0x103F, 0x03FE, 0xF021, 0xF025];
+
+#[test]
+fn the_time_banner_counts_the_instructions_exactly() {
+    let directory = env::temp_dir();
+    let image = directory.join(format!("lc3-time-{}.obj", std::process::id()));
+    let keys = directory.join(format!("lc3-time-{}.keys", std::process::id()));
+    let bytes: Vec<u8> = COUNTDOWN
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&image, bytes).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .arg("--time")
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    let banner = errors
+        .lines()
+        .find_map(|line| line.strip_prefix("time: "))
+        .unwrap_or_else(|| panic!("no banner in {}", errors));
+    let fields: HashMap<&str, &str> = banner
+        .split(' ')
+        .map(|field| field.split_once('=').unwrap())
+        .collect();
+    assert_eq!(fields["instructions"], "14");
+    assert_eq!(fields["traps"], "2");
+    assert_eq!(fields["trap_x21"], "1");
+    assert_eq!(fields["trap_x25"], "1");
+    assert_eq!(fields["footprint"], "6");
+    assert!(fields["wall_ms"].parse::<f64>().is_ok());
+    assert!(fields["mips"].parse::<f64>().is_ok());
+}
+
+#[test]
+fn the_banner_counts_the_keys_a_full_buffer_dropped() {
+    let directory = env::temp_dir();
+    let keys = directory.join(format!("lc3-time-dropped-{}.keys", std::process::id()));
+    fs::write(&keys, b"abcdefgh.").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/echo.obj"
+        ))
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--kb-buffer", "4", "--kb-overflow", "drop-old", "--time"])
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(keys);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    // The whole script arrived at the first GETC, only its last 4 keys fit
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("FGH"));
+    let banner = errors
+        .lines()
+        .find_map(|line| line.strip_prefix("time: "))
+        .unwrap_or_else(|| panic!("no banner in {}", errors));
+    assert!(banner.ends_with(" keys_dropped=5"), "{}", banner);
+}
@@ -0,0 +1,47 @@
+//! Programs checking themselves with the ASSERT trap (x26)
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+fn run_asserts(flags: &[&str]) -> Output {
+    let keys = env::temp_dir().join(format!("lc3-trap-assert-{}.keys", std::process::id()));
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/programs/asserts.obj"
+        ))
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(flags)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(keys);
+    output
+}
+
+#[test]
+fn a_failed_assert_trap_is_the_exit_code() {
+    let output = run_asserts(&[]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", errors);
+    assert!(errors.contains("ASSERT PASS: 2 + 3 is 5\n"), "{}", errors);
+    assert!(errors.contains("ASSERT FAIL: 2 + 3 is 6\n"), "{}", errors);
+    assert!(errors.contains("1 assertion(s) failed"), "{}", errors);
+    // The program went on after the failure
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "done\nHALT");
+}
+
+#[test]
+fn halt_on_assert_fail_stops_at_the_failure() {
+    let output = run_asserts(&["--halt-on-assert-fail"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", errors);
+    assert!(
+        errors.contains("ASSERT FAIL: 2 + 3 is 6 at x300E (TRAP x26)"),
+        "{}",
+        errors
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
@@ -0,0 +1,43 @@
+//! `--trap` moves a routine of the VM to another vector, see `lc3::trap_table`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::Command;
+use std::{env, fs};
+
+/// LEA R0 to the string, TRAP x40, HALT, then "ok"
+const PUTS_AT_X40: [u16; 7] = [0x3000, 0xE002, 0xF040, 0xF025, 0x006F, 0x006B, 0x0000];
+
+#[test]
+fn trap_runs_a_routine_of_the_vm_at_another_vector() {
+    let directory = env::temp_dir();
+    let image = directory.join(format!("lc3-trap-table-{}.obj", std::process::id()));
+    let keys = directory.join(format!("lc3-trap-table-{}.keys", std::process::id()));
+    let bytes: Vec<u8> = PUTS_AT_X40
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+    fs::write(&image, bytes).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let run = |remap: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+            .arg(&image)
+            .arg("--stdin-file")
+            .arg(&keys)
+            .args(remap)
+            .output()
+            .unwrap()
+    };
+    let remapped = run(&["--trap", "x40=puts"]);
+    let unmapped = run(&[]);
+    let bad = run(&["--trap", "x140=puts"]);
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    assert!(
+        remapped.status.success(),
+        "{}",
+        String::from_utf8_lossy(&remapped.stderr)
+    );
+    assert_eq!(remapped.stdout, b"okHALT");
+    assert!(!unmapped.status.success());
+    assert!(!bad.status.success());
+}
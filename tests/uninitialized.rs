@@ -0,0 +1,60 @@
+//! Falling off the end of a program into memory nothing was loaded to, the most common bug of a missing HALT
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// Run an image of `ADD R1, R1, #1` at x3000 and nothing after it
+fn run_without_halt(name: &str, options: &[&str]) -> Output {
+    let directory = env::temp_dir();
+    let image = directory.join(format!(
+        "lc3-uninitialized-{}-{}.obj",
+        name,
+        std::process::id()
+    ));
+    let keys = directory.join(format!(
+        "lc3-uninitialized-{}-{}.keys",
+        name,
+        std::process::id()
+    ));
+    fs::write(&image, [0x30, 0x00, 0x12, 0x61]).unwrap();
+    fs::write(&keys, b"").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&image)
+        .arg("--stdin-file")
+        .arg(&keys)
+        .args(["--max-steps", "1000"])
+        .args(options)
+        .output()
+        .unwrap();
+    let _ = fs::remove_file(image);
+    let _ = fs::remove_file(keys);
+    output
+}
+
+#[test]
+fn falling_off_the_program_warns_exactly_once() {
+    let output = run_without_halt("warn", &[]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(errors.matches("warning[").count(), 1, "{}", errors);
+    assert!(errors.contains("executing x3001"), "{}", errors);
+    // It kept running until the budget
+    assert!(
+        errors.contains("didn't halt after 1000 instructions"),
+        "{}",
+        errors
+    );
+}
+
+#[test]
+fn strict_exec_stops_at_the_first_uninitialized_word() {
+    let output = run_without_halt("strict", &["--strict-exec"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!errors.contains("warning["), "{}", errors);
+    assert!(
+        errors.contains("Executing x3001, nothing was loaded or written there"),
+        "{}",
+        errors
+    );
+}
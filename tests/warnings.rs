@@ -0,0 +1,134 @@
+//! The warnings of a run going through a single emitter, with their codes, see `lc3::warning`
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// Three stores through R6 outside of a stack at x4000-x40FF, each a W002
+const STORES_OUTSIDE: &str = "
+        .ORIG x3000
+        LD R6, TOP
+        STR R0, R6, #0
+        STR R0, R6, #1
+        STR R0, R6, #2
+        HALT
+TOP     .FILL x5000
+        .END
+";
+
+fn source(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lc3-warnings-{}-{}.asm", name, std::process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn run(name: &str, sources: &[&str], options: &[&str]) -> (Output, String) {
+    let paths: Vec<PathBuf> = sources
+        .iter()
+        .enumerate()
+        .map(|(index, text)| source(&format!("{}-{}", name, index), text))
+        .collect();
+    let summary =
+        env::temp_dir().join(format!("lc3-warnings-{}-{}.json", name, std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .args(&paths)
+        .args(["--stdin-file", "/dev/null", "--json-summary"])
+        .arg(&summary)
+        .args(options)
+        .output()
+        .unwrap();
+    let summary_text = fs::read_to_string(&summary).unwrap_or_default();
+    for path in paths {
+        let _ = fs::remove_file(path);
+    }
+    let _ = fs::remove_file(summary);
+    (output, summary_text)
+}
+
+#[test]
+fn a_warning_is_printed_once_and_counted() {
+    let (output, summary) = run("dedup", &[STORES_OUTSIDE], &["--stack", "x4000:x40FF"]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert_eq!(errors.matches("warning[W002]").count(), 1, "{}", errors);
+    assert!(
+        errors.contains("warning[W002]: Store through R6 outside of the stack at x3001: x5000\n"),
+        "{}",
+        errors
+    );
+    assert!(
+        errors.contains("Warnings:\n  W002 stack discipline: 3 time(s)\n"),
+        "{}",
+        errors
+    );
+    let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+    assert_eq!(
+        summary["warnings"],
+        serde_json::json!([{
+            "code": "W002",
+            "message": "Store through R6 outside of the stack at x3001: x5000",
+            "count": 3
+        }])
+    );
+}
+
+#[test]
+fn an_allowed_warning_is_left_out() {
+    let (output, summary) = run(
+        "allow",
+        &[STORES_OUTSIDE],
+        &["--stack", "x4000:x40FF", "--allow", "w002"],
+    );
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(!errors.contains("arning"), "{}", errors);
+    let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+    assert_eq!(summary["warnings"], serde_json::json!([]));
+}
+
+#[test]
+fn a_denied_warning_stops_the_program() {
+    let (output, summary) = run(
+        "deny",
+        &[STORES_OUTSIDE],
+        &["--stack", "x4000:x40FF", "--deny", "W002"],
+    );
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", errors);
+    assert!(
+        errors.contains("Denied warning W002: Store through R6 outside of the stack at x3001"),
+        "{}",
+        errors
+    );
+    let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+    assert_eq!(summary["outcome"], "error");
+    assert_eq!(summary["error"]["kind"], "WarningDenied");
+    // Stopped before the store
+    assert_eq!(summary["registers"]["PC"], 0x3001);
+}
+
+#[test]
+fn an_image_loaded_over_another_is_warned_about() {
+    let first = ".ORIG x3000\nHALT\n.FILL x1234\n.END\n";
+    let second = ".ORIG x3001\n.FILL x5678\n.END\n";
+    let (output, _) = run("overlap", &[first, second], &[]);
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", errors);
+    assert!(
+        errors.contains(
+            "warning[W004]: the image at x3001-x3001 overwrites words another image loaded, from x3001 on"
+        ),
+        "{}",
+        errors
+    );
+    let (output, _) = run("overlap-denied", &[first, second], &["--deny", "W004"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn an_unknown_code_is_a_usage_error() {
+    let (output, _) = run("unknown", &[STORES_OUTSIDE], &["--allow", "W999"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
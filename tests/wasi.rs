@@ -0,0 +1,74 @@
+//! The command line VM built for `wasm32-wasip1` and run by wasmtime must print what the native one does for the golden programs.
+//! It needs `wasmtime` on the path and the binary built with `cargo build --target wasm32-wasip1`, or its path in `LC3_WASI_BINARY`,
+//! otherwise it is skipped
+#![cfg(all(unix, feature = "cli"))]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::{env, fs};
+
+/// The golden cases: image and keys
+const CASES: [(&str, &[u8]); 4] = [
+    ("tests/programs/hello.obj", b""),
+    ("tests/programs/count.obj", b""),
+    ("tests/programs/echo.obj", b"Hello lc3.ignored"),
+    ("images/2048.obj", b"nwasdwasd"),
+];
+
+fn wasi_binary() -> Option<PathBuf> {
+    let path = match env::var_os("LC3_WASI_BINARY") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("target/wasm32-wasip1/debug/LC-3-VM.wasm")
+        }
+    };
+    let wasmtime = Command::new("wasmtime").arg("--version").output();
+    (path.exists() && wasmtime.is_ok_and(|output| output.status.success())).then_some(path)
+}
+
+fn summary(output: &Output) -> (Option<i32>, String, String) {
+    (
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn wasmtime_prints_what_the_native_binary_prints() {
+    let Some(wasm) = wasi_binary() else {
+        eprintln!(
+            "skipped: build with `cargo build --target wasm32-wasip1` and put wasmtime on the path"
+        );
+        return;
+    };
+    let manifest = env!("CARGO_MANIFEST_DIR");
+    let keys_directory = env::temp_dir();
+    for (image, keys) in CASES {
+        let image = format!("{}/{}", manifest, image);
+        let keys_path = keys_directory.join(format!("lc3-wasi-{}.keys", std::process::id()));
+        fs::write(&keys_path, keys).unwrap();
+        let native = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+            .arg("--stdin-file")
+            .arg(&keys_path)
+            .arg(&image)
+            .output()
+            .unwrap();
+        // Files are only reachable through preopened directories
+        let wasi = Command::new("wasmtime")
+            .arg("run")
+            .arg("--dir")
+            .arg(manifest)
+            .arg("--dir")
+            .arg(&keys_directory)
+            .arg(&wasm)
+            .arg("--stdin-file")
+            .arg(&keys_path)
+            .arg(&image)
+            .output()
+            .unwrap();
+        fs::remove_file(&keys_path).unwrap();
+        assert_eq!(summary(&wasi), summary(&native), "{}", image);
+        assert!(!native.stdout.is_empty(), "{}", image);
+    }
+}
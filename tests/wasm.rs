@@ -0,0 +1,57 @@
+//! Smoke test of the JavaScript bindings, run in node with
+//! `cargo test --target wasm32-unknown-unknown --features wasm --test wasm` (needs `wasm-bindgen-cli`, see the README)
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use lc3::wasm::{RunStatus, WasmVm};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn runs_a_program_to_halt() {
+    let mut vm = WasmVm::new();
+    vm.load_image(include_bytes!("programs/hello.obj")).unwrap();
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::Halted);
+    assert_eq!(vm.take_output(), "Hello, World!\nHALT");
+    assert_eq!(vm.take_output(), "");
+}
+
+#[wasm_bindgen_test]
+fn waits_for_keys_and_resumes() {
+    let mut vm = WasmVm::new();
+    vm.load_image(include_bytes!("programs/echo.obj")).unwrap();
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::WaitingForInput);
+    for key in b"hi" {
+        vm.key_down(*key);
+    }
+    assert_eq!(vm.step(3).unwrap(), RunStatus::Running);
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::WaitingForInput);
+    assert_eq!(vm.take_output(), "HI");
+    vm.key_down(b'.');
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::Halted);
+    assert_eq!(vm.take_output(), "\nbye\nHALT");
+    // R5 counts the keys
+    assert_eq!(vm.read_registers()[5], 3);
+    assert_eq!(vm.read_memory(0x3000, 2), [0x2413, 0x2613]);
+}
+
+#[wasm_bindgen_test]
+fn a_kept_keyboard_handle_feeds_the_program() {
+    let mut vm = WasmVm::new();
+    vm.load_image(include_bytes!("programs/echo.obj")).unwrap();
+    let keyboard = vm.keyboard();
+    keyboard.push_str("ab.");
+    assert_eq!(keyboard.pending(), 3);
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::Halted);
+    assert_eq!(keyboard.pending(), 0);
+    assert_eq!(vm.take_output(), "AB\nbye\nHALT");
+}
+
+#[wasm_bindgen_test]
+fn the_output_can_be_drained_and_bounded() {
+    let mut vm = WasmVm::new();
+    vm.load_image(include_bytes!("programs/hello.obj")).unwrap();
+    vm.set_output_capacity(Some(5));
+    assert_eq!(vm.run(1_000.0).unwrap(), RunStatus::Halted);
+    assert_eq!(vm.output_len(), 5);
+    assert_eq!(vm.drain_output(), b"Hello");
+    assert_eq!(vm.output_overflowed(), 13.0);
+}
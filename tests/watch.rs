@@ -0,0 +1,87 @@
+//! `--watch` running a source again once it changed
+#![cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, thread};
+
+fn printing(text: &str) -> String {
+    format!(
+        ".ORIG x3000\nLEA R0, TEXT\nPUTS\nHALT\nTEXT .STRINGZ \"{}\"\n.END\n",
+        text
+    )
+}
+
+/// Wait for `expected` in what `output` gave so far, at most a few seconds
+fn wait_for(output: &mpsc::Receiver<u8>, printed: &mut Vec<u8>, expected: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !String::from_utf8_lossy(printed).contains(expected) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match output.recv_timeout(left) {
+            Ok(byte) => printed.push(byte),
+            Err(_) => panic!(
+                "{:?} never came, got {:?}",
+                expected,
+                String::from_utf8_lossy(printed)
+            ),
+        }
+    }
+}
+
+#[test]
+fn a_changed_source_runs_again() {
+    let directory = env::temp_dir().join(format!("lc3-watch-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let source = directory.join("program.asm");
+    fs::write(&source, printing("one")).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_LC-3-VM"))
+        .arg(&source)
+        .args([
+            "--watch",
+            "--watch-interval",
+            "20ms",
+            "--stdin-file",
+            "/dev/null",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (sender, output) = mpsc::channel();
+    let mut stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        let mut byte = [0];
+        while stdout.read(&mut byte).is_ok_and(|read| read == 1) {
+            let _ = sender.send(byte[0]);
+        }
+    });
+    let mut printed = Vec::new();
+    wait_for(&output, &mut printed, "oneHALT");
+    fs::write(&source, printing("two")).unwrap();
+    // Later than the first write whatever the resolution of the file system
+    fs::File::options()
+        .write(true)
+        .open(&source)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(5))
+        .unwrap();
+    wait_for(&output, &mut printed, "twoHALT");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let mut errors = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut errors)
+        .unwrap();
+    let _ = fs::remove_dir_all(directory);
+    assert_eq!(String::from_utf8_lossy(&printed), "oneHALTtwoHALT");
+    assert!(
+        errors.contains("program.asm changed, running again"),
+        "{}",
+        errors
+    );
+}